@@ -3,23 +3,47 @@
 //! Sends authorization requests to authd daemon.
 //! authd handles all UI (session-lock dialog).
 
-use authd_protocol::{AuthRequest, collect_wayland_env};
 #[cfg(not(coverage))]
-use authd_protocol::{AuthResponse, DaemonRequest, SOCKET_PATH};
+use authd_protocol::details::Details;
+use authd_protocol::frame::FrameError;
+use authd_protocol::instance::InstanceName;
+use authd_protocol::messages::{Message, MessageId};
+use authd_protocol::{
+    ActiveScope, AuthRequest, AuthResponse, KillScopeRequest, KillSignal, LockdownRequest,
+    collect_wayland_env,
+};
 #[cfg(not(coverage))]
-use peercred_ipc::Client;
+use authd_protocol::{DaemonRequest, KillScopeResponse, LockdownResponse, frame, instance};
 #[cfg(not(coverage))]
 use std::env;
+#[cfg(not(coverage))]
+use std::fmt::Display;
 use std::path::PathBuf;
 #[cfg(not(coverage))]
 use std::process;
+#[cfg(not(coverage))]
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[cfg(not(coverage))]
 fn main() {
     let args = cli_args();
-    handle_meta_args(&args);
-    let request = build_request(&args);
-    exit_with_response(send_request(&request));
+    let (instance, args) = split_instance(&args).unwrap_or_else(exit_with_error);
+    if args.is_empty() {
+        print_help();
+        process::exit(1);
+    }
+    let socket = instance::socket_path(instance.as_ref());
+    handle_meta_args(args, &socket);
+    let (show_details, args) = split_details(args);
+    if args.is_empty() {
+        exit_with_error(Message::from(MessageId::DetailsNeedsCommand));
+    }
+    let request = build_request(args).unwrap_or_else(exit_with_error);
+    if show_details {
+        let details = Details::of(&request);
+        eprintln!("authctl: {}", MessageId::AskingToRun.with(&[&details]));
+    }
+    exit_with_response(send_request(&socket, &request));
 }
 
 #[cfg(coverage)]
@@ -29,14 +53,23 @@ fn main() {}
 fn print_help() {
     eprintln!("authctl - privilege escalation client for authd");
     eprintln!();
-    eprintln!("Usage: authctl <command> [args...]");
+    eprintln!("Usage: authctl [--instance <name>] [--details] <command> [args...]");
+    eprintln!("       authctl [--instance <name>] --list-active");
+    eprintln!("       authctl [--instance <name>] --kill <unit> [--signal TERM|HUP|INT]");
+    eprintln!("       authctl [--instance <name>] --lockdown on|off");
     eprintln!();
     eprintln!("Sends authorization requests to authd daemon.");
     eprintln!("If authorized, the command runs as root.");
     eprintln!();
     eprintln!("Options:");
-    eprintln!("  -h, --help     Show this help");
-    eprintln!("  -V, --version  Show version");
+    eprintln!("  --instance <name>  Talk to the named authd instance");
+    eprintln!("  --details          Print the full argv and environment being approved");
+    eprintln!("  --list-active      List running processes authd launched for you");
+    eprintln!("  --kill <unit>      Stop one of them by unit name");
+    eprintln!("  --signal <name>    Send HUP or INT instead of stopping it");
+    eprintln!("  --lockdown on|off  Refuse everything but emergency_allow (admins only)");
+    eprintln!("  -h, --help         Show this help");
+    eprintln!("  -V, --version      Show version");
 }
 
 #[cfg(not(coverage))]
@@ -49,8 +82,28 @@ fn cli_args() -> Vec<String> {
     args
 }
 
+/// Strip a leading `--instance <name>` off the arguments
+fn split_instance(args: &[String]) -> Result<(Option<InstanceName>, &[String]), String> {
+    match args {
+        [flag, name, rest @ ..] if flag == "--instance" => {
+            let name = InstanceName::new(name).map_err(|e| e.to_string())?;
+            Ok((Some(name), rest))
+        }
+        [flag] if flag == "--instance" => Err("--instance requires a name".into()),
+        _ => Ok((None, args)),
+    }
+}
+
+/// Strip a leading `--details` off the command
+fn split_details(args: &[String]) -> (bool, &[String]) {
+    match args {
+        [flag, rest @ ..] if flag == "--details" => (true, rest),
+        _ => (false, args),
+    }
+}
+
 #[cfg(not(coverage))]
-fn handle_meta_args(args: &[String]) {
+fn handle_meta_args(args: &[String], socket: &str) {
     match args.first().map(String::as_str) {
         Some("--help" | "-h") => {
             print_help();
@@ -60,53 +113,218 @@ fn handle_meta_args(args: &[String]) {
             println!("authctl {}", env!("CARGO_PKG_VERSION"));
             process::exit(0);
         }
+        Some("--list-active") => list_active(socket),
+        Some("--kill") => match kill_request(&args[1..]) {
+            Ok(request) => kill_scope(socket, request),
+            Err(message) => exit_with_error(message),
+        },
+        Some("--lockdown") => match lockdown_request(&args[1..]) {
+            Ok(request) => set_lockdown(socket, request),
+            Err(message) => exit_with_error(message),
+        },
         _ => {}
     }
 }
 
-fn build_request(args: &[String]) -> AuthRequest {
-    AuthRequest {
-        target: PathBuf::from(&args[0]),
-        args: args.iter().skip(1).cloned().collect(),
-        env: collect_wayland_env(),
-        password: String::new(),
-        confirm_only: false,
-        prompt_title: None,
-        prompt_message: None,
-        prompt_detail: None,
+#[cfg(not(coverage))]
+fn list_active(socket: &str) -> ! {
+    let scopes: Vec<ActiveScope> =
+        frame::call_socket(socket, &DaemonRequest::ListActive).unwrap_or_else(exit_with_error);
+    let now_secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    for line in scope_table(&scopes, now_secs) {
+        println!("{}", line);
     }
+    process::exit(0);
+}
+
+#[cfg(not(coverage))]
+fn kill_scope(socket: &str, request: KillScopeRequest) -> ! {
+    let unit = request.unit.clone();
+    match frame::call_socket(socket, &DaemonRequest::KillScope(request)) {
+        Ok(KillScopeResponse::Stopped) => {
+            eprintln!("authctl: {}", MessageId::ScopeStopped.with(&[&unit]));
+            process::exit(0);
+        }
+        Ok(KillScopeResponse::NotFound) => exit_with_error(MessageId::NoActiveScope.with(&[&unit])),
+        Ok(KillScopeResponse::Denied { reason }) => {
+            exit_with_error(MessageId::Denied.with(&[&reason]))
+        }
+        Ok(KillScopeResponse::Error { message }) => {
+            exit_with_error(MessageId::DaemonError.with(&[&message]))
+        }
+        Err(e) => exit_with_error(e),
+    }
+}
+
+#[cfg(not(coverage))]
+fn set_lockdown(socket: &str, request: LockdownRequest) -> ! {
+    match frame::call_socket(socket, &DaemonRequest::Lockdown(request)) {
+        Ok(LockdownResponse::Set { active }) => {
+            let id = if active {
+                MessageId::LockdownOn
+            } else {
+                MessageId::LockdownCleared
+            };
+            eprintln!("authctl: {}", Message::from(id));
+            process::exit(0);
+        }
+        Ok(LockdownResponse::Denied { reason }) => {
+            exit_with_error(MessageId::Denied.with(&[&reason]))
+        }
+        Ok(LockdownResponse::Error { message }) => {
+            exit_with_error(MessageId::DaemonError.with(&[&message]))
+        }
+        Err(e) => exit_with_error(e),
+    }
+}
+
+/// `on` or `off` after `--lockdown`
+fn lockdown_request(args: &[String]) -> Result<LockdownRequest, String> {
+    match args {
+        [state] if state == "on" => Ok(LockdownRequest { active: true }),
+        [state] if state == "off" => Ok(LockdownRequest { active: false }),
+        _ => Err("usage: --lockdown on|off".into()),
+    }
+}
+
+/// `<unit> [--signal <name>]` after `--kill`
+fn kill_request(args: &[String]) -> Result<KillScopeRequest, String> {
+    match args {
+        [unit] => Ok(KillScopeRequest::new(unit.as_str(), KillSignal::Term)),
+        [unit, flag, name] if flag == "--signal" => Ok(KillScopeRequest::new(
+            unit.as_str(),
+            KillSignal::parse(name)?,
+        )),
+        [_, flag] if flag == "--signal" => Err("--signal requires a signal name".into()),
+        [] => Err("--kill requires a unit name".into()),
+        _ => Err("usage: --kill <unit> [--signal TERM|HUP|INT]".into()),
+    }
+}
+
+/// Header plus one line per scope, with start time shown as an age.
+fn scope_table(scopes: &[ActiveScope], now_secs: u64) -> Vec<String> {
+    let mut lines = vec![format!(
+        "{:<28} {:>8} {:>8}  {:<24} {}",
+        "UNIT", "PID", "AGE", "CALLER", "TARGET"
+    )];
+    lines.extend(scopes.iter().map(|scope| {
+        format!(
+            "{:<28} {:>8} {:>8}  {:<24} {}",
+            scope.unit,
+            scope.pid,
+            format_age(now_secs.saturating_sub(scope.started_secs)),
+            scope.caller.display(),
+            scope.target.display()
+        )
+    }));
+    lines
+}
+
+fn format_age(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+fn build_request(args: &[String]) -> Result<AuthRequest, FrameError> {
+    AuthRequest::try_new(
+        PathBuf::from(&args[0]),
+        args.iter().skip(1).cloned().collect(),
+        collect_wayland_env(),
+    )
 }
 
 #[cfg(not(coverage))]
 fn exit_with_response(response: Result<AuthResponse, String>) -> ! {
-    match response {
-        Ok(AuthResponse::Success { pid }) => {
-            eprintln!("authctl: process spawned (pid {})", pid);
+    match response_message(response) {
+        Ok(message) => {
+            eprintln!("authctl: {}", message);
             process::exit(0);
         }
-        Ok(AuthResponse::Denied { reason }) => exit_with_error(&format!("denied - {}", reason)),
-        Ok(AuthResponse::UnknownTarget) => exit_with_error("no policy for this command"),
-        Ok(AuthResponse::AuthFailed) => exit_with_error("authentication failed"),
-        Ok(AuthResponse::Error { message }) => exit_with_error(&format!("error - {}", message)),
-        Err(error) if error.contains("connect") => exit_with_error("daemon not running"),
-        Err(error) => exit_with_error(&error),
+        Err(message) => exit_with_error(message),
+    }
+}
+
+/// What to tell the user about authd's answer: Ok if the command was
+/// launched, Err otherwise
+#[cfg_attr(coverage, allow(dead_code))]
+fn response_message(response: Result<AuthResponse, String>) -> Result<Message, Message> {
+    match response {
+        Ok(AuthResponse::Success { pid, cached: false }) => {
+            Ok(MessageId::ProcessSpawned.with(&[&pid]))
+        }
+        Ok(AuthResponse::Success { pid, cached: true }) => {
+            Ok(MessageId::ProcessSpawnedCached.with(&[&pid]))
+        }
+        Ok(AuthResponse::Denied { reason }) => Err(MessageId::Denied.with(&[&reason])),
+        Ok(AuthResponse::UnknownTarget) => Err(MessageId::NoPolicy.into()),
+        Ok(AuthResponse::AuthFailed) => Err(MessageId::AuthenticationFailed.into()),
+        Ok(AuthResponse::Error { message }) => Err(MessageId::DaemonError.with(&[&message])),
+        // Only sent for ExecWait, which authctl doesn't use
+        Ok(AuthResponse::Exited { .. }) => Err(MessageId::UnexpectedResponse.into()),
+        Err(error) if error.contains("connect") => Err(MessageId::DaemonNotRunning.into()),
+        Err(error) => Err(MessageId::RequestFailed.with(&[&error])),
     }
 }
 
 #[cfg(not(coverage))]
-fn exit_with_error(message: &str) -> ! {
+fn exit_with_error(message: impl Display) -> ! {
     eprintln!("authctl: {}", message);
     process::exit(1)
 }
 
 #[cfg(not(coverage))]
-fn send_request(request: &AuthRequest) -> Result<AuthResponse, String> {
-    Client::call(SOCKET_PATH, &DaemonRequest::Exec(request.clone())).map_err(|e| e.to_string())
+fn send_request(socket: &str, request: &AuthRequest) -> Result<AuthResponse, String> {
+    frame::call_socket(socket, &DaemonRequest::Exec(request.clone())).map_err(|e| e.to_string())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use authd_protocol::frame::MAX_FRAME_LEN;
+
+    #[test]
+    fn leading_instance_flag_selects_the_instance() {
+        let args: Vec<String> = ["--instance", "test", "/usr/bin/id", "--instance"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+
+        let (instance, rest) = split_instance(&args).unwrap();
+        assert_eq!(instance, Some(InstanceName::new("test").unwrap()));
+        // Only a leading flag counts; later ones belong to the command
+        assert_eq!(rest, ["/usr/bin/id", "--instance"]);
+
+        let (instance, rest) = split_instance(&args[2..]).unwrap();
+        assert_eq!(instance, None);
+        assert_eq!(rest, ["/usr/bin/id", "--instance"]);
+
+        assert!(split_instance(&args[3..]).is_err());
+        assert!(split_instance(&["--instance".into(), "a/b".into()]).is_err());
+    }
+
+    #[test]
+    fn details_flag_only_counts_before_the_command() {
+        let args: Vec<String> = ["--details", "/usr/bin/id", "--details"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+
+        assert_eq!(
+            split_details(&args),
+            (
+                true,
+                &["/usr/bin/id".to_string(), "--details".to_string()][..]
+            )
+        );
+        assert_eq!(split_details(&args[1..]), (false, &args[1..]));
+    }
 
     #[test]
     fn builds_exec_request_from_cli_args() {
@@ -116,7 +334,7 @@ mod tests {
             "--name".to_string(),
         ];
 
-        let request = build_request(&args);
+        let request = build_request(&args).unwrap();
 
         assert_eq!(request.target, PathBuf::from("/usr/bin/id"));
         assert_eq!(request.args, vec!["-u", "--name"]);
@@ -125,6 +343,100 @@ mod tests {
         assert!(request.prompt_title.is_none());
     }
 
+    #[test]
+    fn oversized_commands_are_refused_locally() {
+        let args = vec!["/usr/bin/echo".to_string(), "x".repeat(MAX_FRAME_LEN)];
+
+        assert!(matches!(build_request(&args), Err(FrameError::TooLarge(_))));
+    }
+
+    #[test]
+    fn daemon_answers_map_to_messages() {
+        let spawned = response_message(Ok(AuthResponse::Success {
+            pid: 42,
+            cached: false,
+        }))
+        .unwrap();
+        assert_eq!(spawned.id, MessageId::ProcessSpawned);
+        assert_eq!(spawned.args, ["42"]);
+        let cached = response_message(Ok(AuthResponse::Success {
+            pid: 42,
+            cached: true,
+        }))
+        .unwrap();
+        assert_eq!(cached.id, MessageId::ProcessSpawnedCached);
+        assert_eq!(
+            cached.to_string(),
+            "authorized (cached), process spawned (pid 42)"
+        );
+
+        let denied = response_message(Ok(AuthResponse::Denied {
+            reason: "not in group wheel".into(),
+        }));
+        assert_eq!(denied.unwrap_err().id, MessageId::Denied);
+        let unknown = response_message(Ok(AuthResponse::UnknownTarget));
+        assert_eq!(unknown.unwrap_err().id, MessageId::NoPolicy);
+        let offline = response_message(Err("connect: No such file or directory".into()));
+        assert_eq!(offline.unwrap_err().id, MessageId::DaemonNotRunning);
+    }
+
+    #[test]
+    fn kill_takes_an_optional_safe_signal() {
+        fn args(args: &[&str]) -> Vec<String> {
+            args.iter().map(|arg| arg.to_string()).collect()
+        }
+
+        let request = kill_request(&args(&["a.scope"])).unwrap();
+        assert_eq!(request.unit, "a.scope");
+        assert_eq!(request.checked_signal(), Ok(KillSignal::Term));
+
+        let request = kill_request(&args(&["a.scope", "--signal", "HUP"])).unwrap();
+        assert_eq!(request.checked_signal(), Ok(KillSignal::Hup));
+
+        assert!(kill_request(&args(&["a.scope", "--signal", "KILL"])).is_err());
+        assert!(kill_request(&args(&["a.scope", "--signal"])).is_err());
+        assert!(kill_request(&[]).is_err());
+    }
+
+    #[test]
+    fn lockdown_is_switched_on_or_off() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert!(lockdown_request(&args(&["on"])).unwrap().active);
+        assert!(!lockdown_request(&args(&["off"])).unwrap().active);
+        assert!(lockdown_request(&args(&["yes"])).is_err());
+        assert!(lockdown_request(&[]).is_err());
+    }
+
+    #[test]
+    fn scope_table_lists_one_line_per_scope() {
+        let scopes = vec![ActiveScope {
+            unit: "authd-1000-42-1.scope".into(),
+            target: PathBuf::from("/usr/bin/gparted"),
+            caller: PathBuf::from("/usr/bin/authctl"),
+            uid: 1000,
+            pid: 4242,
+            started_secs: 1_000,
+        }];
+
+        let lines = scope_table(&scopes, 1_090);
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("UNIT"));
+        assert!(lines[1].starts_with("authd-1000-42-1.scope"));
+        assert!(lines[1].contains(" 4242 "));
+        assert!(lines[1].contains(" 1m "));
+        assert!(lines[1].ends_with("/usr/bin/gparted"));
+    }
+
+    #[test]
+    fn ages_use_the_largest_sensible_unit() {
+        assert_eq!(format_age(5), "5s");
+        assert_eq!(format_age(125), "2m");
+        assert_eq!(format_age(3_660), "1h01m");
+        assert_eq!(format_age(2 * 86_400), "2d");
+    }
+
     #[cfg(coverage)]
     #[test]
     fn coverage_main_stub_is_callable() {
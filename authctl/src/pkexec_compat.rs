@@ -1,6 +1,8 @@
 //! pkexec compatibility wrapper
 //! Translates pkexec-style arguments to authctl
 
+#[cfg(not(coverage))]
+use authd_protocol::messages::{Message, MessageId};
 #[cfg(not(coverage))]
 use std::env;
 #[cfg(not(coverage))]
@@ -37,7 +39,7 @@ fn main() {
     }
 
     if cmd_args.is_empty() {
-        eprintln!("pkexec: missing program");
+        eprintln!("pkexec: {}", Message::from(MessageId::MissingProgram));
         std::process::exit(1);
     }
 
@@ -47,7 +49,7 @@ fn main() {
     match status {
         Ok(s) => std::process::exit(s.code().unwrap_or(1)),
         Err(e) => {
-            eprintln!("pkexec: failed to run authctl: {}", e);
+            eprintln!("pkexec: {}", MessageId::RunAuthctlFailed.with(&[&e]));
             std::process::exit(1);
         }
     }
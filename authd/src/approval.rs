@@ -0,0 +1,808 @@
+//! Two-person rule (`require_approver_group`)
+//!
+//! A target under the rule runs only when the request carries the
+//! credentials of someone else: a member of every required group, other than
+//! the requester, whose password checks out against PAM (service `authd`, or
+//! the target rule's `pam_service`).
+//! Approvals are checked on every request and never cached.
+//!
+//! A PAM module can stall (an OTP push nobody answers, an unreachable
+//! server), so the password check runs on a blocking thread under a deadline
+//! (`pam_timeout_secs`). PAM offers no way to interrupt a conversation: a
+//! stalled one keeps its thread until the module returns, but the request is
+//! answered when the deadline passes.
+//!
+//! Where no PAM stack is configured (minimal containers), `pam_fallback`
+//! decides: `deny` (the default) refuses every approval saying why, `shadow`
+//! checks `/etc/shadow` directly through libcrypt.
+
+#[cfg(not(coverage))]
+use authd_policy::PamFallback;
+use authd_protocol::Approval;
+#[cfg(any(test, not(coverage)))]
+use authd_protocol::pam::DEFAULT_SERVICE;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A password-checking backend
+pub trait Authenticator: Send + Sync {
+    /// Whether the backend can check passwords on this system at all
+    fn available(&self) -> bool {
+        true
+    }
+
+    /// Check `password` through PAM `service` (`pam_service`), which
+    /// backends other than PAM ignore. Ok holds the verdict; Err means the
+    /// password couldn't be checked.
+    fn authenticate(&self, service: &str, user: &str, password: &str) -> Result<bool, String>;
+}
+
+/// `primary`, or `fallback` on systems where `primary` isn't available
+pub struct WithFallback {
+    pub primary: Box<dyn Authenticator>,
+    pub fallback: Box<dyn Authenticator>,
+}
+
+impl WithFallback {
+    fn active(&self) -> &dyn Authenticator {
+        if self.primary.available() {
+            self.primary.as_ref()
+        } else {
+            self.fallback.as_ref()
+        }
+    }
+}
+
+impl Authenticator for WithFallback {
+    fn available(&self) -> bool {
+        self.active().available()
+    }
+
+    fn authenticate(&self, service: &str, user: &str, password: &str) -> Result<bool, String> {
+        self.active().authenticate(service, user, password)
+    }
+}
+
+/// The `deny` fallback: refuses every approval, and says why
+pub struct NoBackend;
+
+impl Authenticator for NoBackend {
+    fn available(&self) -> bool {
+        false
+    }
+
+    fn authenticate(&self, _service: &str, _user: &str, _password: &str) -> Result<bool, String> {
+        Err("PAM is not configured and pam_fallback is \"deny\"".into())
+    }
+}
+
+/// PAM, falling back as `fallback` says where it isn't configured
+#[cfg(not(coverage))]
+pub fn backend(fallback: PamFallback) -> WithFallback {
+    WithFallback {
+        primary: Box::new(Pam),
+        fallback: fallback_backend(fallback),
+    }
+}
+
+#[cfg(not(coverage))]
+fn fallback_backend(fallback: PamFallback) -> Box<dyn Authenticator> {
+    match fallback {
+        PamFallback::Deny => Box::new(NoBackend),
+        PamFallback::Shadow => Box::new(Shadow),
+    }
+}
+
+/// Verify `approval` for a request by `caller_uid`, giving PAM `service` at
+/// most `timeout`; Ok holds the approver's name for the audit trail.
+pub async fn verify(
+    approval: Option<&Approval>,
+    caller_uid: u32,
+    groups: &[String],
+    authenticator: Arc<dyn Authenticator>,
+    service: &str,
+    timeout: Duration,
+) -> Result<String, String> {
+    let Some(approval) = approval else {
+        return Err(format!(
+            "requires approval by another member of {}",
+            groups.join(", ")
+        ));
+    };
+    let Some(approver_uid) = authd_policy::uid_from_username(&approval.user) else {
+        return Err(format!("unknown approver {}", approval.user));
+    };
+    if approver_uid == caller_uid {
+        return Err("the approver must be someone other than the requester".into());
+    }
+    if let Some(group) = groups
+        .iter()
+        .find(|group| !authd_policy::user_in_group(approver_uid, group))
+    {
+        return Err(format!("{} is not a member of {}", approval.user, group));
+    }
+    let checked = authenticate_within(
+        authenticator,
+        service,
+        &approval.user,
+        &approval.password,
+        timeout,
+    )
+    .await;
+    match checked {
+        Some(Ok(true)) => Ok(approval.user.clone()),
+        Some(Ok(false)) => Err(format!(
+            "authentication failed for approver {}",
+            approval.user
+        )),
+        Some(Err(reason)) => Err(format!(
+            "cannot authenticate approver {}: {}",
+            approval.user, reason
+        )),
+        None => Err(format!(
+            "authentication timed out for approver {} after {}s",
+            approval.user,
+            timeout.as_secs()
+        )),
+    }
+}
+
+/// Check the requester's own `password`, for `headless_confirm =
+/// "require_auth"`, and for `authsudo --test-auth`. Ok holds the verdict; an
+/// empty password is wrong without asking the backend.
+pub async fn verify_requester(
+    caller_uid: u32,
+    password: &str,
+    authenticator: Arc<dyn Authenticator>,
+    service: &str,
+    timeout: Duration,
+) -> Result<bool, String> {
+    let Some(user) = authd_policy::username_from_uid(caller_uid) else {
+        return Err(format!("uid {} has no user name", caller_uid));
+    };
+    if password.is_empty() {
+        return Ok(false);
+    }
+    match authenticate_within(authenticator, service, &user, password, timeout).await {
+        Some(verdict) => verdict,
+        None => Err(format!(
+            "authentication timed out after {}s",
+            timeout.as_secs()
+        )),
+    }
+}
+
+/// Run `authenticator` on a blocking thread, giving it at most `timeout`
+/// (None). A check that panicked counts as a wrong password.
+async fn authenticate_within(
+    authenticator: Arc<dyn Authenticator>,
+    service: &str,
+    user: &str,
+    password: &str,
+    timeout: Duration,
+) -> Option<Result<bool, String>> {
+    let (service, user, password) = (service.to_string(), user.to_string(), password.to_string());
+    let check =
+        tokio::task::spawn_blocking(move || authenticator.authenticate(&service, &user, &password));
+    match tokio::time::timeout(timeout, check).await {
+        Ok(Ok(verdict)) => Some(verdict),
+        Ok(Err(_)) => Some(Ok(false)),
+        Err(_) => None,
+    }
+}
+
+/// Password check through PAM; whether it's configured at all is judged by
+/// the `authd` service
+#[cfg(not(coverage))]
+pub struct Pam;
+
+#[cfg(not(coverage))]
+impl Authenticator for Pam {
+    fn available(&self) -> bool {
+        pam::configured(DEFAULT_SERVICE)
+    }
+
+    fn authenticate(&self, service: &str, user: &str, password: &str) -> Result<bool, String> {
+        pam::authenticate(service, user, password)
+    }
+}
+
+/// Password check against `/etc/shadow` (the `shadow` fallback); needs root
+#[cfg(not(coverage))]
+pub struct Shadow;
+
+#[cfg(not(coverage))]
+impl Authenticator for Shadow {
+    fn authenticate(&self, _service: &str, user: &str, password: &str) -> Result<bool, String> {
+        shadow::authenticate(user, password)
+    }
+}
+
+/// Why a shadow entry's aging fields (in days since the epoch, -1 where
+/// unset) keep it from approving on `today`, as pam_unix's account check
+/// has it: past `sp_expire`, a password its owner must change first
+/// (`sp_lstchg` 0), or one older than `sp_max`
+#[cfg_attr(coverage, allow(dead_code))]
+fn shadow_expiry(today: i64, lstchg: i64, max: i64, expire: i64) -> Option<&'static str> {
+    if expire >= 0 && today >= expire {
+        Some("account has expired")
+    } else if lstchg == 0 {
+        Some("password must be changed")
+    } else if max >= 0 && lstchg > 0 && today >= lstchg + max {
+        Some("password has expired")
+    } else {
+        None
+    }
+}
+
+/// Compare without exiting at the first difference, so timing doesn't tell
+/// how much of a hash matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(not(coverage))]
+mod shadow {
+    use libc::c_char;
+    use std::ffi::{CStr, CString};
+    use std::io;
+    use std::sync::Mutex;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[link(name = "crypt")]
+    unsafe extern "C" {
+        fn crypt(phrase: *const c_char, setting: *const c_char) -> *mut c_char;
+    }
+
+    /// `getspnam` and `crypt` both return static buffers
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    pub fn authenticate(user: &str, password: &str) -> Result<bool, String> {
+        let (Ok(user), Ok(password)) = (CString::new(user), CString::new(password)) else {
+            return Ok(false);
+        };
+        let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        // A miss leaves errno alone, so clear it to tell misses from errors
+        unsafe { *libc::__errno_location() = 0 };
+        let entry = unsafe { libc::getspnam(user.as_ptr()) };
+        if entry.is_null() {
+            return match io::Error::last_os_error().raw_os_error() {
+                Some(libc::EACCES) => {
+                    Err("cannot read /etc/shadow (authd must run as root)".into())
+                }
+                _ => Ok(false),
+            };
+        }
+        let hash = unsafe { CStr::from_ptr((*entry).sp_pwdp) }.to_owned();
+        let (lstchg, max, expire) =
+            unsafe { ((*entry).sp_lstchg, (*entry).sp_max, (*entry).sp_expire) };
+        // Locked ("!", "*") and passwordless accounts can't approve
+        if matches!(hash.to_bytes().first(), None | Some(b'!' | b'*')) {
+            return Ok(false);
+        }
+
+        // libxcrypt returns a "*"-prefixed failure token rather than null,
+        // which never equals a usable hash
+        let computed = unsafe { crypt(password.as_ptr(), hash.as_ptr()) };
+        if computed.is_null() {
+            return Err("crypt failed".into());
+        }
+        let computed = unsafe { CStr::from_ptr(computed) };
+        if !super::constant_time_eq(computed.to_bytes(), hash.to_bytes()) {
+            return Ok(false);
+        }
+        // Like pam_acct_mgmt after pam_authenticate: the right password
+        // for an expired account is an account problem, not a verdict
+        let today = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() / 86_400) as i64;
+        match super::shadow_expiry(today, lstchg.into(), max.into(), expire.into()) {
+            Some(reason) => Err(reason.into()),
+            None => Ok(true),
+        }
+    }
+}
+
+#[cfg(not(coverage))]
+mod pam {
+    use libc::{c_char, c_int, c_void};
+    use std::ffi::{CStr, CString};
+    use std::path::Path;
+
+    const PAM_SUCCESS: c_int = 0;
+    const PAM_AUTH_ERR: c_int = 7;
+    const PAM_USER_UNKNOWN: c_int = 10;
+    const PAM_MAXTRIES: c_int = 11;
+    const PAM_PROMPT_ECHO_OFF: c_int = 1;
+    const PAM_BUF_ERR: c_int = 5;
+    const PAM_CONV_ERR: c_int = 19;
+
+    // Layouts from <security/pam_appl.h>; PAM reads the fields authd doesn't
+    #[repr(C)]
+    #[allow(dead_code)]
+    struct PamMessage {
+        msg_style: c_int,
+        msg: *const c_char,
+    }
+
+    #[repr(C)]
+    #[allow(dead_code)]
+    struct PamResponse {
+        resp: *mut c_char,
+        resp_retcode: c_int,
+    }
+
+    type Converse = unsafe extern "C" fn(
+        c_int,
+        *mut *const PamMessage,
+        *mut *mut PamResponse,
+        *mut c_void,
+    ) -> c_int;
+
+    #[repr(C)]
+    struct PamConv {
+        conv: Converse,
+        appdata_ptr: *mut c_void,
+    }
+
+    enum PamHandle {}
+
+    #[link(name = "pam")]
+    unsafe extern "C" {
+        fn pam_start(
+            service: *const c_char,
+            user: *const c_char,
+            conv: *const PamConv,
+            pamh: *mut *mut PamHandle,
+        ) -> c_int;
+        fn pam_authenticate(pamh: *mut PamHandle, flags: c_int) -> c_int;
+        fn pam_acct_mgmt(pamh: *mut PamHandle, flags: c_int) -> c_int;
+        fn pam_end(pamh: *mut PamHandle, status: c_int) -> c_int;
+        fn pam_strerror(pamh: *mut PamHandle, errnum: c_int) -> *const c_char;
+    }
+
+    /// Answers password prompts with `appdata` (a `CString`), everything
+    /// else with nothing. PAM frees the responses.
+    unsafe extern "C" fn converse(
+        count: c_int,
+        messages: *mut *const PamMessage,
+        responses: *mut *mut PamResponse,
+        appdata: *mut c_void,
+    ) -> c_int {
+        let Ok(count) = usize::try_from(count) else {
+            return PAM_CONV_ERR;
+        };
+        let replies =
+            unsafe { libc::calloc(count, size_of::<PamResponse>()) }.cast::<PamResponse>();
+        if replies.is_null() {
+            return PAM_BUF_ERR;
+        }
+        let password = unsafe { &*appdata.cast::<CString>() };
+        for i in 0..count {
+            let message = unsafe { &**messages.add(i) };
+            if message.msg_style == PAM_PROMPT_ECHO_OFF {
+                let copy = unsafe { libc::strdup(password.as_ptr()) };
+                if copy.is_null() {
+                    // PAM never gets to see `replies`, so it won't free them
+                    for j in 0..i {
+                        unsafe { libc::free((*replies.add(j)).resp.cast()) };
+                    }
+                    unsafe { libc::free(replies.cast()) };
+                    return PAM_BUF_ERR;
+                }
+                unsafe { (*replies.add(i)).resp = copy };
+            }
+        }
+        unsafe { *responses = replies };
+        PAM_SUCCESS
+    }
+
+    /// A stack for `service`, or the `other` one PAM falls back to, exists
+    pub fn configured(service: &str) -> bool {
+        Path::new("/etc/pam.d").join(service).exists()
+            || Path::new("/etc/pam.d/other").exists()
+            || Path::new("/etc/pam.conf").exists()
+    }
+
+    pub fn authenticate(service: &str, user: &str, password: &str) -> Result<bool, String> {
+        let (Ok(service), Ok(user), Ok(mut password)) = (
+            CString::new(service),
+            CString::new(user),
+            CString::new(password),
+        ) else {
+            return Ok(false);
+        };
+        let conv = PamConv {
+            conv: converse,
+            appdata_ptr: (&raw mut password).cast(),
+        };
+
+        let mut handle = std::ptr::null_mut();
+        let started = unsafe { pam_start(service.as_ptr(), user.as_ptr(), &conv, &mut handle) };
+        if started != PAM_SUCCESS {
+            return Err(format!("pam_start failed ({})", started));
+        }
+        let mut step = "pam_authenticate";
+        let mut status = unsafe { pam_authenticate(handle, 0) };
+        if status == PAM_SUCCESS {
+            // Expired or locked accounts can't approve either
+            step = "pam_acct_mgmt";
+            status = unsafe { pam_acct_mgmt(handle, 0) };
+        }
+        // A wrong password (or user) is a verdict; anything else is a
+        // module or account problem, reported in PAM's own words
+        let verdict = match status {
+            PAM_SUCCESS => Ok(true),
+            PAM_AUTH_ERR | PAM_USER_UNKNOWN | PAM_MAXTRIES => Ok(false),
+            _ => Err(format!("{}: {}", step, strerror(handle, status))),
+        };
+        unsafe { pam_end(handle, status) };
+        verdict
+    }
+
+    fn strerror(handle: *mut PamHandle, status: c_int) -> String {
+        let text = unsafe { pam_strerror(handle, status) };
+        if text.is_null() {
+            return format!("PAM error {}", status);
+        }
+        unsafe { CStr::from_ptr(text) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Accepts exactly one user/password pair
+#[cfg(test)]
+pub struct ScriptedAuthenticator {
+    pub user: String,
+    pub password: String,
+}
+
+#[cfg(test)]
+impl Authenticator for ScriptedAuthenticator {
+    fn authenticate(&self, _service: &str, user: &str, password: &str) -> Result<bool, String> {
+        Ok(user == self.user && password == self.password)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::sync::mpsc::{self, Receiver};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Never answers until the sender of `release` is dropped, like a PAM
+    /// module waiting on an OTP push nobody accepts
+    struct StalledAuthenticator {
+        release: Mutex<Receiver<()>>,
+    }
+
+    impl Authenticator for StalledAuthenticator {
+        fn authenticate(
+            &self,
+            _service: &str,
+            _user: &str,
+            _password: &str,
+        ) -> Result<bool, String> {
+            let release = self.release.lock().unwrap_or_else(|e| e.into_inner());
+            let _ = release.recv();
+            Ok(false)
+        }
+    }
+
+    /// A backend missing on this system, like PAM in a minimal container
+    struct Missing;
+
+    impl Authenticator for Missing {
+        fn available(&self) -> bool {
+            false
+        }
+
+        fn authenticate(
+            &self,
+            _service: &str,
+            _user: &str,
+            _password: &str,
+        ) -> Result<bool, String> {
+            panic!("an unavailable backend must not be asked");
+        }
+    }
+
+    /// Accepts anything, noting which PAM service it was asked through
+    #[derive(Default)]
+    struct RecordsService(Mutex<Vec<String>>);
+
+    impl Authenticator for RecordsService {
+        fn authenticate(
+            &self,
+            service: &str,
+            _user: &str,
+            _password: &str,
+        ) -> Result<bool, String> {
+            self.0.lock().unwrap().push(service.to_string());
+            Ok(true)
+        }
+    }
+
+    fn without_pam(fallback: Box<dyn Authenticator>) -> Arc<dyn Authenticator> {
+        Arc::new(WithFallback {
+            primary: Box::new(Missing),
+            fallback,
+        })
+    }
+
+    fn root_accepts(password: &str) -> Arc<dyn Authenticator> {
+        Arc::from(root_accepts_boxed(password))
+    }
+
+    fn root_accepts_boxed(password: &str) -> Box<dyn Authenticator> {
+        Box::new(ScriptedAuthenticator {
+            user: "root".into(),
+            password: password.into(),
+        })
+    }
+
+    fn approval(user: &str, password: &str) -> Approval {
+        Approval {
+            user: user.into(),
+            password: password.into(),
+            reason: None,
+        }
+    }
+
+    // root and its primary group exist on every Linux system
+    fn root_group() -> Vec<String> {
+        vec!["root".to_string()]
+    }
+
+    #[tokio::test]
+    async fn different_member_with_the_right_password_approves() {
+        let approved = verify(
+            Some(&approval("root", "hunter2")),
+            1000,
+            &root_group(),
+            root_accepts("hunter2"),
+            DEFAULT_SERVICE,
+            TIMEOUT,
+        )
+        .await;
+        assert_eq!(approved, Ok("root".to_string()));
+    }
+
+    #[tokio::test]
+    async fn requester_cannot_approve_their_own_request() {
+        let error = verify(
+            Some(&approval("root", "hunter2")),
+            0,
+            &root_group(),
+            root_accepts("hunter2"),
+            DEFAULT_SERVICE,
+            TIMEOUT,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            error.contains("someone other than the requester"),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn wrong_password_or_missing_approval_is_refused() {
+        let auth = root_accepts("hunter2");
+        let wrong = verify(
+            Some(&approval("root", "nope")),
+            1000,
+            &root_group(),
+            auth.clone(),
+            DEFAULT_SERVICE,
+            TIMEOUT,
+        )
+        .await;
+        assert!(wrong.is_err());
+
+        let error = verify(None, 1000, &root_group(), auth, DEFAULT_SERVICE, TIMEOUT)
+            .await
+            .unwrap_err();
+        assert!(error.starts_with("requires approval"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn approver_must_be_in_every_required_group() {
+        let groups = vec!["root".to_string(), "authd-no-such-group".to_string()];
+        let error = verify(
+            Some(&approval("root", "hunter2")),
+            1000,
+            &groups,
+            root_accepts("hunter2"),
+            DEFAULT_SERVICE,
+            TIMEOUT,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            error.contains("not a member of authd-no-such-group"),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn stalled_pam_conversation_times_out() {
+        let (release, stalled) = mpsc::channel();
+        let auth = Arc::new(StalledAuthenticator {
+            release: Mutex::new(stalled),
+        });
+
+        let error = verify(
+            Some(&approval("root", "hunter2")),
+            1000,
+            &root_group(),
+            auth,
+            DEFAULT_SERVICE,
+            Duration::from_millis(50),
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            error.starts_with("authentication timed out for approver root"),
+            "{error}"
+        );
+        // Let the stuck blocking thread finish so the runtime can shut down
+        drop(release);
+    }
+
+    #[tokio::test]
+    async fn configured_primary_is_used_over_the_fallback() {
+        let auth = Arc::new(WithFallback {
+            primary: Box::new(ScriptedAuthenticator {
+                user: "root".into(),
+                password: "hunter2".into(),
+            }),
+            fallback: Box::new(NoBackend),
+        });
+        let approved = verify(
+            Some(&approval("root", "hunter2")),
+            1000,
+            &root_group(),
+            auth,
+            DEFAULT_SERVICE,
+            TIMEOUT,
+        )
+        .await;
+        assert_eq!(approved, Ok("root".to_string()));
+    }
+
+    #[tokio::test]
+    async fn missing_pam_with_deny_fallback_refuses_saying_why() {
+        let error = verify(
+            Some(&approval("root", "hunter2")),
+            1000,
+            &root_group(),
+            without_pam(Box::new(NoBackend)),
+            DEFAULT_SERVICE,
+            TIMEOUT,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error,
+            "cannot authenticate approver root: PAM is not configured and pam_fallback is \"deny\""
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_pam_uses_the_configured_fallback() {
+        let approved = verify(
+            Some(&approval("root", "hunter2")),
+            1000,
+            &root_group(),
+            without_pam(root_accepts_boxed("hunter2")),
+            DEFAULT_SERVICE,
+            TIMEOUT,
+        )
+        .await;
+        assert_eq!(approved, Ok("root".to_string()));
+    }
+
+    #[tokio::test]
+    async fn requester_password_is_checked_for_their_own_account() {
+        let checked = verify_requester(
+            0,
+            "hunter2",
+            root_accepts("hunter2"),
+            DEFAULT_SERVICE,
+            TIMEOUT,
+        )
+        .await;
+        assert_eq!(checked, Ok(true));
+        let checked = verify_requester(
+            0,
+            "wrong",
+            root_accepts("hunter2"),
+            DEFAULT_SERVICE,
+            TIMEOUT,
+        )
+        .await;
+        assert_eq!(checked, Ok(false));
+        // Nothing typed is never checked, let alone accepted
+        let checked = verify_requester(0, "", root_accepts(""), DEFAULT_SERVICE, TIMEOUT).await;
+        assert_eq!(checked, Ok(false));
+    }
+
+    #[tokio::test]
+    async fn the_rules_pam_service_is_the_one_asked() {
+        let auth = Arc::new(RecordsService::default());
+        let approved = verify(
+            Some(&approval("root", "hunter2")),
+            1000,
+            &root_group(),
+            auth.clone(),
+            "authd-power",
+            TIMEOUT,
+        )
+        .await;
+        assert_eq!(approved, Ok("root".to_string()));
+        let checked = verify_requester(0, "hunter2", auth.clone(), "authd-strict", TIMEOUT).await;
+        assert_eq!(checked, Ok(true));
+
+        assert_eq!(*auth.0.lock().unwrap(), ["authd-power", "authd-strict"]);
+    }
+
+    #[cfg(not(coverage))]
+    #[test]
+    fn pam_fallback_setting_picks_the_backend() {
+        let deny = fallback_backend(PamFallback::Deny);
+        assert!(!deny.available());
+        assert!(
+            deny.authenticate(DEFAULT_SERVICE, "root", "hunter2")
+                .is_err()
+        );
+
+        // Without root the shadow file can't be read; either way an unknown
+        // user never gets in
+        let shadow = fallback_backend(PamFallback::Shadow);
+        assert!(shadow.available());
+        assert_ne!(
+            shadow.authenticate(DEFAULT_SERVICE, "authd-no-such-user", "x"),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn expired_shadow_accounts_cannot_approve() {
+        let today = 20_000;
+        // No aging at all, and a password changed yesterday with a max
+        assert_eq!(shadow_expiry(today, -1, -1, -1), None);
+        assert_eq!(shadow_expiry(today, today - 1, 90, today + 1), None);
+
+        assert_eq!(
+            shadow_expiry(today, today - 1, 90, today),
+            Some("account has expired")
+        );
+        assert_eq!(
+            shadow_expiry(today, 0, -1, -1),
+            Some("password must be changed")
+        );
+        assert_eq!(
+            shadow_expiry(today, today - 90, 90, -1),
+            Some("password has expired")
+        );
+    }
+
+    #[test]
+    fn hashes_compare_in_full() {
+        assert!(constant_time_eq(b"$6$salt$hash", b"$6$salt$hash"));
+        assert!(!constant_time_eq(b"$6$salt$hash", b"$6$salt$hasH"));
+        assert!(!constant_time_eq(b"$6$salt$hash", b"$6$salt$has"));
+    }
+
+    #[test]
+    fn approval_debug_hides_the_password() {
+        let debug = format!("{:?}", approval("root", "hunter2"));
+        assert!(debug.contains("root"));
+        assert!(!debug.contains("hunter2"));
+    }
+}
@@ -0,0 +1,331 @@
+//! Audit trail for authorization outcomes
+//!
+//! With the default `log_format = "text"`, events go to the `authd::audit`
+//! tracing target so they can be filtered into their own journal stream.
+//! Overriding a discouraged action is logged at `warn` so it stands out.
+//!
+//! With `log_format = "json"`, each event is written to stdout as one JSON
+//! object per line, for SIEM tooling to ingest:
+//!
+//! | key              | type           | meaning |
+//! |------------------|----------------|---------|
+//! | `schema_version` | number         | [`SCHEMA_VERSION`] |
+//! | `time`           | number         | Unix time in seconds |
+//! | `event`          | string         | `discouraged`, `approval` or `confirm_timeout` |
+//! | `outcome`        | string         | `allowed` or `denied` |
+//! | `uid`            | number         | requesting user |
+//! | `pid`            | number         | requesting process |
+//! | `target`         | string         | program the request was for |
+//! | `reason`         | string         | human-readable explanation |
+//! | `approver`       | string or null | who approved, for `approval` events |
+//!
+//! Within a schema version changes are additive only: keys are never removed,
+//! renamed or given another type, so consumers should ignore keys they don't
+//! know. Anything else bumps [`SCHEMA_VERSION`].
+//!
+//! Whatever the log format, every event is also published as that JSON to
+//! an [`AuditFeed`], which admins follow live with `SubscribeAudit`.
+
+use authd_policy::LogFormat;
+use serde::{Serialize, Serializer};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+/// Version of the JSON event schema described above
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Events an [`AuditFeed`] holds for a subscriber that hasn't read them
+/// yet, before the oldest are dropped for it
+pub const FEED_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEventType {
+    /// The user's answer to the warning for an `auth = "discourage"` target
+    Discouraged,
+    /// A two-person rule approval was checked (`require_approver_group`)
+    Approval,
+    /// A confirmation dialog went unanswered until it timed out
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    ConfirmTimeout,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Allowed,
+    Denied,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditEvent {
+    pub event: AuditEventType,
+    pub outcome: AuditOutcome,
+    pub uid: u32,
+    pub pid: u32,
+    #[serde(serialize_with = "lossy_path")]
+    pub target: PathBuf,
+    pub reason: String,
+    pub approver: Option<String>,
+}
+
+/// An event as written in JSON mode
+#[derive(Serialize)]
+struct Record<'a> {
+    schema_version: u32,
+    time: u64,
+    #[serde(flatten)]
+    event: &'a AuditEvent,
+}
+
+/// Paths needn't be UTF-8, which JSON strings must be
+pub(crate) fn lossy_path<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+impl AuditEvent {
+    pub fn record(&self, format: LogFormat) {
+        match format {
+            LogFormat::Text => self.log(),
+            LogFormat::Json => {
+                let line = self.to_json(now_secs());
+                let _ = writeln!(std::io::stdout().lock(), "{}", line);
+            }
+        }
+    }
+
+    /// The event as one line of JSON, stamped with `time`
+    pub fn to_json(&self, time: u64) -> String {
+        let record = Record {
+            schema_version: SCHEMA_VERSION,
+            time,
+            event: self,
+        };
+        // Plain structs with string keys and a lossy target: serializing
+        // can't fail
+        serde_json::to_string(&record).unwrap_or_default()
+    }
+
+    fn log(&self) {
+        let target = self.target.display();
+        let discouraged = self.event == AuditEventType::Discouraged;
+        match (self.outcome, discouraged) {
+            (AuditOutcome::Allowed, true) => warn!(
+                target: "authd::audit",
+                uid = self.uid,
+                pid = self.pid,
+                "DISCOURAGED action allowed by user override: {target} ({})",
+                self.reason
+            ),
+            (AuditOutcome::Allowed, false) => info!(
+                target: "authd::audit",
+                uid = self.uid,
+                pid = self.pid,
+                "allowed: {target} ({})",
+                self.reason
+            ),
+            (AuditOutcome::Denied, _) => info!(
+                target: "authd::audit",
+                uid = self.uid,
+                pid = self.pid,
+                discouraged,
+                "denied: {target} ({})",
+                self.reason
+            ),
+        }
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Recorded events, as JSON lines, for live subscribers. Publishing never
+/// waits: a subscriber that falls more than the feed's capacity behind
+/// loses the oldest events and is told how many on its next read.
+#[derive(Debug, Clone)]
+pub struct AuditFeed(broadcast::Sender<String>);
+
+impl AuditFeed {
+    pub fn new(capacity: usize) -> Self {
+        Self(broadcast::channel(capacity).0)
+    }
+
+    /// Receive every event published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.0.subscribe()
+    }
+
+    pub fn publish(&self, event: &AuditEvent) {
+        // No subscribers is the usual case, not an error
+        let _ = self.0.send(event.to_json(now_secs()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::{Value, json};
+
+    fn sample(event: AuditEventType, outcome: AuditOutcome) -> AuditEvent {
+        AuditEvent {
+            event,
+            outcome,
+            uid: 1000,
+            pid: 4242,
+            target: PathBuf::from("/usr/bin/rm"),
+            reason: "user proceeded past warning".into(),
+            approver: None,
+        }
+    }
+
+    fn parsed(event: &AuditEvent) -> Value {
+        serde_json::from_str(&event.to_json(1_700_000_000)).unwrap()
+    }
+
+    #[test]
+    fn discouraged_event_shape() {
+        let event = sample(AuditEventType::Discouraged, AuditOutcome::Allowed);
+        assert_eq!(
+            parsed(&event),
+            json!({
+                "schema_version": 1,
+                "time": 1_700_000_000,
+                "event": "discouraged",
+                "outcome": "allowed",
+                "uid": 1000,
+                "pid": 4242,
+                "target": "/usr/bin/rm",
+                "reason": "user proceeded past warning",
+                "approver": null,
+            })
+        );
+    }
+
+    #[test]
+    fn approval_event_shape() {
+        let event = AuditEvent {
+            reason: "approved by alice".into(),
+            approver: Some("alice".into()),
+            ..sample(AuditEventType::Approval, AuditOutcome::Allowed)
+        };
+        assert_eq!(
+            parsed(&event),
+            json!({
+                "schema_version": 1,
+                "time": 1_700_000_000,
+                "event": "approval",
+                "outcome": "allowed",
+                "uid": 1000,
+                "pid": 4242,
+                "target": "/usr/bin/rm",
+                "reason": "approved by alice",
+                "approver": "alice",
+            })
+        );
+
+        let denied = sample(AuditEventType::Approval, AuditOutcome::Denied);
+        assert_eq!(parsed(&denied)["outcome"], "denied");
+    }
+
+    #[test]
+    fn confirm_timeouts_have_an_event_of_their_own() {
+        let event = sample(AuditEventType::ConfirmTimeout, AuditOutcome::Denied);
+        assert_eq!(parsed(&event)["event"], "confirm_timeout");
+    }
+
+    #[test]
+    fn non_utf8_targets_still_serialize() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let event = AuditEvent {
+            target: PathBuf::from(OsStr::from_bytes(b"/tmp/\xff")),
+            ..sample(AuditEventType::Discouraged, AuditOutcome::Denied)
+        };
+        assert_eq!(parsed(&event)["target"], "/tmp/\u{fffd}");
+    }
+
+    #[test]
+    fn subscribers_get_events_published_after_they_subscribe() {
+        let feed = AuditFeed::new(FEED_CAPACITY);
+        feed.publish(&sample(AuditEventType::Approval, AuditOutcome::Denied));
+        let mut events = feed.subscribe();
+        feed.publish(&sample(AuditEventType::Discouraged, AuditOutcome::Allowed));
+
+        let line = events.try_recv().unwrap();
+        let event: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(event["event"], "discouraged");
+        assert_eq!(event["schema_version"], SCHEMA_VERSION);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn a_slow_subscriber_is_told_what_it_missed() {
+        let feed = AuditFeed::new(2);
+        let mut events = feed.subscribe();
+        for _ in 0..5 {
+            feed.publish(&sample(AuditEventType::Approval, AuditOutcome::Allowed));
+        }
+
+        assert_eq!(
+            events.try_recv(),
+            Err(broadcast::error::TryRecvError::Lagged(3))
+        );
+        assert!(events.try_recv().is_ok());
+        assert!(events.try_recv().is_ok());
+    }
+
+    #[test]
+    fn one_event_per_line() {
+        let event = AuditEvent {
+            reason: "line\nbreak".into(),
+            ..sample(AuditEventType::Discouraged, AuditOutcome::Denied)
+        };
+        assert!(!event.to_json(0).contains('\n'));
+    }
+
+    /// Frozen copy of a version 1 event. New keys may appear, but everything
+    /// here must keep its name, type and meaning while the version stays 1.
+    const V1_EVENT: &str = r#"{"schema_version":1,"time":1700000000,"event":"discouraged","outcome":"allowed","uid":1000,"pid":4242,"target":"/usr/bin/rm","reason":"user proceeded past warning","approver":null}"#;
+
+    #[test]
+    fn current_events_keep_every_v1_key() {
+        let v1: Value = serde_json::from_str(V1_EVENT).unwrap();
+        let current = parsed(&sample(AuditEventType::Discouraged, AuditOutcome::Allowed));
+
+        assert_eq!(current["schema_version"], SCHEMA_VERSION);
+        for (key, value) in v1.as_object().unwrap() {
+            assert_eq!(&current[key], value, "v1 key {key} changed");
+        }
+    }
+
+    #[test]
+    fn old_consumers_ignore_added_keys() {
+        /// What a consumer written against version 1 might read
+        #[derive(Deserialize)]
+        struct V1Consumer {
+            schema_version: u32,
+            event: String,
+            outcome: String,
+            uid: u32,
+            target: String,
+        }
+
+        let mut newer: Value = serde_json::from_str(V1_EVENT).unwrap();
+        newer["session_type"] = json!("ssh");
+        let read: V1Consumer = serde_json::from_value(newer).unwrap();
+
+        assert_eq!(read.schema_version, 1);
+        assert_eq!(read.event, "discouraged");
+        assert_eq!(read.outcome, "allowed");
+        assert_eq!(read.uid, 1000);
+        assert_eq!(read.target, "/usr/bin/rm");
+    }
+}
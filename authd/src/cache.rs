@@ -0,0 +1,624 @@
+//! Per-user authorization cache
+//!
+//! After a user confirms a target, repeat requests for the same (uid, target)
+//! skip the dialog until the rule's `cache_timeout` elapses. Deadlines use
+//! `Instant`, so wall-clock changes can't stretch or shorten them.
+//!
+//! Each entry records the [`Kind`] of authorization it stands for. A rule
+//! asking for a confirmation alone is remembered for its
+//! `confirm_cache_timeout` and satisfies only confirm rules; anything else
+//! (a password, a method list) is remembered for `cache_timeout` and
+//! satisfies both.
+//!
+//! The cache is saved to `CACHE_STATE_PATH` (or a named instance's own file,
+//! see `state_path`) so a daemon restart doesn't re-prompt. `Instant` doesn't
+//! survive a restart, so the saved form uses wall-clock deadlines; those are
+//! distrusted on load (see `restore_at`).
+
+use crate::runtime_file;
+use authd_protocol::instance::{INSTANCE_DIR, InstanceName};
+use authd_protocol::{AuthMethod, MethodSet};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// On tmpfs, so the cache never outlives a reboot
+pub const CACHE_STATE_PATH: &str = "/run/authd/cache";
+
+/// Where `instance` keeps its cache; the default instance uses `CACHE_STATE_PATH`
+pub fn state_path(instance: Option<&InstanceName>) -> PathBuf {
+    match instance {
+        Some(name) => Path::new(INSTANCE_DIR).join(format!("{}.cache", name)),
+        None => PathBuf::from(CACHE_STATE_PATH),
+    }
+}
+
+/// A restored deadline further ahead than its timeout plus this is treated
+/// as corrupt or the product of a large clock jump, and discarded.
+const MAX_CLOCK_STEP: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What a cached authorization was given for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// A rule asking for more than a confirmation
+    Credential,
+    /// A rule asking for a confirmation alone. Entries saved before kinds
+    /// were recorded restore as this, the kind that satisfies less.
+    #[default]
+    Confirmation,
+}
+
+impl Kind {
+    /// The kind a rule accepting `methods` needs
+    pub fn needed(methods: Option<&MethodSet>) -> Self {
+        match methods {
+            Some(MethodSet::Any(methods)) if methods == &[AuthMethod::Confirm] => {
+                Self::Confirmation
+            }
+            _ => Self::Credential,
+        }
+    }
+
+    /// Whether an authorization of this kind satisfies a rule needing `needed`
+    fn satisfies(self, needed: Kind) -> bool {
+        self == Self::Credential || needed == Self::Confirmation
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AuthCache {
+    entries: HashMap<(u32, PathBuf), Entry>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    deadline: Instant,
+    /// The rule's cache window at insert time; bounds restored deadlines
+    timeout: Duration,
+    kind: Kind,
+}
+
+/// A cache entry as saved across restarts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PersistedEntry {
+    pub uid: u32,
+    pub target: PathBuf,
+    /// Wall-clock expiry, seconds since the Unix epoch
+    pub deadline_secs: u64,
+    pub timeout_secs: u64,
+    #[serde(default)]
+    pub kind: Kind,
+}
+
+impl AuthCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cache a successful authorization. A zero timeout caches nothing.
+    pub fn insert(&mut self, uid: u32, target: &Path, kind: Kind, timeout: Duration) {
+        self.insert_at(uid, target, kind, timeout, Instant::now());
+    }
+
+    fn insert_at(&mut self, uid: u32, target: &Path, kind: Kind, timeout: Duration, now: Instant) {
+        self.prune_at(now);
+        if timeout.is_zero() {
+            return;
+        }
+        let entry = Entry {
+            deadline: now + timeout,
+            timeout,
+            kind,
+        };
+        self.entries.insert((uid, target.to_path_buf()), entry);
+    }
+
+    /// Time left before a cached authorization satisfying a rule that needs
+    /// `needed` expires, if there is one
+    pub fn remaining(&self, uid: u32, target: &Path, needed: Kind) -> Option<Duration> {
+        self.remaining_at(uid, target, needed, Instant::now())
+    }
+
+    fn remaining_at(
+        &self,
+        uid: u32,
+        target: &Path,
+        needed: Kind,
+        now: Instant,
+    ) -> Option<Duration> {
+        let entry = self.entries.get(&(uid, target.to_path_buf()))?;
+        if !entry.kind.satisfies(needed) {
+            return None;
+        }
+        entry
+            .deadline
+            .checked_duration_since(now)
+            .filter(|left| !left.is_zero())
+    }
+
+    /// Targets `uid` has an unexpired cached authorization for, with the time
+    /// left on each, sorted by target
+    pub fn entries_for(&self, uid: u32) -> Vec<(PathBuf, Duration)> {
+        self.entries_for_at(uid, Instant::now())
+    }
+
+    fn entries_for_at(&self, uid: u32, now: Instant) -> Vec<(PathBuf, Duration)> {
+        let mut entries: Vec<(PathBuf, Duration)> = self
+            .entries
+            .keys()
+            .filter(|(owner, _)| *owner == uid)
+            .filter_map(|(_, target)| {
+                // Every kind satisfies a confirmation
+                let left = self.remaining_at(uid, target, Kind::Confirmation, now)?;
+                Some((target.clone(), left))
+            })
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    /// Drop expired entries
+    fn prune_at(&mut self, now: Instant) {
+        self.entries.retain(|_, entry| entry.deadline > now);
+    }
+
+    /// Load the saved cache. A missing or unreadable file is an empty cache.
+    pub fn load(path: &Path) -> Self {
+        let entries = match fs::read(path) {
+            Ok(bytes) => rmp_serde::from_slice(&bytes).unwrap_or_else(|e| {
+                warn!("ignoring corrupt auth cache {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                warn!("ignoring unreadable auth cache {}: {}", path.display(), e);
+                Vec::new()
+            }
+        };
+        Self::restore_at(entries, Instant::now(), SystemTime::now())
+    }
+
+    /// Atomically replace the saved cache (mode 0600).
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let entries = self.snapshot_at(Instant::now(), SystemTime::now());
+        let bytes = rmp_serde::to_vec(&entries).map_err(io::Error::other)?;
+        runtime_file::write_atomic(path, &bytes, runtime_file::STATE_MODE)
+    }
+
+    fn snapshot_at(&self, now: Instant, wall: SystemTime) -> Vec<PersistedEntry> {
+        self.entries
+            .iter()
+            .filter_map(|((uid, target), entry)| {
+                let left = entry.deadline.checked_duration_since(now)?;
+                let deadline = (wall + left).duration_since(UNIX_EPOCH).ok()?;
+                Some(PersistedEntry {
+                    uid: *uid,
+                    target: target.clone(),
+                    // Round down: a restored entry may expire early, never late
+                    deadline_secs: deadline.as_secs(),
+                    timeout_secs: entry.timeout.as_secs(),
+                    kind: entry.kind,
+                })
+            })
+            .collect()
+    }
+
+    /// Rebuild a cache from saved entries. The wall clock may have moved
+    /// since they were written (NTP step, manual change), so no entry gets
+    /// more than its original timeout from `now`, and entries that claim to
+    /// be implausibly far ahead are dropped.
+    fn restore_at(entries: Vec<PersistedEntry>, now: Instant, wall: SystemTime) -> Self {
+        let mut cache = Self::new();
+        for saved in entries {
+            let timeout = Duration::from_secs(saved.timeout_secs);
+            let deadline = UNIX_EPOCH + Duration::from_secs(saved.deadline_secs);
+            // Already past (or the clock jumped forward): expired
+            let Ok(left) = deadline.duration_since(wall) else {
+                continue;
+            };
+            if left.is_zero() || timeout.is_zero() {
+                continue;
+            }
+            if left > timeout + MAX_CLOCK_STEP {
+                warn!(
+                    "discarding cached authorization for uid={} {}: deadline {}s ahead",
+                    saved.uid,
+                    saved.target.display(),
+                    left.as_secs()
+                );
+                continue;
+            }
+            if left > timeout {
+                warn!(
+                    "clock moved back; clamping cached authorization for uid={} {} to {}s",
+                    saved.uid,
+                    saved.target.display(),
+                    timeout.as_secs()
+                );
+            }
+            let entry = Entry {
+                deadline: now + left.min(timeout),
+                timeout,
+                kind: saved.kind,
+            };
+            cache.entries.insert((saved.uid, saved.target), entry);
+        }
+        cache
+    }
+}
+
+/// Whole seconds left, rounded up so "1ms left" still reads as valid
+pub fn remaining_secs(remaining: Duration) -> u64 {
+    remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGET: &str = "/usr/bin/gparted";
+
+    #[test]
+    fn instances_keep_separate_caches() {
+        let test = InstanceName::new("test").unwrap();
+        assert_eq!(state_path(None), PathBuf::from(CACHE_STATE_PATH));
+        assert_eq!(
+            state_path(Some(&test)),
+            PathBuf::from("/run/authd/test.cache")
+        );
+    }
+
+    #[test]
+    fn remaining_counts_down_to_expiry() {
+        let mut cache = AuthCache::new();
+        let start = Instant::now();
+        cache.insert_at(
+            1000,
+            Path::new(TARGET),
+            Kind::Confirmation,
+            Duration::from_secs(300),
+            start,
+        );
+
+        assert_eq!(
+            cache.remaining_at(1000, Path::new(TARGET), Kind::Confirmation, start),
+            Some(Duration::from_secs(300))
+        );
+        assert_eq!(
+            cache.remaining_at(
+                1000,
+                Path::new(TARGET),
+                Kind::Confirmation,
+                start + Duration::from_secs(60)
+            ),
+            Some(Duration::from_secs(240))
+        );
+    }
+
+    #[test]
+    fn remaining_near_expiry_rounds_up_then_expires() {
+        let mut cache = AuthCache::new();
+        let start = Instant::now();
+        cache.insert_at(
+            1000,
+            Path::new(TARGET),
+            Kind::Confirmation,
+            Duration::from_secs(300),
+            start,
+        );
+
+        let almost = start + Duration::from_millis(299_999);
+        let left = cache
+            .remaining_at(1000, Path::new(TARGET), Kind::Confirmation, almost)
+            .unwrap();
+        assert_eq!(left, Duration::from_millis(1));
+        assert_eq!(remaining_secs(left), 1);
+
+        let deadline = start + Duration::from_secs(300);
+        assert_eq!(
+            cache.remaining_at(1000, Path::new(TARGET), Kind::Confirmation, deadline),
+            None
+        );
+        let after = deadline + Duration::from_secs(1);
+        assert_eq!(
+            cache.remaining_at(1000, Path::new(TARGET), Kind::Confirmation, after),
+            None
+        );
+    }
+
+    #[test]
+    fn entries_are_scoped_to_uid_and_target() {
+        let mut cache = AuthCache::new();
+        cache.insert(
+            1000,
+            Path::new(TARGET),
+            Kind::Confirmation,
+            Duration::from_secs(300),
+        );
+
+        assert!(
+            cache
+                .remaining(1000, Path::new(TARGET), Kind::Confirmation)
+                .is_some()
+        );
+        assert!(
+            cache
+                .remaining(1001, Path::new(TARGET), Kind::Confirmation)
+                .is_none()
+        );
+        assert!(
+            cache
+                .remaining(1000, Path::new("/usr/bin/other"), Kind::Confirmation)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn listing_shows_only_the_users_own_unexpired_entries() {
+        let mut cache = AuthCache::new();
+        let start = Instant::now();
+        let minute = Duration::from_secs(60);
+        cache.insert_at(
+            1000,
+            Path::new(TARGET),
+            Kind::Confirmation,
+            5 * minute,
+            start,
+        );
+        cache.insert_at(
+            1000,
+            Path::new("/usr/bin/id"),
+            Kind::Confirmation,
+            minute,
+            start,
+        );
+        cache.insert_at(
+            1001,
+            Path::new("/usr/bin/other"),
+            Kind::Confirmation,
+            5 * minute,
+            start,
+        );
+
+        assert_eq!(
+            cache.entries_for_at(1000, start + minute / 2),
+            vec![
+                (PathBuf::from("/usr/bin/gparted"), 4 * minute + minute / 2),
+                (PathBuf::from("/usr/bin/id"), minute / 2),
+            ]
+        );
+        // Expired entries drop out even before anything prunes them
+        assert_eq!(
+            cache.entries_for_at(1000, start + 2 * minute),
+            vec![(PathBuf::from(TARGET), 3 * minute)]
+        );
+        assert!(cache.entries_for_at(1002, start).is_empty());
+    }
+
+    #[test]
+    fn confirmations_satisfy_only_confirm_rules() {
+        let mut cache = AuthCache::new();
+        let minute = Duration::from_secs(60);
+        cache.insert(1000, Path::new(TARGET), Kind::Confirmation, minute);
+        cache.insert(1000, Path::new("/usr/bin/id"), Kind::Credential, minute);
+
+        assert!(
+            cache
+                .remaining(1000, Path::new(TARGET), Kind::Confirmation)
+                .is_some()
+        );
+        assert!(
+            cache
+                .remaining(1000, Path::new(TARGET), Kind::Credential)
+                .is_none()
+        );
+        // A credential stands in for a confirmation
+        for needed in [Kind::Confirmation, Kind::Credential] {
+            assert!(
+                cache
+                    .remaining(1000, Path::new("/usr/bin/id"), needed)
+                    .is_some()
+            );
+        }
+    }
+
+    #[test]
+    fn kinds_follow_the_methods_a_rule_accepts() {
+        let any = |methods: &[AuthMethod]| MethodSet::Any(methods.to_vec());
+        assert_eq!(
+            Kind::needed(Some(&any(&[AuthMethod::Confirm]))),
+            Kind::Confirmation
+        );
+        assert_eq!(
+            Kind::needed(Some(&any(&[AuthMethod::Password]))),
+            Kind::Credential
+        );
+        assert_eq!(
+            Kind::needed(Some(&any(&[AuthMethod::Confirm, AuthMethod::Hwkey]))),
+            Kind::Credential
+        );
+        assert_eq!(Kind::needed(None), Kind::Credential);
+    }
+
+    #[test]
+    fn kinds_survive_a_restart_and_old_entries_count_as_confirmations() {
+        let mut cache = AuthCache::new();
+        let start = Instant::now();
+        let wall = UNIX_EPOCH + Duration::from_secs(SAVED_AT);
+        let timeout = Duration::from_secs(300);
+        cache.insert_at(1000, Path::new(TARGET), Kind::Credential, timeout, start);
+        let restored = AuthCache::restore_at(cache.snapshot_at(start, wall), start, wall);
+        assert!(
+            restored
+                .remaining_at(1000, Path::new(TARGET), Kind::Credential, start)
+                .is_some()
+        );
+
+        #[derive(Serialize)]
+        struct Unkinded {
+            uid: u32,
+            target: PathBuf,
+            deadline_secs: u64,
+            timeout_secs: u64,
+        }
+        let old = rmp_serde::to_vec(&[Unkinded {
+            uid: 1000,
+            target: PathBuf::from(TARGET),
+            deadline_secs: SAVED_AT + 200,
+            timeout_secs: 300,
+        }])
+        .unwrap();
+        let entries: Vec<PersistedEntry> = rmp_serde::from_slice(&old).unwrap();
+        assert_eq!(entries, vec![saved(SAVED_AT + 200, 300)]);
+    }
+
+    #[test]
+    fn zero_timeout_is_never_cached() {
+        let mut cache = AuthCache::new();
+        cache.insert(1000, Path::new(TARGET), Kind::Confirmation, Duration::ZERO);
+
+        assert!(
+            cache
+                .remaining(1000, Path::new(TARGET), Kind::Confirmation)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn prune_drops_expired_entries() {
+        let mut cache = AuthCache::new();
+        let start = Instant::now();
+        cache.insert_at(
+            1000,
+            Path::new(TARGET),
+            Kind::Confirmation,
+            Duration::from_secs(1),
+            start,
+        );
+        cache.insert_at(
+            1000,
+            Path::new("/usr/bin/other"),
+            Kind::Confirmation,
+            Duration::from_secs(300),
+            start,
+        );
+
+        cache.prune_at(start + Duration::from_secs(10));
+
+        assert_eq!(cache.entries.len(), 1);
+    }
+
+    fn saved(deadline_secs: u64, timeout_secs: u64) -> PersistedEntry {
+        PersistedEntry {
+            uid: 1000,
+            target: PathBuf::from(TARGET),
+            deadline_secs,
+            timeout_secs,
+            kind: Kind::Confirmation,
+        }
+    }
+
+    /// Wall-clock time the entries were saved at
+    const SAVED_AT: u64 = 1_700_000_000;
+
+    #[test]
+    fn snapshot_restores_with_the_same_remaining_time() {
+        let mut cache = AuthCache::new();
+        let start = Instant::now();
+        let wall = UNIX_EPOCH + Duration::from_secs(SAVED_AT);
+        cache.insert_at(
+            1000,
+            Path::new(TARGET),
+            Kind::Confirmation,
+            Duration::from_secs(300),
+            start,
+        );
+
+        let entries = cache.snapshot_at(start + Duration::from_secs(100), wall);
+        assert_eq!(entries, vec![saved(SAVED_AT + 200, 300)]);
+
+        let restored = AuthCache::restore_at(entries, start, wall);
+        assert_eq!(
+            restored.remaining_at(1000, Path::new(TARGET), Kind::Confirmation, start),
+            Some(Duration::from_secs(200))
+        );
+    }
+
+    #[test]
+    fn backward_clock_jump_is_clamped_to_the_timeout() {
+        let start = Instant::now();
+        // Saved with 200s left, then the clock was set back an hour
+        let wall = UNIX_EPOCH + Duration::from_secs(SAVED_AT - 3600);
+
+        let restored = AuthCache::restore_at(vec![saved(SAVED_AT + 200, 300)], start, wall);
+
+        assert_eq!(
+            restored.remaining_at(1000, Path::new(TARGET), Kind::Confirmation, start),
+            Some(Duration::from_secs(300))
+        );
+    }
+
+    #[test]
+    fn forward_clock_jump_only_shortens_or_expires() {
+        let start = Instant::now();
+        let entries = vec![saved(SAVED_AT + 200, 300)];
+
+        let wall = UNIX_EPOCH + Duration::from_secs(SAVED_AT + 150);
+        let restored = AuthCache::restore_at(entries.clone(), start, wall);
+        assert_eq!(
+            restored.remaining_at(1000, Path::new(TARGET), Kind::Confirmation, start),
+            Some(Duration::from_secs(50))
+        );
+
+        let wall = UNIX_EPOCH + Duration::from_secs(SAVED_AT + 3600);
+        let restored = AuthCache::restore_at(entries, start, wall);
+        assert!(restored.entries.is_empty());
+    }
+
+    #[test]
+    fn implausibly_distant_deadlines_are_discarded() {
+        let start = Instant::now();
+        let wall = UNIX_EPOCH + Duration::from_secs(SAVED_AT);
+        let a_year = 365 * 24 * 3600;
+
+        let restored = AuthCache::restore_at(vec![saved(SAVED_AT + a_year, 300)], start, wall);
+
+        assert!(restored.entries.is_empty());
+    }
+
+    #[test]
+    fn save_and_load_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("authd-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cache");
+
+        let mut cache = AuthCache::new();
+        cache.insert(
+            1000,
+            Path::new(TARGET),
+            Kind::Confirmation,
+            Duration::from_secs(300),
+        );
+        cache.save(&path).unwrap();
+        let loaded = AuthCache::load(&path);
+
+        let left = loaded
+            .remaining(1000, Path::new(TARGET), Kind::Confirmation)
+            .unwrap();
+        assert!(left <= Duration::from_secs(300));
+        assert!(AuthCache::load(&dir.join("missing")).entries.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn remaining_secs_rounds_partial_seconds_up() {
+        assert_eq!(remaining_secs(Duration::from_secs(240)), 240);
+        assert_eq!(remaining_secs(Duration::from_millis(239_001)), 240);
+        assert_eq!(remaining_secs(Duration::ZERO), 0);
+    }
+}
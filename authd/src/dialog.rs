@@ -1,315 +1,453 @@
-//! Confirmation dialog for authd using session-dialog library
+//! Confirmation dialogs for authd
 //!
-//! Shows a secure session-lock confirmation dialog via the session-dialog crate.
-
+//! authd reaches dialogs through the `Confirmer` trait so tests can script
+//! answers. With the `gui` feature (on by default) the real one is the
+//! session-lock dialog in [`session`]; without it, [`headless`] stands in and
+//! no dialog can ever be shown. Either way, a prompt the dialog can't show
+//! (no graphical session, no compositor) goes on down `dialog_fallback`, by
+//! default to the caller's terminal (see [`tty`]).
+//!
+//! Each dialog runs in a child process (`authd --dialog-helper`) rather than a
+//! thread, so a dialog whose requester hung up can be killed through its
+//! [`DialogCancel`] instead of holding the session until it times out.
+
+mod challenge;
+#[cfg(not(feature = "gui"))]
+mod headless;
+#[cfg(feature = "gui")]
+mod session;
+mod tty;
+
+pub use challenge::Challenge;
+#[cfg(not(feature = "gui"))]
+pub use headless::SessionDialog;
+#[cfg(all(not(feature = "gui"), not(coverage)))]
+pub use headless::run_helper;
+#[cfg(feature = "gui")]
+pub use session::SessionDialog;
+#[cfg(all(feature = "gui", not(coverage)))]
+pub use session::run_helper;
+
+use authd_policy::DialogBackend;
+use authd_protocol::{AuthRequest, PolkitRequest};
 use peercred_ipc::CallerInfo;
-use session_dialog::DialogKind;
-#[cfg(not(coverage))]
-use session_dialog::{DialogConfig, DialogResult as SdResult};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
-const REQUIRED_SESSION_ENV: &[&str] = &["WAYLAND_DISPLAY", "XDG_RUNTIME_DIR"];
+/// First argument that makes authd run one dialog instead of the daemon
+pub const HELPER_ARG: &str = "--dialog-helper";
+/// How often a waiting dialog checks whether its helper has exited
+const HELPER_POLL: Duration = Duration::from_millis(50);
 
 /// Result of showing the confirmation dialog
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum DialogResult {
     Confirmed,
     Denied,
+    /// Left unanswered until the dialog gave up
+    TimedOut,
     Error,
 }
 
-/// Show a confirmation dialog using session-dialog
-///
-/// Runs the dialog inline (no fork) with the caller's Wayland env vars.
-/// The dialog locks the session and shows a confirmation prompt.
-pub fn show_confirmation_dialog(
-    _caller: &CallerInfo,
-    target: &PathBuf,
-    args: &[String],
-    env: &HashMap<String, String>,
-    prompt_title: Option<&str>,
-    prompt_message: Option<&str>,
-    prompt_detail: Option<&str>,
-) -> DialogResult {
-    if !has_reachable_session_env(env) {
-        return DialogResult::Error;
-    }
-
-    show_confirmation_dialog_with_session_env(
-        target,
-        args,
-        env,
-        prompt_title,
-        prompt_message,
-        prompt_detail,
-    )
+/// Asks the user to approve a request. Every prompt blocks until answered or
+/// until `cancel` is cancelled, whichever comes first.
+pub trait Confirmer: Send + Sync {
+    /// Confirm an exec request (`auth = "confirm"`), honoring its prompt text
+    fn confirm(
+        &self,
+        caller: &CallerInfo,
+        request: &AuthRequest,
+        cancel: &DialogCancel,
+    ) -> DialogResult;
+    /// Warn about a discouraged target and ask whether to proceed anyway
+    fn confirm_discouraged(
+        &self,
+        caller: &CallerInfo,
+        request: &AuthRequest,
+        cancel: &DialogCancel,
+    ) -> DialogResult;
+    /// Confirm an exec request whose rule sets `require_type_confirm`: only
+    /// the target's file name, typed out, approves
+    fn confirm_typed(
+        &self,
+        caller: &CallerInfo,
+        request: &AuthRequest,
+        cancel: &DialogCancel,
+    ) -> DialogResult;
+    /// Confirm a polkit action forwarded by the agent
+    fn confirm_polkit(&self, request: &PolkitRequest, cancel: &DialogCancel) -> DialogResult;
 }
 
-#[cfg(not(coverage))]
-fn show_confirmation_dialog_with_session_env(
-    target: &PathBuf,
-    args: &[String],
-    env: &HashMap<String, String>,
-    prompt_title: Option<&str>,
-    prompt_message: Option<&str>,
-    prompt_detail: Option<&str>,
+/// Ask through each backend of `chain` in turn. One that can't show the
+/// prompt at all answers `Error` and hands over to the next; any other
+/// answer is final. `Error` if every backend failed.
+pub fn ask_in_order(
+    chain: &[DialogBackend],
+    mut ask: impl FnMut(DialogBackend) -> DialogResult,
 ) -> DialogResult {
-    let config = DialogConfig {
-        kind: dialog_kind(target, args, prompt_title, prompt_message, prompt_detail),
-        timeout_secs: Some(30),
-    };
-
-    // Run in separate thread to avoid tokio runtime conflicts
-    let handle = session_dialog::show_dialog_async(config, env.clone());
-    let result = handle.join().unwrap_or(SdResult::Error);
-
-    match result {
-        SdResult::Confirmed => DialogResult::Confirmed,
-        SdResult::Denied | SdResult::Timeout => DialogResult::Denied,
-        SdResult::Error => DialogResult::Error,
+    for &backend in chain {
+        match ask(backend) {
+            DialogResult::Error => {
+                tracing::info!("dialog backend {:?} unavailable, trying the next", backend)
+            }
+            result => {
+                tracing::info!("asked through dialog backend {:?}", backend);
+                return result;
+            }
+        }
     }
+    tracing::warn!("no dialog backend could ask: {:?}", chain);
+    DialogResult::Error
 }
 
-#[cfg(coverage)]
-fn show_confirmation_dialog_with_session_env(
-    target: &PathBuf,
-    args: &[String],
-    _env: &HashMap<String, String>,
-    prompt_title: Option<&str>,
-    prompt_message: Option<&str>,
-    prompt_detail: Option<&str>,
-) -> DialogResult {
-    let _ = dialog_kind(target, args, prompt_title, prompt_message, prompt_detail);
-    DialogResult::Error
+/// Tears down a dialog whose requester went away: kills the helper process
+/// showing it, which releases the session it locked.
+#[derive(Default)]
+pub struct DialogCancel {
+    cancelled: AtomicBool,
+    /// The helper while it runs; taken by whichever of the waiter and
+    /// `cancel` gets to it first, so it's reaped exactly once
+    helper: Mutex<Option<Child>>,
 }
 
-fn dialog_kind(
-    target: &PathBuf,
-    args: &[String],
-    prompt_title: Option<&str>,
-    prompt_message: Option<&str>,
-    prompt_detail: Option<&str>,
-) -> DialogKind {
-    match (prompt_title, prompt_message, prompt_detail) {
-        (Some(title), Some(message), Some(detail)) => DialogKind::Generic {
-            title: title.to_string(),
-            message: message.to_string(),
-            detail: detail.to_string(),
-        },
-        _ => DialogKind::PrivilegeEscalation {
-            command: command_text(target, args),
-        },
+impl DialogCancel {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        if let Some(mut helper) = self.helper().take() {
+            let _ = helper.kill();
+            let _ = helper.wait();
+        }
     }
-}
 
-fn command_text(target: &PathBuf, args: &[String]) -> String {
-    if args.is_empty() {
-        target.to_string_lossy().to_string()
-    } else {
-        format!("{} {}", target.display(), args.join(" "))
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
     }
-}
 
-/// Show a confirmation dialog for a polkit authentication request.
-///
-/// Uses polkit's own human-readable `message` as the prompt and the action id
-/// as the detail line. Allow/Deny only — no password entry.
-pub fn show_polkit_dialog(
-    message: &str,
-    action_id: &str,
-    env: &HashMap<String, String>,
-) -> DialogResult {
-    if !has_reachable_session_env(env) {
-        return DialogResult::Error;
+    fn helper(&self) -> MutexGuard<'_, Option<Child>> {
+        self.helper.lock().unwrap_or_else(|e| e.into_inner())
     }
 
-    show_polkit_dialog_with_session_env(message, action_id, env)
+    /// Whether a helper is running for it now
+    #[cfg(test)]
+    pub fn has_helper(&self) -> bool {
+        self.helper().is_some()
+    }
+
+    /// Wait for `helper` to exit. None if it was killed by `cancel`, before
+    /// or while waiting.
+    #[cfg_attr(any(coverage, not(feature = "gui")), allow(dead_code))]
+    pub fn wait(&self, helper: Child) -> Option<std::process::ExitStatus> {
+        *self.helper() = Some(helper);
+        // Cancelled before the helper was handed over: nobody else will kill it
+        if self.is_cancelled() {
+            self.cancel();
+            return None;
+        }
+
+        loop {
+            {
+                let mut slot = self.helper();
+                let helper = slot.as_mut()?;
+                match helper.try_wait() {
+                    Ok(Some(status)) => {
+                        slot.take();
+                        return Some(status);
+                    }
+                    Ok(None) => {}
+                    Err(_) => {
+                        drop(slot);
+                        self.cancel();
+                        return None;
+                    }
+                }
+            }
+            std::thread::sleep(HELPER_POLL);
+        }
+    }
 }
 
-#[cfg(not(coverage))]
-fn show_polkit_dialog_with_session_env(
-    message: &str,
-    action_id: &str,
-    env: &HashMap<String, String>,
-) -> DialogResult {
-    let config = DialogConfig {
-        kind: DialogKind::Generic {
-            title: "Authorization Required".to_string(),
-            message: message.to_string(),
-            detail: action_id.to_string(),
-        },
-        timeout_secs: Some(30),
-    };
-
-    let handle = session_dialog::show_dialog_async(config, env.clone());
-    match handle.join().unwrap_or(SdResult::Error) {
-        SdResult::Confirmed => DialogResult::Confirmed,
-        SdResult::Denied | SdResult::Timeout => DialogResult::Denied,
-        SdResult::Error => DialogResult::Error,
+/// When a prompt starts taking "yes" for an answer. Until then a confirm is
+/// ignored: it comes from a key that was held down or typed before the
+/// prompt appeared, not from someone who read it.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadyAt(Instant);
+
+impl ReadyAt {
+    /// Ready `delay` after `shown`, the moment the prompt appeared
+    pub fn after(shown: Instant, delay: Duration) -> Self {
+        Self(shown + delay)
+    }
+
+    /// Whether a confirm at `now` counts
+    pub fn accepts(&self, now: Instant) -> bool {
+        now >= self.0
+    }
+
+    /// How long from `now` until confirms count
+    pub fn remaining(&self, now: Instant) -> Duration {
+        self.0.saturating_duration_since(now)
     }
 }
 
-#[cfg(coverage)]
-fn show_polkit_dialog_with_session_env(
-    message: &str,
-    action_id: &str,
-    _env: &HashMap<String, String>,
-) -> DialogResult {
-    let _ = DialogKind::Generic {
-        title: "Authorization Required".to_string(),
-        message: message.to_string(),
-        detail: action_id.to_string(),
-    };
-    DialogResult::Error
+/// Cancels the dialog when dropped, e.g. with the request future of a
+/// client that disconnected. Harmless once the dialog has been answered.
+pub struct CancelOnDrop(pub Arc<DialogCancel>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
 }
 
-fn has_reachable_session_env(env: &HashMap<String, String>) -> bool {
-    REQUIRED_SESSION_ENV
-        .iter()
-        .all(|key| env.get(*key).is_some_and(|value| !value.is_empty()))
+/// Answers every prompt with a fixed result and counts how often it was asked
+#[cfg(test)]
+pub struct ScriptedConfirmer {
+    result: DialogResult,
+    /// How long each "dialog" stays up, blocking like the real one
+    delay: std::time::Duration,
+    shown: std::sync::atomic::AtomicUsize,
+    open: std::sync::atomic::AtomicUsize,
+    max_open: std::sync::atomic::AtomicUsize,
+    torn_down: std::sync::atomic::AtomicUsize,
+    /// Arguments of the last request shown
+    shown_args: std::sync::Mutex<Vec<String>>,
 }
 
 #[cfg(test)]
-mod tests {
-    use super::*;
+impl ScriptedConfirmer {
+    pub fn new(result: DialogResult) -> Self {
+        Self::with_delay(result, std::time::Duration::ZERO)
+    }
 
-    #[test]
-    fn session_env_requires_wayland_display_and_runtime_dir() {
-        let env = HashMap::from([
-            ("WAYLAND_DISPLAY".to_string(), "wayland-1".to_string()),
-            ("XDG_RUNTIME_DIR".to_string(), "/run/user/1000".to_string()),
-        ]);
+    pub fn with_delay(result: DialogResult, delay: std::time::Duration) -> Self {
+        Self {
+            result,
+            delay,
+            shown: std::sync::atomic::AtomicUsize::new(0),
+            open: std::sync::atomic::AtomicUsize::new(0),
+            max_open: std::sync::atomic::AtomicUsize::new(0),
+            torn_down: std::sync::atomic::AtomicUsize::new(0),
+            shown_args: std::sync::Mutex::new(Vec::new()),
+        }
+    }
 
-        assert!(has_reachable_session_env(&env));
+    pub fn shown(&self) -> usize {
+        self.shown.load(std::sync::atomic::Ordering::SeqCst)
     }
 
-    #[test]
-    fn session_env_rejects_missing_or_empty_values() {
-        assert!(!has_reachable_session_env(&HashMap::new()));
-
-        let missing_runtime =
-            HashMap::from([("WAYLAND_DISPLAY".to_string(), "wayland-1".to_string())]);
-        assert!(!has_reachable_session_env(&missing_runtime));
-
-        let empty_display = HashMap::from([
-            ("WAYLAND_DISPLAY".to_string(), String::new()),
-            ("XDG_RUNTIME_DIR".to_string(), "/run/user/1000".to_string()),
-        ]);
-        assert!(!has_reachable_session_env(&empty_display));
+    /// The most dialogs that were ever up at the same time
+    pub fn max_open(&self) -> usize {
+        self.max_open.load(std::sync::atomic::Ordering::SeqCst)
     }
 
-    #[test]
-    fn polkit_dialog_returns_error_without_session_env() {
-        let result = show_polkit_dialog(
-            "Authentication is required.",
-            "org.freedesktop.systemd1.manage-units",
-            &HashMap::new(),
-        );
+    /// How many dialogs were cancelled while still up
+    pub fn torn_down(&self) -> usize {
+        self.torn_down.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// The arguments the last exec request was shown with
+    pub fn shown_args(&self) -> Vec<String> {
+        self.shown_args.lock().unwrap().clone()
+    }
 
-        assert_eq!(result, DialogResult::Error);
+    fn show(&self, request: &AuthRequest, cancel: &DialogCancel) -> DialogResult {
+        *self.shown_args.lock().unwrap() = request.args.clone();
+        self.answer(cancel)
     }
 
-    #[cfg(coverage)]
+    fn answer(&self, cancel: &DialogCancel) -> DialogResult {
+        self.shown.fetch_add(1, Ordering::SeqCst);
+        let open = self.open.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_open.fetch_max(open, Ordering::SeqCst);
+
+        let until = std::time::Instant::now() + self.delay;
+        let mut result = self.result;
+        while std::time::Instant::now() < until {
+            if cancel.is_cancelled() {
+                self.torn_down.fetch_add(1, Ordering::SeqCst);
+                result = DialogResult::Denied;
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        self.open.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+}
+
+#[cfg(test)]
+impl Confirmer for ScriptedConfirmer {
+    fn confirm(
+        &self,
+        _caller: &CallerInfo,
+        request: &AuthRequest,
+        cancel: &DialogCancel,
+    ) -> DialogResult {
+        self.show(request, cancel)
+    }
+
+    fn confirm_discouraged(
+        &self,
+        _caller: &CallerInfo,
+        request: &AuthRequest,
+        cancel: &DialogCancel,
+    ) -> DialogResult {
+        self.show(request, cancel)
+    }
+
+    fn confirm_typed(
+        &self,
+        _caller: &CallerInfo,
+        request: &AuthRequest,
+        cancel: &DialogCancel,
+    ) -> DialogResult {
+        self.show(request, cancel)
+    }
+
+    fn confirm_polkit(&self, _request: &PolkitRequest, cancel: &DialogCancel) -> DialogResult {
+        self.answer(cancel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
     #[test]
-    fn dialog_stubs_return_error_with_session_env() {
-        let env = HashMap::from([
-            ("WAYLAND_DISPLAY".to_string(), "wayland-1".to_string()),
-            ("XDG_RUNTIME_DIR".to_string(), "/run/user/1000".to_string()),
-        ]);
-        let caller = CallerInfo {
+    fn scripted_confirmer_counts_prompts() {
+        let confirmer = ScriptedConfirmer::new(DialogResult::Denied);
+        let polkit = PolkitRequest {
+            action_id: "org.example.Action".into(),
+            message: "Message".into(),
             uid: 1000,
-            gid: 1000,
-            pid: 42,
-            exe: PathBuf::from("/usr/bin/authsudo"),
+            cookie: "cookie".into(),
+            env: HashMap::new(),
         };
 
         assert_eq!(
-            show_confirmation_dialog(
-                &caller,
-                &PathBuf::from("/usr/bin/id"),
-                &["-u".to_string()],
-                &env,
-                Some("Title"),
-                Some("Message"),
-                Some("Detail"),
-            ),
+            confirmer.confirm_polkit(&polkit, &DialogCancel::default()),
+            DialogResult::Denied
+        );
+        assert_eq!(confirmer.shown(), 1);
+    }
+
+    #[test]
+    fn failing_backends_hand_over_to_the_next() {
+        let chain = [
+            DialogBackend::SessionLock,
+            DialogBackend::Tty,
+            DialogBackend::Deny,
+        ];
+        let mut asked = Vec::new();
+        let result = ask_in_order(&chain, |backend| {
+            asked.push(backend);
+            match backend {
+                DialogBackend::SessionLock => DialogResult::Error,
+                _ => DialogResult::Confirmed,
+            }
+        });
+        assert_eq!(result, DialogResult::Confirmed);
+        assert_eq!(asked, [DialogBackend::SessionLock, DialogBackend::Tty]);
+
+        // A real answer, even a refusal, is final
+        let mut asked = Vec::new();
+        let result = ask_in_order(&chain, |backend| {
+            asked.push(backend);
+            DialogResult::Denied
+        });
+        assert_eq!(result, DialogResult::Denied);
+        assert_eq!(asked, [DialogBackend::SessionLock]);
+    }
+
+    #[test]
+    fn exhausted_chains_fail() {
+        let chain = [DialogBackend::SessionLock, DialogBackend::Tty];
+        assert_eq!(
+            ask_in_order(&chain, |_| DialogResult::Error),
             DialogResult::Error
         );
         assert_eq!(
-            show_polkit_dialog("Message", "org.example.Action", &env),
+            ask_in_order(&[], |_| DialogResult::Confirmed),
             DialogResult::Error
         );
-        assert_eq!(DialogResult::Confirmed, DialogResult::Confirmed);
-        assert_eq!(DialogResult::Denied, DialogResult::Denied);
     }
 
     #[test]
-    fn confirmation_dialog_returns_error_without_session_env() {
-        let caller = CallerInfo {
-            uid: 1000,
-            gid: 1000,
-            pid: 42,
-            exe: PathBuf::from("/usr/bin/authsudo"),
-        };
-
-        let result = show_confirmation_dialog(
-            &caller,
-            &PathBuf::from("/usr/bin/id"),
-            &["-u".to_string()],
-            &HashMap::new(),
-            None,
-            None,
-            None,
+    fn confirms_before_the_ready_instant_are_ignored() {
+        let shown = Instant::now();
+        let ready = ReadyAt::after(shown, Duration::from_millis(400));
+
+        assert!(!ready.accepts(shown));
+        assert!(!ready.accepts(shown + Duration::from_millis(399)));
+        assert!(ready.accepts(shown + Duration::from_millis(400)));
+        assert!(ready.accepts(shown + Duration::from_secs(5)));
+        assert_eq!(
+            ready.remaining(shown + Duration::from_millis(100)),
+            Duration::from_millis(300)
+        );
+        assert_eq!(
+            ready.remaining(shown + Duration::from_secs(1)),
+            Duration::ZERO
         );
 
-        assert_eq!(result, DialogResult::Error);
+        // No delay: ready as soon as it's shown
+        assert!(ReadyAt::after(shown, Duration::ZERO).accepts(shown));
     }
 
     #[test]
-    fn dialog_kind_prefers_explicit_prompt_text() {
-        let kind = dialog_kind(
-            &PathBuf::from("/usr/bin/id"),
-            &["-u".to_string()],
-            Some("Title"),
-            Some("Message"),
-            Some("Detail"),
-        );
-
-        match kind {
-            DialogKind::Generic {
-                title,
-                message,
-                detail,
-            } => {
-                assert_eq!(title, "Title");
-                assert_eq!(message, "Message");
-                assert_eq!(detail, "Detail");
-            }
-            _ => panic!("expected generic dialog"),
+    fn cancel_kills_the_helper_being_waited_for() {
+        let cancel = Arc::new(DialogCancel::default());
+        let helper = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let pid = helper.id() as libc::pid_t;
+
+        let waiter = std::thread::spawn({
+            let cancel = Arc::clone(&cancel);
+            move || cancel.wait(helper)
+        });
+        while cancel.helper().is_none() {
+            std::thread::sleep(Duration::from_millis(5));
         }
+        drop(CancelOnDrop(Arc::clone(&cancel)));
+
+        assert_eq!(waiter.join().unwrap(), None);
+        assert!(cancel.is_cancelled());
+        // Killed and reaped, not left behind as a zombie or orphan
+        assert_eq!(unsafe { libc::kill(pid, 0) }, -1);
     }
 
     #[test]
-    fn dialog_kind_formats_privilege_command() {
-        let kind = dialog_kind(
-            &PathBuf::from("/usr/bin/id"),
-            &["-u".to_string(), "root".to_string()],
-            None,
-            None,
-            None,
-        );
+    fn helper_handed_over_after_cancel_is_killed_at_once() {
+        let cancel = DialogCancel::default();
+        cancel.cancel();
+        let helper = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+
+        let started = std::time::Instant::now();
+        assert_eq!(cancel.wait(helper), None);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
 
-        match kind {
-            DialogKind::PrivilegeEscalation { command } => {
-                assert_eq!(command, "/usr/bin/id -u root");
-            }
-            _ => panic!("expected privilege escalation dialog"),
-        }
+    #[test]
+    fn waiting_reports_the_helper_exit_status() {
+        let cancel = DialogCancel::default();
+        let helper = std::process::Command::new("sh")
+            .args(["-c", "exit 1"])
+            .spawn()
+            .unwrap();
 
         assert_eq!(
-            command_text(&PathBuf::from("/usr/bin/id"), &[]),
-            "/usr/bin/id"
+            cancel.wait(helper).and_then(|status| status.code()),
+            Some(1)
         );
+        assert!(!cancel.is_cancelled());
     }
 }
@@ -0,0 +1,111 @@
+//! `require_challenge`: a fresh four-digit code with every confirmation
+//!
+//! On a terminal the user has to type the code back instead of `y`, so a
+//! confirmation can't come from a reflexive keypress or from text something
+//! else injected ahead of time. The session-lock dialog can't be drawn over
+//! by other clients and has no text entry; it shows the code so the genuine
+//! dialog is told apart from a look-alike window.
+//!
+//! A rule's `require_type_confirm` asks for the target's file name instead,
+//! so approving `mkfs` means typing `mkfs`. That needs a text entry, which
+//! only the terminal has.
+
+use std::fmt;
+use std::path::Path;
+
+/// The code the user has to type back to approve one prompt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Challenge(String);
+
+impl Challenge {
+    pub fn new() -> Self {
+        Self::from_random(random_u32())
+    }
+
+    fn from_random(random: u32) -> Self {
+        Self(format!("{:04}", random % 10_000))
+    }
+
+    /// The file name of `target`, for `require_type_confirm`
+    pub fn command(target: &Path) -> Self {
+        let name = target.file_name().unwrap_or(target.as_os_str());
+        Self(name.to_string_lossy().into_owned())
+    }
+
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn code(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether `answer`, as typed, approves
+    pub fn accepts(&self, answer: &str) -> bool {
+        answer.trim() == self.0
+    }
+}
+
+/// The instruction shown with the prompt
+impl fmt::Display for Challenge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Type {} to approve", self.0)
+    }
+}
+
+/// From the kernel's CSPRNG. A failure there only makes the code guessable,
+/// never an approval automatic, so the clock stands in.
+fn random_u32() -> u32 {
+    let mut bytes = [0u8; 4];
+    let read = unsafe { libc::getrandom(bytes.as_mut_ptr().cast(), bytes.len(), 0) };
+    if read == bytes.len() as isize {
+        return u32::from_ne_bytes(bytes);
+    }
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.subsec_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_are_four_digits() {
+        assert_eq!(Challenge::from_random(7).code(), "0007");
+        assert_eq!(Challenge::from_random(123_456).code(), "3456");
+        for _ in 0..100 {
+            let challenge = Challenge::new();
+            assert_eq!(challenge.code().len(), 4);
+            assert!(challenge.code().bytes().all(|b| b.is_ascii_digit()));
+        }
+    }
+
+    #[test]
+    fn codes_differ_between_prompts() {
+        let codes: std::collections::HashSet<_> = (0..20)
+            .map(|_| Challenge::new().code().to_string())
+            .collect();
+        assert!(codes.len() > 1);
+    }
+
+    #[test]
+    fn only_the_code_itself_approves() {
+        let challenge = Challenge::from_random(4721);
+        assert_eq!(challenge.to_string(), "Type 4721 to approve");
+        assert!(challenge.accepts("4721"));
+        assert!(challenge.accepts(" 4721\n"));
+        assert!(!challenge.accepts("y"));
+        assert!(!challenge.accepts("472"));
+        assert!(!challenge.accepts("47210"));
+        assert!(!challenge.accepts(""));
+    }
+
+    #[test]
+    fn a_command_challenge_wants_the_file_name_typed_out() {
+        let challenge = Challenge::command(Path::new("/usr/sbin/mkfs.ext4"));
+        assert_eq!(challenge.to_string(), "Type mkfs.ext4 to approve");
+        assert!(challenge.accepts("mkfs.ext4\n"));
+        assert!(!challenge.accepts("y"));
+        assert!(!challenge.accepts("mkfs"));
+        assert!(!challenge.accepts("MKFS.EXT4"));
+        assert!(!challenge.accepts("/usr/sbin/mkfs.ext4"));
+    }
+}
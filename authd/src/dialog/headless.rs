@@ -0,0 +1,175 @@
+//! Stand-in for the session-lock dialog in builds without the `gui` feature
+//!
+//! There is nothing to show a dialog with, so `session_lock` in
+//! `dialog_fallback` always fails over to the next backend: only the
+//! caller's terminal can be asked, and polkit requests always fail (or are
+//! denied, with `deny` in the chain). authd doesn't get here for
+//! `auth = "confirm"`, which `headless_confirm` turns into a password check
+//! or a denial first.
+
+use super::{Challenge, Confirmer, DialogCancel, DialogResult, ask_in_order, tty};
+use authd_policy::DialogBackend;
+use authd_policy::config::DEFAULT_DIALOG_FALLBACK;
+use authd_protocol::{AuthRequest, PolkitRequest};
+use peercred_ipc::CallerInfo;
+use std::time::Duration;
+
+pub struct SessionDialog {
+    /// How long after appearing a terminal prompt ignores answers
+    confirm_delay: Duration,
+    /// Whether every prompt asks for a fresh code (`require_challenge`)
+    require_challenge: bool,
+    /// Backends to ask through, in order (`dialog_fallback`)
+    backends: Vec<DialogBackend>,
+}
+
+impl SessionDialog {
+    pub fn new(confirm_delay: Duration) -> Self {
+        Self {
+            confirm_delay,
+            require_challenge: false,
+            backends: DEFAULT_DIALOG_FALLBACK.to_vec(),
+        }
+    }
+
+    pub fn with_challenge(self, require_challenge: bool) -> Self {
+        Self {
+            require_challenge,
+            ..self
+        }
+    }
+
+    pub fn with_backends(self, backends: Vec<DialogBackend>) -> Self {
+        Self { backends, ..self }
+    }
+
+    fn challenge(&self) -> Option<Challenge> {
+        self.require_challenge.then(Challenge::new)
+    }
+}
+
+impl Confirmer for SessionDialog {
+    fn confirm(
+        &self,
+        caller: &CallerInfo,
+        request: &AuthRequest,
+        cancel: &DialogCancel,
+    ) -> DialogResult {
+        ask_in_order(&self.backends, |backend| match backend {
+            DialogBackend::SessionLock => DialogResult::Error,
+            DialogBackend::Tty => tty::confirm(
+                caller.pid,
+                request,
+                &tty::question(request),
+                self.challenge().as_ref(),
+                self.confirm_delay,
+                cancel,
+            ),
+            DialogBackend::Deny => DialogResult::Denied,
+        })
+    }
+
+    fn confirm_discouraged(
+        &self,
+        caller: &CallerInfo,
+        request: &AuthRequest,
+        cancel: &DialogCancel,
+    ) -> DialogResult {
+        ask_in_order(&self.backends, |backend| match backend {
+            DialogBackend::SessionLock => DialogResult::Error,
+            DialogBackend::Tty => tty::confirm(
+                caller.pid,
+                request,
+                &tty::discouraged_question(request),
+                self.challenge().as_ref(),
+                self.confirm_delay,
+                cancel,
+            ),
+            DialogBackend::Deny => DialogResult::Denied,
+        })
+    }
+
+    fn confirm_typed(
+        &self,
+        caller: &CallerInfo,
+        request: &AuthRequest,
+        cancel: &DialogCancel,
+    ) -> DialogResult {
+        ask_in_order(&self.backends, |backend| match backend {
+            DialogBackend::SessionLock => DialogResult::Error,
+            DialogBackend::Tty => tty::confirm(
+                caller.pid,
+                request,
+                &tty::question(request),
+                Some(&Challenge::command(&request.target)),
+                self.confirm_delay,
+                cancel,
+            ),
+            DialogBackend::Deny => DialogResult::Denied,
+        })
+    }
+
+    fn confirm_polkit(&self, _request: &PolkitRequest, _cancel: &DialogCancel) -> DialogResult {
+        ask_in_order(&self.backends, |backend| match backend {
+            DialogBackend::SessionLock | DialogBackend::Tty => DialogResult::Error,
+            DialogBackend::Deny => DialogResult::Denied,
+        })
+    }
+}
+
+/// `authd --dialog-helper` has no dialog to show
+#[cfg(not(coverage))]
+pub fn run_helper() -> i32 {
+    eprintln!("authd dialog helper: built without the gui feature");
+    2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    #[test]
+    fn prompts_fail_without_a_gui_or_terminal() {
+        let caller = CallerInfo {
+            uid: 1000,
+            gid: 1000,
+            pid: 0,
+            exe: PathBuf::from("/usr/bin/authctl"),
+        };
+        let request = AuthRequest {
+            target: PathBuf::from("/usr/bin/id"),
+            args: Vec::new(),
+            env: HashMap::new(),
+            password: String::new(),
+            confirm_only: false,
+            prompt_title: None,
+            prompt_message: None,
+            prompt_detail: None,
+            approval: None,
+            discouraged: false,
+            methods: None,
+            pam_service: None,
+        };
+        let polkit = PolkitRequest {
+            action_id: "org.example.Action".into(),
+            message: "Message".into(),
+            uid: 1000,
+            cookie: "cookie".into(),
+            env: HashMap::new(),
+        };
+
+        let dialog = SessionDialog::new(Duration::ZERO);
+        let cancel = DialogCancel::default();
+        assert_eq!(
+            dialog.confirm(&caller, &request, &cancel),
+            DialogResult::Error
+        );
+        assert_eq!(
+            dialog.confirm_discouraged(&caller, &request, &cancel),
+            DialogResult::Error
+        );
+        assert_eq!(dialog.confirm_polkit(&polkit, &cancel), DialogResult::Error);
+    }
+}
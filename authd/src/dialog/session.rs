@@ -0,0 +1,773 @@
+//! The session-lock dialog, from the session-dialog crate (`gui` feature)
+//!
+//! Every dialog is shown by a fresh `authd --dialog-helper`, which gets what
+//! to show as msgpack on stdin and answers with its exit code. A confirm
+//! within `confirm_delay_ms` of the dialog appearing is taken for a key that
+//! was already down, and the helper shows the dialog again.
+
+use super::{Challenge, Confirmer, DialogCancel, DialogResult, ask_in_order, tty};
+#[cfg(not(coverage))]
+use super::{HELPER_ARG, ReadyAt};
+use authd_policy::DialogBackend;
+use authd_policy::config::DEFAULT_DIALOG_FALLBACK;
+use authd_protocol::details::Details;
+use authd_protocol::messages::{Message, MessageId};
+use authd_protocol::{AuthRequest, PolkitRequest};
+use peercred_ipc::CallerInfo;
+#[cfg(not(coverage))]
+use serde::{Deserialize, Serialize};
+use session_dialog::DialogKind;
+#[cfg(not(coverage))]
+use session_dialog::{DialogConfig, DialogResult as SdResult};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+#[cfg(not(coverage))]
+use std::time::Instant;
+
+const REQUIRED_SESSION_ENV: &[&str] = &["WAYLAND_DISPLAY", "XDG_RUNTIME_DIR"];
+
+/// The caller's own wording for a confirmation dialog; with all three unset
+/// the dialog shows the command instead
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Prompt<'a> {
+    pub title: Option<&'a str>,
+    pub message: Option<&'a str>,
+    pub detail: Option<&'a str>,
+}
+
+impl<'a> Prompt<'a> {
+    /// The wording a request carries in its `prompt_*` fields
+    pub fn of(request: &'a AuthRequest) -> Self {
+        Self {
+            title: request.prompt_title.as_deref(),
+            message: request.prompt_message.as_deref(),
+            detail: request.prompt_detail.as_deref(),
+        }
+    }
+}
+
+/// How long a dialog stays up unanswered, across re-shows
+#[cfg(not(coverage))]
+const HELPER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The real session-lock dialog, or whatever comes next in `dialog_fallback`
+/// where it can't be shown
+pub struct SessionDialog {
+    /// How long after appearing a prompt ignores confirms
+    confirm_delay: Duration,
+    /// Whether every prompt comes with a fresh code (`require_challenge`)
+    require_challenge: bool,
+    /// Backends to ask through, in order (`dialog_fallback`)
+    backends: Vec<DialogBackend>,
+}
+
+impl Confirmer for SessionDialog {
+    fn confirm(
+        &self,
+        caller: &CallerInfo,
+        request: &AuthRequest,
+        cancel: &DialogCancel,
+    ) -> DialogResult {
+        ask_in_order(&self.backends, |backend| match backend {
+            DialogBackend::SessionLock => self.show_confirmation_dialog(
+                caller,
+                &request.target,
+                &request.args,
+                &request.env,
+                Prompt::of(request),
+                cancel,
+            ),
+            DialogBackend::Tty => tty::confirm(
+                caller.pid,
+                request,
+                &tty::question(request),
+                self.challenge().as_ref(),
+                self.confirm_delay,
+                cancel,
+            ),
+            DialogBackend::Deny => DialogResult::Denied,
+        })
+    }
+
+    fn confirm_discouraged(
+        &self,
+        caller: &CallerInfo,
+        request: &AuthRequest,
+        cancel: &DialogCancel,
+    ) -> DialogResult {
+        ask_in_order(&self.backends, |backend| match backend {
+            DialogBackend::SessionLock => self.show_discouraged_dialog(
+                caller,
+                &request.target,
+                &request.args,
+                &request.env,
+                cancel,
+            ),
+            DialogBackend::Tty => tty::confirm(
+                caller.pid,
+                request,
+                &tty::discouraged_question(request),
+                self.challenge().as_ref(),
+                self.confirm_delay,
+                cancel,
+            ),
+            DialogBackend::Deny => DialogResult::Denied,
+        })
+    }
+
+    fn confirm_typed(
+        &self,
+        caller: &CallerInfo,
+        request: &AuthRequest,
+        cancel: &DialogCancel,
+    ) -> DialogResult {
+        // No text entry to type the name into
+        ask_in_order(&self.backends, |backend| match backend {
+            DialogBackend::SessionLock => DialogResult::Error,
+            DialogBackend::Tty => tty::confirm(
+                caller.pid,
+                request,
+                &tty::question(request),
+                Some(&Challenge::command(&request.target)),
+                self.confirm_delay,
+                cancel,
+            ),
+            DialogBackend::Deny => DialogResult::Denied,
+        })
+    }
+
+    fn confirm_polkit(&self, request: &PolkitRequest, cancel: &DialogCancel) -> DialogResult {
+        ask_in_order(&self.backends, |backend| match backend {
+            DialogBackend::SessionLock => {
+                self.show_polkit_dialog(&request.message, &request.action_id, &request.env, cancel)
+            }
+            // polkit checks don't come from a process with a terminal
+            DialogBackend::Tty => DialogResult::Error,
+            DialogBackend::Deny => DialogResult::Denied,
+        })
+    }
+}
+
+impl SessionDialog {
+    pub fn new(confirm_delay: Duration) -> Self {
+        Self {
+            confirm_delay,
+            require_challenge: false,
+            backends: DEFAULT_DIALOG_FALLBACK.to_vec(),
+        }
+    }
+
+    pub fn with_challenge(self, require_challenge: bool) -> Self {
+        Self {
+            require_challenge,
+            ..self
+        }
+    }
+
+    pub fn with_backends(self, backends: Vec<DialogBackend>) -> Self {
+        Self { backends, ..self }
+    }
+
+    fn challenge(&self) -> Option<Challenge> {
+        self.require_challenge.then(Challenge::new)
+    }
+
+    /// Show a confirmation dialog using session-dialog
+    ///
+    /// Runs the dialog inline (no fork) with the caller's Wayland env vars.
+    /// The dialog locks the session and shows a confirmation prompt.
+    pub fn show_confirmation_dialog(
+        &self,
+        _caller: &CallerInfo,
+        target: &PathBuf,
+        args: &[String],
+        env: &HashMap<String, String>,
+        prompt: Prompt<'_>,
+        cancel: &DialogCancel,
+    ) -> DialogResult {
+        if !has_reachable_session_env(env) {
+            return DialogResult::Error;
+        }
+
+        self.show_confirmation_dialog_with_session_env(target, args, env, prompt, cancel)
+    }
+
+    /// Show the soft-deny warning for a target whose rule says `auth = "discourage"`.
+    pub fn show_discouraged_dialog(
+        &self,
+        caller: &CallerInfo,
+        target: &PathBuf,
+        args: &[String],
+        env: &HashMap<String, String>,
+        cancel: &DialogCancel,
+    ) -> DialogResult {
+        let command = command_text(target, args);
+        let title = Message::from(MessageId::DiscouragedTitle).to_string();
+        let message = Message::from(MessageId::DiscouragedMessage).to_string();
+        self.show_confirmation_dialog(
+            caller,
+            target,
+            args,
+            env,
+            Prompt {
+                title: Some(&title),
+                message: Some(&message),
+                detail: Some(&command),
+            },
+            cancel,
+        )
+    }
+
+    #[cfg(not(coverage))]
+    fn show_confirmation_dialog_with_session_env(
+        &self,
+        target: &PathBuf,
+        args: &[String],
+        env: &HashMap<String, String>,
+        prompt: Prompt<'_>,
+        cancel: &DialogCancel,
+    ) -> DialogResult {
+        let request = HelperRequest {
+            target: target.clone(),
+            args: args.to_vec(),
+            title: prompt.title.map(str::to_string),
+            message: prompt.message.map(str::to_string),
+            detail: prompt.detail.map(str::to_string),
+            details: Some(Details::new(target, args, env).to_string()),
+            code: self
+                .challenge()
+                .map(|challenge| challenge.code().to_string()),
+            env: env.clone(),
+            confirm_delay_ms: self.confirm_delay.as_millis() as u64,
+        };
+        run_in_helper(&request, cancel)
+    }
+
+    #[cfg(coverage)]
+    fn show_confirmation_dialog_with_session_env(
+        &self,
+        target: &PathBuf,
+        args: &[String],
+        env: &HashMap<String, String>,
+        prompt: Prompt<'_>,
+        _cancel: &DialogCancel,
+    ) -> DialogResult {
+        let challenge = self.challenge();
+        let code = challenge.as_ref().map(Challenge::code);
+        let details = Details::new(target, args, env).to_string();
+        let _ = dialog_kind(
+            target,
+            args,
+            prompt.title,
+            prompt.message,
+            prompt.detail,
+            code,
+            Some(&details),
+        );
+        DialogResult::Error
+    }
+
+    /// Show a confirmation dialog for a polkit authentication request.
+    ///
+    /// Uses polkit's own human-readable `message` as the prompt and the action id
+    /// as the detail line. Allow/Deny only — no password entry.
+    pub fn show_polkit_dialog(
+        &self,
+        message: &str,
+        action_id: &str,
+        env: &HashMap<String, String>,
+        cancel: &DialogCancel,
+    ) -> DialogResult {
+        if !has_reachable_session_env(env) {
+            return DialogResult::Error;
+        }
+
+        self.show_polkit_dialog_with_session_env(message, action_id, env, cancel)
+    }
+
+    #[cfg(not(coverage))]
+    fn show_polkit_dialog_with_session_env(
+        &self,
+        message: &str,
+        action_id: &str,
+        env: &HashMap<String, String>,
+        cancel: &DialogCancel,
+    ) -> DialogResult {
+        // Full prompt text always makes a generic dialog; no command to show
+        let request = HelperRequest {
+            target: PathBuf::new(),
+            args: Vec::new(),
+            title: Some(Message::from(MessageId::PolkitTitle).to_string()),
+            message: Some(message.to_string()),
+            detail: Some(action_id.to_string()),
+            code: None,
+            details: None,
+            env: env.clone(),
+            confirm_delay_ms: self.confirm_delay.as_millis() as u64,
+        };
+        run_in_helper(&request, cancel)
+    }
+
+    #[cfg(coverage)]
+    fn show_polkit_dialog_with_session_env(
+        &self,
+        message: &str,
+        action_id: &str,
+        _env: &HashMap<String, String>,
+        _cancel: &DialogCancel,
+    ) -> DialogResult {
+        let _ = DialogKind::Generic {
+            title: Message::from(MessageId::PolkitTitle).to_string(),
+            message: message.to_string(),
+            detail: action_id.to_string(),
+        };
+        DialogResult::Error
+    }
+}
+
+fn dialog_kind(
+    target: &PathBuf,
+    args: &[String],
+    prompt_title: Option<&str>,
+    prompt_message: Option<&str>,
+    prompt_detail: Option<&str>,
+    code: Option<&str>,
+    details: Option<&str>,
+) -> DialogKind {
+    // The dialog has no text entry: the code is only shown, after the command,
+    // and the full argv and environment below both
+    let shown = |text: String| {
+        let text = match code {
+            Some(code) => format!("{}  (code {})", text, code),
+            None => text,
+        };
+        match details {
+            Some(details) => format!("{}\n\n{}", text, details),
+            None => text,
+        }
+    };
+    match (prompt_title, prompt_message, prompt_detail) {
+        (Some(title), Some(message), Some(detail)) => DialogKind::Generic {
+            title: title.to_string(),
+            message: message.to_string(),
+            detail: shown(detail.to_string()),
+        },
+        _ => DialogKind::PrivilegeEscalation {
+            command: shown(command_text(target, args)),
+        },
+    }
+}
+
+fn command_text(target: &PathBuf, args: &[String]) -> String {
+    if args.is_empty() {
+        target.to_string_lossy().to_string()
+    } else {
+        format!("{} {}", target.display(), args.join(" "))
+    }
+}
+
+/// What a dialog helper shows, sent as msgpack on its stdin
+#[cfg(not(coverage))]
+#[derive(Serialize, Deserialize)]
+struct HelperRequest {
+    target: PathBuf,
+    args: Vec<String>,
+    title: Option<String>,
+    message: Option<String>,
+    detail: Option<String>,
+    /// `require_challenge` code to show with the prompt
+    code: Option<String>,
+    /// What's approved in full, shown below the prompt
+    details: Option<String>,
+    env: HashMap<String, String>,
+    confirm_delay_ms: u64,
+}
+
+/// Helper exit codes; anything else (including a signal) is an error
+#[cfg(not(coverage))]
+const HELPER_CONFIRMED: i32 = 0;
+#[cfg(not(coverage))]
+const HELPER_DENIED: i32 = 1;
+#[cfg(not(coverage))]
+const HELPER_ERROR: i32 = 2;
+#[cfg(not(coverage))]
+const HELPER_TIMED_OUT: i32 = 3;
+
+/// Show `request` in a fresh `authd --dialog-helper` and wait for its answer
+/// or for `cancel`
+#[cfg(not(coverage))]
+fn run_in_helper(request: &HelperRequest, cancel: &DialogCancel) -> DialogResult {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let spawned = Command::new("/proc/self/exe")
+        .arg(HELPER_ARG)
+        .stdin(Stdio::piped())
+        .spawn();
+    let mut helper = match spawned {
+        Ok(helper) => helper,
+        Err(e) => {
+            tracing::error!("spawn dialog helper: {}", e);
+            return DialogResult::Error;
+        }
+    };
+
+    let sent = match helper.stdin.take() {
+        Some(mut stdin) => rmp_serde::to_vec(request)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| stdin.write_all(&bytes).map_err(|e| e.to_string())),
+        None => Err("no stdin".to_string()),
+    };
+    if let Err(e) = sent {
+        tracing::error!("send dialog to helper: {}", e);
+        let _ = helper.kill();
+        let _ = helper.wait();
+        return DialogResult::Error;
+    }
+
+    match cancel.wait(helper).and_then(|status| status.code()) {
+        Some(HELPER_CONFIRMED) => DialogResult::Confirmed,
+        Some(HELPER_DENIED) => DialogResult::Denied,
+        Some(HELPER_TIMED_OUT) => DialogResult::TimedOut,
+        // Torn down on purpose; the requester isn't waiting for an answer
+        None if cancel.is_cancelled() => DialogResult::Denied,
+        _ => DialogResult::Error,
+    }
+}
+
+/// Body of `authd --dialog-helper`: show the dialog read from stdin and
+/// report the answer as the exit code
+#[cfg(not(coverage))]
+pub fn run_helper() -> i32 {
+    let request: HelperRequest = match rmp_serde::from_read(std::io::stdin()) {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("authd dialog helper: bad request: {}", e);
+            return HELPER_ERROR;
+        }
+    };
+    let confirm_delay = Duration::from_millis(request.confirm_delay_ms);
+    let deadline = Instant::now() + HELPER_TIMEOUT;
+
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now()).as_secs();
+        if left == 0 {
+            return HELPER_TIMED_OUT;
+        }
+        let config = DialogConfig {
+            kind: dialog_kind(
+                &request.target,
+                &request.args,
+                request.title.as_deref(),
+                request.message.as_deref(),
+                request.detail.as_deref(),
+                request.code.as_deref(),
+                request.details.as_deref(),
+            ),
+            timeout_secs: Some(left),
+        };
+
+        // session-dialog runs its own event loop on a thread of its own
+        let ready = ReadyAt::after(Instant::now(), confirm_delay);
+        let handle = session_dialog::show_dialog_async(config, request.env.clone());
+        match handle.join().unwrap_or(SdResult::Error) {
+            // Enter was already down when the dialog came up; ask again
+            SdResult::Confirmed if !ready.accepts(Instant::now()) => continue,
+            SdResult::Confirmed => return HELPER_CONFIRMED,
+            SdResult::Denied => return HELPER_DENIED,
+            SdResult::Timeout => return HELPER_TIMED_OUT,
+            SdResult::Error => return HELPER_ERROR,
+        }
+    }
+}
+
+fn has_reachable_session_env(env: &HashMap<String, String>) -> bool {
+    REQUIRED_SESSION_ENV
+        .iter()
+        .all(|key| env.get(*key).is_some_and(|value| !value.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_env_requires_wayland_display_and_runtime_dir() {
+        let env = HashMap::from([
+            ("WAYLAND_DISPLAY".to_string(), "wayland-1".to_string()),
+            ("XDG_RUNTIME_DIR".to_string(), "/run/user/1000".to_string()),
+        ]);
+
+        assert!(has_reachable_session_env(&env));
+    }
+
+    #[test]
+    fn session_env_rejects_missing_or_empty_values() {
+        assert!(!has_reachable_session_env(&HashMap::new()));
+
+        let missing_runtime =
+            HashMap::from([("WAYLAND_DISPLAY".to_string(), "wayland-1".to_string())]);
+        assert!(!has_reachable_session_env(&missing_runtime));
+
+        let empty_display = HashMap::from([
+            ("WAYLAND_DISPLAY".to_string(), String::new()),
+            ("XDG_RUNTIME_DIR".to_string(), "/run/user/1000".to_string()),
+        ]);
+        assert!(!has_reachable_session_env(&empty_display));
+    }
+
+    #[test]
+    fn polkit_dialog_returns_error_without_session_env() {
+        let result = SessionDialog::new(Duration::ZERO).show_polkit_dialog(
+            "Authentication is required.",
+            "org.freedesktop.systemd1.manage-units",
+            &HashMap::new(),
+            &DialogCancel::default(),
+        );
+
+        assert_eq!(result, DialogResult::Error);
+    }
+
+    #[cfg(coverage)]
+    #[test]
+    fn dialog_stubs_return_error_with_session_env() {
+        let env = HashMap::from([
+            ("WAYLAND_DISPLAY".to_string(), "wayland-1".to_string()),
+            ("XDG_RUNTIME_DIR".to_string(), "/run/user/1000".to_string()),
+        ]);
+        let caller = CallerInfo {
+            uid: 1000,
+            gid: 1000,
+            pid: 42,
+            exe: PathBuf::from("/usr/bin/authsudo"),
+        };
+
+        assert_eq!(
+            SessionDialog::new(Duration::ZERO).show_confirmation_dialog(
+                &caller,
+                &PathBuf::from("/usr/bin/id"),
+                &["-u".to_string()],
+                &env,
+                Prompt {
+                    title: Some("Title"),
+                    message: Some("Message"),
+                    detail: Some("Detail"),
+                },
+                &DialogCancel::default(),
+            ),
+            DialogResult::Error
+        );
+        assert_eq!(
+            SessionDialog::new(Duration::ZERO).show_polkit_dialog(
+                "Message",
+                "org.example.Action",
+                &env,
+                &DialogCancel::default()
+            ),
+            DialogResult::Error
+        );
+        assert_eq!(DialogResult::Confirmed, DialogResult::Confirmed);
+        assert_eq!(DialogResult::Denied, DialogResult::Denied);
+    }
+
+    #[test]
+    fn confirmation_dialog_returns_error_without_session_env() {
+        let caller = CallerInfo {
+            uid: 1000,
+            gid: 1000,
+            pid: 42,
+            exe: PathBuf::from("/usr/bin/authsudo"),
+        };
+
+        let result = SessionDialog::new(Duration::ZERO).show_confirmation_dialog(
+            &caller,
+            &PathBuf::from("/usr/bin/id"),
+            &["-u".to_string()],
+            &HashMap::new(),
+            Prompt::default(),
+            &DialogCancel::default(),
+        );
+
+        assert_eq!(result, DialogResult::Error);
+    }
+
+    #[test]
+    fn discouraged_dialog_returns_error_without_session_env() {
+        let caller = CallerInfo {
+            uid: 1000,
+            gid: 1000,
+            pid: 42,
+            exe: PathBuf::from("/usr/bin/authctl"),
+        };
+
+        let result = SessionDialog::new(Duration::ZERO).show_discouraged_dialog(
+            &caller,
+            &PathBuf::from("/usr/bin/rm"),
+            &["-rf".to_string()],
+            &HashMap::new(),
+            &DialogCancel::default(),
+        );
+
+        assert_eq!(result, DialogResult::Error);
+    }
+
+    #[test]
+    fn session_dialog_needs_a_session_to_confirm() {
+        let caller = CallerInfo {
+            uid: 1000,
+            gid: 1000,
+            pid: 42,
+            exe: PathBuf::from("/usr/bin/authctl"),
+        };
+        let request = AuthRequest {
+            target: PathBuf::from("/usr/bin/id"),
+            args: Vec::new(),
+            env: HashMap::new(),
+            password: String::new(),
+            confirm_only: false,
+            prompt_title: None,
+            prompt_message: None,
+            prompt_detail: None,
+            approval: None,
+            discouraged: false,
+            methods: None,
+            pam_service: None,
+        };
+
+        let cancel = DialogCancel::default();
+        assert_eq!(
+            SessionDialog::new(Duration::ZERO).confirm(&caller, &request, &cancel),
+            DialogResult::Error
+        );
+        assert_eq!(
+            SessionDialog::new(Duration::ZERO).confirm_discouraged(&caller, &request, &cancel),
+            DialogResult::Error
+        );
+    }
+
+    #[test]
+    fn prompts_fall_back_down_the_chain_without_a_session() {
+        let caller = CallerInfo {
+            uid: 1000,
+            gid: 1000,
+            pid: 0,
+            exe: PathBuf::from("/usr/bin/authctl"),
+        };
+        let request = AuthRequest {
+            target: PathBuf::from("/usr/bin/id"),
+            args: Vec::new(),
+            env: HashMap::new(),
+            password: String::new(),
+            confirm_only: false,
+            prompt_title: None,
+            prompt_message: None,
+            prompt_detail: None,
+            approval: None,
+            discouraged: false,
+            methods: None,
+            pam_service: None,
+        };
+        let polkit = PolkitRequest {
+            action_id: "org.example.Action".into(),
+            message: "Message".into(),
+            uid: 1000,
+            cookie: "cookie".into(),
+            env: HashMap::new(),
+        };
+        let cancel = DialogCancel::default();
+
+        // Neither the session lock nor a terminal can ask; deny ends the chain
+        let dialog = SessionDialog::new(Duration::ZERO).with_backends(vec![
+            DialogBackend::SessionLock,
+            DialogBackend::Tty,
+            DialogBackend::Deny,
+        ]);
+        assert_eq!(
+            dialog.confirm(&caller, &request, &cancel),
+            DialogResult::Denied
+        );
+        assert_eq!(
+            dialog.confirm_discouraged(&caller, &request, &cancel),
+            DialogResult::Denied
+        );
+        assert_eq!(
+            dialog.confirm_polkit(&polkit, &cancel),
+            DialogResult::Denied
+        );
+
+        // Without deny the failure shows as an error
+        let dialog = SessionDialog::new(Duration::ZERO).with_backends(vec![DialogBackend::Tty]);
+        assert_eq!(
+            dialog.confirm(&caller, &request, &cancel),
+            DialogResult::Error
+        );
+    }
+
+    #[test]
+    fn dialog_kind_prefers_explicit_prompt_text() {
+        let kind = dialog_kind(
+            &PathBuf::from("/usr/bin/id"),
+            &["-u".to_string()],
+            Some("Title"),
+            Some("Message"),
+            Some("Detail"),
+            None,
+            None,
+        );
+
+        match kind {
+            DialogKind::Generic {
+                title,
+                message,
+                detail,
+            } => {
+                assert_eq!(title, "Title");
+                assert_eq!(message, "Message");
+                assert_eq!(detail, "Detail");
+            }
+            _ => panic!("expected generic dialog"),
+        }
+    }
+
+    #[test]
+    fn dialog_kind_formats_privilege_command() {
+        let kind = dialog_kind(
+            &PathBuf::from("/usr/bin/id"),
+            &["-u".to_string(), "root".to_string()],
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+
+        match kind {
+            DialogKind::PrivilegeEscalation { command } => {
+                assert_eq!(command, "/usr/bin/id -u root");
+            }
+            _ => panic!("expected privilege escalation dialog"),
+        }
+
+        let kind = dialog_kind(
+            &PathBuf::from("/usr/bin/id"),
+            &[],
+            None,
+            None,
+            None,
+            Some("4721"),
+            Some("environment: (none)"),
+        );
+        match kind {
+            DialogKind::PrivilegeEscalation { command } => {
+                assert_eq!(command, "/usr/bin/id  (code 4721)\n\nenvironment: (none)");
+            }
+            _ => panic!("expected privilege escalation dialog"),
+        }
+
+        assert_eq!(
+            command_text(&PathBuf::from("/usr/bin/id"), &[]),
+            "/usr/bin/id"
+        );
+    }
+}
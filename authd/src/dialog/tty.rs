@@ -0,0 +1,501 @@
+//! Confirming on the caller's terminal where there is no graphical session
+//!
+//! Over SSH there's no Wayland session to lock, so authd writes the question
+//! to the caller's controlling terminal and reads a y/n answer from it. Only
+//! the terminal the caller's logind session was started on counts: the
+//! master end of a pty the caller opened itself (`script`, `expect`) is in
+//! the caller's hands, and it could answer for the user.
+//!
+//! Opening the terminal needs authd to run as root (or in group `tty`), so
+//! this does nothing under `privsep_user`.
+
+use super::{Challenge, DialogCancel, DialogResult, ReadyAt};
+use authd_protocol::AuthRequest;
+use authd_protocol::details::Details;
+use authd_protocol::messages::{Message, MessageId};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// How long the user has to answer, like the session-lock dialog
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often a waiting prompt checks whether it was cancelled
+const POLL: Duration = Duration::from_millis(100);
+/// Longest answer read; anything longer is a no
+const MAX_ANSWER: usize = 256;
+
+/// Ask `question` on the terminal of the caller's session, below the
+/// [`Details`] of `request`, ignoring what's typed in the first
+/// `confirm_delay`. With a `challenge`, its code is the only answer that
+/// approves.
+pub fn confirm(
+    pid: u32,
+    request: &AuthRequest,
+    question: &str,
+    challenge: Option<&Challenge>,
+    confirm_delay: Duration,
+    cancel: &DialogCancel,
+) -> DialogResult {
+    let Some(path) = session_terminal(pid) else {
+        return DialogResult::Error;
+    };
+    let opened = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(&path);
+    let tty = match opened {
+        Ok(tty) => tty,
+        Err(e) => {
+            tracing::warn!("open {} to confirm: {}", path.display(), e);
+            return DialogResult::Error;
+        }
+    };
+    match ask(
+        &tty,
+        &spelled_out(&Details::of(request), question),
+        challenge,
+        confirm_delay,
+        ANSWER_TIMEOUT,
+        || cancel.is_cancelled(),
+    ) {
+        Ok(result) => result,
+        Err(e) => {
+            tracing::warn!("confirm on {}: {}", path.display(), e);
+            DialogResult::Error
+        }
+    }
+}
+
+/// The question for an exec request (`auth = "confirm"`), on one line
+pub fn question(request: &AuthRequest) -> String {
+    let command = command_line(request);
+    match request.prompt_message.as_deref() {
+        Some(message) => format!(
+            "{} ({})",
+            message,
+            request.prompt_detail.as_deref().unwrap_or(&command)
+        ),
+        None => MessageId::RunAsRootQuestion.with(&[&command]).to_string(),
+    }
+}
+
+/// The question for a discouraged target, on one line
+pub fn discouraged_question(request: &AuthRequest) -> String {
+    MessageId::DiscouragedQuestion
+        .with(&[&command_line(request)])
+        .to_string()
+}
+
+/// `question` after the full argv and environment it approves, which the
+/// one line it fits on can hide. Control characters, which could rewrite
+/// what's already on the terminal, are shown escaped.
+fn spelled_out(details: &Details, question: &str) -> String {
+    let details: String = details
+        .to_string()
+        .chars()
+        .map(|c| match c {
+            '\n' => "\r\n".to_string(),
+            c if c.is_control() => c.escape_default().to_string(),
+            c => c.to_string(),
+        })
+        .collect();
+    format!("{}\r\nauthd: {}", details, question)
+}
+
+fn command_line(request: &AuthRequest) -> String {
+    let mut line = request.target.display().to_string();
+    for arg in &request.args {
+        line.push(' ');
+        line.push_str(arg);
+    }
+    line
+}
+
+/// The caller's controlling terminal, if it is its logind session's
+fn session_terminal(pid: u32) -> Option<PathBuf> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let tty_nr = authd_policy::session::controlling_tty(&stat).filter(|&tty| tty != 0)?;
+    let path = authd_policy::session::session_tty(pid)?;
+    let rdev = fs::metadata(&path).ok()?.rdev();
+    (device(tty_nr as u64) == device(rdev)).then_some(path)
+}
+
+/// Major and minor number of a device number, as glibc's `dev_t` or the
+/// kernel's `tty_nr` (the low 32 bits of the same layout) has them
+fn device(dev: u64) -> (u64, u64) {
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & 0xffff_f000);
+    let minor = (dev & 0xff) | ((dev >> 12) & 0xffff_ff00);
+    (major, minor)
+}
+
+/// Write `question` to `tty` and read one line back: confirmed for `y` or
+/// `yes` (the code of `challenge`, if there is one), timed out without an
+/// answer within `timeout`, denied otherwise or once `cancelled` turns true.
+/// Whatever is typed in the first `confirm_delay` is thrown away.
+pub fn ask(
+    tty: &File,
+    question: &str,
+    challenge: Option<&Challenge>,
+    confirm_delay: Duration,
+    timeout: Duration,
+    cancelled: impl Fn() -> bool,
+) -> Result<DialogResult, String> {
+    // Type-ahead from before the question must not answer it
+    unsafe { libc::tcflush(tty.as_raw_fd(), libc::TCIFLUSH) };
+    let mut out = tty;
+    match challenge {
+        Some(challenge) => write!(out, "\r\nauthd: {} {}: ", question, challenge),
+        None => write!(out, "\r\nauthd: {} [y/N] ", question),
+    }
+    .map_err(|e| e.to_string())?;
+
+    let shown = Instant::now();
+    let ready = ReadyAt::after(shown, confirm_delay);
+    let deadline = shown + timeout;
+    // Nor may keys still coming in right after it appeared: a repeating key
+    // or the rest of a line typed ahead
+    while !ready.accepts(Instant::now()) {
+        if cancelled() {
+            not_confirmed(out);
+            return Ok(DialogResult::Denied);
+        }
+        std::thread::sleep(ready.remaining(Instant::now()).min(POLL));
+    }
+    unsafe { libc::tcflush(tty.as_raw_fd(), libc::TCIFLUSH) };
+
+    let mut line = Vec::new();
+    while !line.contains(&b'\n') {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            not_confirmed(out);
+            return Ok(DialogResult::TimedOut);
+        }
+        if cancelled() || line.len() > MAX_ANSWER {
+            not_confirmed(out);
+            return Ok(DialogResult::Denied);
+        }
+        if !readable(tty, left.min(POLL))? {
+            continue;
+        }
+        let mut buf = [0; 64];
+        let read = out.read(&mut buf).map_err(|e| e.to_string())?;
+        if read == 0 {
+            return Err("terminal closed".into());
+        }
+        line.extend_from_slice(&buf[..read]);
+    }
+    let answer = String::from_utf8_lossy(&line);
+    let confirmed = match challenge {
+        Some(challenge) => challenge.accepts(&answer),
+        None => matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"),
+    };
+    if confirmed {
+        Ok(DialogResult::Confirmed)
+    } else {
+        Ok(DialogResult::Denied)
+    }
+}
+
+fn not_confirmed(mut out: &File) {
+    let _ = write!(
+        out,
+        "\r\nauthd: {}\r\n",
+        Message::from(MessageId::NotConfirmed)
+    );
+}
+
+/// Wait up to `wait` for input on `tty`
+fn readable(tty: &File, wait: Duration) -> Result<bool, String> {
+    let mut poll = libc::pollfd {
+        fd: tty.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let millis = libc::c_int::try_from(wait.as_millis()).unwrap_or(libc::c_int::MAX);
+    match unsafe { libc::poll(&mut poll, 1, millis) } {
+        -1 if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted => {
+            Ok(false)
+        }
+        -1 => Err(std::io::Error::last_os_error().to_string()),
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use std::os::fd::FromRawFd;
+    use std::path::Path;
+
+    /// A pty pair: (master, the terminal end authd would open)
+    fn pty() -> (File, File) {
+        unsafe {
+            let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(master >= 0);
+            assert_eq!(libc::grantpt(master), 0);
+            assert_eq!(libc::unlockpt(master), 0);
+            let mut name = [0 as libc::c_char; 64];
+            assert_eq!(libc::ptsname_r(master, name.as_mut_ptr(), name.len()), 0);
+            let path = CStr::from_ptr(name.as_ptr()).to_str().unwrap().to_string();
+            let terminal = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(libc::O_NOCTTY)
+                .open(path)
+                .unwrap();
+            (File::from_raw_fd(master), terminal)
+        }
+    }
+
+    /// Read what authd wrote until the question is complete, then type `answer`
+    fn answer_after_prompt(mut master: File, answer: &'static str) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || {
+            let mut shown = Vec::new();
+            let mut buf = [0; 256];
+            while !String::from_utf8_lossy(&shown).contains("[y/N] ") {
+                let read = master.read(&mut buf).unwrap();
+                shown.extend_from_slice(&buf[..read]);
+            }
+            master.write_all(answer.as_bytes()).unwrap();
+            // Keep the master open until the answer has been read
+            std::thread::sleep(Duration::from_millis(200));
+        })
+    }
+
+    #[test]
+    fn yes_on_the_terminal_confirms() {
+        let (master, terminal) = pty();
+        let typist = answer_after_prompt(master, "y\n");
+
+        let answer = ask(
+            &terminal,
+            "run /usr/bin/id?",
+            None,
+            Duration::ZERO,
+            Duration::from_secs(5),
+            || false,
+        );
+        assert_eq!(answer, Ok(DialogResult::Confirmed));
+        typist.join().unwrap();
+    }
+
+    #[test]
+    fn anything_but_yes_denies() {
+        let (master, terminal) = pty();
+        let typist = answer_after_prompt(master, "nope\n");
+
+        let answer = ask(
+            &terminal,
+            "run /usr/bin/id?",
+            None,
+            Duration::ZERO,
+            Duration::from_secs(5),
+            || false,
+        );
+        assert_eq!(answer, Ok(DialogResult::Denied));
+        typist.join().unwrap();
+    }
+
+    #[test]
+    fn with_a_challenge_only_its_code_confirms() {
+        let challenge = Challenge::new();
+        for (typed, expected) in [
+            ("y\n".to_string(), DialogResult::Denied),
+            (format!("{}\n", challenge.code()), DialogResult::Confirmed),
+        ] {
+            let (mut master, terminal) = pty();
+            let typist = std::thread::spawn(move || {
+                let mut shown = Vec::new();
+                let mut buf = [0; 256];
+                while !String::from_utf8_lossy(&shown).contains("to approve: ") {
+                    let read = master.read(&mut buf).unwrap();
+                    shown.extend_from_slice(&buf[..read]);
+                }
+                master.write_all(typed.as_bytes()).unwrap();
+                std::thread::sleep(Duration::from_millis(200));
+                String::from_utf8_lossy(&shown).into_owned()
+            });
+
+            let answer = ask(
+                &terminal,
+                "run /usr/bin/id?",
+                Some(&challenge),
+                Duration::ZERO,
+                Duration::from_secs(5),
+                || false,
+            );
+            assert_eq!(answer, Ok(expected));
+            let shown = typist.join().unwrap();
+            assert!(shown.contains(&format!("run /usr/bin/id? {}: ", challenge)));
+        }
+    }
+
+    #[test]
+    fn a_typed_confirmation_needs_the_whole_command_name() {
+        let challenge = Challenge::command(Path::new("/usr/sbin/mkfs"));
+        for (typed, expected) in [
+            ("y\n", DialogResult::Denied),
+            ("mkf\n", DialogResult::Denied),
+            ("/usr/sbin/mkfs\n", DialogResult::Denied),
+            ("mkfs\n", DialogResult::Confirmed),
+        ] {
+            let (mut master, terminal) = pty();
+            let typist = std::thread::spawn(move || {
+                let mut shown = Vec::new();
+                let mut buf = [0; 256];
+                while !String::from_utf8_lossy(&shown).contains("to approve: ") {
+                    let read = master.read(&mut buf).unwrap();
+                    shown.extend_from_slice(&buf[..read]);
+                }
+                master.write_all(typed.as_bytes()).unwrap();
+                std::thread::sleep(Duration::from_millis(200));
+                String::from_utf8_lossy(&shown).into_owned()
+            });
+
+            let answer = ask(
+                &terminal,
+                "run /usr/sbin/mkfs?",
+                Some(&challenge),
+                Duration::ZERO,
+                Duration::from_secs(5),
+                || false,
+            );
+            assert_eq!(answer, Ok(expected), "{typed:?}");
+            let shown = typist.join().unwrap();
+            assert!(shown.contains("run /usr/sbin/mkfs? Type mkfs to approve: "));
+        }
+    }
+
+    #[test]
+    fn answers_typed_before_the_delay_are_ignored() {
+        let (mut master, terminal) = pty();
+        let typist = std::thread::spawn(move || {
+            let mut shown = Vec::new();
+            let mut buf = [0; 256];
+            while !String::from_utf8_lossy(&shown).contains("[y/N] ") {
+                let read = master.read(&mut buf).unwrap();
+                shown.extend_from_slice(&buf[..read]);
+            }
+            // A held key repeating into the prompt, then the real answer
+            master.write_all(b"y\ny\ny\n").unwrap();
+            std::thread::sleep(Duration::from_millis(1500));
+            master.write_all(b"n\n").unwrap();
+            std::thread::sleep(Duration::from_millis(200));
+        });
+
+        let answer = ask(
+            &terminal,
+            "run /usr/bin/id?",
+            None,
+            Duration::from_secs(1),
+            Duration::from_secs(5),
+            || false,
+        );
+        assert_eq!(answer, Ok(DialogResult::Denied));
+        typist.join().unwrap();
+    }
+
+    #[test]
+    fn no_answer_in_time_times_out_and_cancelling_denies() {
+        let (_master, terminal) = pty();
+
+        let started = Instant::now();
+        let answer = ask(
+            &terminal,
+            "run?",
+            None,
+            Duration::ZERO,
+            Duration::from_millis(200),
+            || false,
+        );
+        assert_eq!(answer, Ok(DialogResult::TimedOut));
+
+        let answer = ask(
+            &terminal,
+            "run?",
+            None,
+            Duration::from_secs(30),
+            Duration::from_secs(30),
+            || true,
+        );
+        assert_eq!(answer, Ok(DialogResult::Denied));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn questions_fit_on_one_line() {
+        let mut request = AuthRequest {
+            target: PathBuf::from("/usr/bin/rm"),
+            args: vec!["-rf".into(), "/tmp/x".into()],
+            env: Default::default(),
+            password: String::new(),
+            confirm_only: false,
+            prompt_title: None,
+            prompt_message: None,
+            prompt_detail: None,
+            approval: None,
+            discouraged: false,
+            methods: None,
+            pam_service: None,
+        };
+        assert_eq!(question(&request), "run /usr/bin/rm -rf /tmp/x as root?");
+        assert_eq!(
+            discouraged_question(&request),
+            "/usr/bin/rm -rf /tmp/x is discouraged. Run it anyway?"
+        );
+
+        request.prompt_message = Some("Apply the new config?".into());
+        request.prompt_detail = Some("/etc/app.toml".into());
+        assert_eq!(question(&request), "Apply the new config? (/etc/app.toml)");
+    }
+
+    #[test]
+    fn questions_follow_the_details_they_approve() {
+        let request = AuthRequest {
+            target: PathBuf::from("/usr/bin/env"),
+            args: vec!["-i".into()],
+            env: [("EDITOR".to_string(), "vim\x1b[2K".to_string())].into(),
+            password: String::new(),
+            confirm_only: false,
+            prompt_title: None,
+            prompt_message: None,
+            prompt_detail: None,
+            approval: None,
+            discouraged: false,
+            methods: None,
+            pam_service: None,
+        };
+        assert_eq!(
+            spelled_out(&Details::of(&request), &question(&request)),
+            "argv[0] \"/usr/bin/env\"\r\n\
+             argv[1] \"-i\"\r\n\
+             environment:\r\n  \
+             EDITOR=vim\\u{1b}[2K\r\n\
+             authd: run /usr/bin/env -i as root?"
+        );
+    }
+
+    #[test]
+    fn callers_without_a_session_terminal_get_no_prompt() {
+        // Tests don't run in a logind session's terminal, and no pid is 0
+        assert_eq!(
+            confirm(0, "run?", Duration::ZERO, &DialogCancel::default()),
+            DialogResult::Error
+        );
+    }
+
+    #[test]
+    fn tty_nr_decodes_to_major_and_minor() {
+        // /dev/pts/3 and /dev/tty2
+        assert_eq!(device(34819), (136, 3));
+        assert_eq!(device(1026), (4, 2));
+        // Minor numbers above 255 are split around the major
+        assert_eq!(device((136 << 8) | (1 << 20) | 4), (136, 260));
+    }
+}
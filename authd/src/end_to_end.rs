@@ -0,0 +1,244 @@
+//! End-to-end tests: authd's own accept loop on a temporary socket, driven
+//! through `frame::call_socket` the way authctl and authsudo drive it.
+//!
+//! Policy comes from a string, dialogs and approvals are scripted, and the
+//! caller every rule admits is this test binary. Launching a target needs
+//! root (`systemd-run` into a scope), so the tests that get that far are
+//! skipped for anyone else.
+
+use super::*;
+use approval::ScriptedAuthenticator;
+use authd_protocol::frame;
+use dialog::ScriptedConfirmer;
+use serde::de::DeserializeOwned;
+use std::sync::mpsc;
+
+/// A daemon serving on a socket of its own until the test process exits
+struct TestDaemon {
+    dir: PathBuf,
+    socket: String,
+}
+
+impl TestDaemon {
+    /// Serve `policy`, with `confirmer` answering every dialog
+    fn start(name: &str, policy: &str, confirmer: Arc<ScriptedConfirmer>) -> Self {
+        Self::start_with(name, policy, confirmer, Config::default())
+    }
+
+    fn start_with(
+        name: &str,
+        policy: &str,
+        confirmer: Arc<ScriptedConfirmer>,
+        config: Config,
+    ) -> Self {
+        let dir = std::env::temp_dir().join(format!("authd-e2e-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("authd.sock").to_string_lossy().into_owned();
+
+        let mut engine = PolicyEngine::new();
+        engine.load_from_str(policy).unwrap();
+        let state = Arc::new(AppState {
+            policy: RwLock::new(Arc::new(engine)),
+            config,
+            cache: Mutex::new(AuthCache::new()),
+            cache_path: None,
+            scopes: Arc::new(Mutex::new(ScopeRegistry::new())),
+            confirmer,
+            dialogs: DialogQueue::default(),
+            authenticator: Arc::new(ScriptedAuthenticator {
+                user: String::new(),
+                password: String::new(),
+            }),
+            lockdown: Lockdown::new(dir.join("lockdown")),
+            sessions: Arc::new(ScriptedSessions(Vec::new())),
+            mode: StartupMode::Full,
+            audit_feed: AuditFeed::new(audit::FEED_CAPACITY),
+            bus: None,
+        });
+
+        let (bound, ready) = mpsc::channel();
+        let path = socket.clone();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async move {
+                let server = Server::bind(&path).unwrap();
+                bound.send(()).unwrap();
+                serve(server, state).await;
+            });
+        });
+        ready.recv().unwrap();
+        Self { dir, socket }
+    }
+
+    fn call<R: DeserializeOwned>(&self, request: &DaemonRequest) -> R {
+        frame::call_socket(&self.socket, request)
+            .unwrap_or_else(|e| panic!("call {}: {}", self.socket, e))
+    }
+
+    fn exec(&self, target: &str) -> AuthResponse {
+        self.call(&DaemonRequest::Exec(AuthRequest {
+            target: PathBuf::from(target),
+            args: Vec::new(),
+            env: HashMap::new(),
+            password: String::new(),
+            confirm_only: false,
+            prompt_title: None,
+            prompt_message: None,
+            prompt_detail: None,
+            approval: None,
+            discouraged: false,
+            methods: None,
+            pam_service: None,
+        }))
+    }
+
+    fn check(&self, target: &str) -> AuthCheckResponse {
+        self.call(&DaemonRequest::Check(AuthCheckRequest {
+            target: PathBuf::from(target),
+            args: Vec::new(),
+        }))
+    }
+}
+
+impl Drop for TestDaemon {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// `true` runs straight away, `id` after confirmation, `rm` never, each
+/// only for this test binary
+fn policy() -> String {
+    let me = std::fs::canonicalize(std::env::current_exe().unwrap()).unwrap();
+    let rule = |target: &str, auth: &str| {
+        format!(
+            "[[rules]]\ntarget = \"{target}\"\nallow_callers = [\"{}\"]\nauth = \"{auth}\"\n",
+            me.display()
+        )
+    };
+    [
+        rule("/usr/bin/true", "none"),
+        rule("/usr/bin/id", "confirm"),
+        rule("/usr/bin/rm", "deny"),
+    ]
+    .join("\n")
+}
+
+#[test]
+fn allowed_targets_need_no_dialog() {
+    let confirmer = Arc::new(ScriptedConfirmer::new(DialogResult::Error));
+    let daemon = TestDaemon::start("allowed", &policy(), confirmer.clone());
+
+    assert!(matches!(
+        daemon.check("/usr/bin/true"),
+        AuthCheckResponse::Allowed
+    ));
+    assert!(matches!(
+        daemon.check("/usr/bin/id"),
+        AuthCheckResponse::PasswordRequired
+    ));
+    assert_eq!(confirmer.shown(), 0);
+}
+
+#[test]
+fn denied_and_unknown_targets_are_refused() {
+    let confirmer = Arc::new(ScriptedConfirmer::new(DialogResult::Confirmed));
+    let daemon = TestDaemon::start("refused", &policy(), confirmer.clone());
+
+    assert!(matches!(
+        daemon.exec("/usr/bin/rm"),
+        AuthResponse::Denied { reason } if reason == "target denied by policy"
+    ));
+    assert!(matches!(
+        daemon.check("/usr/bin/rm"),
+        AuthCheckResponse::Denied { .. }
+    ));
+    assert!(matches!(
+        daemon.exec("/usr/bin/unlisted"),
+        AuthResponse::UnknownTarget
+    ));
+    assert!(matches!(
+        daemon.check("/usr/bin/unlisted"),
+        AuthCheckResponse::Unknown
+    ));
+    assert_eq!(confirmer.shown(), 0);
+}
+
+#[cfg(feature = "gui")]
+#[test]
+fn confirmation_turned_down_is_refused() {
+    let confirmer = Arc::new(ScriptedConfirmer::new(DialogResult::Denied));
+    let daemon = TestDaemon::start("turned-down", &policy(), confirmer.clone());
+
+    assert!(matches!(
+        daemon.exec("/usr/bin/id"),
+        AuthResponse::Denied { .. }
+    ));
+    assert_eq!(confirmer.shown(), 1);
+    // Nothing was remembered
+    assert!(matches!(
+        daemon.check("/usr/bin/id"),
+        AuthCheckResponse::PasswordRequired
+    ));
+}
+
+#[cfg(feature = "gui")]
+#[test]
+fn confirmed_targets_launch_and_are_remembered() {
+    if unsafe { libc::getuid() } != 0 {
+        eprintln!("skipped: launching a target needs root");
+        return;
+    }
+    let confirmer = Arc::new(ScriptedConfirmer::new(DialogResult::Confirmed));
+    let daemon = TestDaemon::start("confirmed", &policy(), confirmer.clone());
+
+    // Without systemd running, systemd-run fails after the confirmation
+    let response = daemon.exec("/usr/bin/id");
+    assert!(
+        matches!(
+            response,
+            AuthResponse::Success { .. } | AuthResponse::Error { .. }
+        ),
+        "{response:?}"
+    );
+    assert_eq!(confirmer.shown(), 1);
+    assert!(matches!(
+        daemon.check("/usr/bin/id"),
+        AuthCheckResponse::Cached { .. }
+    ));
+}
+
+#[test]
+fn clients_that_never_send_a_request_are_dropped() {
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+    use std::time::Instant;
+
+    let confirmer = Arc::new(ScriptedConfirmer::new(DialogResult::Error));
+    let config = Config {
+        request_timeout_secs: 1,
+        ..Config::default()
+    };
+    let daemon = TestDaemon::start_with("stalled", &policy(), confirmer, config);
+
+    let mut stalled = UnixStream::connect(&daemon.socket).unwrap();
+    stalled
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let connected = Instant::now();
+    // The daemon answers with an error and closes; a read timeout fails here
+    let mut reply = Vec::new();
+    stalled.read_to_end(&mut reply).unwrap();
+    assert!(connected.elapsed() >= Duration::from_secs(1));
+    assert!(!reply.is_empty());
+
+    // Clients that do send a request are unaffected
+    assert!(matches!(
+        daemon.check("/usr/bin/true"),
+        AuthCheckResponse::Allowed
+    ));
+}
@@ -0,0 +1,211 @@
+//! The groups a caller process actually holds
+//!
+//! Read from `/proc/<pid>/status` for `group_source = "process"`. The
+//! status's real uid must still be the peer's, so a pid recycled by another
+//! user's process after connecting isn't taken for the caller.
+//!
+//! They are read once, when the connection is accepted, and that snapshot
+//! decides the whole request: a caller that changes its groups while its
+//! request waits is still judged by the groups it connected with.
+
+use peercred_ipc::CallerInfo;
+use std::ops::Deref;
+
+/// A connected caller with the groups and SELinux context it held when it
+/// connected
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub info: CallerInfo,
+    /// What `process_groups` returned at accept time
+    pub gids: Result<Vec<u32>, String>,
+    /// What `secontext::of_caller` returned at accept time
+    pub secontext: Option<String>,
+    /// The systemd unit `authd_policy::unit::of_process` found at accept time
+    pub unit: Option<String>,
+}
+
+impl Caller {
+    /// Take the group, context and unit snapshot for a caller that just
+    /// connected
+    pub fn accepted(info: CallerInfo) -> Self {
+        let gids = process_groups(&info);
+        let secontext = crate::secontext::of_caller(&info);
+        let unit = unit_of_caller(&info);
+        Self {
+            info,
+            gids,
+            secontext,
+            unit,
+        }
+    }
+}
+
+/// The caller's systemd unit, read after its cgroup so that a pid recycled
+/// by someone else in between isn't taken for the caller
+fn unit_of_caller(caller: &CallerInfo) -> Option<String> {
+    let unit = authd_policy::unit::of_process(caller.pid)?;
+    let owner = process_owner(caller.pid).ok()?;
+    (owner.0 == caller.uid).then_some(unit)
+}
+
+impl Deref for Caller {
+    type Target = CallerInfo;
+
+    fn deref(&self) -> &CallerInfo {
+        &self.info
+    }
+}
+
+/// Real gid followed by the supplementary groups of the calling process
+pub fn process_groups(caller: &CallerInfo) -> Result<Vec<u32>, String> {
+    let path = format!("/proc/{}/status", caller.pid);
+    let status = std::fs::read_to_string(&path).map_err(|e| format!("read {}: {}", path, e))?;
+    let status = parse_status(&status).ok_or_else(|| format!("malformed {}", path))?;
+    if status.uid != caller.uid {
+        return Err(format!("caller pid {} changed owner", caller.pid));
+    }
+
+    let mut gids = vec![status.gid];
+    gids.extend(status.groups.into_iter().filter(|&gid| gid != status.gid));
+    Ok(gids)
+}
+
+/// Real uid and gid of process `pid`, for a caller known only by its pid
+pub fn process_owner(pid: u32) -> Result<(u32, u32), String> {
+    let path = format!("/proc/{}/status", pid);
+    let status = std::fs::read_to_string(&path).map_err(|e| format!("read {}: {}", path, e))?;
+    let status = parse_status(&status).ok_or_else(|| format!("malformed {}", path))?;
+    Ok((status.uid, status.gid))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct ProcStatus {
+    uid: u32,
+    gid: u32,
+    groups: Vec<u32>,
+}
+
+/// The real uid/gid and `Groups:` of a status file; None if any is missing
+fn parse_status(status: &str) -> Option<ProcStatus> {
+    let mut uid = None;
+    let mut gid = None;
+    let mut groups = None;
+
+    for line in status.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        match key {
+            // Uid:/Gid: list real, effective, saved and filesystem ids
+            "Uid" => uid = first_id(value),
+            "Gid" => gid = first_id(value),
+            "Groups" => groups = Some(parse_groups(value)?),
+            _ => {}
+        }
+    }
+
+    Some(ProcStatus {
+        uid: uid?,
+        gid: gid?,
+        groups: groups?,
+    })
+}
+
+fn first_id(value: &str) -> Option<u32> {
+    value.split_whitespace().next()?.parse().ok()
+}
+
+/// `Groups:` holds space-separated gids, and is empty for a process
+/// without supplementary groups
+fn parse_groups(value: &str) -> Option<Vec<u32>> {
+    value
+        .split_whitespace()
+        .map(|gid| gid.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUS: &str = "Name:\tbash\n\
+                          Umask:\t0022\n\
+                          State:\tS (sleeping)\n\
+                          Uid:\t1000\t1000\t1000\t1000\n\
+                          Gid:\t100\t100\t100\t100\n\
+                          FDSize:\t256\n\
+                          Groups:\t10 100 998 \n\
+                          NStgid:\t4242\n";
+
+    #[test]
+    fn parses_real_ids_and_supplementary_groups() {
+        assert_eq!(
+            parse_status(STATUS),
+            Some(ProcStatus {
+                uid: 1000,
+                gid: 100,
+                groups: vec![10, 100, 998],
+            })
+        );
+    }
+
+    #[test]
+    fn empty_groups_line_means_no_supplementary_groups() {
+        let status = STATUS.replace("Groups:\t10 100 998 ", "Groups:\t");
+        assert_eq!(parse_status(&status).unwrap().groups, Vec::<u32>::new());
+    }
+
+    #[test]
+    fn rejects_missing_or_garbled_fields() {
+        assert_eq!(parse_status(&STATUS.replace("Groups:", "Grps:")), None);
+        assert_eq!(parse_status(&STATUS.replace("998", "wheel")), None);
+        assert_eq!(
+            parse_status(&STATUS.replace("Uid:\t1000\t1000\t1000\t1000", "Uid:")),
+            None
+        );
+    }
+
+    #[test]
+    fn reads_own_process_groups() {
+        let caller = CallerInfo {
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+            pid: std::process::id(),
+            exe: std::path::PathBuf::from("/usr/bin/authsudo"),
+        };
+
+        let gids = process_groups(&caller).unwrap();
+        assert_eq!(gids[0], caller.gid);
+
+        let stranger = CallerInfo {
+            uid: caller.uid.wrapping_add(1),
+            ..caller
+        };
+        assert!(process_groups(&stranger).is_err());
+    }
+
+    #[test]
+    fn accepted_callers_keep_their_groups_from_connect_time() {
+        let info = CallerInfo {
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+            pid: std::process::id(),
+            exe: std::path::PathBuf::from("/usr/bin/authsudo"),
+        };
+        let caller = Caller::accepted(info.clone());
+        assert_eq!(caller.gids, process_groups(&info));
+        assert_eq!(caller.pid, info.pid);
+        assert_eq!(caller.unit, authd_policy::unit::of_process(info.pid));
+
+        let gone = Caller::accepted(CallerInfo { pid: 0, ..info });
+        assert!(gone.gids.is_err());
+        assert_eq!(gone.unit, None);
+    }
+
+    #[test]
+    fn reads_own_process_owner() {
+        let own = unsafe { (libc::getuid(), libc::getgid()) };
+        assert_eq!(process_owner(std::process::id()), Ok(own));
+        assert!(process_owner(0).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+//! The request socket, read and written through `authd_protocol::frame`
+//!
+//! The same length-prefixed msgpack frames clients send, so every request
+//! is held to `MAX_FRAME_LEN` before a buffer is allocated for it, on the
+//! path the fuzz targets cover. Callers are identified by `SO_PEERCRED` at
+//! accept time, and by `/proc/<pid>/exe` for the executable.
+
+use authd_protocol::frame::{self, FrameError};
+use peercred_ipc::CallerInfo;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+
+/// A bound request socket
+pub struct Server {
+    listener: UnixListener,
+}
+
+impl Server {
+    /// Bind `path`, replacing a socket left behind by an authd that's gone.
+    /// One still answering is left alone.
+    pub fn bind(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use by a running daemon", path.display()),
+            ));
+        }
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        Ok(Self {
+            listener: UnixListener::bind(path)?,
+        })
+    }
+
+    /// The next client, with the credentials it connected with
+    #[cfg_attr(coverage, allow(dead_code))]
+    pub async fn accept(&self) -> io::Result<(Connection, CallerInfo)> {
+        let (stream, _) = self.listener.accept().await?;
+        let cred = stream.peer_cred()?;
+        let pid = cred
+            .pid()
+            .and_then(|pid| u32::try_from(pid).ok())
+            .ok_or_else(|| io::Error::other("peer pid unknown"))?;
+        let exe =
+            std::fs::read_link(format!("/proc/{}/exe", pid)).unwrap_or_else(|_| PathBuf::new());
+        let caller = CallerInfo {
+            uid: cred.uid(),
+            gid: cred.gid(),
+            pid,
+            exe,
+        };
+        Ok((Connection { stream }, caller))
+    }
+}
+
+/// One client's connection
+pub struct Connection {
+    stream: UnixStream,
+}
+
+impl Connection {
+    /// Read one frame, however many reads it takes
+    pub async fn read<T: DeserializeOwned>(&mut self) -> Result<T, FrameError> {
+        let mut prefix = [0u8; frame::PREFIX_LEN];
+        self.stream.read_exact(&mut prefix).await?;
+        let mut payload = vec![0u8; frame::payload_len(prefix)?];
+        self.stream.read_exact(&mut payload).await?;
+        frame::decode_payload(&payload)
+    }
+
+    /// Write one frame
+    pub async fn write<T: Serialize>(&mut self, message: &T) -> Result<(), FrameError> {
+        self.stream.write_all(&frame::encode(message)?).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use authd_protocol::{AuthResponse, DaemonRequest};
+
+    fn pair() -> (Connection, UnixStream) {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        (Connection { stream: ours }, theirs)
+    }
+
+    #[tokio::test]
+    async fn requests_are_read_as_frames() {
+        let (mut conn, mut client) = pair();
+        let frame = frame::encode(&DaemonRequest::ListActive).unwrap();
+        // Split across writes, the way a stream may deliver it
+        let (head, tail) = frame.split_at(3);
+        client.write_all(head).await.unwrap();
+        let reader = tokio::spawn(async move { conn.read::<DaemonRequest>().await });
+        client.write_all(tail).await.unwrap();
+        assert!(matches!(
+            reader.await.unwrap(),
+            Ok(DaemonRequest::ListActive)
+        ));
+    }
+
+    #[tokio::test]
+    async fn oversized_frames_are_refused_before_allocating() {
+        let (mut conn, mut client) = pair();
+        let prefix = (frame::MAX_FRAME_LEN as u32 + 1).to_be_bytes();
+        client.write_all(&prefix).await.unwrap();
+        assert!(matches!(
+            conn.read::<DaemonRequest>().await,
+            Err(FrameError::TooLarge(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn responses_are_written_as_frames() {
+        let (mut conn, client) = pair();
+        conn.write(&AuthResponse::Denied {
+            reason: "no".into(),
+        })
+        .await
+        .unwrap();
+        let mut client = client.into_std().unwrap();
+        client.set_nonblocking(false).unwrap();
+        let response: AuthResponse = frame::read_frame(&mut client).unwrap();
+        assert!(matches!(response, AuthResponse::Denied { reason } if reason == "no"));
+    }
+
+    #[tokio::test]
+    async fn a_live_socket_is_not_replaced() {
+        let dir = std::env::temp_dir().join(format!("authd-ipc-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("authd.sock");
+
+        let first = Server::bind(&path).unwrap();
+        let error = Server::bind(&path).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+        // Gone, its socket file is stale and may be bound again
+        drop(first);
+        assert!(Server::bind(&path).is_ok());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,98 @@
+//! authd's side of lockdown: switching the flag file on admins' request
+//! (see `authd_protocol::lockdown`)
+
+use crate::runtime_file;
+use authd_protocol::lockdown;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+pub struct Lockdown {
+    flag: PathBuf,
+    /// The default instance's flag, which a named instance also obeys
+    global: Option<PathBuf>,
+}
+
+impl Lockdown {
+    pub fn new(flag: PathBuf) -> Self {
+        Self { flag, global: None }
+    }
+
+    /// Also locked down while `global` is set, whatever this instance's
+    /// own flag says
+    pub fn with_global(mut self, global: Option<PathBuf>) -> Self {
+        self.global = global;
+        self
+    }
+
+    /// Read from the flags on every call, so a lockdown set before a restart
+    /// (or cleared by hand) is seen straight away
+    pub fn is_active(&self) -> bool {
+        lockdown::is_active(&self.flag) || self.global.as_deref().is_some_and(lockdown::is_active)
+    }
+
+    pub fn set(&self, active: bool) -> io::Result<()> {
+        if active {
+            return runtime_file::touch(&self.flag, runtime_file::STATE_MODE);
+        }
+        match fs::remove_file(&self.flag) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn lockdown_survives_a_restart_until_cleared() {
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let flag = std::env::temp_dir().join(format!("authd-lockdown-{nonce}"));
+
+        let lockdown = Lockdown::new(flag.clone());
+        assert!(!lockdown.is_active());
+        lockdown.set(true).unwrap();
+        assert!(lockdown.is_active());
+        // Setting it twice is fine
+        lockdown.set(true).unwrap();
+
+        let restarted = Lockdown::new(flag.clone());
+        assert!(restarted.is_active());
+        restarted.set(false).unwrap();
+        assert!(!lockdown.is_active());
+        // So is clearing it twice
+        restarted.set(false).unwrap();
+        assert!(!flag.exists());
+    }
+
+    #[test]
+    fn named_instances_obey_the_global_flag() {
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let global = std::env::temp_dir().join(format!("authd-lockdown-global-{nonce}"));
+        let flag = std::env::temp_dir().join(format!("authd-lockdown-test-{nonce}"));
+        let default = Lockdown::new(global.clone());
+        let named = Lockdown::new(flag.clone()).with_global(Some(global.clone()));
+
+        default.set(true).unwrap();
+        assert!(named.is_active());
+        // Clearing its own flag doesn't lift the global lockdown
+        named.set(false).unwrap();
+        assert!(named.is_active());
+        default.set(false).unwrap();
+        assert!(!named.is_active());
+
+        // Its own lockdown is its alone
+        named.set(true).unwrap();
+        assert!(!default.is_active());
+        named.set(false).unwrap();
+    }
+}
@@ -1,20 +1,72 @@
+mod approval;
+mod audit;
+mod cache;
 mod dialog;
+#[cfg(all(test, not(coverage)))]
+mod end_to_end;
+mod groups;
+mod ipc;
+mod lockdown;
+mod notify;
+mod output;
+#[cfg(feature = "polkit-service")]
+mod polkit_service;
+mod privsep;
+mod queue;
+mod runas;
+mod runtime_file;
+mod scopes;
+mod secontext;
 
-use authd_policy::{PolicyDecision, PolicyEngine};
-use authd_protocol::{AuthRequest, AuthResponse};
+use approval::Authenticator;
+use audit::{AuditEvent, AuditEventType, AuditFeed, AuditOutcome};
+use authd_policy::HeadlessConfirm;
 #[cfg(not(coverage))]
-use authd_protocol::{DaemonRequest, PolkitReply, PolkitRequest, SOCKET_PATH};
+use authd_policy::session::Logind;
+use authd_policy::session::{SessionSource, others_logged_in_refusal};
+use authd_policy::{
+    CallerInfo as PolicyCaller, Config, GroupSource, Groups, PolicyDecision, PolicyEngine,
+    RunSettings,
+};
 #[cfg(not(coverage))]
-use dialog::{DialogResult, show_confirmation_dialog, show_polkit_dialog};
-#[cfg(coverage)]
-use peercred_ipc::CallerInfo;
+use authd_policy::{LogFormat, SocketDirCheck};
+use authd_protocol::instance::InstanceName;
+use authd_protocol::limits::ResourceLimits;
+use authd_protocol::lockdown::LOCKDOWN_REASON;
+use authd_protocol::pam::{DEFAULT_SERVICE as DEFAULT_PAM_SERVICE, PamService};
+use authd_protocol::{
+    ActiveScope, AuditStreamMessage, AuthCheckRequest, AuthCheckResponse, AuthMethod, AuthRequest,
+    AuthResponse, CachedAuth, MethodSet,
+};
 #[cfg(not(coverage))]
-use peercred_ipc::{CallerInfo, Connection, Server};
-use std::collections::HashMap;
+use authd_protocol::{
+    DaemonRequest, KillScopeRequest, KillScopeResponse, KillSignal, PolkitReply, PolkitRequest,
+    instance,
+};
+use authd_protocol::{LockdownRequest, LockdownResponse, TestAuthRequest, TestAuthResponse};
+use cache::{AuthCache, Kind};
 #[cfg(not(coverage))]
-use std::sync::Arc;
+use dialog::SessionDialog;
+use dialog::{CancelOnDrop, Confirmer, DialogCancel, DialogResult};
+use groups::Caller;
+use ipc::{Connection, Server};
+use lockdown::Lockdown;
+use output::LogOutput;
 #[cfg(not(coverage))]
-use tracing::{error, info};
+use privsep::StartupMode;
+use queue::{DialogQueue, QueueError};
+use runas::RunAs;
+#[cfg(not(coverage))]
+use scopes::KillRefusal;
+use scopes::ScopeRegistry;
+use std::collections::HashMap;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
+use tracing::{error, info, warn};
 #[cfg(not(coverage))]
 use zbus::zvariant::Value;
 
@@ -25,38 +77,231 @@ const PK_AUTHORITY_PATH: &str = "/org/freedesktop/PolicyKit1/Authority";
 #[cfg(not(coverage))]
 const PK_AUTHORITY_IFACE: &str = "org.freedesktop.PolicyKit1.Authority";
 
+/// Why a request is denied when its confirmation dialog went unanswered
+#[cfg(feature = "gui")]
+const CONFIRM_TIMED_OUT: &str = "confirmation timed out";
+
+/// The methods authd's dialog can perform: a keypress. It has no text
+/// entry; a password comes with the request and is checked through PAM.
+const DIALOG_METHODS: &[AuthMethod] = &[AuthMethod::Confirm];
+
+/// Whether a rule taking `methods` needs the requester's password: the
+/// dialog alone can't satisfy it
+fn needs_password(methods: Option<&MethodSet>) -> bool {
+    methods.is_some_and(|methods| !methods.satisfied_by(DIALOG_METHODS))
+}
+
+/// Whether a rule taking `methods` needs the dialog: the password alone
+/// doesn't satisfy it
+fn needs_dialog(methods: Option<&MethodSet>) -> bool {
+    !methods.is_some_and(|methods| methods.satisfied_by(&[AuthMethod::Password]))
+}
+
+/// What an evaluation-only daemon answers every launch with
+const EVALUATION_ONLY: &str =
+    "authd is running evaluation only (not started as root) and launches nothing";
+
+/// How often a request waiting for the dialog checks its client still runs
+const CALLER_POLL: Duration = Duration::from_millis(500);
+
 struct AppState {
-    policy: PolicyEngine,
-    /// System-bus connection used to assert polkit authentication responses.
+    /// The loaded policy; read through [`AppState::policy`], swapped whole
+    /// by [`AppState::replace_policy`]
+    policy: RwLock<Arc<PolicyEngine>>,
+    config: Config,
+    /// Recent confirmations, so repeats within `cache_timeout` skip the dialog.
+    cache: Mutex<AuthCache>,
+    /// Where the cache is saved after each change (None: memory only)
+    cache_path: Option<PathBuf>,
+    /// Scopes launched by authd that are still running; shared with the
+    /// per-launch tasks that remove entries on exit.
+    scopes: Arc<Mutex<ScopeRegistry>>,
+    /// Shows confirmation dialogs (scripted in tests)
+    confirmer: Arc<dyn Confirmer>,
+    /// Serializes dialogs so concurrent requests are prompted one at a time
+    dialogs: DialogQueue,
+    /// Checks approvers' passwords for the two-person rule (PAM; scripted
+    /// in tests)
+    authenticator: Arc<dyn Authenticator>,
+    /// Set by admins in an incident: everything but `emergency_allow` is
+    /// refused
+    lockdown: Lockdown,
+    /// Who is logged in, for `forbid_if_others_logged_in` (logind; scripted
+    /// in tests)
+    sessions: Arc<dyn SessionSource>,
+    /// Whether authd may launch anything (it was started as root)
+    mode: StartupMode,
+    /// Every recorded audit event, for `SubscribeAudit` streams
+    audit_feed: AuditFeed,
+    /// System-bus connection used to assert polkit authentication responses
+    /// (None in tests, which run without a bus)
     #[cfg(not(coverage))]
-    bus: zbus::Connection,
+    bus: Option<zbus::Connection>,
+}
+
+/// These users, and nobody else, are logged in
+#[cfg(test)]
+struct ScriptedSessions(Vec<u32>);
+
+#[cfg(test)]
+impl SessionSource for ScriptedSessions {
+    fn logged_in_uids(&self) -> std::io::Result<Vec<u32>> {
+        Ok(self.0.clone())
+    }
 }
 
 #[cfg(not(coverage))]
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
+    // Read first so the log format applies from the very first line
+    let config = Config::load();
+    let format = config
+        .as_ref()
+        .map_or(LogFormat::Text, |config| config.log_format);
+    init_logging(format);
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some(dialog::HELPER_ARG) {
+        std::process::exit(dialog::run_helper());
+    }
+    let instance = parse_instance(&args).map_err(|e| anyhow::anyhow!(e))?;
+    if let Some(name) = &instance {
+        info!("running as instance {}", name);
+    }
+
+    // Defaults could be laxer than what the file meant to set: don't guess
+    let config = config.map_err(|e| anyhow::anyhow!("failed to load config: {}", e))?;
+    authd_policy::procfs::require().map_err(|e| anyhow::anyhow!(e))?;
+    // Without root every launch would fail later, and less clearly
+    let mode = privsep::startup_mode(unsafe { libc::geteuid() }, config.non_root_startup)
+        .map_err(|e| anyhow::anyhow!(e))?;
+    if mode == StartupMode::EvaluationOnly {
+        warn!("not started as root: evaluation only, every launch will be refused");
+    }
 
     // Load policies
     let mut policy = PolicyEngine::new();
-    if let Err(e) = policy.load() {
+    policy.set_resolution(config.rule_resolution);
+    policy.set_duplicate_targets(config.duplicate_targets);
+    policy.set_force_password_targets(&config.force_password_targets);
+    let drop_ins = authd_policy::drop_in_dir(instance.as_ref(), config.profile.as_deref())
+        .map_err(|e| anyhow::anyhow!(e))?;
+    if let Some(profile) = &config.profile {
+        info!("using policy profile {}", profile);
+    }
+    if let Err(e) = policy.load_all(&authd_policy::policy_file(instance.as_ref()), &drop_ins) {
         error!("failed to load policies: {}", e);
     }
+    for warning in policy.validate() {
+        warn!("policy: {}", warning);
+    }
+    info!(
+        "loaded {} policy rules ({} disabled)",
+        policy.rule_count(),
+        policy.disabled_count()
+    );
+    // Running on would deny everything with no hint why
+    if policy.nothing_loaded() {
+        anyhow::bail!("no policy file could be loaded; refusing to start");
+    }
+
+    // Everything needing root's identity happens before privileges are dropped
+    let socket_path =
+        std::env::var("AUTHD_SOCKET").unwrap_or_else(|_| instance::socket_path(instance.as_ref()));
+    check_socket_dir(Path::new(&socket_path), config.socket_dir_check)?;
+    let server = Server::bind(&socket_path)?;
+    runtime_file::set_mode(Path::new(&socket_path), runtime_file::SOCKET_MODE)
+        .map_err(|e| anyhow::anyhow!("set socket mode: {e}"))?;
+    info!("authd listening on {}", socket_path);
 
     let bus = zbus::Connection::system()
         .await
         .map_err(|e| anyhow::anyhow!("connect system bus: {e}"))?;
 
-    let state = Arc::new(AppState { policy, bus });
+    if let Some(user) = &config.privsep_user {
+        privsep::drop_privileges(user).map_err(|e| anyhow::anyhow!("drop privileges: {e}"))?;
+        info!("privilege separation: running as {}", user);
+    }
+    let creds = privsep::current_creds().map_err(|e| anyhow::anyhow!(e))?;
+    privsep::check_privileges(config.privsep_user.as_deref(), &creds)
+        .map_err(|e| anyhow::anyhow!(e))?;
+
+    let authenticator = approval::backend(config.pam_fallback);
+    if !authenticator.primary.available() {
+        warn!(
+            "no PAM stack configured; approvals fall back to {:?}",
+            config.pam_fallback
+        );
+    }
 
-    let socket_path = std::env::var("AUTHD_SOCKET").unwrap_or_else(|_| SOCKET_PATH.to_string());
-    let server = Server::bind(&socket_path)?;
-    info!("authd listening on {}", socket_path);
+    let cache_path = cache::state_path(instance.as_ref());
+    let lockdown = Lockdown::new(authd_protocol::lockdown::flag_path(instance.as_ref()))
+        .with_global(authd_protocol::lockdown::global_flag(instance.as_ref()));
+    if lockdown.is_active() {
+        warn!("in lockdown: only emergency_allow targets may run");
+    }
+    let confirmer = SessionDialog::new(Duration::from_millis(config.confirm_delay_ms))
+        .with_challenge(config.require_challenge)
+        .with_backends(config.dialog_fallback.clone());
+    let state = Arc::new(AppState {
+        policy: RwLock::new(Arc::new(policy)),
+        config,
+        cache: Mutex::new(AuthCache::load(&cache_path)),
+        cache_path: Some(cache_path),
+        scopes: Arc::new(Mutex::new(ScopeRegistry::new())),
+        confirmer: Arc::new(confirmer),
+        dialogs: DialogQueue::default(),
+        authenticator: Arc::new(authenticator),
+        lockdown,
+        sessions: Arc::new(Logind),
+        mode,
+        audit_feed: AuditFeed::new(audit::FEED_CAPACITY),
+        bus: Some(bus.clone()),
+    });
+
+    // One bus name, so only the default instance answers polkit checks
+    #[cfg(feature = "polkit-service")]
+    if instance.is_none() {
+        match polkit_service::serve(&bus, Arc::clone(&state)).await {
+            Ok(()) => info!("serving polkit checks as {}", polkit_service::BUS_NAME),
+            Err(e) => warn!("serve {}: {}", polkit_service::BUS_NAME, e),
+        }
+    }
+
+    tokio::select! {
+        () = serve(server, Arc::clone(&state)) => {}
+        signal = shutdown_signal() => info!("{}: shutting down", signal?),
+    }
+    // A dialog still up holds the session lock; release it before exiting
+    state.dialogs.close();
+    Ok(())
+}
+
+/// Resolves with the name of the first SIGTERM or SIGINT received
+#[cfg(not(coverage))]
+async fn shutdown_signal() -> anyhow::Result<&'static str> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    Ok(tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = interrupt.recv() => "SIGINT",
+    })
+}
 
+#[cfg(coverage)]
+fn main() {}
+
+/// Accept connections on `server` and answer each from `state`; never
+/// returns
+#[cfg(not(coverage))]
+async fn serve(server: Server, state: Arc<AppState>) {
     loop {
         match server.accept().await {
             Ok((conn, caller)) => {
                 let state = Arc::clone(&state);
+                let caller = Caller::accepted(caller);
                 tokio::spawn(handle_connection(conn, caller, state));
             }
             Err(e) => {
@@ -66,19 +311,58 @@ async fn main() -> anyhow::Result<()> {
     }
 }
 
-#[cfg(coverage)]
-fn main() {}
+#[cfg(not(coverage))]
+fn init_logging(format: LogFormat) {
+    let logger = tracing_subscriber::fmt();
+    match format {
+        LogFormat::Text => logger.init(),
+        LogFormat::Json => logger.json().init(),
+    }
+}
+
+/// Check the directory `socket_path` is bound in, as strictly as
+/// `socket_dir_check` asks
+#[cfg(not(coverage))]
+fn check_socket_dir(socket_path: &Path, check: SocketDirCheck) -> anyhow::Result<()> {
+    if check == SocketDirCheck::Off {
+        return Ok(());
+    }
+    let dir = socket_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    match runtime_file::check_socket_dir(dir, 0) {
+        Ok(()) => Ok(()),
+        Err(e) if check == SocketDirCheck::Warn => {
+            warn!("{}", e);
+            Ok(())
+        }
+        Err(e) => anyhow::bail!("{e}; refusing to start (see socket_dir_check)"),
+    }
+}
+
+/// `authd [--instance <name>]`
+fn parse_instance(args: &[String]) -> Result<Option<InstanceName>, String> {
+    match args {
+        [] => Ok(None),
+        [flag, name] if flag == "--instance" => {
+            InstanceName::new(name).map(Some).map_err(|e| e.to_string())
+        }
+        _ => Err("usage: authd [--instance <name>]".into()),
+    }
+}
 
 #[cfg(not(coverage))]
-async fn handle_connection(mut conn: Connection, caller: CallerInfo, state: Arc<AppState>) {
+async fn handle_connection(mut conn: Connection, caller: Caller, state: Arc<AppState>) {
     info!(
         "connection from uid={} pid={} exe={:?}",
         caller.uid, caller.pid, caller.exe
     );
 
-    let request: DaemonRequest = match conn.read().await {
-        Ok(r) => r,
-        Err(e) => {
+    let idle = Duration::from_secs(state.config.request_timeout_secs);
+    let request: DaemonRequest = match tokio::time::timeout(idle, conn.read()).await {
+        Ok(Ok(r)) => r,
+        Ok(Err(e)) => {
             error!("{}", e);
             let _ = conn
                 .write(&AuthResponse::Error {
@@ -87,34 +371,290 @@ async fn handle_connection(mut conn: Connection, caller: CallerInfo, state: Arc<
                 .await;
             return;
         }
+        Err(_) => {
+            warn!(
+                "client pid={} sent no request within {}s, dropping it",
+                caller.pid,
+                idle.as_secs()
+            );
+            let _ = conn
+                .write(&AuthResponse::Error {
+                    message: "timed out waiting for a request".into(),
+                })
+                .await;
+            return;
+        }
     };
 
     match request {
         DaemonRequest::Exec(request) => {
-            let response = process_request(&caller, &request, &state).await;
+            let response = tokio::select! {
+                response = process_request(&caller, &request, &state) => response,
+                () = hangup(&mut conn) => return abandoned(&caller),
+            };
+            let _ = conn.write(&response).await;
+        }
+        DaemonRequest::ExecWait(request) => {
+            // Hanging up only stops the wait; the process keeps running
+            let response = tokio::select! {
+                response = process_request_and_wait(&caller, &request, &state) => response,
+                () = hangup(&mut conn) => return abandoned(&caller),
+            };
             let _ = conn.write(&response).await;
         }
         DaemonRequest::Polkit(request) => {
-            let response = handle_polkit(&caller, &request, &state).await;
+            let response = tokio::select! {
+                response = handle_polkit(&caller, &request, &state) => response,
+                () = hangup(&mut conn) => return abandoned(&caller),
+            };
+            let _ = conn.write(&response).await;
+        }
+        DaemonRequest::Check(request) => {
+            let response = check_response(&caller, &request, &state);
+            let _ = conn.write(&response).await;
+        }
+        DaemonRequest::ListActive => {
+            let response = state.active_scopes(caller.uid);
             let _ = conn.write(&response).await;
         }
+        DaemonRequest::KillScope(request) => {
+            let response = kill_scope(&caller, &request, &state).await;
+            let _ = conn.write(&response).await;
+        }
+        DaemonRequest::Lockdown(request) => {
+            let response = set_lockdown(&caller, &request, &state);
+            let _ = conn.write(&response).await;
+        }
+        DaemonRequest::TestAuth(request) => {
+            let response = test_auth(&caller, &request, &state).await;
+            let _ = conn.write(&response).await;
+        }
+        DaemonRequest::ListCache => {
+            let response = state.cached_for(caller.uid);
+            let _ = conn.write(&response).await;
+        }
+        DaemonRequest::SubscribeAudit => stream_audit(&mut conn, &caller, &state).await,
+    }
+}
+
+/// Follow the audit feed on an admin's connection until they hang up. A
+/// subscriber that can't keep up misses events rather than holding up the
+/// requests that record them, and gets a `Lagged` message saying so.
+#[cfg(not(coverage))]
+async fn stream_audit(conn: &mut Connection, caller: &Caller, state: &AppState) {
+    let mut events = match subscribe_audit(caller, state) {
+        Ok(events) => events,
+        Err(denied) => {
+            let _ = conn.write(&denied).await;
+            return;
+        }
+    };
+    info!("uid={} subscribed to the audit feed", caller.uid);
+    loop {
+        let received = tokio::select! {
+            received = events.recv() => received,
+            () = hangup(conn) => return,
+        };
+        let Some(message) = audit_stream_message(received) else {
+            return;
+        };
+        if conn.write(&message).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// A receiver for the audit feed, if `caller` is an admin
+#[cfg_attr(coverage, allow(dead_code))]
+fn subscribe_audit(
+    caller: &Caller,
+    state: &AppState,
+) -> Result<tokio::sync::broadcast::Receiver<String>, AuditStreamMessage> {
+    if !state.config.is_admin(caller.uid) {
+        warn!("refusing audit subscription for uid={}", caller.uid);
+        return Err(AuditStreamMessage::Denied {
+            reason: "the audit feed is restricted to admins".into(),
+        });
+    }
+    Ok(state.audit_feed.subscribe())
+}
+
+/// What a subscriber is sent for one read of the feed; None once the feed
+/// has closed
+#[cfg_attr(coverage, allow(dead_code))]
+fn audit_stream_message(
+    received: Result<String, tokio::sync::broadcast::error::RecvError>,
+) -> Option<AuditStreamMessage> {
+    use tokio::sync::broadcast::error::RecvError;
+    match received {
+        Ok(json) => Some(AuditStreamMessage::Event { json }),
+        Err(RecvError::Lagged(missed)) => Some(AuditStreamMessage::Lagged { missed }),
+        Err(RecvError::Closed) => None,
+    }
+}
+
+/// Resolves once the client closes the connection. Clients send nothing
+/// after their request, so anything read here means they're gone (or
+/// misbehaving) and the answer has nobody to go to.
+#[cfg(not(coverage))]
+async fn hangup(conn: &mut Connection) {
+    let _ = conn.read::<DaemonRequest>().await;
+}
+
+/// Log a request dropped because its client hung up. Dropping it tears
+/// down any dialog it had up.
+#[cfg(not(coverage))]
+fn abandoned(caller: &Caller) {
+    info!("client pid={} hung up, abandoning its request", caller.pid);
+}
+
+/// Stop a scope authd launched, if `caller` launched it or is an admin.
+#[cfg(not(coverage))]
+async fn kill_scope(
+    caller: &Caller,
+    request: &KillScopeRequest,
+    state: &AppState,
+) -> KillScopeResponse {
+    let signal = match request.checked_signal() {
+        Ok(signal) => signal,
+        Err(reason) => {
+            warn!(
+                "refusing to signal {} for uid={}: {}",
+                request.unit, caller.uid, reason
+            );
+            return KillScopeResponse::Denied { reason };
+        }
+    };
+    let is_admin = state.config.is_admin(caller.uid);
+    let authorized = state
+        .scopes()
+        .authorize_kill(&request.unit, caller.uid, is_admin);
+
+    match authorized {
+        Err(KillRefusal::NotFound) => KillScopeResponse::NotFound,
+        Err(KillRefusal::NotOwner) => KillScopeResponse::Denied {
+            reason: "scope was launched by another user".into(),
+        },
+        Ok(()) => match signal_unit(&request.unit, signal).await {
+            Ok(()) => {
+                info!(
+                    "sent SIG{} to {} for uid={}",
+                    signal.name(),
+                    request.unit,
+                    caller.uid
+                );
+                KillScopeResponse::Stopped
+            }
+            Err(message) => {
+                error!("stop {}: {}", request.unit, message);
+                KillScopeResponse::Error { message }
+            }
+        },
+    }
+}
+
+/// `systemctl stop` the unit for `SIGTERM`, else `systemctl kill` it with
+/// `signal`. Only called with names from the registry.
+#[cfg(not(coverage))]
+async fn signal_unit(unit: &str, signal: KillSignal) -> Result<(), String> {
+    use tokio::process::Command;
+
+    let mut command = Command::new("systemctl");
+    match signal {
+        KillSignal::Term => command.args(["stop", "--", unit]),
+        _ => command.args(["kill", "--signal", signal.name(), "--", unit]),
+    };
+    let status = command
+        .uid(0)
+        .gid(0)
+        .status()
+        .await
+        .map_err(|e| format!("systemctl: {}", e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("systemctl exited with {}", status))
+    }
+}
+
+/// Turn lockdown on or off, if `caller` is an admin
+fn set_lockdown(caller: &Caller, request: &LockdownRequest, state: &AppState) -> LockdownResponse {
+    if !state.config.is_admin(caller.uid) {
+        warn!("refusing lockdown change for uid={}", caller.uid);
+        return LockdownResponse::Denied {
+            reason: "lockdown is restricted to admins".into(),
+        };
+    }
+    match state.lockdown.set(request.active) {
+        Ok(()) => {
+            let change = if request.active { "on" } else { "cleared" };
+            warn!("lockdown {} by uid={}", change, caller.uid);
+            LockdownResponse::Set {
+                active: request.active,
+            }
+        }
+        Err(e) => {
+            error!("set lockdown: {}", e);
+            LockdownResponse::Error {
+                message: format!("set lockdown: {}", e),
+            }
+        }
     }
 }
 
+/// `authsudo --test-auth`: the caller's own password against the backend
+/// approvals use. Nothing is cached or launched, and only the caller's own
+/// account can be checked.
+async fn test_auth(
+    caller: &Caller,
+    request: &TestAuthRequest,
+    state: &AppState,
+) -> TestAuthResponse {
+    let checked = approval::verify_requester(
+        caller.uid,
+        &request.password,
+        Arc::clone(&state.authenticator),
+        DEFAULT_PAM_SERVICE,
+        Duration::from_secs(state.config.pam_timeout_secs),
+    )
+    .await;
+    let response = match checked {
+        Ok(true) => TestAuthResponse::Accepted,
+        Ok(false) => TestAuthResponse::Rejected,
+        Err(message) => TestAuthResponse::Error { message },
+    };
+    info!("test-auth by uid {}: {:?}", caller.uid, response);
+    response
+}
+
 /// Handle a polkit `BeginAuthentication` forwarded by `authd-polkit-agent`:
 /// confirm with the user, then assert the response to polkitd over the system bus.
 #[cfg(not(coverage))]
-async fn handle_polkit(
-    caller: &CallerInfo,
-    request: &PolkitRequest,
-    state: &AppState,
-) -> PolkitReply {
+async fn handle_polkit(caller: &Caller, request: &PolkitRequest, state: &AppState) -> PolkitReply {
     info!(
         "polkit request: action={} uid={} agent_uid={}",
         request.action_id, request.uid, caller.uid
     );
+    // polkit actions have no target to find on `emergency_allow`
+    if state.lockdown.is_active() {
+        return PolkitReply::Error {
+            message: LOCKDOWN_REASON.into(),
+        };
+    }
 
-    match show_polkit_dialog(&request.message, &request.action_id, &request.env) {
+    let request_owned = request.clone();
+    let shown = state.prompt(caller, move |dialog, cancel| {
+        dialog.confirm_polkit(&request_owned, cancel)
+    });
+    let result = match shown.await {
+        Ok(result) => result,
+        Err(e) => {
+            return PolkitReply::Error {
+                message: e.to_string(),
+            };
+        }
+    };
+    match result {
         DialogResult::Confirmed => match assert_polkit_response(state, request).await {
             Ok(()) => {
                 info!("polkit response asserted for {}", request.action_id);
@@ -126,6 +666,10 @@ async fn handle_polkit(
             }
         },
         DialogResult::Denied => PolkitReply::Denied,
+        DialogResult::TimedOut => {
+            info!("polkit confirmation for {} timed out", request.action_id);
+            PolkitReply::Denied
+        }
         DialogResult::Error => PolkitReply::Error {
             message: "failed to show confirmation dialog".into(),
         },
@@ -139,152 +683,1003 @@ async fn assert_polkit_response(state: &AppState, request: &PolkitRequest) -> Re
     let mut attrs: HashMap<&str, Value> = HashMap::new();
     attrs.insert("uid", Value::from(request.uid));
     let identity = ("unix-user", attrs);
+    let Some(bus) = &state.bus else {
+        return Err("no system bus connection".into());
+    };
 
-    state
-        .bus
-        .call_method(
-            Some(PK_SERVICE),
-            PK_AUTHORITY_PATH,
-            Some(PK_AUTHORITY_IFACE),
-            "AuthenticationAgentResponse2",
-            &(request.uid, request.cookie.as_str(), identity),
-        )
-        .await
-        .map(|_| ())
-        .map_err(|e| e.to_string())
+    bus.call_method(
+        Some(PK_SERVICE),
+        PK_AUTHORITY_PATH,
+        Some(PK_AUTHORITY_IFACE),
+        "AuthenticationAgentResponse2",
+        &(request.uid, request.cookie.as_str(), identity),
+    )
+    .await
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+async fn process_request(caller: &Caller, request: &AuthRequest, state: &AppState) -> AuthResponse {
+    launch(caller, request, state).await.0
 }
 
+/// `process_request`, answered with the exit status once the launched
+/// process is gone. Requests that launch nothing get their usual answer.
 #[cfg(not(coverage))]
-async fn process_request(
-    caller: &CallerInfo,
+async fn process_request_and_wait(
+    caller: &Caller,
     request: &AuthRequest,
     state: &AppState,
 ) -> AuthResponse {
-    info!("auth request: target={:?}", request.target);
-    if request.confirm_only && is_trusted_confirm_consumer(caller) {
-        return confirmation_response(caller, request);
+    let (response, exit) = launch(caller, request, state).await;
+    let (AuthResponse::Success { pid, .. }, Some(exit)) = (&response, exit) else {
+        return response;
+    };
+    match exit.await {
+        Ok(Ok(status)) => exited_response(*pid, status),
+        Ok(Err(e)) => AuthResponse::Error {
+            message: format!("wait: {}", e),
+        },
+        Err(_) => AuthResponse::Error {
+            message: "lost track of the process".into(),
+        },
     }
+}
 
-    match policy_response(caller, request, state) {
-        Some(response) => return response,
-        None => {}
+/// Check, confirm and spawn. Alongside the answer comes the launched
+/// process's exit status, once it has one.
+async fn launch(
+    caller: &Caller,
+    request: &AuthRequest,
+    state: &AppState,
+) -> (AuthResponse, Option<ExitReceiver>) {
+    info!("auth request: target={:?}", request.target);
+    if let Some(reason) = state.lockdown_refusal(&request.target) {
+        warn!(
+            "lockdown: refusing {:?} for uid={}",
+            request.target, caller.uid
+        );
+        return (AuthResponse::Denied { reason }, None);
+    }
+    if let Some(message) = state.config.env_refusal(&request.env) {
+        warn!(
+            "refusing {:?} for uid={}: {}",
+            request.target, caller.uid, message
+        );
+        return (AuthResponse::Error { message }, None);
+    }
+    // Refused up front, so nobody confirms a launch that can't happen
+    if state.mode == StartupMode::EvaluationOnly && !request.confirm_only {
+        warn!(
+            "evaluation only: refusing to launch {:?} for uid={}",
+            request.target, caller.uid
+        );
+        return (
+            AuthResponse::Error {
+                message: EVALUATION_ONLY.into(),
+            },
+            None,
+        );
+    }
+    // Trusted confirm consumers check policy themselves, and send the methods
+    // and PAM service their deciding rule takes
+    let trusted = request.confirm_only && is_trusted_confirm_consumer(caller);
+    let checked = if trusted {
+        None
+    } else {
+        match state.policy_check(caller, &request.target, &request.args) {
+            Ok(checked) => Some(checked),
+            Err(message) => return (AuthResponse::Error { message }, None),
+        }
+    };
+    let pam_service = match &checked {
+        Some(checked) => checked.settings.pam_service(),
+        None => request
+            .pam_service
+            .as_ref()
+            .map_or(DEFAULT_PAM_SERVICE, PamService::as_str),
+    };
+    // Checked before anything is asked, trusted confirm consumers included
+    if let Some(response) = approval_response(caller, request, state, pam_service).await {
+        return (response, None);
     }
+    let Some(checked) = &checked else {
+        let methods = request.methods.as_ref();
+        if let Some(reason) = methods.and_then(|methods| methods.unchecked_refusal(&request.target))
+        {
+            return (AuthResponse::Denied { reason }, None);
+        }
+        // authsudo's discouraged overrides are audited like authctl's
+        let response = if request.discouraged {
+            discouraged_response(caller, request, state, methods, pam_service)
+                .await
+                .unwrap_or(AuthResponse::Success {
+                    pid: 0,
+                    cached: false,
+                })
+        } else {
+            authorization_response(caller, request, state, methods, pam_service).await
+        };
+        if matches!(response, AuthResponse::Success { .. }) {
+            notify_authorized(state, caller, request);
+        }
+        return (response, None);
+    };
+
+    let cached = match policy_response(caller, request, state, checked).await {
+        Ok(authorized) => authorized == Authorized::Cached,
+        Err(response) => return (response, None),
+    };
+    notify_authorized(state, caller, request);
 
     if request.confirm_only {
-        return AuthResponse::Success { pid: 0 };
+        return (AuthResponse::Success { pid: 0, cached }, None);
+    }
+
+    let log_output = match output::resolve(
+        checked.settings.log_output.as_deref(),
+        caller.uid,
+        caller.gid,
+    ) {
+        Ok(log) => log,
+        Err(message) => return (AuthResponse::Error { message }, None),
+    };
+    let run_as = runas::resolve(
+        checked.settings.runas.as_deref(),
+        &state.config.allow_runas,
+        caller.uid,
+        caller.gid,
+        runas::lookup_user,
+    );
+    let run_as = match run_as {
+        Ok(run_as) => run_as,
+        Err(message) => return (AuthResponse::Error { message }, None),
+    };
+    let limits = state.policy().limits(&request.target, &request.args);
+    let max_concurrent = state
+        .policy()
+        .max_concurrent(&request.target, &request.args);
+    let Some(unit) = state
+        .scopes()
+        .reserve(caller.uid, &request.target, max_concurrent)
+    else {
+        return (
+            AuthResponse::Error {
+                message: "already running".into(),
+            },
+            None,
+        );
+    };
+    let env = launch_environment(
+        &request.env,
+        state
+            .policy()
+            .restricts_scripts(&request.target, &request.args),
+    );
+    match spawn_process(request, &env, &unit, run_as, &limits, log_output.as_ref()).await {
+        Ok(child) => {
+            let pid = child.id().unwrap_or(0);
+            let exit = track_scope(state, caller, request, unit, pid, child);
+            (AuthResponse::Success { pid, cached }, Some(exit))
+        }
+        Err(e) => {
+            state.scopes().release(&unit);
+            (AuthResponse::Error { message: e }, None)
+        }
     }
+}
 
-    match spawn_process(request).await {
-        Ok(pid) => AuthResponse::Success { pid },
-        Err(e) => AuthResponse::Error { message: e },
+/// Alert the `notify_webhook`s of the rules matching an authorized run,
+/// in the background
+fn notify_authorized(state: &AppState, caller: &Caller, request: &AuthRequest) {
+    let urls = state
+        .policy()
+        .notify_webhooks(&request.target, &request.args);
+    if urls.is_empty() {
+        return;
     }
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    notify::send(
+        urls,
+        &notify::Notification::authorized(caller, &state.shown(request), time),
+    );
 }
 
-fn is_trusted_confirm_consumer(caller: &CallerInfo) -> bool {
-    caller
-        .exe
-        .file_name()
-        .and_then(|name| name.to_str())
-        .is_some_and(|name| matches!(name, "authsudo" | "config-guard"))
+/// Resolves to how a launched process ended
+type ExitReceiver = oneshot::Receiver<std::io::Result<ExitStatus>>;
+
+/// Register a launched scope and drop it from the registry once the process
+/// `systemd-run` exec'd into exits. Every launch is waited for here, so none
+/// is left a zombie in the long-lived daemon. Its exit status is logged and
+/// goes to the receiver, which callers not waiting for it just drop.
+fn track_scope(
+    state: &AppState,
+    caller: &Caller,
+    request: &AuthRequest,
+    unit: String,
+    pid: u32,
+    mut child: tokio::process::Child,
+) -> ExitReceiver {
+    let started_secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    state.scopes().insert(ActiveScope {
+        unit: unit.clone(),
+        target: request.target.clone(),
+        caller: caller.exe.clone(),
+        uid: caller.uid,
+        pid,
+        started_secs,
+    });
+
+    let scopes = Arc::clone(&state.scopes);
+    let (exited, exit) = oneshot::channel();
+    tokio::spawn(async move {
+        let status = child.wait().await;
+        match &status {
+            Ok(status) => info!("{} (pid {}) exited: {}", unit, pid, status),
+            Err(e) => warn!("wait for {} (pid {}): {}", unit, pid, e),
+        }
+        scopes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&unit);
+        let _ = exited.send(status);
+    });
+    exit
 }
 
-fn policy_response(
-    caller: &CallerInfo,
+/// The answer to an `ExecWait` whose process ended with `status`
+#[cfg_attr(coverage, allow(dead_code))]
+fn exited_response(pid: u32, status: ExitStatus) -> AuthResponse {
+    AuthResponse::Exited {
+        pid,
+        code: status.code(),
+        signal: status.signal(),
+    }
+}
+
+/// Enforce the two-person rule: None when the target doesn't need an
+/// approver or the request's approval checks out.
+async fn approval_response(
+    caller: &Caller,
     request: &AuthRequest,
     state: &AppState,
+    pam_service: &str,
 ) -> Option<AuthResponse> {
-    let decision = state
-        .policy
-        .check_with_caller(&request.target, caller.uid, Some(&caller.exe));
-
-    match decision {
-        PolicyDecision::Unknown => Some(AuthResponse::UnknownTarget),
-        PolicyDecision::Denied(reason) => Some(AuthResponse::Denied { reason }),
-        PolicyDecision::AllowImmediate => None,
-        PolicyDecision::AllowWithConfirm => confirmation_response(caller, request).into_error(),
+    let policy = state.policy();
+    let groups = policy.approver_groups(&request.target, &request.args);
+    if groups.is_empty() {
+        return None;
     }
+    let verified = approval::verify(
+        request.approval.as_ref(),
+        caller.uid,
+        &groups,
+        Arc::clone(&state.authenticator),
+        pam_service,
+        Duration::from_secs(state.config.pam_timeout_secs),
+    )
+    .await;
+    let (response, entry) = approval_outcome(caller, request, verified);
+    state.audit(&entry);
+    response
 }
 
-#[cfg(not(coverage))]
-fn confirmation_response(caller: &CallerInfo, request: &AuthRequest) -> AuthResponse {
-    let result = show_confirmation_dialog(
-        caller,
-        &request.target,
-        &request.args,
-        &request.env,
-        request.prompt_title.as_deref(),
-        request.prompt_message.as_deref(),
-        request.prompt_detail.as_deref(),
-    );
-    match result {
-        DialogResult::Confirmed => {
-            info!("user confirmed");
-            AuthResponse::Success { pid: 0 }
-        }
-        DialogResult::Denied => AuthResponse::Denied {
-            reason: "user cancelled".into(),
-        },
-        DialogResult::Error => AuthResponse::Error {
-            message: "failed to show confirmation dialog".into(),
-        },
+/// Map the approval check to a response (None = proceed) and the audit
+/// entry naming both parties: the requester by uid, the approver by name,
+/// and the requester's justification if they gave one.
+fn approval_outcome(
+    caller: &Caller,
+    request: &AuthRequest,
+    verified: Result<String, String>,
+) -> (Option<AuthResponse>, AuditEvent) {
+    let (response, outcome, mut reason, approver) = match verified {
+        Ok(approver) => (
+            None,
+            AuditOutcome::Allowed,
+            format!("approved by {}", approver),
+            Some(approver),
+        ),
+        Err(reason) => (
+            Some(AuthResponse::Denied {
+                reason: reason.clone(),
+            }),
+            AuditOutcome::Denied,
+            reason,
+            None,
+        ),
+    };
+    let justification = request
+        .approval
+        .as_ref()
+        .and_then(|approval| approval.reason.as_deref());
+    if let Some(justification) = justification {
+        // Quoted, so nothing the requester typed can pass for another entry
+        reason.push_str(&format!("; requester's reason: {:?}", justification));
     }
+    let entry = AuditEvent {
+        event: AuditEventType::Approval,
+        outcome,
+        uid: caller.uid,
+        pid: caller.pid,
+        target: request.target.clone(),
+        reason,
+        approver,
+    };
+    (response, entry)
 }
 
-#[cfg(coverage)]
-fn confirmation_response(_caller: &CallerInfo, _request: &AuthRequest) -> AuthResponse {
-    AuthResponse::Error {
-        message: "confirmation dialog unavailable in coverage build".into(),
-    }
+fn is_trusted_confirm_consumer(caller: &Caller) -> bool {
+    caller
+        .exe
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| matches!(name, "authsudo" | "config-guard"))
 }
 
-trait ConfirmationOutcome {
-    fn into_error(self) -> Option<AuthResponse>;
+/// How a request the policy lets through was authorized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Authorized {
+    /// Just now, or with nothing to ask
+    Fresh,
+    /// By an earlier confirmation still in the cache
+    Cached,
 }
 
-impl ConfirmationOutcome for AuthResponse {
-    fn into_error(self) -> Option<AuthResponse> {
-        match self {
-            AuthResponse::Success { .. } => None,
-            other => Some(other),
+/// Act on what `policy_check` found for the request
+async fn policy_response(
+    caller: &Caller,
+    request: &AuthRequest,
+    state: &AppState,
+    checked: &Checked,
+) -> Result<Authorized, AuthResponse> {
+    let (kind, methods) = (checked.kind, checked.methods.as_ref());
+    let pam_service = checked.settings.pam_service();
+
+    match &checked.decision {
+        PolicyDecision::Unknown => Err(AuthResponse::UnknownTarget),
+        PolicyDecision::Denied(reason) => Err(AuthResponse::Denied {
+            reason: reason.clone(),
+        }),
+        PolicyDecision::AllowImmediate => Ok(Authorized::Fresh),
+        PolicyDecision::AllowWithConfirm => {
+            if state
+                .cached_remaining(caller.uid, &request.target, &request.args, kind)
+                .is_some()
+            {
+                info!("using cached authorization");
+                return Ok(Authorized::Cached);
+            }
+            if let Some(error) =
+                authorization_response(caller, request, state, methods, pam_service)
+                    .await
+                    .into_error()
+            {
+                return Err(error);
+            }
+            state.remember(caller.uid, &request.target, &request.args, kind);
+            Ok(Authorized::Fresh)
+        }
+        PolicyDecision::Discouraged => {
+            match discouraged_response(caller, request, state, methods, pam_service).await {
+                Some(error) => Err(error),
+                None => Ok(Authorized::Fresh),
+            }
         }
     }
 }
 
-#[cfg(not(coverage))]
-async fn spawn_process(request: &AuthRequest) -> Result<u32, String> {
-    use tokio::process::Command;
+/// Show the soft-deny warning and audit what the user chose; None if they
+/// proceeded. The requester's password comes first where `methods` need it.
+async fn discouraged_response(
+    caller: &Caller,
+    request: &AuthRequest,
+    state: &AppState,
+    methods: Option<&MethodSet>,
+    pam_service: &str,
+) -> Option<AuthResponse> {
+    if needs_password(methods) {
+        let checked = password_response(caller, request, state, pam_service).await;
+        if let Some(error) = checked.into_error() {
+            return Some(error);
+        }
+    }
+    let (caller_owned, request_owned) = (caller.clone(), state.shown(request));
+    let result = match state
+        .prompt(caller, move |dialog, cancel| {
+            dialog.confirm_discouraged(&caller_owned, &request_owned, cancel)
+        })
+        .await
+    {
+        Ok(result) => result,
+        Err(e) => {
+            return Some(AuthResponse::Error {
+                message: e.to_string(),
+            });
+        }
+    };
+    let (error, entry) = discouraged_outcome(caller, request, result);
+    state.audit(&entry);
+    error
+}
+
+/// Map the soft-deny warning dialog to a response (None = proceed) and the
+/// audit entry recording what the user chose. Discouraged actions are never
+/// cached: every run shows the warning again.
+fn discouraged_outcome(
+    caller: &Caller,
+    request: &AuthRequest,
+    result: DialogResult,
+) -> (Option<AuthResponse>, AuditEvent) {
+    let (error, outcome, reason) = match result {
+        DialogResult::Confirmed => (None, AuditOutcome::Allowed, "user proceeded past warning"),
+        DialogResult::Denied => (
+            Some(AuthResponse::Denied {
+                reason: "user cancelled discouraged action".into(),
+            }),
+            AuditOutcome::Denied,
+            "user cancelled",
+        ),
+        DialogResult::TimedOut => (
+            Some(AuthResponse::Denied {
+                reason: "discouraged action timed out".into(),
+            }),
+            AuditOutcome::Denied,
+            "warning timed out",
+        ),
+        DialogResult::Error => (
+            Some(AuthResponse::Error {
+                message: "failed to show confirmation dialog".into(),
+            }),
+            AuditOutcome::Denied,
+            "dialog unavailable",
+        ),
+    };
+    let entry = AuditEvent {
+        event: AuditEventType::Discouraged,
+        outcome,
+        uid: caller.uid,
+        pid: caller.pid,
+        target: request.target.clone(),
+        reason: reason.into(),
+        approver: None,
+    };
+    (error, entry)
+}
+
+/// Answer an `AuthCheckRequest` from policy and cache state, without prompting.
+fn check_response(
+    caller: &Caller,
+    request: &AuthCheckRequest,
+    state: &AppState,
+) -> AuthCheckResponse {
+    if let Some(reason) = state.lockdown_refusal(&request.target) {
+        return AuthCheckResponse::Denied { reason };
+    }
+    let Checked { decision, kind, .. } =
+        match state.policy_check(caller, &request.target, &request.args) {
+            Ok(checked) => checked,
+            Err(reason) => return AuthCheckResponse::Denied { reason },
+        };
+
+    // Approvals are needed on every run, cached confirmation or not
+    let needs_approver = !state
+        .policy()
+        .approver_groups(&request.target, &request.args)
+        .is_empty();
+    match decision {
+        PolicyDecision::Unknown => AuthCheckResponse::Unknown,
+        PolicyDecision::Denied(reason) => AuthCheckResponse::Denied { reason },
+        _ if needs_approver => AuthCheckResponse::PasswordRequired,
+        PolicyDecision::AllowImmediate => AuthCheckResponse::Allowed,
+        PolicyDecision::Discouraged => AuthCheckResponse::PasswordRequired,
+        PolicyDecision::AllowWithConfirm => {
+            match state.cached_remaining(caller.uid, &request.target, &request.args, kind) {
+                Some(left) => AuthCheckResponse::Cached {
+                    remaining_secs: cache::remaining_secs(left),
+                },
+                None => AuthCheckResponse::PasswordRequired,
+            }
+        }
+    }
+}
+
+/// What `policy_check` found for a request
+struct Checked {
+    decision: PolicyDecision,
+    /// The kind of cached authorization that would satisfy the decision
+    kind: Kind,
+    /// The methods the deciding rule takes (`Explanation::methods`)
+    methods: Option<MethodSet>,
+    /// What the deciding rule sets for the run
+    settings: RunSettings,
+}
+
+impl Checked {
+    fn denied(reason: String) -> Self {
+        Self {
+            decision: PolicyDecision::Denied(reason),
+            kind: Kind::Credential,
+            methods: None,
+            settings: RunSettings::default(),
+        }
+    }
+}
+
+impl AppState {
+    /// Record `entry` in the log and publish it to audit subscribers
+    fn audit(&self, entry: &AuditEvent) {
+        entry.record(self.config.log_format);
+        self.audit_feed.publish(entry);
+    }
+
+    /// The policy as it stands. Cheap to take, and unaffected by a reload
+    /// that lands while the caller still holds it.
+    fn policy(&self) -> Arc<PolicyEngine> {
+        let policy = self.policy.read().unwrap_or_else(|e| e.into_inner());
+        Arc::clone(&policy)
+    }
+
+    /// Swap in a freshly loaded policy. Checks already under way finish
+    /// against the engine they took; later ones see only the new one.
+    #[cfg_attr(not(test), allow(dead_code))]
+    fn replace_policy(&self, policy: PolicyEngine) {
+        let mut current = self.policy.write().unwrap_or_else(|e| e.into_inner());
+        *current = Arc::new(policy);
+    }
+
+    /// `policy_check`'s decision alone
+    #[cfg(test)]
+    fn policy_decision(
+        &self,
+        caller: &Caller,
+        target: &Path,
+        args: &[String],
+    ) -> Result<PolicyDecision, String> {
+        self.policy_check(caller, target, args)
+            .map(|checked| checked.decision)
+    }
+
+    /// Check `target` run with `args` for the caller, taking group
+    /// memberships from NSS or, for `group_source = "process"`, the groups
+    /// it connected with, and the session type from the caller's process.
+    fn policy_check(
+        &self,
+        caller: &Caller,
+        target: &Path,
+        args: &[String],
+    ) -> Result<Checked, String> {
+        // One engine for the whole check, even if a reload lands meanwhile
+        let policy = self.policy();
+        if let Some(reason) = policy.script_refusal(target, args) {
+            return Ok(Checked::denied(reason));
+        }
+        let gids = match self.config.group_source {
+            GroupSource::Nss => None,
+            GroupSource::Process => Some(caller.gids.clone()?),
+        };
+        let groups = gids.as_deref().map_or(Groups::Nss, Groups::Process);
+        let callers = [PolicyCaller {
+            exe: &caller.exe,
+            cmdline_path: None,
+            secontext: caller.secontext.as_deref(),
+            unit: caller.unit.as_deref(),
+        }];
+        let session = authd_policy::session::of_process(caller.pid);
+        let explanation =
+            policy.check_explain_command(target, args, caller.uid, groups, session, &callers);
+        let unchecked = explanation
+            .methods
+            .as_ref()
+            .and_then(|methods| methods.unchecked_refusal(target));
+        if let Some(reason) = unchecked {
+            return Ok(Checked::denied(reason));
+        }
+        let checked = Checked {
+            kind: Kind::needed(explanation.methods.as_ref()),
+            decision: explanation.decision,
+            methods: explanation.methods,
+            settings: explanation.settings,
+        };
+        if matches!(
+            checked.decision,
+            PolicyDecision::Denied(_) | PolicyDecision::Unknown
+        ) || !policy.forbids_others_logged_in(target, args)
+        {
+            return Ok(checked);
+        }
+        match others_logged_in_refusal(self.sessions.as_ref(), caller.uid) {
+            Some(reason) => Ok(Checked::denied(reason)),
+            None => Ok(checked),
+        }
+    }
+
+    /// Why `target` can't run now: authd is in lockdown and it isn't on
+    /// `emergency_allow`
+    fn lockdown_refusal(&self, target: &Path) -> Option<String> {
+        let refused = self.lockdown.is_active() && !self.config.emergency_allowed(target);
+        refused.then(|| LOCKDOWN_REASON.to_string())
+    }
+
+    fn scopes(&self) -> MutexGuard<'_, ScopeRegistry> {
+        self.scopes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Running scopes `uid` may see: its own, or all of them for admins.
+    fn active_scopes(&self, uid: u32) -> Vec<ActiveScope> {
+        let is_admin = self.config.is_admin(uid);
+        self.scopes().list_for(uid, is_admin)
+    }
+
+    /// Time left on `uid`'s cached authorization for `target`. Rules with
+    /// `cache_timeout = 0` never cache, so an entry made under an earlier
+    /// policy, or restored from before a restart, doesn't count either.
+    fn cached_remaining(
+        &self,
+        uid: u32,
+        target: &Path,
+        args: &[String],
+        needed: Kind,
+    ) -> Option<Duration> {
+        if self.cache_window(target, args, needed).is_zero() {
+            return None;
+        }
+        let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache.remaining(uid, target, needed)
+    }
+
+    /// How long an authorization of `kind` for `target` run with `args` is
+    /// remembered: `confirm_cache_timeout` for a confirmation,
+    /// `cache_timeout` otherwise
+    fn cache_window(&self, target: &Path, args: &[String], kind: Kind) -> Duration {
+        let policy = self.policy();
+        Duration::from_secs(match kind {
+            Kind::Confirmation => policy.confirm_cache_timeout(target, args),
+            Kind::Credential => policy.cache_timeout(target, args),
+        })
+    }
+
+    /// `uid`'s own cached authorizations, never anyone else's. Like
+    /// `cached_remaining`, targets whose rules no longer cache are left out.
+    fn cached_for(&self, uid: u32) -> Vec<CachedAuth> {
+        let policy = self.policy();
+        // Entries don't keep the arguments, so command rules don't count
+        let no_args: &[String] = &[];
+        let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache
+            .entries_for(uid)
+            .into_iter()
+            .filter(|(target, _)| {
+                policy.cache_timeout(target, no_args) != 0
+                    || policy.confirm_cache_timeout(target, no_args) != 0
+            })
+            .map(|(target, left)| CachedAuth {
+                target,
+                remaining_secs: cache::remaining_secs(left),
+            })
+            .collect()
+    }
+
+    fn remember(&self, uid: u32, target: &Path, args: &[String], kind: Kind) {
+        let timeout = self.cache_window(target, args, kind);
+        if timeout.is_zero() {
+            return;
+        }
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache.insert(uid, target, kind, timeout);
+        let Some(path) = &self.cache_path else {
+            return;
+        };
+        if let Err(e) = cache.save(path) {
+            warn!("failed to save auth cache to {}: {}", path.display(), e);
+        }
+    }
+
+    /// `request` as dialogs and notifications show it, with `redact_args`
+    /// hidden; the launch keeps the real arguments
+    fn shown(&self, request: &AuthRequest) -> AuthRequest {
+        self.config.redact_args.request(request)
+    }
+
+    /// Show a dialog once every earlier one has been answered, or give up if
+    /// the queue is full, the wait runs too long or `caller` exits while
+    /// waiting. Dropping the returned future (the client hung up) leaves the
+    /// queue, or tears down a dialog that is already up.
+    async fn prompt(
+        &self,
+        caller: &Caller,
+        show: impl FnOnce(&dyn Confirmer, &DialogCancel) -> DialogResult + Send + 'static,
+    ) -> Result<DialogResult, QueueError> {
+        let _turn = self.dialogs.acquire(caller_exited(caller.pid)).await?;
+        let cancel = Arc::new(DialogCancel::default());
+        // Declared after `_turn`, so the next dialog waits for this teardown
+        let _teardown = CancelOnDrop(Arc::clone(&cancel));
+        self.dialogs.show(&cancel);
+
+        let confirmer = Arc::clone(&self.confirmer);
+        let shown = tokio::task::spawn_blocking(move || show(confirmer.as_ref(), &cancel));
+        Ok(shown.await.unwrap_or(DialogResult::Error))
+    }
+}
+
+/// Resolves once process `pid` is gone. A client can exit while its
+/// connection stays open in a child it forked, so its hangup is never seen;
+/// a queued request of its is withdrawn this way instead. A reused pid only
+/// keeps the request waiting.
+async fn caller_exited(pid: u32) {
+    while Path::new(&format!("/proc/{pid}")).exists() {
+        tokio::time::sleep(CALLER_POLL).await;
+    }
+}
+
+#[cfg(feature = "gui")]
+async fn confirmation_response(
+    caller: &Caller,
+    request: &AuthRequest,
+    state: &AppState,
+    _pam_service: &str,
+) -> AuthResponse {
+    let (caller_owned, request_owned) = (caller.clone(), state.shown(request));
+    let typed = state
+        .policy()
+        .requires_type_confirm(&request.target, &request.args);
+    let shown = state.prompt(caller, move |dialog, cancel| {
+        if typed {
+            dialog.confirm_typed(&caller_owned, &request_owned, cancel)
+        } else {
+            dialog.confirm(&caller_owned, &request_owned, cancel)
+        }
+    });
+    let result = match shown.await {
+        Ok(result) => result,
+        Err(e) => {
+            return AuthResponse::Error {
+                message: e.to_string(),
+            };
+        }
+    };
+    let (response, entry) = confirmation_outcome(caller, request, result);
+    if let Some(entry) = entry {
+        state.audit(&entry);
+    }
+    response
+}
+
+/// Map the confirmation dialog to a response, and to the audit entry
+/// recording a dialog that timed out unanswered
+#[cfg(feature = "gui")]
+fn confirmation_outcome(
+    caller: &Caller,
+    request: &AuthRequest,
+    result: DialogResult,
+) -> (AuthResponse, Option<AuditEvent>) {
+    let response = match result {
+        DialogResult::Confirmed => {
+            info!("user confirmed");
+            AuthResponse::Success {
+                pid: 0,
+                cached: false,
+            }
+        }
+        DialogResult::Denied => AuthResponse::Denied {
+            reason: "user cancelled".into(),
+        },
+        DialogResult::TimedOut => {
+            let entry = AuditEvent {
+                event: AuditEventType::ConfirmTimeout,
+                outcome: AuditOutcome::Denied,
+                uid: caller.uid,
+                pid: caller.pid,
+                target: request.target.clone(),
+                reason: CONFIRM_TIMED_OUT.into(),
+                approver: None,
+            };
+            let response = AuthResponse::Denied {
+                reason: CONFIRM_TIMED_OUT.into(),
+            };
+            return (response, Some(entry));
+        }
+        DialogResult::Error => AuthResponse::Error {
+            message: "failed to show confirmation dialog".into(),
+        },
+    };
+    (response, None)
+}
+
+/// Without the `gui` feature there is no dialog to confirm with
+#[cfg(not(feature = "gui"))]
+async fn confirmation_response(
+    caller: &Caller,
+    request: &AuthRequest,
+    state: &AppState,
+    pam_service: &str,
+) -> AuthResponse {
+    headless_confirmation_response(caller, request, state, pam_service).await
+}
+
+/// A confirmation without a dialog: `headless_confirm` decides whether the
+/// requester's own password (sent in `request.password`) stands in for it,
+/// or nothing does. Built with or without `gui`, so both builds test it.
+#[cfg_attr(feature = "gui", allow(dead_code))]
+async fn headless_confirmation_response(
+    caller: &Caller,
+    request: &AuthRequest,
+    state: &AppState,
+    pam_service: &str,
+) -> AuthResponse {
+    match state.config.headless_confirm {
+        HeadlessConfirm::Deny => AuthResponse::Denied {
+            reason: "confirmation needs a dialog, and authd was built without one".into(),
+        },
+        HeadlessConfirm::RequireAuth => {
+            password_response(caller, request, state, pam_service).await
+        }
+    }
+}
+
+/// Check the requester's own password, sent in `request.password`, through
+/// `pam_service`. `AuthFailed` for a missing one, so the client can ask for
+/// it and retry.
+async fn password_response(
+    caller: &Caller,
+    request: &AuthRequest,
+    state: &AppState,
+    pam_service: &str,
+) -> AuthResponse {
+    let checked = approval::verify_requester(
+        caller.uid,
+        &request.password,
+        Arc::clone(&state.authenticator),
+        pam_service,
+        Duration::from_secs(state.config.pam_timeout_secs),
+    )
+    .await;
+    match checked {
+        Ok(true) => {
+            info!("requester authenticated");
+            AuthResponse::Success {
+                pid: 0,
+                cached: false,
+            }
+        }
+        Ok(false) => AuthResponse::AuthFailed,
+        Err(reason) => AuthResponse::Denied { reason },
+    }
+}
+
+/// Ask for what a rule taking `methods` needs: the requester's password
+/// where the dialog can't satisfy it, then the dialog unless the password
+/// alone does
+async fn authorization_response(
+    caller: &Caller,
+    request: &AuthRequest,
+    state: &AppState,
+    methods: Option<&MethodSet>,
+    pam_service: &str,
+) -> AuthResponse {
+    if needs_password(methods) {
+        let response = password_response(caller, request, state, pam_service).await;
+        if !needs_dialog(methods) || !matches!(response, AuthResponse::Success { .. }) {
+            return response;
+        }
+    }
+    confirmation_response(caller, request, state, pam_service).await
+}
+
+trait ConfirmationOutcome {
+    fn into_error(self) -> Option<AuthResponse>;
+}
+
+impl ConfirmationOutcome for AuthResponse {
+    fn into_error(self) -> Option<AuthResponse> {
+        match self {
+            AuthResponse::Success { .. } => None,
+            other => Some(other),
+        }
+    }
+}
+
+/// Variables an interpreter restricted by `allow_scripts` is still passed:
+/// enough to reach the caller's display and speak its language. Anything
+/// else, `PYTHONPATH`, `PERL5LIB` and `RUBYLIB` among them, could load code
+/// the script's author never listed.
+const SCRIPT_ENV: &[&str] = &[
+    "WAYLAND_DISPLAY",
+    "DISPLAY",
+    "XAUTHORITY",
+    "XDG_RUNTIME_DIR",
+    "XDG_SESSION_TYPE",
+    "LANG",
+    "LANGUAGE",
+];
+
+/// The variables the target starts with, sorted by name: the request's own,
+/// or only `SCRIPT_ENV` and `LC_*` of them for an interpreter
+fn launch_environment(env: &HashMap<String, String>, scripts: bool) -> Vec<(&str, &str)> {
+    let mut env: Vec<(&str, &str)> = env
+        .iter()
+        .filter(|(key, _)| !scripts || key.starts_with("LC_") || SCRIPT_ENV.contains(&key.as_str()))
+        .map(|(key, val)| (key.as_str(), val.as_str()))
+        .collect();
+    env.sort();
+    env
+}
+
+#[cfg(not(coverage))]
+async fn spawn_process(
+    request: &AuthRequest,
+    env: &[(&str, &str)],
+    unit: &str,
+    run_as: RunAs,
+    limits: &ResourceLimits,
+    log_output: Option<&LogOutput>,
+) -> Result<tokio::process::Child, String> {
+    use tokio::process::Command;
 
     let mut cmd = Command::new("systemd-run");
     cmd.args(["--scope", "--quiet", "--collect"]);
+    cmd.arg(format!("--unit={}", unit));
+    cmd.args(run_as.systemd_args());
+    for property in limits.systemd_properties() {
+        cmd.arg(format!("--property={}", property));
+    }
 
     // Pass environment variables (for Wayland access)
-    for (key, val) in &request.env {
+    for (key, val) in env {
         cmd.args(["--setenv", &format!("{}={}", key, val)]);
     }
 
     cmd.arg("--");
     cmd.arg(&request.target);
     cmd.args(&request.args);
+    // No-op when authd is root; under privilege separation the child uses the
+    // retained CAP_SETUID/CAP_SETGID to become root just for this exec.
+    cmd.uid(0).gid(0);
+
+    if let Some(log) = log_output {
+        output::redirect(&mut cmd, log)?;
+    }
 
-    let child = cmd.spawn().map_err(|e| format!("spawn: {}", e))?;
-    let pid = child.id().unwrap_or(0);
+    // The caller gets its answer right away; the child is awaited in the
+    // background only to keep the scope registry current
+    cmd.spawn().map_err(|e| format!("spawn: {}", e))
+}
 
-    // Don't wait for the process to complete
-    Ok(pid)
+#[cfg(coverage)]
+async fn spawn_process(
+    _request: &AuthRequest,
+    _env: &[(&str, &str)],
+    _unit: &str,
+    _run_as: RunAs,
+    _limits: &ResourceLimits,
+    _log_output: Option<&LogOutput>,
+) -> Result<tokio::process::Child, String> {
+    Err("process spawning unavailable in coverage build".into())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use approval::ScriptedAuthenticator;
     use authd_protocol::{AuthRequirement, PolicyRule};
-    use std::path::PathBuf;
+    use dialog::ScriptedConfirmer;
+    use peercred_ipc::CallerInfo;
 
-    fn caller(exe: &str, uid: u32) -> CallerInfo {
-        CallerInfo {
-            uid,
-            gid: uid,
-            pid: 123,
-            exe: PathBuf::from(exe),
+    fn caller(exe: &str, uid: u32) -> Caller {
+        Caller {
+            info: CallerInfo {
+                uid,
+                gid: uid,
+                pid: 123,
+                exe: PathBuf::from(exe),
+            },
+            gids: Ok(vec![uid]),
+            secontext: None,
+            unit: None,
         }
     }
 
@@ -298,21 +1693,199 @@ mod tests {
             prompt_title: None,
             prompt_message: None,
             prompt_detail: None,
+            approval: None,
+            discouraged: false,
+            methods: None,
+            pam_service: None,
         }
     }
 
-    #[cfg(coverage)]
+    #[test]
+    fn exited_response_carries_code_or_signal() {
+        // Wait statuses: exit code in the high byte, signal in the low bits
+        assert!(matches!(
+            exited_response(42, ExitStatus::from_raw(3 << 8)),
+            AuthResponse::Exited {
+                pid: 42,
+                code: Some(3),
+                signal: None
+            }
+        ));
+        assert!(matches!(
+            exited_response(42, ExitStatus::from_raw(libc::SIGKILL)),
+            AuthResponse::Exited {
+                pid: 42,
+                code: None,
+                signal: Some(libc::SIGKILL)
+            }
+        ));
+    }
+
+    /// Turns every approver away
+    fn no_approvers() -> ScriptedAuthenticator {
+        ScriptedAuthenticator {
+            user: String::new(),
+            password: String::new(),
+        }
+    }
+
+    /// `policy_response` after the check `launch` makes first
+    async fn checked_response(
+        caller: &Caller,
+        request: &AuthRequest,
+        state: &AppState,
+    ) -> Result<Authorized, AuthResponse> {
+        let checked = state
+            .policy_check(caller, &request.target, &request.args)
+            .map_err(|message| AuthResponse::Error { message })?;
+        policy_response(caller, request, state, &checked).await
+    }
+
     fn state_with_rule(auth: AuthRequirement) -> AppState {
+        state_with_confirmer(auth, Arc::new(ScriptedConfirmer::new(DialogResult::Error)))
+    }
+
+    fn state_with_policy(policy: PolicyEngine) -> AppState {
+        AppState {
+            policy: RwLock::new(Arc::new(policy)),
+            config: Config::default(),
+            cache: Mutex::new(AuthCache::new()),
+            cache_path: None,
+            scopes: Arc::new(Mutex::new(ScopeRegistry::new())),
+            confirmer: Arc::new(ScriptedConfirmer::new(DialogResult::Error)),
+            dialogs: DialogQueue::default(),
+            authenticator: Arc::new(no_approvers()),
+            lockdown: Lockdown::new(PathBuf::from("/nonexistent/authd.lockdown")),
+            sessions: Arc::new(ScriptedSessions(Vec::new())),
+            mode: StartupMode::Full,
+            audit_feed: AuditFeed::new(audit::FEED_CAPACITY),
+            #[cfg(not(coverage))]
+            bus: None,
+        }
+    }
+
+    fn state_with_confirmer(auth: AuthRequirement, confirmer: Arc<ScriptedConfirmer>) -> AppState {
         let mut policy = PolicyEngine::new();
         policy.add_rule(PolicyRule {
             target: PathBuf::from("/usr/bin/id"),
             allow_users: Vec::new(),
             allow_groups: Vec::new(),
             allow_callers: vec![PathBuf::from("/usr/bin/authsudo")],
+            allow_units: Vec::new(),
             auth,
             cache_timeout: 300,
+            log_output: None,
+            enabled: true,
+            min_binary_age_secs: None,
+            require_approver_group: None,
+            allow_session_types: Vec::new(),
+            allow_contexts: Vec::new(),
+            notify_webhook: None,
+            allow_scripts: Vec::new(),
+            allow_argv0: Vec::new(),
+            forbid_setuid_target: false,
+            forbid_file_caps: false,
+            priority: 0,
+            limits: ResourceLimits::default(),
+            max_concurrent: None,
+            min_caller_age_secs: None,
+            runas: None,
+            require_type_confirm: false,
+            forbid_if_others_logged_in: false,
+            pam_service: None,
+            command: None,
+            confirm_cache_timeout: None,
+            clean_env: None,
         });
-        AppState { policy }
+        AppState {
+            policy: RwLock::new(Arc::new(policy)),
+            config: Config::default(),
+            cache: Mutex::new(AuthCache::new()),
+            cache_path: None,
+            scopes: Arc::new(Mutex::new(ScopeRegistry::new())),
+            confirmer,
+            dialogs: DialogQueue::default(),
+            authenticator: Arc::new(no_approvers()),
+            lockdown: Lockdown::new(PathBuf::from("/nonexistent/authd.lockdown")),
+            sessions: Arc::new(ScriptedSessions(Vec::new())),
+            mode: StartupMode::Full,
+            audit_feed: AuditFeed::new(audit::FEED_CAPACITY),
+            #[cfg(not(coverage))]
+            bus: None,
+        }
+    }
+
+    #[test]
+    fn instance_is_the_only_daemon_argument() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(parse_instance(&args(&[])), Ok(None));
+        assert_eq!(
+            parse_instance(&args(&["--instance", "test"])),
+            Ok(Some(InstanceName::new("test").unwrap()))
+        );
+        assert!(parse_instance(&args(&["--instance", "../x"])).is_err());
+        assert!(parse_instance(&args(&["--instance"])).is_err());
+        assert!(parse_instance(&args(&["--verbose"])).is_err());
+    }
+
+    #[test]
+    fn instances_with_different_policies_answer_independently() {
+        let root = std::env::temp_dir().join(format!("authd-instances-{}", std::process::id()));
+        let instance_policy = |name: &str, auth: &str| {
+            let dir = root.join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(
+                dir.join("rules.toml"),
+                format!(
+                    "[[rules]]\ntarget = \"/usr/bin/id\"\nallow_callers = [\"/usr/bin/authsudo\"]\nauth = \"{auth}\"\n"
+                ),
+            )
+            .unwrap();
+            let mut policy = PolicyEngine::new();
+            policy.load_from_dir(&dir).unwrap();
+            state_with_policy(policy)
+        };
+        let prod = instance_policy("prod", "confirm");
+        let test = instance_policy("test", "none");
+        let authsudo = caller("/usr/bin/authsudo", 1000);
+        let check = AuthCheckRequest {
+            target: PathBuf::from("/usr/bin/id"),
+            args: Vec::new(),
+        };
+
+        assert!(matches!(
+            check_response(&authsudo, &check, &prod),
+            AuthCheckResponse::PasswordRequired
+        ));
+        assert!(matches!(
+            check_response(&authsudo, &check, &test),
+            AuthCheckResponse::Allowed
+        ));
+
+        // A confirmation in one instance is not remembered by the other
+        prod.remember(1000, Path::new("/usr/bin/id"), &[], Kind::Confirmation);
+        assert!(
+            test.cached_remaining(1000, Path::new("/usr/bin/id"), &[], Kind::Confirmation)
+                .is_none()
+        );
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn queued_requests_notice_their_client_exiting() {
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        let wait = Duration::from_secs(2);
+
+        assert!(tokio::time::timeout(wait, caller_exited(pid)).await.is_ok());
+        let alive = caller_exited(std::process::id());
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), alive)
+                .await
+                .is_err()
+        );
     }
 
     #[test]
@@ -328,52 +1901,1367 @@ mod tests {
         assert!(!is_trusted_confirm_consumer(&caller("/usr/bin/curl", 1000)));
     }
 
-    #[cfg(coverage)]
-    #[test]
-    fn policy_response_maps_terminal_decisions() {
-        let unknown = AppState {
-            policy: PolicyEngine::new(),
-        };
+    #[tokio::test]
+    async fn policy_response_maps_terminal_decisions() {
+        let unknown = state_with_policy(PolicyEngine::new());
         assert!(matches!(
-            policy_response(
+            checked_response(
                 &caller("/usr/bin/authsudo", 1000),
                 &request("/usr/bin/none"),
                 &unknown
-            ),
-            Some(AuthResponse::UnknownTarget)
+            )
+            .await,
+            Err(AuthResponse::UnknownTarget)
         ));
 
         let deny = state_with_rule(AuthRequirement::Deny);
         assert!(matches!(
-            policy_response(
+            checked_response(
                 &caller("/usr/bin/authsudo", 1000),
                 &request("/usr/bin/id"),
                 &deny
-            ),
-            Some(AuthResponse::Denied { .. })
+            )
+            .await,
+            Err(AuthResponse::Denied { .. })
         ));
 
         let allow = state_with_rule(AuthRequirement::None);
-        assert!(
-            policy_response(
+        assert_eq!(
+            checked_response(
                 &caller("/usr/bin/authsudo", 1000),
                 &request("/usr/bin/id"),
                 &allow
             )
-            .is_none()
+            .await
+            .unwrap(),
+            Authorized::Fresh
         );
     }
 
-    #[test]
-    fn success_confirmation_outcome_means_no_error() {
-        assert!(AuthResponse::Success { pid: 42 }.into_error().is_none());
+    #[cfg(feature = "gui")]
+    #[tokio::test]
+    async fn denied_confirmation_denies_the_request() {
+        let confirmer = Arc::new(ScriptedConfirmer::new(DialogResult::Denied));
+        let state = state_with_confirmer(AuthRequirement::Confirm, confirmer.clone());
+
+        let response = process_request(
+            &caller("/usr/bin/authsudo", 1000),
+            &request("/usr/bin/id"),
+            &state,
+        )
+        .await;
+
+        assert!(matches!(response, AuthResponse::Denied { .. }));
+        assert_eq!(confirmer.shown(), 1);
+        assert!(
+            state
+                .cached_remaining(1000, Path::new("/usr/bin/id"), &[], Kind::Confirmation)
+                .is_none()
+        );
+    }
+
+    #[cfg(feature = "gui")]
+    #[tokio::test]
+    async fn dialogs_show_secret_arguments_redacted() {
+        let confirmer = Arc::new(ScriptedConfirmer::new(DialogResult::Denied));
+        let state = state_with_confirmer(AuthRequirement::Confirm, confirmer.clone());
+        let request = AuthRequest {
+            args: vec!["--token".into(), "abc123".into()],
+            ..request("/usr/bin/id")
+        };
+
+        let _ = process_request(&caller("/usr/bin/authsudo", 1000), &request, &state).await;
+        assert_eq!(confirmer.shown_args(), ["--token", "<redacted>"]);
+        // What would be launched is untouched
+        assert_eq!(request.args, ["--token", "abc123"]);
+    }
+
+    #[cfg(all(coverage, feature = "gui"))]
+    #[tokio::test]
+    async fn confirmed_request_proceeds_to_spawn_and_is_cached() {
+        let confirmer = Arc::new(ScriptedConfirmer::new(DialogResult::Confirmed));
+        let state = state_with_confirmer(AuthRequirement::Confirm, confirmer.clone());
+        let authsudo = caller("/usr/bin/authsudo", 1000);
+
+        // The coverage spawn stub fails, which shows the request got past policy
+        let response = process_request(&authsudo, &request("/usr/bin/id"), &state).await;
         assert!(matches!(
-            AuthResponse::Denied {
-                reason: "no".into()
+            response,
+            AuthResponse::Error { message } if message.contains("spawning")
+        ));
+        assert_eq!(confirmer.shown(), 1);
+
+        // Within cache_timeout the dialog isn't shown again
+        let _ = process_request(&authsudo, &request("/usr/bin/id"), &state).await;
+        assert_eq!(confirmer.shown(), 1);
+    }
+
+    #[cfg(feature = "gui")]
+    #[tokio::test]
+    async fn success_says_whether_the_cache_authorized_it() {
+        let mut policy = PolicyEngine::new();
+        policy
+            .load_from_str(
+                r#"
+                [[rules]]
+                target = "/usr/bin/id"
+                allow_users = ["root"]
+                auth = "confirm"
+                "#,
+            )
+            .unwrap();
+        let confirmer = Arc::new(ScriptedConfirmer::new(DialogResult::Confirmed));
+        let state = AppState {
+            confirmer: confirmer.clone(),
+            ..state_with_policy(policy)
+        };
+        // Not a trusted confirm consumer, so the request goes through policy
+        let helper = caller("/usr/libexec/helper", 0);
+        let mut confirm_only = request("/usr/bin/id");
+        confirm_only.confirm_only = true;
+
+        let fresh = process_request(&helper, &confirm_only, &state).await;
+        assert!(matches!(
+            fresh,
+            AuthResponse::Success {
+                pid: 0,
+                cached: false
             }
-            .into_error(),
-            Some(AuthResponse::Denied { .. })
         ));
+        let cached = process_request(&helper, &confirm_only, &state).await;
+        assert!(matches!(
+            cached,
+            AuthResponse::Success {
+                pid: 0,
+                cached: true
+            }
+        ));
+        assert_eq!(confirmer.shown(), 1);
+    }
+
+    #[cfg(coverage)]
+    #[tokio::test]
+    async fn max_concurrent_refuses_launches_beyond_the_limit() {
+        let mut policy = PolicyEngine::new();
+        policy
+            .load_from_str(
+                r#"
+                [[rules]]
+                target = "/usr/bin/id"
+                allow_callers = ["/usr/bin/authsudo"]
+                auth = "none"
+                max_concurrent = 1
+                "#,
+            )
+            .unwrap();
+        let state = state_with_policy(policy);
+        let authsudo = caller("/usr/bin/authsudo", 1000);
+        let id = Path::new("/usr/bin/id");
+
+        // One launch of it still running
+        let unit = state.scopes().reserve(1000, id, None).unwrap();
+        state.scopes().insert(ActiveScope {
+            unit: unit.clone(),
+            target: id.to_path_buf(),
+            caller: PathBuf::from("/usr/bin/authsudo"),
+            uid: 1000,
+            pid: 4242,
+            started_secs: 0,
+        });
+        let response = process_request(&authsudo, &request("/usr/bin/id"), &state).await;
+        assert!(matches!(
+            response,
+            AuthResponse::Error { message } if message == "already running"
+        ));
+
+        // Once it has exited, the next one gets as far as spawning, and
+        // its failed spawn gives the slot back
+        state.scopes().remove(&unit);
+        for _ in 0..2 {
+            let response = process_request(&authsudo, &request("/usr/bin/id"), &state).await;
+            assert!(matches!(
+                response,
+                AuthResponse::Error { message } if message.contains("spawning")
+            ));
+        }
+    }
+
+    #[cfg(all(coverage, feature = "gui"))]
+    #[tokio::test]
+    async fn zero_cache_timeout_asks_every_time() {
+        let confirmer = Arc::new(ScriptedConfirmer::new(DialogResult::Confirmed));
+        let state = state_with_confirmer(AuthRequirement::Confirm, confirmer.clone());
+        let authsudo = caller("/usr/bin/authsudo", 1000);
+        // Confirmed while the rule still cached for five minutes
+        state.remember(1000, Path::new("/usr/bin/id"), &[], Kind::Confirmation);
+
+        let mut policy = PolicyEngine::new();
+        policy
+            .load_from_str(
+                r#"
+                [[rules]]
+                target = "/usr/bin/id"
+                allow_callers = ["/usr/bin/authsudo"]
+                auth = "confirm"
+                cache_timeout = 0
+                "#,
+            )
+            .unwrap();
+        state.replace_policy(policy);
+        assert!(
+            state
+                .cached_remaining(1000, Path::new("/usr/bin/id"), &[], Kind::Confirmation)
+                .is_none()
+        );
+
+        let _ = process_request(&authsudo, &request("/usr/bin/id"), &state).await;
+        let _ = process_request(&authsudo, &request("/usr/bin/id"), &state).await;
+        assert_eq!(confirmer.shown(), 2);
+    }
+
+    #[cfg(feature = "gui")]
+    #[tokio::test]
+    async fn confirm_only_requests_stop_after_confirmation() {
+        let confirmer = Arc::new(ScriptedConfirmer::new(DialogResult::Confirmed));
+        let state = state_with_confirmer(AuthRequirement::Confirm, confirmer);
+        let mut confirm_only = request("/usr/bin/id");
+        confirm_only.confirm_only = true;
+
+        let response =
+            process_request(&caller("/usr/bin/authsudo", 1000), &confirm_only, &state).await;
+
+        assert!(matches!(response, AuthResponse::Success { pid: 0, .. }));
+    }
+
+    #[tokio::test]
+    async fn authsudo_discouraged_overrides_are_audited() {
+        let confirmer = Arc::new(ScriptedConfirmer::new(DialogResult::Confirmed));
+        let state = state_with_confirmer(AuthRequirement::Discourage, confirmer.clone());
+        let mut events = state.audit_feed.subscribe();
+        let mut discouraged = request("/usr/bin/id");
+        discouraged.confirm_only = true;
+        discouraged.discouraged = true;
+
+        let response =
+            process_request(&caller("/usr/bin/authsudo", 1000), &discouraged, &state).await;
+
+        assert!(matches!(response, AuthResponse::Success { pid: 0, .. }));
+        assert_eq!(confirmer.shown(), 1);
+        let event = events.try_recv().unwrap();
+        assert!(event.contains(r#""event":"discouraged""#), "{event}");
+        assert!(event.contains(r#""outcome":"allowed""#), "{event}");
+    }
+
+    #[cfg(feature = "gui")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn concurrent_confirmations_are_shown_one_at_a_time() {
+        let confirmer = Arc::new(ScriptedConfirmer::with_delay(
+            DialogResult::Confirmed,
+            Duration::from_millis(50),
+        ));
+        let state = Arc::new(state_with_confirmer(
+            AuthRequirement::Confirm,
+            confirmer.clone(),
+        ));
+        let mut confirm_only = request("/usr/bin/id");
+        confirm_only.confirm_only = true;
+
+        let prompts: Vec<_> = (0..2)
+            .map(|_| {
+                let (state, request) = (Arc::clone(&state), confirm_only.clone());
+                tokio::spawn(async move {
+                    // Alive, so the one queued behind isn't withdrawn
+                    let mut authsudo = caller("/usr/bin/authsudo", 1000);
+                    authsudo.info.pid = std::process::id();
+                    process_request(&authsudo, &request, &state).await
+                })
+            })
+            .collect();
+        for prompt in prompts {
+            let response = prompt.await.unwrap();
+            assert!(matches!(response, AuthResponse::Success { pid: 0, .. }));
+        }
+
+        assert_eq!(confirmer.shown(), 2);
+        assert_eq!(confirmer.max_open(), 1);
+    }
+
+    #[cfg(feature = "gui")]
+    #[tokio::test]
+    async fn two_person_rule_needs_a_different_authenticated_approver() {
+        let confirmer = Arc::new(ScriptedConfirmer::new(DialogResult::Confirmed));
+        let mut state = state_with_confirmer(AuthRequirement::Confirm, confirmer.clone());
+        // In place of the rule without an approver group
+        let mut policy = PolicyEngine::new();
+        policy.add_rule(PolicyRule {
+            target: PathBuf::from("/usr/bin/id"),
+            allow_users: Vec::new(),
+            allow_groups: Vec::new(),
+            allow_callers: vec![PathBuf::from("/usr/bin/authsudo")],
+            allow_units: Vec::new(),
+            auth: AuthRequirement::Confirm,
+            cache_timeout: 300,
+            log_output: None,
+            enabled: true,
+            min_binary_age_secs: None,
+            require_approver_group: Some("root".into()),
+            allow_session_types: Vec::new(),
+            allow_contexts: Vec::new(),
+            notify_webhook: None,
+            allow_scripts: Vec::new(),
+            allow_argv0: Vec::new(),
+            forbid_setuid_target: false,
+            forbid_file_caps: false,
+            priority: 0,
+            limits: ResourceLimits::default(),
+            max_concurrent: None,
+            min_caller_age_secs: None,
+            runas: None,
+            require_type_confirm: false,
+            forbid_if_others_logged_in: false,
+            pam_service: None,
+            command: None,
+            confirm_cache_timeout: None,
+            clean_env: None,
+        });
+        state.replace_policy(policy);
+        state.authenticator = Arc::new(ScriptedAuthenticator {
+            user: "root".into(),
+            password: "hunter2".into(),
+        });
+        let mut confirm_only = request("/usr/bin/id");
+        confirm_only.confirm_only = true;
+        let approved_by_root = |mut request: AuthRequest| {
+            request.approval = Some(authd_protocol::Approval {
+                user: "root".into(),
+                password: "hunter2".into(),
+                reason: None,
+            });
+            request
+        };
+
+        // No approval: refused before any dialog
+        let response =
+            process_request(&caller("/usr/bin/authsudo", 1000), &confirm_only, &state).await;
+        assert!(matches!(response, AuthResponse::Denied { .. }));
+        assert_eq!(confirmer.shown(), 0);
+
+        // The requester approving themselves
+        let response = process_request(
+            &caller("/usr/bin/authsudo", 0),
+            &approved_by_root(confirm_only.clone()),
+            &state,
+        )
+        .await;
+        assert!(matches!(
+            response,
+            AuthResponse::Denied { reason } if reason.contains("someone other than")
+        ));
+        assert_eq!(confirmer.shown(), 0);
+
+        // Someone else, in the group, with the right password
+        let response = process_request(
+            &caller("/usr/bin/authsudo", 1000),
+            &approved_by_root(confirm_only.clone()),
+            &state,
+        )
+        .await;
+        assert!(matches!(response, AuthResponse::Success { pid: 0, .. }));
+        assert_eq!(confirmer.shown(), 1);
+    }
+
+    #[cfg(feature = "gui")]
+    #[tokio::test]
+    async fn client_hanging_up_mid_dialog_tears_the_dialog_down() {
+        let confirmer = Arc::new(ScriptedConfirmer::with_delay(
+            DialogResult::Confirmed,
+            Duration::from_secs(30),
+        ));
+        let state = state_with_confirmer(AuthRequirement::Confirm, confirmer.clone());
+        let authsudo = caller("/usr/bin/authsudo", 1000);
+        let mut confirm_only = request("/usr/bin/id");
+        confirm_only.confirm_only = true;
+
+        // The request future is dropped when the hang-up wins the race
+        let hung_up = tokio::time::sleep(Duration::from_millis(50));
+        tokio::select! {
+            _ = process_request(&authsudo, &confirm_only, &state) => {
+                panic!("dialog answered before the hang-up")
+            }
+            () = hung_up => {}
+        }
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while confirmer.torn_down() == 0 {
+            assert!(tokio::time::Instant::now() < deadline, "dialog left up");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(confirmer.shown(), 1);
+        // Nothing was confirmed, so nothing may be cached
+        assert!(
+            state
+                .cached_remaining(1000, Path::new("/usr/bin/id"), &[], Kind::Confirmation)
+                .is_none()
+        );
+        // And the queue slot went with it
+        assert!(state.dialogs.acquire(std::future::pending()).await.is_ok());
+    }
+
+    #[cfg(feature = "gui")]
+    #[tokio::test]
+    async fn confirmations_beyond_the_queue_cap_are_rejected() {
+        let confirmer = Arc::new(ScriptedConfirmer::new(DialogResult::Confirmed));
+        let mut state = state_with_confirmer(AuthRequirement::Confirm, confirmer.clone());
+        state.dialogs = DialogQueue::new(0, Duration::from_secs(1));
+        let shown = state.dialogs.acquire(std::future::pending()).await.unwrap();
+
+        let response = process_request(
+            &caller("/usr/bin/authsudo", 1000),
+            &request("/usr/bin/id"),
+            &state,
+        )
+        .await;
+
+        assert!(matches!(
+            response,
+            AuthResponse::Error { message } if message == "too many pending confirmations"
+        ));
+        assert_eq!(confirmer.shown(), 0);
+        drop(shown);
+    }
+
+    #[tokio::test]
+    async fn headless_confirmations_take_the_requesters_password_if_allowed() {
+        let mut state = state_with_rule(AuthRequirement::Confirm);
+        let root = caller("/usr/bin/authsudo", 0);
+        let confirm_only = |password: &str| AuthRequest {
+            password: password.into(),
+            confirm_only: true,
+            ..request("/usr/bin/id")
+        };
+
+        // The default refuses outright
+        let response = headless_confirmation_response(
+            &root,
+            &confirm_only("hunter2"),
+            &state,
+            DEFAULT_PAM_SERVICE,
+        )
+        .await;
+        assert!(matches!(
+            response,
+            AuthResponse::Denied { reason } if reason.contains("built without")
+        ));
+
+        state.config.headless_confirm = HeadlessConfirm::RequireAuth;
+        state.authenticator = Arc::new(ScriptedAuthenticator {
+            user: "root".into(),
+            password: "hunter2".into(),
+        });
+        let response = headless_confirmation_response(
+            &root,
+            &confirm_only("wrong"),
+            &state,
+            DEFAULT_PAM_SERVICE,
+        )
+        .await;
+        assert!(matches!(response, AuthResponse::AuthFailed));
+        let response = headless_confirmation_response(
+            &root,
+            &confirm_only("hunter2"),
+            &state,
+            DEFAULT_PAM_SERVICE,
+        )
+        .await;
+        assert!(matches!(response, AuthResponse::Success { pid: 0, .. }));
+    }
+
+    #[cfg(all(coverage, not(feature = "gui")))]
+    #[tokio::test]
+    async fn confirm_rules_fall_back_to_headless_confirm() {
+        let mut state = state_with_rule(AuthRequirement::Confirm);
+        let root = caller("/usr/bin/authsudo", 0);
+        let with_password = |password: &str| AuthRequest {
+            password: password.into(),
+            ..request("/usr/bin/id")
+        };
+
+        // The default refuses outright
+        let response = process_request(&root, &with_password("hunter2"), &state).await;
+        assert!(matches!(
+            response,
+            AuthResponse::Denied { reason } if reason.contains("built without")
+        ));
+
+        state.config.headless_confirm = HeadlessConfirm::RequireAuth;
+        state.authenticator = Arc::new(ScriptedAuthenticator {
+            user: "root".into(),
+            password: "hunter2".into(),
+        });
+        let response = process_request(&root, &with_password("wrong"), &state).await;
+        assert!(matches!(response, AuthResponse::AuthFailed));
+        // The coverage spawn stub fails, which shows the request got past policy
+        let response = process_request(&root, &with_password("hunter2"), &state).await;
+        assert!(matches!(
+            response,
+            AuthResponse::Error { message } if message.contains("spawning")
+        ));
+        // Cached like a confirmation would be
+        assert!(
+            state
+                .cached_remaining(0, Path::new("/usr/bin/id"), &[], Kind::Confirmation)
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn check_response_reports_remaining_cache_time() {
+        let authsudo = caller("/usr/bin/authsudo", 1000);
+        let check = AuthCheckRequest {
+            target: PathBuf::from("/usr/bin/id"),
+            args: Vec::new(),
+        };
+
+        let confirm = state_with_rule(AuthRequirement::Confirm);
+        assert!(matches!(
+            check_response(&authsudo, &check, &confirm),
+            AuthCheckResponse::PasswordRequired
+        ));
+
+        confirm.remember(1000, Path::new("/usr/bin/id"), &[], Kind::Confirmation);
+        assert!(matches!(
+            check_response(&authsudo, &check, &confirm),
+            AuthCheckResponse::Cached { remaining_secs } if remaining_secs > 0 && remaining_secs <= 300
+        ));
+        // The cache is per-uid
+        assert!(matches!(
+            check_response(&caller("/usr/bin/authsudo", 1001), &check, &confirm),
+            AuthCheckResponse::PasswordRequired
+        ));
+
+        let allow = state_with_rule(AuthRequirement::None);
+        assert!(matches!(
+            check_response(&authsudo, &check, &allow),
+            AuthCheckResponse::Allowed
+        ));
+    }
+
+    #[test]
+    fn discouraged_confirm_proceeds_and_is_audited() {
+        let (error, entry) = discouraged_outcome(
+            &caller("/usr/bin/authctl", 1000),
+            &request("/usr/bin/rm"),
+            DialogResult::Confirmed,
+        );
+
+        assert!(error.is_none());
+        assert_eq!(entry.outcome, AuditOutcome::Allowed);
+        assert_eq!(entry.event, AuditEventType::Discouraged);
+        assert_eq!(entry.uid, 1000);
+        assert_eq!(entry.target, PathBuf::from("/usr/bin/rm"));
+    }
+
+    #[test]
+    fn discouraged_cancel_denies_and_is_audited() {
+        let (error, entry) = discouraged_outcome(
+            &caller("/usr/bin/authctl", 1000),
+            &request("/usr/bin/rm"),
+            DialogResult::Denied,
+        );
+
+        assert!(matches!(error, Some(AuthResponse::Denied { .. })));
+        assert_eq!(entry.outcome, AuditOutcome::Denied);
+        assert_eq!(entry.event, AuditEventType::Discouraged);
+
+        let (error, entry) = discouraged_outcome(
+            &caller("/usr/bin/authctl", 1000),
+            &request("/usr/bin/rm"),
+            DialogResult::Error,
+        );
+        assert!(matches!(error, Some(AuthResponse::Error { .. })));
+        assert_eq!(entry.outcome, AuditOutcome::Denied);
+    }
+
+    #[test]
+    fn discouraged_timeout_is_told_apart_from_a_cancel() {
+        let (error, entry) = discouraged_outcome(
+            &caller("/usr/bin/authctl", 1000),
+            &request("/usr/bin/rm"),
+            DialogResult::TimedOut,
+        );
+
+        assert!(matches!(
+            error,
+            Some(AuthResponse::Denied { reason }) if reason == "discouraged action timed out"
+        ));
+        assert_eq!(entry.outcome, AuditOutcome::Denied);
+        assert_eq!(entry.reason, "warning timed out");
+    }
+
+    #[test]
+    fn approvals_are_audited_with_both_parties_and_the_reason() {
+        let mut approved = request("/usr/bin/poweroff");
+        approved.approval = Some(authd_protocol::Approval {
+            user: "bob".into(),
+            password: "hunter2".into(),
+            reason: Some("kernel update\ndenied: forged".into()),
+        });
+        let (response, entry) = approval_outcome(
+            &caller("/usr/bin/authsudo", 1000),
+            &approved,
+            Ok("bob".into()),
+        );
+
+        assert!(response.is_none());
+        assert_eq!(entry.event, AuditEventType::Approval);
+        assert_eq!(entry.outcome, AuditOutcome::Allowed);
+        assert_eq!(entry.uid, 1000);
+        assert_eq!(entry.approver.as_deref(), Some("bob"));
+        assert_eq!(
+            entry.reason,
+            r#"approved by bob; requester's reason: "kernel update\ndenied: forged""#
+        );
+
+        let (response, entry) = approval_outcome(
+            &caller("/usr/bin/authsudo", 1000),
+            &request("/usr/bin/poweroff"),
+            Err("requires approval by another member of wheel".into()),
+        );
+        assert!(matches!(response, Some(AuthResponse::Denied { .. })));
+        assert_eq!(entry.outcome, AuditOutcome::Denied);
+        assert_eq!(entry.approver, None);
+        assert_eq!(entry.reason, "requires approval by another member of wheel");
+    }
+
+    #[cfg(feature = "gui")]
+    #[test]
+    fn confirmation_timeout_is_denied_and_audited_as_such() {
+        let authctl = caller("/usr/bin/authctl", 1000);
+        let (response, entry) =
+            confirmation_outcome(&authctl, &request("/usr/bin/id"), DialogResult::TimedOut);
+
+        assert!(matches!(
+            response,
+            AuthResponse::Denied { reason } if reason == "confirmation timed out"
+        ));
+        let entry = entry.unwrap();
+        assert_eq!(entry.event, AuditEventType::ConfirmTimeout);
+        assert_eq!(entry.outcome, AuditOutcome::Denied);
+        assert_eq!(entry.uid, 1000);
+
+        // A cancel is no timeout, and isn't audited
+        let (response, entry) =
+            confirmation_outcome(&authctl, &request("/usr/bin/id"), DialogResult::Denied);
+        assert!(matches!(
+            response,
+            AuthResponse::Denied { reason } if reason == "user cancelled"
+        ));
+        assert!(entry.is_none());
+    }
+
+    #[test]
+    fn success_confirmation_outcome_means_no_error() {
+        assert!(
+            AuthResponse::Success {
+                pid: 42,
+                cached: false
+            }
+            .into_error()
+            .is_none()
+        );
+        assert!(matches!(
+            AuthResponse::Denied {
+                reason: "no".into()
+            }
+            .into_error(),
+            Some(AuthResponse::Denied { .. })
+        ));
+    }
+
+    #[test]
+    fn process_group_source_refuses_a_caller_it_cannot_verify() {
+        let mut state = state_with_rule(AuthRequirement::None);
+        state.config.group_source = GroupSource::Process;
+        // Our own pid, but claimed by a different uid
+        let impostor = Caller::accepted(CallerInfo {
+            pid: std::process::id(),
+            ..caller(
+                "/usr/bin/authsudo",
+                unsafe { libc::getuid() }.wrapping_add(1),
+            )
+            .info
+        });
+        let check = AuthCheckRequest {
+            target: PathBuf::from("/usr/bin/id"),
+            args: Vec::new(),
+        };
+
+        assert!(matches!(
+            check_response(&impostor, &check, &state),
+            AuthCheckResponse::Denied { reason } if reason.contains("changed owner")
+        ));
+    }
+
+    #[test]
+    fn process_group_source_judges_the_groups_held_at_connect() {
+        let mut policy = PolicyEngine::new();
+        policy
+            .load_from_str(
+                "[[rules]]\ntarget = \"/usr/bin/id\"\nallow_groups = [\"root\"]\nallow_callers = [\"/usr/bin/authsudo\"]\nauth = \"none\"\n",
+            )
+            .unwrap();
+        let mut state = state_with_policy(policy);
+        state.config.group_source = GroupSource::Process;
+        let check = AuthCheckRequest {
+            target: PathBuf::from("/usr/bin/id"),
+            args: Vec::new(),
+        };
+        // Both are this live process, whatever groups it holds now; only
+        // the snapshots taken at connect differ
+        let connected_as = |gids: Vec<u32>| Caller {
+            info: CallerInfo {
+                pid: std::process::id(),
+                ..caller("/usr/bin/authsudo", unsafe { libc::getuid() }).info
+            },
+            gids: Ok(gids),
+            secontext: None,
+            unit: None,
+        };
+
+        assert!(matches!(
+            check_response(&connected_as(vec![0]), &check, &state),
+            AuthCheckResponse::Allowed
+        ));
+        assert!(!matches!(
+            check_response(&connected_as(vec![4_000_000]), &check, &state),
+            AuthCheckResponse::Allowed
+        ));
+    }
+
+    #[tokio::test]
+    async fn runas_outside_allow_runas_is_refused_before_spawning() {
+        let mut policy = PolicyEngine::new();
+        policy
+            .load_from_str(
+                r#"
+                [[rules]]
+                target = "/usr/bin/id"
+                allow_users = ["root"]
+                auth = "none"
+                runas = "backup"
+                "#,
+            )
+            .unwrap();
+        let state = state_with_policy(policy);
+        let response = process_request(
+            &caller("/usr/bin/authctl", 0),
+            &request("/usr/bin/id"),
+            &state,
+        )
+        .await;
+        assert!(matches!(
+            response,
+            AuthResponse::Error { message } if message.contains("allow_runas")
+        ));
+        assert!(state.scopes().list_for(0, true).is_empty());
+    }
+
+    fn id_rule(auth: &str) -> PolicyEngine {
+        let mut policy = PolicyEngine::new();
+        policy
+            .load_from_str(&format!(
+                "[[rules]]\ntarget = \"/usr/bin/id\"\nallow_users = [\"root\"]\nauth = \"{auth}\"\n"
+            ))
+            .unwrap();
+        policy
+    }
+
+    #[test]
+    fn a_policy_taken_before_a_reload_stays_whole() {
+        let state = state_with_policy(id_rule("none"));
+        let before = state.policy();
+        state.replace_policy(PolicyEngine::new());
+
+        let id = Path::new("/usr/bin/id");
+        assert!(matches!(
+            before.check(id, 0),
+            PolicyDecision::AllowImmediate
+        ));
+        assert!(matches!(
+            state.policy().check(id, 0),
+            PolicyDecision::Unknown
+        ));
+    }
+
+    #[test]
+    fn checks_during_reloads_see_the_old_policy_or_the_new() {
+        let state = Arc::new(state_with_policy(id_rule("none")));
+        let reloader = {
+            let state = Arc::clone(&state);
+            std::thread::spawn(move || {
+                for i in 0..500 {
+                    state.replace_policy(id_rule(if i % 2 == 0 { "confirm" } else { "none" }));
+                }
+            })
+        };
+
+        let root = caller("/usr/bin/authctl", 0);
+        let mut checks = 0;
+        while !reloader.is_finished() || checks == 0 {
+            let decision = state
+                .policy_decision(&root, Path::new("/usr/bin/id"), &[])
+                .unwrap();
+            assert!(
+                matches!(
+                    decision,
+                    PolicyDecision::AllowImmediate | PolicyDecision::AllowWithConfirm
+                ),
+                "{decision:?}"
+            );
+            checks += 1;
+        }
+        reloader.join().unwrap();
+    }
+
+    #[test]
+    fn others_logged_in_deny_only_targets_that_forbid_it() {
+        let mut policy = PolicyEngine::new();
+        policy
+            .load_from_str(
+                r#"
+                [[rules]]
+                target = "/usr/bin/reboot"
+                allow_users = ["root"]
+                auth = "none"
+                forbid_if_others_logged_in = true
+
+                [[rules]]
+                target = "/usr/bin/id"
+                allow_users = ["root"]
+                auth = "none"
+                "#,
+            )
+            .unwrap();
+        let mut state = state_with_policy(policy);
+        let root = caller("/usr/bin/authctl", 0);
+        let decide = |state: &AppState, target: &str| {
+            state
+                .policy_decision(&root, Path::new(target), &[])
+                .unwrap()
+        };
+
+        state.sessions = Arc::new(ScriptedSessions(vec![0, 0]));
+        assert!(matches!(
+            decide(&state, "/usr/bin/reboot"),
+            PolicyDecision::AllowImmediate
+        ));
+
+        state.sessions = Arc::new(ScriptedSessions(vec![0, 1000]));
+        assert!(matches!(
+            decide(&state, "/usr/bin/reboot"),
+            PolicyDecision::Denied(reason) if reason.contains("uid 1000")
+        ));
+        assert!(matches!(
+            decide(&state, "/usr/bin/id"),
+            PolicyDecision::AllowImmediate
+        ));
+    }
+
+    /// Accepts root's `hunter2`, but only through PAM service `authd-power`
+    struct PowerServiceOnly;
+
+    impl Authenticator for PowerServiceOnly {
+        fn authenticate(&self, service: &str, user: &str, password: &str) -> Result<bool, String> {
+            Ok(service == "authd-power" && user == "root" && password == "hunter2")
+        }
+    }
+
+    #[tokio::test]
+    async fn approvals_are_checked_through_the_rules_pam_service() {
+        let rules = |pam_service: &str| {
+            let mut policy = PolicyEngine::new();
+            policy
+                .load_from_str(&format!(
+                    "[[rules]]\ntarget = \"/usr/bin/poweroff\"\nallow_users = [\"root\"]\nauth = \"none\"\nrequire_approver_group = \"root\"\n{pam_service}"
+                ))
+                .unwrap();
+            policy
+        };
+        let mut state = state_with_policy(rules("pam_service = \"authd-power\"\n"));
+        state.authenticator = Arc::new(PowerServiceOnly);
+        let mut request = request("/usr/bin/poweroff");
+        request.approval = Some(authd_protocol::Approval {
+            user: "root".into(),
+            password: "hunter2".into(),
+            reason: None,
+        });
+        let requester = caller("/usr/bin/authsudo", 0);
+        // The service `launch` takes from the rule admitting the requester
+        let service = |state: &AppState| {
+            let checked = state
+                .policy_check(&requester, &request.target, &request.args)
+                .unwrap();
+            checked.settings.pam_service().to_string()
+        };
+
+        let pam_service = service(&state);
+        assert_eq!(pam_service, "authd-power");
+        assert!(
+            approval_response(&requester, &request, &state, &pam_service)
+                .await
+                .is_none()
+        );
+
+        // The same approval through the default `authd` service fails
+        state.replace_policy(rules(""));
+        let pam_service = service(&state);
+        assert!(matches!(
+            approval_response(&requester, &request, &state, &pam_service).await,
+            Some(AuthResponse::Denied { .. })
+        ));
+    }
+
+    #[test]
+    fn audit_stream_marks_lag_and_ends_when_the_feed_closes() {
+        use tokio::sync::broadcast::error::RecvError;
+
+        assert_eq!(
+            audit_stream_message(Ok("{}".into())),
+            Some(AuditStreamMessage::Event { json: "{}".into() })
+        );
+        assert_eq!(
+            audit_stream_message(Err(RecvError::Lagged(7))),
+            Some(AuditStreamMessage::Lagged { missed: 7 })
+        );
+        assert_eq!(audit_stream_message(Err(RecvError::Closed)), None);
+    }
+
+    #[tokio::test]
+    async fn decisions_are_streamed_to_audit_subscribers() {
+        let mut policy = PolicyEngine::new();
+        policy
+            .load_from_str(
+                "[[rules]]\ntarget = \"/usr/bin/poweroff\"\nallow_users = [\"root\"]\nauth = \"none\"\nrequire_approver_group = \"root\"\n",
+            )
+            .unwrap();
+        let state = state_with_policy(policy);
+        assert!(matches!(
+            subscribe_audit(&caller("/usr/bin/authctl", 4242424), &state),
+            Err(AuditStreamMessage::Denied { .. })
+        ));
+        let mut events = subscribe_audit(&caller("/usr/bin/authctl", 0), &state).unwrap();
+
+        // Nobody can approve, so the approval is refused and audited
+        let response = approval_response(
+            &caller("/usr/bin/authsudo", 1000),
+            &request("/usr/bin/poweroff"),
+            &state,
+            DEFAULT_PAM_SERVICE,
+        )
+        .await;
+        assert!(matches!(response, Some(AuthResponse::Denied { .. })));
+
+        let Some(AuditStreamMessage::Event { json }) = audit_stream_message(events.recv().await)
+        else {
+            panic!("no audit event streamed");
+        };
+        let event: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(event["event"], "approval");
+        assert_eq!(event["outcome"], "denied");
+        assert_eq!(event["uid"], 1000);
+        assert_eq!(event["target"], "/usr/bin/poweroff");
+    }
+
+    #[tokio::test]
+    async fn short_lived_launches_are_all_reaped() {
+        let state = state_with_rule(AuthRequirement::None);
+        let authsudo = caller("/usr/bin/authsudo", 1000);
+        let mut launched = Vec::new();
+        for i in 0..50 {
+            let child = tokio::process::Command::new("true").spawn().unwrap();
+            let pid = child.id().unwrap();
+            let unit = format!("authd-reap-{i}.scope");
+            let exit = track_scope(&state, &authsudo, &request("/usr/bin/id"), unit, pid, child);
+            launched.push((pid, exit));
+        }
+
+        for (pid, exit) in launched {
+            assert!(exit.await.unwrap().unwrap().success());
+            // Gone, or its pid already taken by some unrelated live process
+            let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).unwrap_or_default();
+            let state_char = stat
+                .rsplit_once(") ")
+                .and_then(|(_, rest)| rest.chars().next());
+            assert_ne!(state_char, Some('Z'), "pid {pid} left a zombie");
+        }
+        assert!(state.scopes().list_for(0, true).is_empty());
+    }
+
+    #[cfg(coverage)]
+    #[tokio::test]
+    async fn oversized_environments_are_refused_before_spawning() {
+        let mut state = state_with_rule(AuthRequirement::None);
+        state.config.max_env_entries = 2;
+        state.config.max_env_bytes = 32;
+        let authsudo = caller("/usr/bin/authsudo", 1000);
+
+        let mut crowded = request("/usr/bin/id");
+        crowded.env = (0..3).map(|i| (format!("VAR{i}"), "x".into())).collect();
+        let response = process_request(&authsudo, &crowded, &state).await;
+        assert!(matches!(
+            response,
+            AuthResponse::Error { message } if message.contains("3 variables")
+        ));
+
+        let mut bulky = request("/usr/bin/id");
+        bulky.env = HashMap::from([("WAYLAND_DISPLAY".into(), "w".repeat(64))]);
+        let response = process_request(&authsudo, &bulky, &state).await;
+        assert!(matches!(
+            response,
+            AuthResponse::Error { message } if message.contains("80 bytes")
+        ));
+
+        // Within both limits the request goes on to the (stubbed) spawn
+        let mut fine = request("/usr/bin/id");
+        fine.env = HashMap::from([("WAYLAND_DISPLAY".into(), "wayland-1".into())]);
+        let response = process_request(&authsudo, &fine, &state).await;
+        assert!(matches!(
+            response,
+            AuthResponse::Error { message } if message.contains("spawning")
+        ));
+    }
+
+    #[test]
+    fn interpreters_get_only_display_and_locale_variables() {
+        let env = HashMap::from([
+            ("WAYLAND_DISPLAY".to_string(), "wayland-1".to_string()),
+            ("LANG".into(), "de_DE.UTF-8".into()),
+            ("LC_TIME".into(), "C".into()),
+            ("PYTHONPATH".into(), "/home/alice/lib".into()),
+            ("PERL5LIB".into(), "/home/alice/perl".into()),
+            ("RUBYLIB".into(), "/home/alice/ruby".into()),
+        ]);
+        assert_eq!(
+            launch_environment(&env, true),
+            vec![
+                ("LANG", "de_DE.UTF-8"),
+                ("LC_TIME", "C"),
+                ("WAYLAND_DISPLAY", "wayland-1"),
+            ]
+        );
+        // Targets without allow_scripts get the request's variables as sent
+        assert_eq!(launch_environment(&env, false).len(), 6);
+    }
+
+    #[cfg(coverage)]
+    #[tokio::test]
+    async fn lockdown_refuses_everything_but_emergency_targets() {
+        let flag = std::env::temp_dir().join(format!("authd-lockdown-{}", std::process::id()));
+        let mut state = state_with_rule(AuthRequirement::None);
+        state.lockdown = Lockdown::new(flag.clone());
+        let admin = caller("/usr/bin/authctl", 0);
+        assert!(matches!(
+            set_lockdown(&admin, &LockdownRequest { active: true }, &state),
+            LockdownResponse::Set { active: true }
+        ));
+
+        let authsudo = caller("/usr/bin/authsudo", 1000);
+        let response = process_request(&authsudo, &request("/usr/bin/id"), &state).await;
+        assert!(matches!(
+            response,
+            AuthResponse::Denied { reason } if reason == LOCKDOWN_REASON
+        ));
+
+        // The coverage spawn stub fails, which shows the request got past lockdown
+        state.config.emergency_allow = vec![PathBuf::from("/usr/bin/id")];
+        let response = process_request(&authsudo, &request("/usr/bin/id"), &state).await;
+        assert!(matches!(
+            response,
+            AuthResponse::Error { message } if message.contains("spawning")
+        ));
+
+        set_lockdown(&admin, &LockdownRequest { active: false }, &state);
+        assert!(!flag.exists());
+    }
+
+    #[tokio::test]
+    async fn an_evaluation_only_daemon_answers_but_never_launches() {
+        let mut state = state_with_rule(AuthRequirement::None);
+        state.mode = StartupMode::EvaluationOnly;
+        let authsudo = caller("/usr/bin/authsudo", 1000);
+
+        let response = process_request(&authsudo, &request("/usr/bin/id"), &state).await;
+        assert!(matches!(
+            response,
+            AuthResponse::Error { message } if message == EVALUATION_ONLY
+        ));
+
+        let mut confirm_only = request("/usr/bin/id");
+        confirm_only.confirm_only = true;
+        let response = process_request(&authsudo, &confirm_only, &state).await;
+        assert!(matches!(response, AuthResponse::Success { pid: 0, .. }));
+    }
+
+    #[tokio::test]
+    async fn rules_needing_methods_authd_lacks_are_refused() {
+        let hwkey_only = state_with_rule(AuthRequirement::Methods(MethodSet::All(vec![
+            AuthMethod::Confirm,
+            AuthMethod::Hwkey,
+        ])));
+        let response = checked_response(
+            &caller("/usr/bin/authsudo", 1000),
+            &request("/usr/bin/id"),
+            &hwkey_only,
+        )
+        .await;
+        assert!(matches!(
+            response,
+            Err(AuthResponse::Denied { reason })
+                if reason == "/usr/bin/id needs all(confirm, hwkey), which authd can't check"
+        ));
+
+        let either = state_with_rule(AuthRequirement::Methods(MethodSet::Any(vec![
+            AuthMethod::Hwkey,
+            AuthMethod::Password,
+        ])));
+        assert!(matches!(
+            either.policy_decision(
+                &caller("/usr/bin/authsudo", 1000),
+                Path::new("/usr/bin/id"),
+                &[]
+            ),
+            Ok(PolicyDecision::AllowWithConfirm)
+        ));
+
+        // authsudo's confirmations carry the methods its rule takes
+        let mut state = state_with_confirmer(
+            AuthRequirement::Confirm,
+            Arc::new(ScriptedConfirmer::new(DialogResult::Confirmed)),
+        );
+        let root = caller("/usr/bin/authsudo", 0);
+        let confirm_only = |methods: MethodSet, password: &str| AuthRequest {
+            password: password.into(),
+            confirm_only: true,
+            methods: Some(methods),
+            ..request("/usr/bin/id")
+        };
+        let response = process_request(
+            &root,
+            &confirm_only(MethodSet::Any(vec![AuthMethod::Hwkey]), ""),
+            &state,
+        )
+        .await;
+        assert!(matches!(
+            response,
+            AuthResponse::Denied { reason }
+                if reason == "/usr/bin/id needs any(hwkey), which authd can't check"
+        ));
+
+        // A confirmed dialog is no password
+        let password_or_key = MethodSet::Any(vec![AuthMethod::Password, AuthMethod::Hwkey]);
+        let response =
+            process_request(&root, &confirm_only(password_or_key.clone(), ""), &state).await;
+        assert!(matches!(response, AuthResponse::AuthFailed));
+        state.authenticator = Arc::new(ScriptedAuthenticator {
+            user: "root".into(),
+            password: "hunter2".into(),
+        });
+        let response =
+            process_request(&root, &confirm_only(password_or_key, "hunter2"), &state).await;
+        assert!(matches!(response, AuthResponse::Success { pid: 0, .. }));
+    }
+
+    #[tokio::test]
+    async fn forced_password_targets_need_the_password_not_a_click() {
+        let mut policy = PolicyEngine::new();
+        policy
+            .load_from_str(
+                r#"
+                [[rules]]
+                target = "/usr/bin/id"
+                allow_callers = ["/usr/bin/authsudo"]
+                auth = "confirm"
+
+                [[rules]]
+                target = "/usr/bin/rm"
+                allow_callers = ["/usr/bin/authsudo"]
+                auth = "discourage"
+                "#,
+            )
+            .unwrap();
+        policy.set_force_password_targets(&[PathBuf::from("/usr/bin/*")]);
+        let mut state = state_with_policy(policy);
+        state.confirmer = Arc::new(ScriptedConfirmer::new(DialogResult::Confirmed));
+        state.authenticator = Arc::new(ScriptedAuthenticator {
+            user: "root".into(),
+            password: "hunter2".into(),
+        });
+        let root = caller("/usr/bin/authsudo", 0);
+        let with_password = |target: &str, password: &str| AuthRequest {
+            password: password.into(),
+            ..request(target)
+        };
+
+        for target in ["/usr/bin/id", "/usr/bin/rm"] {
+            let response = checked_response(&root, &with_password(target, ""), &state).await;
+            assert!(
+                matches!(response, Err(AuthResponse::AuthFailed)),
+                "{target}: {response:?}"
+            );
+            let response = checked_response(&root, &with_password(target, "hunter2"), &state).await;
+            assert!(
+                matches!(response, Ok(Authorized::Fresh)),
+                "{target}: {response:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn only_admins_may_change_lockdown() {
+        let flag = std::env::temp_dir().join(format!("authd-lockdown-user-{}", std::process::id()));
+        let mut state = state_with_rule(AuthRequirement::None);
+        state.lockdown = Lockdown::new(flag.clone());
+
+        assert!(matches!(
+            set_lockdown(
+                &caller("/usr/bin/authctl", 4_000_000),
+                &LockdownRequest { active: true },
+                &state
+            ),
+            LockdownResponse::Denied { .. }
+        ));
+        assert!(!flag.exists());
+    }
+
+    #[tokio::test]
+    async fn test_auth_reports_the_backend_verdict_without_launching() {
+        let mut state = state_with_rule(AuthRequirement::None);
+        state.authenticator = Arc::new(ScriptedAuthenticator {
+            user: "root".into(),
+            password: "hunter2".into(),
+        });
+        let root = caller("/usr/bin/authsudo", 0);
+        let test = |password: &str| TestAuthRequest {
+            password: password.into(),
+        };
+
+        assert!(matches!(
+            test_auth(&root, &test("hunter2"), &state).await,
+            TestAuthResponse::Accepted
+        ));
+        assert!(matches!(
+            test_auth(&root, &test("wrong"), &state).await,
+            TestAuthResponse::Rejected
+        ));
+        assert!(state.scopes().list_for(0, true).is_empty());
+        assert!(
+            state
+                .cached_remaining(0, Path::new("/usr/bin/id"), &[], Kind::Confirmation)
+                .is_none()
+        );
+
+        // A backend that can't check passwords says why
+        state.authenticator = Arc::new(approval::NoBackend);
+        let response = test_auth(&root, &test("hunter2"), &state).await;
+        assert!(matches!(
+            response,
+            TestAuthResponse::Error { message } if message.contains("pam_fallback")
+        ));
+    }
+
+    #[test]
+    fn confirmations_are_remembered_apart_from_credentials() {
+        let mut policy = PolicyEngine::new();
+        policy
+            .load_from_str(
+                r#"
+                [[rules]]
+                target = "/usr/bin/id"
+                allow_callers = ["/usr/bin/authsudo"]
+                auth = "confirm"
+                cache_timeout = 600
+                confirm_cache_timeout = 30
+
+                [[rules]]
+                target = "/usr/bin/passwd"
+                allow_callers = ["/usr/bin/authsudo"]
+                auth = "password"
+                cache_timeout = 600
+                confirm_cache_timeout = 30
+                "#,
+            )
+            .unwrap();
+        let state = state_with_policy(policy);
+        let authsudo = caller("/usr/bin/authsudo", 1000);
+        let check = |target: &str| {
+            let request = AuthCheckRequest {
+                target: PathBuf::from(target),
+                args: Vec::new(),
+            };
+            check_response(&authsudo, &request, &state)
+        };
+
+        // A confirm rule: missed, then remembered for confirm_cache_timeout
+        assert!(matches!(
+            check("/usr/bin/id"),
+            AuthCheckResponse::PasswordRequired
+        ));
+        state.remember(1000, Path::new("/usr/bin/id"), &[], Kind::Confirmation);
+        assert!(matches!(
+            check("/usr/bin/id"),
+            AuthCheckResponse::Cached { remaining_secs } if remaining_secs > 0 && remaining_secs <= 30
+        ));
+
+        // A password rule: a confirmation doesn't stand in for the password,
+        // which is remembered for cache_timeout
+        state.remember(1000, Path::new("/usr/bin/passwd"), &[], Kind::Confirmation);
+        assert!(matches!(
+            check("/usr/bin/passwd"),
+            AuthCheckResponse::PasswordRequired
+        ));
+        state.remember(1000, Path::new("/usr/bin/passwd"), &[], Kind::Credential);
+        assert!(matches!(
+            check("/usr/bin/passwd"),
+            AuthCheckResponse::Cached { remaining_secs } if remaining_secs > 30
+        ));
+    }
+
+    #[test]
+    fn cache_listing_is_limited_to_the_callers_own_entries() {
+        let state = state_with_rule(AuthRequirement::Confirm);
+        assert!(state.cached_for(1000).is_empty());
+
+        state.remember(1000, Path::new("/usr/bin/id"), &[], Kind::Confirmation);
+        state.remember(1001, Path::new("/usr/bin/id"), &[], Kind::Confirmation);
+        let listed = state.cached_for(1000);
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].target, PathBuf::from("/usr/bin/id"));
+        assert!(listed[0].remaining_secs > 0 && listed[0].remaining_secs <= 300);
+        assert!(state.cached_for(1002).is_empty());
+    }
+
+    #[test]
+    fn active_scopes_are_limited_to_the_launching_user() {
+        let state = state_with_rule(AuthRequirement::Confirm);
+        for (unit, uid) in [("mine.scope", 1000), ("theirs.scope", 1001)] {
+            state.scopes().insert(ActiveScope {
+                unit: unit.into(),
+                target: PathBuf::from("/usr/bin/id"),
+                caller: PathBuf::from("/usr/bin/authctl"),
+                uid,
+                pid: 4242,
+                started_secs: 0,
+            });
+        }
+
+        let units: Vec<String> = state
+            .active_scopes(1000)
+            .into_iter()
+            .map(|scope| scope.unit)
+            .collect();
+        assert_eq!(units, vec!["mine.scope"]);
+        // root is always an admin
+        assert_eq!(state.active_scopes(0).len(), 2);
     }
 
     #[cfg(coverage)]
@@ -0,0 +1,293 @@
+//! `notify_webhook`: an out-of-band alert each time a target is authorized
+//!
+//! Every matching rule's URL gets one POST of a JSON [`Notification`]
+//! ("prod-deploy was run by alice"). Posts are sent in the background with a
+//! five second timeout and never retried: a failure is logged and the
+//! request goes ahead as if nothing happened. At most 16 posts are
+//! outstanding at once, so an endpoint that hangs can't pile up tasks; past
+//! that, notifications are dropped with a warning.
+//!
+//! The HTTP client is behind the `webhook` feature. Without it, rules that
+//! set `notify_webhook` only get a warning in the log.
+
+use crate::audit::lossy_path;
+use authd_protocol::AuthRequest;
+use peercred_ipc::CallerInfo;
+use serde::Serialize;
+use std::path::PathBuf;
+#[cfg(feature = "webhook")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "webhook")]
+use std::time::Duration;
+use tracing::warn;
+
+/// How long one POST may take, connecting included
+#[cfg(feature = "webhook")]
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Posts outstanding at once before new notifications are dropped
+#[cfg(feature = "webhook")]
+const MAX_IN_FLIGHT: usize = 16;
+
+/// The JSON body of a notification
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Notification {
+    /// Always `authorized`
+    pub event: &'static str,
+    /// Unix time in seconds
+    pub time: u64,
+    pub uid: u32,
+    /// `uid`'s user name, if it has one
+    pub user: Option<String>,
+    pub pid: u32,
+    /// Executable of the client that asked
+    #[serde(serialize_with = "lossy_path")]
+    pub caller: PathBuf,
+    #[serde(serialize_with = "lossy_path")]
+    pub target: PathBuf,
+    pub args: Vec<String>,
+}
+
+impl Notification {
+    pub fn authorized(caller: &CallerInfo, request: &AuthRequest, time: u64) -> Self {
+        Self {
+            event: "authorized",
+            time,
+            uid: caller.uid,
+            user: authd_policy::username_from_uid(caller.uid),
+            pid: caller.pid,
+            caller: caller.exe.clone(),
+            target: request.target.clone(),
+            args: request.args.clone(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        // Strings, numbers and lossy paths: serializing can't fail
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// POST `notification` to each of `urls` in the background
+#[cfg(feature = "webhook")]
+pub fn send(urls: Vec<String>, notification: &Notification) {
+    let body = notification.to_json();
+    for url in urls {
+        let Some(slot) = Slot::take() else {
+            warn!(
+                "notify {}: too many notifications outstanding, dropped",
+                url
+            );
+            continue;
+        };
+        let body = body.clone();
+        tokio::spawn(async move {
+            let _slot = slot;
+            if let Err(e) = post(&url, body).await {
+                warn!("notify {}: {}", url, e);
+            }
+        });
+    }
+}
+
+#[cfg(not(feature = "webhook"))]
+pub fn send(urls: Vec<String>, _notification: &Notification) {
+    for url in urls {
+        warn!(
+            "not notifying {}: authd was built without the webhook feature",
+            url
+        );
+    }
+}
+
+#[cfg(feature = "webhook")]
+async fn post(url: &str, body: String) -> Result<(), String> {
+    let response = client()
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = response.status();
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(format!("HTTP {}", status))
+    }
+}
+
+#[cfg(feature = "webhook")]
+fn client() -> &'static reqwest::Client {
+    static CLIENT: std::sync::OnceLock<reqwest::Client> = std::sync::OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            // A redirect could send the summary somewhere the rule didn't name
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(feature = "webhook")]
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// One of the [`MAX_IN_FLIGHT`] posts, given back on drop
+#[cfg(feature = "webhook")]
+struct Slot;
+
+#[cfg(feature = "webhook")]
+impl Slot {
+    fn take() -> Option<Self> {
+        if IN_FLIGHT.fetch_add(1, Ordering::SeqCst) >= MAX_IN_FLIGHT {
+            IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(Self)
+    }
+}
+
+#[cfg(feature = "webhook")]
+impl Drop for Slot {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+    use std::collections::HashMap;
+
+    fn notification() -> Notification {
+        let caller = CallerInfo {
+            uid: 1000,
+            gid: 1000,
+            pid: 4242,
+            exe: PathBuf::from("/usr/bin/authctl"),
+        };
+        let request = AuthRequest {
+            target: PathBuf::from("/usr/local/bin/prod-deploy"),
+            args: vec!["--region".into(), "eu".into()],
+            env: HashMap::new(),
+            password: String::new(),
+            confirm_only: false,
+            prompt_title: None,
+            prompt_message: None,
+            prompt_detail: None,
+            approval: None,
+            discouraged: false,
+            methods: None,
+            pam_service: None,
+        };
+        Notification {
+            user: Some("alice".into()),
+            ..Notification::authorized(&caller, &request, 1_700_000_000)
+        }
+    }
+
+    #[test]
+    fn notification_shape() {
+        let body: Value = serde_json::from_str(&notification().to_json()).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "event": "authorized",
+                "time": 1_700_000_000,
+                "uid": 1000,
+                "user": "alice",
+                "pid": 4242,
+                "caller": "/usr/bin/authctl",
+                "target": "/usr/local/bin/prod-deploy",
+                "args": ["--region", "eu"],
+            })
+        );
+    }
+
+    /// Accept one request on `listener`, answer `status`, and return its
+    /// request line and body
+    #[cfg(feature = "webhook")]
+    async fn receive(listener: &tokio::net::TcpListener, status: &str) -> (String, String) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut received = Vec::new();
+        let mut buf = [0; 4096];
+        let (head, body_len) = loop {
+            let read = stream.read(&mut buf).await.unwrap();
+            assert!(read > 0, "connection closed mid-request");
+            received.extend_from_slice(&buf[..read]);
+            let text = String::from_utf8_lossy(&received).into_owned();
+            if let Some(end) = text.find("\r\n\r\n") {
+                let head = text[..end].to_string();
+                let len = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                break (head, end + 4 + len);
+            }
+        };
+        while received.len() < body_len {
+            let read = stream.read(&mut buf).await.unwrap();
+            received.extend_from_slice(&buf[..read]);
+        }
+        let reply = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+        stream.write_all(reply.as_bytes()).await.unwrap();
+
+        let body = String::from_utf8_lossy(&received[head.len() + 4..body_len]).into_owned();
+        let request_line = head.lines().next().unwrap_or_default().to_string();
+        (request_line, body)
+    }
+
+    #[cfg(feature = "webhook")]
+    #[tokio::test]
+    async fn matched_rule_webhook_gets_the_summary() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hooks/deploy", listener.local_addr().unwrap());
+        let mut engine = authd_policy::PolicyEngine::new();
+        engine
+            .load_from_str(&format!(
+                r#"
+                [[rules]]
+                target = "/usr/local/bin/prod-deploy"
+                allow_groups = ["ops"]
+                notify_webhook = "{url}"
+                "#
+            ))
+            .unwrap();
+
+        let urls = engine.notify_webhooks(
+            std::path::Path::new("/usr/local/bin/prod-deploy"),
+            &[] as &[&str],
+        );
+        send(urls, &notification());
+
+        let (request_line, body) = receive(&listener, "204 No Content").await;
+        assert_eq!(request_line, "POST /hooks/deploy HTTP/1.1");
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["target"], "/usr/local/bin/prod-deploy");
+        assert_eq!(body["user"], "alice");
+        assert_eq!(body["args"], json!(["--region", "eu"]));
+    }
+
+    #[cfg(feature = "webhook")]
+    #[tokio::test]
+    async fn failing_endpoints_are_errors() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let (posted, _) = tokio::join!(
+            post(&url, notification().to_json()),
+            receive(&listener, "500 Internal Server Error")
+        );
+        assert_eq!(posted, Err("HTTP 500 Internal Server Error".into()));
+
+        drop(listener);
+        assert!(post(&url, String::new()).await.is_err());
+    }
+}
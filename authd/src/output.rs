@@ -0,0 +1,127 @@
+//! Redirecting a launched process's output to a rule's `log_output`
+//!
+//! The path is expanded for the calling user. A path under their home
+//! (`%h/...`) is opened with the caller's filesystem uid/gid, so a symlink or
+//! directory they control can't turn it into a root write elsewhere. Other
+//! paths are opened as root, refusing a symlink as the final component.
+
+use authd_policy::template::{self, TemplateVars};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogOutput {
+    pub path: PathBuf,
+    /// (uid, gid) to open the file as; `None` opens it as root
+    pub open_as: Option<(u32, u32)>,
+}
+
+/// The deciding rule's `log_output` template, expanded for a caller
+pub fn resolve(
+    log_template: Option<&Path>,
+    uid: u32,
+    gid: u32,
+) -> Result<Option<LogOutput>, String> {
+    let Some(log_template) = log_template else {
+        return Ok(None);
+    };
+    let path = TemplateVars::for_uid(uid)
+        .and_then(|vars| template::expand(log_template, &vars))
+        .map_err(|e| format!("log_output: {}", e))?;
+    let in_home = log_template.starts_with("%h");
+    Ok(Some(LogOutput {
+        path,
+        open_as: in_home.then_some((uid, gid)),
+    }))
+}
+
+/// Point the command's stdout and stderr at `log`, appending.
+#[cfg(not(coverage))]
+pub fn redirect(cmd: &mut tokio::process::Command, log: &LogOutput) -> Result<(), String> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let Some((uid, gid)) = log.open_as else {
+        let file = std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o640)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(&log.path)
+            .map_err(|e| format!("open {}: {}", log.path.display(), e))?;
+        let stderr = file
+            .try_clone()
+            .map_err(|e| format!("open {}: {}", log.path.display(), e))?;
+        cmd.stdout(file).stderr(stderr);
+        return Ok(());
+    };
+
+    redirect_as(cmd, &log.path, uid, gid)
+}
+
+/// Open the log in the forked child, after it has become root but before
+/// exec, with fsuid/fsgid switched to the caller for just the `open`.
+#[cfg(not(coverage))]
+fn redirect_as(
+    cmd: &mut tokio::process::Command,
+    path: &Path,
+    uid: u32,
+    gid: u32,
+) -> Result<(), String> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| format!("log_output {} contains NUL", path.display()))?;
+    let flags = libc::O_WRONLY | libc::O_APPEND | libc::O_CREAT | libc::O_NOFOLLOW;
+
+    // SAFETY: runs between fork and exec and only makes async-signal-safe
+    // syscalls; the path was allocated before the fork.
+    unsafe {
+        cmd.pre_exec(move || {
+            libc::setfsgid(gid);
+            libc::setfsuid(uid);
+            let fd = libc::open(c_path.as_ptr(), flags, 0o640 as libc::c_uint);
+            let open_error = std::io::Error::last_os_error();
+            libc::setfsuid(0);
+            libc::setfsgid(0);
+            if fd < 0 {
+                return Err(open_error);
+            }
+            libc::dup2(fd, 1);
+            libc::dup2(fd, 2);
+            libc::close(fd);
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_log_output_without_a_rule_setting_one() {
+        assert_eq!(resolve(None, 0, 0), Ok(None));
+    }
+
+    #[test]
+    fn log_path_is_expanded_for_the_caller() {
+        // root always has a passwd entry
+        let template = Path::new("/var/log/authd/%U-%u.log");
+
+        let log = resolve(Some(template), 0, 0).unwrap().unwrap();
+
+        assert_eq!(log.path, PathBuf::from("/var/log/authd/0-root.log"));
+        assert_eq!(log.open_as, None);
+    }
+
+    #[test]
+    fn home_logs_are_opened_as_the_caller() {
+        let template = Path::new("%h/authd.log");
+
+        let log = resolve(Some(template), 0, 0).unwrap().unwrap();
+
+        assert!(log.path.ends_with("authd.log"));
+        assert_eq!(log.open_as, Some((0, 0)));
+    }
+}
@@ -0,0 +1,419 @@
+//! A polkit authority on the system bus, answered from authd's policy
+//! (`polkit-service` feature)
+//!
+//! Some programs ask polkit over D-Bus instead of running pkexec. Pointed
+//! at `com.ossonet.authd.PolicyKit1` instead of `org.freedesktop.PolicyKit1`,
+//! their `CheckAuthorization` calls are decided by the policy target
+//! `polkit_actions` in authd.toml maps the action id to, for the uid of the
+//! process asking:
+//!
+//! - `auth = "none"`, or `confirm` within the confirmation cache: authorized
+//! - `confirm` otherwise, and `discourage`: not authorized, but a challenge
+//!   (the user could confirm it through authctl or authsudo)
+//! - `deny`, no matching rule, or an action without a mapping: not authorized
+//!
+//! No dialog is ever shown from here, whatever flags the caller passes.
+
+use crate::AppState;
+use crate::groups::{self, Caller};
+use authd_policy::PolicyDecision;
+use peercred_ipc::CallerInfo;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+#[cfg(not(coverage))]
+use std::sync::Arc;
+#[cfg(not(coverage))]
+use tracing::info;
+use zbus::zvariant::OwnedValue;
+
+/// Well-known name the authority is served under
+#[cfg(not(coverage))]
+pub const BUS_NAME: &str = "com.ossonet.authd.PolicyKit1";
+/// Object path, the same as polkit's
+#[cfg(not(coverage))]
+const AUTHORITY_PATH: &str = "/org/freedesktop/PolicyKit1/Authority";
+
+/// polkit `Subject`: `(sa{sv})`, a kind and its attributes
+type Subject = (String, HashMap<String, OwnedValue>);
+
+/// The target deciding `action_id`: its own entry, or else the longest
+/// `prefix.*` entry it falls under
+#[cfg_attr(coverage, allow(dead_code))]
+pub fn action_target<'a>(
+    actions: &'a BTreeMap<String, PathBuf>,
+    action_id: &str,
+) -> Option<&'a Path> {
+    if let Some(target) = actions.get(action_id) {
+        return Some(target);
+    }
+    actions
+        .iter()
+        .filter_map(|(pattern, target)| {
+            let prefix = pattern.strip_suffix('*')?;
+            (prefix.ends_with('.') && action_id.starts_with(prefix))
+                .then_some((prefix.len(), target))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, target)| target.as_path())
+}
+
+/// polkit's `AuthorizationResult`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Authorization {
+    pub is_authorized: bool,
+    pub is_challenge: bool,
+    pub details: HashMap<String, String>,
+}
+
+#[cfg_attr(coverage, allow(dead_code))]
+impl Authorization {
+    /// The answer for `decision` on `target`; `cached` says whether the
+    /// subject's confirmation of it is still in the cache
+    pub fn from_decision(target: &Path, decision: &PolicyDecision, cached: bool) -> Self {
+        let (is_authorized, is_challenge) = match decision {
+            PolicyDecision::AllowImmediate => (true, false),
+            PolicyDecision::AllowWithConfirm if cached => (true, false),
+            PolicyDecision::AllowWithConfirm | PolicyDecision::Discouraged => (false, true),
+            PolicyDecision::Denied(_) | PolicyDecision::Unknown => (false, false),
+        };
+        let mut details = HashMap::from([(
+            "authd.target".to_string(),
+            target.to_string_lossy().into_owned(),
+        )]);
+        if let PolicyDecision::Denied(reason) = decision {
+            details.insert("authd.reason".to_string(), reason.clone());
+        }
+        Self {
+            is_authorized,
+            is_challenge,
+            details,
+        }
+    }
+
+    /// The answer for an action id `polkit_actions` doesn't map
+    pub fn unmapped() -> Self {
+        Self {
+            is_authorized: false,
+            is_challenge: false,
+            details: HashMap::new(),
+        }
+    }
+
+    /// As the `(bba{ss})` D-Bus struct
+    pub fn into_dbus(self) -> (bool, bool, HashMap<String, String>) {
+        (self.is_authorized, self.is_challenge, self.details)
+    }
+}
+
+/// Who a subject names
+#[derive(Debug, PartialEq, Eq)]
+enum SubjectRef {
+    /// `unix-process`; `start_time` (in clock ticks since boot, as in
+    /// `/proc/<pid>/stat`) tells a recycled pid apart, so it is required
+    Process { pid: u32, start_time: u64 },
+    /// `system-bus-name`: whoever owns the name now, as the bus daemon knows
+    /// its credentials
+    BusName(String),
+}
+
+#[cfg_attr(coverage, allow(dead_code))]
+fn parse_subject((kind, attrs): &Subject) -> Result<SubjectRef, String> {
+    let attr = |key: &str| {
+        attrs
+            .get(key)
+            .ok_or_else(|| format!("{} subject without {}", kind, key))
+    };
+    match kind.as_str() {
+        "unix-process" => {
+            let pid = u32::try_from(attr("pid")?.clone())
+                .map_err(|_| "unix-process pid is not a uint32".to_string())?;
+            // A bare pid may name whatever process got it since
+            let start_time = u64::try_from(attr("start-time")?.clone())
+                .map_err(|_| "unix-process start-time is not a uint64".to_string())?;
+            Ok(SubjectRef::Process { pid, start_time })
+        }
+        "system-bus-name" => String::try_from(attr("name")?.clone())
+            .map(SubjectRef::BusName)
+            .map_err(|_| "system-bus-name name is not a string".to_string()),
+        other => Err(format!("unsupported subject kind {}", other)),
+    }
+}
+
+/// Start time of a process, from its `/proc/<pid>/stat`
+#[cfg_attr(coverage, allow(dead_code))]
+fn start_time(stat: &str) -> Option<u64> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    // Fields from 3 (state) on; starttime is field 22
+    rest.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Whether `pid` is still the process that started at `expected`
+#[cfg_attr(coverage, allow(dead_code))]
+fn same_process(pid: u32, expected: u64) -> Result<(), String> {
+    let path = format!("/proc/{}/stat", pid);
+    let stat = std::fs::read_to_string(&path).map_err(|e| format!("read {}: {}", path, e))?;
+    if start_time(&stat) != Some(expected) {
+        return Err(format!("process {} is gone", pid));
+    }
+    Ok(())
+}
+
+/// The caller a process subject stands for, if `pid` is still the process
+/// that was meant, before and after its owner is read
+#[cfg_attr(coverage, allow(dead_code))]
+fn process_caller(pid: u32, expected_start: u64) -> Result<Caller, String> {
+    same_process(pid, expected_start)?;
+    let (uid, gid) = groups::process_owner(pid)?;
+    let exe = std::fs::read_link(format!("/proc/{}/exe", pid)).unwrap_or_default();
+    same_process(pid, expected_start)?;
+    Ok(Caller::accepted(CallerInfo { uid, gid, pid, exe }))
+}
+
+/// The caller a bus name stands for: `uid` as the bus daemon vouches for
+/// it, with `pid` only adding what `/proc` knows (gid, executable, groups).
+/// A pid since taken by another user's process is refused.
+#[cfg_attr(coverage, allow(dead_code))]
+fn bus_caller(uid: u32, pid: u32) -> Result<Caller, String> {
+    let (owner, gid) = groups::process_owner(pid)?;
+    if owner != uid {
+        return Err(format!("process {} is gone", pid));
+    }
+    let exe = std::fs::read_link(format!("/proc/{}/exe", pid)).unwrap_or_default();
+    Ok(Caller::accepted(CallerInfo { uid, gid, pid, exe }))
+}
+
+/// Decide `action_id` for `caller`
+#[cfg_attr(coverage, allow(dead_code))]
+fn check(state: &AppState, caller: &Caller, action_id: &str) -> Authorization {
+    let Some(target) = action_target(&state.config.polkit_actions, action_id) else {
+        return Authorization::unmapped();
+    };
+    if let Some(reason) = state.lockdown_refusal(target) {
+        return Authorization::from_decision(target, &PolicyDecision::Denied(reason), false);
+    }
+    match state.policy_check(caller, target, &[]) {
+        Ok(checked) => {
+            let cached = state
+                .cached_remaining(caller.uid, target, &[], checked.kind)
+                .is_some();
+            Authorization::from_decision(target, &checked.decision, cached)
+        }
+        Err(reason) => Authorization::from_decision(target, &PolicyDecision::Denied(reason), false),
+    }
+}
+
+#[cfg(not(coverage))]
+struct Authority {
+    state: Arc<AppState>,
+}
+
+#[cfg(not(coverage))]
+#[zbus::interface(name = "org.freedesktop.PolicyKit1.Authority")]
+impl Authority {
+    async fn check_authorization(
+        &self,
+        #[zbus(connection)] conn: &zbus::Connection,
+        subject: Subject,
+        action_id: String,
+        _details: HashMap<String, String>,
+        _flags: u32,
+        _cancellation_id: String,
+    ) -> zbus::fdo::Result<(bool, bool, HashMap<String, String>)> {
+        let caller = match parse_subject(&subject).map_err(zbus::fdo::Error::InvalidArgs)? {
+            SubjectRef::Process { pid, start_time } => process_caller(pid, start_time),
+            SubjectRef::BusName(name) => {
+                let name = zbus::names::BusName::try_from(name)
+                    .map_err(|e| zbus::fdo::Error::InvalidArgs(e.to_string()))?;
+                let credentials = zbus::fdo::DBusProxy::new(conn)
+                    .await?
+                    .get_connection_credentials(name)
+                    .await?;
+                match (credentials.unix_user_id(), credentials.process_id()) {
+                    (Some(uid), Some(pid)) => bus_caller(uid, pid),
+                    _ => Err("bus name without unix credentials".to_string()),
+                }
+            }
+        }
+        .map_err(zbus::fdo::Error::Failed)?;
+
+        let authorization = check(&self.state, &caller, &action_id);
+        info!(
+            "polkit check: action={} uid={} pid={} authorized={}",
+            action_id, caller.uid, caller.pid, authorization.is_authorized
+        );
+        Ok(authorization.into_dbus())
+    }
+}
+
+/// Serve the authority on `bus` as [`BUS_NAME`]
+#[cfg(not(coverage))]
+pub async fn serve(bus: &zbus::Connection, state: Arc<AppState>) -> zbus::Result<()> {
+    bus.object_server()
+        .at(AUTHORITY_PATH, Authority { state })
+        .await?;
+    bus.request_name(BUS_NAME).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn actions() -> BTreeMap<String, PathBuf> {
+        BTreeMap::from([
+            (
+                "org.freedesktop.udisks2.filesystem-mount".to_string(),
+                PathBuf::from("/usr/bin/udisksctl"),
+            ),
+            (
+                "org.example.*".to_string(),
+                PathBuf::from("/usr/local/bin/example"),
+            ),
+            (
+                "org.example.backup.*".to_string(),
+                PathBuf::from("/usr/local/bin/backup"),
+            ),
+            (
+                "org.example.backup.restore".to_string(),
+                PathBuf::from("/usr/local/bin/restore"),
+            ),
+        ])
+    }
+
+    #[test]
+    fn action_ids_map_to_targets() {
+        let actions = actions();
+        let target = |id| action_target(&actions, id).map(Path::to_path_buf);
+
+        assert_eq!(
+            target("org.freedesktop.udisks2.filesystem-mount"),
+            Some(PathBuf::from("/usr/bin/udisksctl"))
+        );
+        // An exact entry beats any prefix, the longest prefix beats shorter
+        assert_eq!(
+            target("org.example.backup.restore"),
+            Some(PathBuf::from("/usr/local/bin/restore"))
+        );
+        assert_eq!(
+            target("org.example.backup.run"),
+            Some(PathBuf::from("/usr/local/bin/backup"))
+        );
+        assert_eq!(
+            target("org.example.other"),
+            Some(PathBuf::from("/usr/local/bin/example"))
+        );
+        // Prefixes end at a dot, and unmapped ids have no target
+        assert_eq!(target("org.examples.thing"), None);
+        assert_eq!(target("org.freedesktop.udisks2.filesystem-unmount"), None);
+    }
+
+    #[test]
+    fn decisions_map_to_authorization_results() {
+        let target = Path::new("/usr/bin/udisksctl");
+        let answer = |decision, cached| {
+            let result = Authorization::from_decision(target, &decision, cached);
+            (result.is_authorized, result.is_challenge)
+        };
+
+        assert_eq!(answer(PolicyDecision::AllowImmediate, false), (true, false));
+        assert_eq!(
+            answer(PolicyDecision::AllowWithConfirm, true),
+            (true, false)
+        );
+        assert_eq!(
+            answer(PolicyDecision::AllowWithConfirm, false),
+            (false, true)
+        );
+        assert_eq!(answer(PolicyDecision::Discouraged, true), (false, true));
+        assert_eq!(answer(PolicyDecision::Unknown, false), (false, false));
+        assert_eq!(
+            answer(PolicyDecision::Denied("no".into()), true),
+            (false, false)
+        );
+
+        let (authorized, challenge, details) = Authorization::from_decision(
+            target,
+            &PolicyDecision::Denied("user not in group".into()),
+            false,
+        )
+        .into_dbus();
+        assert!(!authorized && !challenge);
+        assert_eq!(details["authd.target"], "/usr/bin/udisksctl");
+        assert_eq!(details["authd.reason"], "user not in group");
+
+        assert_eq!(
+            Authorization::unmapped().into_dbus(),
+            (false, false, HashMap::new())
+        );
+    }
+
+    #[test]
+    fn parses_process_and_bus_name_subjects() {
+        let process = (
+            "unix-process".to_string(),
+            HashMap::from([
+                ("pid".to_string(), OwnedValue::from(4242_u32)),
+                ("start-time".to_string(), OwnedValue::from(123456_u64)),
+            ]),
+        );
+        assert_eq!(
+            parse_subject(&process),
+            Ok(SubjectRef::Process {
+                pid: 4242,
+                start_time: 123456
+            })
+        );
+
+        let name = (
+            "system-bus-name".to_string(),
+            HashMap::from([(
+                "name".to_string(),
+                OwnedValue::try_from(zbus::zvariant::Value::from(":1.42")).unwrap(),
+            )]),
+        );
+        assert_eq!(
+            parse_subject(&name),
+            Ok(SubjectRef::BusName(":1.42".into()))
+        );
+
+        assert!(parse_subject(&("unix-session".to_string(), HashMap::new())).is_err());
+        assert!(parse_subject(&("unix-process".to_string(), HashMap::new())).is_err());
+        let pid_only = (
+            "unix-process".to_string(),
+            HashMap::from([("pid".to_string(), OwnedValue::from(4242_u32))]),
+        );
+        assert_eq!(
+            parse_subject(&pid_only),
+            Err("unix-process subject without start-time".to_string())
+        );
+    }
+
+    #[test]
+    fn process_subjects_must_still_be_the_same_process() {
+        let pid = std::process::id();
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+        let started = start_time(&stat).unwrap();
+
+        let caller = process_caller(pid, started).unwrap();
+        assert_eq!(caller.uid, unsafe { libc::getuid() });
+        assert_eq!(caller.pid, pid);
+        assert!(process_caller(pid, started + 1).is_err());
+    }
+
+    #[test]
+    fn bus_names_are_judged_by_the_uid_the_bus_vouches_for() {
+        let pid = std::process::id();
+        let uid = unsafe { libc::getuid() };
+
+        let caller = bus_caller(uid, pid).unwrap();
+        assert_eq!((caller.uid, caller.pid), (uid, pid));
+        // The pid now belongs to someone else
+        assert!(bus_caller(uid.wrapping_add(1), pid).is_err());
+    }
+
+    #[test]
+    fn start_time_survives_odd_process_names() {
+        let stat = "1234 (a) b (c) S 1 1234 1234 0 -1 4194560 100 0 0 0 \
+                    1 2 0 0 20 0 1 0 987654 1000 100";
+        assert_eq!(start_time(stat), Some(987654));
+    }
+}
@@ -0,0 +1,284 @@
+//! Privilege separation
+//!
+//! With `privsep_user` set in authd.toml, authd binds its socket and connects
+//! to the system bus as root, then switches every uid/gid to that service user.
+//! Request parsing, policy evaluation and the confirmation dialog all run as
+//! the service user. Only these capabilities are kept:
+//!
+//! - `CAP_SETUID`/`CAP_SETGID`: a spawned child switches back to uid/gid 0
+//!   right before exec'ing `systemd-run`, so only the target runs as root.
+//! - `CAP_DAC_OVERRIDE`: reaching the caller's Wayland socket, which lives in
+//!   their 0700 `XDG_RUNTIME_DIR`, to show the dialog.
+//!
+//! The bus connection keeps the root identity it was opened with, which
+//! polkitd requires for `AuthenticationAgentResponse2`.
+//!
+//! Started without root at all, authd could never launch a target as anyone
+//! else; `non_root_startup` decides whether it refuses to start or runs
+//! evaluation only.
+
+use authd_policy::NonRootStartup;
+use std::ffi::CString;
+
+const CAP_DAC_OVERRIDE: u32 = 1;
+const CAP_SETGID: u32 = 6;
+const CAP_SETUID: u32 = 7;
+
+/// Capabilities authd keeps after dropping to the service user
+pub const RETAINED_CAPS: u64 = (1 << CAP_DAC_OVERRIDE) | (1 << CAP_SETGID) | (1 << CAP_SETUID);
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_1026;
+
+/// Credentials of the running process, as reported by `/proc/self/status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessCreds {
+    pub ruid: u32,
+    pub euid: u32,
+    pub suid: u32,
+    pub cap_effective: u64,
+    pub cap_permitted: u64,
+}
+
+/// Parse the `Uid:`, `CapEff:` and `CapPrm:` lines of `/proc/<pid>/status`
+pub fn parse_status(status: &str) -> Option<ProcessCreds> {
+    let mut uids = None;
+    let mut cap_effective = None;
+    let mut cap_permitted = None;
+
+    for line in status.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key {
+            "Uid" => {
+                let ids: Vec<u32> = value
+                    .split_whitespace()
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()
+                    .ok()?;
+                uids = Some((*ids.first()?, *ids.get(1)?, *ids.get(2)?));
+            }
+            "CapEff" => cap_effective = u64::from_str_radix(value, 16).ok(),
+            "CapPrm" => cap_permitted = u64::from_str_radix(value, 16).ok(),
+            _ => {}
+        }
+    }
+
+    let (ruid, euid, suid) = uids?;
+    Some(ProcessCreds {
+        ruid,
+        euid,
+        suid,
+        cap_effective: cap_effective?,
+        cap_permitted: cap_permitted?,
+    })
+}
+
+pub fn current_creds() -> Result<ProcessCreds, String> {
+    let status = std::fs::read_to_string("/proc/self/status")
+        .map_err(|e| format!("read /proc/self/status: {e}"))?;
+    parse_status(&status).ok_or_else(|| "unparseable /proc/self/status".to_string())
+}
+
+/// Refuse to serve requests when separation is configured but the process
+/// still holds root's uid or capabilities beyond `RETAINED_CAPS`.
+pub fn check_privileges(privsep_user: Option<&str>, creds: &ProcessCreds) -> Result<(), String> {
+    let Some(user) = privsep_user else {
+        return Ok(());
+    };
+    if creds.ruid == 0 || creds.euid == 0 || creds.suid == 0 {
+        return Err(format!(
+            "privilege separation configured (user {user}) but authd still runs as root"
+        ));
+    }
+    let extra = (creds.cap_effective | creds.cap_permitted) & !RETAINED_CAPS;
+    if extra != 0 {
+        return Err(format!(
+            "privilege separation configured but authd holds extra capabilities {extra:#x}"
+        ));
+    }
+    Ok(())
+}
+
+/// What a daemon may do, given who started it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupMode {
+    /// Started as root: everything
+    Full,
+    /// Started as another user under `non_root_startup = "evaluate"`: policy
+    /// checks and confirm-only requests, but no launches
+    EvaluationOnly,
+}
+
+/// How a daemon started with effective uid `euid` runs, or why it must not,
+/// as `non_root` says
+pub fn startup_mode(euid: u32, non_root: NonRootStartup) -> Result<StartupMode, String> {
+    match (euid, non_root) {
+        (0, _) => Ok(StartupMode::Full),
+        (_, NonRootStartup::Evaluate) => Ok(StartupMode::EvaluationOnly),
+        (euid, NonRootStartup::Refuse) => Err(format!(
+            "authd must be started as root to launch anything, not as uid {euid} \
+             (non_root_startup = \"evaluate\" runs it evaluation only)"
+        )),
+    }
+}
+
+/// Switch to `user`, keeping only `RETAINED_CAPS`
+pub fn drop_privileges(user: &str) -> Result<(), String> {
+    let (uid, gid) = lookup_user(user)?;
+    if uid == 0 {
+        return Err(format!("privsep user {user} must not be root"));
+    }
+
+    unsafe {
+        check(
+            libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0),
+            "prctl(KEEPCAPS)",
+        )?;
+        check(libc::setgroups(0, std::ptr::null()), "setgroups")?;
+        check(libc::setresgid(gid, gid, gid), "setresgid")?;
+        check(libc::setresuid(uid, uid, uid), "setresuid")?;
+        check(
+            libc::prctl(libc::PR_SET_KEEPCAPS, 0, 0, 0, 0),
+            "prctl(KEEPCAPS)",
+        )?;
+    }
+    set_capabilities(RETAINED_CAPS)
+}
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+fn set_capabilities(caps: u64) -> Result<(), String> {
+    let header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let data = [
+        CapData {
+            effective: caps as u32,
+            permitted: caps as u32,
+            inheritable: 0,
+        },
+        CapData {
+            effective: (caps >> 32) as u32,
+            permitted: (caps >> 32) as u32,
+            inheritable: 0,
+        },
+    ];
+    let rc = unsafe { libc::syscall(libc::SYS_capset, &header as *const CapHeader, data.as_ptr()) };
+    check(rc as libc::c_int, "capset")
+}
+
+fn lookup_user(user: &str) -> Result<(u32, u32), String> {
+    let name = CString::new(user).map_err(|_| format!("invalid user name: {user}"))?;
+    let pwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if pwd.is_null() {
+        return Err(format!("unknown privsep user: {user}"));
+    }
+    unsafe { Ok(((*pwd).pw_uid, (*pwd).pw_gid)) }
+}
+
+fn check(rc: libc::c_int, what: &str) -> Result<(), String> {
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(format!("{what}: {}", std::io::Error::last_os_error()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOT_STATUS: &str = "Name:\tauthd\n\
+        Uid:\t0\t0\t0\t0\n\
+        Gid:\t0\t0\t0\t0\n\
+        CapInh:\t0000000000000000\n\
+        CapPrm:\t000001ffffffffff\n\
+        CapEff:\t000001ffffffffff\n";
+
+    const SEPARATED_STATUS: &str = "Name:\tauthd\n\
+        Uid:\t971\t971\t971\t971\n\
+        Gid:\t971\t971\t971\t971\n\
+        CapPrm:\t00000000000000c2\n\
+        CapEff:\t00000000000000c2\n";
+
+    fn creds(status: &str) -> ProcessCreds {
+        parse_status(status).unwrap()
+    }
+
+    #[test]
+    fn parses_uids_and_capabilities() {
+        let root = creds(ROOT_STATUS);
+        assert_eq!((root.ruid, root.euid, root.suid), (0, 0, 0));
+        assert_eq!(root.cap_effective, 0x1ff_ffff_ffff);
+
+        let separated = creds(SEPARATED_STATUS);
+        assert_eq!(separated.euid, 971);
+        assert_eq!(separated.cap_permitted, RETAINED_CAPS);
+        assert!(parse_status("Name:\tx\n").is_none());
+    }
+
+    #[test]
+    fn refuses_full_root_when_separation_configured() {
+        let error = check_privileges(Some("authd"), &creds(ROOT_STATUS)).unwrap_err();
+        assert!(error.contains("still runs as root"));
+
+        // A lingering saved uid 0 would let the process switch back
+        let mut saved_root = creds(SEPARATED_STATUS);
+        saved_root.suid = 0;
+        assert!(check_privileges(Some("authd"), &saved_root).is_err());
+    }
+
+    #[test]
+    fn refuses_capabilities_beyond_the_retained_set() {
+        let mut creds = creds(SEPARATED_STATUS);
+        creds.cap_permitted |= 1 << 21; // CAP_SYS_ADMIN
+
+        let error = check_privileges(Some("authd"), &creds).unwrap_err();
+        assert!(error.contains("extra capabilities 0x200000"));
+    }
+
+    #[test]
+    fn accepts_separated_process_and_unconfigured_root() {
+        assert!(check_privileges(Some("authd"), &creds(SEPARATED_STATUS)).is_ok());
+        assert!(check_privileges(None, &creds(ROOT_STATUS)).is_ok());
+    }
+
+    #[test]
+    fn non_root_startup_refuses_or_degrades_as_configured() {
+        assert_eq!(
+            startup_mode(0, NonRootStartup::Refuse),
+            Ok(StartupMode::Full)
+        );
+        assert_eq!(
+            startup_mode(0, NonRootStartup::Evaluate),
+            Ok(StartupMode::Full)
+        );
+        assert_eq!(
+            startup_mode(1000, NonRootStartup::Evaluate),
+            Ok(StartupMode::EvaluationOnly)
+        );
+        let error = startup_mode(1000, NonRootStartup::Refuse).unwrap_err();
+        assert!(error.contains("must be started as root"), "{error}");
+        assert!(error.contains("uid 1000"), "{error}");
+    }
+
+    #[test]
+    fn retained_caps_cover_setuid_setgid_and_dac_override() {
+        assert_eq!(RETAINED_CAPS, 0xc2);
+    }
+}
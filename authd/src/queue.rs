@@ -0,0 +1,318 @@
+//! One confirmation dialog at a time
+//!
+//! Each dialog locks the session, so concurrent requests must not stack
+//! them. Requests wait for their turn in arrival order (tokio's semaphore is
+//! fair); past `max_waiting` queued requests, new ones are turned away.
+//!
+//! A request whose client hung up or gave up leaves the queue right away,
+//! through its withdrawal signal or by its wait being dropped, rather than
+//! holding a place until it reaches the front.
+//!
+//! When authd shuts down, [`DialogQueue::close`] tears down the dialog up
+//! at the time, so the session it locked isn't left locked behind a dead
+//! daemon, and turns away every request still waiting.
+
+use crate::dialog::DialogCancel;
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Requests allowed to wait behind the dialog currently shown
+pub const MAX_PENDING_DIALOGS: usize = 8;
+
+/// How long a request waits for its turn. Dialogs time out after 30s, so
+/// this covers a few of them ahead in the queue.
+pub const DIALOG_WAIT_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueError {
+    Full,
+    TimedOut,
+    Withdrawn,
+    ShuttingDown,
+}
+
+impl fmt::Display for QueueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full => f.write_str("too many pending confirmations"),
+            Self::TimedOut => f.write_str("timed out waiting for another confirmation"),
+            Self::Withdrawn => f.write_str("withdrawn while waiting for another confirmation"),
+            Self::ShuttingDown => f.write_str("authd is shutting down"),
+        }
+    }
+}
+
+pub struct DialogQueue {
+    slot: Semaphore,
+    waiting: AtomicUsize,
+    max_waiting: usize,
+    wait_timeout: Duration,
+    /// The dialog up now, for `close` to tear down
+    shown: Mutex<Weak<DialogCancel>>,
+}
+
+impl Default for DialogQueue {
+    fn default() -> Self {
+        Self::new(MAX_PENDING_DIALOGS, DIALOG_WAIT_TIMEOUT)
+    }
+}
+
+impl DialogQueue {
+    pub fn new(max_waiting: usize, wait_timeout: Duration) -> Self {
+        Self {
+            slot: Semaphore::new(1),
+            waiting: AtomicUsize::new(0),
+            max_waiting,
+            wait_timeout,
+            shown: Mutex::new(Weak::new()),
+        }
+    }
+
+    /// Note the dialog about to be shown with the slot just acquired. After
+    /// `close` it is torn down at once instead.
+    pub fn show(&self, cancel: &Arc<DialogCancel>) {
+        *self.shown() = Arc::downgrade(cancel);
+        if self.slot.is_closed() {
+            cancel.cancel();
+        }
+    }
+
+    /// Tear down the dialog up now, if any, and turn away every request
+    /// waiting or yet to come
+    pub fn close(&self) {
+        self.slot.close();
+        let shown = std::mem::take(&mut *self.shown());
+        if let Some(cancel) = shown.upgrade() {
+            cancel.cancel();
+        }
+    }
+
+    fn shown(&self) -> MutexGuard<'_, Weak<DialogCancel>> {
+        self.shown.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Wait for the dialog slot; it's released when the permit drops. Gives
+    /// up as soon as `withdrawn` resolves, freeing its place for the
+    /// requests behind it.
+    pub async fn acquire(
+        &self,
+        withdrawn: impl Future<Output = ()>,
+    ) -> Result<SemaphorePermit<'_>, QueueError> {
+        // Fast path: nobody showing a dialog, so no need to queue
+        if let Ok(permit) = self.slot.try_acquire() {
+            return Ok(permit);
+        }
+
+        let _waiting = Waiting::enter(&self.waiting, self.max_waiting)?;
+        tokio::select! {
+            acquired = tokio::time::timeout(self.wait_timeout, self.slot.acquire()) => {
+                match acquired {
+                    Ok(Ok(permit)) => Ok(permit),
+                    // Only `close` closes the semaphore
+                    Ok(Err(_)) => Err(QueueError::ShuttingDown),
+                    Err(_) => Err(QueueError::TimedOut),
+                }
+            }
+            () = withdrawn => Err(QueueError::Withdrawn),
+        }
+    }
+}
+
+/// Counts a queued request, including one whose connection goes away
+/// mid-wait (its future is dropped).
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    fn enter(counter: &'a AtomicUsize, max: usize) -> Result<Self, QueueError> {
+        let ahead = counter.fetch_add(1, Ordering::SeqCst);
+        let waiting = Self(counter);
+        if ahead >= max {
+            return Err(QueueError::Full);
+        }
+        Ok(waiting)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::pending;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn concurrent_requests_take_turns_in_order() {
+        let queue = Arc::new(DialogQueue::default());
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let first = queue.acquire(pending()).await.unwrap();
+        let mut waiters = Vec::new();
+        for id in 0..3 {
+            let (queue, log) = (Arc::clone(&queue), Arc::clone(&log));
+            waiters.push(tokio::spawn(async move {
+                let _turn = queue.acquire(pending()).await.unwrap();
+                log.lock().unwrap().push(format!("start {id}"));
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                log.lock().unwrap().push(format!("end {id}"));
+            }));
+            // Let each waiter enqueue before the next one is spawned
+            tokio::task::yield_now().await;
+        }
+        drop(first);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+
+        let log = log.lock().unwrap();
+        assert_eq!(
+            *log,
+            vec!["start 0", "end 0", "start 1", "end 1", "start 2", "end 2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_beyond_the_cap() {
+        let queue = Arc::new(DialogQueue::new(1, DIALOG_WAIT_TIMEOUT));
+        let shown = queue.acquire(pending()).await.unwrap();
+
+        let waiter = {
+            let queue = Arc::clone(&queue);
+            tokio::spawn(async move { queue.acquire(pending()).await.map(drop) })
+        };
+        tokio::task::yield_now().await;
+
+        let error = queue.acquire(pending()).await.unwrap_err();
+        assert_eq!(error, QueueError::Full);
+        assert_eq!(error.to_string(), "too many pending confirmations");
+
+        drop(shown);
+        assert_eq!(waiter.await.unwrap(), Ok(()));
+        assert_eq!(queue.waiting.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn waiting_gives_up_after_the_timeout() {
+        let queue = DialogQueue::new(4, Duration::from_millis(10));
+        let _shown = queue.acquire(pending()).await.unwrap();
+
+        assert_eq!(
+            queue.acquire(pending()).await.unwrap_err(),
+            QueueError::TimedOut
+        );
+        assert_eq!(queue.waiting.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn withdrawn_requests_free_their_place_at_once() {
+        let queue = Arc::new(DialogQueue::new(1, DIALOG_WAIT_TIMEOUT));
+        let shown = queue.acquire(pending()).await.unwrap();
+
+        let (give_up, gave_up) = oneshot::channel::<()>();
+        let withdrawn = {
+            let queue = Arc::clone(&queue);
+            tokio::spawn(async move {
+                let gave_up = async {
+                    let _ = gave_up.await;
+                };
+                queue.acquire(gave_up).await.map(drop)
+            })
+        };
+        tokio::task::yield_now().await;
+        assert_eq!(
+            queue.acquire(pending()).await.unwrap_err(),
+            QueueError::Full
+        );
+
+        // Its client's timeout elapses while the first dialog is still up
+        give_up.send(()).unwrap();
+        assert_eq!(withdrawn.await.unwrap(), Err(QueueError::Withdrawn));
+        assert_eq!(queue.waiting.load(Ordering::SeqCst), 0);
+
+        // The next request queues in its place and gets the slot next
+        let next = {
+            let queue = Arc::clone(&queue);
+            tokio::spawn(async move { queue.acquire(pending()).await.map(drop) })
+        };
+        tokio::task::yield_now().await;
+        drop(shown);
+        assert_eq!(next.await.unwrap(), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn closing_tears_down_the_dialog_shown_and_turns_requests_away() {
+        let queue = Arc::new(DialogQueue::new(1, DIALOG_WAIT_TIMEOUT));
+        let turn = queue.acquire(pending()).await.unwrap();
+        let cancel = Arc::new(DialogCancel::default());
+        queue.show(&cancel);
+        // Stands in for the helper holding the session lock
+        let helper = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let pid = helper.id() as libc::pid_t;
+        let dialog = std::thread::spawn({
+            let cancel = Arc::clone(&cancel);
+            move || cancel.wait(helper)
+        });
+        let waiting = {
+            let queue = Arc::clone(&queue);
+            tokio::spawn(async move { queue.acquire(pending()).await.map(drop) })
+        };
+        tokio::task::yield_now().await;
+        // Handed over to the waiter only once it's running
+        while !cancel.has_helper() {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        queue.close();
+
+        assert_eq!(dialog.join().unwrap(), None);
+        assert!(cancel.is_cancelled());
+        assert_eq!(unsafe { libc::kill(pid, 0) }, -1);
+        assert_eq!(waiting.await.unwrap(), Err(QueueError::ShuttingDown));
+        drop(turn);
+        assert_eq!(
+            queue.acquire(pending()).await.unwrap_err(),
+            QueueError::ShuttingDown
+        );
+
+        // A dialog noted after closing never stays up
+        let late = Arc::new(DialogCancel::default());
+        queue.show(&late);
+        assert!(late.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn dropped_waits_leave_the_queue_too() {
+        let queue = Arc::new(DialogQueue::new(1, DIALOG_WAIT_TIMEOUT));
+        let shown = queue.acquire(pending()).await.unwrap();
+
+        let hung_up = {
+            let queue = Arc::clone(&queue);
+            tokio::spawn(async move { queue.acquire(pending()).await.map(drop) })
+        };
+        tokio::task::yield_now().await;
+        // What happens to a request whose connection closes
+        hung_up.abort();
+        let _ = hung_up.await;
+        assert_eq!(queue.waiting.load(Ordering::SeqCst), 0);
+
+        let next = {
+            let queue = Arc::clone(&queue);
+            tokio::spawn(async move { queue.acquire(pending()).await.map(drop) })
+        };
+        tokio::task::yield_now().await;
+        drop(shown);
+        assert_eq!(next.await.unwrap(), Ok(()));
+    }
+}
@@ -0,0 +1,122 @@
+//! Which account a launched target runs as (a rule's `runas`)
+//!
+//! Targets run as root unless their rule says otherwise. `runas = "caller"`
+//! runs the target as the user who asked, so the confirmation only gates and
+//! audits the launch; a user name runs it as that account. Named accounts
+//! other than root must be on `allow_runas` in authd.toml.
+
+use std::ffi::CString;
+
+/// `runas` value for the requesting user
+pub const CALLER: &str = "caller";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunAs {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl RunAs {
+    pub const ROOT: Self = Self { uid: 0, gid: 0 };
+
+    /// `systemd-run` arguments that switch the scope's process to this
+    /// account; none for root
+    pub fn systemd_args(self) -> Vec<String> {
+        if self == Self::ROOT {
+            return Vec::new();
+        }
+        vec![format!("--uid={}", self.uid), format!("--gid={}", self.gid)]
+    }
+}
+
+/// The account a rule's `runas` names for a caller with `caller_uid` and
+/// `caller_gid`. `lookup` finds a user's uid and primary gid by name.
+pub fn resolve(
+    runas: Option<&str>,
+    allow_runas: &[String],
+    caller_uid: u32,
+    caller_gid: u32,
+    lookup: impl Fn(&str) -> Option<(u32, u32)>,
+) -> Result<RunAs, String> {
+    let user = match runas {
+        None | Some("root") => return Ok(RunAs::ROOT),
+        Some(CALLER) => {
+            return Ok(RunAs {
+                uid: caller_uid,
+                gid: caller_gid,
+            });
+        }
+        Some(user) => user,
+    };
+    if !allow_runas.iter().any(|allowed| allowed == user) {
+        return Err(format!("runas {} is not on allow_runas", user));
+    }
+    let (uid, gid) = lookup(user).ok_or_else(|| format!("runas: unknown user {}", user))?;
+    Ok(RunAs { uid, gid })
+}
+
+/// uid and primary gid of `user` from the user database
+pub fn lookup_user(user: &str) -> Option<(u32, u32)> {
+    let name = CString::new(user).ok()?;
+    let pwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if pwd.is_null() {
+        return None;
+    }
+    unsafe { Some(((*pwd).pw_uid, (*pwd).pw_gid)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backup(user: &str) -> Option<(u32, u32)> {
+        (user == "backup").then_some((34, 34))
+    }
+
+    #[test]
+    fn targets_run_as_root_by_default() {
+        assert_eq!(resolve(None, &[], 1000, 1000, backup), Ok(RunAs::ROOT));
+        assert_eq!(
+            resolve(Some("root"), &[], 1000, 1000, backup),
+            Ok(RunAs::ROOT)
+        );
+        assert!(RunAs::ROOT.systemd_args().is_empty());
+    }
+
+    #[test]
+    fn caller_runs_the_target_as_the_requesting_user() {
+        let run_as = resolve(Some(CALLER), &[], 1000, 100, backup).unwrap();
+        assert_eq!(
+            run_as,
+            RunAs {
+                uid: 1000,
+                gid: 100
+            }
+        );
+        assert_eq!(run_as.systemd_args(), ["--uid=1000", "--gid=100"]);
+    }
+
+    #[test]
+    fn named_accounts_must_be_allowed_and_exist() {
+        let allowed = vec!["backup".to_string(), "ghost".to_string()];
+        let run_as = resolve(Some("backup"), &allowed, 1000, 1000, backup).unwrap();
+        assert_eq!(run_as, RunAs { uid: 34, gid: 34 });
+        assert_eq!(run_as.systemd_args(), ["--uid=34", "--gid=34"]);
+
+        assert_eq!(
+            resolve(Some("backup"), &[], 1000, 1000, backup),
+            Err("runas backup is not on allow_runas".into())
+        );
+        assert_eq!(
+            resolve(Some("ghost"), &allowed, 1000, 1000, backup),
+            Err("runas: unknown user ghost".into())
+        );
+    }
+
+    #[test]
+    fn looks_users_up_in_the_user_database() {
+        assert_eq!(lookup_user("root"), Some((0, 0)));
+        assert_eq!(lookup_user("no-such-user-authd"), None);
+        assert_eq!(lookup_user("bad\0name"), None);
+    }
+}
@@ -0,0 +1,228 @@
+//! Files authd creates at runtime (socket, cache and other saved state)
+//!
+//! Modes are set with `fchmod`/`chmod` after creation instead of being left
+//! to `open`'s mode argument, which the process umask can narrow or a
+//! lenient one leave wider than intended. Files must also be owned by authd's
+//! effective uid: one planted by someone else is refused rather than reused.
+
+use std::fs::{self, Metadata, Permissions};
+use std::io::{self, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+/// Saved state: readable by authd alone
+pub const STATE_MODE: u32 = 0o600;
+/// The request socket: any user may connect, since callers are identified
+/// by their peer credentials rather than by file permissions
+pub const SOCKET_MODE: u32 = 0o666;
+
+/// Atomically replace `path` with `bytes`, at exactly `mode`.
+pub fn write_atomic(path: &Path, bytes: &[u8], mode: u32) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    // Left over from a crash mid-save; never reopened, since it could be
+    // anyone's by now
+    match fs::remove_file(&tmp) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(&tmp)?;
+    file.set_permissions(Permissions::from_mode(mode))?;
+    check_owner(&tmp, &file.metadata()?)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// Create `path` empty unless it exists, at exactly `mode`: a flag that
+/// matters only by existing, so there's nothing to write atomically.
+pub fn touch(path: &Path, mode: u32) -> io::Result<()> {
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .mode(mode)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)?;
+    check_owner(path, &file.metadata()?)?;
+    file.set_permissions(Permissions::from_mode(mode))
+}
+
+/// Give an existing file, e.g. a socket just bound, exactly `mode`.
+pub fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.file_type().is_symlink() {
+        return Err(io::Error::other(format!("{} is a symlink", path.display())));
+    }
+    check_owner(path, &metadata)?;
+    fs::set_permissions(path, Permissions::from_mode(mode))
+}
+
+/// Check that only `owner` can add, remove or rename entries in `dir`, the
+/// directory a socket is bound in: anyone else who could would be able to
+/// swap the socket for their own and answer authd's clients.
+pub fn check_socket_dir(dir: &Path, owner: u32) -> Result<(), String> {
+    let metadata = fs::metadata(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    if !metadata.is_dir() {
+        return Err(format!("{} is not a directory", dir.display()));
+    }
+    if metadata.uid() != owner {
+        return Err(format!(
+            "socket directory {} is owned by uid {}, not {}",
+            dir.display(),
+            metadata.uid(),
+            owner
+        ));
+    }
+    if metadata.mode() & 0o022 != 0 {
+        return Err(format!(
+            "socket directory {} is writable by group or others (mode {:o})",
+            dir.display(),
+            metadata.mode() & 0o7777
+        ));
+    }
+    Ok(())
+}
+
+fn check_owner(path: &Path, metadata: &Metadata) -> io::Result<()> {
+    let euid = unsafe { libc::geteuid() };
+    if metadata.uid() != euid {
+        return Err(io::Error::other(format!(
+            "{} is owned by uid {}, not {}",
+            path.display(),
+            metadata.uid(),
+            euid
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("authd-runtime-{name}-{nonce}"));
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    fn mode_of(path: &Path) -> u32 {
+        fs::symlink_metadata(path).unwrap().mode() & 0o7777
+    }
+
+    #[test]
+    fn written_files_get_exactly_the_requested_mode() {
+        let dir = temp_dir("write");
+        let path = dir.join("state");
+
+        // 0o666 is wider than the usual umask lets `open` create
+        for mode in [STATE_MODE, 0o660, 0o666, STATE_MODE] {
+            write_atomic(&path, b"state", mode).unwrap();
+            assert_eq!(mode_of(&path), mode);
+            assert_eq!(fs::read(&path).unwrap(), b"state");
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stale_temp_file_is_replaced_not_reused() {
+        let dir = temp_dir("stale");
+        let path = dir.join("state");
+        fs::write(dir.join("state.tmp"), "stale").unwrap();
+        fs::set_permissions(dir.join("state.tmp"), Permissions::from_mode(0o666)).unwrap();
+
+        write_atomic(&path, b"fresh", STATE_MODE).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"fresh");
+        assert_eq!(mode_of(&path), STATE_MODE);
+        assert!(!dir.join("state.tmp").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn touched_flags_get_their_mode_and_keep_their_content() {
+        let dir = temp_dir("touch");
+        let path = dir.join("flag");
+
+        touch(&path, 0o666).unwrap();
+        assert_eq!(mode_of(&path), 0o666);
+        fs::write(&path, "kept").unwrap();
+        touch(&path, STATE_MODE).unwrap();
+        assert_eq!(mode_of(&path), STATE_MODE);
+        assert_eq!(fs::read(&path).unwrap(), b"kept");
+
+        std::os::unix::fs::symlink(&path, dir.join("link")).unwrap();
+        assert!(touch(&dir.join("link"), STATE_MODE).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn socket_gets_its_mode_whatever_it_was_bound_with() {
+        let dir = temp_dir("socket");
+        let path = dir.join("authd.sock");
+
+        let _listener = UnixListener::bind(&path).unwrap();
+        // As a restrictive umask would have left it
+        fs::set_permissions(&path, Permissions::from_mode(0o600)).unwrap();
+        set_mode(&path, SOCKET_MODE).unwrap();
+        assert_eq!(mode_of(&path), SOCKET_MODE);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn socket_dirs_must_be_owned_and_writable_only_by_their_owner() {
+        let dir = temp_dir("socket-dir");
+        let euid = unsafe { libc::geteuid() };
+
+        for (mode, accepted) in [
+            (0o755, true),
+            (0o700, true),
+            (0o775, false),
+            (0o757, false),
+            (0o777, false),
+            (0o1777, false),
+        ] {
+            fs::set_permissions(&dir, Permissions::from_mode(mode)).unwrap();
+            assert_eq!(
+                check_socket_dir(&dir, euid).is_ok(),
+                accepted,
+                "mode {mode:o}"
+            );
+        }
+        fs::set_permissions(&dir, Permissions::from_mode(0o755)).unwrap();
+        let error = check_socket_dir(&dir, euid.wrapping_add(1)).unwrap_err();
+        assert!(error.contains("owned by uid"), "{error}");
+        assert!(check_socket_dir(&dir.join("missing"), euid).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn set_mode_refuses_symlinks() {
+        let dir = temp_dir("symlink");
+        let target = dir.join("target");
+        fs::write(&target, "").unwrap();
+        fs::set_permissions(&target, Permissions::from_mode(0o600)).unwrap();
+        std::os::unix::fs::symlink(&target, dir.join("link")).unwrap();
+
+        assert!(set_mode(&dir.join("link"), SOCKET_MODE).is_err());
+        assert_eq!(mode_of(&target), 0o600);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
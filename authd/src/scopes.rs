@@ -0,0 +1,222 @@
+//! Registry of privileged scopes authd launched and that are still running
+//!
+//! Every exec runs in a named transient scope (`authd-<uid>-<authd pid>-<seq>.scope`)
+//! so it can be listed and stopped later. Entries are removed when the
+//! launched process exits.
+//!
+//! The registry also enforces `max_concurrent`: a launch reserves its unit,
+//! and with it a slot of its target, before spawning, so two requests racing
+//! for the last slot can't both get it.
+
+use authd_protocol::ActiveScope;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default)]
+pub struct ScopeRegistry {
+    next_seq: u64,
+    active: HashMap<String, ActiveScope>,
+    /// Units reserved for a launch still being spawned, with their target
+    reserved: HashMap<String, PathBuf>,
+}
+
+/// Why a kill request may not proceed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KillRefusal {
+    NotFound,
+    NotOwner,
+}
+
+impl ScopeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reserve a unique unit name for a launch on behalf of `uid`.
+    pub fn next_unit(&mut self, uid: u32) -> String {
+        self.next_seq += 1;
+        format!(
+            "authd-{}-{}-{}.scope",
+            uid,
+            std::process::id(),
+            self.next_seq
+        )
+    }
+
+    /// Reserve a unit for launching `target` for `uid`, unless `max`
+    /// launches of it are already running or being spawned
+    pub fn reserve(&mut self, uid: u32, target: &Path, max: Option<usize>) -> Option<String> {
+        if max.is_some_and(|max| self.running(target) >= max) {
+            return None;
+        }
+        let unit = self.next_unit(uid);
+        self.reserved.insert(unit.clone(), target.to_path_buf());
+        Some(unit)
+    }
+
+    /// Give back a reservation whose launch failed
+    pub fn release(&mut self, unit: &str) {
+        self.reserved.remove(unit);
+    }
+
+    /// Launches of `target` running or being spawned
+    fn running(&self, target: &Path) -> usize {
+        let active = self.active.values().filter(|scope| scope.target == target);
+        let reserved = self
+            .reserved
+            .values()
+            .filter(|reserved| *reserved == target);
+        active.count() + reserved.count()
+    }
+
+    /// Register a launched scope, taking over its unit's reservation
+    pub fn insert(&mut self, scope: ActiveScope) {
+        self.reserved.remove(&scope.unit);
+        self.active.insert(scope.unit.clone(), scope);
+    }
+
+    pub fn remove(&mut self, unit: &str) -> Option<ActiveScope> {
+        self.active.remove(unit)
+    }
+
+    /// Scopes visible to `uid`: its own launches, or all of them for admins.
+    /// Sorted by start time, oldest first.
+    pub fn list_for(&self, uid: u32, is_admin: bool) -> Vec<ActiveScope> {
+        let mut scopes: Vec<ActiveScope> = self
+            .active
+            .values()
+            .filter(|scope| is_admin || scope.uid == uid)
+            .cloned()
+            .collect();
+        scopes.sort_by(|a, b| {
+            a.started_secs
+                .cmp(&b.started_secs)
+                .then_with(|| a.unit.cmp(&b.unit))
+        });
+        scopes
+    }
+
+    /// Only the launching uid or an admin may stop a scope.
+    pub fn authorize_kill(&self, unit: &str, uid: u32, is_admin: bool) -> Result<(), KillRefusal> {
+        let Some(scope) = self.active.get(unit) else {
+            return Err(KillRefusal::NotFound);
+        };
+        if is_admin || scope.uid == uid {
+            Ok(())
+        } else {
+            Err(KillRefusal::NotOwner)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn scope(unit: &str, uid: u32, started_secs: u64) -> ActiveScope {
+        ActiveScope {
+            unit: unit.into(),
+            target: PathBuf::from("/usr/bin/gparted"),
+            caller: PathBuf::from("/usr/bin/authctl"),
+            uid,
+            pid: 4242,
+            started_secs,
+        }
+    }
+
+    fn registry() -> ScopeRegistry {
+        let mut registry = ScopeRegistry::new();
+        registry.insert(scope("b.scope", 1000, 20));
+        registry.insert(scope("a.scope", 1000, 10));
+        registry.insert(scope("other.scope", 1001, 5));
+        registry
+    }
+
+    #[test]
+    fn unit_names_are_unique_and_carry_uid() {
+        let mut registry = ScopeRegistry::new();
+        let first = registry.next_unit(1000);
+        let second = registry.next_unit(1000);
+
+        assert_ne!(first, second);
+        assert!(first.starts_with("authd-1000-"));
+        assert!(first.ends_with(".scope"));
+    }
+
+    #[test]
+    fn list_shows_own_scopes_sorted_by_start() {
+        let units: Vec<String> = registry()
+            .list_for(1000, false)
+            .into_iter()
+            .map(|scope| scope.unit)
+            .collect();
+        assert_eq!(units, vec!["a.scope", "b.scope"]);
+    }
+
+    #[test]
+    fn admins_list_every_scope() {
+        let units: Vec<String> = registry()
+            .list_for(1000, true)
+            .into_iter()
+            .map(|scope| scope.unit)
+            .collect();
+        assert_eq!(units, vec!["other.scope", "a.scope", "b.scope"]);
+    }
+
+    #[test]
+    fn removed_scopes_are_not_listed() {
+        let mut registry = registry();
+        assert!(registry.remove("a.scope").is_some());
+        assert!(registry.remove("a.scope").is_none());
+        assert_eq!(registry.list_for(1000, false).len(), 1);
+    }
+
+    #[test]
+    fn max_concurrent_counts_running_and_spawning_launches() {
+        let target = Path::new("/usr/bin/gparted");
+        let mut registry = ScopeRegistry::new();
+
+        let first = registry.reserve(1000, target, Some(1)).unwrap();
+        // Still spawning: the slot is taken
+        assert_eq!(registry.reserve(1001, target, Some(1)), None);
+        registry.insert(scope(&first, 1000, 10));
+        assert_eq!(registry.reserve(1001, target, Some(1)), None);
+        // Other targets aren't affected
+        assert!(
+            registry
+                .reserve(1001, Path::new("/usr/bin/id"), Some(1))
+                .is_some()
+        );
+
+        // Once it exits, the next launch may start
+        registry.remove(&first);
+        assert!(registry.reserve(1000, target, Some(1)).is_some());
+    }
+
+    #[test]
+    fn released_reservations_free_their_slot() {
+        let target = Path::new("/usr/bin/gparted");
+        let mut registry = ScopeRegistry::new();
+
+        let unit = registry.reserve(1000, target, Some(1)).unwrap();
+        registry.release(&unit);
+        assert!(registry.reserve(1000, target, Some(1)).is_some());
+    }
+
+    #[test]
+    fn kill_is_limited_to_owner_or_admin() {
+        let registry = registry();
+
+        assert_eq!(registry.authorize_kill("a.scope", 1000, false), Ok(()));
+        assert_eq!(
+            registry.authorize_kill("other.scope", 1000, false),
+            Err(KillRefusal::NotOwner)
+        );
+        assert_eq!(registry.authorize_kill("other.scope", 1000, true), Ok(()));
+        assert_eq!(
+            registry.authorize_kill("missing.scope", 0, true),
+            Err(KillRefusal::NotFound)
+        );
+    }
+}
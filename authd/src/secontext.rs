@@ -0,0 +1,79 @@
+//! The SELinux context a caller runs in, for rules' `allow_contexts`
+//!
+//! Read once, when the connection is accepted, alongside the caller's
+//! groups. peercred-ipc hands authd the peer's credentials but not its
+//! socket, so the label is the process's own from
+//! `/proc/<pid>/attr/current`, which is what `SO_PEERSEC` reports for a
+//! peer that hasn't changed context since connecting. As with the groups,
+//! the process must still belong to the peer's uid afterwards, so a
+//! recycled pid isn't taken for the caller.
+//!
+//! Without the `selinux` feature no context is ever known, and rules with
+//! `allow_contexts` admit nobody.
+
+use peercred_ipc::CallerInfo;
+
+/// The caller's context; None if authd was built without `selinux`, the
+/// system doesn't run it, or the process is gone
+#[cfg(feature = "selinux")]
+pub fn of_caller(caller: &CallerInfo) -> Option<String> {
+    let label = std::fs::read(format!("/proc/{}/attr/current", caller.pid)).ok()?;
+    let owner = crate::groups::process_owner(caller.pid).ok()?;
+    if owner.0 != caller.uid {
+        return None;
+    }
+    parse_label(&label)
+}
+
+#[cfg(not(feature = "selinux"))]
+pub fn of_caller(_caller: &CallerInfo) -> Option<String> {
+    None
+}
+
+/// A label as the kernel writes it: text, maybe NUL- or newline-terminated.
+/// Without an LSM providing one it reads empty, or `unconfined` under
+/// AppArmor, which is no SELinux context.
+#[cfg_attr(not(feature = "selinux"), allow(dead_code))]
+fn parse_label(raw: &[u8]) -> Option<String> {
+    let label = std::str::from_utf8(raw).ok()?;
+    let label = label.trim_end_matches(['\0', '\n']);
+    let fields = label.split(':').count();
+    (fields >= 3 && !label.contains(char::is_whitespace)).then(|| label.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_lose_their_terminator() {
+        assert_eq!(
+            parse_label(b"unconfined_u:unconfined_r:unconfined_t:s0-s0:c0.c1023\0"),
+            Some("unconfined_u:unconfined_r:unconfined_t:s0-s0:c0.c1023".into())
+        );
+        assert_eq!(
+            parse_label(b"system_u:system_r:sshd_t:s0\n"),
+            Some("system_u:system_r:sshd_t:s0".into())
+        );
+    }
+
+    #[test]
+    fn anything_but_a_selinux_context_is_none() {
+        assert_eq!(parse_label(b""), None);
+        assert_eq!(parse_label(b"unconfined\n"), None);
+        assert_eq!(parse_label(b"/usr/bin/firefox (enforce)\n"), None);
+        assert_eq!(parse_label(b"user_u:\xff:user_t:s0"), None);
+    }
+
+    #[cfg(not(feature = "selinux"))]
+    #[test]
+    fn without_the_feature_no_context_is_known() {
+        let own = CallerInfo {
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+            pid: std::process::id(),
+            exe: std::path::PathBuf::from("/usr/bin/authsudo"),
+        };
+        assert_eq!(of_caller(&own), None);
+    }
+}
@@ -0,0 +1,144 @@
+//! Asking for a second person under the two-person rule
+//!
+//! authsudo only collects the approver's name and password from the
+//! terminal; authd checks them, so a setuid binary never verifies passwords.
+//! Before the approver types anything they're shown who is asking, for
+//! what, and why: the requester's reason travels with the approval and is
+//! audited alongside it.
+
+#[cfg(not(coverage))]
+use authd_protocol::Approval;
+#[cfg(not(coverage))]
+use std::fs::{File, OpenOptions};
+#[cfg(not(coverage))]
+use std::io::{BufRead, BufReader, Write};
+#[cfg(not(coverage))]
+use std::os::fd::AsRawFd;
+
+/// First line shown before asking for the approver
+pub fn intro(groups: &[String]) -> String {
+    let groups = match groups {
+        [group] => format!("group {}", group),
+        _ => format!("groups {}", groups.join(", ")),
+    };
+    format!(
+        "This command needs approval by another member of {}.",
+        groups
+    )
+}
+
+/// What the approver reads before approving: who asks to run what, and why
+pub fn briefing(requester: &str, command: &str, reason: Option<&str>) -> String {
+    let reason = match reason {
+        Some(reason) => format!("Reason: {}", reason),
+        None => "No reason given.".into(),
+    };
+    format!("{} asks to run: {}\n{}", requester, command, reason)
+}
+
+/// Ask on the controlling terminal for the requester's reason, then show
+/// the approver the request and ask who they are, and for their password
+#[cfg(not(coverage))]
+pub fn prompt(groups: &[String], requester: &str, command: &str) -> Result<Approval, String> {
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .map_err(|e| format!("approval needs a terminal: {}", e))?;
+    writeln!(tty, "{}", intro(groups)).map_err(|e| e.to_string())?;
+
+    write!(tty, "Reason for the approver: ").map_err(|e| e.to_string())?;
+    let reason = Some(read_line(&tty)?).filter(|reason| !reason.is_empty());
+    writeln!(tty, "\n{}", briefing(requester, command, reason.as_deref()))
+        .map_err(|e| e.to_string())?;
+
+    write!(tty, "Approver: ").map_err(|e| e.to_string())?;
+    let user = read_line(&tty)?;
+    if user.is_empty() {
+        return Err("no approver given".into());
+    }
+    // authd refuses it too; saying so here spares typing a password
+    if user == requester {
+        return Err("the approver must be someone other than the requester".into());
+    }
+
+    write!(tty, "Password for {}: ", user).map_err(|e| e.to_string())?;
+    let password = without_echo(&tty, || read_line(&tty))?;
+    writeln!(tty).map_err(|e| e.to_string())?;
+    Ok(Approval {
+        user,
+        password,
+        reason,
+    })
+}
+
+/// Ask on the controlling terminal for the requester's own password, for an
+/// authd without a dialog (`headless_confirm = "require_auth"`)
+#[cfg(not(coverage))]
+pub fn prompt_password() -> Result<String, String> {
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .map_err(|e| format!("password entry needs a terminal: {}", e))?;
+    write!(tty, "[authsudo] password: ").map_err(|e| e.to_string())?;
+    let password = without_echo(&tty, || read_line(&tty))?;
+    writeln!(tty).map_err(|e| e.to_string())?;
+    Ok(password)
+}
+
+#[cfg(not(coverage))]
+fn read_line(tty: &File) -> Result<String, String> {
+    let mut line = String::new();
+    BufReader::new(tty)
+        .read_line(&mut line)
+        .map_err(|e| e.to_string())?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Run `read` with terminal echo off, restoring it even if reading fails
+#[cfg(not(coverage))]
+fn without_echo<T>(tty: &File, read: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    let fd = tty.as_raw_fd();
+    let mut saved = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(fd, &mut saved) } != 0 {
+        return Err("cannot read terminal settings".into());
+    }
+    let mut quiet = saved;
+    quiet.c_lflag &= !libc::ECHO;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &quiet) } != 0 {
+        return Err("cannot turn off terminal echo".into());
+    }
+    let result = read();
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &saved) };
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intro_names_the_required_groups() {
+        assert_eq!(
+            intro(&["security".into()]),
+            "This command needs approval by another member of group security."
+        );
+        assert_eq!(
+            intro(&["release".into(), "security".into()]),
+            "This command needs approval by another member of groups release, security."
+        );
+    }
+
+    #[test]
+    fn briefing_tells_the_approver_who_asks_and_why() {
+        assert_eq!(
+            briefing("alice", "'/usr/bin/poweroff'", Some("kernel update")),
+            "alice asks to run: '/usr/bin/poweroff'\nReason: kernel update"
+        );
+        assert_eq!(
+            briefing("uid 4242", "'/usr/bin/poweroff'", None),
+            "uid 4242 asks to run: '/usr/bin/poweroff'\nNo reason given."
+        );
+    }
+}
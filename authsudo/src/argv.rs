@@ -0,0 +1,273 @@
+//! Checks on the command line before policy sees it
+//!
+//! Rules match the target program, so everything that decides which program
+//! runs is checked and put in one shape first. Refused outright:
+//!
+//! - an empty command name (`authsudo ''`), which PATH lookup would turn into
+//!   a directory
+//! - NUL bytes in any argument: exec would cut the argument short, so what
+//!   runs would differ from what was checked
+//! - a command name containing `=`: sudo reads `VAR=value` there as an
+//!   environment assignment, authsudo doesn't support those, and running a
+//!   program by that name is never what was meant
+//!
+//! The resolved target's directory is canonicalized (symlinks, `.`, `..`),
+//! so `/usr/sbin/x` on a merged-`/usr` system is checked as `/usr/bin/x`,
+//! whichever spelling the caller used. A target that is itself a symlink is
+//! then followed to the program it runs, and policy is checked against that:
+//! a user-owned `/usr/bin/allowed -> /bin/sh` would otherwise run a shell
+//! under `allowed`'s rule. Every link on the way must be root-owned. The
+//! name the program was invoked by is kept for `argv[0]`, which multi-call
+//! binaries like busybox pick their behavior from, if a rule's `allow_argv0`
+//! admits it.
+//!
+//! With `-s`/`-i` the command is handed to the shell as one string, where
+//! `VAR=value cmd` is fine, so only NUL bytes are refused there.
+//!
+//! `--help`/`--version` skip policy only as the sole argument. Anywhere else
+//! the program may not treat them as options at all (`sh -c '...' --help`).
+
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Arguments that bypass auth (harmless info commands)
+const BYPASS_ARGS: &[&str] = &["--help", "-h", "--version", "-V"];
+
+/// Links followed before giving up, as the kernel's `MAXSYMLINKS`
+const MAX_LINK_HOPS: usize = 40;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgvError {
+    EmptyCommand,
+    /// Argument `index` (0 is the command) contains a NUL byte
+    NulByte(usize),
+    /// The command name looks like a `VAR=value` assignment
+    Assignment(OsString),
+    /// A symlink on the way to the target isn't root-owned
+    UntrustedLink(PathBuf),
+    /// A symlink that can't be read, or a chain too long to be a real one
+    UnresolvedLink(PathBuf),
+}
+
+impl fmt::Display for ArgvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyCommand => f.write_str("empty command name"),
+            Self::NulByte(0) => f.write_str("command name contains a NUL byte"),
+            Self::NulByte(index) => write!(f, "argument {} contains a NUL byte", index),
+            Self::Assignment(name) => write!(
+                f,
+                "{} looks like an environment assignment, which authsudo doesn't support",
+                name.to_string_lossy()
+            ),
+            Self::UntrustedLink(link) => {
+                write!(f, "{} is a symlink not owned by root", link.display())
+            }
+            Self::UnresolvedLink(link) => {
+                write!(f, "cannot resolve symlink {}", link.display())
+            }
+        }
+    }
+}
+
+/// Refuse command lines that can't be checked as they'd run
+pub fn validate(command: &[OsString]) -> Result<(), ArgvError> {
+    reject_nul(command)?;
+    let Some(name) = command.first() else {
+        return Ok(());
+    };
+    if name.is_empty() {
+        return Err(ArgvError::EmptyCommand);
+    }
+    if name.as_bytes().contains(&b'=') {
+        return Err(ArgvError::Assignment(name.clone()));
+    }
+    Ok(())
+}
+
+/// Refuse NUL bytes, which exec can't pass on
+pub fn reject_nul(command: &[OsString]) -> Result<(), ArgvError> {
+    match command.iter().position(|arg| arg.as_bytes().contains(&0)) {
+        Some(index) => Err(ArgvError::NulByte(index)),
+        None => Ok(()),
+    }
+}
+
+/// `target` with its directory canonicalized and its file name kept, or
+/// unchanged if the directory can't be resolved
+pub fn canonical_target(target: &Path) -> PathBuf {
+    let (Some(dir), Some(name)) = (target.parent(), target.file_name()) else {
+        return target.to_path_buf();
+    };
+    match std::fs::canonicalize(dir) {
+        Ok(dir) => dir.join(name),
+        Err(_) => target.to_path_buf(),
+    }
+}
+
+/// The program `target` runs: followed through every symlink, each of which
+/// must be root-owned. `target` is expected to come from `canonical_target`.
+pub fn resolve_links(target: &Path) -> Result<PathBuf, ArgvError> {
+    resolve_links_owned_by(target, 0)
+}
+
+fn resolve_links_owned_by(target: &Path, owner: u32) -> Result<PathBuf, ArgvError> {
+    let mut path = target.to_path_buf();
+    for _ in 0..MAX_LINK_HOPS {
+        // Missing or unreadable: nothing to follow, exec reports it
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            return Ok(path);
+        };
+        if !metadata.file_type().is_symlink() {
+            return Ok(path);
+        }
+        if metadata.uid() != owner {
+            return Err(ArgvError::UntrustedLink(path));
+        }
+        let Ok(next) = fs::read_link(&path) else {
+            return Err(ArgvError::UnresolvedLink(path));
+        };
+        // A relative link is relative to its own directory
+        let next = match path.parent() {
+            Some(dir) => dir.join(next),
+            None => next,
+        };
+        path = canonical_target(&next);
+    }
+    Err(ArgvError::UnresolvedLink(target.to_path_buf()))
+}
+
+/// Whether the target's arguments only ask for help or a version
+pub fn is_info_only(args: &[OsString]) -> bool {
+    match args {
+        [arg] => BYPASS_ARGS
+            .iter()
+            .any(|bypass| arg.as_os_str() == OsStr::new(bypass)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn nul_bytes_are_rejected_anywhere() {
+        let command = vec![OsString::from("/usr/bin/rm"), OsString::from("a\0b")];
+        assert_eq!(validate(&command), Err(ArgvError::NulByte(1)));
+
+        let command = vec![OsString::from("/usr/bin/id\0-u")];
+        let error = validate(&command).unwrap_err();
+        assert_eq!(error.to_string(), "command name contains a NUL byte");
+    }
+
+    #[test]
+    fn empty_command_name_is_rejected() {
+        assert_eq!(validate(&args(&["", "-la"])), Err(ArgvError::EmptyCommand));
+    }
+
+    #[test]
+    fn assignments_are_not_commands() {
+        assert!(matches!(
+            validate(&args(&["PATH=/tmp", "id"])),
+            Err(ArgvError::Assignment(_))
+        ));
+        // Only the command name; arguments may contain anything else
+        assert_eq!(validate(&args(&["env", "FOO=bar", "-rf"])), Ok(()));
+        assert_eq!(validate(&[]), Ok(()));
+    }
+
+    #[test]
+    fn target_directory_is_canonicalized_and_name_kept() {
+        let dir = std::env::temp_dir().join(format!("authsudo-argv-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("real")).unwrap();
+        let link = dir.join("link");
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink(dir.join("real"), &link).unwrap();
+        let real = std::fs::canonicalize(dir.join("real")).unwrap();
+
+        assert_eq!(canonical_target(&link.join("tool")), real.join("tool"));
+        assert_eq!(
+            canonical_target(&dir.join("real/../link/./tool")),
+            real.join("tool")
+        );
+        // Nothing to resolve against: left alone
+        assert_eq!(
+            canonical_target(Path::new("/nonexistent/dir/tool")),
+            Path::new("/nonexistent/dir/tool")
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn links_are_followed_only_when_owned_by_root() {
+        let dir = std::env::temp_dir().join(format!("authsudo-links-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("bin")).unwrap();
+        let dir = std::fs::canonicalize(dir).unwrap();
+        let shell = dir.join("bin/sh");
+        std::fs::write(&shell, "").unwrap();
+        let allowed = dir.join("allowed");
+        std::os::unix::fs::symlink("bin/../bin/sh", &allowed).unwrap();
+        let hop = dir.join("hop");
+        std::os::unix::fs::symlink(&allowed, &hop).unwrap();
+        // Our own links stand in for root-owned ones, as seen by root
+        let us = unsafe { libc::getuid() };
+
+        assert_eq!(resolve_links_owned_by(&hop, us), Ok(shell.clone()));
+        assert_eq!(resolve_links_owned_by(&shell, us), Ok(shell.clone()));
+        // The same links owned by anyone else are refused at the first hop
+        assert_eq!(
+            resolve_links_owned_by(&hop, us.wrapping_add(1)),
+            Err(ArgvError::UntrustedLink(hop.clone()))
+        );
+        // A plain file needs no owner check
+        assert_eq!(
+            resolve_links_owned_by(&shell, us.wrapping_add(1)),
+            Ok(shell.clone())
+        );
+
+        let looped = dir.join("loop");
+        std::os::unix::fs::symlink(&looped, &looped).unwrap();
+        assert_eq!(
+            resolve_links_owned_by(&looped, us),
+            Err(ArgvError::UnresolvedLink(looped.clone()))
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn user_owned_links_are_not_followed_as_root() {
+        if unsafe { libc::getuid() } == 0 {
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("authsudo-user-link-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let link = dir.join("allowed");
+        std::os::unix::fs::symlink("/bin/sh", &link).unwrap();
+
+        let error = resolve_links(&link).unwrap_err();
+        assert_eq!(error, ArgvError::UntrustedLink(link.clone()));
+        assert!(
+            error
+                .to_string()
+                .ends_with("is a symlink not owned by root")
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn help_only_bypasses_as_the_sole_argument() {
+        assert!(is_info_only(&args(&["--help"])));
+        assert!(is_info_only(&args(&["-V"])));
+        assert!(!is_info_only(&args(&["-c", "rm -rf /", "--help"])));
+        assert!(!is_info_only(&args(&[])));
+    }
+}
@@ -0,0 +1,182 @@
+//! `min_binary_age_secs`: refuse freshly written target binaries
+//!
+//! An attacker able to write into a path a rule allows would otherwise get
+//! their binary run as root straight away. Both mtime and ctime count, since
+//! mtime can be backdated with `touch -d` but ctime can't be set at all. The
+//! binary must also be root-owned and not writable by group or others, or its
+//! age would say nothing about who last wrote it.
+
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The parts of a target's metadata the check looks at
+#[derive(Debug, Clone, Copy)]
+pub struct BinaryStat {
+    pub uid: u32,
+    pub mode: u32,
+    pub modified: SystemTime,
+    pub changed: SystemTime,
+}
+
+impl BinaryStat {
+    pub fn of(path: &Path) -> std::io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(Self {
+            uid: metadata.uid(),
+            mode: metadata.mode(),
+            modified: timestamp(metadata.mtime(), metadata.mtime_nsec()),
+            changed: timestamp(metadata.ctime(), metadata.ctime_nsec()),
+        })
+    }
+}
+
+fn timestamp(secs: i64, nsecs: i64) -> SystemTime {
+    let since_epoch = Duration::new(secs.max(0) as u64, nsecs.clamp(0, 999_999_999) as u32);
+    UNIX_EPOCH + since_epoch
+}
+
+/// Stat `path` and check it against `min_age`
+pub fn check_path(path: &Path, min_age: Duration) -> Result<(), String> {
+    let stat = BinaryStat::of(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    check(path, &stat, min_age, SystemTime::now())
+}
+
+pub fn check(
+    path: &Path,
+    stat: &BinaryStat,
+    min_age: Duration,
+    now: SystemTime,
+) -> Result<(), String> {
+    if stat.uid != 0 {
+        return Err(format!(
+            "{} is not owned by root; refusing under min_binary_age_secs",
+            path.display()
+        ));
+    }
+    if stat.mode & 0o022 != 0 {
+        return Err(format!(
+            "{} is writable by group or others; refusing under min_binary_age_secs",
+            path.display()
+        ));
+    }
+
+    let newest = stat.modified.max(stat.changed);
+    // A timestamp in the future counts as brand new
+    let age = now.duration_since(newest).unwrap_or(Duration::ZERO);
+    if age < min_age {
+        return Err(format!(
+            "{} was modified {}s ago; rules require it to be at least {}s old",
+            path.display(),
+            age.as_secs(),
+            min_age.as_secs()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    fn temp_binary(name: &str) -> std::path::PathBuf {
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("authsudo-age-{name}-{nonce}"));
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("tool");
+        File::create(&path).unwrap();
+        path
+    }
+
+    /// Real timestamps from `path`, with ownership and mode made acceptable
+    /// so only age is under test
+    fn root_owned(path: &Path) -> BinaryStat {
+        BinaryStat {
+            uid: 0,
+            mode: 0o100755,
+            ..BinaryStat::of(path).unwrap()
+        }
+    }
+
+    #[test]
+    fn freshly_written_binary_is_denied() {
+        let path = temp_binary("fresh");
+
+        let error = check(&path, &root_owned(&path), HOUR, SystemTime::now()).unwrap_err();
+        assert!(error.contains("at least 3600s old"), "{error}");
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn backdated_mtime_is_still_caught_by_ctime() {
+        let path = temp_binary("backdated");
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() - 24 * HOUR).unwrap();
+
+        let stat = root_owned(&path);
+        assert!(SystemTime::now().duration_since(stat.modified).unwrap() >= HOUR);
+        assert!(check(&path, &stat, HOUR, SystemTime::now()).is_err());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn old_binary_is_allowed() {
+        let path = temp_binary("old");
+        let stat = root_owned(&path);
+
+        let later = stat.modified.max(stat.changed) + 2 * HOUR;
+        assert_eq!(check(&path, &stat, HOUR, later), Ok(()));
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn future_timestamps_count_as_new() {
+        let now = SystemTime::now();
+        let stat = BinaryStat {
+            uid: 0,
+            mode: 0o100755,
+            modified: now + HOUR,
+            changed: now - 2 * HOUR,
+        };
+
+        assert!(check(Path::new("/usr/bin/tool"), &stat, HOUR, now).is_err());
+    }
+
+    #[test]
+    fn requires_root_ownership_and_no_foreign_write_access() {
+        let now = SystemTime::now();
+        let old = BinaryStat {
+            uid: 0,
+            mode: 0o100755,
+            modified: now - 2 * HOUR,
+            changed: now - 2 * HOUR,
+        };
+        let tool = Path::new("/usr/local/bin/tool");
+        assert_eq!(check(tool, &old, HOUR, now), Ok(()));
+
+        let user_owned = BinaryStat { uid: 1000, ..old };
+        assert!(
+            check(tool, &user_owned, HOUR, now)
+                .unwrap_err()
+                .contains("not owned by root")
+        );
+
+        for mode in [0o100775, 0o100757] {
+            let writable = BinaryStat { mode, ..old };
+            assert!(
+                check(tool, &writable, HOUR, now)
+                    .unwrap_err()
+                    .contains("writable by group or others")
+            );
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! `min_caller_age_secs`: refuse callers that were started moments ago
+//!
+//! A script that spawns a fresh shell just to run authsudo looks different
+//! from a user's long-lived terminal. The age of authsudo's immediate parent
+//! is taken from its `starttime` in `/proc/<pid>/stat`, in clock ticks since
+//! boot, and the boot time in `/proc/stat`. This is a heuristic: a patient
+//! attacker can simply wait, so it only narrows the window.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// `starttime` from `/proc/<pid>/stat`, in clock ticks since boot. `comm`
+/// may contain spaces and parentheses, so fields are counted from the last
+/// `)`; `starttime` is the 22nd field overall.
+pub fn starttime_ticks(stat: &str) -> Option<u64> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Boot time from the `btime` line of `/proc/stat`, in seconds since the
+/// epoch
+pub fn boot_time(proc_stat: &str) -> Option<u64> {
+    proc_stat
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// How long ago a process started `starttime` ticks after a boot at
+/// `boot_time`. A start time in the future counts as brand new.
+pub fn age(starttime: u64, ticks_per_sec: u64, boot_time: u64, now: SystemTime) -> Duration {
+    let since_boot = Duration::from_secs(starttime / ticks_per_sec)
+        + Duration::from_secs(starttime % ticks_per_sec) / ticks_per_sec as u32;
+    let started = UNIX_EPOCH + Duration::from_secs(boot_time) + since_boot;
+    now.duration_since(started).unwrap_or(Duration::ZERO)
+}
+
+/// Age of process `pid`, or None if it can't be worked out
+fn process_age(pid: u32) -> Option<Duration> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let proc_stat = std::fs::read_to_string("/proc/stat").ok()?;
+    let ticks_per_sec = u64::try_from(unsafe { libc::sysconf(libc::_SC_CLK_TCK) }).ok()?;
+    if ticks_per_sec == 0 {
+        return None;
+    }
+    Some(age(
+        starttime_ticks(&stat)?,
+        ticks_per_sec,
+        boot_time(&proc_stat)?,
+        SystemTime::now(),
+    ))
+}
+
+/// Check authsudo's parent against `min_age`
+pub fn check_parent(min_age: Duration) -> Result<(), String> {
+    let ppid = unsafe { libc::getppid() } as u32;
+    check(ppid, process_age(ppid), min_age)
+}
+
+/// A caller whose age is unknown is refused: the rule asked for proof
+pub fn check(pid: u32, age: Option<Duration>, min_age: Duration) -> Result<(), String> {
+    let Some(age) = age else {
+        return Err(format!(
+            "can't tell when caller {} started; refusing under min_caller_age_secs",
+            pid
+        ));
+    };
+    if age < min_age {
+        return Err(format!(
+            "caller {} started {}s ago; rules require it to be at least {}s old",
+            pid,
+            age.as_secs(),
+            min_age.as_secs()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOOT: u64 = 1_700_000_000;
+
+    #[test]
+    fn starttime_is_found_past_awkward_process_names() {
+        let stat = "4242 (my (odd) shell) S 1 4242 4242 34816 4242 4194304 \
+                    1062 0 0 0 2 1 0 0 20 0 1 0 123456 9220096 1213 \
+                    18446744073709551615 1 1 0 0 0 0 65536 4 65538 0 0 0 17 3 0 0";
+        assert_eq!(starttime_ticks(stat), Some(123456));
+        assert_eq!(starttime_ticks("4242 (truncated) S 1 2 3"), None);
+        assert_eq!(starttime_ticks("garbage"), None);
+    }
+
+    #[test]
+    fn boot_time_comes_from_btime() {
+        let proc_stat = "cpu  1 2 3 4\nintr 5\nctxt 6\nbtime 1700000000\nprocesses 7\n";
+        assert_eq!(boot_time(proc_stat), Some(BOOT));
+        assert_eq!(boot_time("cpu  1 2 3 4\n"), None);
+    }
+
+    #[test]
+    fn age_counts_from_boot_plus_starttime() {
+        // Started 10.5s after boot at 100 ticks per second
+        let now = UNIX_EPOCH + Duration::from_secs(BOOT + 60);
+        assert_eq!(age(1050, 100, BOOT, now), Duration::from_millis(49_500));
+        assert_eq!(age(0, 100, BOOT, now), Duration::from_secs(60));
+        // Clock stepped back past the start: brand new
+        assert_eq!(age(9000, 100, BOOT, now), Duration::ZERO);
+    }
+
+    #[test]
+    fn young_or_unknown_callers_are_refused() {
+        let min_age = Duration::from_secs(5);
+        assert_eq!(check(42, Some(Duration::from_secs(5)), min_age), Ok(()));
+        let error = check(42, Some(Duration::from_secs(1)), min_age).unwrap_err();
+        assert_eq!(
+            error,
+            "caller 42 started 1s ago; rules require it to be at least 5s old"
+        );
+        assert!(check(42, None, min_age).is_err());
+    }
+
+    #[test]
+    fn this_process_has_a_readable_age() {
+        assert!(process_age(std::process::id()).is_some());
+    }
+}
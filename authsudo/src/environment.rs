@@ -0,0 +1,154 @@
+//! The environment a target starts in under `clean_env`
+//!
+//! Like sudo's `env_reset`: of the caller's variables only the terminal and
+//! locale ones and those named in `keep_env` carry over. `PATH` comes from
+//! `command_search_path`, and `HOME`, `USER`, `LOGNAME` and `SHELL` describe
+//! the target user, whatever the caller had. Locale variables holding a `/`
+//! are dropped, so they can't point the target's libc at files of the
+//! caller's choosing.
+
+use crate::shell::{Account, LOGIN_PATH};
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+/// Terminal variables carried over from the caller's environment
+const TERMINAL_KEEP: &[&str] = &["TERM", "COLORTERM"];
+
+/// The target's complete environment, sorted by name
+pub fn clean(
+    search_path: &[PathBuf],
+    account: Option<&Account>,
+    keep_env: &[String],
+    inherited: impl IntoIterator<Item = (OsString, OsString)>,
+) -> Vec<(OsString, OsString)> {
+    let mut env: BTreeMap<OsString, OsString> = inherited
+        .into_iter()
+        .filter(|(name, value)| kept(name, value, keep_env))
+        .collect();
+    let path = env::join_paths(search_path).unwrap_or_else(|_| LOGIN_PATH.into());
+    env.insert("PATH".into(), path);
+    if let Some(account) = account {
+        env.insert("HOME".into(), account.home.clone().into_os_string());
+        env.insert("USER".into(), account.name.clone());
+        env.insert("LOGNAME".into(), account.name.clone());
+        env.insert("SHELL".into(), account.shell.clone().into_os_string());
+    }
+    env.into_iter().collect()
+}
+
+fn kept(name: &OsStr, value: &OsStr, keep_env: &[String]) -> bool {
+    if keep_env.iter().any(|keep| name == keep.as_str()) {
+        return true;
+    }
+    if name.as_bytes().starts_with(b"LC_") || name == "LANG" || name == "LANGUAGE" {
+        return !value.as_bytes().contains(&b'/');
+    }
+    TERMINAL_KEEP.iter().any(|&keep| name == keep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account() -> Account {
+        Account {
+            name: "root".into(),
+            home: PathBuf::from("/root"),
+            shell: PathBuf::from("/usr/bin/zsh"),
+        }
+    }
+
+    fn caller_env() -> Vec<(OsString, OsString)> {
+        [
+            ("HOME", "/home/alice"),
+            ("PATH", "/home/alice/bin:/usr/bin"),
+            ("TERM", "xterm-256color"),
+            ("LANG", "en_US.UTF-8"),
+            ("LC_TIME", "de_DE.UTF-8"),
+            ("LD_PRELOAD", "/home/alice/evil.so"),
+            ("PYTHONPATH", "/home/alice/lib"),
+            ("EDITOR", "vim"),
+            ("http_proxy", "http://proxy:3128"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.into(), value.into()))
+        .collect()
+    }
+
+    fn env_of(env: &[(OsString, OsString)]) -> Vec<(&str, &str)> {
+        env.iter()
+            .map(|(name, value)| (name.to_str().unwrap(), value.to_str().unwrap()))
+            .collect()
+    }
+
+    fn search_path() -> Vec<PathBuf> {
+        vec![PathBuf::from("/usr/sbin"), PathBuf::from("/usr/bin")]
+    }
+
+    #[test]
+    fn only_the_baseline_survives_a_clean_environment() {
+        let env = clean(&search_path(), Some(&account()), &[], caller_env());
+        assert_eq!(
+            env_of(&env),
+            vec![
+                ("HOME", "/root"),
+                ("LANG", "en_US.UTF-8"),
+                ("LC_TIME", "de_DE.UTF-8"),
+                ("LOGNAME", "root"),
+                ("PATH", "/usr/sbin:/usr/bin"),
+                ("SHELL", "/usr/bin/zsh"),
+                ("TERM", "xterm-256color"),
+                ("USER", "root"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_env_adds_to_the_baseline() {
+        let keep = ["EDITOR".to_string(), "http_proxy".into(), "MISSING".into()];
+        let env = clean(&search_path(), Some(&account()), &keep, caller_env());
+        assert_eq!(
+            env_of(&env),
+            vec![
+                ("EDITOR", "vim"),
+                ("HOME", "/root"),
+                ("LANG", "en_US.UTF-8"),
+                ("LC_TIME", "de_DE.UTF-8"),
+                ("LOGNAME", "root"),
+                ("PATH", "/usr/sbin:/usr/bin"),
+                ("SHELL", "/usr/bin/zsh"),
+                ("TERM", "xterm-256color"),
+                ("USER", "root"),
+                ("http_proxy", "http://proxy:3128"),
+            ]
+        );
+        // Kept names still can't override what describes the target user
+        let keep = ["HOME".to_string(), "PATH".into()];
+        let env = clean(&search_path(), Some(&account()), &keep, caller_env());
+        assert!(env_of(&env).contains(&("HOME", "/root")));
+        assert!(env_of(&env).contains(&("PATH", "/usr/sbin:/usr/bin")));
+    }
+
+    #[test]
+    fn locales_naming_paths_are_dropped() {
+        let inherited = [
+            (OsString::from("LANG"), OsString::from("../../tmp/evil")),
+            ("LC_ALL".into(), "/tmp/locale".into()),
+            ("LC_CTYPE".into(), "C.UTF-8".into()),
+        ];
+        let env = clean(&search_path(), None, &[], inherited);
+        assert_eq!(
+            env_of(&env),
+            vec![("LC_CTYPE", "C.UTF-8"), ("PATH", "/usr/sbin:/usr/bin")]
+        );
+    }
+
+    #[test]
+    fn an_unjoinable_search_path_falls_back_to_the_login_path() {
+        let env = clean(&[PathBuf::from("/opt/a:b")], None, &[], []);
+        assert_eq!(env_of(&env), vec![("PATH", LOGIN_PATH)]);
+    }
+}
@@ -0,0 +1,193 @@
+//! `authsudo --repeat`: the last command each user ran through authsudo
+//!
+//! One file per user in [`HISTORY_DIR`], replaced by authsudo (still root)
+//! right before it execs a command, and handed to that user so they can read
+//! it. Nothing in it is trusted: `--repeat` only fills in the command line,
+//! which then goes through policy like one typed out.
+
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+/// Where the per-user history files live
+#[cfg_attr(coverage, allow(dead_code))]
+pub const HISTORY_DIR: &str = "/var/lib/authd/history";
+
+/// A command as authsudo last ran it for someone
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The user it ran as (`-u`)
+    pub run_as: u32,
+    pub target: PathBuf,
+    pub args: Vec<OsString>,
+}
+
+impl Entry {
+    /// NUL-terminated fields: the run-as uid, the target, then each
+    /// argument. Arguments can't contain NUL, and needn't be UTF-8.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let run_as = self.run_as.to_string();
+        let fields = [OsStr::new(&run_as), self.target.as_os_str()]
+            .into_iter()
+            .chain(self.args.iter().map(OsString::as_os_str));
+        for field in fields {
+            bytes.extend_from_slice(field.as_bytes());
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let fields = bytes.strip_suffix(&[0])?.split(|&b| b == 0);
+        let mut fields = fields.map(|field| OsString::from_vec(field.to_vec()));
+        let run_as = fields.next()?.to_str()?.parse().ok()?;
+        let target = PathBuf::from(fields.next()?);
+        if target.as_os_str().is_empty() {
+            return None;
+        }
+        Some(Self {
+            run_as,
+            target,
+            args: fields.collect(),
+        })
+    }
+
+    /// The command line that runs it again: `-u #<uid> -- <target> <args...>`
+    #[cfg_attr(coverage, allow(dead_code))]
+    pub fn to_args(&self) -> Vec<OsString> {
+        let mut args = vec![
+            OsString::from("-u"),
+            OsString::from(format!("#{}", self.run_as)),
+            OsString::from("--"),
+            self.target.clone().into_os_string(),
+        ];
+        args.extend(self.args.iter().cloned());
+        args
+    }
+}
+
+fn path_for(dir: &Path, uid: u32) -> PathBuf {
+    dir.join(uid.to_string())
+}
+
+/// Replace `uid`'s last command in `dir`, as a file only they (and root) can
+/// read. The directory stays root's, so they can't swap the file out.
+#[cfg_attr(coverage, allow(dead_code))]
+pub fn save(dir: &Path, uid: u32, gid: u32, entry: &Entry) -> io::Result<()> {
+    DirBuilder::new().recursive(true).mode(0o755).create(dir)?;
+    let path = path_for(dir, uid);
+    let temp = dir.join(format!(".{}.tmp", uid));
+    // Left over from an interrupted save; only root writes here
+    if let Err(e) = fs::remove_file(&temp) {
+        if e.kind() != ErrorKind::NotFound {
+            return Err(e);
+        }
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(&temp)?;
+    file.write_all(&entry.to_bytes())?;
+    std::os::unix::fs::fchown(&file, Some(uid), Some(gid))?;
+    file.sync_all()?;
+    fs::rename(&temp, &path)
+}
+
+/// `uid`'s last command in `dir`; None if they have none
+#[cfg_attr(coverage, allow(dead_code))]
+pub fn load(dir: &Path, uid: u32) -> io::Result<Option<Entry>> {
+    let bytes = match fs::read(path_for(dir, uid)) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Entry::from_bytes(&bytes)
+        .map(Some)
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "corrupt history file"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    fn entry() -> Entry {
+        Entry {
+            run_as: 1234,
+            target: PathBuf::from("/usr/bin/systemctl"),
+            args: vec![
+                OsString::from("restart"),
+                OsString::from(""),
+                OsStr::from_bytes(b"caf\xe9.service").to_os_string(),
+            ],
+        }
+    }
+
+    #[test]
+    fn entries_survive_serialization() {
+        let entry = entry();
+        assert_eq!(Entry::from_bytes(&entry.to_bytes()), Some(entry));
+
+        let bare = Entry {
+            run_as: 0,
+            target: PathBuf::from("/usr/bin/id"),
+            args: Vec::new(),
+        };
+        assert_eq!(bare.to_bytes(), b"0\0/usr/bin/id\0");
+        assert_eq!(Entry::from_bytes(&bare.to_bytes()), Some(bare));
+    }
+
+    #[test]
+    fn damaged_entries_are_refused() {
+        let cases: [&[u8]; 5] = [
+            b"",
+            b"0\0/usr/bin/id",
+            b"root\0/usr/bin/id\0",
+            b"0\0\0",
+            b"0\0",
+        ];
+        for bytes in cases {
+            assert_eq!(Entry::from_bytes(bytes), None, "{bytes:?}");
+        }
+    }
+
+    #[test]
+    fn repeating_runs_the_same_command_as_the_same_user() {
+        let args = entry().to_args();
+        assert_eq!(&args[..3], ["-u", "#1234", "--"]);
+        assert_eq!(args[3], "/usr/bin/systemctl");
+        assert_eq!(args[4..], entry().args);
+    }
+
+    #[test]
+    fn saved_history_is_readable_by_its_user_alone() {
+        let dir = std::env::temp_dir().join(format!("authsudo-history-{}", std::process::id()));
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        assert_eq!(load(&dir, uid).unwrap(), None);
+
+        save(&dir, uid, gid, &entry()).unwrap();
+        let metadata = fs::metadata(path_for(&dir, uid)).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert_eq!((metadata.uid(), metadata.gid()), (uid, gid));
+        assert_eq!(load(&dir, uid).unwrap(), Some(entry()));
+
+        // The next command replaces the last
+        let next = Entry {
+            run_as: 0,
+            target: PathBuf::from("/usr/bin/id"),
+            args: Vec::new(),
+        };
+        save(&dir, uid, gid, &next).unwrap();
+        assert_eq!(load(&dir, uid).unwrap(), Some(next));
+
+        fs::write(path_for(&dir, uid), b"garbage").unwrap();
+        assert!(load(&dir, uid).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,337 @@
+//! `authsudo --suggest-policy`: draft rules from requests no rule matched
+//!
+//! With `learn_denials` on, authsudo (still root) appends each request that
+//! was refused only because no rule covered its target to [`LEARNING_LOG`]:
+//! who asked, for what, and from which program. `--suggest-policy` turns the
+//! log into candidate rules, one per target, for an admin to review. Nothing
+//! is ever installed from it: the output is a draft on stdout.
+
+use crate::sudoers::{toml_list, toml_string};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+/// Where denials are logged
+#[cfg_attr(coverage, allow(dead_code))]
+pub const LEARNING_LOG: &str = "/var/lib/authd/learning.log";
+
+/// Past this size the log takes no more entries, so callers can't fill the
+/// disk with made-up commands
+pub const MAX_LOG_BYTES: u64 = 1 << 20;
+
+#[cfg_attr(coverage, allow(dead_code))]
+pub const USAGE: &str = "usage: authsudo --suggest-policy [learning-log]";
+
+/// A request refused because no rule matched its target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Denial {
+    pub uid: u32,
+    pub target: PathBuf,
+    /// The program that ran authsudo, if it could be read
+    pub caller: Option<PathBuf>,
+}
+
+impl Denial {
+    /// Three NUL-terminated fields, the uid, the target and the caller
+    /// (empty if unknown), then a newline. Paths needn't be UTF-8 and can't
+    /// contain NUL, and both are absolute, so only a record's end is a NUL
+    /// followed by a newline.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.uid.to_string().into_bytes();
+        bytes.push(0);
+        bytes.extend_from_slice(self.target.as_os_str().as_bytes());
+        bytes.push(0);
+        if let Some(caller) = &self.caller {
+            bytes.extend_from_slice(caller.as_os_str().as_bytes());
+        }
+        bytes.extend_from_slice(RECORD_END);
+        bytes
+    }
+
+    fn from_bytes(record: &[u8]) -> Option<Self> {
+        let mut fields = record.split(|&b| b == 0);
+        let uid = std::str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+        let target = fields.next().filter(|target| target.starts_with(b"/"))?;
+        let caller = fields.next()?;
+        if fields.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            uid,
+            target: PathBuf::from(OsString::from_vec(target.to_vec())),
+            caller: (!caller.is_empty())
+                .then(|| PathBuf::from(OsString::from_vec(caller.to_vec()))),
+        })
+    }
+}
+
+const RECORD_END: &[u8] = b"\0\n";
+
+/// Append `denial` to the log at `path`, readable by root only. A full log
+/// is left as it is.
+#[cfg_attr(coverage, allow(dead_code))]
+pub fn record(path: &Path, denial: &Denial) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        DirBuilder::new().recursive(true).mode(0o755).create(dir)?;
+    }
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)?;
+    if file.metadata()?.len() >= MAX_LOG_BYTES {
+        return Ok(());
+    }
+    // One write, so concurrent appends don't interleave
+    file.write_all(&denial.to_bytes())
+}
+
+/// Every denial in the log at `path`; none if there is no log yet. Records
+/// that don't parse, such as one cut short by a crash mid-write, are skipped.
+pub fn load(path: &Path) -> io::Result<Vec<Denial>> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut rest = bytes.as_slice();
+    let mut denials = Vec::new();
+    while let Some(end) = rest.windows(2).position(|window| window == RECORD_END) {
+        denials.extend(Denial::from_bytes(&rest[..end]));
+        rest = &rest[end + RECORD_END.len()..];
+    }
+    Ok(denials)
+}
+
+/// A candidate rule for one target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    pub target: PathBuf,
+    /// How many logged requests it would have covered
+    pub requests: usize,
+    /// Users who asked for it, by name
+    pub users: Vec<String>,
+    /// Uids that asked for it but have no name to allow
+    pub unnamed: Vec<u32>,
+    /// Programs the requests came from, for the admin to judge; never
+    /// suggested as `allow_callers`, which would admit anyone they run for
+    pub callers: Vec<PathBuf>,
+}
+
+/// One suggestion per target in `denials`, by target, naming each uid
+/// with `user_name`
+pub fn suggest(denials: &[Denial], user_name: impl Fn(u32) -> Option<String>) -> Vec<Suggestion> {
+    let mut by_target: BTreeMap<&Path, Vec<&Denial>> = BTreeMap::new();
+    for denial in denials {
+        by_target.entry(&denial.target).or_default().push(denial);
+    }
+    by_target
+        .into_iter()
+        .map(|(target, denials)| {
+            let mut uids: Vec<u32> = denials.iter().map(|denial| denial.uid).collect();
+            uids.sort_unstable();
+            uids.dedup();
+            let mut users = Vec::new();
+            let mut unnamed = Vec::new();
+            for uid in uids {
+                match user_name(uid) {
+                    Some(name) => users.push(name),
+                    None => unnamed.push(uid),
+                }
+            }
+            users.sort();
+            users.dedup();
+            let mut callers: Vec<PathBuf> = denials
+                .iter()
+                .filter_map(|denial| denial.caller.clone())
+                .collect();
+            callers.sort();
+            callers.dedup();
+            Suggestion {
+                target: target.to_path_buf(),
+                requests: denials.len(),
+                users,
+                unnamed,
+                callers,
+            }
+        })
+        .collect()
+}
+
+/// Render suggestions as a commented policy draft. Every rule asks for
+/// confirmation; loosening that is the admin's call.
+pub fn to_toml(suggestions: &[Suggestion]) -> String {
+    let mut out = String::from(
+        "# Suggested by authsudo --suggest-policy from requests no rule matched.\n\
+         # Nothing here is applied: review each rule before installing it.\n\n",
+    );
+    for suggestion in suggestions {
+        let times = if suggestion.requests == 1 {
+            "time"
+        } else {
+            "times"
+        };
+        out.push_str(&format!("# refused {} {times}\n", suggestion.requests));
+        if !suggestion.callers.is_empty() {
+            let callers: Vec<String> = suggestion
+                .callers
+                .iter()
+                .map(|caller| caller.display().to_string())
+                .collect();
+            out.push_str(&format!("# called from {}\n", callers.join(", ")));
+        }
+        for uid in &suggestion.unnamed {
+            out.push_str(&format!("# also asked by uid {uid}, which has no name\n"));
+        }
+        // A rule with nobody to admit would be no suggestion at all
+        let comment = if suggestion.users.is_empty() {
+            "# "
+        } else {
+            ""
+        };
+        out.push_str(&format!("{comment}[[rules]]\n"));
+        out.push_str(&format!(
+            "{comment}target = {}\n",
+            toml_string(&suggestion.target.to_string_lossy())
+        ));
+        out.push_str(&format!(
+            "{comment}allow_users = {}\n",
+            toml_list(&suggestion.users)
+        ));
+        out.push_str(&format!("{comment}auth = \"confirm\"\n\n"));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn denial(uid: u32, target: &str, caller: Option<&str>) -> Denial {
+        Denial {
+            uid,
+            target: PathBuf::from(target),
+            caller: caller.map(PathBuf::from),
+        }
+    }
+
+    fn names(uid: u32) -> Option<String> {
+        match uid {
+            1000 => Some("alice".into()),
+            1001 => Some("bob".into()),
+            _ => None,
+        }
+    }
+
+    fn temp_log(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("authsudo-learning-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn logged_denials_come_back_in_order() {
+        let path = temp_log("round-trip");
+        let denials = [
+            denial(1000, "/usr/bin/nft", Some("/usr/bin/bash")),
+            denial(1001, "/usr/sbin/ip", None),
+        ];
+        for denial in &denials {
+            record(&path, denial).unwrap();
+        }
+
+        assert_eq!(load(&path).unwrap(), denials);
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_missing_log_has_no_denials_and_a_torn_record_is_skipped() {
+        let path = temp_log("torn");
+        assert_eq!(load(&path).unwrap(), Vec::new());
+
+        let mut bytes = b"1001\0/usr/sb".to_vec();
+        bytes.extend(denial(1000, "/usr/bin/nft", None).to_bytes());
+        bytes.extend(denial(1001, "/usr/bin/odd\nname", None).to_bytes());
+        bytes.extend_from_slice(b"1002\0/usr/bin/tr");
+        fs::write(&path, bytes).unwrap();
+        assert_eq!(
+            load(&path).unwrap(),
+            [denial(1001, "/usr/bin/odd\nname", None)]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn denials_become_one_confirm_rule_per_target() {
+        let denials = [
+            denial(1000, "/usr/bin/nft", Some("/usr/bin/bash")),
+            denial(1001, "/usr/bin/nft", Some("/usr/bin/zsh")),
+            denial(1000, "/usr/bin/nft", Some("/usr/bin/bash")),
+            denial(1000, "/usr/sbin/ip", None),
+        ];
+
+        let suggestions = suggest(&denials, names);
+
+        assert_eq!(
+            suggestions,
+            [
+                Suggestion {
+                    target: PathBuf::from("/usr/bin/nft"),
+                    requests: 3,
+                    users: vec!["alice".into(), "bob".into()],
+                    unnamed: Vec::new(),
+                    callers: vec![
+                        PathBuf::from("/usr/bin/bash"),
+                        PathBuf::from("/usr/bin/zsh")
+                    ],
+                },
+                Suggestion {
+                    target: PathBuf::from("/usr/sbin/ip"),
+                    requests: 1,
+                    users: vec!["alice".into()],
+                    unnamed: Vec::new(),
+                    callers: Vec::new(),
+                },
+            ]
+        );
+        let draft = to_toml(&suggestions);
+        assert!(draft.contains("Nothing here is applied"));
+        assert!(draft.contains(
+            "# refused 3 times\n\
+             # called from /usr/bin/bash, /usr/bin/zsh\n\
+             [[rules]]\n\
+             target = \"/usr/bin/nft\"\n\
+             allow_users = [\"alice\", \"bob\"]\n\
+             auth = \"confirm\"\n"
+        ));
+        assert!(!draft.contains("allow_callers"));
+    }
+
+    #[test]
+    fn the_draft_loads_as_a_policy() {
+        let denials = [
+            denial(1000, "/usr/bin/nft", None),
+            denial(4242, "/usr/bin/nft", None),
+            denial(4242, "/usr/sbin/ip", None),
+        ];
+        let suggestions = suggest(&denials, names);
+        assert_eq!(suggestions[0].unnamed, [4242]);
+
+        let draft = to_toml(&suggestions);
+        assert!(draft.contains("# also asked by uid 4242, which has no name\n"));
+        // Nobody named asked for ip, so its rule stays commented out
+        assert!(draft.contains("# target = \"/usr/sbin/ip\"\n"));
+
+        let mut engine = authd_policy::PolicyEngine::new();
+        assert_eq!(engine.load_from_str(&draft).unwrap(), 1);
+    }
+}
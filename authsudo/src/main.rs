@@ -5,16 +5,53 @@
 //! 2. Checks policies
 //! 3. Authenticates if required (or requests confirmation via authd)
 //! 4. exec() the target command as root or specified user (-u)
+//!
+//! `-s`/`-i` run the target user's shell instead (see [`shell`]).
+//!
+//! `authsudo --test-policy ...` instead evaluates policy offline (root only),
+//! `authsudo --record`/`--verify-recordings` keep decisions stable across
+//! policy edits (see [`recording`]),
+//! `authsudo --import-sudoers [file]` prints authd rules for a sudoers file,
+//! `authsudo --test-auth` checks the caller's password through authd's PAM
+//! service, `authsudo --cache-status` lists the caller's cached
+//! authorizations, `authsudo --repeat` runs the caller's last command
+//! again (see [`history`]), and `authsudo --suggest-policy` drafts rules from
+//! requests no rule matched (see [`learning`]).
 
-#[cfg(coverage)]
-use authd_policy::CallerInfo;
+mod approval;
+mod argv;
+mod binary_age;
+mod caller_age;
+mod environment;
+mod history;
+mod learning;
+mod recording;
+mod rlimits;
+mod shell;
+mod sudoers;
+mod target_privileges;
+mod test_policy;
+mod verbose;
+
+use authd_policy::session::{Logind, others_logged_in_refusal};
+use authd_policy::{CallerInfo, Config, Groups, PolicyDecision, PolicyEngine, SessionType};
 #[cfg(not(coverage))]
-use authd_policy::{CallerInfo, PolicyDecision, PolicyEngine};
+use authd_policy::{Explanation, GroupSource, Redaction, RunSettings};
+use authd_protocol::CachedAuth;
+use authd_protocol::instance::InstanceName;
+use authd_protocol::lockdown::LOCKDOWN_REASON;
+use authd_protocol::messages::{Message, MessageId};
 #[cfg(not(coverage))]
-use authd_protocol::{AuthRequest, AuthResponse, DaemonRequest, SOCKET_PATH, collect_wayland_env};
+use authd_protocol::{
+    Approval, AuthRequest, AuthResponse, DaemonRequest, TestAuthRequest, TestAuthResponse,
+    collect_wayland_env, frame, instance, lockdown,
+};
 #[cfg(not(coverage))]
-use peercred_ipc::Client as IpcClient;
+use shell::Account;
+use shell::ShellMode;
 use std::env;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
 #[cfg(not(coverage))]
 use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
@@ -22,24 +59,61 @@ use std::path::{Path, PathBuf};
 use std::process;
 #[cfg(not(coverage))]
 use std::process::Command;
+use std::time::Duration;
 
-/// Arguments that bypass auth (harmless info commands)
 #[cfg(not(coverage))]
-const BYPASS_ARGS: &[&str] = &["--help", "-h", "--version", "-V"];
+const USAGE: &str = "usage: authsudo [--instance <name>] [-v] [-u user] [--] <command> [args...]
+       authsudo [--instance <name>] [-v] [-u user] -s|-i [command [args...]]";
 
 /// Target user for command execution
 struct TargetUser {
     uid: u32,
     gid: u32,
-    name: Option<String>,
+    /// Raw passwd name (kept as bytes so initgroups sees the exact entry)
+    name: Option<OsString>,
+}
+
+/// Leading options, and the command line after them
+struct Options {
+    target_user: TargetUser,
+    /// Whether `-u` named the target user
+    user_given: bool,
+    shell: Option<ShellMode>,
+    /// `-v`: trace the run on stderr
+    verbose: bool,
+    command: Vec<OsString>,
+}
+
+impl Options {
+    /// Nothing to run: no command and no shell (`authsudo`, `authsudo -u
+    /// bob`, `authsudo --`), answered with the usage message
+    fn is_empty(&self) -> bool {
+        self.shell.is_none() && self.command.is_empty()
+    }
 }
 
 #[cfg(not(coverage))]
 struct Invocation {
+    /// Named authd instance to use (its policy and its daemon)
+    instance: Option<InstanceName>,
     target_user: TargetUser,
+    /// Whether `target_user` was asked for, by `-u` or by `-s`/`-i` starting
+    /// their shell, so a rule's `runas` may not replace it
+    user_pinned: bool,
+    /// The command, or the target user's shell for `-s`/`-i`
     target: PathBuf,
-    target_args: Vec<String>,
+    /// The command as invoked, before following a symlink to `target`;
+    /// passed on as `argv[0]` if `allow_argv0` admits it
+    invoked_as: PathBuf,
+    target_args: Vec<OsString>,
     has_bypass_arg: bool,
+    /// `-s`/`-i`, with the account whose shell `target` is
+    shell: Option<(ShellMode, Account)>,
+    /// Whether `target` was found; only ever false under
+    /// `conceal_missing_commands`, which looks at policy first
+    found: bool,
+    /// `-v`: trace the policy decision and authorization on stderr
+    verbose: bool,
 }
 
 impl TargetUser {
@@ -47,14 +121,14 @@ impl TargetUser {
         Self {
             uid: 0,
             gid: 0,
-            name: Some("root".to_string()),
+            name: Some(OsString::from("root")),
         }
     }
 
-    fn from_spec(spec: &str) -> Option<Self> {
+    fn from_spec(spec: &OsStr) -> Option<Self> {
         // Support #uid format
-        if let Some(uid_str) = spec.strip_prefix('#') {
-            let uid: u32 = uid_str.parse().ok()?;
+        if let Some(uid_bytes) = spec.as_bytes().strip_prefix(b"#") {
+            let uid: u32 = std::str::from_utf8(uid_bytes).ok()?.parse().ok()?;
             // Get primary group and name for this UID
             unsafe {
                 let pwd = libc::getpwuid(uid);
@@ -66,9 +140,8 @@ impl TargetUser {
                         name: None,
                     });
                 }
-                let name = std::ffi::CStr::from_ptr((*pwd).pw_name)
-                    .to_string_lossy()
-                    .into_owned();
+                let name = OsStr::from_bytes(std::ffi::CStr::from_ptr((*pwd).pw_name).to_bytes())
+                    .to_os_string();
                 return Some(Self {
                     uid,
                     gid: (*pwd).pw_gid,
@@ -79,7 +152,7 @@ impl TargetUser {
 
         // Username lookup
         unsafe {
-            let c_name = std::ffi::CString::new(spec).ok()?;
+            let c_name = std::ffi::CString::new(spec.as_bytes()).ok()?;
             let pwd = libc::getpwnam(c_name.as_ptr());
             if pwd.is_null() {
                 return None;
@@ -87,7 +160,7 @@ impl TargetUser {
             Some(Self {
                 uid: (*pwd).pw_uid,
                 gid: (*pwd).pw_gid,
-                name: Some(spec.to_string()),
+                name: Some(spec.to_os_string()),
             })
         }
     }
@@ -96,13 +169,76 @@ impl TargetUser {
 #[cfg(not(coverage))]
 fn main() {
     let real_uid = unsafe { libc::getuid() };
-    let invocation = parse_invocation();
-    let engine = load_policy_engine();
-    let caller_info = get_caller_info();
+    match env::args_os().nth(1) {
+        Some(arg) if arg == "--test-policy" => run_policy_test(real_uid),
+        Some(arg) if arg == "--record" => run_record(real_uid),
+        Some(arg) if arg == "--verify-recordings" => run_verify_recordings(real_uid),
+        Some(arg) if arg == "--import-sudoers" => run_sudoers_import(),
+        Some(arg) if arg == "--test-auth" => run_auth_test(),
+        Some(arg) if arg == "--cache-status" => run_cache_status(),
+        Some(arg) if arg == "--suggest-policy" => run_policy_suggestion(),
+        _ => {}
+    }
+    let config = Config::load().unwrap_or_else(|error| {
+        report(MessageId::ConfigLoadFailed.with(&[&error]));
+        Config::default()
+    });
+    // Callers are found through /proc; without it none would ever match
+    if let Err(e) = authd_policy::procfs::require() {
+        eprintln!("authsudo: {}", e);
+        process::exit(1);
+    }
+    let args: Vec<OsString> = match env::args_os().nth(1) {
+        Some(arg) if arg == "--repeat" => repeat_args(real_uid),
+        _ => env::args_os().skip(1).collect(),
+    };
+    let mut invocation = parse_invocation(&config, &args);
+    if let Err(e) = check_instance(&config, invocation.instance.as_ref(), real_uid) {
+        eprintln!("authsudo: {}", e);
+        process::exit(1);
+    }
+    let engine = load_policy_engine(&config, invocation.instance.as_ref());
+    let caller_info =
+        get_caller_info(|found| engine.callers_settled(&invocation.target, &policy_callers(found)));
     let callers = policy_callers(&caller_info);
-    enforce_policy(&engine, &invocation, real_uid, &callers);
+    let settings = enforce_policy(&engine, &config, &invocation, real_uid, &callers);
+    let requested = std::mem::replace(&mut invocation.target_user, TargetUser::root());
+    invocation.target_user = runas_user(
+        settings.runas.as_deref(),
+        &config.allow_runas,
+        real_uid,
+        requested,
+        invocation.user_pinned,
+        TargetUser::from_spec,
+    )
+    .unwrap_or_else(|error| {
+        eprintln!("authsudo: {}: {}", invocation.target.display(), error);
+        process::exit(1);
+    });
+    if let Err(e) = rlimits::apply(&engine.limits(&invocation.target, &invocation.target_args)) {
+        eprintln!("authsudo: cannot apply resource limits: {}", e);
+        process::exit(1);
+    }
+    if invocation.shell.is_none() {
+        remember(&invocation, real_uid);
+    }
     switch_to_target_user(&invocation.target_user);
-    exec_target(&invocation.target, &invocation.target_args);
+    let environment = target_environment(settings.clean_env, &config, &invocation);
+    match &invocation.shell {
+        Some((mode, account)) => exec_shell(
+            *mode,
+            account,
+            &invocation.target,
+            &invocation.target_args,
+            shell_environment(*mode, account, environment, env::vars_os()),
+        ),
+        None => exec_target(
+            &invocation.target,
+            exec_argv0(&engine, &invocation),
+            &invocation.target_args,
+            environment,
+        ),
+    }
 }
 
 #[cfg(coverage)]
@@ -113,11 +249,13 @@ struct ProcessInfo {
     exe: PathBuf,
     /// Resolved path of cmdline arg0 (for scripts run via interpreters)
     cmdline_path: Option<PathBuf>,
+    /// The systemd unit the process runs under
+    unit: Option<String>,
 }
 
 /// Resolve cmdline arg0 to a canonical path
 #[cfg(not(coverage))]
-fn resolve_cmdline_path(arg0: &str, pid: i32) -> Option<PathBuf> {
+fn resolve_cmdline_path(arg0: &OsStr, pid: i32) -> Option<PathBuf> {
     if arg0.is_empty() {
         return None;
     }
@@ -129,16 +267,16 @@ fn resolve_cmdline_path(arg0: &str, pid: i32) -> Option<PathBuf> {
         return std::fs::canonicalize(path).ok();
     }
 
-    // Get process's PATH from its environment
+    // Get process's PATH from its environment (raw bytes, not lossy UTF-8)
     let environ = std::fs::read(format!("/proc/{}/environ", pid)).ok()?;
-    let path_var = environ.split(|&b| b == 0).find_map(|entry| {
-        let entry = String::from_utf8_lossy(entry);
-        entry.strip_prefix("PATH=").map(|p| p.to_string())
-    })?;
+    let path_var = environ
+        .split(|&b| b == 0)
+        .find_map(|entry| entry.strip_prefix(b"PATH="))
+        .map(OsStr::from_bytes)?;
 
     // Search PATH for the command
-    for dir in path_var.split(':') {
-        let full = PathBuf::from(dir).join(arg0);
+    for dir in env::split_paths(path_var) {
+        let full = dir.join(arg0);
         if let Ok(resolved) = std::fs::canonicalize(&full) {
             return Some(resolved);
         }
@@ -147,19 +285,43 @@ fn resolve_cmdline_path(arg0: &str, pid: i32) -> Option<PathBuf> {
     None
 }
 
+/// How far up the process tree trusted callers are looked for
+const MAX_ANCESTORS: usize = 10;
+
 /// Get caller info (walk up process tree to find trusted callers)
 #[cfg(not(coverage))]
-fn get_caller_info() -> Vec<ProcessInfo> {
+fn get_caller_info(settled: impl Fn(&[ProcessInfo]) -> bool) -> Vec<ProcessInfo> {
+    let parent = unsafe { libc::getppid() } as i32;
+    walk_callers(parent, caller_entry, parent_pid, settled)
+}
+
+/// Ancestors from `pid` up, read with `entry` and followed with `parent`.
+/// Each one costs several `/proc` reads, so the walk stops as soon as
+/// `settled` says those found so far decide the policy check. Stopping at
+/// the first `allow_callers` match alone could miss a stricter rule whose
+/// caller sits further up.
+///
+/// A parent read back as a pid already walked (itself, or an ancestor
+/// whose pid was reused while the tree was read) ends the walk there, so a
+/// cycle is neither followed nor counted twice.
+#[cfg_attr(coverage, allow(dead_code))]
+fn walk_callers(
+    mut pid: i32,
+    entry: impl Fn(i32) -> Option<ProcessInfo>,
+    parent: impl Fn(i32) -> Option<i32>,
+    settled: impl Fn(&[ProcessInfo]) -> bool,
+) -> Vec<ProcessInfo> {
     let mut callers = Vec::new();
-    let mut pid = unsafe { libc::getppid() } as i32;
-    for _ in 0..10 {
-        if pid <= 1 {
+    let mut walked = Vec::with_capacity(MAX_ANCESTORS);
+    while walked.len() < MAX_ANCESTORS {
+        if pid <= 1 || walked.contains(&pid) || settled(&callers) {
             break;
         }
-        if let Some(caller) = caller_entry(pid) {
+        walked.push(pid);
+        if let Some(caller) = entry(pid) {
             callers.push(caller);
         }
-        let Some(parent_pid) = parent_pid(pid) else {
+        let Some(parent_pid) = parent(pid) else {
             break;
         };
         pid = parent_pid;
@@ -167,8 +329,12 @@ fn get_caller_info() -> Vec<ProcessInfo> {
     callers
 }
 
-/// Resolve a command to its absolute path
-fn resolve_path(cmd: &Path) -> Option<PathBuf> {
+/// Resolve a command to its absolute path. Bare names are looked up in
+/// `search_path` (`command_search_path`), never the caller's `PATH`.
+///
+/// Works on `Path`/`OsStr` throughout so a non-UTF8 component is matched and
+/// exec'd byte-for-byte rather than through a lossy conversion.
+fn resolve_path(cmd: &Path, search_path: &[PathBuf]) -> Option<PathBuf> {
     if cmd.is_absolute() {
         if cmd.exists() {
             return Some(cmd.to_path_buf());
@@ -187,108 +353,656 @@ fn resolve_path(cmd: &Path) -> Option<PathBuf> {
         return None;
     }
 
-    // Search PATH for simple command names
-    if let Ok(path_var) = env::var("PATH") {
-        for dir in path_var.split(':') {
-            let full = PathBuf::from(dir).join(cmd);
-            if full.exists() {
-                return Some(full);
-            }
-        }
-    }
-
-    None
+    // Search the configured directories for simple command names
+    search_path
+        .iter()
+        .map(|dir| dir.join(cmd))
+        .find(|full| full.exists())
 }
 
 /// Request confirmation from authd via session-lock dialog
 #[cfg(not(coverage))]
-fn request_confirmation(target: &Path, args: &[String]) -> bool {
-    let request = AuthRequest {
+fn request_confirmation(
+    socket: &str,
+    target: &Path,
+    args: &[OsString],
+    approval: Option<Approval>,
+    rule: Option<&Explanation>,
+) -> bool {
+    send_confirmation(socket, confirmation_request(target, args, approval, rule))
+}
+
+/// Request the soft-deny warning dialog for a discouraged target
+#[cfg(not(coverage))]
+fn request_discouraged_confirmation(
+    socket: &str,
+    redaction: &Redaction,
+    target: &Path,
+    args: &[OsString],
+    approval: Option<Approval>,
+    rule: Option<&Explanation>,
+) -> bool {
+    let mut request = confirmation_request(target, args, approval, rule);
+    request.discouraged = true;
+    request.prompt_title = Some(Message::from(MessageId::DiscouragedTitle).to_string());
+    request.prompt_message = Some(Message::from(MessageId::DiscouragedMessage).to_string());
+    request.prompt_detail = Some(command_text(target, &redaction.args(&request.args)));
+    send_confirmation(socket, request)
+}
+
+/// A confirm-only request for `target`. authd doesn't check policy for
+/// authsudo, so it carries what the deciding rule of the check in `rule`
+/// takes: its methods and PAM service.
+#[cfg(not(coverage))]
+fn confirmation_request(
+    target: &Path,
+    args: &[OsString],
+    approval: Option<Approval>,
+    rule: Option<&Explanation>,
+) -> AuthRequest {
+    AuthRequest {
         target: target.to_path_buf(),
-        args: args.to_vec(),
+        // Display only: the dialog shows these, exec uses the raw OsStrings
+        args: display_args(args),
         env: collect_wayland_env(),
         password: String::new(),
         confirm_only: true,
         prompt_title: None,
         prompt_message: None,
         prompt_detail: None,
-    };
+        approval,
+        discouraged: false,
+        methods: rule.and_then(|rule| rule.methods.clone()),
+        pam_service: rule.and_then(|rule| rule.settings.pam_service.clone()),
+    }
+}
 
-    match IpcClient::call(SOCKET_PATH, &DaemonRequest::Exec(request)) {
+#[cfg(not(coverage))]
+fn send_confirmation(socket: &str, mut request: AuthRequest) -> bool {
+    if let Err(e) = request.check_size() {
+        eprintln!("authsudo: {}", e);
+        return false;
+    }
+    match frame::call_socket(socket, &DaemonRequest::Exec(request.clone())) {
         Ok(AuthResponse::Success { .. }) => true,
+        // An authd without a dialog wants the password instead; ask once
+        Ok(AuthResponse::AuthFailed) if request.password.is_empty() => {
+            match approval::prompt_password() {
+                Ok(password) if !password.is_empty() => {
+                    request.password = password;
+                    send_confirmation(socket, request)
+                }
+                Ok(_) => false,
+                Err(e) => {
+                    eprintln!("authsudo: {}", e);
+                    false
+                }
+            }
+        }
+        Ok(AuthResponse::AuthFailed) => {
+            report(MessageId::IncorrectPassword);
+            false
+        }
         Ok(AuthResponse::Denied { reason }) => {
             eprintln!("authsudo: {}", reason);
             false
         }
         Err(e) => {
-            eprintln!("authsudo: cannot connect to authd: {}", e);
+            report(MessageId::ConnectFailed.with(&[&e]));
             false
         }
         _ => false,
     }
 }
 
-/// Parse -u/--user flag from arguments
-fn parse_user_flag(args: &[String]) -> (TargetUser, Vec<String>) {
+fn command_text(target: &Path, args: &[String]) -> String {
+    let mut text = target.display().to_string();
+    for arg in args {
+        text.push(' ');
+        text.push_str(arg);
+    }
+    text
+}
+
+/// Lossy rendering of argv for display (dialogs, messages), never for exec
+#[cfg(not(coverage))]
+fn display_args(args: &[OsString]) -> Vec<String> {
+    args.iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Strip a leading `--instance <name>` off the arguments
+fn split_instance(args: &[OsString]) -> Result<(Option<InstanceName>, &[OsString]), String> {
+    match args {
+        [flag, name, rest @ ..] if flag == "--instance" => {
+            let name = name
+                .to_str()
+                .ok_or_else(|| format!("invalid instance name {:?}", name))?;
+            let name = InstanceName::new(name).map_err(|e| e.to_string())?;
+            Ok((Some(name), rest))
+        }
+        [flag] if flag == "--instance" => Err("--instance requires a name".into()),
+        _ => Ok((None, args)),
+    }
+}
+
+/// Only root and `admin_groups` may pick a named instance: authsudo is
+/// setuid, and an instance's policy may be laxer than the default one
+fn check_instance(
+    config: &Config,
+    instance: Option<&InstanceName>,
+    real_uid: u32,
+) -> Result<(), String> {
+    match instance {
+        Some(name) if !config.is_admin(real_uid) => Err(format!(
+            "instance {} may only be used by root and admin_groups",
+            name
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// The user to run as when the winning rule sets `runas`, as authd would:
+/// the caller for `caller`, or the named account, which must be on
+/// `allow_runas` unless it's root. Without `runas` the requested user stands.
+/// A pinned request for anyone else is refused rather than overridden.
+fn runas_user(
+    runas: Option<&str>,
+    allow_runas: &[String],
+    real_uid: u32,
+    requested: TargetUser,
+    pinned: bool,
+    lookup: impl Fn(&OsStr) -> Option<TargetUser>,
+) -> Result<TargetUser, String> {
+    let spec = match runas {
+        None => return Ok(requested),
+        Some("caller") => OsString::from(format!("#{}", real_uid)),
+        Some(user) if user == "root" || allow_runas.iter().any(|allowed| allowed == user) => {
+            OsString::from(user)
+        }
+        Some(user) => return Err(format!("runas {} is not on allow_runas", user)),
+    };
+    let user =
+        lookup(&spec).ok_or_else(|| format!("runas: unknown user {}", spec.to_string_lossy()))?;
+    if pinned && user.uid != requested.uid {
+        return Err(format!(
+            "its rule runs it as uid {}, not uid {}",
+            user.uid, requested.uid
+        ));
+    }
+    Ok(user)
+}
+
+/// Parse -u/--user and -s/--shell, -i/--login, in any order, up to the command
+/// or a `--` ending the options
+fn parse_options(args: &[OsString]) -> Options {
     let mut iter = args.iter().peekable();
-    let mut target_user = TargetUser::root();
+    let mut user_spec = None;
+    let mut shell = None;
+    let mut verbose = false;
     let mut remaining = Vec::new();
 
     while let Some(arg) = iter.next() {
+        if arg == "-s" || arg == "--shell" {
+            shell = Some(ShellMode::Shell);
+            continue;
+        }
+        if arg == "-i" || arg == "--login" {
+            shell = Some(ShellMode::Login);
+            continue;
+        }
+        if arg == "-v" || arg == "--verbose" {
+            verbose = true;
+            continue;
+        }
+
         if arg == "-u" || arg == "--user" {
-            let user_spec = iter.next().unwrap_or_else(|| missing_user_argument());
-            target_user = parse_target_user(user_spec);
+            user_spec = Some(
+                iter.next()
+                    .map(OsString::as_os_str)
+                    .unwrap_or_else(|| missing_user_argument()),
+            );
             continue;
         }
 
-        if let Some(user_spec) = arg.strip_prefix("-u") {
-            target_user = parse_target_user(user_spec);
+        if let Some(spec) = arg.as_bytes().strip_prefix(b"-u") {
+            user_spec = Some(OsStr::from_bytes(spec));
             continue;
         }
 
-        remaining.push(arg.clone());
+        if arg != "--" {
+            remaining.push(arg.clone());
+        }
         remaining.extend(iter.cloned());
         break;
     }
 
-    (target_user, remaining)
+    // With nothing to run, the usage message says more than a user lookup
+    let target_user = match user_spec {
+        Some(spec) if shell.is_some() || !remaining.is_empty() => parse_target_user(spec),
+        _ => TargetUser::root(),
+    };
+    Options {
+        target_user,
+        user_given: user_spec.is_some(),
+        shell,
+        verbose,
+        command: remaining,
+    }
 }
 
 #[cfg(not(coverage))]
-fn parse_invocation() -> Invocation {
-    let args: Vec<String> = env::args().skip(1).collect();
-    if args.is_empty() {
-        eprintln!("usage: authsudo [-u user] <command> [args...]");
+fn parse_invocation(config: &Config, args: &[OsString]) -> Invocation {
+    let (instance, args) = split_instance(args).unwrap_or_else(|error| {
+        eprintln!("authsudo: {}", error);
+        process::exit(1);
+    });
+    let options = parse_options(args);
+    if options.is_empty() {
+        eprintln!("{}", USAGE);
         process::exit(1);
     }
-
-    let (target_user, args) = parse_user_flag(&args);
-    if args.is_empty() {
-        eprintln!("usage: authsudo [-u user] <command> [args...]");
+    let Options {
+        target_user,
+        user_given,
+        shell,
+        verbose,
+        command,
+    } = options;
+    let checked = match shell {
+        Some(_) => argv::reject_nul(&command),
+        None => argv::validate(&command),
+    };
+    if let Err(error) = checked {
+        eprintln!("authsudo: {}", error);
         process::exit(1);
     }
+    if let Some(mode) = shell {
+        let account = shell_account(&target_user);
+        return Invocation {
+            instance,
+            target_user,
+            user_pinned: true,
+            target: account.shell.clone(),
+            invoked_as: account.shell.clone(),
+            target_args: shell::args(&command),
+            // The shell runs whatever it's given, so nothing is harmless
+            has_bypass_arg: false,
+            shell: Some((mode, account)),
+            found: true,
+            verbose,
+        };
+    }
+    let target_args: Vec<OsString> = command.iter().skip(1).cloned().collect();
+    let Some(resolved) = resolve_path(Path::new(&command[0]), &config.command_search_path) else {
+        let typed = PathBuf::from(&command[0]);
+        if !config.conceal_missing_commands {
+            report(MessageId::CommandNotFound.with(&[&typed.display()]));
+            process::exit(127);
+        }
+        // Policy decides first; "not found" is only for those it allows
+        return Invocation {
+            instance,
+            target_user,
+            user_pinned: user_given,
+            target: typed.clone(),
+            invoked_as: typed,
+            has_bypass_arg: argv::is_info_only(&target_args),
+            target_args,
+            shell: None,
+            found: false,
+            verbose,
+        };
+    };
 
-    let target_args: Vec<String> = args.iter().skip(1).cloned().collect();
-    let target = resolve_path(Path::new(&args[0])).unwrap_or_else(|| {
-        eprintln!("authsudo: command not found: {}", args[0]);
-        process::exit(127);
+    let invoked_as = argv::canonical_target(&resolved);
+    let target = argv::resolve_links(&invoked_as).unwrap_or_else(|error| {
+        eprintln!("authsudo: {}", error);
+        process::exit(1);
     });
-
     Invocation {
+        instance,
         target_user,
+        user_pinned: user_given,
         target,
-        has_bypass_arg: target_args
-            .iter()
-            .any(|arg| BYPASS_ARGS.contains(&arg.as_str())),
+        invoked_as,
+        has_bypass_arg: argv::is_info_only(&target_args),
         target_args,
+        shell: None,
+        found: true,
+        verbose,
+    }
+}
+
+/// `authsudo --repeat`: the command line of the caller's last command
+#[cfg(not(coverage))]
+fn repeat_args(real_uid: u32) -> Vec<OsString> {
+    match history::load(Path::new(history::HISTORY_DIR), real_uid) {
+        Ok(Some(entry)) => {
+            report(
+                MessageId::Repeating
+                    .with(&[&command_text(&entry.target, &display_args(&entry.args))]),
+            );
+            entry.to_args()
+        }
+        Ok(None) => {
+            report(MessageId::NothingToRepeat);
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("authsudo: {}: {}", history::HISTORY_DIR, e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Record the command about to run for `--repeat`. Failing to doesn't stop
+/// it from running.
+#[cfg(not(coverage))]
+fn remember(invocation: &Invocation, real_uid: u32) {
+    let entry = history::Entry {
+        run_as: invocation.target_user.uid,
+        target: invocation.invoked_as.clone(),
+        args: invocation.target_args.clone(),
+    };
+    let real_gid = unsafe { libc::getgid() };
+    if let Err(e) = history::save(Path::new(history::HISTORY_DIR), real_uid, real_gid, &entry) {
+        eprintln!("authsudo: cannot save history: {}", e);
+    }
+}
+
+/// The target user's passwd entry, if their shell may be started
+#[cfg(not(coverage))]
+fn shell_account(target_user: &TargetUser) -> Account {
+    let Some(account) = Account::of(target_user.uid) else {
+        report(MessageId::NoPasswdEntry.with(&[&target_user.uid]));
+        process::exit(1);
+    };
+    let etc_shells = std::fs::read_to_string(shell::SHELLS).ok();
+    if let Err(error) = shell::check_shell(&account.shell, etc_shells.as_deref()) {
+        eprintln!("authsudo: {} for {}", error, account.name.to_string_lossy());
+        process::exit(1);
     }
+    account
 }
 
+/// `authsudo --test-policy`: print the decision for a synthetic caller
 #[cfg(not(coverage))]
-fn load_policy_engine() -> PolicyEngine {
+fn run_policy_test(real_uid: u32) -> ! {
+    if real_uid != 0 {
+        report(MessageId::TestPolicyRootOnly);
+        process::exit(1);
+    }
+
+    let args: Vec<OsString> = env::args_os().skip(2).collect();
+    let mut test = test_policy::parse_args(&args).unwrap_or_else(|error| {
+        eprintln!("authsudo: {}", error);
+        eprintln!("{}", test_policy::USAGE);
+        process::exit(1);
+    });
+    let config = Config::load().unwrap_or_default();
+    if let Some(resolved) = resolve_path(&test.target, &config.command_search_path) {
+        test.target = resolved;
+    }
+    let engine = load_test_engine(&config, &test.policy_dir, test.policy_file.as_deref());
+
+    let explanation = test_policy::evaluate(&engine, &test);
+    println!("{}", test_policy::report(&test, &explanation));
+    process::exit(test_policy::exit_code(&explanation.decision))
+}
+
+/// The policy `--test-policy` and the recordings are checked against: the
+/// combined `policy_file`, if any, then `policy_dir`. Exits if it can't be
+/// loaded.
+#[cfg(not(coverage))]
+fn load_test_engine(
+    config: &Config,
+    policy_dir: &Path,
+    policy_file: Option<&Path>,
+) -> PolicyEngine {
+    let mut engine = PolicyEngine::new();
+    engine.set_resolution(config.rule_resolution);
+    engine.set_duplicate_targets(config.duplicate_targets);
+    engine.set_force_password_targets(&config.force_password_targets);
+    let loaded = match policy_file {
+        Some(file) => engine.load_all(file, policy_dir),
+        None => engine.load_from_dir(policy_dir),
+    };
+    if let Err(error) = loaded {
+        report(MessageId::PoliciesLoadFailed.with(&[&error]));
+        process::exit(1);
+    }
+    engine
+}
+
+/// `authsudo --record <dir> ...`: save a decision to check later policies
+/// against
+#[cfg(not(coverage))]
+fn run_record(real_uid: u32) -> ! {
+    if real_uid != 0 {
+        report(MessageId::RecordingRootOnly);
+        process::exit(1);
+    }
+
+    let args: Vec<OsString> = env::args_os().skip(2).collect();
+    let (dir, mut test) = recording::parse_record_args(&args).unwrap_or_else(|error| {
+        eprintln!("authsudo: {}", error);
+        eprintln!("{}", recording::RECORD_USAGE);
+        process::exit(1);
+    });
+    let config = Config::load().unwrap_or_default();
+    if let Some(resolved) = resolve_path(&test.target, &config.command_search_path) {
+        test.target = resolved;
+    }
+    let engine = load_test_engine(&config, &test.policy_dir, test.policy_file.as_deref());
+
+    let input = recording::DecisionInput::from_test(&test).unwrap_or_else(|error| {
+        eprintln!("authsudo: {}", error);
+        process::exit(1);
+    });
+    let record = recording::DecisionRecord::record(&engine, input);
+    match recording::save(&dir, &record) {
+        Ok(path) => {
+            println!("decision: {}", record.decision);
+            println!("{}", MessageId::Recorded.with(&[&path.display()]));
+            process::exit(0)
+        }
+        Err(e) => {
+            eprintln!("authsudo: {}: {}", dir.display(), e);
+            process::exit(1)
+        }
+    }
+}
+
+/// `authsudo --verify-recordings <dir>`: replay saved decisions through the
+/// current policy; exits 1 if any changed
+#[cfg(not(coverage))]
+fn run_verify_recordings(real_uid: u32) -> ! {
+    if real_uid != 0 {
+        report(MessageId::RecordingRootOnly);
+        process::exit(1);
+    }
+
+    let args: Vec<OsString> = env::args_os().skip(2).collect();
+    let verification = recording::parse_verify_args(&args).unwrap_or_else(|error| {
+        eprintln!("authsudo: {}", error);
+        eprintln!("{}", recording::VERIFY_USAGE);
+        process::exit(1);
+    });
+    let config = Config::load().unwrap_or_default();
+    let engine = load_test_engine(
+        &config,
+        &verification.policy_dir,
+        verification.policy_file.as_deref(),
+    );
+    let recordings = recording::load_dir(&verification.dir).unwrap_or_else(|error| {
+        eprintln!("authsudo: {}", error);
+        process::exit(1);
+    });
+
+    let changed = recording::changed(&engine, &recordings);
+    for line in &changed {
+        println!("{}", line);
+    }
+    if changed.is_empty() {
+        println!(
+            "{}",
+            MessageId::RecordingsUnchanged.with(&[&recordings.len()])
+        );
+        process::exit(0)
+    }
+    report(MessageId::RecordingsChanged.with(&[&changed.len(), &recordings.len()]));
+    process::exit(1)
+}
+
+/// `authsudo --import-sudoers [file]`: print authd rules for a sudoers file,
+/// with what couldn't be converted reported on stderr
+#[cfg(not(coverage))]
+fn run_sudoers_import() -> ! {
+    // Read with the invoker's own permissions, not setuid root's
+    drop_to_real_user();
+
+    let args: Vec<OsString> = env::args_os().skip(2).collect();
+    let path = match args.as_slice() {
+        [] => PathBuf::from(sudoers::DEFAULT_SUDOERS),
+        [path] => PathBuf::from(path),
+        _ => {
+            eprintln!("{}", sudoers::USAGE);
+            process::exit(1);
+        }
+    };
+    let content = std::fs::read_to_string(&path).unwrap_or_else(|error| {
+        eprintln!("authsudo: {}: {}", path.display(), error);
+        process::exit(1);
+    });
+
+    let import = sudoers::import(&content);
+    for warning in &import.warnings {
+        report(MessageId::SudoersLineSkipped.with(&[
+            &path.display(),
+            &warning.line,
+            &warning.message,
+        ]));
+    }
+    print!("{}", sudoers::to_toml(&import.rules));
+    process::exit(0)
+}
+
+/// `authsudo --suggest-policy [learning-log]`: print candidate rules for the
+/// requests in the learning log, for an admin to review; nothing is installed
+#[cfg(not(coverage))]
+fn run_policy_suggestion() -> ! {
+    // The log is root's alone, so only root gets a draft
+    drop_to_real_user();
+
+    let args: Vec<OsString> = env::args_os().skip(2).collect();
+    let path = match args.as_slice() {
+        [] => PathBuf::from(learning::LEARNING_LOG),
+        [path] => PathBuf::from(path),
+        _ => {
+            eprintln!("{}", learning::USAGE);
+            process::exit(1);
+        }
+    };
+    let denials = learning::load(&path).unwrap_or_else(|error| {
+        eprintln!("authsudo: {}: {}", path.display(), error);
+        process::exit(1);
+    });
+    let suggestions = learning::suggest(&denials, authd_policy::username_from_uid);
+    print!("{}", learning::to_toml(&suggestions));
+    process::exit(0)
+}
+
+/// `authsudo --test-auth`: ask for the caller's password and have authd check
+/// it the way it checks approvals, with no policy and nothing run, so a
+/// broken PAM stack shows up on its own
+#[cfg(not(coverage))]
+fn run_auth_test() -> ! {
+    // authd checks the password of whoever connects: the invoker, not root
+    drop_to_real_user();
+
+    let password = approval::prompt_password().unwrap_or_else(|e| {
+        eprintln!("authsudo: {}", e);
+        process::exit(1);
+    });
+    let request = DaemonRequest::TestAuth(TestAuthRequest { password });
+    match frame::call_socket(&instance::socket_path(None), &request) {
+        Ok(TestAuthResponse::Accepted) => {
+            println!("{}", Message::from(MessageId::AuthenticationSucceeded));
+            process::exit(0)
+        }
+        Ok(TestAuthResponse::Rejected) => report(MessageId::IncorrectPassword),
+        Ok(TestAuthResponse::Error { message }) => {
+            report(MessageId::AuthenticationError.with(&[&message]))
+        }
+        Err(e) => report(MessageId::ConnectFailed.with(&[&e])),
+    }
+    process::exit(1)
+}
+
+/// `authsudo --cache-status`: the targets the caller can run again without
+/// confirming, and for how long. authd answers for whoever connects, so no
+/// one sees another user's entries.
+#[cfg(not(coverage))]
+fn run_cache_status() -> ! {
+    drop_to_real_user();
+
+    let listed: Result<Vec<CachedAuth>, _> =
+        frame::call_socket(&instance::socket_path(None), &DaemonRequest::ListCache);
+    let entries = listed.unwrap_or_else(|e| {
+        report(MessageId::ConnectFailed.with(&[&e]));
+        process::exit(1);
+    });
+    if entries.is_empty() {
+        println!("{}", Message::from(MessageId::NothingCached));
+    }
+    for line in cache_table(&entries) {
+        println!("{}", line);
+    }
+    process::exit(0)
+}
+
+fn cache_table(entries: &[CachedAuth]) -> Vec<String> {
+    if entries.is_empty() {
+        return Vec::new();
+    }
+    let mut lines = vec![format!("{:>9}  {}", "REMAINING", "TARGET")];
+    lines.extend(entries.iter().map(|entry| {
+        format!(
+            "{:>9}  {}",
+            format!("{}s", entry.remaining_secs),
+            entry.target.display()
+        )
+    }));
+    lines
+}
+
+#[cfg(not(coverage))]
+fn drop_to_real_user() {
+    let dropped = unsafe { libc::setgid(libc::getgid()) == 0 && libc::setuid(libc::getuid()) == 0 };
+    if !dropped {
+        report(MessageId::DropPrivilegesFailed);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(coverage))]
+fn load_policy_engine(config: &Config, instance: Option<&InstanceName>) -> PolicyEngine {
     let mut engine = PolicyEngine::new();
-    if let Err(error) = engine.load() {
-        eprintln!("authsudo: failed to load policies: {}", error);
+    engine.set_resolution(config.rule_resolution);
+    engine.set_duplicate_targets(config.duplicate_targets);
+    engine.set_force_password_targets(&config.force_password_targets);
+    let drop_ins = match authd_policy::drop_in_dir(instance, config.profile.as_deref()) {
+        Ok(dir) => dir,
+        Err(error) => {
+            report(MessageId::PoliciesLoadFailed.with(&[&error]));
+            process::exit(1);
+        }
+    };
+    let loaded = engine.load_all(&authd_policy::policy_file(instance), &drop_ins);
+    if let Err(error) = loaded {
+        report(MessageId::PoliciesLoadFailed.with(&[&error]));
         process::exit(1);
     }
     engine
@@ -300,37 +1014,354 @@ fn policy_callers(callers: &[ProcessInfo]) -> Vec<CallerInfo<'_>> {
         .map(|caller| CallerInfo {
             exe: caller.exe.as_path(),
             cmdline_path: caller.cmdline_path.as_deref(),
+            // Only authd reads callers' contexts (its `selinux` feature)
+            secontext: None,
+            unit: caller.unit.as_deref(),
         })
         .collect()
 }
 
+/// `root_bypass` keys off the real uid only: authsudo is setuid root, so its
+/// euid is 0 for every invoker and must never grant the bypass. An allowed
+/// target still has to pass any `allow_argv0`, `allow_scripts`,
+/// `min_binary_age_secs`, `min_caller_age_secs`, `forbid_setuid_target` and
+/// `forbid_file_caps` checks, and one whose rule takes only methods authd
+/// can't check is refused.
+#[allow(clippy::too_many_arguments)]
+fn policy_decision(
+    engine: &PolicyEngine,
+    config: &Config,
+    target: &Path,
+    invoked_as: &Path,
+    target_args: &[OsString],
+    has_bypass_arg: bool,
+    real_uid: u32,
+    groups: Groups<'_>,
+    session: Option<SessionType>,
+    callers: &[CallerInfo<'_>],
+) -> PolicyDecision {
+    if bypasses_policy(config, has_bypass_arg, real_uid) {
+        return PolicyDecision::AllowImmediate;
+    }
+    let explanation =
+        engine.check_explain_command(target, target_args, real_uid, groups, session, callers);
+    let unchecked = explanation
+        .methods
+        .as_ref()
+        .and_then(|methods| methods.unchecked_refusal(target));
+    if let Some(reason) = unchecked {
+        return PolicyDecision::Denied(reason);
+    }
+    let decision = explanation.decision;
+    if matches!(
+        decision,
+        PolicyDecision::Denied(_) | PolicyDecision::Unknown
+    ) {
+        return decision;
+    }
+    if let Some(reason) = engine.argv0_refusal(target, target_args, invoked_as) {
+        return PolicyDecision::Denied(reason);
+    }
+    if let Some(reason) = engine.script_refusal(target, target_args) {
+        return PolicyDecision::Denied(reason);
+    }
+    let aged = engine
+        .min_binary_age_secs(target, target_args)
+        .map_or(Ok(()), |min_age| {
+            binary_age::check_path(target, Duration::from_secs(min_age))
+        });
+    if let Err(reason) = aged {
+        return PolicyDecision::Denied(reason);
+    }
+    let caller_aged = engine
+        .min_caller_age_secs(target, target_args)
+        .map_or(Ok(()), |min_age| {
+            caller_age::check_parent(Duration::from_secs(min_age))
+        });
+    if let Err(reason) = caller_aged {
+        return PolicyDecision::Denied(reason);
+    }
+    let others = engine
+        .forbids_others_logged_in(target, target_args)
+        .then(|| others_logged_in_refusal(&Logind, real_uid))
+        .flatten();
+    if let Some(reason) = others {
+        return PolicyDecision::Denied(reason);
+    }
+    let (forbid_setid, forbid_caps) = (
+        engine.forbids_setuid_target(target, target_args),
+        engine.forbids_file_caps(target, target_args),
+    );
+    if !forbid_setid && !forbid_caps {
+        return decision;
+    }
+    match target_privileges::check_path(target, forbid_setid, forbid_caps) {
+        Ok(()) => decision,
+        Err(reason) => PolicyDecision::Denied(reason),
+    }
+}
+
+/// `argv[0]` for the target: the name it was invoked by where a rule's
+/// `allow_argv0` vetted it, its own path otherwise, so a multi-call binary
+/// can't be steered into an applet nobody allowed
+#[cfg(not(coverage))]
+fn exec_argv0<'a>(engine: &PolicyEngine, invocation: &'a Invocation) -> &'a Path {
+    if engine.pins_argv0(&invocation.target, &invocation.target_args) {
+        &invocation.invoked_as
+    } else {
+        &invocation.target
+    }
+}
+
+/// The target's environment under `clean_env` (the deciding rule's, or
+/// authd.toml's where it sets none), or None to run it in the caller's.
+/// `-s` shells get the same.
+#[cfg(not(coverage))]
+fn target_environment(
+    clean_env: Option<bool>,
+    config: &Config,
+    invocation: &Invocation,
+) -> Option<Vec<(OsString, OsString)>> {
+    if !clean_env.unwrap_or(config.clean_env) {
+        return None;
+    }
+    let account = shell::Account::of(invocation.target_user.uid);
+    Some(environment::clean(
+        &config.command_search_path,
+        account.as_ref(),
+        &config.keep_env,
+        env::vars_os(),
+    ))
+}
+
+/// The shell's environment: for `-s` the caller's, or `cleaned` under
+/// `clean_env`; `-i` always starts from its own fresh login environment
+fn shell_environment(
+    mode: ShellMode,
+    account: &Account,
+    cleaned: Option<Vec<(OsString, OsString)>>,
+    inherited: impl IntoIterator<Item = (OsString, OsString)>,
+) -> Vec<(OsString, OsString)> {
+    match (mode, cleaned) {
+        (ShellMode::Shell, Some(cleaned)) => cleaned,
+        _ => shell::environment(mode, account, inherited),
+    }
+}
+
+fn bypasses_policy(config: &Config, has_bypass_arg: bool, real_uid: u32) -> bool {
+    has_bypass_arg || (config.root_bypass && real_uid == 0)
+}
+
+/// Checked here too, since `auth = "none"` targets never reach authd
+fn under_lockdown(
+    config: &Config,
+    target: &Path,
+    locked_down: bool,
+    decision: PolicyDecision,
+) -> PolicyDecision {
+    if locked_down && !config.emergency_allowed(target) {
+        return PolicyDecision::Denied(LOCKDOWN_REASON.into());
+    }
+    decision
+}
+
+/// Real gid and supplementary groups, which setuid leaves as the invoker's
+#[cfg(not(coverage))]
+fn own_groups() -> Vec<u32> {
+    let real_gid = unsafe { libc::getgid() };
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    let mut groups = vec![0; usize::try_from(count).unwrap_or(0)];
+    let count = unsafe { libc::getgroups(count.max(0), groups.as_mut_ptr()) };
+    if count < 0 {
+        report(MessageId::ReadGroupsFailed);
+        process::exit(1);
+    }
+    groups.truncate(count as usize);
+    groups.retain(|&gid| gid != real_gid);
+    groups.insert(0, real_gid);
+    groups
+}
+
 #[cfg(not(coverage))]
 fn enforce_policy(
     engine: &PolicyEngine,
+    config: &Config,
     invocation: &Invocation,
     real_uid: u32,
     callers: &[CallerInfo<'_>],
-) {
-    let decision = if invocation.has_bypass_arg {
-        PolicyDecision::AllowImmediate
-    } else {
-        engine.check_with_callers(&invocation.target, real_uid, callers)
+) -> RunSettings {
+    let gids = match config.group_source {
+        GroupSource::Nss => None,
+        GroupSource::Process => Some(own_groups()),
     };
+    let groups = gids.as_deref().map_or(Groups::Nss, Groups::Process);
+    let session = authd_policy::session::of_process(process::id());
+    let decision = policy_decision(
+        engine,
+        config,
+        &invocation.target,
+        &invocation.invoked_as,
+        &invocation.target_args,
+        invocation.has_bypass_arg,
+        real_uid,
+        groups,
+        session,
+        callers,
+    );
+    let bypassed = bypasses_policy(config, invocation.has_bypass_arg, real_uid);
+    let locked_down = !bypassed && lockdown::instance_is_active(invocation.instance.as_ref());
+    let decision = under_lockdown(config, &invocation.target, locked_down, decision);
+    let explanation = (!bypassed).then(|| {
+        engine.check_explain_command(
+            &invocation.target,
+            &invocation.target_args,
+            real_uid,
+            groups,
+            session,
+            callers,
+        )
+    });
+    if invocation.verbose {
+        for line in
+            verbose::policy_trace(&invocation.target, callers, explanation.as_ref(), &decision)
+        {
+            eprintln!("authsudo: {}", line);
+        }
+    }
+
+    let refused = matches!(
+        decision,
+        PolicyDecision::Denied(_) | PolicyDecision::Unknown
+    );
+    // Allowed, so its absence is no secret; and nothing to confirm
+    if !refused && !invocation.found {
+        report(MessageId::CommandNotFound.with(&[&invocation.target.display()]));
+        process::exit(127);
+    }
 
+    let socket = instance::socket_path(invocation.instance.as_ref());
+    let approval = match decision {
+        PolicyDecision::Denied(_) | PolicyDecision::Unknown => None,
+        _ => prompt_approval(engine, config, invocation, real_uid),
+    };
+    let auth = verbose::auth_label(&decision, approval.as_ref());
+    if let Some(auth) = auth.as_ref().filter(|_| invocation.verbose) {
+        eprintln!("authsudo: auth: {}", auth);
+    }
     match decision {
-        PolicyDecision::AllowImmediate => {}
-        PolicyDecision::AllowWithConfirm => {
-            if !request_confirmation(&invocation.target, &invocation.target_args) {
-                eprintln!("authsudo: authorization denied");
+        // An approval is only checked by authd, so it can't skip the round trip
+        PolicyDecision::AllowImmediate if approval.is_none() => {}
+        PolicyDecision::AllowImmediate | PolicyDecision::AllowWithConfirm => {
+            if !request_confirmation(
+                &socket,
+                &invocation.target,
+                &invocation.target_args,
+                approval,
+                explanation.as_ref(),
+            ) {
+                report(MessageId::AuthorizationDenied);
                 process::exit(1);
             }
         }
-        PolicyDecision::Denied(reason) => {
-            eprintln!("authsudo: {}", reason);
+        PolicyDecision::Discouraged => {
+            if !request_discouraged_confirmation(
+                &socket,
+                &config.redact_args,
+                &invocation.target,
+                &invocation.target_args,
+                approval,
+                explanation.as_ref(),
+            ) {
+                report(MessageId::AuthorizationDenied);
+                process::exit(1);
+            }
+            report(MessageId::RunningDiscouraged.with(&[&invocation.target.display()]));
+        }
+        PolicyDecision::Denied(_) | PolicyDecision::Unknown => {
+            if matches!(decision, PolicyDecision::Unknown) && config.learn_denials {
+                learn_denial(invocation, real_uid);
+            }
+            eprintln!(
+                "authsudo: {}",
+                refusal(config, &invocation.target, &decision)
+            );
             process::exit(1);
         }
-        PolicyDecision::Unknown => {
-            eprintln!("authsudo: no policy for {}", invocation.target.display());
+    }
+    // A shell's argv is its own, built at the exec
+    if let Some(auth) = auth.filter(|_| invocation.verbose && invocation.shell.is_none()) {
+        let summary = verbose::run_summary(
+            &invocation.target,
+            exec_argv0(engine, invocation),
+            &invocation.target_args,
+            (invocation.target_user.uid, invocation.target_user.gid),
+            &auth,
+            explanation.as_ref(),
+        );
+        eprintln!("authsudo: {}", summary);
+    }
+    // Bypassing policy bypasses its rules' settings too
+    explanation.map_or_else(RunSettings::default, |explanation| explanation.settings)
+}
+
+/// Log a request no rule matched for `--suggest-policy`. Commands that
+/// don't exist are no rule to suggest.
+#[cfg(not(coverage))]
+fn learn_denial(invocation: &Invocation, real_uid: u32) {
+    if !invocation.found {
+        return;
+    }
+    let parent = unsafe { libc::getppid() };
+    let denial = learning::Denial {
+        uid: real_uid,
+        target: invocation.target.clone(),
+        caller: caller_entry(parent)
+            .map(|caller| caller.exe)
+            .filter(|exe| !exe.as_os_str().is_empty()),
+    };
+    if let Err(e) = learning::record(Path::new(learning::LEARNING_LOG), &denial) {
+        eprintln!("authsudo: cannot log denial: {}", e);
+    }
+}
+
+/// What authsudo says when policy refuses `target`. Under
+/// `conceal_missing_commands` every refusal reads the same, whether or not
+/// the command exists and whatever the reason.
+fn refusal(config: &Config, target: &Path, decision: &PolicyDecision) -> String {
+    match decision {
+        PolicyDecision::Denied(reason) if !config.conceal_missing_commands => reason.clone(),
+        PolicyDecision::Unknown if !config.conceal_missing_commands => MessageId::NoPolicyFor
+            .with(&[&target.display()])
+            .to_string(),
+        _ => Message::from(MessageId::AuthorizationDenied).to_string(),
+    }
+}
+
+/// Collect the approver's credentials if the target is under the two-person
+/// rule (and policy applies at all)
+#[cfg(not(coverage))]
+fn prompt_approval(
+    engine: &PolicyEngine,
+    config: &Config,
+    invocation: &Invocation,
+    real_uid: u32,
+) -> Option<Approval> {
+    if bypasses_policy(config, invocation.has_bypass_arg, real_uid) {
+        return None;
+    }
+    let groups = engine.approver_groups(&invocation.target, &invocation.target_args);
+    if groups.is_empty() {
+        return None;
+    }
+    let requester =
+        authd_policy::username_from_uid(real_uid).unwrap_or_else(|| format!("uid {}", real_uid));
+    let mut command = vec![invocation.target.clone().into_os_string()];
+    command.extend(invocation.target_args.iter().cloned());
+    let command = shell::command_line(&command);
+    match approval::prompt(&groups, &requester, &command.to_string_lossy()) {
+        Ok(approval) => Some(approval),
+        Err(e) => {
+            eprintln!("authsudo: {}", e);
             process::exit(1);
         }
     }
@@ -340,7 +1371,7 @@ fn enforce_policy(
 fn switch_to_target_user(target_user: &TargetUser) {
     unsafe {
         if let Some(name) = &target_user.name {
-            let c_name = std::ffi::CString::new(name.as_str()).unwrap();
+            let c_name = std::ffi::CString::new(name.as_bytes()).unwrap();
             libc::initgroups(c_name.as_ptr(), target_user.gid);
         } else {
             libc::setgroups(0, std::ptr::null());
@@ -350,10 +1381,49 @@ fn switch_to_target_user(target_user: &TargetUser) {
     }
 }
 
+/// Print `message` on stderr under authsudo's name
+#[cfg(not(coverage))]
+fn report(message: impl Into<Message>) {
+    eprintln!("authsudo: {}", message.into());
+}
+
 #[cfg(not(coverage))]
-fn exec_target(target: &Path, target_args: &[String]) -> ! {
-    let err = Command::new(target).args(target_args).exec();
-    eprintln!("authsudo: failed to execute {}: {}", target.display(), err);
+fn exec_target(
+    target: &Path,
+    argv0: &Path,
+    target_args: &[OsString],
+    environment: Option<Vec<(OsString, OsString)>>,
+) -> ! {
+    let mut command = Command::new(target);
+    command.arg0(argv0).args(target_args);
+    if let Some(environment) = environment {
+        command.env_clear().envs(environment);
+    }
+    let err = command.exec();
+    report(MessageId::ExecFailed.with(&[&target.display(), &err]));
+    process::exit(126)
+}
+
+/// Start the target user's shell in `env`, already switched to them; `-i`
+/// also moves to their home
+#[cfg(not(coverage))]
+fn exec_shell(
+    mode: ShellMode,
+    account: &Account,
+    shell: &Path,
+    args: &[OsString],
+    env: Vec<(OsString, OsString)>,
+) -> ! {
+    let mut command = Command::new(shell);
+    command.args(args).env_clear().envs(env);
+    if mode == ShellMode::Login {
+        command.arg0(shell::login_arg0(shell));
+        if let Err(e) = env::set_current_dir(&account.home) {
+            report(MessageId::ChdirFailed.with(&[&account.home.display(), &e]));
+        }
+    }
+    let err = command.exec();
+    report(MessageId::ExecFailed.with(&[&shell.display(), &err]));
     process::exit(126)
 }
 
@@ -364,7 +1434,12 @@ fn caller_entry(pid: i32) -> Option<ProcessInfo> {
     if exe.as_os_str().is_empty() && cmdline_path.is_none() {
         return None;
     }
-    Some(ProcessInfo { exe, cmdline_path })
+    let unit = authd_policy::unit::of_process(pid as u32);
+    Some(ProcessInfo {
+        exe,
+        cmdline_path,
+        unit,
+    })
 }
 
 #[cfg(not(coverage))]
@@ -375,9 +1450,8 @@ fn caller_cmdline_path(pid: i32) -> Option<PathBuf> {
             bytes
                 .split(|&byte| byte == 0)
                 .next()
-                .map(|arg0| arg0.to_vec())
+                .map(|arg0| OsStr::from_bytes(arg0).to_os_string())
         })
-        .and_then(|arg0| String::from_utf8(arg0).ok())
         .and_then(|arg0| resolve_cmdline_path(&arg0, pid))
 }
 
@@ -390,90 +1464,325 @@ fn parent_pid(pid: i32) -> Option<i32> {
 }
 
 #[cfg(not(coverage))]
-fn parse_target_user(spec: &str) -> TargetUser {
+fn parse_target_user(spec: &OsStr) -> TargetUser {
     match TargetUser::from_spec(spec) {
         Some(user) => user,
         None => {
-            eprintln!("authsudo: unknown user: {}", spec);
+            let spec = spec.to_string_lossy();
+            report(MessageId::UnknownUser.with(&[&spec]));
             process::exit(1);
         }
     }
 }
 
 #[cfg(coverage)]
-fn parse_target_user(spec: &str) -> TargetUser {
-    TargetUser::from_spec(spec).unwrap_or_else(|| panic!("authsudo: unknown user: {spec}"))
+fn parse_target_user(spec: &OsStr) -> TargetUser {
+    TargetUser::from_spec(spec).unwrap_or_else(|| {
+        panic!(
+            "authsudo: {}",
+            MessageId::UnknownUser.with(&[&spec.to_string_lossy()])
+        )
+    })
 }
 
 #[cfg(not(coverage))]
 fn missing_user_argument() -> ! {
-    eprintln!("authsudo: -u requires an argument");
+    report(MessageId::UserArgumentMissing);
     process::exit(1)
 }
 
 #[cfg(coverage)]
 fn missing_user_argument() -> ! {
-    panic!("authsudo: -u requires an argument")
+    panic!(
+        "authsudo: {}",
+        Message::from(MessageId::UserArgumentMissing)
+    )
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn os_args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let nonce = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = env::temp_dir().join(format!("authsudo-{name}-{nonce}"));
+        std::fs::create_dir(&dir).unwrap();
+        dir
+    }
+
     #[test]
     fn target_user_parses_root_and_numeric_specs() {
         let root = TargetUser::root();
         assert_eq!(root.uid, 0);
         assert_eq!(root.gid, 0);
-        assert_eq!(root.name.as_deref(), Some("root"));
+        assert_eq!(root.name.as_deref(), Some(OsStr::new("root")));
 
-        let numeric = TargetUser::from_spec("#4242").unwrap();
+        let numeric = TargetUser::from_spec(OsStr::new("#4242")).unwrap();
         assert_eq!(numeric.uid, 4242);
-        assert!(TargetUser::from_spec("#not-a-uid").is_none());
+        assert!(TargetUser::from_spec(OsStr::new("#not-a-uid")).is_none());
 
-        let named_root = TargetUser::from_spec("root").unwrap();
+        let named_root = TargetUser::from_spec(OsStr::new("root")).unwrap();
         assert_eq!(named_root.uid, 0);
-        assert_eq!(named_root.name.as_deref(), Some("root"));
+        assert_eq!(named_root.name.as_deref(), Some(OsStr::new("root")));
+    }
+
+    #[test]
+    fn parse_options_extracts_target_user_and_command() {
+        let args = os_args(&["-u#1234", "/usr/bin/id", "-u"]);
+
+        let options = parse_options(&args);
+
+        assert_eq!(options.target_user.uid, 1234);
+        assert_eq!(options.shell, None);
+        assert_eq!(options.command, os_args(&["/usr/bin/id", "-u"]));
+    }
+
+    #[test]
+    fn shell_flags_combine_with_user_in_any_order() {
+        let options = parse_options(&os_args(&["-i", "-u", "#1234"]));
+        assert_eq!(options.shell, Some(ShellMode::Login));
+        assert_eq!(options.target_user.uid, 1234);
+        assert!(options.command.is_empty());
+
+        let options = parse_options(&os_args(&["--user", "#1234", "-s", "ls", "-i"]));
+        assert_eq!(options.shell, Some(ShellMode::Shell));
+        assert_eq!(options.target_user.uid, 1234);
+        // Flags after the command belong to it
+        assert_eq!(options.command, os_args(&["ls", "-i"]));
+    }
+
+    #[test]
+    fn leading_instance_flag_selects_the_instance() {
+        let args = os_args(&["--instance", "test", "-u", "#0", "/usr/bin/id"]);
+
+        let (instance, rest) = split_instance(&args).unwrap();
+        assert_eq!(instance, Some(InstanceName::new("test").unwrap()));
+        assert_eq!(rest, os_args(&["-u", "#0", "/usr/bin/id"]));
+
+        let (instance, rest) = split_instance(&args[2..]).unwrap();
+        assert_eq!(instance, None);
+        assert_eq!(rest.len(), 3);
+
+        assert!(split_instance(&os_args(&["--instance"])).is_err());
+        assert!(split_instance(&os_args(&["--instance", "../etc", "/usr/bin/id"])).is_err());
+    }
+
+    #[test]
+    fn clean_env_covers_shells_started_with_s() {
+        let account = Account {
+            name: "root".into(),
+            home: PathBuf::from("/root"),
+            shell: PathBuf::from("/usr/bin/zsh"),
+        };
+        let caller_env = || {
+            [
+                ("HOME", "/home/alice"),
+                ("TERM", "xterm-256color"),
+                ("LD_PRELOAD", "/home/alice/evil.so"),
+                ("PYTHONPATH", "/home/alice/lib"),
+            ]
+            .map(|(name, value)| (OsString::from(name), OsString::from(value)))
+        };
+        let names = |env: Vec<(OsString, OsString)>| {
+            env.into_iter()
+                .map(|(name, _)| name.into_string().unwrap())
+                .collect::<Vec<_>>()
+        };
+        let search_path = [PathBuf::from("/usr/bin")];
+        let cleaned = environment::clean(&search_path, Some(&account), &[], caller_env());
+
+        assert_eq!(
+            names(shell_environment(
+                ShellMode::Shell,
+                &account,
+                Some(cleaned.clone()),
+                caller_env()
+            )),
+            ["HOME", "LOGNAME", "PATH", "SHELL", "TERM", "USER"]
+        );
+        // With clean_env off, -s keeps everything
+        assert!(
+            names(shell_environment(
+                ShellMode::Shell,
+                &account,
+                None,
+                caller_env()
+            ))
+            .contains(&"LD_PRELOAD".to_string())
+        );
+        // -i is a login environment either way
+        assert_eq!(
+            shell_environment(ShellMode::Login, &account, Some(cleaned), caller_env()),
+            shell::environment(ShellMode::Login, &account, caller_env())
+        );
+    }
+
+    #[test]
+    fn only_admins_pick_an_instance() {
+        let config = Config {
+            admin_groups: Vec::new(),
+            ..Config::default()
+        };
+        let test = InstanceName::new("test").unwrap();
+
+        assert!(check_instance(&config, Some(&test), 0).is_ok());
+        assert!(check_instance(&config, Some(&test), 4242).is_err());
+        // The default instance is everyone's
+        assert!(check_instance(&config, None, 4242).is_ok());
+    }
+
+    #[test]
+    fn runas_picks_the_target_user_unless_one_was_asked_for() {
+        let lookup = |spec: &OsStr| match spec.to_str()? {
+            "#1000" => Some(TargetUser {
+                uid: 1000,
+                gid: 1000,
+                name: Some("alice".into()),
+            }),
+            "backup" => Some(TargetUser {
+                uid: 34,
+                gid: 34,
+                name: Some("backup".into()),
+            }),
+            _ => None,
+        };
+        let allowed = ["backup".to_string()];
+        let run = |runas, requested, pinned| {
+            runas_user(runas, &allowed, 1000, requested, pinned, lookup).map(|user| user.uid)
+        };
+
+        // Without runas, -u (or its root default) decides
+        assert_eq!(run(None, TargetUser::root(), false), Ok(0));
+        // runas replaces the default root
+        assert_eq!(run(Some("caller"), TargetUser::root(), false), Ok(1000));
+        assert_eq!(run(Some("backup"), TargetUser::root(), false), Ok(34));
+        // ...but never a user that was asked for
+        assert!(run(Some("caller"), TargetUser::root(), true).is_err());
+        assert_eq!(
+            run(Some("backup"), lookup(OsStr::new("backup")).unwrap(), true),
+            Ok(34)
+        );
+        // Named accounts must be on allow_runas, as for authd
+        assert!(run(Some("nobody"), TargetUser::root(), false).is_err());
+    }
+
+    #[test]
+    fn parse_options_supports_long_user_option() {
+        let args = os_args(&["--user", "#4321", "/usr/bin/true"]);
+
+        let options = parse_options(&args);
+
+        assert_eq!(options.target_user.uid, 4321);
+        assert_eq!(options.command, os_args(&["/usr/bin/true"]));
     }
 
     #[test]
-    fn parse_user_flag_extracts_target_user_and_command() {
-        let args = vec![
-            "-u#1234".to_string(),
-            "/usr/bin/id".to_string(),
-            "-u".to_string(),
+    fn missing_commands_get_the_usage_message() {
+        let cases: [&[&str]; 5] = [
+            &[],
+            &["-u", "bob"],
+            &["-ubob"],
+            &["--"],
+            &["-u", "bob", "--"],
         ];
+        for args in cases {
+            let options = parse_options(&os_args(args));
+            assert!(options.is_empty(), "{args:?}");
+            assert_eq!(options.target_user.uid, 0, "{args:?}");
+        }
+        assert!(!parse_options(&os_args(&["-s"])).is_empty());
+    }
 
-        let (target_user, remaining) = parse_user_flag(&args);
+    #[test]
+    fn verbose_flag_is_an_option_until_the_command() {
+        let options = parse_options(&os_args(&["-v", "-u", "#1234", "/usr/bin/id", "-v"]));
+        assert!(options.verbose);
+        assert_eq!(options.target_user.uid, 1234);
+        assert_eq!(options.command, os_args(&["/usr/bin/id", "-v"]));
 
-        assert_eq!(target_user.uid, 1234);
-        assert_eq!(remaining, vec!["/usr/bin/id", "-u"]);
+        let options = parse_options(&os_args(&["--", "-v"]));
+        assert!(!options.verbose);
+        assert_eq!(options.command, os_args(&["-v"]));
+        assert!(parse_options(&os_args(&["--verbose", "-s"])).verbose);
     }
 
     #[test]
-    fn parse_user_flag_supports_long_user_option() {
-        let args = vec![
-            "--user".to_string(),
-            "#4321".to_string(),
-            "/usr/bin/true".to_string(),
+    fn cache_status_lists_one_line_per_target() {
+        assert!(cache_table(&[]).is_empty());
+
+        let entries = [
+            CachedAuth {
+                target: PathBuf::from("/usr/bin/gparted"),
+                remaining_secs: 240,
+            },
+            CachedAuth {
+                target: PathBuf::from("/usr/bin/id"),
+                remaining_secs: 1,
+            },
         ];
+        assert_eq!(
+            cache_table(&entries),
+            [
+                "REMAINING  TARGET",
+                "     240s  /usr/bin/gparted",
+                "       1s  /usr/bin/id",
+            ]
+        );
+    }
 
-        let (target_user, remaining) = parse_user_flag(&args);
+    #[test]
+    fn repeated_commands_parse_back_into_the_same_invocation() {
+        let entry = history::Entry {
+            run_as: 1234,
+            target: PathBuf::from("/usr/bin/id"),
+            // Options of the target stay its own
+            args: os_args(&["-u", "-s"]),
+        };
 
-        assert_eq!(target_user.uid, 4321);
-        assert_eq!(remaining, vec!["/usr/bin/true"]);
+        let options = parse_options(&entry.to_args());
+
+        assert_eq!(options.target_user.uid, 1234);
+        assert_eq!(options.shell, None);
+        assert_eq!(options.command, os_args(&["/usr/bin/id", "-u", "-s"]));
+    }
+
+    #[test]
+    fn double_dash_ends_the_options() {
+        let options = parse_options(&os_args(&["-u", "#1234", "--", "-s", "/usr/bin/id"]));
+        assert_eq!(options.target_user.uid, 1234);
+        assert_eq!(options.shell, None);
+        assert_eq!(options.command, os_args(&["-s", "/usr/bin/id"]));
+
+        // Only the first `--` is ours
+        let options = parse_options(&os_args(&["--", "--", "x"]));
+        assert_eq!(options.command, os_args(&["--", "x"]));
+    }
+
+    #[test]
+    fn parse_options_preserves_non_utf8_arguments() {
+        let raw = OsStr::from_bytes(b"/tmp/caf\xe9").to_os_string();
+        let args = vec![OsString::from("/usr/bin/ls"), raw.clone()];
+
+        let remaining = parse_options(&args).command;
+
+        assert_eq!(remaining[1], raw);
+        assert_eq!(remaining[1].as_bytes(), b"/tmp/caf\xe9");
     }
 
     #[cfg(coverage)]
     #[test]
     #[should_panic(expected = "authsudo: unknown user")]
-    fn parse_user_flag_rejects_unknown_user_in_coverage() {
-        let args = vec![
-            "--user".to_string(),
-            "__missing_authsudo_user__".to_string(),
-        ];
+    fn parse_options_rejects_unknown_user_in_coverage() {
+        let args = os_args(&["--user", "__missing_authsudo_user__", "/usr/bin/id"]);
 
-        let _ = parse_user_flag(&args);
+        let _ = parse_options(&args);
     }
 
     #[test]
@@ -481,21 +1790,558 @@ mod tests {
         let callers = vec![ProcessInfo {
             exe: PathBuf::from("/usr/bin/authsudo"),
             cmdline_path: Some(PathBuf::from("/usr/bin/sudo")),
+            unit: Some("backup.service".into()),
         }];
 
         let borrowed = policy_callers(&callers);
 
         assert_eq!(borrowed[0].exe, Path::new("/usr/bin/authsudo"));
         assert_eq!(borrowed[0].cmdline_path, Some(Path::new("/usr/bin/sudo")));
+        assert_eq!(borrowed[0].unit, Some("backup.service"));
+    }
+
+    #[test]
+    fn caller_walk_stops_once_the_callers_settle_the_check() {
+        // 400 (shell) -> 300 (claude) -> 200 (terminal) -> 100 (session) -> 1
+        let tree = |pid| match pid {
+            400 => Some(("/usr/bin/bash", 300)),
+            300 => Some(("/usr/bin/claude", 200)),
+            200 => Some(("/usr/bin/foot", 100)),
+            100 => Some(("/usr/lib/systemd/systemd", 1)),
+            _ => None,
+        };
+        let read = std::cell::RefCell::new(Vec::new());
+        let walk = |engine: &PolicyEngine| {
+            read.borrow_mut().clear();
+            let found = walk_callers(
+                400,
+                |pid| {
+                    read.borrow_mut().push(pid);
+                    tree(pid).map(|(exe, _)| ProcessInfo {
+                        exe: PathBuf::from(exe),
+                        cmdline_path: None,
+                        unit: None,
+                    })
+                },
+                |pid| tree(pid).map(|(_, parent)| parent),
+                |found| engine.callers_settled(Path::new("/usr/bin/id"), &policy_callers(found)),
+            );
+            found.len()
+        };
+
+        let mut engine = PolicyEngine::new();
+        engine
+            .load_from_str(
+                r#"
+                [[rules]]
+                target = "*"
+                allow_callers = ["/usr/bin/claude"]
+                auth = "none"
+                "#,
+            )
+            .unwrap();
+        assert_eq!(walk(&engine), 2);
+        assert_eq!(*read.borrow(), vec![400, 300]);
+
+        // A caller rule nobody matches: the whole tree is read
+        let mut engine = PolicyEngine::new();
+        engine
+            .load_from_str(
+                r#"
+                [[rules]]
+                target = "*"
+                allow_callers = ["/opt/agent/bin/agent"]
+                "#,
+            )
+            .unwrap();
+        assert_eq!(walk(&engine), 4);
+        assert_eq!(*read.borrow(), vec![400, 300, 200, 100]);
+
+        // No caller rules: nothing needs reading
+        assert_eq!(walk(&PolicyEngine::new()), 0);
+        assert!(read.borrow().is_empty());
+
+        // A unit rule judges the direct caller, so that one is read
+        let mut engine = PolicyEngine::new();
+        engine
+            .load_from_str(
+                r#"
+                [[rules]]
+                target = "/usr/bin/id"
+                allow_units = ["backup.service"]
+                "#,
+            )
+            .unwrap();
+        assert_eq!(walk(&engine), 1);
+        assert_eq!(*read.borrow(), vec![400]);
+    }
+
+    #[test]
+    fn caller_walk_ends_at_a_cycle_in_the_tree() {
+        let walk = |tree: &dyn Fn(i32) -> Option<i32>| {
+            let read = std::cell::RefCell::new(Vec::new());
+            walk_callers(
+                400,
+                |pid| {
+                    read.borrow_mut().push(pid);
+                    Some(ProcessInfo {
+                        exe: PathBuf::from(format!("/usr/bin/p{pid}")),
+                        cmdline_path: None,
+                        unit: None,
+                    })
+                },
+                tree,
+                |_| false,
+            );
+            read.into_inner()
+        };
+
+        // 400 -> 300 -> 200 -> 300: a reused pid points back down the tree
+        let cycle = |pid: i32| match pid {
+            400 => Some(300),
+            300 => Some(200),
+            200 => Some(300),
+            _ => None,
+        };
+        assert_eq!(walk(&cycle), [400, 300, 200]);
+
+        // A process that reads as its own parent
+        let own_parent = |pid: i32| Some(pid);
+        assert_eq!(walk(&own_parent), [400]);
+
+        // A chain that never ends still stops at MAX_ANCESTORS
+        let endless = |pid: i32| Some(pid + 1);
+        assert_eq!(walk(&endless).len(), MAX_ANCESTORS);
+    }
+
+    #[test]
+    fn root_bypass_skips_policy_for_real_root() {
+        let engine = PolicyEngine::new();
+        let config = Config::default();
+
+        assert!(matches!(
+            policy_decision(
+                &engine,
+                &config,
+                Path::new("/usr/bin/id"),
+                Path::new("/usr/bin/id"),
+                &[],
+                false,
+                0,
+                Groups::Nss,
+                None,
+                &[]
+            ),
+            PolicyDecision::AllowImmediate
+        ));
+        // Any other real uid still needs a rule, even though euid is 0
+        assert!(matches!(
+            policy_decision(
+                &engine,
+                &config,
+                Path::new("/usr/bin/id"),
+                Path::new("/usr/bin/id"),
+                &[],
+                false,
+                1000,
+                Groups::Nss,
+                None,
+                &[]
+            ),
+            PolicyDecision::Unknown
+        ));
+    }
+
+    #[test]
+    fn disabled_root_bypass_makes_root_match_policy() {
+        let engine = PolicyEngine::new();
+        let config = Config {
+            root_bypass: false,
+            ..Config::default()
+        };
+
+        assert!(matches!(
+            policy_decision(
+                &engine,
+                &config,
+                Path::new("/usr/bin/id"),
+                Path::new("/usr/bin/id"),
+                &[],
+                false,
+                0,
+                Groups::Nss,
+                None,
+                &[]
+            ),
+            PolicyDecision::Unknown
+        ));
+    }
+
+    #[test]
+    fn rules_taking_methods_authd_cant_check_are_refused() {
+        let decide = |auth: &str| {
+            let mut engine = PolicyEngine::new();
+            engine
+                .load_from_str(&format!(
+                    r#"
+                    [[rules]]
+                    target = "/usr/bin/id"
+                    allow_users = ["root"]
+                    auth = {auth}
+                    "#
+                ))
+                .unwrap();
+            let config = Config {
+                root_bypass: false,
+                ..Config::default()
+            };
+            policy_decision(
+                &engine,
+                &config,
+                Path::new("/usr/bin/id"),
+                Path::new("/usr/bin/id"),
+                &[],
+                false,
+                0,
+                Groups::Nss,
+                None,
+                &[],
+            )
+        };
+
+        // One Allow click must not stand in for a hardware key
+        for auth in [r#"["hwkey"]"#, r#"{ all = ["confirm", "hwkey"] }"#] {
+            let decision = decide(auth);
+            assert!(
+                matches!(&decision, PolicyDecision::Denied(reason) if reason.contains("hwkey")),
+                "{auth}: {decision:?}"
+            );
+        }
+        assert!(matches!(
+            decide(r#"["password", "hwkey"]"#),
+            PolicyDecision::AllowWithConfirm
+        ));
+    }
+
+    #[test]
+    fn allowed_target_younger_than_min_binary_age_is_denied() {
+        // The test binary was just built, so it's far younger than a decade
+        let target = env::current_exe().unwrap();
+        let mut engine = PolicyEngine::new();
+        engine
+            .load_from_str(&format!(
+                r#"
+                [[rules]]
+                target = "{}"
+                allow_callers = ["/usr/bin/claude"]
+                auth = "none"
+                min_binary_age_secs = 315360000
+                "#,
+                target.display()
+            ))
+            .unwrap();
+        let callers = [CallerInfo {
+            exe: Path::new("/usr/bin/claude"),
+            cmdline_path: None,
+            secontext: None,
+            unit: None,
+        }];
+
+        let decision = policy_decision(
+            &engine,
+            &Config::default(),
+            &target,
+            &target,
+            &[],
+            false,
+            1000,
+            Groups::Nss,
+            None,
+            &callers,
+        );
+        assert!(
+            matches!(decision, PolicyDecision::Denied(_)),
+            "{decision:?}"
+        );
+
+        // Root's bypass skips policy, including the age check
+        let decision = policy_decision(
+            &engine,
+            &Config::default(),
+            &target,
+            &target,
+            &[],
+            false,
+            0,
+            Groups::Nss,
+            None,
+            &callers,
+        );
+        assert!(matches!(decision, PolicyDecision::AllowImmediate));
+    }
+
+    #[test]
+    fn allowed_target_from_a_caller_younger_than_min_caller_age_is_denied() {
+        // Whatever started the test binary hasn't been running for a decade
+        let mut engine = PolicyEngine::new();
+        engine
+            .load_from_str(
+                r#"
+                [[rules]]
+                target = "/usr/bin/id"
+                allow_callers = ["/usr/bin/claude"]
+                auth = "none"
+                min_caller_age_secs = 315360000
+                "#,
+            )
+            .unwrap();
+        let callers = [CallerInfo {
+            exe: Path::new("/usr/bin/claude"),
+            cmdline_path: None,
+            secontext: None,
+            unit: None,
+        }];
+
+        let decision = policy_decision(
+            &engine,
+            &Config::default(),
+            Path::new("/usr/bin/id"),
+            Path::new("/usr/bin/id"),
+            &[],
+            false,
+            1000,
+            Groups::Nss,
+            None,
+            &callers,
+        );
+        assert!(
+            matches!(&decision, PolicyDecision::Denied(reason) if reason.contains("315360000s")),
+            "{decision:?}"
+        );
+    }
+
+    #[test]
+    fn setuid_targets_are_denied_under_forbid_setuid_target() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = env::temp_dir().join(format!("authsudo-forbid-setuid-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let (setuid, plain) = (dir.join("setuid"), dir.join("plain"));
+        for (path, mode) in [(&setuid, 0o4755), (&plain, 0o755)] {
+            std::fs::File::create(path).unwrap();
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+        let mut engine = PolicyEngine::new();
+        engine
+            .load_from_str(
+                r#"
+                [[rules]]
+                target = "*"
+                allow_users = ["root"]
+                auth = "none"
+                forbid_setuid_target = true
+                "#,
+            )
+            .unwrap();
+        let config = Config {
+            root_bypass: false,
+            ..Config::default()
+        };
+        let decide = |target: &Path| {
+            policy_decision(
+                &engine,
+                &config,
+                target,
+                target,
+                &[],
+                false,
+                0,
+                Groups::Nss,
+                None,
+                &[],
+            )
+        };
+
+        assert!(matches!(
+            decide(&setuid),
+            PolicyDecision::Denied(reason) if reason.contains("setuid or setgid")
+        ));
+        assert!(matches!(decide(&plain), PolicyDecision::AllowImmediate));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn applets_run_only_under_allowed_names() {
+        let mut engine = PolicyEngine::new();
+        engine
+            .load_from_str(
+                r#"
+                [[rules]]
+                target = "/usr/bin/busybox"
+                allow_users = ["root"]
+                auth = "none"
+                allow_argv0 = ["ls"]
+                "#,
+            )
+            .unwrap();
+        let config = Config {
+            root_bypass: false,
+            ..Config::default()
+        };
+        let busybox = Path::new("/usr/bin/busybox");
+        let decide = |invoked_as: &str| {
+            policy_decision(
+                &engine,
+                &config,
+                busybox,
+                Path::new(invoked_as),
+                &[],
+                false,
+                0,
+                Groups::Nss,
+                None,
+                &[],
+            )
+        };
+
+        assert!(matches!(
+            decide("/usr/bin/ls"),
+            PolicyDecision::AllowImmediate
+        ));
+        assert!(matches!(
+            decide("/usr/bin/sh"),
+            PolicyDecision::Denied(reason) if reason.contains("may not be run as /usr/bin/sh")
+        ));
+    }
+
+    #[test]
+    fn lockdown_denies_all_but_emergency_targets() {
+        let config = Config {
+            emergency_allow: vec![PathBuf::from("/usr/bin/systemctl")],
+            ..Config::default()
+        };
+        let allowed = || PolicyDecision::AllowImmediate;
+
+        assert!(matches!(
+            under_lockdown(&config, Path::new("/usr/bin/id"), true, allowed()),
+            PolicyDecision::Denied(reason) if reason == LOCKDOWN_REASON
+        ));
+        assert!(matches!(
+            under_lockdown(&config, Path::new("/usr/bin/systemctl"), true, allowed()),
+            PolicyDecision::AllowImmediate
+        ));
+        assert!(matches!(
+            under_lockdown(&config, Path::new("/usr/bin/id"), false, allowed()),
+            PolicyDecision::AllowImmediate
+        ));
+    }
+
+    #[test]
+    fn concealed_refusals_read_the_same_whether_or_not_the_command_exists() {
+        let mut engine = PolicyEngine::new();
+        engine
+            .load_from_str(
+                r#"
+                [[rules]]
+                target = "/usr/bin/id"
+                auth = "deny"
+                "#,
+            )
+            .unwrap();
+        let refuse = |config: &Config, target: &str| {
+            let target = Path::new(target);
+            let decision = policy_decision(
+                &engine,
+                config,
+                target,
+                target,
+                &[],
+                false,
+                1000,
+                Groups::Nss,
+                None,
+                &[],
+            );
+            refusal(config, target, &decision)
+        };
+
+        let config = Config {
+            conceal_missing_commands: true,
+            ..Config::default()
+        };
+        let existing = refuse(&config, "/usr/bin/id");
+        assert_eq!(existing, "authorization denied");
+        assert_eq!(existing, refuse(&config, "/usr/bin/authd-no-such-command"));
+        assert_eq!(existing, refuse(&config, "authd-no-such-command"));
+
+        // By default the refusal says why, which tells the two apart
+        let config = Config::default();
+        assert_ne!(
+            refuse(&config, "/usr/bin/id"),
+            refuse(&config, "/usr/bin/authd-no-such-command")
+        );
+    }
+
+    #[test]
+    fn command_text_joins_target_and_args() {
+        assert_eq!(command_text(Path::new("/usr/bin/rm"), &[]), "/usr/bin/rm");
+        assert_eq!(
+            command_text(Path::new("/usr/bin/rm"), &["-rf".into(), "/tmp/x".into()]),
+            "/usr/bin/rm -rf /tmp/x"
+        );
     }
 
     #[test]
     fn resolve_path_handles_absolute_existing_and_missing_paths() {
         assert_eq!(
-            resolve_path(Path::new("/definitely/not/authsudo-test")),
+            resolve_path(Path::new("/definitely/not/authsudo-test"), &[]),
             None
         );
-        assert!(resolve_path(Path::new("/bin/sh")).is_some());
+        assert!(resolve_path(Path::new("/bin/sh"), &[]).is_some());
+    }
+
+    #[test]
+    fn bare_names_resolve_only_within_the_search_path() {
+        let system = temp_dir("search-system");
+        let writable = temp_dir("search-writable");
+        std::fs::write(system.join("tool"), "").unwrap();
+        std::fs::write(writable.join("tool"), "").unwrap();
+        std::fs::write(writable.join("only-here"), "").unwrap();
+
+        // A caller's PATH could list `writable` first; it isn't searched
+        let search_path = [system.clone()];
+        assert_eq!(
+            resolve_path(Path::new("tool"), &search_path),
+            Some(system.join("tool"))
+        );
+        assert_eq!(resolve_path(Path::new("only-here"), &search_path), None);
+        // Explicit paths are used as given
+        let explicit = writable.join("only-here");
+        assert_eq!(resolve_path(&explicit, &search_path), Some(explicit));
+
+        std::fs::remove_dir_all(system).unwrap();
+        std::fs::remove_dir_all(writable).unwrap();
+    }
+
+    #[test]
+    fn resolve_path_keeps_non_utf8_components_exact() {
+        let dir = temp_dir("non-utf8");
+        let name = OsStr::from_bytes(b"tool-\xff");
+        std::fs::write(dir.join(name), "").unwrap();
+
+        let by_name = resolve_path(Path::new(name), &[dir.clone()]).unwrap();
+        assert_eq!(by_name, dir.join(name));
+        assert_eq!(by_name.file_name().unwrap().as_bytes(), b"tool-\xff");
+
+        // A different invalid byte must not resolve to the same file, even
+        // though both render as U+FFFD when converted lossily.
+        let lookalike = OsStr::from_bytes(b"tool-\xfe");
+        assert_eq!(resolve_path(Path::new(lookalike), &[dir.clone()]), None);
+
+        let absolute = dir.join(name);
+        assert_eq!(resolve_path(&absolute, &[]), Some(absolute.clone()));
+        std::fs::remove_dir_all(dir).unwrap();
     }
 
     #[cfg(coverage)]
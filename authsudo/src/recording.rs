@@ -0,0 +1,339 @@
+//! `authsudo --record` and `--verify-recordings`: a regression harness for
+//! policy edits
+//!
+//! `--record <dir>` takes the same arguments as `--test-policy`, evaluates
+//! them and saves the input together with the decision as one TOML file in
+//! `<dir>`. `--verify-recordings <dir>` replays every file there through the
+//! current policy and lists those whose decision changed, so a policy
+//! repository's CI can catch an edit that widens or narrows access by
+//! accident. Both are root only, like `--test-policy`.
+//!
+//! The environment isn't recorded: no rule looks at it.
+
+use crate::test_policy::{self, PolicyTest};
+use authd_policy::{POLICY_DIR, POLICY_FILE, PolicyEngine};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+pub const RECORD_USAGE: &str = "usage: authsudo --record <dir> --uid <uid> [--caller <path>]... \
+                                [--policy-dir <dir>] [--policy-file <file>] <command> [args...]";
+pub const VERIFY_USAGE: &str = "usage: authsudo --verify-recordings <dir> \
+                                [--policy-dir <dir>] [--policy-file <file>]";
+
+/// What a decision was made for: who asked, through which callers, to run
+/// what
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecisionInput {
+    pub uid: u32,
+    /// Caller chain, nearest ancestor first
+    #[serde(default)]
+    pub callers: Vec<PathBuf>,
+    pub target: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl DecisionInput {
+    /// The input of a `--test-policy` style run. Arguments have to be UTF-8
+    /// to be written out and read back unchanged.
+    pub fn from_test(test: &PolicyTest) -> Result<Self, String> {
+        let args = test
+            .args
+            .iter()
+            .map(|arg| {
+                arg.to_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| format!("argument is not UTF-8: {}", arg.to_string_lossy()))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            uid: test.uid,
+            callers: test.callers.clone(),
+            target: test.target.clone(),
+            args,
+        })
+    }
+
+    /// The decision `engine` makes for this input now, as
+    /// [`test_policy::decision_label`] words it
+    pub fn decide(&self, engine: &PolicyEngine) -> String {
+        let args: Vec<OsString> = self.args.iter().map(OsString::from).collect();
+        let explanation =
+            test_policy::evaluate_command(engine, self.uid, &self.callers, &self.target, &args);
+        test_policy::decision_label(&explanation.decision)
+    }
+}
+
+/// An input and the decision it got when it was recorded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecisionRecord {
+    #[serde(flatten)]
+    pub input: DecisionInput,
+    pub decision: String,
+}
+
+impl DecisionRecord {
+    pub fn record(engine: &PolicyEngine, input: DecisionInput) -> Self {
+        let decision = input.decide(engine);
+        Self { input, decision }
+    }
+}
+
+/// Where recordings are replayed from, and the policy they are replayed
+/// through
+#[derive(Debug, PartialEq)]
+pub struct Verification {
+    pub dir: PathBuf,
+    pub policy_dir: PathBuf,
+    /// As for `--test-policy`: only the default file goes with the default
+    /// directory
+    pub policy_file: Option<PathBuf>,
+}
+
+/// Split the arguments following `--record` into the recordings directory
+/// and the decision to record
+pub fn parse_record_args(args: &[OsString]) -> Result<(PathBuf, PolicyTest), String> {
+    let (dir, rest) = args.split_first().ok_or("--record requires a directory")?;
+    Ok((PathBuf::from(dir), test_policy::parse_args(rest)?))
+}
+
+/// Parse the arguments following `--verify-recordings`
+pub fn parse_verify_args(args: &[OsString]) -> Result<Verification, String> {
+    let mut iter = args.iter();
+    let dir = iter
+        .next()
+        .ok_or("--verify-recordings requires a directory")?;
+    let mut policy_dir = None;
+    let mut policy_file = None;
+    while let Some(arg) = iter.next() {
+        if arg != "--policy-dir" && arg != "--policy-file" {
+            return Err(format!("unexpected argument: {}", arg.to_string_lossy()));
+        }
+        let value = iter
+            .next()
+            .ok_or_else(|| format!("{} requires an argument", arg.to_string_lossy()))?;
+        if arg == "--policy-dir" {
+            policy_dir = Some(PathBuf::from(value));
+        } else {
+            policy_file = Some(PathBuf::from(value));
+        }
+    }
+    if policy_dir.is_none() && policy_file.is_none() {
+        policy_file = Some(PathBuf::from(POLICY_FILE));
+    }
+    Ok(Verification {
+        dir: PathBuf::from(dir),
+        policy_dir: policy_dir.unwrap_or_else(|| PathBuf::from(POLICY_DIR)),
+        policy_file,
+    })
+}
+
+/// Write `record` to a new file in `dir`, named after its target's file
+/// name (`id.toml`, then `id-2.toml`, ...), and return its path
+pub fn save(dir: &Path, record: &DecisionRecord) -> io::Result<PathBuf> {
+    let text = toml::to_string(record).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+    fs::create_dir_all(dir)?;
+    let name = record
+        .input
+        .target
+        .file_name()
+        .map_or("recording".into(), |name| name.to_string_lossy());
+    for n in 1.. {
+        let path = match n {
+            1 => dir.join(format!("{name}.toml")),
+            n => dir.join(format!("{name}-{n}.toml")),
+        };
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(text.as_bytes())?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!("ran out of file names")
+}
+
+/// Every `*.toml` recording in `dir`, in file name order
+pub fn load_dir(dir: &Path) -> Result<Vec<(PathBuf, DecisionRecord)>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("{}: {}", dir.display(), e))?
+            .path();
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| {
+            let text =
+                fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let record = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+            Ok((path, record))
+        })
+        .collect()
+}
+
+/// One line for each recording whose decision `engine` no longer makes
+pub fn changed(engine: &PolicyEngine, recordings: &[(PathBuf, DecisionRecord)]) -> Vec<String> {
+    recordings
+        .iter()
+        .filter_map(|(path, record)| {
+            let now = record.input.decide(engine);
+            (now != record.decision).then(|| {
+                format!(
+                    "{}: {} was {}, now {}",
+                    path.display(),
+                    record.input.target.display(),
+                    record.decision,
+                    now
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = r#"
+        [[rules]]
+        target = "/usr/bin/foo"
+        allow_callers = ["/usr/bin/claude"]
+        auth = "none"
+
+        [[rules]]
+        target = "/usr/bin/bar"
+        allow_users = ["root"]
+        auth = "password"
+    "#;
+
+    fn engine(policy: &str) -> PolicyEngine {
+        let mut engine = PolicyEngine::new();
+        engine.load_from_str(policy).unwrap();
+        engine
+    }
+
+    fn input(target: &str, callers: &[&str]) -> DecisionInput {
+        DecisionInput {
+            uid: 4242424,
+            callers: callers.iter().map(PathBuf::from).collect(),
+            target: PathBuf::from(target),
+            args: vec!["--flag".into()],
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("authsudo-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn parses_record_and_verify_arguments() {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+
+        let (dir, test) =
+            parse_record_args(&args(&["/tmp/rec", "--uid", "0", "/usr/bin/id", "-u"])).unwrap();
+        assert_eq!(dir, PathBuf::from("/tmp/rec"));
+        assert_eq!(test.target, PathBuf::from("/usr/bin/id"));
+        assert_eq!(test.args, vec![OsString::from("-u")]);
+        assert_eq!(
+            parse_record_args(&[]).unwrap_err(),
+            "--record requires a directory"
+        );
+
+        assert_eq!(
+            parse_verify_args(&args(&["/tmp/rec", "--policy-dir", "/tmp/policies"])).unwrap(),
+            Verification {
+                dir: PathBuf::from("/tmp/rec"),
+                policy_dir: PathBuf::from("/tmp/policies"),
+                policy_file: None,
+            }
+        );
+        assert_eq!(
+            parse_verify_args(&args(&["/tmp/rec"])).unwrap().policy_file,
+            Some(PathBuf::from(POLICY_FILE))
+        );
+        assert_eq!(
+            parse_verify_args(&args(&["/tmp/rec", "--policy-dir"])).unwrap_err(),
+            "--policy-dir requires an argument"
+        );
+        assert_eq!(
+            parse_verify_args(&args(&["/tmp/rec", "extra"])).unwrap_err(),
+            "unexpected argument: extra"
+        );
+    }
+
+    #[test]
+    fn records_survive_a_round_trip_through_a_file() {
+        let dir = temp_dir("record");
+        let record =
+            DecisionRecord::record(&engine(POLICY), input("/usr/bin/foo", &["/usr/bin/claude"]));
+        assert_eq!(record.decision, "allow");
+
+        let first = save(&dir, &record).unwrap();
+        let second = save(&dir, &record).unwrap();
+        assert_eq!(first, dir.join("foo.toml"));
+        assert_eq!(second, dir.join("foo-2.toml"));
+        assert_eq!(
+            load_dir(&dir).unwrap(),
+            vec![(first, record.clone()), (second, record)]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recordings_matching_the_current_policy_pass() {
+        let engine = engine(POLICY);
+        let recordings = vec![
+            (
+                PathBuf::from("foo.toml"),
+                DecisionRecord::record(&engine, input("/usr/bin/foo", &["/usr/bin/claude"])),
+            ),
+            (
+                PathBuf::from("bar.toml"),
+                DecisionRecord::record(&engine, input("/usr/bin/bar", &[])),
+            ),
+        ];
+        assert!(changed(&engine, &recordings).is_empty());
+    }
+
+    #[test]
+    fn a_changed_policy_is_flagged() {
+        let recordings = vec![(
+            PathBuf::from("foo.toml"),
+            DecisionRecord::record(&engine(POLICY), input("/usr/bin/foo", &["/usr/bin/claude"])),
+        )];
+        let edited = POLICY.replacen(r#"auth = "none""#, r#"auth = "confirm""#, 1);
+
+        assert_eq!(
+            changed(&engine(&edited), &recordings),
+            vec!["foo.toml: /usr/bin/foo was allow, now confirm"]
+        );
+    }
+
+    #[test]
+    fn unreadable_recordings_name_their_file() {
+        let dir = temp_dir("bad-record");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("broken.toml"), "uid = \"root\"\n").unwrap();
+        fs::write(dir.join("notes.txt"), "not a recording").unwrap();
+
+        let error = load_dir(&dir).unwrap_err();
+        assert!(
+            error.starts_with(&dir.join("broken.toml").display().to_string()),
+            "{error}"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
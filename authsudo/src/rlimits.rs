@@ -0,0 +1,71 @@
+//! A rule's `limits`, applied to authsudo itself right before exec so the
+//! target inherits them
+//!
+//! Soft and hard limits are both lowered, and only root can raise a hard
+//! limit again: the target can't undo them once it runs as another user with
+//! `-u`. Root itself ignores `nproc` (the kernel exempts `CAP_SYS_RESOURCE`).
+
+use authd_protocol::limits::ResourceLimits;
+use std::io;
+
+/// Set each limit in `limits`. Async-signal-safe, so it can run in a
+/// `pre_exec` hook.
+#[cfg_attr(coverage, allow(dead_code))]
+pub fn apply(limits: &ResourceLimits) -> io::Result<()> {
+    let resources = [
+        (libc::RLIMIT_NOFILE, limits.nofile),
+        (libc::RLIMIT_NPROC, limits.nproc),
+        (libc::RLIMIT_AS, limits.address_space),
+        (libc::RLIMIT_CPU, limits.cpu),
+    ];
+    for (resource, limit) in resources {
+        let Some(limit) = limit else {
+            continue;
+        };
+        let value = libc::rlimit {
+            rlim_cur: limit.get() as libc::rlim_t,
+            rlim_max: limit.get() as libc::rlim_t,
+        };
+        if unsafe { libc::setrlimit(resource, &value) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    /// `ulimit` output of a shell started under `limits`
+    fn ulimit_under(limits: ResourceLimits, flag: &str) -> String {
+        let mut shell = Command::new("/bin/sh");
+        shell.args(["-c", &format!("ulimit -H {flag}; ulimit -S {flag}")]);
+        unsafe { shell.pre_exec(move || apply(&limits)) };
+        let output = shell.output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn targets_inherit_soft_and_hard_limits() {
+        let limits = ResourceLimits {
+            nofile: std::num::NonZeroU64::new(64),
+            ..ResourceLimits::default()
+        };
+        assert_eq!(ulimit_under(limits, "-n"), "64\n64\n");
+
+        let limits = ResourceLimits {
+            cpu: std::num::NonZeroU64::new(30),
+            ..ResourceLimits::default()
+        };
+        assert_eq!(ulimit_under(limits, "-t"), "30\n30\n");
+    }
+
+    #[test]
+    fn no_limits_leave_the_process_alone() {
+        assert!(apply(&ResourceLimits::default()).is_ok());
+    }
+}
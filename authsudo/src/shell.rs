@@ -0,0 +1,239 @@
+//! `authsudo -s` / `authsudo -i`: a shell as the target user
+//!
+//! The shell comes from the target user's passwd entry and is itself the
+//! policy target, so shell access is granted by a rule for e.g.
+//! `/usr/bin/bash` (or by `*`, which allows running a shell anyway). It must
+//! also be listed in `/etc/shells`; `nologin` and friends never are.
+//!
+//! `-s` keeps the caller's environment unless `clean_env` cleans it as for
+//! any command (see `environment`). `-i` starts a login shell the way
+//! `login` would: a fresh environment with only `TERM` and `LANG` carried
+//! over, `HOME`/`USER`/`LOGNAME`/`SHELL`/`PATH` for the target user, and the
+//! working directory set to their home.
+
+use std::collections::BTreeMap;
+use std::ffi::{CStr, OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+/// Where valid login shells are listed
+pub const SHELLS: &str = "/etc/shells";
+
+/// `PATH` of a login shell started with `-i`
+pub const LOGIN_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Carried over from the caller's environment into a login shell
+const LOGIN_KEEP: &[&str] = &["TERM", "LANG"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellMode {
+    /// `-s`: the target user's shell in the caller's environment, cleaned
+    /// under `clean_env`
+    Shell,
+    /// `-i`: a login shell with a fresh environment, in the user's home
+    Login,
+}
+
+/// The passwd fields a shell needs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub name: OsString,
+    pub home: PathBuf,
+    pub shell: PathBuf,
+}
+
+impl Account {
+    pub fn of(uid: u32) -> Option<Self> {
+        fn field(ptr: *const libc::c_char) -> OsString {
+            OsString::from_vec(unsafe { CStr::from_ptr(ptr) }.to_bytes().to_vec())
+        }
+        unsafe {
+            let pwd = libc::getpwuid(uid);
+            if pwd.is_null() {
+                return None;
+            }
+            Some(Self {
+                name: field((*pwd).pw_name),
+                home: PathBuf::from(field((*pwd).pw_dir)),
+                shell: PathBuf::from(field((*pwd).pw_shell)),
+            })
+        }
+    }
+}
+
+/// Refuse shells not listed in `/etc/shells` (its contents, or `None` if it
+/// doesn't exist, in which case only `nologin`/`false` are refused)
+pub fn check_shell(shell: &Path, etc_shells: Option<&str>) -> Result<(), String> {
+    let listed = match etc_shells {
+        Some(content) => content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .any(|line| Path::new(line) == shell),
+        None => !matches!(
+            shell.file_name().and_then(OsStr::to_str),
+            Some("nologin" | "false") | None
+        ),
+    };
+    if listed {
+        Ok(())
+    } else {
+        Err(format!("{} is not a valid login shell", shell.display()))
+    }
+}
+
+/// Shell arguments: none for an interactive shell, else `-c` with the
+/// command quoted back into one line
+pub fn args(command: &[OsString]) -> Vec<OsString> {
+    if command.is_empty() {
+        return Vec::new();
+    }
+    vec![OsString::from("-c"), command_line(command)]
+}
+
+/// Single-quote every word so the shell runs exactly these arguments
+pub fn command_line(command: &[OsString]) -> OsString {
+    let words: Vec<Vec<u8>> = command
+        .iter()
+        .map(|word| {
+            let mut quoted = vec![b'\''];
+            for &byte in word.as_bytes() {
+                if byte == b'\'' {
+                    quoted.extend_from_slice(b"'\\''");
+                } else {
+                    quoted.push(byte);
+                }
+            }
+            quoted.push(b'\'');
+            quoted
+        })
+        .collect();
+    OsString::from_vec(words.join(&b' '))
+}
+
+/// `argv[0]` of a login shell: its name with a leading `-`
+pub fn login_arg0(shell: &Path) -> OsString {
+    let mut arg0 = OsString::from("-");
+    arg0.push(shell.file_name().unwrap_or(shell.as_os_str()));
+    arg0
+}
+
+/// The shell's complete environment, sorted by name
+pub fn environment(
+    mode: ShellMode,
+    account: &Account,
+    inherited: impl IntoIterator<Item = (OsString, OsString)>,
+) -> Vec<(OsString, OsString)> {
+    let mut env: BTreeMap<OsString, OsString> = inherited
+        .into_iter()
+        .filter(|(name, _)| kept(mode, name))
+        .collect();
+    env.insert("SHELL".into(), account.shell.clone().into_os_string());
+    if mode == ShellMode::Login {
+        env.insert("HOME".into(), account.home.clone().into_os_string());
+        env.insert("USER".into(), account.name.clone());
+        env.insert("LOGNAME".into(), account.name.clone());
+        env.insert("PATH".into(), LOGIN_PATH.into());
+    }
+    env.into_iter().collect()
+}
+
+fn kept(mode: ShellMode, name: &OsStr) -> bool {
+    mode == ShellMode::Shell || LOGIN_KEEP.iter().any(|&keep| name == keep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account() -> Account {
+        Account {
+            name: "root".into(),
+            home: PathBuf::from("/root"),
+            shell: PathBuf::from("/usr/bin/zsh"),
+        }
+    }
+
+    fn caller_env() -> Vec<(OsString, OsString)> {
+        [
+            ("HOME", "/home/alice"),
+            ("PATH", "/home/alice/bin:/usr/bin"),
+            ("TERM", "xterm-256color"),
+            ("SHELL", "/usr/bin/bash"),
+            ("EDITOR", "vim"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.into(), value.into()))
+        .collect()
+    }
+
+    fn env_of(env: &[(OsString, OsString)]) -> Vec<(&str, &str)> {
+        env.iter()
+            .map(|(name, value)| (name.to_str().unwrap(), value.to_str().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn shell_mode_keeps_the_callers_environment() {
+        let env = environment(ShellMode::Shell, &account(), caller_env());
+        assert_eq!(
+            env_of(&env),
+            vec![
+                ("EDITOR", "vim"),
+                ("HOME", "/home/alice"),
+                ("PATH", "/home/alice/bin:/usr/bin"),
+                ("SHELL", "/usr/bin/zsh"),
+                ("TERM", "xterm-256color"),
+            ]
+        );
+    }
+
+    #[test]
+    fn login_mode_starts_from_a_clean_environment() {
+        let env = environment(ShellMode::Login, &account(), caller_env());
+        assert_eq!(
+            env_of(&env),
+            vec![
+                ("HOME", "/root"),
+                ("LOGNAME", "root"),
+                ("PATH", LOGIN_PATH),
+                ("SHELL", "/usr/bin/zsh"),
+                ("TERM", "xterm-256color"),
+                ("USER", "root"),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_listed_shells_are_allowed() {
+        let etc_shells =
+            "# Pathnames of valid login shells.\n/bin/sh\n/usr/bin/bash\n/usr/bin/zsh\n";
+
+        assert!(check_shell(Path::new("/usr/bin/zsh"), Some(etc_shells)).is_ok());
+        let error = check_shell(Path::new("/usr/bin/nologin"), Some(etc_shells)).unwrap_err();
+        assert_eq!(error, "/usr/bin/nologin is not a valid login shell");
+        assert!(check_shell(Path::new("/tmp/evil"), Some(etc_shells)).is_err());
+    }
+
+    #[test]
+    fn without_etc_shells_only_nologin_and_false_are_refused() {
+        assert!(check_shell(Path::new("/usr/bin/bash"), None).is_ok());
+        assert!(check_shell(Path::new("/usr/sbin/nologin"), None).is_err());
+        assert!(check_shell(Path::new("/bin/false"), None).is_err());
+    }
+
+    #[test]
+    fn commands_are_quoted_for_the_shell() {
+        assert!(args(&[]).is_empty());
+        let command = [OsString::from("echo"), "it's $HOME".into()];
+        assert_eq!(
+            args(&command),
+            vec![OsString::from("-c"), "'echo' 'it'\\''s $HOME'".into()]
+        );
+    }
+
+    #[test]
+    fn login_shells_get_a_dash_in_argv0() {
+        assert_eq!(login_arg0(Path::new("/usr/bin/zsh")), "-zsh");
+    }
+}
@@ -0,0 +1,585 @@
+//! `authsudo --import-sudoers`: translate a sudoers file into authd rules
+//!
+//! Understands the common subset: user specs with users, `%groups` and
+//! `User_Alias`es, `ALL` hosts, `(ALL)`/`(root)` runas, the `NOPASSWD` and
+//! `PASSWD` tags, and command lists of plain paths, `Cmnd_Alias`es or `ALL`.
+//! Anything authd can't express exactly (argument restrictions, negation,
+//! other hosts or runas users, `NOEXEC`, ...) is reported as a warning and
+//! left out, since a looser rule would grant more than sudo did.
+
+use authd_protocol::limits::ResourceLimits;
+use authd_protocol::{AuthMethod, AuthRequirement, MethodSet, PolicyRule};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+pub const DEFAULT_SUDOERS: &str = "/etc/sudoers";
+
+pub const USAGE: &str = "usage: authsudo --import-sudoers [file]";
+
+/// Runas specs whose authd equivalent (root, or `-u` any user) grants nothing
+/// sudo didn't: running as another user is never more than running as root.
+const ROOT_RUNAS: &[&str] = &[
+    "",
+    "ALL",
+    "root",
+    "ALL:ALL",
+    "root:root",
+    "ALL:root",
+    "root:ALL",
+];
+
+/// Guards against `Cmnd_Alias`es that refer to each other in a cycle
+const MAX_ALIAS_DEPTH: usize = 16;
+
+/// Tags that don't change who may run what
+const IGNORED_TAGS: &[&str] = &[
+    "EXEC",
+    "NOINTERCEPT",
+    "SETENV",
+    "NOSETENV",
+    "LOG_INPUT",
+    "NOLOG_INPUT",
+    "LOG_OUTPUT",
+    "NOLOG_OUTPUT",
+    "MAIL",
+    "NOMAIL",
+    "FOLLOW",
+    "NOFOLLOW",
+];
+
+/// A generated rule and the sudoers line it came from
+#[derive(Debug, Clone)]
+pub struct ImportedRule {
+    pub line: usize,
+    pub rule: PolicyRule,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Import {
+    pub rules: Vec<ImportedRule>,
+    pub warnings: Vec<Warning>,
+}
+
+#[derive(Debug, Default)]
+struct Aliases {
+    users: HashMap<String, Vec<String>>,
+    commands: HashMap<String, Vec<String>>,
+}
+
+/// Parse sudoers `content` into rules plus warnings for what was skipped.
+pub fn import(content: &str) -> Import {
+    let mut import = Import::default();
+    let mut aliases = Aliases::default();
+
+    for (line, text) in logical_lines(content) {
+        let result = parse_line(&text, &mut aliases);
+        match result {
+            Ok(rules) => import
+                .rules
+                .extend(rules.into_iter().map(|rule| ImportedRule { line, rule })),
+            Err(message) => import.warnings.push(Warning { line, message }),
+        }
+    }
+
+    import
+}
+
+/// Join `\`-continued lines and drop comments and blanks. Yields the 1-based
+/// number of each logical line's first physical line.
+fn logical_lines(content: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+
+    for (index, raw) in content.lines().enumerate() {
+        let (start, mut text) = pending.take().unwrap_or((index + 1, String::new()));
+        match raw.strip_suffix('\\') {
+            Some(continued) => {
+                text.push_str(continued);
+                text.push(' ');
+                pending = Some((start, text));
+            }
+            None => {
+                text.push_str(raw);
+                let text = strip_comment(&text).trim().to_string();
+                if !text.is_empty() {
+                    lines.push((start, text));
+                }
+            }
+        }
+    }
+    if let Some((start, text)) = pending {
+        let text = strip_comment(&text).trim().to_string();
+        if !text.is_empty() {
+            lines.push((start, text));
+        }
+    }
+
+    lines
+}
+
+/// `#` starts a comment, except `#include`/`#includedir` (kept so they can be
+/// reported) and `#<uid>` user ids.
+fn strip_comment(text: &str) -> &str {
+    let trimmed = text.trim_start();
+    if trimmed.starts_with("#include") {
+        return text;
+    }
+    let bytes = text.as_bytes();
+    for (index, byte) in bytes.iter().enumerate() {
+        let is_uid = bytes.get(index + 1).is_some_and(u8::is_ascii_digit);
+        if *byte == b'#' && !is_uid {
+            return &text[..index];
+        }
+    }
+    text
+}
+
+fn parse_line(text: &str, aliases: &mut Aliases) -> Result<Vec<PolicyRule>, String> {
+    let keyword = text.split_whitespace().next().unwrap_or_default();
+    match keyword {
+        "#include" | "#includedir" | "@include" | "@includedir" => Err(format!(
+            "{keyword} not followed; convert included files separately"
+        )),
+        _ if keyword.starts_with("Defaults") => Err("Defaults ignored".into()),
+        "User_Alias" => {
+            define_aliases(&text[keyword.len()..], &mut aliases.users).map(|()| Vec::new())
+        }
+        "Cmnd_Alias" | "Cmd_Alias" => {
+            define_aliases(&text[keyword.len()..], &mut aliases.commands).map(|()| Vec::new())
+        }
+        "Host_Alias" | "Runas_Alias" => Err(format!("{keyword} not supported")),
+        _ => parse_user_spec(text, aliases),
+    }
+}
+
+/// `NAME = a, b : OTHER = c`
+fn define_aliases(text: &str, aliases: &mut HashMap<String, Vec<String>>) -> Result<(), String> {
+    for definition in text.split(':') {
+        let (name, members) = definition
+            .split_once('=')
+            .ok_or_else(|| format!("malformed alias {:?}", definition.trim()))?;
+        let name = name.trim();
+        if !is_alias_name(name) {
+            return Err(format!("invalid alias name {name:?}"));
+        }
+        aliases.insert(name.to_string(), split_list(members));
+    }
+    Ok(())
+}
+
+/// `users hosts = cmnd_spec, cmnd_spec, ...`
+fn parse_user_spec(text: &str, aliases: &Aliases) -> Result<Vec<PolicyRule>, String> {
+    let (lhs, rhs) = text
+        .split_once('=')
+        .ok_or_else(|| format!("unrecognized line {text:?}"))?;
+    if rhs.contains('=') {
+        return Err("multiple host specs on one line not supported".into());
+    }
+
+    let lhs = normalize_commas(lhs);
+    let words: Vec<&str> = lhs.split_whitespace().collect();
+    let [users, hosts] = words[..] else {
+        return Err(format!("unrecognized user spec {:?}", lhs.trim()));
+    };
+    if hosts.split(',').any(|host| host != "ALL") {
+        return Err(format!("host restriction {hosts:?} not supported"));
+    }
+    let (allow_users, allow_groups) = principals(users, aliases)?;
+
+    let mut rules = Vec::new();
+    let mut runas = String::new();
+    let mut auth = AuthRequirement::Password;
+    for spec in split_list(rhs) {
+        let command = parse_command_spec(&spec, &mut runas, &mut auth)?;
+        for target in command_targets(command, aliases, 0)? {
+            rules.push(PolicyRule {
+                target,
+                allow_groups: allow_groups.clone(),
+                allow_users: allow_users.clone(),
+                allow_callers: Vec::new(),
+                allow_units: Vec::new(),
+                auth: auth.clone(),
+                cache_timeout: 300,
+                log_output: None,
+                enabled: true,
+                min_binary_age_secs: None,
+                require_approver_group: None,
+                allow_session_types: Vec::new(),
+                allow_contexts: Vec::new(),
+                notify_webhook: None,
+                allow_scripts: Vec::new(),
+                allow_argv0: Vec::new(),
+                forbid_setuid_target: false,
+                forbid_file_caps: false,
+                priority: 0,
+                limits: ResourceLimits::default(),
+                max_concurrent: None,
+                min_caller_age_secs: None,
+                runas: None,
+                require_type_confirm: false,
+                forbid_if_others_logged_in: false,
+                pam_service: None,
+                command: None,
+                confirm_cache_timeout: None,
+                clean_env: None,
+            });
+        }
+    }
+    Ok(rules)
+}
+
+/// Split the user list into (users, groups), expanding `User_Alias`es
+fn principals(users: &str, aliases: &Aliases) -> Result<(Vec<String>, Vec<String>), String> {
+    let mut names = Vec::new();
+    let mut groups = Vec::new();
+    let mut expanded = HashSet::new();
+    let mut pending: Vec<String> = users.split(',').map(str::to_string).collect();
+
+    while let Some(user) = pending.pop() {
+        if let Some(members) = aliases.users.get(&user) {
+            // An alias that (indirectly) includes itself adds nothing new
+            if expanded.insert(user) {
+                pending.extend(members.iter().cloned());
+            }
+        } else if user == "ALL" {
+            return Err("ALL users can't be expressed; grant a group instead".into());
+        } else if let Some(group) = user.strip_prefix('%') {
+            if group.starts_with(['#', ':']) {
+                return Err(format!("group {user:?} not supported"));
+            }
+            groups.push(group.to_string());
+        } else if user.starts_with(['!', '#', '+']) || is_alias_name(&user) {
+            return Err(format!("user {user:?} not supported"));
+        } else {
+            names.push(user);
+        }
+    }
+
+    names.reverse();
+    groups.reverse();
+    Ok((names, groups))
+}
+
+/// Strip a command spec's runas and tags, which carry over to later specs on
+/// the same line, and return the command itself.
+fn parse_command_spec<'a>(
+    spec: &'a str,
+    runas: &mut String,
+    auth: &mut AuthRequirement,
+) -> Result<&'a str, String> {
+    let mut rest = spec.trim();
+
+    if let Some(after) = rest.strip_prefix('(') {
+        let (inner, after) = after
+            .split_once(')')
+            .ok_or_else(|| format!("unterminated runas in {spec:?}"))?;
+        *runas = inner.split_whitespace().collect::<Vec<_>>().join("");
+        rest = after.trim_start();
+    }
+    if !ROOT_RUNAS.contains(&runas.as_str()) {
+        return Err(format!("runas ({runas}) not supported"));
+    }
+
+    while let Some((tag, after)) = rest.split_once(':') {
+        if tag.is_empty() || !tag.chars().all(|c| c.is_ascii_uppercase() || c == '_') {
+            break;
+        }
+        match tag {
+            "NOPASSWD" => *auth = AuthRequirement::None,
+            "PASSWD" => *auth = AuthRequirement::Password,
+            _ if IGNORED_TAGS.contains(&tag) => {}
+            _ => return Err(format!("tag {tag} not supported")),
+        }
+        rest = after.trim_start();
+    }
+
+    Ok(rest)
+}
+
+fn command_targets(command: &str, aliases: &Aliases, depth: usize) -> Result<Vec<PathBuf>, String> {
+    if depth > MAX_ALIAS_DEPTH {
+        return Err(format!("Cmnd_Alias {command} nests too deeply"));
+    }
+    if command == "ALL" {
+        return Ok(vec![PathBuf::from("*")]);
+    }
+    if let Some(members) = aliases.commands.get(command) {
+        let mut targets = Vec::new();
+        for member in members {
+            targets.extend(command_targets(member, aliases, depth + 1)?);
+        }
+        return Ok(targets);
+    }
+    if command.starts_with('!') {
+        return Err(format!("negated command {command:?} can't be expressed"));
+    }
+
+    let mut words = command.split_whitespace();
+    let path = words.next().unwrap_or_default();
+    if words.next().is_some() {
+        return Err(format!(
+            "argument restrictions in {command:?} not supported"
+        ));
+    }
+    let exact = path.starts_with('/') && !path.ends_with('/') && !path.contains(['*', '?', '[']);
+    if !exact {
+        return Err(format!("command {path:?} not supported; only exact paths"));
+    }
+    Ok(vec![PathBuf::from(path)])
+}
+
+/// Render rules as a policy file, each preceded by its source line number
+pub fn to_toml(rules: &[ImportedRule]) -> String {
+    let mut out = String::new();
+    for imported in rules {
+        let rule = &imported.rule;
+        out.push_str(&format!("# sudoers line {}\n[[rules]]\n", imported.line));
+        out.push_str(&format!(
+            "target = {}\n",
+            toml_string(&rule.target.to_string_lossy())
+        ));
+        if !rule.allow_users.is_empty() {
+            out.push_str(&format!("allow_users = {}\n", toml_list(&rule.allow_users)));
+        }
+        if !rule.allow_groups.is_empty() {
+            out.push_str(&format!(
+                "allow_groups = {}\n",
+                toml_list(&rule.allow_groups)
+            ));
+        }
+        out.push_str(&format!("auth = {}\n\n", toml_auth(&rule.auth)));
+    }
+    out
+}
+
+/// `auth` as a TOML value: a word, a list of methods or a table with `all`
+fn toml_auth(auth: &AuthRequirement) -> String {
+    let names = |methods: &[AuthMethod]| {
+        let names: Vec<String> = methods.iter().map(ToString::to_string).collect();
+        toml_list(&names)
+    };
+    match auth {
+        AuthRequirement::Methods(MethodSet::Any(methods)) => names(methods),
+        AuthRequirement::Methods(MethodSet::All(methods)) => {
+            format!("{{ all = {} }}", names(methods))
+        }
+        word => toml_string(&word.to_string()),
+    }
+}
+
+pub fn toml_list(items: &[String]) -> String {
+    let items: Vec<String> = items.iter().map(|item| toml_string(item)).collect();
+    format!("[{}]", items.join(", "))
+}
+
+/// A TOML basic string
+pub fn toml_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Split a comma list, ignoring commas inside a `(runas)` group
+fn split_list(text: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0usize;
+    for c in text.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                items.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    items.push(current.trim().to_string());
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+/// `alice , bob` -> `alice,bob`, so the user and host lists are single words
+fn normalize_commas(text: &str) -> String {
+    text.split(',').map(str::trim).collect::<Vec<_>>().join(",")
+}
+
+fn is_alias_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use authd_policy::{PolicyDecision, PolicyEngine};
+    use std::path::Path;
+
+    fn targets(import: &Import) -> Vec<String> {
+        import
+            .rules
+            .iter()
+            .map(|imported| imported.rule.target.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    fn engine(import: &Import) -> PolicyEngine {
+        let mut engine = PolicyEngine::new();
+        engine.load_from_str(&to_toml(&import.rules)).unwrap();
+        engine
+    }
+
+    #[test]
+    fn converts_group_and_user_specs() {
+        let import = import(
+            "# comment\n\
+             %wheel ALL=(ALL:ALL) ALL\n\
+             alice, %admin ALL = (root) NOPASSWD: /usr/bin/pacman, /usr/bin/systemctl\n",
+        );
+
+        assert!(import.warnings.is_empty(), "{:?}", import.warnings);
+        assert_eq!(
+            targets(&import),
+            vec!["*", "/usr/bin/pacman", "/usr/bin/systemctl"]
+        );
+
+        let wheel = &import.rules[0];
+        assert_eq!(wheel.line, 2);
+        assert_eq!(wheel.rule.allow_groups, vec!["wheel"]);
+        assert_eq!(wheel.rule.auth, AuthRequirement::Password);
+
+        let pacman = &import.rules[1].rule;
+        assert_eq!(pacman.allow_users, vec!["alice"]);
+        assert_eq!(pacman.allow_groups, vec!["admin"]);
+        assert_eq!(pacman.auth, AuthRequirement::None);
+    }
+
+    #[test]
+    fn tags_carry_over_until_changed() {
+        let import = import("root ALL = NOPASSWD: /bin/a, /bin/b, PASSWD: /bin/c\n");
+
+        let auths: Vec<AuthRequirement> = import
+            .rules
+            .iter()
+            .map(|imported| imported.rule.auth.clone())
+            .collect();
+        assert_eq!(
+            auths,
+            vec![
+                AuthRequirement::None,
+                AuthRequirement::None,
+                AuthRequirement::Password
+            ]
+        );
+    }
+
+    #[test]
+    fn expands_user_and_command_aliases() {
+        let import = import(
+            "User_Alias ADMINS = alice, %ops\n\
+             Cmnd_Alias PKG = /usr/bin/pacman, \\\n    /usr/bin/paru\n\
+             ADMINS ALL = NOPASSWD: PKG\n",
+        );
+
+        assert!(import.warnings.is_empty(), "{:?}", import.warnings);
+        assert_eq!(targets(&import), vec!["/usr/bin/pacman", "/usr/bin/paru"]);
+        assert_eq!(import.rules[0].line, 4);
+        assert_eq!(import.rules[0].rule.allow_users, vec!["alice"]);
+        assert_eq!(import.rules[0].rule.allow_groups, vec!["ops"]);
+    }
+
+    #[test]
+    fn warns_instead_of_widening() {
+        let import = import(
+            "Defaults env_reset\n\
+             #includedir /etc/sudoers.d\n\
+             bob ALL = /usr/bin/systemctl restart nginx\n\
+             bob ALL = ALL, !/usr/bin/su\n\
+             bob ALL = (www-data) /usr/bin/php\n\
+             bob web01 = /usr/bin/id\n\
+             bob ALL = NOEXEC: /usr/bin/vi\n\
+             bob ALL = /usr/local/bin/*\n\
+             ALL ALL = /usr/bin/id\n",
+        );
+
+        assert!(import.rules.is_empty(), "{:?}", import.rules);
+        let lines: Vec<usize> = import.warnings.iter().map(|w| w.line).collect();
+        assert_eq!(lines, (1..=9).collect::<Vec<_>>());
+        assert!(import.warnings[2].message.contains("argument restrictions"));
+        assert!(import.warnings[3].message.contains("negated"));
+        assert!(import.warnings[4].message.contains("runas"));
+    }
+
+    #[test]
+    fn cyclic_aliases_terminate() {
+        let import = import(
+            "User_Alias A = alice, B\n\
+             User_Alias B = A\n\
+             Cmnd_Alias X = Y\n\
+             Cmnd_Alias Y = X\n\
+             A ALL = /usr/bin/id\n\
+             A ALL = X\n",
+        );
+
+        assert_eq!(targets(&import), vec!["/usr/bin/id"]);
+        assert_eq!(import.rules[0].rule.allow_users, vec!["alice"]);
+        assert!(import.warnings[0].message.contains("nests too deeply"));
+    }
+
+    #[test]
+    fn uid_specs_are_not_comments() {
+        let import = import("#1000 ALL = /usr/bin/id  # trailing comment\n");
+
+        assert!(import.rules.is_empty());
+        assert!(import.warnings[0].message.contains("#1000"));
+    }
+
+    #[test]
+    fn generated_policy_decodes_and_matches() {
+        let import = import(
+            "root ALL=(ALL) NOPASSWD: /usr/bin/pacman\n\
+             %root ALL=(ALL) /usr/bin/visudo\n",
+        );
+        let engine = engine(&import);
+
+        assert!(matches!(
+            engine.check(Path::new("/usr/bin/pacman"), 0),
+            PolicyDecision::AllowImmediate
+        ));
+        // PASSWD (the default) maps to auth = "password"
+        assert!(matches!(
+            engine.check(Path::new("/usr/bin/visudo"), 0),
+            PolicyDecision::AllowWithConfirm
+        ));
+        assert!(matches!(
+            engine.check(Path::new("/usr/bin/other"), 0),
+            PolicyDecision::Unknown
+        ));
+    }
+
+    #[test]
+    fn toml_strings_are_escaped() {
+        assert_eq!(toml_string("a\"b\\c"), r#""a\"b\\c""#);
+        assert_eq!(toml_string("tab\t"), r#""tab\u0009""#);
+    }
+}
@@ -0,0 +1,149 @@
+//! `forbid_setuid_target` and `forbid_file_caps`: refuse targets that carry
+//! privileges of their own
+//!
+//! Running a setuid/setgid binary or one with file capabilities from authsudo
+//! stacks its privileges on top of root's (or the `-u` user's), which a rule
+//! may not have meant to grant. File capabilities are the
+//! `security.capability` extended attribute that `cap_get_file` reads.
+
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+const CAPABILITY_XATTR: &[u8] = b"security.capability\0";
+
+/// The privileges a target binary carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetPrivileges {
+    /// The setuid or setgid bit is set
+    pub setid: bool,
+    pub file_caps: bool,
+}
+
+impl TargetPrivileges {
+    pub fn of(path: &Path) -> std::io::Result<Self> {
+        let mode = std::fs::metadata(path)?.mode();
+        Ok(Self {
+            setid: mode & (libc::S_ISUID | libc::S_ISGID) != 0,
+            file_caps: has_file_caps(path)?,
+        })
+    }
+}
+
+fn has_file_caps(path: &Path) -> std::io::Result<bool> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let size = unsafe {
+        libc::getxattr(
+            c_path.as_ptr(),
+            CAPABILITY_XATTR.as_ptr().cast(),
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if size >= 0 {
+        return Ok(true);
+    }
+    let error = std::io::Error::last_os_error();
+    match error.raw_os_error() {
+        // No such attribute, or a filesystem without extended attributes
+        Some(libc::ENODATA) | Some(libc::ENOTSUP) => Ok(false),
+        _ => Err(error),
+    }
+}
+
+/// Look at `path` and check it against what the rules forbid
+pub fn check_path(path: &Path, forbid_setid: bool, forbid_caps: bool) -> Result<(), String> {
+    let privileges =
+        TargetPrivileges::of(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    check(path, privileges, forbid_setid, forbid_caps)
+}
+
+pub fn check(
+    path: &Path,
+    privileges: TargetPrivileges,
+    forbid_setid: bool,
+    forbid_caps: bool,
+) -> Result<(), String> {
+    if forbid_setid && privileges.setid {
+        return Err(format!(
+            "{} is setuid or setgid; refusing under forbid_setuid_target",
+            path.display()
+        ));
+    }
+    if forbid_caps && privileges.file_caps {
+        return Err(format!(
+            "{} has file capabilities; refusing under forbid_file_caps",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn temp_binary(name: &str, mode: u32) -> std::path::PathBuf {
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("authsudo-privileges-{name}-{nonce}"));
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("tool");
+        File::create(&path).unwrap();
+        fs::set_permissions(&path, Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+    #[test]
+    fn setuid_fixture_is_refused_only_when_forbidden() {
+        let path = temp_binary("setuid", 0o4755);
+        assert!(TargetPrivileges::of(&path).unwrap().setid);
+
+        let error = check_path(&path, true, false).unwrap_err();
+        assert!(error.contains("forbid_setuid_target"), "{error}");
+        assert_eq!(check_path(&path, false, true), Ok(()));
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn plain_fixture_passes_both_checks() {
+        let path = temp_binary("plain", 0o755);
+        assert_eq!(
+            TargetPrivileges::of(&path).unwrap(),
+            TargetPrivileges {
+                setid: false,
+                file_caps: false,
+            }
+        );
+        assert_eq!(check_path(&path, true, true), Ok(()));
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn file_caps_are_refused_only_when_forbidden() {
+        // Setting file capabilities needs CAP_SETFCAP, so this one is built
+        let tool = Path::new("/usr/bin/ping");
+        let privileges = TargetPrivileges {
+            setid: false,
+            file_caps: true,
+        };
+
+        let error = check(tool, privileges, false, true).unwrap_err();
+        assert!(error.contains("forbid_file_caps"), "{error}");
+        assert_eq!(check(tool, privileges, true, false), Ok(()));
+    }
+
+    #[test]
+    fn missing_target_is_an_error() {
+        assert!(check_path(Path::new("/nonexistent/authsudo-tool"), true, true).is_err());
+    }
+}
@@ -0,0 +1,382 @@
+//! `authsudo --test-policy`: evaluate policies offline for a synthetic caller
+//!
+//! Loads a policy directory (and combined policy file) and prints what `check_explain` decides for a
+//! given uid, caller chain and command, without having to be that uid or run
+//! under that caller. Handy for CI of policy repositories. Restricted to root
+//! so it can't be used to probe policy as someone else.
+
+use authd_policy::{
+    CallerInfo, Explanation, Groups, POLICY_DIR, POLICY_FILE, PolicyDecision, PolicyEngine,
+};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+pub const USAGE: &str = "usage: authsudo --test-policy --uid <uid> [--caller <path>]... \
+                         [--policy-dir <dir>] [--policy-file <file>] <command> [args...]";
+
+#[derive(Debug)]
+pub struct PolicyTest {
+    pub uid: u32,
+    /// Caller chain, nearest ancestor first (as authsudo would see it)
+    pub callers: Vec<PathBuf>,
+    pub policy_dir: PathBuf,
+    /// Combined policy file read before `policy_dir`; only the default one
+    /// goes with the default directory
+    pub policy_file: Option<PathBuf>,
+    pub target: PathBuf,
+    pub args: Vec<OsString>,
+}
+
+/// Parse the arguments following `--test-policy`
+pub fn parse_args(args: &[OsString]) -> Result<PolicyTest, String> {
+    let mut uid = None;
+    let mut callers = Vec::new();
+    let mut policy_dir = None;
+    let mut policy_file = None;
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        if arg == "--uid" {
+            uid = Some(parse_uid(flag_value(&mut iter, "--uid")?)?);
+        } else if arg == "--caller" {
+            callers.push(PathBuf::from(flag_value(&mut iter, "--caller")?));
+        } else if arg == "--policy-dir" {
+            policy_dir = Some(PathBuf::from(flag_value(&mut iter, "--policy-dir")?));
+        } else if arg == "--policy-file" {
+            policy_file = Some(PathBuf::from(flag_value(&mut iter, "--policy-file")?));
+        } else {
+            let target = if arg == "--" { iter.next() } else { Some(arg) };
+            let target = target.ok_or("missing command")?;
+            if policy_dir.is_none() && policy_file.is_none() {
+                policy_file = Some(PathBuf::from(POLICY_FILE));
+            }
+            return Ok(PolicyTest {
+                uid: uid.ok_or("--uid is required")?,
+                callers,
+                policy_dir: policy_dir.unwrap_or_else(|| PathBuf::from(POLICY_DIR)),
+                policy_file,
+                target: PathBuf::from(target),
+                args: iter.cloned().collect(),
+            });
+        }
+    }
+
+    Err("missing command".into())
+}
+
+fn flag_value<'a>(
+    iter: &mut impl Iterator<Item = &'a OsString>,
+    flag: &str,
+) -> Result<&'a OsString, String> {
+    iter.next()
+        .ok_or_else(|| format!("{flag} requires an argument"))
+}
+
+fn parse_uid(value: &OsString) -> Result<u32, String> {
+    value
+        .to_str()
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| format!("invalid uid: {}", value.to_string_lossy()))
+}
+
+/// Run the policy check for a synthetic uid/caller chain, including
+/// `allow_scripts` against the command's arguments
+pub fn evaluate(engine: &PolicyEngine, test: &PolicyTest) -> Explanation {
+    evaluate_command(engine, test.uid, &test.callers, &test.target, &test.args)
+}
+
+/// [`evaluate`] for `target` run with `args` by `uid` through `callers`
+pub fn evaluate_command(
+    engine: &PolicyEngine,
+    uid: u32,
+    callers: &[PathBuf],
+    target: &Path,
+    args: &[OsString],
+) -> Explanation {
+    let callers: Vec<CallerInfo> = callers
+        .iter()
+        .map(|exe| CallerInfo {
+            exe,
+            cmdline_path: None,
+            secontext: None,
+            unit: None,
+        })
+        .collect();
+    let mut explanation =
+        engine.check_explain_command(target, args, uid, Groups::Nss, None, &callers);
+    if !matches!(
+        explanation.decision,
+        PolicyDecision::Denied(_) | PolicyDecision::Unknown
+    ) {
+        if let Some(reason) = engine.script_refusal(target, args) {
+            explanation.decision = PolicyDecision::Denied(reason);
+        }
+    }
+    explanation
+}
+
+/// Human-readable report of a decision
+pub fn report(test: &PolicyTest, explanation: &Explanation) -> String {
+    let mut lines = vec![
+        format!("target: {}", test.target.display()),
+        format!("uid: {}", test.uid),
+    ];
+    for caller in &test.callers {
+        lines.push(format!("caller: {}", caller.display()));
+    }
+    if !test.args.is_empty() {
+        let args: Vec<_> = test.args.iter().map(|arg| arg.to_string_lossy()).collect();
+        lines.push(format!("args: {}", args.join(" ")));
+    }
+    lines.extend(rule_lines(explanation));
+    lines.push(format!(
+        "decision: {}",
+        decision_label(&explanation.decision)
+    ));
+    lines.join("\n")
+}
+
+/// One line per rule considered, with its verdict and source file, then
+/// the disabled ones
+pub fn rule_lines(explanation: &Explanation) -> Vec<String> {
+    let mut lines = Vec::new();
+    for rule in &explanation.rules {
+        let auth = rule.auth.to_string();
+        let verdict = if rule.admits { "admits" } else { "rejects" };
+        let source = rule
+            .source
+            .as_ref()
+            .map(|source| format!(" ({})", source.display()))
+            .unwrap_or_default();
+        lines.push(format!(
+            "rule: {} auth={auth} {verdict}{source}",
+            rule.target.display()
+        ));
+    }
+    for target in &explanation.disabled {
+        lines.push(format!("rule: {} disabled", target.display()));
+    }
+    lines
+}
+
+pub fn decision_label(decision: &PolicyDecision) -> String {
+    match decision {
+        PolicyDecision::AllowImmediate => "allow".into(),
+        PolicyDecision::AllowWithConfirm => "confirm".into(),
+        PolicyDecision::Discouraged => "discouraged".into(),
+        PolicyDecision::Denied(reason) => format!("denied ({reason})"),
+        PolicyDecision::Unknown => "unknown (no matching rule)".into(),
+    }
+}
+
+/// 0 when the command would be allowed (possibly after interaction),
+/// 1 when denied or not covered by any rule
+pub fn exit_code(decision: &PolicyDecision) -> i32 {
+    match decision {
+        PolicyDecision::AllowImmediate
+        | PolicyDecision::AllowWithConfirm
+        | PolicyDecision::Discouraged => 0,
+        PolicyDecision::Denied(_) | PolicyDecision::Unknown => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = r#"
+        [[rules]]
+        target = "/usr/bin/foo"
+        allow_callers = ["/usr/bin/claude"]
+        auth = "none"
+
+        [[rules]]
+        target = "/usr/bin/foo"
+        allow_users = ["root"]
+        auth = "password"
+
+        [[rules]]
+        target = "/usr/bin/bar"
+        allow_users = ["root"]
+        auth = "deny"
+
+        [[rules]]
+        target = "/usr/bin/bar"
+        allow_users = ["root"]
+        auth = "none"
+        enabled = false
+
+        [[rules]]
+        target = "/usr/bin/python3"
+        allow_users = ["root"]
+        auth = "none"
+        allow_scripts = ["/opt/tools/*.py"]
+    "#;
+
+    /// A uid with no passwd entry, so only caller rules can admit it
+    const NOBODY_UID: &str = "4242424";
+
+    fn engine() -> PolicyEngine {
+        let mut engine = PolicyEngine::new();
+        engine.load_from_str(POLICY).unwrap();
+        engine
+    }
+
+    fn test_for(args: &[&str]) -> PolicyTest {
+        let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+        parse_args(&args).unwrap()
+    }
+
+    fn decision_for(args: &[&str]) -> PolicyDecision {
+        evaluate(&engine(), &test_for(args)).decision
+    }
+
+    #[test]
+    fn parses_flags_target_and_args() {
+        let test = test_for(&[
+            "--uid",
+            "1000",
+            "--caller",
+            "/usr/bin/claude",
+            "--caller",
+            "/usr/bin/bash",
+            "--policy-dir",
+            "/tmp/policies",
+            "/usr/bin/foo",
+            "--uid",
+            "arg",
+        ]);
+
+        assert_eq!(test.uid, 1000);
+        assert_eq!(
+            test.callers,
+            vec![
+                PathBuf::from("/usr/bin/claude"),
+                PathBuf::from("/usr/bin/bash")
+            ]
+        );
+        assert_eq!(test.policy_dir, PathBuf::from("/tmp/policies"));
+        assert_eq!(test.policy_file, None);
+        assert_eq!(test.target, PathBuf::from("/usr/bin/foo"));
+        assert_eq!(
+            test.args,
+            vec![OsString::from("--uid"), OsString::from("arg")]
+        );
+
+        let test = test_for(&["--uid", "0", "--", "--weird-name"]);
+        assert_eq!(test.target, PathBuf::from("--weird-name"));
+        assert_eq!(test.policy_dir, PathBuf::from(POLICY_DIR));
+        assert_eq!(test.policy_file, Some(PathBuf::from(POLICY_FILE)));
+
+        let test = test_for(&["--uid", "0", "--policy-file", "/tmp/policy.toml", "id"]);
+        assert_eq!(test.policy_file, Some(PathBuf::from("/tmp/policy.toml")));
+    }
+
+    #[test]
+    fn rejects_missing_or_invalid_inputs() {
+        let parse = |args: &[&str]| {
+            let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+            parse_args(&args).unwrap_err()
+        };
+
+        assert_eq!(parse(&["/usr/bin/foo"]), "--uid is required");
+        assert_eq!(parse(&["--uid", "x", "/usr/bin/foo"]), "invalid uid: x");
+        assert_eq!(parse(&["--uid"]), "--uid requires an argument");
+        assert_eq!(parse(&["--uid", "0"]), "missing command");
+        assert_eq!(parse(&["--uid", "0", "--"]), "missing command");
+    }
+
+    #[test]
+    fn trusted_caller_is_allowed_immediately() {
+        let decision = decision_for(&[
+            "--uid",
+            NOBODY_UID,
+            "--caller",
+            "/usr/bin/claude",
+            "/usr/bin/foo",
+        ]);
+        assert!(matches!(decision, PolicyDecision::AllowImmediate));
+        assert_eq!(exit_code(&decision), 0);
+    }
+
+    #[test]
+    fn interpreter_runs_only_allowed_scripts() {
+        let decision = decision_for(&["--uid", "0", "/usr/bin/python3", "/opt/tools/a.py"]);
+        assert!(matches!(decision, PolicyDecision::AllowImmediate));
+
+        for args in [&["-c", "evil"][..], &["/tmp/b.py"]] {
+            let mut test = vec!["--uid", "0", "/usr/bin/python3"];
+            test.extend(args);
+            let decision = decision_for(&test);
+            assert!(matches!(decision, PolicyDecision::Denied(_)), "{args:?}");
+            assert_eq!(exit_code(&decision), 1);
+        }
+    }
+
+    #[test]
+    fn user_rule_requires_interaction() {
+        let decision = decision_for(&["--uid", "0", "/usr/bin/foo"]);
+        assert!(matches!(decision, PolicyDecision::AllowWithConfirm));
+        assert_eq!(exit_code(&decision), 0);
+    }
+
+    #[test]
+    fn untrusted_caller_and_unlisted_user_is_denied() {
+        let decision = decision_for(&[
+            "--uid",
+            NOBODY_UID,
+            "--caller",
+            "/usr/bin/curl",
+            "/usr/bin/foo",
+        ]);
+        assert!(matches!(decision, PolicyDecision::Denied(_)));
+        assert_eq!(exit_code(&decision), 1);
+    }
+
+    #[test]
+    fn deny_rule_and_unknown_target() {
+        let denied = decision_for(&["--uid", "0", "/usr/bin/bar"]);
+        assert!(matches!(denied, PolicyDecision::Denied(_)));
+
+        let unknown = decision_for(&["--uid", "0", "/usr/bin/baz"]);
+        assert!(matches!(unknown, PolicyDecision::Unknown));
+        assert_eq!(exit_code(&unknown), 1);
+    }
+
+    #[test]
+    fn report_lists_rules_and_decision() {
+        let test = test_for(&[
+            "--uid",
+            NOBODY_UID,
+            "--caller",
+            "/usr/bin/claude",
+            "/usr/bin/foo",
+        ]);
+        let report = report(&test, &evaluate(&engine(), &test));
+
+        assert_eq!(
+            report,
+            "target: /usr/bin/foo\n\
+             uid: 4242424\n\
+             caller: /usr/bin/claude\n\
+             rule: /usr/bin/foo auth=none admits\n\
+             rule: /usr/bin/foo auth=password rejects\n\
+             decision: allow"
+        );
+    }
+
+    #[test]
+    fn report_lists_disabled_rules() {
+        let test = test_for(&["--uid", "0", "/usr/bin/bar"]);
+        let report = report(&test, &evaluate(&engine(), &test));
+
+        assert_eq!(
+            report,
+            "target: /usr/bin/bar\n\
+             uid: 0\n\
+             rule: /usr/bin/bar auth=deny admits\n\
+             rule: /usr/bin/bar disabled\n\
+             decision: denied (target denied by policy)"
+        );
+    }
+}
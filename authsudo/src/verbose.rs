@@ -0,0 +1,245 @@
+//! `authsudo -v`: the steps of a real run, on stderr
+//!
+//! The live counterpart to `--test-policy`: the resolved target, the callers
+//! looked at, the rules `check_explain` considered and where they came from,
+//! the final decision and how it gets authorized, then one line summing up
+//! what is about to run. Only reports; the run goes exactly as it would
+//! without `-v`.
+
+use crate::test_policy::{decision_label, rule_lines};
+use authd_policy::{CallerInfo, Explanation, PolicyDecision};
+use authd_protocol::Approval;
+use std::ffi::OsString;
+use std::path::Path;
+
+/// What policy made of `target` for `callers`, ending in `decision`: the
+/// final one, after every check authsudo adds to the rules'. `explanation`
+/// is what the rules said, None where the run bypassed them.
+pub fn policy_trace(
+    target: &Path,
+    callers: &[CallerInfo<'_>],
+    explanation: Option<&Explanation>,
+    decision: &PolicyDecision,
+) -> Vec<String> {
+    let mut lines = vec![format!("target: {}", target.display())];
+    for caller in callers {
+        lines.push(format!("caller: {}", caller.exe.display()));
+    }
+    match explanation {
+        Some(explanation) => lines.extend(rule_lines(explanation)),
+        None => lines.push("rules: bypassed".into()),
+    }
+    lines.push(format!("decision: {}", decision_label(decision)));
+    lines
+}
+
+/// How an allowed `decision` is authorized; None for refusals
+pub fn auth_label(decision: &PolicyDecision, approval: Option<&Approval>) -> Option<String> {
+    let method = match decision {
+        PolicyDecision::AllowImmediate if approval.is_none() => "none",
+        PolicyDecision::AllowImmediate | PolicyDecision::AllowWithConfirm => "authd",
+        PolicyDecision::Discouraged => "authd (discouraged warning)",
+        PolicyDecision::Denied(_) | PolicyDecision::Unknown => return None,
+    };
+    Some(match approval {
+        Some(approval) => format!("{method}, approved by {}", approval.user),
+        None => method.to_string(),
+    })
+}
+
+/// What an allowed run comes down to, on one line just before the exec: the
+/// file run, its whole argv, the uid and gid it runs as, how it was
+/// authorized (`auth` as [`auth_label`] words it) and the rule that decided,
+/// with its file. Values are quoted, so a path with spaces reads as one.
+pub fn run_summary(
+    target: &Path,
+    argv0: &Path,
+    args: &[OsString],
+    (uid, gid): (u32, u32),
+    auth: &str,
+    explanation: Option<&Explanation>,
+) -> String {
+    let argv: Vec<_> = std::iter::once(argv0.as_os_str())
+        .chain(args.iter().map(OsString::as_os_str))
+        .map(|arg| arg.to_string_lossy())
+        .collect();
+    let deciding =
+        explanation.map(|explanation| explanation.deciding.map(|index| &explanation.rules[index]));
+    let rule = match deciding {
+        None => "rule=bypassed".to_string(),
+        Some(None) => "rule=none".to_string(),
+        Some(Some(rule)) => match &rule.source {
+            Some(source) => format!(
+                "rule={:?} source={:?}",
+                rule.target.to_string_lossy(),
+                source.to_string_lossy()
+            ),
+            None => format!("rule={:?}", rule.target.to_string_lossy()),
+        },
+    };
+    format!(
+        "run: target={:?} argv={argv:?} uid={uid} gid={gid} auth={auth:?} {rule}",
+        target.to_string_lossy()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use authd_policy::PolicyEngine;
+
+    fn engine() -> PolicyEngine {
+        let mut engine = PolicyEngine::new();
+        engine
+            .load_from_str(
+                r#"
+                [[rules]]
+                target = "/usr/bin/id"
+                allow_callers = ["/usr/bin/claude"]
+                auth = "none"
+
+                [[rules]]
+                target = "*"
+                allow_users = ["root"]
+                auth = "deny"
+                "#,
+            )
+            .unwrap();
+        engine
+    }
+
+    #[test]
+    fn trace_follows_target_callers_rules_and_decision() {
+        let callers = [
+            CallerInfo {
+                exe: Path::new("/usr/bin/claude"),
+                cmdline_path: None,
+                secontext: None,
+                unit: None,
+            },
+            CallerInfo {
+                exe: Path::new("/usr/bin/bash"),
+                cmdline_path: None,
+                secontext: None,
+                unit: None,
+            },
+        ];
+        let target = Path::new("/usr/bin/id");
+        let explanation = engine().check_explain(target, 4242424, &callers);
+
+        let trace = policy_trace(target, &callers, Some(&explanation), &explanation.decision);
+        assert_eq!(
+            trace,
+            [
+                "target: /usr/bin/id",
+                "caller: /usr/bin/claude",
+                "caller: /usr/bin/bash",
+                "rule: /usr/bin/id auth=none admits",
+                "rule: * auth=deny rejects",
+                "decision: allow",
+            ]
+        );
+    }
+
+    #[test]
+    fn trace_shows_the_final_decision_and_bypasses() {
+        let target = Path::new("/usr/bin/id");
+        // Refused after the rules admitted it, e.g. under lockdown
+        let locked = PolicyDecision::Denied("lockdown".into());
+        let explanation = engine().check_explain(target, 0, &[]);
+        let trace = policy_trace(target, &[], Some(&explanation), &locked);
+        assert_eq!(trace.last().unwrap(), "decision: denied (lockdown)");
+
+        let trace = policy_trace(target, &[], None, &PolicyDecision::AllowImmediate);
+        assert_eq!(
+            trace,
+            ["target: /usr/bin/id", "rules: bypassed", "decision: allow"]
+        );
+    }
+
+    #[test]
+    fn auth_method_names_who_authorizes_the_run() {
+        let approval = Approval {
+            user: "alice".into(),
+            password: "hunter2".into(),
+            reason: None,
+        };
+        let method = |decision: PolicyDecision, approval| auth_label(&decision, approval);
+
+        assert_eq!(
+            method(PolicyDecision::AllowImmediate, None).as_deref(),
+            Some("none")
+        );
+        assert_eq!(
+            method(PolicyDecision::AllowImmediate, Some(&approval)).as_deref(),
+            Some("authd, approved by alice")
+        );
+        assert_eq!(
+            method(PolicyDecision::AllowWithConfirm, None).as_deref(),
+            Some("authd")
+        );
+        assert_eq!(
+            method(PolicyDecision::Discouraged, None).as_deref(),
+            Some("authd (discouraged warning)")
+        );
+        assert_eq!(method(PolicyDecision::Unknown, None), None);
+    }
+
+    #[test]
+    fn summary_of_an_allowed_run_says_what_runs_as_whom_and_why() {
+        let callers = [CallerInfo {
+            exe: Path::new("/usr/bin/claude"),
+            cmdline_path: None,
+            secontext: None,
+            unit: None,
+        }];
+        let target = Path::new("/usr/bin/id");
+        let explanation = engine().check_explain(target, 4242424, &callers);
+        let auth = auth_label(&explanation.decision, None).unwrap();
+
+        let summary = run_summary(
+            target,
+            Path::new("/usr/local/bin/id"),
+            &["-u".into(), "two words".into()],
+            (0, 0),
+            &auth,
+            Some(&explanation),
+        );
+        assert_eq!(
+            summary,
+            r#"run: target="/usr/bin/id" argv=["/usr/local/bin/id", "-u", "two words"] uid=0 gid=0 auth="none" rule="/usr/bin/id""#
+        );
+
+        let bypassed = run_summary(target, target, &[], (1000, 1000), "none", None);
+        assert!(
+            bypassed.ends_with(r#"uid=1000 gid=1000 auth="none" rule=bypassed"#),
+            "{bypassed}"
+        );
+    }
+
+    #[test]
+    fn summary_names_the_file_of_the_deciding_rule() {
+        let dir = std::env::temp_dir().join(format!("authsudo-summary-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("id.toml");
+        std::fs::write(
+            &file,
+            "[[rules]]\ntarget = \"/usr/bin/id\"\nallow_users = [\"root\"]\nauth = \"confirm\"\n",
+        )
+        .unwrap();
+        let mut engine = PolicyEngine::new();
+        engine.load_from_dir(&dir).unwrap();
+        let target = Path::new("/usr/bin/id");
+        let explanation = engine.check_explain(target, 0, &[]);
+
+        let summary = run_summary(target, target, &[], (0, 0), "authd", Some(&explanation));
+        assert!(
+            summary.ends_with(&format!(
+                r#"rule="/usr/bin/id" source="{}""#,
+                file.display()
+            )),
+            "{summary}"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
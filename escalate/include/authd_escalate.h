@@ -0,0 +1,25 @@
+/* C interface of authd-escalate, built with `--features ffi`.
+ *
+ * Each call returns 0 if the process already runs as the requested user.
+ * Otherwise it execs authsudo, which re-runs this executable with its
+ * original arguments as that user: on success the call never returns and
+ * the program starts over from main(), where the same call returns 0. A
+ * negative code means nothing changed. */
+
+#ifndef AUTHD_ESCALATE_H
+#define AUTHD_ESCALATE_H
+
+#include <sys/types.h>
+
+#define AUTHD_ESCALATE_NO_AUTHSUDO   (-1) /* authsudo isn't installed */
+#define AUTHD_ESCALATE_EXEC_FAILED   (-2) /* authsudo couldn't be exec'd */
+#define AUTHD_ESCALATE_NO_SUCH_USER  (-3) /* no user has the given name */
+#define AUTHD_ESCALATE_INVALID       (-4) /* null or non-UTF-8 argument */
+#define AUTHD_ESCALATE_DENIED        (-5) /* authd refused */
+#define AUTHD_ESCALATE_DAEMON        (-6) /* authd unreachable or failed */
+
+int authd_ensure_root(void);
+int authd_ensure_user(const char *username);
+int authd_ensure_uid(uid_t uid);
+
+#endif
@@ -0,0 +1,120 @@
+//! C ABI for programs that aren't written in Rust (`ffi` feature)
+//!
+//! Build the shared library with `cargo rustc -p authd-escalate --release
+//! --features ffi --crate-type cdylib` and declare the functions from
+//! `include/authd_escalate.h`, or load it through Python's `ctypes`.
+//!
+//! Each function returns 0 if the process already runs as the requested
+//! user. Otherwise it execs authsudo, which re-runs the process's own
+//! executable with its original arguments as that user: on success the call
+//! never returns, and the program starts over from `main` with the new
+//! identity, where the same call then returns 0. Nothing from before the
+//! call survives the exec except what authsudo passes on (arguments,
+//! environment), so call it first thing. A negative `AUTHD_ESCALATE_*` code
+//! means the process is unchanged and still runs as before.
+
+use crate::Error;
+use nix::unistd::Uid;
+use std::ffi::{CStr, c_char, c_int};
+
+/// authsudo isn't installed
+pub const AUTHD_ESCALATE_NO_AUTHSUDO: c_int = -1;
+/// authsudo was found but couldn't be exec'd
+pub const AUTHD_ESCALATE_EXEC_FAILED: c_int = -2;
+/// No user has the given name
+pub const AUTHD_ESCALATE_NO_SUCH_USER: c_int = -3;
+/// A null or non-UTF-8 argument, or a command authd can't be sent
+pub const AUTHD_ESCALATE_INVALID: c_int = -4;
+/// authd refused
+pub const AUTHD_ESCALATE_DENIED: c_int = -5;
+/// authd couldn't be reached or failed
+pub const AUTHD_ESCALATE_DAEMON: c_int = -6;
+
+/// The code an FFI caller gets for `error`
+fn error_code(error: &Error) -> c_int {
+    match error {
+        Error::AuthsudoNotFound => AUTHD_ESCALATE_NO_AUTHSUDO,
+        Error::ExecFailed(_) => AUTHD_ESCALATE_EXEC_FAILED,
+        Error::UserNotFound(_) => AUTHD_ESCALATE_NO_SUCH_USER,
+        Error::InvalidCommand(_) => AUTHD_ESCALATE_INVALID,
+        Error::Denied(_) => AUTHD_ESCALATE_DENIED,
+        Error::Daemon(_) => AUTHD_ESCALATE_DAEMON,
+    }
+}
+
+fn status(result: Result<(), Error>) -> c_int {
+    result.map_or_else(|error| error_code(&error), |()| 0)
+}
+
+/// [`crate::ensure_root`]
+#[unsafe(no_mangle)]
+pub extern "C" fn authd_ensure_root() -> c_int {
+    status(crate::ensure_root())
+}
+
+/// [`crate::ensure_user`]
+///
+/// # Safety
+///
+/// `username` must be null or point to a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn authd_ensure_user(username: *const c_char) -> c_int {
+    if username.is_null() {
+        return AUTHD_ESCALATE_INVALID;
+    }
+    match unsafe { CStr::from_ptr(username) }.to_str() {
+        Ok(username) => status(crate::ensure_user(username)),
+        Err(_) => AUTHD_ESCALATE_INVALID,
+    }
+}
+
+/// [`crate::ensure_user_id`]
+#[unsafe(no_mangle)]
+pub extern "C" fn authd_ensure_uid(uid: u32) -> c_int {
+    status(crate::ensure_user_id(Uid::from_raw(uid)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn errors_map_to_their_documented_codes() {
+        let cases = [
+            (Error::AuthsudoNotFound, -1),
+            (Error::ExecFailed(io::Error::from_raw_os_error(2)), -2),
+            (Error::UserNotFound("nobody-here".into()), -3),
+            (Error::InvalidCommand("too long".into()), -4),
+            (Error::Denied("target denied by policy".into()), -5),
+            (Error::Daemon("connection refused".into()), -6),
+        ];
+        for (error, code) in cases {
+            assert_eq!(error_code(&error), code, "{error}");
+        }
+        assert_eq!(status(Ok(())), 0);
+    }
+
+    #[test]
+    fn already_running_as_the_user_returns_zero() {
+        assert_eq!(authd_ensure_uid(Uid::effective().as_raw()), 0);
+    }
+
+    #[test]
+    fn bad_user_names_are_refused_without_exec() {
+        assert_eq!(
+            unsafe { authd_ensure_user(std::ptr::null()) },
+            AUTHD_ESCALATE_INVALID
+        );
+        let invalid = c"\xff";
+        assert_eq!(
+            unsafe { authd_ensure_user(invalid.as_ptr()) },
+            AUTHD_ESCALATE_INVALID
+        );
+        let unknown = c"authd-no-such-user";
+        assert_eq!(
+            unsafe { authd_ensure_user(unknown.as_ptr()) },
+            AUTHD_ESCALATE_NO_SUCH_USER
+        );
+    }
+}
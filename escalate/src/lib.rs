@@ -13,19 +13,35 @@
 //!     println!("Running as root!");
 //! }
 //! ```
+//!
+//! To run a single command as root instead and propagate its exit status,
+//! use [`run_and_wait`].
+//! [`explain`] returns the authsudo command line escalating would exec,
+//! without exec'ing it, for tools to log.
+//!
+//! Set `AUTHD_INSTANCE` to escalate through a named authd instance instead
+//! of the default one.
+//!
+//! With the `ffi` feature the crate also exports a C ABI (see [`ffi`]).
 
-#[cfg(not(coverage))]
-use std::ffi::OsString;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod run;
+
+pub use run::run_and_wait;
+
+use std::ffi::{OsStr, OsString};
 use std::io;
 #[cfg(not(coverage))]
 use std::os::unix::process::CommandExt;
-#[cfg(not(coverage))]
-use std::path::PathBuf;
-#[cfg(not(coverage))]
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use nix::unistd::{Uid, User};
 
+/// Environment variable naming the authd instance to escalate through
+pub const INSTANCE_ENV: &str = "AUTHD_INSTANCE";
+
 /// Error type for escalation failures.
 #[derive(Debug)]
 pub enum Error {
@@ -35,6 +51,12 @@ pub enum Error {
     ExecFailed(io::Error),
     /// User lookup failed
     UserNotFound(String),
+    /// The command for `run_and_wait` can't be sent to authd
+    InvalidCommand(String),
+    /// authd refused to run the command
+    Denied(String),
+    /// authd couldn't be reached or failed
+    Daemon(String),
 }
 
 impl std::fmt::Display for Error {
@@ -48,6 +70,9 @@ impl std::fmt::Display for Error {
             }
             Error::ExecFailed(e) => write!(f, "Failed to exec authsudo: {}", e),
             Error::UserNotFound(name) => write!(f, "User not found: {}", name),
+            Error::InvalidCommand(reason) => write!(f, "Invalid command: {}", reason),
+            Error::Denied(reason) => write!(f, "Denied by authd: {}", reason),
+            Error::Daemon(message) => write!(f, "authd error: {}", message),
         }
     }
 }
@@ -90,13 +115,49 @@ pub fn ensure_user_id(target_uid: Uid) -> Result<(), Error> {
 
 #[cfg(not(coverage))]
 fn reexec_via_authsudo(target_uid: Uid) -> Result<(), Error> {
-    let authsudo = which("authsudo").ok_or(Error::AuthsudoNotFound)?;
+    let err = escalation_command(target_uid)?.exec();
+    Err(Error::ExecFailed(err))
+}
+
+/// The `authsudo ... <exe> <args>` command line that escalating to
+/// `target_uid` would exec, for logging. Nothing is exec'd, and the command
+/// is explained even if this process already runs as `target_uid`.
+///
+/// ```no_run
+/// use nix::unistd::Uid;
+///
+/// let line = authd_escalate::explain(Uid::from_raw(0)).unwrap();
+/// eprintln!("escalating: {}", line);
+/// ```
+pub fn explain(target_uid: Uid) -> Result<String, Error> {
+    Ok(command_line(&escalation_command(target_uid)?))
+}
 
+/// The authsudo invocation re-running this process as `target_uid`
+fn escalation_command(target_uid: Uid) -> Result<Command, Error> {
+    let authsudo = which("authsudo").ok_or(Error::AuthsudoNotFound)?;
     // Use absolute path to current executable to prevent TOCTOU
-    let exe = std::env::current_exe().map_err(|e| Error::ExecFailed(e))?;
+    let exe = std::env::current_exe().map_err(Error::ExecFailed)?;
     let args: Vec<OsString> = std::env::args_os().skip(1).collect();
+    let instance = std::env::var_os(INSTANCE_ENV);
+    Ok(authsudo_command(
+        &authsudo,
+        instance.as_deref(),
+        target_uid,
+        &exe,
+        &args,
+    ))
+}
 
-    let mut cmd = Command::new(&authsudo);
+fn authsudo_command(
+    authsudo: &Path,
+    instance: Option<&OsStr>,
+    target_uid: Uid,
+    exe: &Path,
+    args: &[OsString],
+) -> Command {
+    let mut cmd = Command::new(authsudo);
+    cmd.args(instance_args(instance));
 
     // If not root, add -u flag
     if target_uid != Uid::from_raw(0) {
@@ -108,10 +169,37 @@ fn reexec_via_authsudo(target_uid: Uid) -> Result<(), Error> {
         }
     }
 
-    cmd.arg(&exe).args(&args);
+    cmd.arg(exe).args(args);
+    cmd
+}
 
-    let err = cmd.exec();
-    Err(Error::ExecFailed(err))
+/// `cmd` as one line a shell would run the same way
+fn command_line(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|word| shell_quote(&word.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn shell_quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./,:=@%+".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// authsudo's `--instance` option for the instance named in the environment
+fn instance_args(instance: Option<&OsStr>) -> Vec<&OsStr> {
+    match instance {
+        Some(name) if !name.is_empty() => vec![OsStr::new("--instance"), name],
+        _ => Vec::new(),
+    }
 }
 
 #[cfg(coverage)]
@@ -130,7 +218,6 @@ pub fn is_available() -> bool {
     false
 }
 
-#[cfg(not(coverage))]
 fn which(binary: &str) -> Option<PathBuf> {
     use std::os::unix::fs::PermissionsExt;
 
@@ -177,6 +264,67 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn instance_from_environment_is_passed_to_authsudo() {
+        assert!(instance_args(None).is_empty());
+        assert!(instance_args(Some(OsStr::new(""))).is_empty());
+        assert_eq!(
+            instance_args(Some(OsStr::new("test"))),
+            vec![OsStr::new("--instance"), OsStr::new("test")]
+        );
+    }
+
+    fn explained(target_uid: Uid) -> String {
+        let args = [OsString::from("--flag"), OsString::from("two words")];
+        command_line(&authsudo_command(
+            Path::new("/usr/bin/authsudo"),
+            Some(OsStr::new("test")),
+            target_uid,
+            Path::new("/opt/tool/bin/tool"),
+            &args,
+        ))
+    }
+
+    #[test]
+    fn explained_root_escalation_has_no_user_flag() {
+        assert_eq!(
+            explained(Uid::from_raw(0)),
+            "/usr/bin/authsudo --instance test /opt/tool/bin/tool --flag 'two words'"
+        );
+    }
+
+    #[test]
+    fn explained_user_escalation_names_the_user() {
+        let uid = Uid::effective();
+        if let (false, Some(user)) = (uid.is_root(), User::from_uid(uid).ok().flatten()) {
+            assert_eq!(
+                explained(uid),
+                format!(
+                    "/usr/bin/authsudo --instance test -u {} /opt/tool/bin/tool --flag 'two words'",
+                    shell_quote(&user.name)
+                )
+            );
+        }
+
+        // No passwd entry: authsudo gets the numeric form
+        let unnamed = Uid::from_raw(u32::MAX - 7);
+        assert_eq!(
+            explained(unnamed),
+            format!(
+                "/usr/bin/authsudo --instance test -u '#{}' /opt/tool/bin/tool --flag 'two words'",
+                u32::MAX - 7
+            )
+        );
+    }
+
+    #[test]
+    fn explained_words_are_shell_quoted() {
+        assert_eq!(shell_quote("/usr/bin/id"), "/usr/bin/id");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote("$HOME"), "'$HOME'");
+    }
+
     #[test]
     fn missing_user_is_reported() {
         assert!(matches!(
@@ -195,5 +343,9 @@ mod tests {
             Error::UserNotFound("nobody-here".to_string()).to_string(),
             "User not found: nobody-here"
         );
+        assert_eq!(
+            Error::Denied("not in group wheel".into()).to_string(),
+            "Denied by authd: not in group wheel"
+        );
     }
 }
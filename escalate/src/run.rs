@@ -0,0 +1,221 @@
+//! Running one command through authd and waiting for it
+//!
+//! Unlike [`ensure_root`](crate::ensure_root), nothing is re-exec'd: authd
+//! launches the command in its own scope and keeps the connection open until
+//! it exits, then answers with the exit status. The command doesn't share the
+//! caller's terminal, so this suits commands whose status is what matters.
+
+use crate::{Error, INSTANCE_ENV, which};
+use authd_protocol::instance::{self, InstanceName};
+use authd_protocol::{AuthRequest, AuthResponse, DaemonRequest, frame};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+/// Run `cmd` as root through authd and return how it exited, for the caller
+/// to propagate. Its program is looked up in `PATH` unless it contains a `/`;
+/// its arguments and explicitly set environment variables are passed on.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::process::Command;
+///
+/// let status = authd_escalate::run_and_wait(Command::new("systemctl").arg("daemon-reload"))
+///     .expect("authd refused");
+/// std::process::exit(status.code().unwrap_or(1));
+/// ```
+pub fn run_and_wait(cmd: &Command) -> Result<ExitStatus, Error> {
+    let instance = match std::env::var(INSTANCE_ENV) {
+        Ok(name) if !name.is_empty() => {
+            Some(InstanceName::new(&name).map_err(|e| Error::Daemon(e.to_string()))?)
+        }
+        _ => None,
+    };
+    run_and_wait_on(&instance::socket_path(instance.as_ref()), cmd)
+}
+
+fn run_and_wait_on(socket: &str, cmd: &Command) -> Result<ExitStatus, Error> {
+    let request = exec_request(cmd)?;
+    let mut stream = UnixStream::connect(socket)
+        .map_err(|e| Error::Daemon(format!("cannot connect to authd: {}", e)))?;
+    let response: AuthResponse = frame::call(&mut stream, &DaemonRequest::ExecWait(request))
+        .map_err(|e| Error::Daemon(e.to_string()))?;
+    exit_status(response)
+}
+
+fn exec_request(cmd: &Command) -> Result<AuthRequest, Error> {
+    let program = Path::new(cmd.get_program());
+    let target = if program.as_os_str().as_encoded_bytes().contains(&b'/') {
+        std::path::absolute(program).map_err(|e| Error::InvalidCommand(e.to_string()))?
+    } else {
+        which(&program.to_string_lossy()).ok_or_else(|| {
+            Error::InvalidCommand(format!("{} not found in PATH", program.display()))
+        })?
+    };
+    // authd takes UTF-8 arguments; a lossy one would run something else
+    let args = cmd
+        .get_args()
+        .map(|arg| {
+            arg.to_str()
+                .map(String::from)
+                .ok_or_else(|| Error::InvalidCommand(format!("argument {:?} is not UTF-8", arg)))
+        })
+        .collect::<Result<_, _>>()?;
+    let env = cmd
+        .get_envs()
+        .filter_map(|(name, value)| Some((name.to_str()?.into(), value?.to_str()?.into())))
+        .collect();
+    AuthRequest::try_new(target, args, env).map_err(|e| Error::InvalidCommand(e.to_string()))
+}
+
+fn exit_status(response: AuthResponse) -> Result<ExitStatus, Error> {
+    match response {
+        // Wait statuses: exit code in the high byte, signal in the low bits
+        AuthResponse::Exited {
+            code: Some(code), ..
+        } => Ok(ExitStatus::from_raw((code & 0xff) << 8)),
+        AuthResponse::Exited {
+            signal: Some(signal),
+            ..
+        } => Ok(ExitStatus::from_raw(signal & 0x7f)),
+        AuthResponse::Exited { .. } => Err(Error::Daemon("exit status missing".into())),
+        AuthResponse::Success { .. } => Err(Error::Daemon(
+            "authd answered without waiting for the command".into(),
+        )),
+        AuthResponse::Denied { reason } => Err(Error::Denied(reason)),
+        AuthResponse::UnknownTarget => Err(Error::Denied("no policy for this command".into())),
+        AuthResponse::AuthFailed => Err(Error::Denied("authentication failed".into())),
+        AuthResponse::Error { message } => Err(Error::Daemon(message)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::thread::JoinHandle;
+
+    /// A daemon that reads one request and answers it with `response`
+    struct StubDaemon {
+        dir: PathBuf,
+        socket: String,
+        served: JoinHandle<DaemonRequest>,
+    }
+
+    impl StubDaemon {
+        fn answering(name: &str, response: AuthResponse) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "authd-escalate-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            let socket = dir.join("authd.sock");
+            let listener = UnixListener::bind(&socket).unwrap();
+            let served = std::thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let request = frame::read_frame(&mut stream).unwrap();
+                frame::write_frame(&mut stream, &response).unwrap();
+                request
+            });
+            Self {
+                dir,
+                socket: socket.to_string_lossy().into_owned(),
+                served,
+            }
+        }
+
+        fn request(self) -> DaemonRequest {
+            let request = self.served.join().unwrap();
+            std::fs::remove_dir_all(&self.dir).unwrap();
+            request
+        }
+    }
+
+    #[test]
+    fn exit_code_comes_back_from_the_daemon() {
+        let daemon = StubDaemon::answering(
+            "code",
+            AuthResponse::Exited {
+                pid: 4242,
+                code: Some(3),
+                signal: None,
+            },
+        );
+        let mut cmd = Command::new("/usr/bin/false");
+        cmd.arg("--quiet").env("LANG", "C");
+
+        let status = run_and_wait_on(&daemon.socket, &cmd).unwrap();
+        assert_eq!(status.code(), Some(3));
+        assert!(!status.success());
+
+        let DaemonRequest::ExecWait(request) = daemon.request() else {
+            panic!("expected ExecWait");
+        };
+        assert_eq!(request.target, PathBuf::from("/usr/bin/false"));
+        assert_eq!(request.args, ["--quiet"]);
+        assert_eq!(request.env.get("LANG").map(String::as_str), Some("C"));
+    }
+
+    #[test]
+    fn killed_commands_report_the_signal() {
+        let daemon = StubDaemon::answering(
+            "signal",
+            AuthResponse::Exited {
+                pid: 4242,
+                code: None,
+                signal: Some(9),
+            },
+        );
+
+        let status = run_and_wait_on(&daemon.socket, &Command::new("/usr/bin/sleep")).unwrap();
+        assert_eq!(status.code(), None);
+        assert_eq!(status.signal(), Some(9));
+        daemon.request();
+    }
+
+    #[test]
+    fn refusals_are_errors() {
+        let daemon = StubDaemon::answering(
+            "denied",
+            AuthResponse::Denied {
+                reason: "not in group wheel".into(),
+            },
+        );
+
+        let result = run_and_wait_on(&daemon.socket, &Command::new("/usr/bin/id"));
+        assert!(matches!(result, Err(Error::Denied(reason)) if reason == "not in group wheel"));
+        daemon.request();
+    }
+
+    #[test]
+    fn missing_daemon_is_reported() {
+        let result = run_and_wait_on("/nonexistent/authd.sock", &Command::new("/usr/bin/id"));
+        assert!(
+            matches!(result, Err(Error::Daemon(message)) if message.starts_with("cannot connect"))
+        );
+    }
+
+    #[test]
+    fn non_utf8_arguments_are_refused() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut cmd = Command::new("/usr/bin/touch");
+        cmd.arg(OsStr::from_bytes(b"/tmp/\xff"));
+        assert!(matches!(exec_request(&cmd), Err(Error::InvalidCommand(_))));
+    }
+
+    #[test]
+    fn commands_too_large_for_a_frame_are_refused() {
+        let mut cmd = Command::new("/usr/bin/echo");
+        cmd.arg("x".repeat(frame::MAX_FRAME_LEN));
+        assert!(matches!(
+            exec_request(&cmd),
+            Err(Error::InvalidCommand(message)) if message.starts_with("frame too large")
+        ));
+    }
+}
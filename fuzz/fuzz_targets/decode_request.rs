@@ -0,0 +1,12 @@
+//! Raw msgpack payloads as authd would decode them off the socket.
+
+#![no_main]
+
+use authd_protocol::{AuthRequest, DaemonRequest, frame};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = rmp_serde::from_slice::<AuthRequest>(data);
+    let _ = frame::decode_payload::<AuthRequest>(data);
+    let _ = frame::decode_payload::<DaemonRequest>(data);
+});
@@ -0,0 +1,12 @@
+//! Length-prefixed frames, including bogus prefixes and short reads.
+
+#![no_main]
+
+use authd_protocol::{DaemonRequest, frame};
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = frame::decode::<DaemonRequest>(data);
+    let _ = frame::read_frame::<DaemonRequest, _>(&mut Cursor::new(data));
+});
@@ -0,0 +1,640 @@
+//! Global settings shared by authd and authsudo
+//!
+//! Read from `/etc/authd/authd.toml`. Every key is optional; a missing file
+//! means all defaults.
+
+use crate::PolicyError;
+use crate::Redaction;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_PATH: &str = "/etc/authd/authd.toml";
+
+/// Default `command_search_path`: system directories only
+pub const DEFAULT_COMMAND_SEARCH_PATH: &[&str] = &[
+    "/usr/local/sbin",
+    "/usr/local/bin",
+    "/usr/sbin",
+    "/usr/bin",
+    "/sbin",
+    "/bin",
+];
+
+/// Default `dialog_fallback`: the session-lock dialog, else the caller's
+/// terminal
+pub const DEFAULT_DIALOG_FALLBACK: &[DialogBackend] =
+    &[DialogBackend::SessionLock, DialogBackend::Tty];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Service user authd switches to after binding its socket (privilege
+    /// separation). `None` keeps the daemon fully root.
+    pub privsep_user: Option<String>,
+    /// Members of these groups (and root) may manage other users' launches
+    /// and use admin-only requests.
+    pub admin_groups: Vec<String>,
+    /// Let real root run anything through authsudo without a matching rule.
+    /// Only the real uid counts; authsudo's euid is always 0.
+    pub root_bypass: bool,
+    /// Where `allow_groups` memberships come from
+    pub group_source: GroupSource,
+    /// How authd writes its log and audit events
+    pub log_format: LogFormat,
+    /// Give up on a PAM conversation (two-person rule approvals) after this
+    /// many seconds, so a stalled module can't hang the request
+    pub pam_timeout_secs: u64,
+    /// What checks approvers' passwords where no PAM stack is configured
+    pub pam_fallback: PamFallback,
+    /// What `auth = "confirm"` rules get from an authd built without the
+    /// `gui` feature, which has no dialog to confirm with
+    pub headless_confirm: HeadlessConfirm,
+    /// Where authsudo looks up bare command names (`authsudo ls`), in order.
+    /// The caller's `PATH` isn't searched, so a directory they can write to
+    /// can't shadow a system binary. Commands containing a `/` are used as
+    /// given. With `clean_env` this is also the `PATH` the command runs with.
+    pub command_search_path: Vec<PathBuf>,
+    /// Start authsudo's targets in a fresh environment, like sudo's
+    /// `env_reset`: `PATH` from `command_search_path`, `HOME`, `USER`,
+    /// `LOGNAME` and `SHELL` for the target user, and of the caller's only
+    /// the terminal and locale variables and `keep_env`. A rule opts its
+    /// target out with `clean_env = false`.
+    pub clean_env: bool,
+    /// More of the caller's variables a clean environment keeps, by name
+    pub keep_env: Vec<String>,
+    /// For this many milliseconds after a confirmation prompt appears, a
+    /// "yes" (Enter in the dialog, `y` on a terminal) is ignored, so a key
+    /// already held down or typed ahead can't confirm a prompt unread
+    pub confirm_delay_ms: u64,
+    /// polkit action ids answered by authd's D-Bus authority (the
+    /// `polkit-service` feature), each with the policy target that decides
+    /// it. A key ending in `.*` covers every action id under that prefix.
+    pub polkit_actions: BTreeMap<String, PathBuf>,
+    /// Targets that may still run while authd is in lockdown
+    pub emergency_allow: Vec<PathBuf>,
+    /// Which admitting rule decides when several match a target
+    pub rule_resolution: RuleResolution,
+    /// Arguments hidden wherever a command is shown: dialogs, terminal
+    /// prompts, notifications
+    pub redact_args: Redaction,
+    /// Drop a client that hasn't sent a complete request this many seconds
+    /// after connecting, so stalled connections can't tie up the daemon
+    pub request_timeout_secs: u64,
+    /// Every confirmation shows a fresh four-digit code, which a terminal
+    /// prompt takes instead of `y`
+    pub require_challenge: bool,
+    /// What authd does at startup if the socket's directory could be written
+    /// by someone other than root, who could replace the socket
+    pub socket_dir_check: SocketDirCheck,
+    /// Load the drop-ins of this profile (`/etc/authd/profiles/<profile>`)
+    /// instead of `policies.d`; authd won't start if it doesn't exist
+    pub profile: Option<String>,
+    /// How confirmations are asked, in order: each backend that can't show
+    /// the prompt (no compositor, no terminal) hands over to the next
+    pub dialog_fallback: Vec<DialogBackend>,
+    /// Check policy before looking the command up, and refuse every
+    /// disallowed command with the same message, so authsudo doesn't reveal
+    /// which binaries exist to someone who may not run them
+    pub conceal_missing_commands: bool,
+    /// Most environment variables a request may pass to its target
+    pub max_env_entries: usize,
+    /// Most bytes a request's environment may total, counted as `KEY=value`
+    /// strings the way the target would see them
+    pub max_env_bytes: usize,
+    /// Accounts a rule's `runas` may name, besides root and `"caller"`, so a
+    /// drop-in can't have authd launch as any account on the system
+    pub allow_runas: Vec<String>,
+    /// What loading does with a policy file that has two enabled rules for
+    /// the same exact target and the same users, groups, callers and session
+    /// types, usually a copy-paste mistake
+    pub duplicate_targets: DuplicateTargets,
+    /// What authd does if started by someone other than root, when it could
+    /// never launch anything as anyone
+    pub non_root_startup: NonRootStartup,
+    /// Targets (exact paths or globs) that always need at least
+    /// `auth = "password"`, even where a rule would let them run without
+    pub force_password_targets: Vec<PathBuf>,
+    /// authsudo logs each request refused only because no rule matched, for
+    /// `authsudo --suggest-policy` to draft rules from
+    pub learn_denials: bool,
+}
+
+/// `nss` asks the user database for the uid's groups; `process` trusts the
+/// groups the calling process actually holds, which differ after `newgrp`,
+/// `setgid` or a membership change since login.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupSource {
+    #[default]
+    Nss,
+    Process,
+}
+
+/// `text` is human-readable tracing output; `json` writes every log line and
+/// audit event as a JSON object for log collectors and SIEM tooling.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// `deny` refuses every approval with a message saying PAM is missing;
+/// `shadow` verifies against `/etc/shadow` through libcrypt.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PamFallback {
+    #[default]
+    Deny,
+    Shadow,
+}
+
+/// `least_restrictive` lets the most permissive admitting rule decide;
+/// `most_restrictive` the strictest. `first_match` takes the first admitting
+/// rule, exact targets before `*`, then drop-ins by file name before the
+/// combined policy file, and by order within a file. `priority` takes the admitting rule with the highest `priority`,
+/// the least restrictive of those on a tie.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleResolution {
+    #[default]
+    LeastRestrictive,
+    FirstMatch,
+    MostRestrictive,
+    Priority,
+}
+
+/// `enforce` refuses to start, `warn` logs and starts anyway, `off` skips the
+/// check (e.g. for a test socket under `AUTHD_SOCKET`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SocketDirCheck {
+    #[default]
+    Enforce,
+    Warn,
+    Off,
+}
+
+/// `refuse` exits with an error saying authd must be started as root;
+/// `evaluate` starts an evaluation-only daemon, which answers policy checks
+/// and confirm-only requests but refuses every launch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NonRootStartup {
+    #[default]
+    Refuse,
+    Evaluate,
+}
+
+/// `warn` loads the file and reports the duplicate through `validate`;
+/// `error` skips the whole file, as if it failed to parse.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateTargets {
+    #[default]
+    Warn,
+    Error,
+}
+
+/// `session_lock` is the dialog over a locked session (`gui` feature, and a
+/// Wayland session to show it in); `tty` asks on the caller's terminal; `deny`
+/// always answers no, ending the chain with a refusal instead of an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DialogBackend {
+    SessionLock,
+    Tty,
+    Deny,
+}
+
+/// `deny` refuses the request; `require_auth` accepts it once the requester's
+/// own password checks out (PAM, or `pam_fallback`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeadlessConfirm {
+    #[default]
+    Deny,
+    RequireAuth,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            privsep_user: None,
+            admin_groups: vec!["wheel".into()],
+            root_bypass: true,
+            group_source: GroupSource::Nss,
+            log_format: LogFormat::Text,
+            pam_timeout_secs: 60,
+            pam_fallback: PamFallback::Deny,
+            headless_confirm: HeadlessConfirm::Deny,
+            command_search_path: DEFAULT_COMMAND_SEARCH_PATH
+                .iter()
+                .map(PathBuf::from)
+                .collect(),
+            clean_env: true,
+            keep_env: Vec::new(),
+            confirm_delay_ms: 500,
+            polkit_actions: BTreeMap::new(),
+            emergency_allow: Vec::new(),
+            rule_resolution: RuleResolution::LeastRestrictive,
+            redact_args: Redaction::default(),
+            request_timeout_secs: 10,
+            require_challenge: false,
+            socket_dir_check: SocketDirCheck::Enforce,
+            profile: None,
+            dialog_fallback: DEFAULT_DIALOG_FALLBACK.to_vec(),
+            conceal_missing_commands: false,
+            max_env_entries: 64,
+            max_env_bytes: 16 * 1024,
+            allow_runas: Vec::new(),
+            duplicate_targets: DuplicateTargets::Warn,
+            non_root_startup: NonRootStartup::Refuse,
+            force_password_targets: Vec::new(),
+            learn_denials: false,
+        }
+    }
+}
+
+impl Config {
+    /// Load the config file, or defaults if it doesn't exist
+    pub fn load() -> Result<Self, PolicyError> {
+        Self::load_from(Path::new(CONFIG_PATH))
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self, PolicyError> {
+        match fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content, path),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Root, or a member of one of `admin_groups`
+    pub fn is_admin(&self, uid: u32) -> bool {
+        uid == 0
+            || self
+                .admin_groups
+                .iter()
+                .any(|group| crate::user_in_group(uid, group))
+    }
+
+    /// Whether `target` is on `emergency_allow`, and so exempt from lockdown
+    pub fn emergency_allowed(&self, target: &Path) -> bool {
+        self.emergency_allow.iter().any(|allowed| allowed == target)
+    }
+
+    /// Why `env` can't be passed to a target: more entries than
+    /// `max_env_entries` or more bytes than `max_env_bytes`
+    pub fn env_refusal(&self, env: &HashMap<String, String>) -> Option<String> {
+        if env.len() > self.max_env_entries {
+            return Some(format!(
+                "environment has {} variables; at most {} are allowed",
+                env.len(),
+                self.max_env_entries
+            ));
+        }
+        let bytes: usize = env
+            .iter()
+            .map(|(key, value)| key.len() + 1 + value.len())
+            .sum();
+        if bytes > self.max_env_bytes {
+            return Some(format!(
+                "environment is {} bytes; at most {} are allowed",
+                bytes, self.max_env_bytes
+            ));
+        }
+        None
+    }
+
+    pub fn load_from_str(content: &str) -> Result<Self, PolicyError> {
+        Self::parse(content, Path::new("<string>"))
+    }
+
+    fn parse(content: &str, path: &Path) -> Result<Self, PolicyError> {
+        toml::from_str(content).map_err(|e| PolicyError::Parse {
+            file: PathBuf::from(path),
+            error: e.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_config_is_default() {
+        let config = Config::load_from_str("").unwrap();
+        assert!(config.privsep_user.is_none());
+    }
+
+    #[test]
+    fn missing_file_is_default() {
+        let config = Config::load_from(Path::new("/nonexistent/authd.toml")).unwrap();
+        assert!(config.privsep_user.is_none());
+    }
+
+    #[test]
+    fn parses_privsep_user() {
+        let config = Config::load_from_str(r#"privsep_user = "authd""#).unwrap();
+        assert_eq!(config.privsep_user.as_deref(), Some("authd"));
+    }
+
+    #[test]
+    fn admin_groups_default_to_wheel_and_root_is_admin() {
+        let config = Config::default();
+        assert_eq!(config.admin_groups, vec!["wheel"]);
+        assert!(config.is_admin(0));
+
+        let config = Config::load_from_str("admin_groups = []").unwrap();
+        assert!(config.is_admin(0));
+        assert!(!config.is_admin(u32::MAX));
+    }
+
+    #[test]
+    fn root_bypass_defaults_on_and_can_be_disabled() {
+        assert!(Config::default().root_bypass);
+        let config = Config::load_from_str("root_bypass = false").unwrap();
+        assert!(!config.root_bypass);
+    }
+
+    #[test]
+    fn group_source_defaults_to_nss() {
+        assert_eq!(Config::default().group_source, GroupSource::Nss);
+        let config = Config::load_from_str(r#"group_source = "process""#).unwrap();
+        assert_eq!(config.group_source, GroupSource::Process);
+        assert!(Config::load_from_str(r#"group_source = "ldap""#).is_err());
+    }
+
+    #[test]
+    fn log_format_defaults_to_text() {
+        assert_eq!(Config::default().log_format, LogFormat::Text);
+        let config = Config::load_from_str(r#"log_format = "json""#).unwrap();
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert!(Config::load_from_str(r#"log_format = "xml""#).is_err());
+    }
+
+    #[test]
+    fn pam_timeout_defaults_to_a_minute() {
+        assert_eq!(Config::default().pam_timeout_secs, 60);
+        let config = Config::load_from_str("pam_timeout_secs = 15").unwrap();
+        assert_eq!(config.pam_timeout_secs, 15);
+    }
+
+    #[test]
+    fn pam_fallback_defaults_to_deny() {
+        assert_eq!(Config::default().pam_fallback, PamFallback::Deny);
+        let config = Config::load_from_str(r#"pam_fallback = "shadow""#).unwrap();
+        assert_eq!(config.pam_fallback, PamFallback::Shadow);
+        assert!(Config::load_from_str(r#"pam_fallback = "none""#).is_err());
+    }
+
+    #[test]
+    fn headless_confirm_defaults_to_deny() {
+        assert_eq!(Config::default().headless_confirm, HeadlessConfirm::Deny);
+        let config = Config::load_from_str(r#"headless_confirm = "require_auth""#).unwrap();
+        assert_eq!(config.headless_confirm, HeadlessConfirm::RequireAuth);
+        assert!(Config::load_from_str(r#"headless_confirm = "allow""#).is_err());
+    }
+
+    #[test]
+    fn rule_resolution_defaults_to_least_restrictive() {
+        assert_eq!(
+            Config::default().rule_resolution,
+            RuleResolution::LeastRestrictive
+        );
+        let config = Config::load_from_str(r#"rule_resolution = "first_match""#).unwrap();
+        assert_eq!(config.rule_resolution, RuleResolution::FirstMatch);
+        assert!(Config::load_from_str(r#"rule_resolution = "last_match""#).is_err());
+    }
+
+    #[test]
+    fn request_timeout_defaults_to_ten_seconds() {
+        assert_eq!(Config::default().request_timeout_secs, 10);
+        let config = Config::load_from_str("request_timeout_secs = 2").unwrap();
+        assert_eq!(config.request_timeout_secs, 2);
+    }
+
+    #[test]
+    fn oversized_environments_are_refused() {
+        let config = Config::load_from_str("max_env_entries = 2\nmax_env_bytes = 20").unwrap();
+        let env = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+
+        assert_eq!(config.env_refusal(&HashMap::new()), None);
+        // "WAYLAND_DISPLAY=w-1" is 19 bytes
+        assert_eq!(
+            config.env_refusal(&env(&[("WAYLAND_DISPLAY", "w-1")])),
+            None
+        );
+        assert_eq!(
+            config.env_refusal(&env(&[("WAYLAND_DISPLAY", "w-10")])),
+            None
+        );
+        assert_eq!(
+            config.env_refusal(&env(&[("WAYLAND_DISPLAY", "w-100")])),
+            Some("environment is 21 bytes; at most 20 are allowed".into())
+        );
+        assert_eq!(
+            config.env_refusal(&env(&[("A", "1"), ("B", "2"), ("C", "3")])),
+            Some("environment has 3 variables; at most 2 are allowed".into())
+        );
+    }
+
+    #[test]
+    fn allow_runas_defaults_to_nobody() {
+        assert!(Config::default().allow_runas.is_empty());
+        let config = Config::load_from_str(r#"allow_runas = ["backup"]"#).unwrap();
+        assert_eq!(config.allow_runas, vec!["backup"]);
+    }
+
+    #[test]
+    fn duplicate_targets_default_to_a_warning() {
+        assert_eq!(Config::default().duplicate_targets, DuplicateTargets::Warn);
+        let config = Config::load_from_str(r#"duplicate_targets = "error""#).unwrap();
+        assert_eq!(config.duplicate_targets, DuplicateTargets::Error);
+        assert!(Config::load_from_str(r#"duplicate_targets = "ignore""#).is_err());
+    }
+
+    #[test]
+    fn conceal_missing_commands_defaults_off() {
+        assert!(!Config::default().conceal_missing_commands);
+        let config = Config::load_from_str("conceal_missing_commands = true").unwrap();
+        assert!(config.conceal_missing_commands);
+    }
+
+    #[test]
+    fn dialog_fallback_lists_backends_in_order() {
+        assert_eq!(
+            Config::default().dialog_fallback,
+            [DialogBackend::SessionLock, DialogBackend::Tty]
+        );
+        let config =
+            Config::load_from_str(r#"dialog_fallback = ["session_lock", "tty", "deny"]"#).unwrap();
+        assert_eq!(
+            config.dialog_fallback,
+            [
+                DialogBackend::SessionLock,
+                DialogBackend::Tty,
+                DialogBackend::Deny
+            ]
+        );
+        assert!(Config::load_from_str(r#"dialog_fallback = ["zenity"]"#).is_err());
+    }
+
+    #[test]
+    fn profile_is_optional() {
+        assert_eq!(Config::default().profile, None);
+        let config = Config::load_from_str(r#"profile = "workstation""#).unwrap();
+        assert_eq!(config.profile.as_deref(), Some("workstation"));
+    }
+
+    #[test]
+    fn socket_dir_check_defaults_to_enforce() {
+        assert_eq!(Config::default().socket_dir_check, SocketDirCheck::Enforce);
+        let config = Config::load_from_str(r#"socket_dir_check = "warn""#).unwrap();
+        assert_eq!(config.socket_dir_check, SocketDirCheck::Warn);
+        assert!(Config::load_from_str(r#"socket_dir_check = "strict""#).is_err());
+    }
+
+    #[test]
+    fn non_root_startup_defaults_to_refuse() {
+        assert_eq!(Config::default().non_root_startup, NonRootStartup::Refuse);
+        let config = Config::load_from_str(r#"non_root_startup = "evaluate""#).unwrap();
+        assert_eq!(config.non_root_startup, NonRootStartup::Evaluate);
+        assert!(Config::load_from_str(r#"non_root_startup = "degrade""#).is_err());
+    }
+
+    #[test]
+    fn force_password_targets_default_to_none() {
+        assert!(Config::default().force_password_targets.is_empty());
+        let config =
+            Config::load_from_str(r#"force_password_targets = ["/usr/bin/passwd", "/usr/sbin/*"]"#)
+                .unwrap();
+        assert_eq!(
+            config.force_password_targets,
+            [
+                PathBuf::from("/usr/bin/passwd"),
+                PathBuf::from("/usr/sbin/*")
+            ]
+        );
+    }
+
+    #[test]
+    fn learn_denials_defaults_off() {
+        assert!(!Config::default().learn_denials);
+        assert!(
+            Config::load_from_str("learn_denials = true")
+                .unwrap()
+                .learn_denials
+        );
+    }
+
+    #[test]
+    fn require_challenge_defaults_off() {
+        assert!(!Config::default().require_challenge);
+        let config = Config::load_from_str("require_challenge = true").unwrap();
+        assert!(config.require_challenge);
+    }
+
+    #[test]
+    fn redact_args_defaults_to_password_and_token() {
+        assert_eq!(
+            Config::default().redact_args.flags,
+            ["--password", "--token"]
+        );
+        let config = Config::load_from_str(
+            r#"
+            [redact_args]
+            flags = ["--api-key"]
+            patterns = ["ghp_*"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.redact_args.flags, ["--api-key"]);
+        assert_eq!(config.redact_args.patterns.len(), 1);
+        assert!(Config::load_from_str("[redact_args]\npatterns = [\"[\"]").is_err());
+    }
+
+    #[test]
+    fn parse_errors_name_the_source() {
+        let error = Config::load_from_str("privsep_user = 3").unwrap_err();
+        assert!(matches!(error, PolicyError::Parse { file, .. } if file == Path::new("<string>")));
+    }
+
+    #[test]
+    fn command_search_path_defaults_to_system_dirs() {
+        let config = Config::default();
+        assert!(config
+            .command_search_path
+            .contains(&PathBuf::from("/usr/bin")));
+        assert!(config
+            .command_search_path
+            .iter()
+            .all(|dir| !dir.starts_with("/home") && !dir.starts_with("/tmp")));
+
+        let config =
+            Config::load_from_str(r#"command_search_path = ["/opt/tools/bin", "/usr/bin"]"#)
+                .unwrap();
+        assert_eq!(
+            config.command_search_path,
+            vec![PathBuf::from("/opt/tools/bin"), PathBuf::from("/usr/bin")]
+        );
+    }
+
+    #[test]
+    fn clean_env_defaults_on_and_keeps_nothing_extra() {
+        let config = Config::default();
+        assert!(config.clean_env);
+        assert!(config.keep_env.is_empty());
+
+        let config =
+            Config::load_from_str("clean_env = false\nkeep_env = [\"EDITOR\", \"http_proxy\"]")
+                .unwrap();
+        assert!(!config.clean_env);
+        assert_eq!(config.keep_env, ["EDITOR", "http_proxy"]);
+    }
+
+    #[test]
+    fn confirm_delay_defaults_to_half_a_second() {
+        assert_eq!(Config::default().confirm_delay_ms, 500);
+        let config = Config::load_from_str("confirm_delay_ms = 0").unwrap();
+        assert_eq!(config.confirm_delay_ms, 0);
+    }
+
+    #[test]
+    fn only_emergency_targets_are_exempt_from_lockdown() {
+        assert!(!Config::default().emergency_allowed(Path::new("/usr/bin/systemctl")));
+        let config = Config::load_from_str(r#"emergency_allow = ["/usr/bin/systemctl"]"#).unwrap();
+        assert!(config.emergency_allowed(Path::new("/usr/bin/systemctl")));
+        assert!(!config.emergency_allowed(Path::new("/usr/bin/systemctl2")));
+        assert!(!config.emergency_allowed(Path::new("/usr/bin/id")));
+    }
+
+    #[test]
+    fn parses_polkit_actions() {
+        assert!(Config::default().polkit_actions.is_empty());
+        let config = Config::load_from_str(
+            r#"
+            [polkit_actions]
+            "org.freedesktop.udisks2.filesystem-mount" = "/usr/bin/udisksctl"
+            "org.example.backup.*" = "/usr/local/bin/backup"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.polkit_actions["org.example.backup.*"],
+            PathBuf::from("/usr/local/bin/backup")
+        );
+        assert_eq!(config.polkit_actions.len(), 2);
+    }
+}
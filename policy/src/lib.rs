@@ -1,13 +1,116 @@
-use authd_protocol::{AuthRequirement, PolicyRule};
-use glob::Pattern;
+pub mod config;
+pub mod procfs;
+pub mod redact;
+pub mod session;
+pub mod template;
+pub mod unit;
+pub mod validate;
+
+pub use config::{
+    Config, DialogBackend, DuplicateTargets, GroupSource, HeadlessConfirm, LogFormat,
+    NonRootStartup, PamFallback, RuleResolution, SocketDirCheck,
+};
+pub use redact::Redaction;
+pub use session::SessionType;
+pub use validate::Warning;
+
+use authd_protocol::instance::InstanceName;
+use authd_protocol::limits::ResourceLimits;
+use authd_protocol::pam::{PamService, DEFAULT_SERVICE as DEFAULT_PAM_SERVICE};
+use authd_protocol::{AuthMethod, AuthRequirement, MethodSet, PolicyRule};
+use glob::{MatchOptions, Pattern};
 use std::collections::HashMap;
+use std::ffi::OsStr;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use thiserror::Error;
 use users::os::unix::GroupExt;
 
 pub const POLICY_DIR: &str = "/etc/authd/policies.d";
 
+/// Single combined policy file, read before the drop-ins in `POLICY_DIR`
+pub const POLICY_FILE: &str = "/etc/authd/policy.toml";
+
+/// What rules from [`PolicyEngine::load_embedded`] give as their source
+pub const EMBEDDED_SOURCE: &str = "<embedded>";
+
+/// The default policy compiled in with the `embedded-policy` feature, from
+/// the file `AUTHD_EMBEDDED_POLICY` names at build time. `load_all` puts it
+/// under the combined file and the drop-ins, so a host with nothing in
+/// `/etc/authd` still has a baseline.
+#[cfg(feature = "embedded-policy")]
+pub const EMBEDDED_POLICY: Option<&str> = Some(include_str!(env!("AUTHD_EMBEDDED_POLICY")));
+#[cfg(not(feature = "embedded-policy"))]
+pub const EMBEDDED_POLICY: Option<&str> = None;
+
+/// Named instances keep their rules in `<INSTANCE_POLICY_ROOT>/<name>/policies.d`
+pub const INSTANCE_POLICY_ROOT: &str = "/etc/authd/instances";
+
+/// Policy directory of `instance`, or of the default instance for `None`
+pub fn policy_dir(instance: Option<&InstanceName>) -> PathBuf {
+    match instance {
+        Some(name) => Path::new(INSTANCE_POLICY_ROOT)
+            .join(name.as_str())
+            .join("policies.d"),
+        None => PathBuf::from(POLICY_DIR),
+    }
+}
+
+/// Policy profiles keep their drop-ins in `<PROFILE_ROOT>/<profile>`, named
+/// instances in `<INSTANCE_POLICY_ROOT>/<name>/profiles/<profile>`
+pub const PROFILE_ROOT: &str = "/etc/authd/profiles";
+
+/// Drop-in directory of `instance`: that of `profile` if the daemon config
+/// selects one, otherwise `policy_dir`
+pub fn drop_in_dir(
+    instance: Option<&InstanceName>,
+    profile: Option<&str>,
+) -> Result<PathBuf, String> {
+    let Some(profile) = profile else {
+        return Ok(policy_dir(instance));
+    };
+    let root = match instance {
+        Some(name) => Path::new(INSTANCE_POLICY_ROOT)
+            .join(name.as_str())
+            .join("profiles"),
+        None => PathBuf::from(PROFILE_ROOT),
+    };
+    profile_dir(&root, profile)
+}
+
+/// `profile`'s directory under `root`. A profile that isn't there is an
+/// error: loading nothing would deny everything, or fall back to rules meant
+/// for another machine.
+pub fn profile_dir(root: &Path, profile: &str) -> Result<PathBuf, String> {
+    let plain = !profile.is_empty()
+        && !profile.starts_with('.')
+        && profile
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !plain {
+        return Err(format!("invalid policy profile name {:?}", profile));
+    }
+    let dir = root.join(profile);
+    if !dir.is_dir() {
+        return Err(format!(
+            "policy profile {} not found ({} is not a directory)",
+            profile,
+            dir.display()
+        ));
+    }
+    Ok(dir)
+}
+
+/// Combined policy file of `instance`, or of the default instance for `None`
+pub fn policy_file(instance: Option<&InstanceName>) -> PathBuf {
+    match instance {
+        Some(name) => Path::new(INSTANCE_POLICY_ROOT)
+            .join(name.as_str())
+            .join("policy.toml"),
+        None => PathBuf::from(POLICY_FILE),
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum PolicyError {
     #[error("io error: {0}")]
@@ -22,23 +125,221 @@ pub enum PolicyDecision {
     AllowImmediate,
     /// Show confirmation dialog
     AllowWithConfirm,
+    /// Soft deny: warn and require explicit confirmation to proceed
+    Discouraged,
     /// Denied by policy
     Denied(String),
     /// No matching policy
     Unknown,
 }
 
+/// A rule considered while checking a target (for diagnostics)
+#[derive(Debug, Clone)]
+pub struct RuleTrace {
+    /// The rule's `target` (exact path or `*`)
+    pub target: PathBuf,
+    pub auth: AuthRequirement,
+    /// Whether the rule's session types and users/groups/callers admitted
+    /// this request
+    pub admits: bool,
+    /// Policy file the rule came from; `None` for rules added in code
+    pub source: Option<PathBuf>,
+}
+
+/// A decision together with the rules that produced it
+#[derive(Debug, Clone)]
+pub struct Explanation {
+    pub decision: PolicyDecision,
+    pub rules: Vec<RuleTrace>,
+    /// Index in `rules` of the rule whose auth requirement became the
+    /// decision; None when no rule admitted the request
+    pub deciding: Option<usize>,
+    /// The methods that can authorize an `AllowWithConfirm` decision, any
+    /// one or all of them as the deciding rule's `auth` says. For a
+    /// `Discouraged` target on `force_password_targets`, the password its
+    /// warning needs as well; None for every other decision.
+    pub methods: Option<MethodSet>,
+    /// Matching rules with `enabled = false`, which took no part in the decision
+    pub disabled: Vec<PathBuf>,
+    /// What the deciding rule sets for the run; all unset when none decided
+    pub settings: RunSettings,
+}
+
+/// A deciding rule's settings for the run it admitted
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunSettings {
+    /// `runas`; None runs the target as root
+    pub runas: Option<String>,
+    /// `pam_service`; None is `authd`
+    pub pam_service: Option<PamService>,
+    /// `clean_env`; None leaves it to authd.toml
+    pub clean_env: Option<bool>,
+    /// `log_output` template, unexpanded; None leaves output alone
+    pub log_output: Option<PathBuf>,
+}
+
+impl RunSettings {
+    fn of(rule: &PolicyRule) -> Self {
+        Self {
+            runas: rule.runas.clone(),
+            pam_service: rule.pam_service.clone(),
+            clean_env: rule.clean_env,
+            log_output: rule.log_output.clone(),
+        }
+    }
+
+    /// The PAM service passwords for the run are checked through
+    pub fn pam_service(&self) -> &str {
+        self.pam_service
+            .as_ref()
+            .map_or(DEFAULT_PAM_SERVICE, PamService::as_str)
+    }
+}
+
 /// Caller info for policy checking
 #[derive(Debug, Clone)]
 pub struct CallerInfo<'a> {
     pub exe: &'a Path,
     /// Full resolved path of cmdline arg0 (for scripts run via interpreters)
     pub cmdline_path: Option<&'a Path>,
+    /// SELinux context the caller connected with, for `allow_contexts`; only
+    /// the first caller's counts
+    pub secontext: Option<&'a str>,
+    /// systemd unit the caller runs under, for `allow_units`; only the first
+    /// caller's counts
+    pub unit: Option<&'a str>,
+}
+
+/// Where a caller's group memberships come from when matching `allow_groups`
+#[derive(Debug, Clone, Copy)]
+pub enum Groups<'a> {
+    /// The uid's memberships as NSS reports them
+    Nss,
+    /// The gids the calling process actually holds (primary and supplementary)
+    Process(&'a [u32]),
+}
+
+impl Groups<'_> {
+    fn contains(&self, uid: u32, group_name: &str) -> bool {
+        match self {
+            Self::Nss => user_in_group(uid, group_name),
+            Self::Process(gids) => users::get_group_by_name(group_name)
+                .is_some_and(|group| gids.contains(&group.gid())),
+        }
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct PolicyEngine {
-    rules: HashMap<PathBuf, Vec<PolicyRule>>,
+    rules: HashMap<PathBuf, Vec<LoadedRule>>,
+    /// Parsed but switched off; kept only for diagnostics
+    disabled: HashMap<PathBuf, Vec<LoadedRule>>,
+    /// Policy files `load_from_dir` skipped, with why
+    file_errors: Vec<(PathBuf, String)>,
+    /// Policy files loaded, with how many rules each contributed
+    sources: Vec<(PathBuf, usize)>,
+    /// Which admitting rule decides (`rule_resolution`)
+    resolution: RuleResolution,
+    /// The combined policy file `load_all` read, whose rules the drop-ins
+    /// override
+    base_file: Option<PathBuf>,
+    /// What a file with two enabled rules for one exact target and the same
+    /// people gets (`duplicate_targets`)
+    duplicate_targets: DuplicateTargets,
+    /// Targets duplicated within a loaded file, by file, under `warn`
+    duplicates: Vec<(PathBuf, PathBuf)>,
+    /// Targets needing at least `auth = "password"` whatever their rules
+    /// say (`force_password_targets`)
+    force_password: Vec<CallerPattern>,
+}
+
+/// A rule with its `allow_callers`, `allow_scripts`, `allow_contexts` and
+/// `allow_units` patterns compiled, and its `command` split, once at load
+/// time
+#[derive(Debug)]
+struct LoadedRule {
+    rule: PolicyRule,
+    callers: Vec<CallerPattern>,
+    scripts: Vec<CallerPattern>,
+    contexts: Vec<Pattern>,
+    units: Vec<Pattern>,
+    /// The arguments of `command` after the target, which a run's must
+    /// start with
+    arg_prefix: Vec<String>,
+    /// Policy file the rule came from
+    source: Option<PathBuf>,
+}
+
+impl LoadedRule {
+    fn new(rule: PolicyRule, source: Option<&Path>) -> Self {
+        let callers = rule
+            .allow_callers
+            .iter()
+            .map(|pattern| CallerPattern::compile(pattern))
+            .collect();
+        let scripts = rule
+            .allow_scripts
+            .iter()
+            .map(|pattern| CallerPattern::compile(pattern))
+            .collect();
+        let contexts = rule.allow_contexts.iter().map(|p| glob(p)).collect();
+        let units = rule.allow_units.iter().map(|p| glob(p)).collect();
+        let arg_prefix = rule
+            .command
+            .iter()
+            .flat_map(|command| command.split_whitespace().skip(1))
+            .map(str::to_string)
+            .collect();
+        Self {
+            rule,
+            callers,
+            scripts,
+            contexts,
+            units,
+            arg_prefix,
+            source: source.map(Path::to_path_buf),
+        }
+    }
+}
+
+/// An `allow_callers` or `allow_scripts` entry: an exact path, or a glob if
+/// it contains glob metacharacters (`*`, `?`, `[`) and compiles.
+#[derive(Debug)]
+enum CallerPattern {
+    Exact(PathBuf),
+    Glob(Pattern),
+}
+
+/// `*`/`?` never match `/`, so `/opt/app-*/bin/agent` covers versioned
+/// installs but not arbitrary deeper paths.
+const CALLER_GLOB_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+impl CallerPattern {
+    fn compile(pattern: &Path) -> Self {
+        // Never glob on a lossy conversion: a non-UTF8 pattern is exact-only
+        let glob = pattern
+            .to_str()
+            .filter(|text| text.contains(['*', '?', '[']))
+            .and_then(|text| Pattern::new(text).ok());
+        match glob {
+            Some(glob) => Self::Glob(glob),
+            None => Self::Exact(pattern.to_path_buf()),
+        }
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        match self {
+            Self::Exact(exact) => path == exact,
+            // A non-UTF8 path simply doesn't glob-match
+            Self::Glob(glob) => path
+                .to_str()
+                .is_some_and(|path| glob.matches_with(path, CALLER_GLOB_OPTIONS)),
+        }
+    }
 }
 
 impl PolicyEngine {
@@ -46,34 +347,93 @@ impl PolicyEngine {
         Self::default()
     }
 
+    /// Decide between several admitting rules as `resolution` says, instead
+    /// of letting the least restrictive win
+    pub fn set_resolution(&mut self, resolution: RuleResolution) {
+        self.resolution = resolution;
+    }
+
+    /// Warn about (the default) or refuse files that define the same rule
+    /// subject twice
+    pub fn set_duplicate_targets(&mut self, duplicate_targets: DuplicateTargets) {
+        self.duplicate_targets = duplicate_targets;
+    }
+
+    /// Raise every target matching one of `targets` (exact paths or globs)
+    /// to at least `auth = "password"`, however permissive the rule that
+    /// admits it. Stricter rules and refusals stand.
+    pub fn set_force_password_targets(&mut self, targets: &[PathBuf]) {
+        self.force_password = targets
+            .iter()
+            .map(|target| CallerPattern::compile(target))
+            .collect();
+    }
+
     /// Add a rule directly (useful for testing)
     pub fn add_rule(&mut self, rule: PolicyRule) {
+        self.add_rule_from(rule, None);
+    }
+
+    fn add_rule_from(&mut self, rule: PolicyRule, source: Option<&Path>) {
+        if !rule.enabled {
+            self.disabled
+                .entry(rule.target.clone())
+                .or_default()
+                .push(LoadedRule::new(rule, source));
+            return;
+        }
         self.rules
             .entry(rule.target.clone())
             .or_default()
-            .push(rule);
+            .push(LoadedRule::new(rule, source));
     }
 
     /// Load policies from TOML string
     pub fn load_from_str(&mut self, content: &str) -> Result<usize, PolicyError> {
-        let config: PolicyFile = toml::from_str(content).map_err(|e| PolicyError::Parse {
-            file: PathBuf::from("<string>"),
+        let file = Path::new("<string>");
+        let mut config: PolicyFile = toml::from_str(content).map_err(|e| PolicyError::Parse {
+            file: file.to_path_buf(),
             error: e.to_string(),
         })?;
+        apply_commands(&mut config.rules).map_err(|error| PolicyError::Parse {
+            file: file.to_path_buf(),
+            error,
+        })?;
+        self.check_duplicates(file, &config.rules)?;
 
         let count = config.rules.len();
         for rule in config.rules {
-            self.rules
-                .entry(rule.target.clone())
-                .or_default()
-                .push(rule);
+            self.add_rule(rule);
         }
         Ok(count)
     }
 
-    /// Load all policies from the policy directory
+    /// Load all policies: `POLICY_FILE`, then the drop-ins in `POLICY_DIR`
     pub fn load(&mut self) -> Result<(), PolicyError> {
-        self.load_from_dir(Path::new(POLICY_DIR))
+        self.load_all(Path::new(POLICY_FILE), Path::new(POLICY_DIR))
+    }
+
+    /// Load the [`EMBEDDED_POLICY`] if built with one, the combined
+    /// `policy_file` if there is one, then the drop-ins in `policy_dir`.
+    /// Their rules are combined as `rule_resolution` says; under
+    /// `first_match` the drop-ins come first, then the combined file, so
+    /// each overrides the layers below it. Like a drop-in, a combined file
+    /// that fails to parse is skipped and reported by `validate`.
+    pub fn load_all(&mut self, policy_file: &Path, policy_dir: &Path) -> Result<(), PolicyError> {
+        if let Some(embedded) = EMBEDDED_POLICY {
+            if let Err(e) = self.load_embedded(embedded) {
+                self.file_errors
+                    .push((PathBuf::from(EMBEDDED_SOURCE), file_error(&e)));
+            }
+        }
+        if policy_file.exists() {
+            self.base_file = Some(policy_file.to_path_buf());
+            if let Err(e) = self.load_file(policy_file) {
+                self.file_errors
+                    .push((policy_file.to_path_buf(), file_error(&e)));
+            }
+        }
+        self.load_from_dir(policy_dir)
     }
 
     /// Load policies from a specific directory
@@ -82,37 +442,358 @@ impl PolicyEngine {
             return Ok(());
         }
 
+        let mut paths = Vec::new();
         for entry in fs::read_dir(policy_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-
+            let path = entry?.path();
             if path.extension().is_some_and(|e| e == "toml") {
-                // Ignore individual file errors, just skip
-                let _ = self.load_file(&path);
+                paths.push(path);
+            }
+        }
+        // In name order, so `first_match` doesn't depend on the directory's
+        paths.sort();
+
+        for path in paths {
+            // A bad file doesn't stop the others loading; `validate` reports it
+            if let Err(e) = self.load_file(&path) {
+                self.file_errors.push((path, file_error(&e)));
             }
         }
 
         Ok(())
     }
 
+    /// Re-read a single policy file, replacing the rules it contributed and
+    /// leaving every other file's alone, e.g. after a watcher saw it change.
+    /// `path` must be spelled as it was when loaded (`<dir>/<file>.toml`).
+    ///
+    /// A file that no longer exists just has its rules removed. One that
+    /// fails to parse loses its rules too, as it would on a full reload, and
+    /// is reported by `validate`.
+    pub fn reload_file(&mut self, path: &Path) -> Result<usize, PolicyError> {
+        let from_path = |loaded: &LoadedRule| loaded.source.as_deref() == Some(path);
+        for rules in [&mut self.rules, &mut self.disabled] {
+            for entries in rules.values_mut() {
+                entries.retain(|loaded| !from_path(loaded));
+            }
+            rules.retain(|_, entries| !entries.is_empty());
+        }
+        self.sources.retain(|(source, _)| source != path);
+        self.file_errors.retain(|(file, _)| file != path);
+        self.duplicates.retain(|(file, _)| file != path);
+
+        if !path.exists() {
+            return Ok(0);
+        }
+        let loaded = self.load_file(path);
+        if let Err(e) = &loaded {
+            self.file_errors.push((path.to_path_buf(), file_error(e)));
+        }
+        loaded
+    }
+
+    /// Load `default_toml` as the base layer, beneath the combined file and
+    /// the drop-ins: its rules have [`EMBEDDED_SOURCE`] as their source and
+    /// come last under `first_match`. For a policy compiled in with
+    /// `include_str!`, such as [`EMBEDDED_POLICY`].
+    pub fn load_embedded(&mut self, default_toml: &str) -> Result<usize, PolicyError> {
+        self.load_content(Path::new(EMBEDDED_SOURCE), default_toml)
+    }
+
     fn load_file(&mut self, path: &Path) -> Result<usize, PolicyError> {
         let content = fs::read_to_string(path)?;
-        let config: PolicyFile = toml::from_str(&content).map_err(|e| PolicyError::Parse {
+        self.load_content(path, &content)
+    }
+
+    /// Load the policy file `content`, read from `path`
+    fn load_content(&mut self, path: &Path, content: &str) -> Result<usize, PolicyError> {
+        let mut config: PolicyFile = toml::from_str(content).map_err(|e| PolicyError::Parse {
             file: path.to_path_buf(),
             error: e.to_string(),
         })?;
+        apply_commands(&mut config.rules).map_err(|error| PolicyError::Parse {
+            file: path.to_path_buf(),
+            error,
+        })?;
+        self.check_duplicates(path, &config.rules)?;
 
         let count = config.rules.len();
         for rule in config.rules {
-            self.rules
-                .entry(rule.target.clone())
-                .or_default()
-                .push(rule);
+            self.add_rule_from(rule, Some(path));
         }
+        self.sources.push((path.to_path_buf(), count));
 
         Ok(count)
     }
 
+    /// Under `duplicate_targets = "error"`, refuse `file` if it has two
+    /// enabled rules for one exact target and the same people; otherwise
+    /// note them for `validate`. Nothing from a refused file is loaded.
+    fn check_duplicates(&mut self, file: &Path, rules: &[PolicyRule]) -> Result<(), PolicyError> {
+        let duplicated = duplicate_targets(rules);
+        if duplicated.is_empty() {
+            return Ok(());
+        }
+        match self.duplicate_targets {
+            DuplicateTargets::Warn => {
+                let found = duplicated
+                    .into_iter()
+                    .map(|target| (file.to_path_buf(), target));
+                self.duplicates.extend(found);
+                Ok(())
+            }
+            DuplicateTargets::Error => {
+                let targets: Vec<String> = duplicated
+                    .iter()
+                    .map(|target| target.display().to_string())
+                    .collect();
+                Err(PolicyError::Parse {
+                    file: file.to_path_buf(),
+                    error: format!("more than one rule for {}", targets.join(", ")),
+                })
+            }
+        }
+    }
+
+    /// Every policy file loaded, in path order, with how many rules it
+    /// contributed (disabled ones included). Files that failed to parse are
+    /// in `validate` instead.
+    pub fn sources(&self) -> Vec<(PathBuf, usize)> {
+        let mut sources = self.sources.clone();
+        sources.sort();
+        sources
+    }
+
+    /// Number of loaded, enabled rules
+    pub fn rule_count(&self) -> usize {
+        self.rules.values().map(Vec::len).sum()
+    }
+
+    /// Number of loaded rules with `enabled = false`
+    pub fn disabled_count(&self) -> usize {
+        self.disabled.values().map(Vec::len).sum()
+    }
+
+    /// Rules matching `target` that apply to a run with `args`: those with
+    /// a `command` only when `args` start with its arguments. The settings
+    /// below come from these, so a command rule's don't reach other runs.
+    fn rules_for_run<A: AsRef<OsStr>>(&self, target: &Path, args: &[A]) -> Vec<&LoadedRule> {
+        matching_rules(&self.rules, target)
+            .into_iter()
+            .filter(|loaded| args_allowed(loaded, args))
+            .collect()
+    }
+
+    /// Shortest `cache_timeout` (seconds) among rules matching `target` run
+    /// with `args`.
+    ///
+    /// Taking the minimum keeps a sensitive exact rule from inheriting a
+    /// longer window from a broad wildcard. Returns 0 when nothing matches.
+    pub fn cache_timeout<A: AsRef<OsStr>>(&self, target: &Path, args: &[A]) -> u64 {
+        self.rules_for_run(target, args)
+            .iter()
+            .map(|loaded| loaded.rule.cache_timeout)
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Shortest window (seconds) a confirmation alone is remembered for
+    /// `target`: each matching rule's `confirm_cache_timeout`, or its
+    /// `cache_timeout` where unset. Returns 0 when nothing matches.
+    pub fn confirm_cache_timeout<A: AsRef<OsStr>>(&self, target: &Path, args: &[A]) -> u64 {
+        self.rules_for_run(target, args)
+            .iter()
+            .map(|loaded| {
+                loaded
+                    .rule
+                    .confirm_cache_timeout
+                    .unwrap_or(loaded.rule.cache_timeout)
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Resource limits for `target` run with `args`: the lowest value of
+    /// each among the rules matching it
+    pub fn limits<A: AsRef<OsStr>>(&self, target: &Path, args: &[A]) -> ResourceLimits {
+        self.rules_for_run(target, args)
+            .iter()
+            .fold(ResourceLimits::default(), |limits, loaded| {
+                limits.strictest(loaded.rule.limits)
+            })
+    }
+
+    /// Lowest `max_concurrent` among rules matching a run, if any of them
+    /// sets one
+    pub fn max_concurrent<A: AsRef<OsStr>>(&self, target: &Path, args: &[A]) -> Option<usize> {
+        self.rules_for_run(target, args)
+            .iter()
+            .filter_map(|loaded| loaded.rule.max_concurrent)
+            .min()
+    }
+
+    /// Strictest `min_binary_age_secs` among rules matching a run, if
+    /// any of them sets one.
+    pub fn min_binary_age_secs<A: AsRef<OsStr>>(&self, target: &Path, args: &[A]) -> Option<u64> {
+        self.rules_for_run(target, args)
+            .iter()
+            .filter_map(|loaded| loaded.rule.min_binary_age_secs)
+            .max()
+    }
+
+    /// Strictest `min_caller_age_secs` among rules matching a run, if
+    /// any of them sets one.
+    pub fn min_caller_age_secs<A: AsRef<OsStr>>(&self, target: &Path, args: &[A]) -> Option<u64> {
+        self.rules_for_run(target, args)
+            .iter()
+            .filter_map(|loaded| loaded.rule.min_caller_age_secs)
+            .max()
+    }
+
+    /// Groups whose members must approve a run under the two-person rule
+    /// (`require_approver_group`), from every rule matching it that sets
+    /// one. The approver has to belong to all of them.
+    pub fn approver_groups<A: AsRef<OsStr>>(&self, target: &Path, args: &[A]) -> Vec<String> {
+        let mut groups: Vec<String> = self
+            .rules_for_run(target, args)
+            .iter()
+            .filter_map(|loaded| loaded.rule.require_approver_group.clone())
+            .collect();
+        groups.sort();
+        groups.dedup();
+        groups
+    }
+
+    /// `notify_webhook` URLs of every rule matching a run that sets one
+    pub fn notify_webhooks<A: AsRef<OsStr>>(&self, target: &Path, args: &[A]) -> Vec<String> {
+        let mut urls: Vec<String> = self
+            .rules_for_run(target, args)
+            .iter()
+            .filter_map(|loaded| loaded.rule.notify_webhook.clone())
+            .collect();
+        urls.sort();
+        urls.dedup();
+        urls
+    }
+
+    /// Why `args` can't be passed to `target`: a rule matching the run lists
+    /// `allow_scripts` and `args[0]` isn't one of them. It has to be a plain
+    /// absolute path, so `-c`, `-m` and `-` (stdin) are refused as well. With
+    /// several such rules the script must be on every list.
+    pub fn script_refusal<A: AsRef<OsStr>>(&self, target: &Path, args: &[A]) -> Option<String> {
+        let restricting: Vec<&LoadedRule> = self
+            .rules_for_run(target, args)
+            .into_iter()
+            .filter(|loaded| !loaded.scripts.is_empty())
+            .collect();
+        if restricting.is_empty() {
+            return None;
+        }
+        let Some(script) = args.first().map(|arg| Path::new(arg.as_ref())) else {
+            return Some(format!("{} only runs allowed scripts", target.display()));
+        };
+        let allowed = is_plain_path(script)
+            && restricting
+                .iter()
+                .all(|loaded| loaded.scripts.iter().any(|pattern| pattern.matches(script)));
+        if allowed {
+            return None;
+        }
+        Some(format!(
+            "{} is not an allowed script for {}",
+            script.display(),
+            target.display()
+        ))
+    }
+
+    /// Whether a rule matching a run lists `allow_scripts`, so `target` is
+    /// an interpreter whose environment is cut down to `SCRIPT_ENV` as well
+    pub fn restricts_scripts<A: AsRef<OsStr>>(&self, target: &Path, args: &[A]) -> bool {
+        self.rules_for_run(target, args)
+            .iter()
+            .any(|loaded| !loaded.scripts.is_empty())
+    }
+
+    /// Whether a rule matching a run lists `allow_argv0`, so `target` is
+    /// started under the name it was invoked by rather than its own path
+    pub fn pins_argv0<A: AsRef<OsStr>>(&self, target: &Path, args: &[A]) -> bool {
+        self.rules_for_run(target, args)
+            .iter()
+            .any(|loaded| !loaded.rule.allow_argv0.is_empty())
+    }
+
+    /// Whether a rule matching a run sets `forbid_setuid_target`
+    pub fn forbids_setuid_target<A: AsRef<OsStr>>(&self, target: &Path, args: &[A]) -> bool {
+        self.rules_for_run(target, args)
+            .iter()
+            .any(|loaded| loaded.rule.forbid_setuid_target)
+    }
+
+    /// Whether a rule matching a run sets `forbid_file_caps`
+    pub fn forbids_file_caps<A: AsRef<OsStr>>(&self, target: &Path, args: &[A]) -> bool {
+        self.rules_for_run(target, args)
+            .iter()
+            .any(|loaded| loaded.rule.forbid_file_caps)
+    }
+
+    /// Whether a rule matching a run sets `require_type_confirm`
+    pub fn requires_type_confirm<A: AsRef<OsStr>>(&self, target: &Path, args: &[A]) -> bool {
+        self.rules_for_run(target, args)
+            .iter()
+            .any(|loaded| loaded.rule.require_type_confirm)
+    }
+
+    /// Whether a rule matching a run sets `forbid_if_others_logged_in`
+    pub fn forbids_others_logged_in<A: AsRef<OsStr>>(&self, target: &Path, args: &[A]) -> bool {
+        self.rules_for_run(target, args)
+            .iter()
+            .any(|loaded| loaded.rule.forbid_if_others_logged_in)
+    }
+
+    /// Why `target` run with `args` can't be started as `argv0`: a rule
+    /// matching the run lists `allow_argv0` and the file name of `argv0`
+    /// isn't on it. With several such rules it must be on every list.
+    pub fn argv0_refusal<A: AsRef<OsStr>>(
+        &self,
+        target: &Path,
+        args: &[A],
+        argv0: &Path,
+    ) -> Option<String> {
+        let name = argv0.file_name().and_then(OsStr::to_str);
+        let allowed = self
+            .rules_for_run(target, args)
+            .iter()
+            .map(|loaded| &loaded.rule.allow_argv0)
+            .filter(|names| !names.is_empty())
+            .all(|names| name.is_some_and(|name| names.iter().any(|allowed| allowed == name)));
+        if allowed {
+            return None;
+        }
+        Some(format!(
+            "{} may not be run as {}",
+            target.display(),
+            argv0.display()
+        ))
+    }
+
+    /// Whether `callers` already decide every rule for `target` that lists
+    /// `allow_callers`, each being admitted by one of them. More ancestors
+    /// can't change a check then, so a walk up the process tree can stop.
+    /// Rules with `allow_units` or `allow_contexts` need the direct caller
+    /// read; otherwise true from the start when no rule lists callers.
+    pub fn callers_settled(&self, target: &Path, callers: &[CallerInfo]) -> bool {
+        let rules = matching_rules(&self.rules, target);
+        let needs_direct = rules
+            .iter()
+            .any(|loaded| !loaded.units.is_empty() || !loaded.contexts.is_empty());
+        if needs_direct && callers.is_empty() {
+            return false;
+        }
+        rules
+            .into_iter()
+            .filter(|loaded| !loaded.callers.is_empty())
+            .all(|loaded| caller_allowed(loaded, callers))
+    }
+
     /// Check if a user is authorized to run a target
     pub fn check(&self, target: &Path, uid: u32) -> PolicyDecision {
         self.check_with_caller(target, uid, None)
@@ -130,6 +811,8 @@ impl PolicyEngine {
             .map(|exe| CallerInfo {
                 exe,
                 cmdline_path: None,
+                secontext: None,
+                unit: None,
             })
             .collect();
         self.check_with_callers(target, uid, &callers)
@@ -142,39 +825,242 @@ impl PolicyEngine {
         uid: u32,
         callers: &[CallerInfo],
     ) -> PolicyDecision {
+        self.check_explain(target, uid, callers).decision
+    }
+
+    /// Like `check_with_callers`, matching `allow_groups` against `groups`
+    pub fn check_with_groups(
+        &self,
+        target: &Path,
+        uid: u32,
+        groups: Groups,
+        callers: &[CallerInfo],
+    ) -> PolicyDecision {
+        self.check_explain_with_groups(target, uid, groups, callers)
+            .decision
+    }
+
+    /// Like `check_with_groups`, for a caller in a `session` of known type.
+    /// Rules with `allow_session_types` apply only when it is one of them;
+    /// without a session type they never apply.
+    pub fn check_in_session(
+        &self,
+        target: &Path,
+        uid: u32,
+        groups: Groups,
+        session: Option<SessionType>,
+        callers: &[CallerInfo],
+    ) -> PolicyDecision {
+        self.check_explain_in_session(target, uid, groups, session, callers)
+            .decision
+    }
+
+    /// Like `check_with_callers`, but also reports every matching rule and
+    /// whether it admitted the request.
+    pub fn check_explain(&self, target: &Path, uid: u32, callers: &[CallerInfo]) -> Explanation {
+        self.check_explain_with_groups(target, uid, Groups::Nss, callers)
+    }
+
+    /// `check_explain` with an explicit source for group memberships
+    pub fn check_explain_with_groups(
+        &self,
+        target: &Path,
+        uid: u32,
+        groups: Groups,
+        callers: &[CallerInfo],
+    ) -> Explanation {
+        self.check_explain_in_session(target, uid, groups, None, callers)
+    }
+
+    /// `check_explain_with_groups` for a caller in a `session` of known type
+    pub fn check_explain_in_session(
+        &self,
+        target: &Path,
+        uid: u32,
+        groups: Groups,
+        session: Option<SessionType>,
+        callers: &[CallerInfo],
+    ) -> Explanation {
+        let no_args: &[&OsStr] = &[];
+        self.check_explain_command(target, no_args, uid, groups, session, callers)
+    }
+
+    /// Like `check_in_session`, for `target` run with `args`. Rules with a
+    /// `command` apply only when `args` start with its arguments; the checks
+    /// above, knowing no arguments, never apply them.
+    pub fn check_command<A: AsRef<OsStr>>(
+        &self,
+        target: &Path,
+        args: &[A],
+        uid: u32,
+        groups: Groups,
+        session: Option<SessionType>,
+        callers: &[CallerInfo],
+    ) -> PolicyDecision {
+        self.check_explain_command(target, args, uid, groups, session, callers)
+            .decision
+    }
+
+    /// `check_explain_in_session` for `target` run with `args`
+    pub fn check_explain_command<A: AsRef<OsStr>>(
+        &self,
+        target: &Path,
+        args: &[A],
+        uid: u32,
+        groups: Groups,
+        session: Option<SessionType>,
+        callers: &[CallerInfo],
+    ) -> Explanation {
+        let disabled = matching_rules(&self.disabled, target)
+            .into_iter()
+            .map(|loaded| loaded.rule.target.clone())
+            .collect();
         let matching_rules = matching_rules(&self.rules, target);
         if matching_rules.is_empty() {
-            return PolicyDecision::Unknown;
+            return Explanation {
+                decision: PolicyDecision::Unknown,
+                rules: Vec::new(),
+                deciding: None,
+                methods: None,
+                disabled,
+                settings: RunSettings::default(),
+            };
         }
 
         let username = username_from_uid(uid);
-        let mut best_auth: Option<&AuthRequirement> = None;
+        let mut admitting = Vec::new();
+        let mut traces = Vec::with_capacity(matching_rules.len());
 
-        for rule in matching_rules {
-            if !rule_allows(rule, uid, username.as_deref(), callers) {
-                continue;
+        for &loaded in &matching_rules {
+            let admits = args_allowed(loaded, args)
+                && session_allowed(&loaded.rule, session)
+                && context_allowed(loaded, callers)
+                && rule_allows(loaded, uid, username.as_deref(), groups, callers);
+            let rule = &loaded.rule;
+            traces.push(RuleTrace {
+                target: rule.target.clone(),
+                auth: rule.auth.clone(),
+                admits,
+                source: loaded.source.clone(),
+            });
+            if admits {
+                admitting.push(loaded);
             }
-            if matches!(rule.auth, AuthRequirement::None) {
-                return PolicyDecision::AllowImmediate;
+        }
+
+        let best = resolve(self.resolution, self.base_file.as_deref(), admitting);
+        let auth = best.map(|loaded| self.effective_auth(target, &loaded.rule.auth));
+        let methods = match &auth {
+            Some(AuthRequirement::Discourage) if self.forces_password(target) => {
+                Some(MethodSet::Any(vec![AuthMethod::Password]))
             }
-            update_best_auth(&mut best_auth, &rule.auth);
+            auth => auth.as_ref().and_then(AuthRequirement::methods),
+        };
+        Explanation {
+            decision: decision_for(auth.as_ref()),
+            rules: traces,
+            deciding: best.and_then(|best| {
+                matching_rules
+                    .iter()
+                    .position(|&loaded| std::ptr::eq(loaded, best))
+            }),
+            methods,
+            disabled,
+            settings: best
+                .map_or_else(RunSettings::default, |loaded| RunSettings::of(&loaded.rule)),
         }
+    }
+
+    /// Whether `target` is on `force_password_targets`
+    fn forces_password(&self, target: &Path) -> bool {
+        self.force_password
+            .iter()
+            .any(|pattern| pattern.matches(target))
+    }
 
-        match best_auth {
-            Some(AuthRequirement::None) => PolicyDecision::AllowImmediate,
-            Some(AuthRequirement::Confirm | AuthRequirement::Password) => {
-                PolicyDecision::AllowWithConfirm
+    /// `auth`, raised to `password` for a target on `force_password_targets`.
+    /// A set of methods keeps its others but takes `password` for `confirm`.
+    fn effective_auth(&self, target: &Path, auth: &AuthRequirement) -> AuthRequirement {
+        let forced = self.forces_password(target);
+        match auth {
+            _ if !forced => auth.clone(),
+            AuthRequirement::Methods(set) => {
+                let raise = |methods: &[AuthMethod]| {
+                    methods
+                        .iter()
+                        .map(|&method| match method {
+                            AuthMethod::Confirm => AuthMethod::Password,
+                            method => method,
+                        })
+                        .collect()
+                };
+                let raised = match set {
+                    MethodSet::Any(methods) => MethodSet::Any(raise(methods)),
+                    MethodSet::All(methods) => MethodSet::All(raise(methods)),
+                };
+                AuthRequirement::from_methods(raised).unwrap_or(AuthRequirement::Password)
+            }
+            _ if auth_priority(auth) < auth_priority(&AuthRequirement::Password) => {
+                AuthRequirement::Password
             }
-            Some(AuthRequirement::Deny) => PolicyDecision::Denied("target denied by policy".into()),
-            None => PolicyDecision::Denied("user not authorized".into()),
+            _ => auth.clone(),
+        }
+    }
+}
+
+/// Exact targets with more than one enabled rule for the same users,
+/// groups, callers, units, session types, contexts and command among
+/// `rules`, sorted. Rules for one target that admit different people are how
+/// policy is written; the same people twice means one rule masks the other.
+/// Several `*` rules are normal, and a disabled copy is an edit in progress.
+fn duplicate_targets(rules: &[PolicyRule]) -> Vec<PathBuf> {
+    let mut seen = HashMap::new();
+    for rule in rules.iter().filter(|rule| rule.enabled) {
+        if rule.target == Path::new("*") {
+            continue;
         }
+        let who = (
+            &rule.target,
+            &rule.allow_users,
+            &rule.allow_groups,
+            &rule.allow_callers,
+            &rule.allow_units,
+            &rule.allow_session_types,
+            &rule.allow_contexts,
+            &rule.command,
+        );
+        *seen.entry(who).or_insert(0) += 1;
     }
+    let mut duplicated: Vec<PathBuf> = seen
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|((target, ..), _)| target.clone())
+        .collect();
+    duplicated.sort();
+    duplicated.dedup();
+    duplicated
 }
 
-fn matching_rules<'a>(
-    rules: &'a HashMap<PathBuf, Vec<PolicyRule>>,
-    target: &Path,
-) -> Vec<&'a PolicyRule> {
+fn file_error(error: &PolicyError) -> String {
+    match error {
+        PolicyError::Parse { error, .. } => error.clone(),
+        e => e.to_string(),
+    }
+}
+
+fn decision_for(best_auth: Option<&AuthRequirement>) -> PolicyDecision {
+    match best_auth {
+        Some(AuthRequirement::None) => PolicyDecision::AllowImmediate,
+        Some(
+            AuthRequirement::Confirm | AuthRequirement::Password | AuthRequirement::Methods(_),
+        ) => PolicyDecision::AllowWithConfirm,
+        Some(AuthRequirement::Discourage) => PolicyDecision::Discouraged,
+        Some(AuthRequirement::Deny) => PolicyDecision::Denied("target denied by policy".into()),
+        None => PolicyDecision::Denied("user not authorized".into()),
+    }
+}
+
+fn matching_rules<'a, R>(rules: &'a HashMap<PathBuf, Vec<R>>, target: &Path) -> Vec<&'a R> {
     let mut matches = Vec::new();
     if let Some(exact_rules) = rules.get(target) {
         matches.extend(exact_rules);
@@ -185,73 +1071,236 @@ fn matching_rules<'a>(
     matches
 }
 
+/// A `!group` exclusion vetoes the rule outright, even for users or callers
+/// it would otherwise admit.
 fn rule_allows(
-    rule: &PolicyRule,
+    loaded: &LoadedRule,
     uid: u32,
     username: Option<&str>,
+    groups: Groups,
     callers: &[CallerInfo],
 ) -> bool {
-    user_allowed(rule, username) || group_allowed(rule, uid) || caller_allowed(rule, callers)
+    let membership = group_match(&loaded.rule.allow_groups, |group| {
+        groups.contains(uid, group)
+    });
+    if membership == GroupMatch::Excluded {
+        return false;
+    }
+    user_allowed(&loaded.rule, username)
+        || membership == GroupMatch::Allowed
+        || caller_allowed(loaded, callers)
+        || unit_allowed(loaded, callers)
+}
+
+/// Rules with `allow_units` admit a first caller running under one of them
+fn unit_allowed(loaded: &LoadedRule, callers: &[CallerInfo]) -> bool {
+    callers
+        .first()
+        .and_then(|caller| caller.unit)
+        .is_some_and(|unit| loaded.units.iter().any(|pattern| pattern.matches(unit)))
+}
+
+/// A glob over names; one that doesn't compile is taken literally
+fn glob(pattern: &str) -> Pattern {
+    Pattern::new(pattern)
+        .unwrap_or_else(|_| Pattern::new(&Pattern::escape(pattern)).expect("escaped pattern"))
+}
+
+fn session_allowed(rule: &PolicyRule, session: Option<SessionType>) -> bool {
+    rule.allow_session_types.is_empty()
+        || session.is_some_and(|session| {
+            rule.allow_session_types
+                .iter()
+                .any(|name| SessionType::parse(name) == Some(session))
+        })
+}
+
+/// Fill in the target of each rule in `rules` that gives a `command`
+/// instead, and check those giving both agree. Every rule needs one or the
+/// other.
+fn apply_commands(rules: &mut [PolicyRule]) -> Result<(), String> {
+    for rule in rules {
+        let Some(command) = &rule.command else {
+            if rule.target.as_os_str().is_empty() {
+                return Err("a rule has neither target nor command".into());
+            }
+            continue;
+        };
+        let Some(program) = command.split_whitespace().next() else {
+            return Err("a rule has an empty command".into());
+        };
+        // Another spelling of the same run (`apt -y remove`) would miss it
+        if rule.auth == AuthRequirement::Deny {
+            return Err(format!(
+                "command {:?} can't have auth = \"deny\"; deny its target instead",
+                command
+            ));
+        }
+        if rule.target.as_os_str().is_empty() {
+            rule.target = PathBuf::from(program);
+        } else if rule.target != Path::new(program) {
+            return Err(format!(
+                "command {:?} doesn't start with its rule's target {}",
+                command,
+                rule.target.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `args` start with the rule's `command` arguments, word for word
+fn args_allowed<A: AsRef<OsStr>>(loaded: &LoadedRule, args: &[A]) -> bool {
+    args.len() >= loaded.arg_prefix.len()
+        && loaded
+            .arg_prefix
+            .iter()
+            .zip(args)
+            .all(|(word, arg)| arg.as_ref() == OsStr::new(word))
+}
+
+/// Rules with `allow_contexts` apply only to a first caller whose SELinux
+/// context matches one of them, and never to one whose context is unknown
+fn context_allowed(loaded: &LoadedRule, callers: &[CallerInfo]) -> bool {
+    loaded.contexts.is_empty()
+        || callers
+            .first()
+            .and_then(|caller| caller.secontext)
+            .is_some_and(|context| {
+                loaded
+                    .contexts
+                    .iter()
+                    .any(|pattern| pattern.matches(context))
+            })
 }
 
 fn user_allowed(rule: &PolicyRule, username: Option<&str>) -> bool {
     username.is_some_and(|username| rule.allow_users.iter().any(|user| user == username))
 }
 
-fn group_allowed(rule: &PolicyRule, uid: u32) -> bool {
-    rule.allow_groups
+/// How a user relates to a rule's `allow_groups`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GroupMatch {
+    /// Member of a listed group and of no `!`-excluded one
+    Allowed,
+    /// Member of no listed group
+    NotListed,
+    /// Member of a `!`-prefixed group; wins over any positive entry
+    Excluded,
+}
+
+fn group_match(allow_groups: &[String], in_group: impl Fn(&str) -> bool) -> GroupMatch {
+    let (excluded, allowed): (Vec<&str>, Vec<&str>) = allow_groups
         .iter()
-        .any(|group| user_in_group(uid, group))
+        .map(String::as_str)
+        .partition(|group| group.starts_with('!'));
+
+    if excluded.iter().any(|group| in_group(&group[1..])) {
+        GroupMatch::Excluded
+    } else if allowed.iter().any(|group| in_group(group)) {
+        GroupMatch::Allowed
+    } else {
+        GroupMatch::NotListed
+    }
+}
+
+/// Absolute, without `.` or `..`, so a glob can't be walked out of
+fn is_plain_path(path: &Path) -> bool {
+    path.is_absolute()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::RootDir | Component::Normal(_)))
 }
 
-fn caller_allowed(rule: &PolicyRule, callers: &[CallerInfo]) -> bool {
+fn caller_allowed(loaded: &LoadedRule, callers: &[CallerInfo]) -> bool {
     callers
         .iter()
-        .any(|caller| caller_matches_rule(rule, caller))
+        .any(|caller| caller_matches_rule(loaded, caller))
 }
 
-fn caller_matches_rule(rule: &PolicyRule, caller: &CallerInfo) -> bool {
-    rule.allow_callers.iter().any(|allowed| {
-        path_matches_pattern(caller.exe, allowed)
+/// Each `allow_callers` pattern is tried against every ancestor's `exe` and
+/// resolved `cmdline_path`.
+fn caller_matches_rule(loaded: &LoadedRule, caller: &CallerInfo) -> bool {
+    loaded.callers.iter().any(|allowed| {
+        allowed.matches(caller.exe)
             || caller
                 .cmdline_path
-                .is_some_and(|path| path_matches_pattern(path, allowed))
+                .is_some_and(|path| allowed.matches(path))
     })
 }
 
-fn update_best_auth<'a>(
-    best_auth: &mut Option<&'a AuthRequirement>,
-    candidate: &'a AuthRequirement,
-) {
-    let dominated = best_auth.is_some_and(|best| auth_priority(candidate) >= auth_priority(best));
-    if !dominated {
-        *best_auth = Some(candidate);
+/// The auth requirement of the rule that decides among `admitting`, which
+/// are exact-target rules before wildcard ones. `base_file`'s rules come
+/// after the drop-ins' for `first_match`, and embedded ones after both.
+fn resolve<'a>(
+    resolution: RuleResolution,
+    base_file: Option<&Path>,
+    mut admitting: Vec<&'a LoadedRule>,
+) -> Option<&'a LoadedRule> {
+    match resolution {
+        RuleResolution::LeastRestrictive => least_restrictive(admitting),
+        RuleResolution::MostRestrictive => admitting
+            .into_iter()
+            .max_by_key(|loaded| auth_priority(&loaded.rule.auth)),
+        RuleResolution::FirstMatch => {
+            // Stable, so the rules of one file keep their order
+            admitting.sort_by_key(|&loaded| {
+                let source = loaded.source.as_deref();
+                let layer = if source == Some(Path::new(EMBEDDED_SOURCE)) {
+                    2
+                } else if source.is_some() && source == base_file {
+                    1
+                } else {
+                    0
+                };
+                (loaded.rule.target == Path::new("*"), layer, source)
+            });
+            admitting.first().copied()
+        }
+        RuleResolution::Priority => {
+            let highest = admitting.iter().map(|loaded| loaded.rule.priority).max()?;
+            admitting.retain(|loaded| loaded.rule.priority == highest);
+            least_restrictive(admitting)
+        }
     }
 }
 
+/// The first of the rules with the least restrictive auth requirement
+fn least_restrictive(rules: Vec<&LoadedRule>) -> Option<&LoadedRule> {
+    rules
+        .into_iter()
+        .min_by_key(|loaded| auth_priority(&loaded.rule.auth))
+}
+
 fn auth_priority(auth: &AuthRequirement) -> u8 {
     match auth {
         AuthRequirement::None => 0,
         AuthRequirement::Confirm => 1,
         AuthRequirement::Password => 2,
-        AuthRequirement::Deny => 3,
+        // A choice is as weak as its weakest method, a combination as
+        // strong as its strongest
+        AuthRequirement::Methods(MethodSet::Any(methods)) => {
+            methods.iter().map(method_priority).min().unwrap_or(2)
+        }
+        AuthRequirement::Methods(MethodSet::All(methods)) => {
+            methods.iter().map(method_priority).max().unwrap_or(2)
+        }
+        AuthRequirement::Discourage => 3,
+        AuthRequirement::Deny => 4,
+    }
+}
+
+fn method_priority(method: &AuthMethod) -> u8 {
+    match method {
+        AuthMethod::Confirm => 1,
+        AuthMethod::Password | AuthMethod::Hwkey => 2,
     }
 }
 
 /// Check if a path matches a pattern (exact match or glob pattern)
+#[cfg(test)]
 fn path_matches_pattern(path: &Path, pattern: &Path) -> bool {
-    // Exact match
-    if path == pattern {
-        return true;
-    }
-    // Glob pattern match (only if pattern contains glob chars)
-    let pattern_str = pattern.to_string_lossy();
-    if pattern_str.contains('*') || pattern_str.contains('?') || pattern_str.contains('[') {
-        if let Ok(glob) = Pattern::new(&pattern_str) {
-            return glob.matches_path(path);
-        }
-    }
-    false
+    CallerPattern::compile(pattern).matches(path)
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -266,6 +1315,10 @@ pub fn username_from_uid(uid: u32) -> Option<String> {
     users::get_user_by_uid(uid).map(|u| u.name().to_string_lossy().into_owned())
 }
 
+pub fn uid_from_username(name: &str) -> Option<u32> {
+    users::get_user_by_name(name).map(|u| u.uid())
+}
+
 pub fn user_in_group(uid: u32, group_name: &str) -> bool {
     let Some(user) = users::get_user_by_uid(uid) else {
         return false;
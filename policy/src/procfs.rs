@@ -0,0 +1,58 @@
+//! Whether `/proc` is there to read
+//!
+//! Callers are identified through `/proc/<pid>` (executable, command line,
+//! groups, session). Without procfs, as in some minimal containers, every
+//! lookup would come back empty: `allow_callers` would quietly stop matching
+//! and process groups and sessions would go unknown. authd and authsudo check
+//! up front instead and refuse to run.
+
+use std::path::Path;
+
+/// Where procfs is mounted
+pub const PROC_ROOT: &str = "/proc";
+
+/// Why authd and authsudo refuse to run without procfs
+pub const PROC_REQUIRED: &str = "authd requires /proc";
+
+/// Check that procfs is mounted at `PROC_ROOT`
+pub fn require() -> Result<(), String> {
+    require_at(Path::new(PROC_ROOT))
+}
+
+/// Check that procfs is mounted at `root`. An empty directory, as left where
+/// nothing was mounted, doesn't count: only procfs has `self/stat`.
+pub fn require_at(root: &Path) -> Result<(), String> {
+    if root.join("self").join("stat").is_file() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} ({} is not mounted)",
+            PROC_REQUIRED,
+            root.display()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mounted_proc_is_accepted() {
+        assert!(require().is_ok());
+    }
+
+    #[test]
+    fn missing_or_empty_proc_is_refused() {
+        let error = require_at(Path::new("/nonexistent/proc")).unwrap_err();
+        assert_eq!(
+            error,
+            "authd requires /proc (/nonexistent/proc is not mounted)"
+        );
+
+        let empty = std::env::temp_dir().join(format!("authd-noproc-{}", std::process::id()));
+        std::fs::create_dir_all(&empty).unwrap();
+        assert!(require_at(&empty).is_err());
+        std::fs::remove_dir(&empty).unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+//! Hiding secrets passed on the command line (`--token abc123`) wherever a
+//! command is shown: confirmation dialogs, terminal prompts and webhook
+//! notifications. Only the rendering is redacted; the command still runs with
+//! the arguments it was given.
+//!
+//! Configured by the `[redact_args]` table of authd.toml:
+//!
+//! ```toml
+//! [redact_args]
+//! flags = ["--password", "--token", "--api-key"]
+//! patterns = ["ghp_*", "*://*:*@*"]
+//! ```
+
+use authd_protocol::AuthRequest;
+use glob::Pattern;
+use serde::{Deserialize, Deserializer};
+
+/// What a hidden argument is shown as
+pub const REDACTED: &str = "<redacted>";
+
+/// Default `redact_args.flags`
+pub const DEFAULT_SECRET_FLAGS: &[&str] = &["--password", "--token"];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Redaction {
+    /// Flags taking a secret, as the next argument (`--token abc`) or after
+    /// `=` (`--token=abc`). Setting this replaces the defaults.
+    pub flags: Vec<String>,
+    /// Glob patterns; an argument matching one is hidden whole
+    #[serde(deserialize_with = "patterns")]
+    pub patterns: Vec<Pattern>,
+}
+
+impl Default for Redaction {
+    fn default() -> Self {
+        Self {
+            flags: DEFAULT_SECRET_FLAGS
+                .iter()
+                .map(|&flag| flag.into())
+                .collect(),
+            patterns: Vec::new(),
+        }
+    }
+}
+
+impl Redaction {
+    /// `args` as they may be shown
+    pub fn args(&self, args: &[String]) -> Vec<String> {
+        let mut hide_next = false;
+        args.iter()
+            .map(|arg| {
+                if std::mem::take(&mut hide_next) {
+                    return REDACTED.into();
+                }
+                if self.is_secret_flag(arg) {
+                    hide_next = true;
+                    return arg.clone();
+                }
+                match arg.split_once('=') {
+                    Some((flag, _)) if self.is_secret_flag(flag) => format!("{flag}={REDACTED}"),
+                    _ if self.patterns.iter().any(|pattern| pattern.matches(arg)) => {
+                        REDACTED.into()
+                    }
+                    _ => arg.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// A copy of `request` to show, never to run
+    pub fn request(&self, request: &AuthRequest) -> AuthRequest {
+        AuthRequest {
+            args: self.args(&request.args),
+            ..request.clone()
+        }
+    }
+
+    fn is_secret_flag(&self, arg: &str) -> bool {
+        self.flags.iter().any(|flag| flag == arg)
+    }
+}
+
+/// Compile at load, so a bad pattern is a config error rather than a secret
+/// shown in the clear
+fn patterns<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Pattern>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|pattern| {
+            Pattern::new(pattern)
+                .map_err(|e| serde::de::Error::custom(format!("pattern {pattern:?}: {e}")))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| arg.into()).collect()
+    }
+
+    fn redaction(toml: &str) -> Redaction {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn values_of_secret_flags_are_hidden() {
+        let redaction = Redaction::default();
+        assert_eq!(
+            redaction.args(&strings(&["--token", "abc123", "--region", "eu"])),
+            strings(&["--token", "<redacted>", "--region", "eu"])
+        );
+        assert_eq!(
+            redaction.args(&strings(&["--password=hunter2", "-v"])),
+            strings(&["--password=<redacted>", "-v"])
+        );
+        // Whatever follows a secret flag is its value, even another flag
+        assert_eq!(
+            redaction.args(&strings(&["--token", "--password", "x"])),
+            strings(&["--token", "<redacted>", "x"])
+        );
+    }
+
+    #[test]
+    fn arguments_matching_a_pattern_are_hidden_whole() {
+        let redaction = redaction(
+            r#"
+            flags = ["--api-key"]
+            patterns = ["ghp_*"]
+            "#,
+        );
+        assert_eq!(
+            redaction.args(&strings(&[
+                "clone",
+                "ghp_abc",
+                "--api-key",
+                "k",
+                "--token",
+                "t"
+            ])),
+            strings(&[
+                "clone",
+                "<redacted>",
+                "--api-key",
+                "<redacted>",
+                "--token",
+                "t"
+            ])
+        );
+    }
+
+    #[test]
+    fn invalid_patterns_are_refused() {
+        assert!(toml::from_str::<Redaction>(r#"patterns = ["[unclosed"]"#).is_err());
+    }
+
+    #[test]
+    fn shown_requests_leave_the_real_arguments_alone() {
+        let request = AuthRequest {
+            target: PathBuf::from("/usr/bin/deploy"),
+            args: strings(&["--token", "abc123"]),
+            env: HashMap::new(),
+            password: String::new(),
+            confirm_only: false,
+            prompt_title: None,
+            prompt_message: None,
+            prompt_detail: None,
+            approval: None,
+            discouraged: false,
+            methods: None,
+            pam_service: None,
+        };
+        let shown = Redaction::default().request(&request);
+        assert_eq!(shown.args, strings(&["--token", "<redacted>"]));
+        assert_eq!(shown.target, request.target);
+        assert_eq!(request.args, strings(&["--token", "abc123"]));
+    }
+}
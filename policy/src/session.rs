@@ -0,0 +1,319 @@
+//! The kind of login session a caller runs in, for `allow_session_types`
+//!
+//! Only logind is asked: a process's session comes from its cgroup
+//! (`session-<id>.scope`) and the session's record under
+//! [`LOGIND_SESSIONS`], neither of which the caller can change. The caller's
+//! environment and terminal are never consulted, since it sets both itself;
+//! without a logind session the type is unknown.
+//!
+//! The same records tell who else is logged in, for
+//! `forbid_if_others_logged_in`.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where logind keeps one `KEY=value` record per session
+pub const LOGIND_SESSIONS: &str = "/run/systemd/sessions";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionType {
+    /// A local text console
+    Tty,
+    /// A local Wayland or X11 desktop
+    Graphical,
+    /// Logged in remotely over SSH
+    Ssh,
+}
+
+impl SessionType {
+    /// Parse a policy's `allow_session_types` entry
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "tty" => Some(Self::Tty),
+            "graphical" => Some(Self::Graphical),
+            "ssh" => Some(Self::Ssh),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Tty => "tty",
+            Self::Graphical => "graphical",
+            Self::Ssh => "ssh",
+        }
+    }
+}
+
+impl fmt::Display for SessionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Session type of process `pid`, or `None` if it can't be told
+pub fn of_process(pid: u32) -> Option<SessionType> {
+    from_session_record(&logind_record(pid)?)
+}
+
+/// The terminal process `pid`'s logind session was started on, if any
+pub fn session_tty(pid: u32) -> Option<PathBuf> {
+    tty_from_session_record(&logind_record(pid)?)
+}
+
+fn logind_record(pid: u32) -> Option<String> {
+    let cgroup = fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+    let id = session_id(&cgroup)?;
+    fs::read_to_string(Path::new(LOGIND_SESSIONS).join(id)).ok()
+}
+
+/// logind session id from the contents of `/proc/<pid>/cgroup`
+pub fn session_id(cgroup: &str) -> Option<&str> {
+    cgroup
+        .lines()
+        .filter_map(|line| line.rsplit(':').next())
+        .flat_map(|path| path.split('/'))
+        .find_map(|part| part.strip_prefix("session-")?.strip_suffix(".scope"))
+        .filter(|id| !id.is_empty() && !id.contains(['/', '.']))
+}
+
+/// Session type from a logind session record. Remote sessions count as SSH
+/// whatever their `TYPE`, since sshd-started ones are registered as `tty`.
+pub fn from_session_record(record: &str) -> Option<SessionType> {
+    let field = |key| record_field(record, key);
+    if field("REMOTE") == Some("1") || field("SERVICE") == Some("sshd") {
+        return Some(SessionType::Ssh);
+    }
+    match field("TYPE")? {
+        "wayland" | "x11" | "mir" => Some(SessionType::Graphical),
+        "tty" => Some(SessionType::Tty),
+        _ => None,
+    }
+}
+
+fn record_field<'a>(record: &'a str, key: &str) -> Option<&'a str> {
+    record
+        .lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+}
+
+/// Who has a session open, for `forbid_if_others_logged_in`
+pub trait SessionSource: Send + Sync {
+    /// The uid of every open user session, once per session
+    fn logged_in_uids(&self) -> io::Result<Vec<u32>>;
+}
+
+/// The session records logind keeps under [`LOGIND_SESSIONS`]
+pub struct Logind;
+
+impl SessionSource for Logind {
+    fn logged_in_uids(&self) -> io::Result<Vec<u32>> {
+        let mut uids = Vec::new();
+        for entry in fs::read_dir(LOGIND_SESSIONS)? {
+            let entry = entry?;
+            // `<id>.ref` next to each record is a FIFO; reading it blocks
+            if entry.file_name().to_string_lossy().contains('.') {
+                continue;
+            }
+            // Gone since the listing: the session has ended
+            if let Ok(record) = fs::read_to_string(entry.path()) {
+                uids.extend(user_of_session_record(&record));
+            }
+        }
+        Ok(uids)
+    }
+}
+
+/// `UID` of a logind session record, if it is a user's session that is
+/// still open. Greeters, lock screens and background sessions (cron jobs)
+/// don't count as anyone being logged in.
+pub fn user_of_session_record(record: &str) -> Option<u32> {
+    let field = |key| record_field(record, key);
+    if field("STATE") == Some("closing") {
+        return None;
+    }
+    if !field("CLASS").is_none_or(|class| class.starts_with("user")) {
+        return None;
+    }
+    field("UID")?.parse().ok()
+}
+
+/// Why `uid` can't run a `forbid_if_others_logged_in` target now: someone
+/// else has a session open, or `sessions` can't tell who does
+pub fn others_logged_in_refusal(sessions: &dyn SessionSource, uid: u32) -> Option<String> {
+    let mut others: Vec<u32> = match sessions.logged_in_uids() {
+        Ok(uids) => uids.into_iter().filter(|&other| other != uid).collect(),
+        Err(e) => {
+            return Some(format!(
+                "can't tell who else is logged in ({}); refusing under forbid_if_others_logged_in",
+                e
+            ));
+        }
+    };
+    if others.is_empty() {
+        return None;
+    }
+    others.sort_unstable();
+    others.dedup();
+    let others: Vec<String> = others.iter().map(u32::to_string).collect();
+    Some(format!(
+        "other users are logged in (uid {}); refusing under forbid_if_others_logged_in",
+        others.join(", ")
+    ))
+}
+
+/// `TTY` of a logind session record, as a path under `/dev`
+pub fn tty_from_session_record(record: &str) -> Option<PathBuf> {
+    let tty = record
+        .lines()
+        .find_map(|line| line.strip_prefix("TTY="))
+        .filter(|tty| !tty.is_empty())?;
+    Some(Path::new("/dev").join(tty.trim_start_matches("/dev/")))
+}
+
+/// `tty_nr` from `/proc/<pid>/stat`. `comm` may contain spaces and
+/// parentheses, so fields are counted from the last `)`.
+pub fn controlling_tty(stat: &str) -> Option<i64> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    // state ppid pgrp session tty_nr
+    rest.split_whitespace().nth(4)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_policy_names_back_and_forth() {
+        for session in [SessionType::Tty, SessionType::Graphical, SessionType::Ssh] {
+            assert_eq!(SessionType::parse(session.as_str()), Some(session));
+        }
+        assert_eq!(SessionType::parse("x11"), None);
+    }
+
+    #[test]
+    fn finds_the_session_scope_in_cgroup_paths() {
+        assert_eq!(
+            session_id("0::/user.slice/user-1000.slice/session-3.scope\n"),
+            Some("3")
+        );
+        assert_eq!(
+            session_id("1:name=systemd:/user.slice/user-1000.slice/session-c2.scope\n"),
+            Some("c2")
+        );
+        assert_eq!(
+            session_id("0::/user.slice/user-1000.slice/user@1000.service/app.slice\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn reads_logind_session_records() {
+        assert_eq!(
+            from_session_record("UID=1000\nTYPE=wayland\nCLASS=user\n"),
+            Some(SessionType::Graphical)
+        );
+        assert_eq!(
+            from_session_record("TYPE=tty\nSERVICE=login\n"),
+            Some(SessionType::Tty)
+        );
+        assert_eq!(
+            from_session_record("TYPE=tty\nREMOTE=1\nSERVICE=sshd\n"),
+            Some(SessionType::Ssh)
+        );
+        assert_eq!(from_session_record("TYPE=unspecified\n"), None);
+    }
+
+    /// A fixed list of sessions, or none readable for `None`
+    struct Sessions(Option<Vec<u32>>);
+
+    impl SessionSource for Sessions {
+        fn logged_in_uids(&self) -> io::Result<Vec<u32>> {
+            self.0
+                .clone()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no logind"))
+        }
+    }
+
+    #[test]
+    fn only_open_user_sessions_count_as_logged_in() {
+        assert_eq!(
+            user_of_session_record("UID=1000\nSTATE=active\nCLASS=user\n"),
+            Some(1000)
+        );
+        assert_eq!(
+            user_of_session_record("UID=1001\nSTATE=online\nREMOTE=1\n"),
+            Some(1001)
+        );
+        assert_eq!(
+            user_of_session_record("UID=1000\nSTATE=closing\nCLASS=user\n"),
+            None
+        );
+        assert_eq!(
+            user_of_session_record("UID=964\nSTATE=active\nCLASS=greeter\n"),
+            None
+        );
+        assert_eq!(
+            user_of_session_record("UID=0\nSTATE=online\nCLASS=background\n"),
+            None
+        );
+        assert_eq!(user_of_session_record("STATE=active\n"), None);
+    }
+
+    #[test]
+    fn other_users_logged_in_refuse() {
+        let refusal = others_logged_in_refusal(&Sessions(Some(vec![1000, 1001, 0, 1001])), 1000);
+        assert_eq!(
+            refusal.as_deref(),
+            Some("other users are logged in (uid 0, 1001); refusing under forbid_if_others_logged_in")
+        );
+    }
+
+    #[test]
+    fn the_callers_own_sessions_dont_refuse() {
+        assert_eq!(
+            others_logged_in_refusal(&Sessions(Some(vec![1000, 1000])), 1000),
+            None
+        );
+        assert_eq!(
+            others_logged_in_refusal(&Sessions(Some(Vec::new())), 1000),
+            None
+        );
+    }
+
+    #[test]
+    fn unknown_sessions_refuse() {
+        let refusal = others_logged_in_refusal(&Sessions(None), 1000).unwrap();
+        assert!(
+            refusal.starts_with("can't tell who else is logged in"),
+            "{refusal}"
+        );
+    }
+
+    #[test]
+    fn session_terminal_is_a_dev_path() {
+        assert_eq!(
+            tty_from_session_record("TYPE=tty\nTTY=pts/3\nREMOTE=1\n"),
+            Some(PathBuf::from("/dev/pts/3"))
+        );
+        assert_eq!(
+            tty_from_session_record("TTY=/dev/tty2\n"),
+            Some(PathBuf::from("/dev/tty2"))
+        );
+        assert_eq!(tty_from_session_record("TYPE=wayland\n"), None);
+    }
+
+    #[test]
+    fn processes_outside_a_logind_session_have_no_type() {
+        // Whatever its environment says, no process has pid 0
+        assert_eq!(of_process(0), None);
+    }
+
+    #[test]
+    fn controlling_tty_survives_odd_process_names() {
+        let stat = "1234 (a) b (c) S 1 1234 1234 34816 1234 4194560";
+        assert_eq!(controlling_tty(stat), Some(34816));
+    }
+}
@@ -0,0 +1,164 @@
+//! Per-user placeholders in rule paths
+//!
+//! Non-target paths such as `log_output` may contain `%u` (username), `%U`
+//! (uid) and `%h` (home directory), expanded against the calling user before
+//! use; `%%` is a literal `%`. Rule targets are never expanded: a target that
+//! depended on the caller could be pointed at a path the caller controls.
+
+use std::path::{Component, Path, PathBuf};
+use thiserror::Error;
+use users::os::unix::UserExt;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TemplateError {
+    #[error("template is not valid UTF-8")]
+    NonUtf8,
+    #[error("unknown placeholder %{0}")]
+    UnknownPlaceholder(char),
+    #[error("trailing % in template")]
+    TrailingPercent,
+    #[error("%h may only start the path")]
+    HomeNotLeading,
+    #[error("%{placeholder} expands to unsafe value {value:?}")]
+    UnsafeValue { placeholder: char, value: String },
+    #[error("expanded path {0:?} must be absolute without `..`")]
+    UnsafePath(PathBuf),
+    #[error("no passwd entry for uid {0}")]
+    UnknownUser(u32),
+}
+
+/// The caller identity placeholders expand to
+#[derive(Debug, Clone)]
+pub struct TemplateVars {
+    pub username: String,
+    pub uid: u32,
+    pub home: PathBuf,
+}
+
+impl TemplateVars {
+    pub fn for_uid(uid: u32) -> Result<Self, TemplateError> {
+        let user = users::get_user_by_uid(uid).ok_or(TemplateError::UnknownUser(uid))?;
+        Ok(Self {
+            username: user.name().to_string_lossy().into_owned(),
+            uid,
+            home: user.home_dir().to_path_buf(),
+        })
+    }
+}
+
+/// Expand `template` for `vars`. The result must be absolute and free of
+/// `..`, and `%u` must not smuggle in separators.
+pub fn expand(template: &Path, vars: &TemplateVars) -> Result<PathBuf, TemplateError> {
+    let text = template.to_str().ok_or(TemplateError::NonUtf8)?;
+    let mut expanded = String::with_capacity(text.len());
+    let mut chars = text.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next().map(|(_, placeholder)| placeholder) {
+            Some('%') => expanded.push('%'),
+            Some('u') => expanded.push_str(path_segment('u', &vars.username)?),
+            Some('U') => expanded.push_str(&vars.uid.to_string()),
+            Some('h') if index == 0 => {
+                let home = vars.home.to_str().ok_or(TemplateError::NonUtf8)?;
+                expanded.push_str(home.trim_end_matches('/'));
+            }
+            Some('h') => return Err(TemplateError::HomeNotLeading),
+            Some(other) => return Err(TemplateError::UnknownPlaceholder(other)),
+            None => return Err(TemplateError::TrailingPercent),
+        }
+    }
+
+    let path = PathBuf::from(expanded);
+    let escapes = path
+        .components()
+        .any(|component| matches!(component, Component::ParentDir));
+    if !path.is_absolute() || escapes {
+        return Err(TemplateError::UnsafePath(path));
+    }
+    Ok(path)
+}
+
+/// A value spliced into one path segment: no separators, not `.`/`..`
+fn path_segment(placeholder: char, value: &str) -> Result<&str, TemplateError> {
+    if value.is_empty() || value.contains('/') || value == "." || value == ".." {
+        return Err(TemplateError::UnsafeValue {
+            placeholder,
+            value: value.to_string(),
+        });
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alice() -> TemplateVars {
+        TemplateVars {
+            username: "alice".into(),
+            uid: 1000,
+            home: PathBuf::from("/home/alice/"),
+        }
+    }
+
+    #[test]
+    fn expands_log_path_placeholders() {
+        let vars = alice();
+        assert_eq!(
+            expand(Path::new("/var/log/authd/%u.log"), &vars).unwrap(),
+            PathBuf::from("/var/log/authd/alice.log")
+        );
+        assert_eq!(
+            expand(Path::new("/var/log/authd/%U-100%%.log"), &vars).unwrap(),
+            PathBuf::from("/var/log/authd/1000-100%.log")
+        );
+        assert_eq!(
+            expand(Path::new("%h/.local/state/authd.log"), &vars).unwrap(),
+            PathBuf::from("/home/alice/.local/state/authd.log")
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_or_misplaced_placeholders() {
+        let vars = alice();
+        assert_eq!(
+            expand(Path::new("/var/log/%x"), &vars),
+            Err(TemplateError::UnknownPlaceholder('x'))
+        );
+        assert_eq!(
+            expand(Path::new("/var/log/%"), &vars),
+            Err(TemplateError::TrailingPercent)
+        );
+        assert_eq!(
+            expand(Path::new("/var/log/%h"), &vars),
+            Err(TemplateError::HomeNotLeading)
+        );
+    }
+
+    #[test]
+    fn rejects_values_that_escape_their_segment() {
+        let mut vars = alice();
+        vars.username = "../root".into();
+        assert!(matches!(
+            expand(Path::new("/var/log/authd/%u.log"), &vars),
+            Err(TemplateError::UnsafeValue {
+                placeholder: 'u',
+                ..
+            })
+        ));
+
+        let vars = alice();
+        assert!(matches!(
+            expand(Path::new("/var/log/../%u.log"), &vars),
+            Err(TemplateError::UnsafePath(_))
+        ));
+        assert!(matches!(
+            expand(Path::new("logs/%u.log"), &vars),
+            Err(TemplateError::UnsafePath(_))
+        ));
+    }
+}
@@ -1,6 +1,10 @@
 use super::*;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+const NO_ARGS: &[&str] = &[];
+
 fn temp_policy_dir(name: &str) -> PathBuf {
     let nonce = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -98,8 +102,31 @@ fn deny_policy() {
         allow_users: vec!["root".into()],
         allow_groups: vec![],
         allow_callers: vec![],
+        allow_units: vec![],
         auth: AuthRequirement::Deny,
         cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
     });
 
     // Even allowed user gets denied due to auth=deny
@@ -118,8 +145,31 @@ fn wildcard_match() {
         allow_users: vec![username],
         allow_groups: vec![],
         allow_callers: vec![],
+        allow_units: vec![],
         auth: AuthRequirement::None,
         cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
     });
 
     // Any target should match the wildcard
@@ -139,8 +189,31 @@ fn least_restrictive_wins() {
         allow_users: vec![username.clone()],
         allow_groups: vec![],
         allow_callers: vec![],
+        allow_units: vec![],
         auth: AuthRequirement::None,
         cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
     });
 
     // Exact match requires password
@@ -149,8 +222,31 @@ fn least_restrictive_wins() {
         allow_users: vec![username],
         allow_groups: vec![],
         allow_callers: vec![],
+        allow_units: vec![],
         auth: AuthRequirement::Password,
         cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
     });
 
     // Least restrictive wins - wildcard's auth=none beats exact's auth=password
@@ -171,8 +267,31 @@ fn current_user_in_wheel() {
         allow_users: vec![],
         allow_groups: vec!["wheel".into()],
         allow_callers: vec![],
+        allow_units: vec![],
         auth: AuthRequirement::None,
         cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
     });
 
     let decision = engine.check(Path::new("/usr/bin/wheeltest"), uid);
@@ -193,8 +312,31 @@ fn current_user_by_name() {
         allow_users: vec![username],
         allow_groups: vec![],
         allow_callers: vec![],
+        allow_units: vec![],
         auth: AuthRequirement::Password,
         cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
     });
 
     // Password now treated same as Confirm
@@ -202,6 +344,74 @@ fn current_user_by_name() {
     assert!(matches!(decision, PolicyDecision::AllowWithConfirm));
 }
 
+#[test]
+fn group_exclusion_wins_over_membership() {
+    let groups = vec!["wheel".to_string(), "!contractors".to_string()];
+
+    let contractor = group_match(&groups, |group| matches!(group, "wheel" | "contractors"));
+    assert_eq!(contractor, GroupMatch::Excluded);
+
+    let staff = group_match(&groups, |group| group == "wheel");
+    assert_eq!(staff, GroupMatch::Allowed);
+
+    let outsider = group_match(&groups, |_| false);
+    assert_eq!(outsider, GroupMatch::NotListed);
+
+    // Exclusions alone never admit anyone
+    let only_excluded = vec!["!contractors".to_string()];
+    assert_eq!(
+        group_match(&only_excluded, |_| false),
+        GroupMatch::NotListed
+    );
+}
+
+#[test]
+fn excluded_group_vetoes_named_user() {
+    let uid = users::get_current_uid();
+    let username = username_from_uid(uid).unwrap();
+    let primary = users::get_user_by_uid(uid).unwrap().primary_group_id();
+    let Some(group) = users::get_group_by_gid(primary) else {
+        return;
+    };
+    let group = group.name().to_string_lossy().into_owned();
+
+    let mut engine = PolicyEngine::new();
+    engine.add_rule(PolicyRule {
+        target: PathBuf::from("/usr/bin/excluded"),
+        allow_users: vec![username],
+        allow_groups: vec![format!("!{group}")],
+        allow_callers: vec![],
+        allow_units: vec![],
+        auth: AuthRequirement::None,
+        cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
+    });
+
+    let decision = engine.check(Path::new("/usr/bin/excluded"), uid);
+    assert!(matches!(decision, PolicyDecision::Denied(_)));
+}
+
 #[test]
 fn user_not_authorized() {
     let mut engine = PolicyEngine::new();
@@ -210,8 +420,31 @@ fn user_not_authorized() {
         allow_users: vec!["nonexistent_user_xyz".into()],
         allow_groups: vec!["nonexistent_group_xyz".into()],
         allow_callers: vec![],
+        allow_units: vec![],
         auth: AuthRequirement::None,
         cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
     });
 
     let decision = engine.check(Path::new("/usr/bin/restricted"), 1000);
@@ -229,8 +462,31 @@ fn confirm_policy() {
         allow_users: vec![username],
         allow_groups: vec![],
         allow_callers: vec![],
+        allow_units: vec![],
         auth: AuthRequirement::Confirm,
         cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
     });
 
     let decision = engine.check(Path::new("/usr/bin/confirm"), uid);
@@ -248,8 +504,31 @@ fn caller_authorization() {
         allow_users: vec![],
         allow_groups: vec![],
         allow_callers: vec![PathBuf::from("/usr/bin/claude")],
+        allow_units: vec![],
         auth: AuthRequirement::None,
         cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
     });
 
     // Without caller info - denied (no user/group match)
@@ -282,8 +561,31 @@ fn caller_cmdline_path_can_authorize_interpreter_scripts() {
         allow_users: vec![],
         allow_groups: vec![],
         allow_callers: vec![PathBuf::from("/opt/scripts/request-access")],
+        allow_units: vec![],
         auth: AuthRequirement::None,
         cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
     });
 
     let decision = engine.check_with_callers(
@@ -292,6 +594,8 @@ fn caller_cmdline_path_can_authorize_interpreter_scripts() {
         &[CallerInfo {
             exe: Path::new("/usr/bin/python"),
             cmdline_path: Some(Path::new("/opt/scripts/request-access")),
+            secontext: None,
+            unit: None,
         }],
     );
 
@@ -309,8 +613,31 @@ fn caller_respects_auth() {
         allow_users: vec![],
         allow_groups: vec![],
         allow_callers: vec![PathBuf::from("/usr/bin/claude")],
+        allow_units: vec![],
         auth: AuthRequirement::Confirm,
         cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
     });
 
     let decision = engine.check_with_caller(
@@ -333,8 +660,31 @@ fn multiple_wildcard_rules() {
         allow_users: vec![username],
         allow_groups: vec![],
         allow_callers: vec![],
+        allow_units: vec![],
         auth: AuthRequirement::Confirm,
         cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
     });
 
     // Rule 2: claude caller with none
@@ -343,8 +693,31 @@ fn multiple_wildcard_rules() {
         allow_users: vec![],
         allow_groups: vec![],
         allow_callers: vec![PathBuf::from("/usr/bin/claude")],
+        allow_units: vec![],
         auth: AuthRequirement::None,
         cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
     });
 
     // Without caller - matches first rule (user allowed, confirm)
@@ -371,8 +744,31 @@ fn caller_only_rule() {
         allow_users: vec![],
         allow_groups: vec![],
         allow_callers: vec![PathBuf::from("/usr/bin/claude")],
+        allow_units: vec![],
         auth: AuthRequirement::None,
         cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
     });
 
     // Without claude - denied
@@ -388,6 +784,72 @@ fn caller_only_rule() {
     assert!(matches!(decision, PolicyDecision::AllowImmediate));
 }
 
+#[test]
+fn callers_settle_once_every_caller_rule_is_matched() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "/usr/bin/id"
+            allow_groups = ["wheel"]
+
+            [[rules]]
+            target = "*"
+            allow_callers = ["/usr/bin/claude"]
+            auth = "none"
+
+            [[rules]]
+            target = "/usr/bin/make"
+            allow_callers = ["/opt/ci/*/runner"]
+            "#,
+        )
+        .unwrap();
+    let caller = |exe| CallerInfo {
+        exe: Path::new(exe),
+        cmdline_path: None,
+        secontext: None,
+        unit: None,
+    };
+    let claude = caller("/usr/bin/claude");
+    let runner = caller("/opt/ci/v2/runner");
+    let shell = caller("/usr/bin/bash");
+
+    // Nothing found yet: the wildcard rule lists callers
+    assert!(!engine.callers_settled(Path::new("/usr/bin/id"), &[]));
+    assert!(!engine.callers_settled(Path::new("/usr/bin/id"), &[shell.clone()]));
+    assert!(engine.callers_settled(Path::new("/usr/bin/id"), &[shell, claude.clone()]));
+    // Both caller rules for make have to be matched
+    assert!(!engine.callers_settled(Path::new("/usr/bin/make"), &[claude.clone()]));
+    assert!(engine.callers_settled(Path::new("/usr/bin/make"), &[runner, claude]));
+
+    // No caller rules at all: settled before looking at any ancestor
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "/usr/bin/id"
+            allow_groups = ["wheel"]
+            "#,
+        )
+        .unwrap();
+    assert!(engine.callers_settled(Path::new("/usr/bin/id"), &[]));
+
+    // Units and contexts are the direct caller's, which has to be read
+    for setting in [
+        "allow_units = [\"backup.service\"]",
+        "allow_contexts = [\"*:sshd_t:*\"]",
+    ] {
+        let mut engine = PolicyEngine::new();
+        engine
+            .load_from_str(&format!("[[rules]]\ntarget = \"/usr/bin/id\"\n{setting}\n"))
+            .unwrap();
+        assert!(!engine.callers_settled(Path::new("/usr/bin/id"), &[]));
+        assert!(engine.callers_settled(Path::new("/usr/bin/id"), &[caller("/usr/bin/bash")]));
+    }
+}
+
 #[test]
 fn caller_glob_pattern() {
     let mut engine = PolicyEngine::new();
@@ -399,8 +861,31 @@ fn caller_glob_pattern() {
         allow_users: vec![],
         allow_groups: vec![],
         allow_callers: vec![PathBuf::from("/home/osso/.local/share/claude/versions/*")],
+        allow_units: vec![],
         auth: AuthRequirement::None,
         cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
     });
 
     // Version 2.1.12 matches
@@ -468,3 +953,1800 @@ fn user_lookup_helpers_reject_missing_entries() {
         "__missing_authd_group__"
     ));
 }
+
+#[test]
+fn non_utf8_target_matches_exact_bytes_only() {
+    let mut engine = PolicyEngine::new();
+    let uid = users::get_current_uid();
+    let target = Path::new(OsStr::from_bytes(b"/opt/caf\xe9/tool"));
+    engine.add_rule(PolicyRule {
+        target: target.to_path_buf(),
+        allow_users: vec![],
+        allow_groups: vec![],
+        allow_callers: vec![PathBuf::from("/usr/bin/claude")],
+        allow_units: vec![],
+        auth: AuthRequirement::None,
+        cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
+    });
+    let claude = Some(Path::new("/usr/bin/claude"));
+
+    let decision = engine.check_with_caller(target, uid, claude);
+    assert!(matches!(decision, PolicyDecision::AllowImmediate));
+
+    // Same lossy rendering ("caf\u{FFFD}"), different bytes: must not match
+    let lookalike = Path::new(OsStr::from_bytes(b"/opt/caf\xff/tool"));
+    assert_eq!(lookalike.to_string_lossy(), target.to_string_lossy());
+    let decision = engine.check_with_caller(lookalike, uid, claude);
+    assert!(matches!(decision, PolicyDecision::Unknown));
+}
+
+#[test]
+fn path_matches_pattern_never_globs_non_utf8_paths() {
+    let path = Path::new(OsStr::from_bytes(b"/opt/app/\xff"));
+
+    assert!(!path_matches_pattern(path, Path::new("/opt/app/*")));
+    assert!(path_matches_pattern(path, path));
+}
+
+#[test]
+fn limits_take_the_lowest_of_matching_rules() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "*"
+            allow_groups = ["wheel"]
+            limits = { nofile = 4096, cpu = 600 }
+
+            [[rules]]
+            target = "/usr/bin/rsync"
+            allow_groups = ["wheel"]
+
+            [rules.limits]
+            nofile = 1024
+            as = 2147483648
+        "#,
+        )
+        .unwrap();
+
+    let rsync = engine.limits(Path::new("/usr/bin/rsync"), NO_ARGS);
+    assert_eq!(
+        rsync.systemd_properties(),
+        ["LimitNOFILE=1024", "LimitAS=2147483648", "LimitCPU=600"]
+    );
+    assert_eq!(
+        engine
+            .limits(Path::new("/usr/bin/other"), NO_ARGS)
+            .systemd_properties(),
+        ["LimitNOFILE=4096", "LimitCPU=600"]
+    );
+    assert!(PolicyEngine::new()
+        .limits(Path::new("/usr/bin/x"), NO_ARGS)
+        .is_empty());
+
+    // Bad values fail the whole file at load
+    let mut engine = PolicyEngine::new();
+    let zero = "[[rules]]\ntarget = \"/usr/bin/x\"\nlimits = { nproc = 0 }\n";
+    assert!(engine.load_from_str(zero).is_err());
+}
+
+#[test]
+fn max_concurrent_takes_the_lowest_of_matching_rules() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "*"
+            allow_groups = ["wheel"]
+            max_concurrent = 4
+
+            [[rules]]
+            target = "/usr/bin/pacman"
+            allow_groups = ["wheel"]
+            max_concurrent = 1
+        "#,
+        )
+        .unwrap();
+
+    assert_eq!(
+        engine.max_concurrent(Path::new("/usr/bin/pacman"), NO_ARGS),
+        Some(1)
+    );
+    assert_eq!(
+        engine.max_concurrent(Path::new("/usr/bin/other"), NO_ARGS),
+        Some(4)
+    );
+    assert_eq!(
+        PolicyEngine::new().max_concurrent(Path::new("/usr/bin/x"), NO_ARGS),
+        None
+    );
+}
+
+#[test]
+fn cache_timeout_uses_shortest_matching_rule() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "*"
+            allow_groups = ["wheel"]
+            cache_timeout = 600
+
+            [[rules]]
+            target = "/usr/bin/passwd"
+            allow_groups = ["wheel"]
+            cache_timeout = 60
+        "#,
+        )
+        .unwrap();
+
+    assert_eq!(
+        engine.cache_timeout(Path::new("/usr/bin/passwd"), NO_ARGS),
+        60
+    );
+    assert_eq!(
+        engine.cache_timeout(Path::new("/usr/bin/other"), NO_ARGS),
+        600
+    );
+    assert_eq!(
+        PolicyEngine::new().cache_timeout(Path::new("/usr/bin/x"), NO_ARGS),
+        0
+    );
+}
+
+#[test]
+fn confirmations_have_their_own_window_defaulting_to_the_cache_timeout() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "/usr/bin/nft"
+            allow_groups = ["wheel"]
+            cache_timeout = 600
+            confirm_cache_timeout = 30
+
+            [[rules]]
+            target = "/usr/bin/ip"
+            allow_groups = ["wheel"]
+            cache_timeout = 120
+        "#,
+        )
+        .unwrap();
+
+    let nft = Path::new("/usr/bin/nft");
+    assert_eq!(engine.cache_timeout(nft, NO_ARGS), 600);
+    assert_eq!(engine.confirm_cache_timeout(nft, NO_ARGS), 30);
+    assert_eq!(
+        engine.confirm_cache_timeout(Path::new("/usr/bin/ip"), NO_ARGS),
+        120
+    );
+    assert_eq!(
+        PolicyEngine::new().confirm_cache_timeout(Path::new("/usr/bin/x"), NO_ARGS),
+        0
+    );
+}
+
+#[test]
+fn run_settings_come_from_the_deciding_rule() {
+    let other = username_from_uid(1).unwrap();
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(&format!(
+            r#"
+            [[rules]]
+            target = "*"
+            allow_groups = ["wheel"]
+            runas = "caller"
+
+            [[rules]]
+            target = "/usr/bin/restic"
+            allow_users = ["root"]
+            runas = "backup"
+            pam_service = "authd-backup"
+
+            [[rules]]
+            target = "/usr/bin/restic"
+            allow_users = ["{other}"]
+            clean_env = false
+        "#
+        ))
+        .unwrap();
+    let settings = |uid| {
+        engine
+            .check_explain_command(
+                Path::new("/usr/bin/restic"),
+                NO_ARGS,
+                uid,
+                Groups::Process(&[]),
+                None,
+                &[],
+            )
+            .settings
+    };
+
+    let root = settings(0);
+    assert_eq!(root.runas.as_deref(), Some("backup"));
+    assert_eq!(root.pam_service(), "authd-backup");
+    assert_eq!(root.clean_env, None);
+    // Another user's rule for the same target keeps its own, and nothing
+    // comes from the `*` rule that didn't admit them
+    let other = settings(1);
+    assert_eq!(other.runas, None);
+    assert_eq!(other.pam_service(), "authd");
+    assert_eq!(other.clean_env, Some(false));
+    assert_eq!(settings(4_242_424), RunSettings::default());
+}
+
+#[test]
+fn unsafe_pam_service_names_fail_the_file() {
+    let mut engine = PolicyEngine::new();
+    let error = engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "/usr/bin/poweroff"
+            allow_groups = ["wheel"]
+            pam_service = "../../etc/shadow"
+        "#,
+        )
+        .unwrap_err();
+    assert!(error.to_string().contains("invalid pam_service"), "{error}");
+    assert_eq!(engine.rule_count(), 0);
+}
+
+#[test]
+fn min_caller_age_uses_strictest_matching_rule() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "*"
+            allow_groups = ["wheel"]
+            min_caller_age_secs = 5
+
+            [[rules]]
+            target = "/usr/bin/passwd"
+            allow_groups = ["wheel"]
+            min_caller_age_secs = 30
+        "#,
+        )
+        .unwrap();
+
+    assert_eq!(
+        engine.min_caller_age_secs(Path::new("/usr/bin/passwd"), NO_ARGS),
+        Some(30)
+    );
+    assert_eq!(
+        engine.min_caller_age_secs(Path::new("/usr/bin/other"), NO_ARGS),
+        Some(5)
+    );
+    assert_eq!(
+        PolicyEngine::new().min_caller_age_secs(Path::new("/usr/bin/passwd"), NO_ARGS),
+        None
+    );
+}
+
+#[test]
+fn min_binary_age_uses_strictest_matching_rule() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "*"
+            allow_groups = ["wheel"]
+            min_binary_age_secs = 60
+
+            [[rules]]
+            target = "/usr/local/bin/deploy"
+            allow_groups = ["wheel"]
+            min_binary_age_secs = 3600
+
+            [[rules]]
+            target = "/usr/local/bin/deploy"
+            allow_groups = ["wheel"]
+        "#,
+        )
+        .unwrap();
+
+    assert_eq!(
+        engine.min_binary_age_secs(Path::new("/usr/local/bin/deploy"), NO_ARGS),
+        Some(3600)
+    );
+    assert_eq!(
+        engine.min_binary_age_secs(Path::new("/usr/bin/other"), NO_ARGS),
+        Some(60)
+    );
+    // Opt-in: nothing matching, no age requirement
+    assert_eq!(
+        PolicyEngine::new().min_binary_age_secs(Path::new("/usr/bin/x"), NO_ARGS),
+        None
+    );
+}
+
+#[test]
+fn approver_groups_collect_every_matching_rule() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "*"
+            allow_groups = ["wheel"]
+            require_approver_group = "security"
+
+            [[rules]]
+            target = "/usr/local/bin/deploy"
+            allow_groups = ["wheel"]
+            require_approver_group = "release"
+
+            [[rules]]
+            target = "/usr/local/bin/deploy"
+            allow_groups = ["ops"]
+            require_approver_group = "security"
+        "#,
+        )
+        .unwrap();
+
+    assert_eq!(
+        engine.approver_groups(Path::new("/usr/local/bin/deploy"), NO_ARGS),
+        ["release", "security"]
+    );
+    assert_eq!(
+        engine.approver_groups(Path::new("/usr/bin/other"), NO_ARGS),
+        ["security"]
+    );
+    assert!(PolicyEngine::new()
+        .approver_groups(Path::new("/usr/bin/x"), NO_ARGS)
+        .is_empty());
+}
+
+#[test]
+fn notify_webhooks_come_from_matching_rules() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "/usr/local/bin/prod-deploy"
+            allow_groups = ["ops"]
+            notify_webhook = "https://alerts.example.com/hooks/deploy"
+
+            [[rules]]
+            target = "/usr/bin/id"
+            auth = "none"
+        "#,
+        )
+        .unwrap();
+
+    assert_eq!(
+        engine.notify_webhooks(Path::new("/usr/local/bin/prod-deploy"), NO_ARGS),
+        ["https://alerts.example.com/hooks/deploy"]
+    );
+    assert!(engine
+        .notify_webhooks(Path::new("/usr/bin/id"), NO_ARGS)
+        .is_empty());
+}
+
+#[test]
+fn interpreter_rules_only_run_allowed_scripts() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "/usr/bin/python3"
+            allow_groups = ["wheel"]
+            allow_scripts = ["/usr/local/lib/ops/rotate-logs.py", "/opt/tools/*.py"]
+
+            [[rules]]
+            target = "/usr/bin/id"
+            auth = "none"
+        "#,
+        )
+        .unwrap();
+    let python = Path::new("/usr/bin/python3");
+
+    assert_eq!(
+        engine.script_refusal(python, &["/usr/local/lib/ops/rotate-logs.py", "--dry-run"]),
+        None
+    );
+    assert_eq!(
+        engine.script_refusal(python, &["/opt/tools/backup.py"]),
+        None
+    );
+    // Inline code, modules and stdin name no script
+    for args in [&["-c", "evil"][..], &["-m", "http.server"], &["-"]] {
+        assert!(engine.script_refusal(python, args).is_some(), "{args:?}");
+    }
+    assert_eq!(
+        engine.script_refusal(python, &["/tmp/evil.py"]).as_deref(),
+        Some("/tmp/evil.py is not an allowed script for /usr/bin/python3")
+    );
+    // Globs can't be escaped with `..`, nor matched from a relative path
+    assert!(engine
+        .script_refusal(python, &["/opt/tools/../../tmp/evil.py"])
+        .is_some());
+    assert!(engine
+        .script_refusal(python, &["tools/backup.py"])
+        .is_some());
+    assert!(engine.script_refusal(python, &[] as &[&str]).is_some());
+    // Targets without allow_scripts take any arguments
+    assert_eq!(
+        engine.script_refusal(Path::new("/usr/bin/id"), &["-u"]),
+        None
+    );
+    assert!(engine.restricts_scripts(python, NO_ARGS));
+    assert!(!engine.restricts_scripts(Path::new("/usr/bin/id"), NO_ARGS));
+}
+
+#[test]
+fn multi_call_binaries_run_only_as_allowed_names() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "/usr/bin/busybox"
+            allow_groups = ["wheel"]
+            allow_argv0 = ["ls", "uptime"]
+
+            [[rules]]
+            target = "/usr/bin/id"
+            auth = "none"
+        "#,
+        )
+        .unwrap();
+    let busybox = Path::new("/usr/bin/busybox");
+
+    assert!(engine.pins_argv0(busybox, NO_ARGS));
+    assert_eq!(
+        engine.argv0_refusal(busybox, NO_ARGS, Path::new("/usr/bin/ls")),
+        None
+    );
+    assert_eq!(
+        engine
+            .argv0_refusal(busybox, NO_ARGS, Path::new("/usr/bin/sh"))
+            .as_deref(),
+        Some("/usr/bin/busybox may not be run as /usr/bin/sh")
+    );
+    // Its own name is no exception
+    assert!(engine.argv0_refusal(busybox, NO_ARGS, busybox).is_some());
+    // Without allow_argv0 any name goes, and argv[0] isn't pinned
+    let id = Path::new("/usr/bin/id");
+    assert!(!engine.pins_argv0(id, NO_ARGS));
+    assert_eq!(
+        engine.argv0_refusal(id, NO_ARGS, Path::new("/usr/bin/sh")),
+        None
+    );
+}
+
+#[test]
+fn privileged_target_checks_apply_if_any_matching_rule_asks() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "/usr/bin/ping"
+            allow_groups = ["wheel"]
+            forbid_file_caps = true
+
+            [[rules]]
+            target = "*"
+            allow_groups = ["wheel"]
+            forbid_setuid_target = true
+        "#,
+        )
+        .unwrap();
+
+    assert!(engine.forbids_file_caps(Path::new("/usr/bin/ping"), NO_ARGS));
+    assert!(engine.forbids_setuid_target(Path::new("/usr/bin/ping"), NO_ARGS));
+    assert!(!engine.forbids_file_caps(Path::new("/usr/bin/id"), NO_ARGS));
+    assert!(engine.forbids_setuid_target(Path::new("/usr/bin/id"), NO_ARGS));
+}
+
+#[test]
+fn type_confirmation_applies_if_any_matching_rule_asks() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "/usr/sbin/mkfs"
+            allow_groups = ["wheel"]
+            auth = "confirm"
+            require_type_confirm = true
+
+            [[rules]]
+            target = "*"
+            allow_groups = ["wheel"]
+            auth = "confirm"
+        "#,
+        )
+        .unwrap();
+
+    assert!(engine.requires_type_confirm(Path::new("/usr/sbin/mkfs"), NO_ARGS));
+    assert!(!engine.requires_type_confirm(Path::new("/usr/bin/id"), NO_ARGS));
+}
+
+#[test]
+fn others_logged_in_apply_if_any_matching_rule_asks() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "/usr/bin/reboot"
+            allow_groups = ["wheel"]
+            forbid_if_others_logged_in = true
+
+            [[rules]]
+            target = "*"
+            allow_groups = ["wheel"]
+        "#,
+        )
+        .unwrap();
+
+    assert!(engine.forbids_others_logged_in(Path::new("/usr/bin/reboot"), NO_ARGS));
+    assert!(!engine.forbids_others_logged_in(Path::new("/usr/bin/id"), NO_ARGS));
+}
+
+#[test]
+fn rule_resolution_picks_which_admitting_rule_decides() {
+    let username = username_from_uid(users::get_current_uid()).unwrap();
+    let rule = |target: &str, auth: &str, priority: i32| {
+        format!(
+            "[[rules]]\ntarget = \"{target}\"\nallow_users = [\"{username}\"]\nauth = \"{auth}\"\npriority = {priority}\n"
+        )
+    };
+    let policy = [
+        rule("/usr/bin/id", "confirm", 0),
+        rule("/usr/bin/id", "deny", 0),
+        rule("*", "none", 0),
+        rule("*", "discourage", 5),
+    ]
+    .join("\n");
+    let decide = |resolution| {
+        let mut engine = PolicyEngine::new();
+        engine.set_resolution(resolution);
+        engine.load_from_str(&policy).unwrap();
+        engine.check(Path::new("/usr/bin/id"), users::get_current_uid())
+    };
+
+    assert!(matches!(
+        decide(RuleResolution::LeastRestrictive),
+        PolicyDecision::AllowImmediate
+    ));
+    assert!(matches!(
+        decide(RuleResolution::MostRestrictive),
+        PolicyDecision::Denied(_)
+    ));
+    assert!(matches!(
+        decide(RuleResolution::FirstMatch),
+        PolicyDecision::AllowWithConfirm
+    ));
+    assert!(matches!(
+        decide(RuleResolution::Priority),
+        PolicyDecision::Discouraged
+    ));
+
+    let deciding = |resolution| {
+        let mut engine = PolicyEngine::new();
+        engine.set_resolution(resolution);
+        engine.load_from_str(&policy).unwrap();
+        let explanation =
+            engine.check_explain(Path::new("/usr/bin/id"), users::get_current_uid(), &[]);
+        explanation
+            .deciding
+            .map(|i| explanation.rules[i].auth.clone())
+    };
+    assert_eq!(
+        deciding(RuleResolution::LeastRestrictive),
+        Some(AuthRequirement::None)
+    );
+    assert_eq!(
+        deciding(RuleResolution::MostRestrictive),
+        Some(AuthRequirement::Deny)
+    );
+    assert_eq!(
+        deciding(RuleResolution::FirstMatch),
+        Some(AuthRequirement::Confirm)
+    );
+    assert_eq!(
+        deciding(RuleResolution::Priority),
+        Some(AuthRequirement::Discourage)
+    );
+}
+
+#[test]
+fn force_password_targets_raise_permissive_rules_only() {
+    let username = username_from_uid(users::get_current_uid()).unwrap();
+    let policy: String = [
+        ("/usr/bin/passwd", "none"),
+        ("/usr/sbin/reboot", "none"),
+        ("/usr/sbin/halt", "deny"),
+        ("/usr/sbin/fdisk", "discourage"),
+        ("/usr/bin/id", "none"),
+    ]
+    .iter()
+    .map(|(target, auth)| {
+        format!(
+            "[[rules]]\ntarget = \"{target}\"\nallow_users = [\"{username}\"]\nauth = \"{auth}\"\n"
+        )
+    })
+    .collect();
+    let mut engine = PolicyEngine::new();
+    engine.load_from_str(&policy).unwrap();
+    engine.set_force_password_targets(&[
+        PathBuf::from("/usr/bin/passwd"),
+        PathBuf::from("/usr/sbin/*"),
+    ]);
+    let uid = users::get_current_uid();
+
+    let explanation = engine.check_explain(Path::new("/usr/bin/passwd"), uid, &[]);
+    assert!(matches!(
+        explanation.decision,
+        PolicyDecision::AllowWithConfirm
+    ));
+    // The rule itself is reported as written
+    assert_eq!(explanation.rules[0].auth, AuthRequirement::None);
+    let password = Some(MethodSet::Any(vec![AuthMethod::Password]));
+    assert_eq!(explanation.methods, password);
+    assert!(matches!(
+        engine.check(Path::new("/usr/sbin/reboot"), uid),
+        PolicyDecision::AllowWithConfirm
+    ));
+    // Stricter rules stand, and nothing else is touched
+    assert!(matches!(
+        engine.check(Path::new("/usr/sbin/halt"), uid),
+        PolicyDecision::Denied(_)
+    ));
+    // A warning still shows, after the password
+    let explanation = engine.check_explain(Path::new("/usr/sbin/fdisk"), uid, &[]);
+    assert!(matches!(explanation.decision, PolicyDecision::Discouraged));
+    assert_eq!(explanation.methods, password);
+    assert!(matches!(
+        engine.check(Path::new("/usr/bin/id"), uid),
+        PolicyDecision::AllowImmediate
+    ));
+    // Listing a target doesn't make up a rule for it
+    assert!(matches!(
+        engine.check(Path::new("/usr/sbin/shutdown"), uid),
+        PolicyDecision::Unknown
+    ));
+}
+
+#[test]
+fn method_sets_rank_by_what_they_take() {
+    let any = |methods: &[AuthMethod]| AuthRequirement::Methods(MethodSet::Any(methods.to_vec()));
+    let all = |methods: &[AuthMethod]| AuthRequirement::Methods(MethodSet::All(methods.to_vec()));
+    let confirm = auth_priority(&AuthRequirement::Confirm);
+    let password = auth_priority(&AuthRequirement::Password);
+
+    // Any one method will do, so a keypress is all the choice asks for
+    assert_eq!(
+        auth_priority(&any(&[AuthMethod::Confirm, AuthMethod::Hwkey])),
+        confirm
+    );
+    assert_eq!(
+        auth_priority(&any(&[AuthMethod::Password, AuthMethod::Hwkey])),
+        password
+    );
+    assert_eq!(
+        auth_priority(&all(&[AuthMethod::Confirm, AuthMethod::Hwkey])),
+        password
+    );
+}
+
+#[test]
+fn force_password_targets_raise_confirm_in_method_sets() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "/usr/bin/either"
+            allow_users = ["root"]
+            auth = ["confirm", "hwkey"]
+
+            [[rules]]
+            target = "/usr/bin/both"
+            allow_users = ["root"]
+            auth = { all = ["confirm", "hwkey"] }
+
+            [[rules]]
+            target = "/usr/bin/strong"
+            allow_users = ["root"]
+            auth = ["password", "hwkey"]
+            "#,
+        )
+        .unwrap();
+    engine.set_force_password_targets(&[PathBuf::from("/usr/bin/*")]);
+    let methods = |target: &str| {
+        engine
+            .check_explain(Path::new(target), 0, &[])
+            .methods
+            .unwrap()
+            .to_string()
+    };
+
+    assert_eq!(methods("/usr/bin/either"), "any(password, hwkey)");
+    assert_eq!(methods("/usr/bin/both"), "all(password, hwkey)");
+    assert_eq!(methods("/usr/bin/strong"), "any(password, hwkey)");
+}
+
+#[test]
+fn rules_report_the_methods_that_authorize_them() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "/usr/bin/confirm"
+            allow_users = ["root"]
+            auth = ["confirm"]
+
+            [[rules]]
+            target = "/usr/bin/either"
+            allow_users = ["root"]
+            auth = ["password", "hwkey"]
+
+            [[rules]]
+            target = "/usr/bin/both"
+            allow_users = ["root"]
+            auth = { all = ["confirm", "hwkey"] }
+
+            [[rules]]
+            target = "/usr/bin/free"
+            allow_users = ["root"]
+            auth = "none"
+            "#,
+        )
+        .unwrap();
+    let explain = |target: &str| engine.check_explain(Path::new(target), 0, &[]);
+
+    let single = explain("/usr/bin/confirm");
+    assert!(matches!(single.decision, PolicyDecision::AllowWithConfirm));
+    assert_eq!(single.rules[0].auth, AuthRequirement::Confirm);
+    assert_eq!(
+        single.methods,
+        Some(MethodSet::Any(vec![authd_protocol::AuthMethod::Confirm]))
+    );
+
+    let either = explain("/usr/bin/either");
+    assert!(matches!(either.decision, PolicyDecision::AllowWithConfirm));
+    assert_eq!(either.methods.unwrap().to_string(), "any(password, hwkey)");
+    let both = explain("/usr/bin/both");
+    assert!(matches!(both.decision, PolicyDecision::AllowWithConfirm));
+    assert_eq!(both.methods.unwrap().to_string(), "all(confirm, hwkey)");
+
+    let free = explain("/usr/bin/free");
+    assert!(matches!(free.decision, PolicyDecision::AllowImmediate));
+    assert_eq!(free.methods, None);
+}
+
+#[test]
+fn first_match_follows_policy_file_names() {
+    let dir = temp_policy_dir("first-match");
+    let username = username_from_uid(users::get_current_uid()).unwrap();
+    for (file, auth) in [("20-late.toml", "deny"), ("10-early.toml", "none")] {
+        fs::write(
+            dir.join(file),
+            format!("[[rules]]\ntarget = \"/usr/bin/id\"\nallow_users = [\"{username}\"]\nauth = \"{auth}\"\n"),
+        )
+        .unwrap();
+    }
+    let mut engine = PolicyEngine::new();
+    engine.set_resolution(RuleResolution::FirstMatch);
+    engine.load_from_dir(&dir).unwrap();
+
+    assert!(matches!(
+        engine.check(Path::new("/usr/bin/id"), users::get_current_uid()),
+        PolicyDecision::AllowImmediate
+    ));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn drop_ins_combine_with_and_override_the_combined_file() {
+    let root = temp_policy_dir("combined");
+    let (file, dir) = (root.join("policy.toml"), root.join("policies.d"));
+    fs::create_dir(&dir).unwrap();
+    let username = username_from_uid(users::get_current_uid()).unwrap();
+    let rule = |auth: &str| {
+        format!("[[rules]]\ntarget = \"/usr/bin/id\"\nallow_users = [\"{username}\"]\nauth = \"{auth}\"\n")
+    };
+    fs::write(
+        &file,
+        rule("deny") + "\n[[rules]]\ntarget = \"/usr/bin/true\"\nauth = \"none\"\n",
+    )
+    .unwrap();
+    fs::write(dir.join("id.toml"), rule("confirm")).unwrap();
+    let load = |resolution| {
+        let mut engine = PolicyEngine::new();
+        engine.set_resolution(resolution);
+        engine.load_all(&file, &dir).unwrap();
+        engine
+    };
+    let id = Path::new("/usr/bin/id");
+    let uid = users::get_current_uid();
+
+    let engine = load(RuleResolution::FirstMatch);
+    assert!(matches!(
+        engine.check(id, uid),
+        PolicyDecision::AllowWithConfirm
+    ));
+    // Rules only in the combined file still count
+    assert!(matches!(
+        engine.check(Path::new("/usr/bin/true"), uid),
+        PolicyDecision::AllowImmediate
+    ));
+    assert_eq!(engine.sources().len(), 2);
+    assert!(matches!(
+        load(RuleResolution::MostRestrictive).check(id, uid),
+        PolicyDecision::Denied(_)
+    ));
+
+    // Without a combined file the drop-ins load as before
+    let mut engine = PolicyEngine::new();
+    engine.load_all(&root.join("missing.toml"), &dir).unwrap();
+    assert_eq!(engine.rule_count(), 1);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn embedded_rules_are_a_base_layer_the_files_override() {
+    let root = temp_policy_dir("embedded");
+    let (file, dir) = (root.join("policy.toml"), root.join("policies.d"));
+    fs::create_dir(&dir).unwrap();
+    let username = username_from_uid(users::get_current_uid()).unwrap();
+    let rule = |target: &str, auth: &str, priority: i32| {
+        format!("[[rules]]\ntarget = \"{target}\"\nallow_users = [\"{username}\"]\nauth = \"{auth}\"\npriority = {priority}\n")
+    };
+    let embedded = rule("/usr/bin/id", "deny", 0) + "\n" + &rule("/usr/bin/true", "none", 0);
+    fs::write(dir.join("id.toml"), rule("/usr/bin/id", "confirm", 1)).unwrap();
+    let load = |resolution| {
+        let mut engine = PolicyEngine::new();
+        engine.set_resolution(resolution);
+        assert_eq!(engine.load_embedded(&embedded).unwrap(), 2);
+        engine.load_all(&file, &dir).unwrap();
+        engine
+    };
+    let (id, uid) = (Path::new("/usr/bin/id"), users::get_current_uid());
+
+    // With nothing on disk for it, the embedded rule applies
+    let engine = load(RuleResolution::FirstMatch);
+    assert!(matches!(
+        engine.check(Path::new("/usr/bin/true"), uid),
+        PolicyDecision::AllowImmediate
+    ));
+    assert!(engine
+        .sources()
+        .contains(&(PathBuf::from(EMBEDDED_SOURCE), 2)));
+    // Drop-ins come before it under first_match and outrank it by priority
+    assert!(matches!(
+        engine.check(id, uid),
+        PolicyDecision::AllowWithConfirm
+    ));
+    assert!(matches!(
+        load(RuleResolution::Priority).check(id, uid),
+        PolicyDecision::AllowWithConfirm
+    ));
+    // ...but it is still a rule like any other where the strictest wins
+    assert!(matches!(
+        load(RuleResolution::MostRestrictive).check(id, uid),
+        PolicyDecision::Denied(_)
+    ));
+
+    // The combined file overrides it too
+    fs::remove_file(dir.join("id.toml")).unwrap();
+    fs::write(&file, rule("/usr/bin/id", "none", 0)).unwrap();
+    assert!(matches!(
+        load(RuleResolution::FirstMatch).check(id, uid),
+        PolicyDecision::AllowImmediate
+    ));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn a_bad_embedded_policy_is_an_error() {
+    let mut engine = PolicyEngine::new();
+    let error = engine.load_embedded("[[rules]]\ntarget = 1\n").unwrap_err();
+    assert!(
+        matches!(&error, PolicyError::Parse { file, .. } if file == Path::new(EMBEDDED_SOURCE)),
+        "{error}"
+    );
+    assert_eq!(engine.rule_count(), 0);
+}
+
+#[test]
+fn profiles_load_only_their_own_rules() {
+    let root = temp_policy_dir("profiles");
+    for (profile, target) in [
+        ("workstation", "/usr/bin/relaxed"),
+        ("server", "/usr/bin/strict"),
+    ] {
+        fs::create_dir(root.join(profile)).unwrap();
+        fs::write(
+            root.join(profile).join("rules.toml"),
+            format!("[[rules]]\ntarget = \"{target}\"\nauth = \"none\"\n"),
+        )
+        .unwrap();
+    }
+
+    let dir = profile_dir(&root, "workstation").unwrap();
+    assert_eq!(dir, root.join("workstation"));
+    let mut engine = PolicyEngine::new();
+    engine.load_all(&root.join("missing.toml"), &dir).unwrap();
+    let uid = users::get_current_uid();
+    assert_eq!(engine.rule_count(), 1);
+    assert!(matches!(
+        engine.check(Path::new("/usr/bin/relaxed"), uid),
+        PolicyDecision::AllowImmediate
+    ));
+    assert!(matches!(
+        engine.check(Path::new("/usr/bin/strict"), uid),
+        PolicyDecision::Unknown
+    ));
+
+    let error = profile_dir(&root, "laptop").unwrap_err();
+    assert!(error.contains("policy profile laptop not found"), "{error}");
+    for name in ["", "..", "../server", "work/station", ".hidden"] {
+        let error = profile_dir(&root, name).unwrap_err();
+        assert!(error.contains("invalid policy profile name"), "{error}");
+    }
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn without_a_profile_drop_ins_come_from_policy_dir() {
+    let test = InstanceName::new("test").unwrap();
+    assert_eq!(drop_in_dir(None, None).unwrap(), PathBuf::from(POLICY_DIR));
+    assert_eq!(
+        drop_in_dir(Some(&test), None).unwrap(),
+        policy_dir(Some(&test))
+    );
+    let error = drop_in_dir(Some(&test), Some("server")).unwrap_err();
+    assert!(
+        error.contains("/etc/authd/instances/test/profiles/server"),
+        "{error}"
+    );
+}
+
+#[test]
+fn discourage_policy_is_a_soft_deny() {
+    let mut engine = PolicyEngine::new();
+    let uid = users::get_current_uid();
+    let username = username_from_uid(uid).unwrap();
+    engine.add_rule(PolicyRule {
+        target: PathBuf::from("/usr/bin/discouraged"),
+        allow_users: vec![username.clone()],
+        allow_groups: vec![],
+        allow_callers: vec![],
+        allow_units: vec![],
+        auth: AuthRequirement::Discourage,
+        cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
+    });
+
+    let decision = engine.check(Path::new("/usr/bin/discouraged"), uid);
+    assert!(matches!(decision, PolicyDecision::Discouraged));
+
+    // Softer than deny, stricter than password
+    assert!(
+        auth_priority(&AuthRequirement::Discourage) > auth_priority(&AuthRequirement::Password)
+    );
+    assert!(auth_priority(&AuthRequirement::Discourage) < auth_priority(&AuthRequirement::Deny));
+
+    // A less restrictive rule still wins
+    engine.add_rule(PolicyRule {
+        target: PathBuf::from("*"),
+        allow_users: vec![username],
+        allow_groups: vec![],
+        allow_callers: vec![],
+        allow_units: vec![],
+        auth: AuthRequirement::Confirm,
+        cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
+    });
+    let decision = engine.check(Path::new("/usr/bin/discouraged"), uid);
+    assert!(matches!(decision, PolicyDecision::AllowWithConfirm));
+}
+
+#[test]
+fn check_explain_reports_each_matching_rule() {
+    let mut engine = PolicyEngine::new();
+    engine.add_rule(PolicyRule {
+        target: PathBuf::from("/usr/bin/tool"),
+        allow_users: vec![],
+        allow_groups: vec![],
+        allow_callers: vec![PathBuf::from("/usr/bin/claude")],
+        allow_units: vec![],
+        auth: AuthRequirement::None,
+        cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
+    });
+    engine.add_rule(PolicyRule {
+        target: PathBuf::from("*"),
+        allow_users: vec!["nonexistent_user_xyz".into()],
+        allow_groups: vec![],
+        allow_callers: vec![],
+        allow_units: vec![],
+        auth: AuthRequirement::Confirm,
+        cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
+    });
+    let callers = [CallerInfo {
+        exe: Path::new("/usr/bin/claude"),
+        cmdline_path: None,
+        secontext: None,
+        unit: None,
+    }];
+
+    let explanation = engine.check_explain(Path::new("/usr/bin/tool"), 1000, &callers);
+
+    assert!(matches!(
+        explanation.decision,
+        PolicyDecision::AllowImmediate
+    ));
+    assert_eq!(explanation.rules.len(), 2);
+    assert_eq!(explanation.rules[0].target, PathBuf::from("/usr/bin/tool"));
+    assert!(explanation.rules[0].admits);
+    assert_eq!(explanation.rules[1].target, PathBuf::from("*"));
+    assert!(!explanation.rules[1].admits);
+    assert_eq!(explanation.deciding, Some(0));
+
+    let unknown = PolicyEngine::new().check_explain(Path::new("/usr/bin/tool"), 1000, &[]);
+    assert!(matches!(unknown.decision, PolicyDecision::Unknown));
+    assert!(unknown.rules.is_empty());
+    assert_eq!(unknown.deciding, None);
+}
+
+#[test]
+fn caller_glob_matches_versioned_install_only() {
+    let mut engine = PolicyEngine::new();
+    let uid = users::get_current_uid();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "/usr/bin/deploy"
+            allow_callers = ["/opt/app-*/bin/agent", "/usr/bin/claude"]
+            auth = "none"
+        "#,
+        )
+        .unwrap();
+    let check = |caller: &str| {
+        engine.check_with_caller(Path::new("/usr/bin/deploy"), uid, Some(Path::new(caller)))
+    };
+
+    assert!(matches!(
+        check("/opt/app-1.2.3/bin/agent"),
+        PolicyDecision::AllowImmediate
+    ));
+    assert!(matches!(
+        check("/opt/app-2.0.0-rc1/bin/agent"),
+        PolicyDecision::AllowImmediate
+    ));
+    // Exact entries keep working alongside globs
+    assert!(matches!(
+        check("/usr/bin/claude"),
+        PolicyDecision::AllowImmediate
+    ));
+
+    // Unrelated binaries, and `*` reaching across a directory boundary
+    assert!(matches!(check("/usr/bin/agent"), PolicyDecision::Denied(_)));
+    assert!(matches!(
+        check("/opt/app-1.2.3/bin/agent-evil"),
+        PolicyDecision::Denied(_)
+    ));
+    assert!(matches!(
+        check("/opt/app-1/tmp/x/bin/agent"),
+        PolicyDecision::Denied(_)
+    ));
+}
+
+#[test]
+fn caller_glob_is_compiled_at_load() {
+    let mut engine = PolicyEngine::new();
+    engine.add_rule(PolicyRule {
+        target: PathBuf::from("*"),
+        allow_users: vec![],
+        allow_groups: vec![],
+        allow_callers: vec![PathBuf::from("/opt/*/agent"), PathBuf::from("[")],
+        allow_units: vec![],
+        auth: AuthRequirement::None,
+        cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
+    });
+
+    let loaded = &engine.rules[Path::new("*")][0];
+    assert!(matches!(loaded.callers[0], CallerPattern::Glob(_)));
+    // Invalid globs fall back to exact matching
+    assert!(matches!(loaded.callers[1], CallerPattern::Exact(_)));
+}
+
+#[test]
+fn disabled_rules_are_parsed_but_never_match() {
+    let mut engine = PolicyEngine::new();
+    let toml = r#"
+            [[rules]]
+            target = "/usr/bin/tool"
+            allow_users = ["root"]
+            auth = "none"
+            enabled = false
+
+            [[rules]]
+            target = "/usr/bin/tool"
+            allow_users = ["root"]
+            auth = "confirm"
+
+            [[rules]]
+            target = "/usr/bin/off"
+            allow_users = ["root"]
+            auth = "none"
+            enabled = false
+        "#;
+
+    assert_eq!(engine.load_from_str(toml).unwrap(), 3);
+    assert_eq!(engine.disabled_count(), 2);
+
+    // Only the enabled rule decides, even though the disabled one is laxer
+    let explanation = engine.check_explain(Path::new("/usr/bin/tool"), 0, &[]);
+    assert!(matches!(
+        explanation.decision,
+        PolicyDecision::AllowWithConfirm
+    ));
+    assert_eq!(explanation.rules.len(), 1);
+    assert_eq!(explanation.disabled, vec![PathBuf::from("/usr/bin/tool")]);
+
+    let off = engine.check_explain(Path::new("/usr/bin/off"), 0, &[]);
+    assert!(matches!(off.decision, PolicyDecision::Unknown));
+    assert_eq!(off.disabled, vec![PathBuf::from("/usr/bin/off")]);
+    assert_eq!(engine.cache_timeout(Path::new("/usr/bin/off"), NO_ARGS), 0);
+}
+
+#[test]
+fn process_groups_replace_nss_membership() {
+    // gid 0 is "root" on any Linux system
+    let mut engine = PolicyEngine::new();
+    engine.add_rule(PolicyRule {
+        target: PathBuf::from("/usr/bin/grouped"),
+        allow_users: vec![],
+        allow_groups: vec!["root".into()],
+        allow_callers: vec![],
+        allow_units: vec![],
+        auth: AuthRequirement::None,
+        cache_timeout: 300,
+        log_output: None,
+        enabled: true,
+        min_binary_age_secs: None,
+        require_approver_group: None,
+        allow_session_types: Vec::new(),
+        allow_contexts: Vec::new(),
+        notify_webhook: None,
+        allow_scripts: Vec::new(),
+        allow_argv0: Vec::new(),
+        forbid_setuid_target: false,
+        forbid_file_caps: false,
+        priority: 0,
+        limits: ResourceLimits::default(),
+        max_concurrent: None,
+        min_caller_age_secs: None,
+        runas: None,
+        require_type_confirm: false,
+        forbid_if_others_logged_in: false,
+        pam_service: None,
+        command: None,
+        confirm_cache_timeout: None,
+        clean_env: None,
+    });
+    let target = Path::new("/usr/bin/grouped");
+    let no_passwd_entry = 4_242_424;
+
+    assert!(matches!(
+        engine.check_with_groups(target, no_passwd_entry, Groups::Nss, &[]),
+        PolicyDecision::Denied(_)
+    ));
+    assert!(matches!(
+        engine.check_with_groups(target, no_passwd_entry, Groups::Process(&[0]), &[]),
+        PolicyDecision::AllowImmediate
+    ));
+    // root's NSS primary group doesn't count when the process dropped it
+    assert!(matches!(
+        engine.check_with_groups(target, 0, Groups::Process(&[]), &[]),
+        PolicyDecision::Denied(_)
+    ));
+}
+
+#[test]
+fn session_types_limit_where_a_rule_applies() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "/usr/bin/tool"
+            allow_users = ["root"]
+            auth = "none"
+            allow_session_types = ["tty", "graphical"]
+
+            [[rules]]
+            target = "/usr/bin/tool"
+            allow_users = ["root"]
+            auth = "deny"
+            allow_session_types = ["ssh"]
+
+            [[rules]]
+            target = "/usr/bin/anywhere"
+            allow_users = ["root"]
+            auth = "none"
+        "#,
+        )
+        .unwrap();
+    let check = |target: &str, session| {
+        engine.check_in_session(Path::new(target), 0, Groups::Nss, session, &[])
+    };
+
+    assert!(matches!(
+        check("/usr/bin/tool", Some(SessionType::Graphical)),
+        PolicyDecision::AllowImmediate
+    ));
+    assert!(matches!(
+        check("/usr/bin/tool", Some(SessionType::Ssh)),
+        PolicyDecision::Denied(_)
+    ));
+    // Session-bound rules never apply when the session can't be told
+    assert!(matches!(
+        check("/usr/bin/tool", None),
+        PolicyDecision::Denied(reason) if reason == "user not authorized"
+    ));
+    // No list means any session, or none
+    for session in [None, Some(SessionType::Ssh), Some(SessionType::Tty)] {
+        assert!(matches!(
+            check("/usr/bin/anywhere", session),
+            PolicyDecision::AllowImmediate
+        ));
+    }
+}
+
+#[test]
+fn commands_limit_a_rule_to_runs_with_their_leading_arguments() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            command = "/usr/bin/apt install"
+            allow_users = ["root"]
+            auth = "none"
+
+            [[rules]]
+            target = "/usr/bin/apt"
+            command = "/usr/bin/apt  list --upgradable"
+            allow_users = ["root"]
+            auth = "confirm"
+        "#,
+        )
+        .unwrap();
+    let apt = Path::new("/usr/bin/apt");
+    let check = |args: &[&str]| engine.check_command(apt, args, 0, Groups::Nss, None, &[]);
+
+    assert!(matches!(
+        check(&["install", "foo"]),
+        PolicyDecision::AllowImmediate
+    ));
+    assert!(matches!(
+        check(&["install"]),
+        PolicyDecision::AllowImmediate
+    ));
+    assert!(matches!(
+        check(&["remove", "foo"]),
+        PolicyDecision::Denied(_)
+    ));
+    // Whole words, in order, from the first argument on
+    assert!(matches!(
+        check(&["installer", "foo"]),
+        PolicyDecision::Denied(_)
+    ));
+    assert!(matches!(
+        check(&["-y", "install", "foo"]),
+        PolicyDecision::Denied(_)
+    ));
+    assert!(matches!(
+        check(&["list", "--upgradable"]),
+        PolicyDecision::AllowWithConfirm
+    ));
+    assert!(matches!(check(&["list"]), PolicyDecision::Denied(_)));
+    // Checks that know no arguments never apply command rules
+    assert!(matches!(engine.check(apt, 0), PolicyDecision::Denied(_)));
+}
+
+#[test]
+fn a_command_must_agree_with_its_target() {
+    let load = |toml: &str| PolicyEngine::new().load_from_str(toml).map(drop);
+
+    let error = load(
+        r#"
+        [[rules]]
+        target = "/usr/bin/apt-get"
+        command = "/usr/bin/apt install"
+    "#,
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("doesn't start with"), "{error}");
+    assert!(load("[[rules]]\ncommand = \"  \"\n").is_err());
+    assert!(load("[[rules]]\nauth = \"none\"\n").is_err());
+    assert!(load("[[rules]]\ncommand = \"/usr/bin/apt\"\n").is_ok());
+    // `apt -y remove` would get past a denied `apt remove`
+    let error =
+        load("[[rules]]\ncommand = \"/usr/bin/apt remove\"\nauth = \"deny\"\n").unwrap_err();
+    assert!(error.to_string().contains("deny its target"), "{error}");
+}
+
+#[test]
+fn command_rule_settings_follow_only_their_runs() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            command = "/usr/bin/apt install"
+            allow_users = ["root"]
+            cache_timeout = 0
+            runas = "caller"
+            clean_env = false
+            pam_service = "authd-apt"
+            min_caller_age_secs = 60
+            limits = { nproc = 10 }
+            max_concurrent = 1
+            notify_webhook = "https://hooks.example/apt"
+            forbid_if_others_logged_in = true
+            log_output = "/var/log/authd/apt.log"
+
+            [[rules]]
+            target = "/usr/bin/apt"
+            allow_users = ["root"]
+            cache_timeout = 300
+        "#,
+        )
+        .unwrap();
+    let apt = Path::new("/usr/bin/apt");
+    let install = ["install", "foo"];
+    let remove = ["remove", "foo"];
+    let settings = |args: &[&str]| {
+        engine
+            .check_explain_command(apt, args, 0, Groups::Nss, None, &[])
+            .settings
+    };
+
+    assert_eq!(engine.cache_timeout(apt, &install), 0);
+    let run = settings(&install);
+    assert_eq!(run.runas.as_deref(), Some("caller"));
+    assert_eq!(run.clean_env, Some(false));
+    assert_eq!(run.pam_service(), "authd-apt");
+    assert_eq!(
+        run.log_output.as_deref(),
+        Some(Path::new("/var/log/authd/apt.log"))
+    );
+    assert_eq!(engine.min_caller_age_secs(apt, &install), Some(60));
+    assert_eq!(engine.max_concurrent(apt, &install), Some(1));
+    assert_eq!(
+        engine.notify_webhooks(apt, &install),
+        ["https://hooks.example/apt"]
+    );
+    assert!(engine.forbids_others_logged_in(apt, &install));
+    assert_eq!(
+        engine.limits(apt, &install).systemd_properties(),
+        ["LimitNPROC=10"]
+    );
+
+    assert_eq!(engine.cache_timeout(apt, &remove), 300);
+    assert_eq!(settings(&remove), RunSettings::default());
+    assert_eq!(engine.min_caller_age_secs(apt, &remove), None);
+    assert!(engine.limits(apt, &remove).is_empty());
+    assert_eq!(engine.max_concurrent(apt, &remove), None);
+    assert!(engine.notify_webhooks(apt, &remove).is_empty());
+    assert!(!engine.forbids_others_logged_in(apt, &remove));
+}
+
+#[test]
+fn contexts_limit_which_callers_a_rule_applies_to() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "/usr/bin/tool"
+            allow_users = ["root"]
+            auth = "none"
+            allow_contexts = ["unconfined_u:unconfined_r:unconfined_t:*"]
+
+            [[rules]]
+            target = "/usr/bin/exact"
+            allow_users = ["root"]
+            auth = "none"
+            allow_contexts = ["system_u:system_r:sshd_t:s0", "[broken"]
+        "#,
+        )
+        .unwrap();
+    let check = |target: &str, secontext| {
+        let callers = [CallerInfo {
+            exe: Path::new("/usr/bin/bash"),
+            cmdline_path: None,
+            secontext,
+            unit: None,
+        }];
+        engine.check_with_callers(Path::new(target), 0, &callers)
+    };
+
+    assert!(matches!(
+        check(
+            "/usr/bin/tool",
+            Some("unconfined_u:unconfined_r:unconfined_t:s0-s0:c0.c1023")
+        ),
+        PolicyDecision::AllowImmediate
+    ));
+    assert!(matches!(
+        check("/usr/bin/tool", Some("user_u:user_r:user_t:s0")),
+        PolicyDecision::Denied(_)
+    ));
+    // Context-bound rules never apply when the context isn't known
+    assert!(matches!(
+        check("/usr/bin/tool", None),
+        PolicyDecision::Denied(_)
+    ));
+    assert!(matches!(
+        engine.check_with_callers(Path::new("/usr/bin/tool"), 0, &[]),
+        PolicyDecision::Denied(_)
+    ));
+    assert!(matches!(
+        check("/usr/bin/exact", Some("system_u:system_r:sshd_t:s0")),
+        PolicyDecision::AllowImmediate
+    ));
+    assert!(matches!(
+        check("/usr/bin/exact", Some("system_u:system_r:sshd_t:s0:c1")),
+        PolicyDecision::Denied(_)
+    ));
+    // A pattern that doesn't compile only matches itself
+    assert!(matches!(
+        check("/usr/bin/exact", Some("[broken")),
+        PolicyDecision::AllowImmediate
+    ));
+}
+
+#[test]
+fn rules_remember_which_file_they_came_from() {
+    let dir = temp_policy_dir("sources");
+    let base = dir.join("10-base.toml");
+    let admins = dir.join("20-admins.toml");
+    fs::write(
+        &base,
+        r#"
+        [[rules]]
+        target = "/usr/bin/tool"
+        allow_users = ["root"]
+        auth = "confirm"
+
+        [[rules]]
+        target = "/usr/bin/other"
+        allow_users = ["root"]
+        enabled = false
+    "#,
+    )
+    .unwrap();
+    fs::write(
+        &admins,
+        r#"
+        [[rules]]
+        target = "*"
+        allow_users = ["root"]
+        auth = "none"
+    "#,
+    )
+    .unwrap();
+    let mut engine = PolicyEngine::new();
+    engine.load_from_dir(&dir).unwrap();
+
+    assert_eq!(
+        engine.sources(),
+        vec![(base.clone(), 2), (admins.clone(), 1)]
+    );
+
+    let explanation = engine.check_explain(Path::new("/usr/bin/tool"), 0, &[]);
+    let sources: Vec<_> = explanation
+        .rules
+        .iter()
+        .map(|rule| (rule.target.clone(), rule.source.clone()))
+        .collect();
+    assert_eq!(
+        sources,
+        vec![
+            (PathBuf::from("/usr/bin/tool"), Some(base)),
+            (PathBuf::from("*"), Some(admins)),
+        ]
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn reloading_one_file_leaves_the_others_alone() {
+    let dir = temp_policy_dir("reload-file");
+    let tools = dir.join("tools.toml");
+    let other = dir.join("other.toml");
+    let rule = |target: &str, auth: &str| {
+        format!("[[rules]]\ntarget = \"{target}\"\nallow_users = [\"root\"]\nauth = \"{auth}\"\n")
+    };
+    fs::write(&tools, rule("/usr/bin/tool", "confirm")).unwrap();
+    fs::write(
+        &other,
+        rule("/usr/bin/other", "none") + &rule("*", "confirm"),
+    )
+    .unwrap();
+    let mut engine = PolicyEngine::new();
+    engine.load_from_dir(&dir).unwrap();
+
+    fs::write(
+        &tools,
+        rule("/usr/bin/tool", "none") + "[[rules]]\ntarget = \"/usr/bin/off\"\nenabled = false\n",
+    )
+    .unwrap();
+    assert_eq!(engine.reload_file(&tools).unwrap(), 2);
+
+    // The edited rule replaced the old one instead of joining it
+    let tool = engine.check_explain(Path::new("/usr/bin/tool"), 0, &[]);
+    assert!(matches!(tool.decision, PolicyDecision::AllowImmediate));
+    assert_eq!(tool.rules.len(), 2);
+    assert_eq!(engine.rule_count(), 3);
+    assert_eq!(engine.disabled_count(), 1);
+    assert!(matches!(
+        engine.check(Path::new("/usr/bin/other"), 0),
+        PolicyDecision::AllowImmediate
+    ));
+    assert_eq!(
+        engine.sources(),
+        vec![(other.clone(), 2), (tools.clone(), 2)]
+    );
+
+    fs::remove_file(&tools).unwrap();
+    assert_eq!(engine.reload_file(&tools).unwrap(), 0);
+    assert_eq!(engine.rule_count(), 2);
+    assert_eq!(engine.disabled_count(), 0);
+    assert!(matches!(
+        engine.check(Path::new("/usr/bin/tool"), 0),
+        PolicyDecision::AllowWithConfirm
+    ));
+    assert_eq!(engine.sources(), vec![(other, 2)]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn reloading_a_broken_file_drops_its_rules_and_reports_it() {
+    let dir = temp_policy_dir("reload-broken");
+    let tools = dir.join("tools.toml");
+    fs::write(
+        &tools,
+        "[[rules]]\ntarget = \"*\"\nallow_users = [\"root\"]\n",
+    )
+    .unwrap();
+    let mut engine = PolicyEngine::new();
+    engine.load_from_dir(&dir).unwrap();
+
+    fs::write(&tools, "not toml").unwrap();
+    assert!(engine.reload_file(&tools).is_err());
+    assert_eq!(engine.rule_count(), 0);
+    assert!(matches!(
+        engine.validate().as_slice(),
+        [Warning::UnloadableFile { file, .. }] if *file == tools
+    ));
+
+    // Fixing it clears the report again
+    fs::write(
+        &tools,
+        "[[rules]]\ntarget = \"*\"\nallow_users = [\"root\"]\n",
+    )
+    .unwrap();
+    assert_eq!(engine.reload_file(&tools).unwrap(), 1);
+    assert!(engine.validate().is_empty());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn rules_from_strings_have_no_source() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str("[[rules]]\ntarget = \"*\"\nallow_users = [\"root\"]\n")
+        .unwrap();
+
+    assert!(engine.sources().is_empty());
+    let explanation = engine.check_explain(Path::new("/usr/bin/tool"), 0, &[]);
+    assert_eq!(explanation.rules[0].source, None);
+}
+
+#[test]
+fn instances_have_separate_policy_dirs() {
+    let test = InstanceName::new("test").unwrap();
+
+    assert_eq!(policy_dir(None), PathBuf::from(POLICY_DIR));
+    assert_eq!(
+        policy_dir(Some(&test)),
+        PathBuf::from("/etc/authd/instances/test/policies.d")
+    );
+}
+
+#[test]
+fn units_admit_callers_running_under_them() {
+    let mut engine = PolicyEngine::new();
+    engine
+        .load_from_str(
+            r#"
+            [[rules]]
+            target = "/usr/local/bin/snapshot"
+            allow_units = ["backup.service", "backup@*.service"]
+            auth = "none"
+        "#,
+        )
+        .unwrap();
+    let caller = |unit| CallerInfo {
+        exe: Path::new("/usr/bin/restic"),
+        cmdline_path: None,
+        secontext: None,
+        unit,
+    };
+    let check = |callers: &[CallerInfo]| {
+        engine.check_with_callers(Path::new("/usr/local/bin/snapshot"), 1000, callers)
+    };
+
+    assert!(matches!(
+        check(&[caller(Some("backup.service"))]),
+        PolicyDecision::AllowImmediate
+    ));
+    assert!(matches!(
+        check(&[caller(Some("backup@db.service"))]),
+        PolicyDecision::AllowImmediate
+    ));
+    // A user's own units all read as their service manager
+    assert!(matches!(
+        check(&[caller(Some("user@1000.service"))]),
+        PolicyDecision::Denied(_)
+    ));
+    assert!(matches!(check(&[caller(None)]), PolicyDecision::Denied(_)));
+    // Only the direct caller's unit counts
+    assert!(matches!(
+        check(&[caller(None), caller(Some("backup.service"))]),
+        PolicyDecision::Denied(_)
+    ));
+}
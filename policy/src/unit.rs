@@ -0,0 +1,106 @@
+//! The systemd unit a caller runs under, for `allow_units`
+//!
+//! Read from the process's cgroup, which an unprivileged process can't
+//! leave. As with `sd_pid_get_unit`, the unit is the first component of the
+//! cgroup path below the slices, so a service's own sub-cgroups still count
+//! as the service. Everything a user's service manager runs belongs to
+//! `user@<uid>.service` that way: a user can name a unit of their own
+//! `backup.service`, but it never reads as the system's.
+
+use std::fs;
+
+/// Suffixes of the unit types processes run in
+const PROCESS_UNITS: &[&str] = &[".service", ".scope", ".socket", ".mount", ".swap"];
+
+/// The system unit process `pid` runs under, if it can be told
+pub fn of_process(pid: u32) -> Option<String> {
+    let cgroup = fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+    from_cgroup(&cgroup).map(str::to_string)
+}
+
+/// The unit from the contents of `/proc/<pid>/cgroup`: the unified
+/// hierarchy's path (`0::`), or on a legacy or hybrid layout where that
+/// names none, the `name=systemd` one
+pub fn from_cgroup(cgroup: &str) -> Option<&str> {
+    let (mut unified, mut systemd) = (None, None);
+    for line in cgroup.lines() {
+        let mut fields = line.splitn(3, ':');
+        let (Some(id), Some(controllers), Some(path)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        if id == "0" && controllers.is_empty() {
+            unified = Some(path);
+        } else if controllers == "name=systemd" {
+            systemd = Some(path);
+        }
+    }
+    unified
+        .and_then(unit_of_path)
+        .or_else(|| systemd.and_then(unit_of_path))
+}
+
+fn unit_of_path(path: &str) -> Option<&str> {
+    let unit = path
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .find(|part| !part.ends_with(".slice"))?;
+    PROCESS_UNITS
+        .iter()
+        .any(|suffix| unit.len() > suffix.len() && unit.ends_with(suffix))
+        .then_some(unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn services_are_read_from_the_unified_hierarchy() {
+        assert_eq!(
+            from_cgroup("0::/system.slice/backup.service\n"),
+            Some("backup.service")
+        );
+        // A service's own sub-cgroups are still the service
+        assert_eq!(
+            from_cgroup("0::/system.slice/system-backup.slice/backup@db.service/worker\n"),
+            Some("backup@db.service")
+        );
+        assert_eq!(
+            from_cgroup("0::/user.slice/user-1000.slice/session-3.scope\n"),
+            Some("session-3.scope")
+        );
+    }
+
+    #[test]
+    fn a_users_own_units_belong_to_their_service_manager() {
+        assert_eq!(
+            from_cgroup(
+                "0::/user.slice/user-1000.slice/user@1000.service/app.slice/backup.service\n"
+            ),
+            Some("user@1000.service")
+        );
+    }
+
+    #[test]
+    fn legacy_layouts_use_the_systemd_hierarchy() {
+        let hybrid = "12:cpu,cpuacct:/\n\
+                      1:name=systemd:/system.slice/backup.service\n\
+                      0::/\n";
+        assert_eq!(from_cgroup(hybrid), Some("backup.service"));
+        let legacy = "4:memory:/system.slice/other.service\n\
+                      1:name=systemd:/system.slice/backup.service\n";
+        assert_eq!(from_cgroup(legacy), Some("backup.service"));
+    }
+
+    #[test]
+    fn anything_but_a_unit_is_none() {
+        assert_eq!(from_cgroup(""), None);
+        assert_eq!(from_cgroup("0::/\n"), None);
+        assert_eq!(from_cgroup("0::/system.slice\n"), None);
+        assert_eq!(from_cgroup("0::/docker/4f1c2a\n"), None);
+        assert_eq!(from_cgroup("0::/.service\n"), None);
+        assert_eq!(from_cgroup("3:cpu:/system.slice/backup.service\n"), None);
+    }
+}
@@ -0,0 +1,404 @@
+//! Sanity checks on loaded policy, for logging at startup
+//!
+//! None of these stop a rule from working as written: a missing target
+//! simply never matches, an unknown user or group never admits anyone. They
+//! usually mean a typo, though, and a file that failed to parse means its
+//! rules silently aren't there.
+
+use crate::{PolicyEngine, SessionType};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum Warning {
+    #[error("{file}: not loaded: {error}")]
+    UnloadableFile { file: PathBuf, error: String },
+    #[error("rule for {0}: target does not exist")]
+    MissingTarget(PathBuf),
+    #[error("rule for {target}: target is a symlink; authsudo checks {resolved} instead")]
+    SymlinkTarget { target: PathBuf, resolved: PathBuf },
+    #[error("rule for {target}: path is not canonical; authsudo checks {canonical} instead")]
+    NonCanonicalTarget { target: PathBuf, canonical: PathBuf },
+    #[error("rule for {target}: unknown user {user}")]
+    UnknownUser { target: PathBuf, user: String },
+    #[error("rule for {target}: unknown group {group}")]
+    UnknownGroup { target: PathBuf, group: String },
+    #[error("rule for {target}: unknown session type {session} (expected tty, graphical or ssh)")]
+    UnknownSessionType { target: PathBuf, session: String },
+    #[error("{file}: more than one rule for {target}")]
+    DuplicateTarget { file: PathBuf, target: PathBuf },
+}
+
+impl PolicyEngine {
+    /// Everything that looks wrong with the loaded policy: skipped files
+    /// first, then targets duplicated within a file, then rules in target
+    /// order.
+    pub fn validate(&self) -> Vec<Warning> {
+        let mut warnings: Vec<Warning> = self
+            .file_errors
+            .iter()
+            .map(|(file, error)| Warning::UnloadableFile {
+                file: file.clone(),
+                error: error.clone(),
+            })
+            .collect();
+        warnings.extend(
+            self.duplicates
+                .iter()
+                .map(|(file, target)| Warning::DuplicateTarget {
+                    file: file.clone(),
+                    target: target.clone(),
+                }),
+        );
+
+        let mut targets: Vec<&PathBuf> = self.rules.keys().collect();
+        targets.sort();
+        for target in targets {
+            if target.as_path() != Path::new("*") {
+                warnings.extend(target_warning(target));
+            }
+            for loaded in &self.rules[target] {
+                warnings.extend(rule_warnings(&loaded.rule));
+            }
+        }
+        warnings
+    }
+
+    /// No rule loaded although policy files were there: every one of them
+    /// failed, and every request would come back unknown.
+    pub fn nothing_loaded(&self) -> bool {
+        self.rule_count() == 0 && self.disabled_count() == 0 && !self.file_errors.is_empty()
+    }
+}
+
+/// authsudo matches the canonical path of the program a symlink points to,
+/// so a rule naming the link, or reaching the program through a linked
+/// directory (`/bin` on merged-/usr systems) or `..`, never applies there
+fn target_warning(target: &Path) -> Option<Warning> {
+    let is_link = fs::symlink_metadata(target).is_ok_and(|metadata| metadata.is_symlink());
+    match fs::canonicalize(target) {
+        Err(_) => Some(Warning::MissingTarget(target.to_path_buf())),
+        Ok(resolved) if is_link => Some(Warning::SymlinkTarget {
+            target: target.to_path_buf(),
+            resolved,
+        }),
+        Ok(canonical) if canonical != target => Some(Warning::NonCanonicalTarget {
+            target: target.to_path_buf(),
+            canonical,
+        }),
+        Ok(_) => None,
+    }
+}
+
+fn rule_warnings(rule: &authd_protocol::PolicyRule) -> Vec<Warning> {
+    let users = rule
+        .allow_users
+        .iter()
+        .filter(|user| users::get_user_by_name(user.as_str()).is_none())
+        .map(|user| Warning::UnknownUser {
+            target: rule.target.clone(),
+            user: user.clone(),
+        });
+    let groups = rule
+        .allow_groups
+        .iter()
+        .map(|group| group.strip_prefix('!').unwrap_or(group))
+        .filter(|group| users::get_group_by_name(group).is_none())
+        .map(|group| Warning::UnknownGroup {
+            target: rule.target.clone(),
+            group: group.to_string(),
+        });
+    let sessions = rule
+        .allow_session_types
+        .iter()
+        .filter(|session| SessionType::parse(session).is_none())
+        .map(|session| Warning::UnknownSessionType {
+            target: rule.target.clone(),
+            session: session.clone(),
+        });
+    users.chain(groups).chain(sessions).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn temp_policy_dir(name: &str) -> PathBuf {
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("authd-validate-{name}-{nonce}"));
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn files_present_but_all_invalid() {
+        let dir = temp_policy_dir("all-invalid");
+        fs::write(dir.join("a.toml"), "not toml").unwrap();
+        fs::write(dir.join("b.toml"), "[[rules]]\nauth = \"none\"\n").unwrap();
+        let mut engine = PolicyEngine::new();
+
+        engine.load_from_dir(&dir).unwrap();
+
+        assert!(engine.nothing_loaded());
+        let mut files: Vec<PathBuf> = engine
+            .validate()
+            .into_iter()
+            .map(|warning| match warning {
+                Warning::UnloadableFile { file, .. } => file,
+                other => panic!("unexpected warning {other}"),
+            })
+            .collect();
+        files.sort();
+        assert_eq!(files, vec![dir.join("a.toml"), dir.join("b.toml")]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn one_good_file_is_enough_to_start() {
+        let dir = temp_policy_dir("partly-invalid");
+        fs::write(dir.join("bad.toml"), "not toml").unwrap();
+        fs::write(
+            dir.join("good.toml"),
+            "[[rules]]\ntarget = \"*\"\nallow_users = [\"root\"]\n",
+        )
+        .unwrap();
+        let mut engine = PolicyEngine::new();
+
+        engine.load_from_dir(&dir).unwrap();
+
+        assert!(!engine.nothing_loaded());
+        assert_eq!(engine.validate().len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn empty_or_missing_directory_is_not_a_failure() {
+        let mut engine = PolicyEngine::new();
+        engine
+            .load_from_dir(Path::new("/nonexistent/authd-policies"))
+            .unwrap();
+
+        assert!(!engine.nothing_loaded());
+        assert!(engine.validate().is_empty());
+    }
+
+    #[test]
+    fn flags_targets_that_are_symlinks() {
+        let dir = fs::canonicalize(temp_policy_dir("symlink-target")).unwrap();
+        fs::write(dir.join("python3.12"), "").unwrap();
+        std::os::unix::fs::symlink("python3.12", dir.join("python3")).unwrap();
+        let mut engine = PolicyEngine::new();
+        for name in ["python3", "python3.12"] {
+            engine
+                .load_from_str(&format!(
+                    "[[rules]]\ntarget = \"{}\"\n",
+                    dir.join(name).display()
+                ))
+                .unwrap();
+        }
+
+        assert_eq!(
+            engine.validate(),
+            vec![Warning::SymlinkTarget {
+                target: dir.join("python3"),
+                resolved: dir.join("python3.12"),
+            }]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn flags_targets_reached_through_linked_directories() {
+        let dir = fs::canonicalize(temp_policy_dir("canonical-target")).unwrap();
+        fs::create_dir(dir.join("usr-bin")).unwrap();
+        fs::write(dir.join("usr-bin/id"), "").unwrap();
+        std::os::unix::fs::symlink("usr-bin", dir.join("bin")).unwrap();
+        let mut engine = PolicyEngine::new();
+        for target in ["bin/id", "usr-bin/../usr-bin/id", "usr-bin/id"] {
+            engine
+                .load_from_str(&format!(
+                    "[[rules]]\ntarget = \"{}\"\n",
+                    dir.join(target).display()
+                ))
+                .unwrap();
+        }
+
+        assert_eq!(
+            engine.validate(),
+            vec![
+                Warning::NonCanonicalTarget {
+                    target: dir.join("bin/id"),
+                    canonical: dir.join("usr-bin/id"),
+                },
+                Warning::NonCanonicalTarget {
+                    target: dir.join("usr-bin/../usr-bin/id"),
+                    canonical: dir.join("usr-bin/id"),
+                },
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    const DUPLICATED: &str = r#"
+        [[rules]]
+        target = "/usr/bin/id"
+        allow_groups = ["wheel"]
+        auth = "none"
+
+        [[rules]]
+        target = "/usr/bin/id"
+        allow_groups = ["wheel"]
+        auth = "deny"
+    "#;
+
+    fn duplicates(engine: &PolicyEngine) -> Vec<Warning> {
+        engine
+            .validate()
+            .into_iter()
+            .filter(|warning| matches!(warning, Warning::DuplicateTarget { .. }))
+            .collect()
+    }
+
+    #[test]
+    fn duplicate_targets_in_one_file_are_reported_by_default() {
+        let dir = temp_policy_dir("duplicate-warn");
+        fs::write(dir.join("dup.toml"), DUPLICATED).unwrap();
+        // The same rule again in another file is an override, not a mistake
+        fs::write(
+            dir.join("other.toml"),
+            "[[rules]]\ntarget = \"/usr/bin/id\"\nallow_groups = [\"wheel\"]\n",
+        )
+        .unwrap();
+        let mut engine = PolicyEngine::new();
+
+        engine.load_from_dir(&dir).unwrap();
+
+        assert_eq!(engine.rule_count(), 3);
+        assert_eq!(
+            duplicates(&engine),
+            vec![Warning::DuplicateTarget {
+                file: dir.join("dup.toml"),
+                target: PathBuf::from("/usr/bin/id"),
+            }]
+        );
+        assert_eq!(
+            duplicates(&engine)[0].to_string(),
+            format!(
+                "{}: more than one rule for /usr/bin/id",
+                dir.join("dup.toml").display()
+            )
+        );
+
+        // Fixing the file clears the report
+        fs::write(dir.join("dup.toml"), "").unwrap();
+        engine.reload_file(&dir.join("dup.toml")).unwrap();
+        assert!(duplicates(&engine).is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn duplicate_targets_can_refuse_the_whole_file() {
+        let dir = temp_policy_dir("duplicate-error");
+        fs::write(dir.join("dup.toml"), DUPLICATED).unwrap();
+        let mut engine = PolicyEngine::new();
+        engine.set_duplicate_targets(crate::DuplicateTargets::Error);
+
+        engine.load_from_dir(&dir).unwrap();
+
+        assert_eq!(engine.rule_count(), 0);
+        assert!(engine.nothing_loaded());
+        assert_eq!(
+            engine.validate(),
+            vec![Warning::UnloadableFile {
+                file: dir.join("dup.toml"),
+                error: "more than one rule for /usr/bin/id".into(),
+            }]
+        );
+        assert!(engine.load_from_str(DUPLICATED).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rules_for_different_people_or_disabled_copies_are_not_duplicates() {
+        let mut engine = PolicyEngine::new();
+        engine.set_duplicate_targets(crate::DuplicateTargets::Error);
+        engine
+            .load_from_str(
+                r#"
+                [[rules]]
+                target = "/usr/bin/id"
+                allow_callers = ["/usr/bin/claude"]
+                auth = "none"
+
+                [[rules]]
+                target = "/usr/bin/id"
+                allow_users = ["root"]
+                auth = "password"
+
+                [[rules]]
+                target = "/usr/bin/id"
+                allow_users = ["root"]
+                auth = "none"
+                enabled = false
+
+                [[rules]]
+                target = "*"
+                allow_users = ["root"]
+
+                [[rules]]
+                target = "*"
+                allow_users = ["root"]
+                auth = "deny"
+            "#,
+            )
+            .unwrap();
+
+        assert_eq!(engine.rule_count(), 4);
+        assert!(duplicates(&engine).is_empty());
+    }
+
+    #[test]
+    fn flags_missing_targets_and_unknown_principals() {
+        let mut engine = PolicyEngine::new();
+        engine
+            .load_from_str(
+                r#"
+                [[rules]]
+                target = "/nonexistent/bin/tool"
+                allow_users = ["root", "nonexistent_user_xyz"]
+                allow_groups = ["root", "!nonexistent_group_xyz"]
+                allow_session_types = ["ssh", "x11"]
+
+                [[rules]]
+                target = "*"
+                allow_users = ["root"]
+            "#,
+            )
+            .unwrap();
+
+        let target = PathBuf::from("/nonexistent/bin/tool");
+        assert_eq!(
+            engine.validate(),
+            vec![
+                Warning::MissingTarget(target.clone()),
+                Warning::UnknownUser {
+                    target: target.clone(),
+                    user: "nonexistent_user_xyz".into(),
+                },
+                Warning::UnknownGroup {
+                    target: target.clone(),
+                    group: "nonexistent_group_xyz".into(),
+                },
+                Warning::UnknownSessionType {
+                    target,
+                    session: "x11".into(),
+                },
+            ]
+        );
+    }
+}
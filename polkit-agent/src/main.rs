@@ -12,9 +12,9 @@ use std::collections::HashMap;
 
 use anyhow::{Context, Result};
 #[cfg(not(coverage))]
-use authd_protocol::{DaemonRequest, PolkitReply, PolkitRequest, SOCKET_PATH, collect_wayland_env};
-#[cfg(not(coverage))]
-use peercred_ipc::Client;
+use authd_protocol::{
+    DaemonRequest, PolkitReply, PolkitRequest, SOCKET_PATH, collect_wayland_env, frame,
+};
 #[cfg(not(coverage))]
 use tracing::{error, info, warn};
 #[cfg(not(coverage))]
@@ -116,7 +116,7 @@ fn caller_uid(identities: &[Identity]) -> Option<u32> {
 async fn ask_authd(request: PolkitRequest) -> Result<PolkitReply> {
     let socket = std::env::var("AUTHD_SOCKET").unwrap_or_else(|_| SOCKET_PATH.to_string());
     tokio::task::spawn_blocking(move || {
-        Client::call::<_, _, PolkitReply>(&socket, &DaemonRequest::Polkit(request))
+        frame::call_socket::<_, PolkitReply>(&socket, &DaemonRequest::Polkit(request))
             .map_err(|e| anyhow::anyhow!("{e}"))
     })
     .await
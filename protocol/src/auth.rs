@@ -0,0 +1,285 @@
+//! A rule's `auth`: a word, or the methods any one (or all) of which
+//! authorize its target
+//!
+//! `auth = "confirm"` and `auth = "password"` are one method each, and equal
+//! `auth = ["confirm"]` and `auth = ["password"]`. A longer list takes any
+//! one of its methods (`auth = ["password", "hwkey"]`); a table with `all`
+//! takes every one (`auth = { all = ["confirm", "hwkey"] }`). `none`,
+//! `discourage` and `deny` aren't methods and stay words.
+
+use crate::AuthRequirement;
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::path::Path;
+
+/// One way of authorizing a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMethod {
+    /// Answering the confirmation dialog
+    Confirm,
+    /// The requester's password
+    Password,
+    /// A hardware security key
+    Hwkey,
+}
+
+impl fmt::Display for AuthMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Confirm => "confirm",
+            Self::Password => "password",
+            Self::Hwkey => "hwkey",
+        })
+    }
+}
+
+/// The methods authd can check: its confirmation dialog, and the
+/// requester's own password, sent with the request and checked through PAM
+pub const CHECKED_METHODS: &[AuthMethod] = &[AuthMethod::Confirm, AuthMethod::Password];
+
+/// The methods a rule accepts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MethodSet {
+    /// Any one of them will do
+    Any(Vec<AuthMethod>),
+    /// Every one of them is needed
+    All(Vec<AuthMethod>),
+}
+
+impl MethodSet {
+    /// Whether a checker able to perform the `available` methods can satisfy
+    /// the set
+    pub fn satisfied_by(&self, available: &[AuthMethod]) -> bool {
+        match self {
+            Self::Any(methods) => methods.iter().any(|method| available.contains(method)),
+            Self::All(methods) => methods.iter().all(|method| available.contains(method)),
+        }
+    }
+
+    /// Why `target` can't be authorized under the set: it can't be met with
+    /// the methods authd checks (`CHECKED_METHODS`)
+    pub fn unchecked_refusal(&self, target: &Path) -> Option<String> {
+        (!self.satisfied_by(CHECKED_METHODS))
+            .then(|| format!("{} needs {self}, which authd can't check", target.display()))
+    }
+}
+
+impl fmt::Display for MethodSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, methods) = match self {
+            Self::Any(methods) => ("any", methods),
+            Self::All(methods) => ("all", methods),
+        };
+        let methods: Vec<String> = methods.iter().map(ToString::to_string).collect();
+        write!(f, "{name}({})", methods.join(", "))
+    }
+}
+
+impl AuthRequirement {
+    /// The methods that authorize a target under this requirement; None for
+    /// `none`, `discourage` and `deny`
+    pub fn methods(&self) -> Option<MethodSet> {
+        match self {
+            Self::Confirm => Some(MethodSet::Any(vec![AuthMethod::Confirm])),
+            Self::Password => Some(MethodSet::Any(vec![AuthMethod::Password])),
+            Self::Methods(set) => Some(set.clone()),
+            Self::None | Self::Discourage | Self::Deny => None,
+        }
+    }
+
+    /// The requirement for `set`. A single method is the word for it, so
+    /// `["confirm"]` and `"confirm"` load as the same requirement.
+    pub fn from_methods(set: MethodSet) -> Result<Self, String> {
+        let (all, methods) = match set {
+            MethodSet::Any(methods) => (false, methods),
+            MethodSet::All(methods) => (true, methods),
+        };
+        let mut distinct = Vec::with_capacity(methods.len());
+        for method in methods {
+            if !distinct.contains(&method) {
+                distinct.push(method);
+            }
+        }
+        match distinct.as_slice() {
+            [] => Err("auth lists no methods".into()),
+            [AuthMethod::Confirm] => Ok(Self::Confirm),
+            [AuthMethod::Password] => Ok(Self::Password),
+            [_, _, ..] if all => Ok(Self::Methods(MethodSet::All(distinct))),
+            _ => Ok(Self::Methods(MethodSet::Any(distinct))),
+        }
+    }
+}
+
+impl fmt::Display for AuthRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => f.write_str("none"),
+            Self::Confirm => f.write_str("confirm"),
+            Self::Password => f.write_str("password"),
+            Self::Discourage => f.write_str("discourage"),
+            Self::Deny => f.write_str("deny"),
+            Self::Methods(set) => set.fmt(f),
+        }
+    }
+}
+
+const WORDS: &[&str] = &["none", "confirm", "password", "discourage", "deny"];
+
+impl Serialize for AuthRequirement {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Methods(MethodSet::Any(methods)) => methods.serialize(serializer),
+            Self::Methods(MethodSet::All(methods)) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("all", methods)?;
+                map.end()
+            }
+            word => serializer.serialize_str(&word.to_string()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for AuthRequirement {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(AuthVisitor)
+    }
+}
+
+struct AuthVisitor;
+
+impl<'de> Visitor<'de> for AuthVisitor {
+    type Value = AuthRequirement;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("an auth word, a list of methods or a table with `all`")
+    }
+
+    fn visit_str<E: de::Error>(self, word: &str) -> Result<Self::Value, E> {
+        match word {
+            "none" => Ok(AuthRequirement::None),
+            "confirm" => Ok(AuthRequirement::Confirm),
+            "password" => Ok(AuthRequirement::Password),
+            "discourage" => Ok(AuthRequirement::Discourage),
+            "deny" => Ok(AuthRequirement::Deny),
+            other => Err(E::unknown_variant(other, WORDS)),
+        }
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut methods = Vec::new();
+        while let Some(method) = seq.next_element()? {
+            methods.push(method);
+        }
+        AuthRequirement::from_methods(MethodSet::Any(methods)).map_err(de::Error::custom)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut all = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key != "all" || all.is_some() {
+                return Err(de::Error::unknown_field(&key, &["all"]));
+            }
+            all = Some(map.next_value()?);
+        }
+        let all = all.ok_or_else(|| de::Error::missing_field("all"))?;
+        AuthRequirement::from_methods(MethodSet::All(all)).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct Rule {
+        auth: AuthRequirement,
+    }
+
+    fn auth(toml: &str) -> Result<AuthRequirement, String> {
+        toml::from_str::<Rule>(toml)
+            .map(|rule| rule.auth)
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn single_methods_are_the_words_for_them() {
+        assert_eq!(auth(r#"auth = ["confirm"]"#), Ok(AuthRequirement::Confirm));
+        assert_eq!(auth(r#"auth = "password""#), auth(r#"auth = ["password"]"#));
+        assert_eq!(
+            auth(r#"auth = { all = ["password"] }"#),
+            Ok(AuthRequirement::Password)
+        );
+        assert_eq!(
+            AuthRequirement::Confirm.methods(),
+            Some(MethodSet::Any(vec![AuthMethod::Confirm]))
+        );
+        assert_eq!(AuthRequirement::Deny.methods(), None);
+    }
+
+    #[test]
+    fn several_methods_take_any_or_all() {
+        let any = auth(r#"auth = ["password", "hwkey"]"#).unwrap();
+        assert_eq!(
+            any,
+            AuthRequirement::Methods(MethodSet::Any(vec![
+                AuthMethod::Password,
+                AuthMethod::Hwkey
+            ]))
+        );
+        assert_eq!(any.to_string(), "any(password, hwkey)");
+
+        let all = auth(r#"auth = { all = ["confirm", "hwkey"] }"#).unwrap();
+        assert_eq!(all.to_string(), "all(confirm, hwkey)");
+
+        let checkable = [AuthMethod::Confirm, AuthMethod::Password];
+        assert!(any.methods().unwrap().satisfied_by(&checkable));
+        assert!(!all.methods().unwrap().satisfied_by(&checkable));
+        assert!(
+            all.methods()
+                .unwrap()
+                .satisfied_by(&[AuthMethod::Hwkey, AuthMethod::Confirm])
+        );
+    }
+
+    #[test]
+    fn bad_auth_values_say_what_is_wrong() {
+        assert!(
+            auth(r#"auth = "sometimes""#)
+                .unwrap_err()
+                .contains("unknown variant")
+        );
+        assert!(
+            auth(r#"auth = ["retina"]"#)
+                .unwrap_err()
+                .contains("unknown variant")
+        );
+        assert!(auth("auth = []").unwrap_err().contains("no methods"));
+        assert!(
+            auth(r#"auth = { any = ["confirm"] }"#)
+                .unwrap_err()
+                .contains("unknown field")
+        );
+    }
+
+    #[test]
+    fn every_form_survives_a_round_trip() {
+        for toml in [
+            r#"auth = "none""#,
+            r#"auth = "discourage""#,
+            r#"auth = ["password", "hwkey"]"#,
+            r#"auth = { all = ["confirm", "hwkey"] }"#,
+        ] {
+            let rule: Rule = toml::from_str(toml).unwrap();
+            let written = toml::to_string(&rule).unwrap();
+            assert_eq!(
+                toml::from_str::<Rule>(&written).unwrap().auth,
+                rule.auth,
+                "{written}"
+            );
+        }
+    }
+}
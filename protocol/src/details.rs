@@ -0,0 +1,179 @@
+//! Everything a confirmation approves, spelled out
+//!
+//! A one-line command can hide long or odd arguments and says nothing of the
+//! environment passed along. [`Details`] lists the full argument vector and
+//! the environment, with values that look like secrets masked so that
+//! showing them can't leak a token onto the screen. Confirmation prompts show
+//! it below the command, and `authctl --details` prints it before asking.
+
+use crate::AuthRequest;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Shown instead of the value of a secret-looking variable
+pub const MASK: &str = "********";
+
+/// Variable name fragments that mark a value as secret (case-insensitive)
+const SECRET_NAME_PARTS: &[&str] = &[
+    "PASSWORD",
+    "PASSWD",
+    "SECRET",
+    "TOKEN",
+    "APIKEY",
+    "API_KEY",
+    "PRIVATE",
+    "CREDENTIAL",
+    "COOKIE",
+];
+
+/// Value prefixes of well-known credential formats
+const SECRET_VALUE_PREFIXES: &[&str] = &["ghp_", "gho_", "github_pat_", "glpat-", "xoxb-", "sk-"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Details {
+    pub target: PathBuf,
+    pub args: Vec<String>,
+    /// Sorted by name, secret values already masked
+    pub env: Vec<(String, String)>,
+}
+
+impl Details {
+    pub fn of(request: &AuthRequest) -> Self {
+        Self::new(&request.target, &request.args, &request.env)
+    }
+
+    pub fn new(target: &Path, args: &[String], env: &HashMap<String, String>) -> Self {
+        let mut env: Vec<_> = env
+            .iter()
+            .map(|(name, value)| (name.clone(), masked(name, value)))
+            .collect();
+        env.sort();
+        Self {
+            target: target.to_path_buf(),
+            args: args.to_vec(),
+            env,
+        }
+    }
+}
+
+/// `value`, or [`MASK`] if the variable looks like it holds a secret
+pub fn masked(name: &str, value: &str) -> String {
+    if looks_secret(name, value) {
+        MASK.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn looks_secret(name: &str, value: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    SECRET_NAME_PARTS.iter().any(|part| name.contains(part))
+        || SECRET_VALUE_PREFIXES
+            .iter()
+            .any(|prefix| value.starts_with(prefix))
+        // user:password@host in URLs
+        || value
+            .split_once("://")
+            .and_then(|(_, rest)| rest.split_once('@'))
+            .is_some_and(|(userinfo, _)| userinfo.contains(':'))
+}
+
+/// One argument per line, numbered like argv, so nothing hides in a long
+/// line; arguments are debug-quoted to make whitespace and control
+/// characters visible.
+impl fmt::Display for Details {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "argv[0] {:?}", self.target.display().to_string())?;
+        for (i, arg) in self.args.iter().enumerate() {
+            writeln!(f, "argv[{}] {:?}", i + 1, arg)?;
+        }
+        if self.env.is_empty() {
+            return write!(f, "environment: (none)");
+        }
+        write!(f, "environment:")?;
+        for (name, value) in &self.env {
+            write!(f, "\n  {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(env: &[(&str, &str)]) -> AuthRequest {
+        AuthRequest {
+            target: PathBuf::from("/usr/bin/env"),
+            args: vec!["-i".into(), "a b".into(), "line\nbreak".into()],
+            env: env
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>(),
+            password: String::new(),
+            confirm_only: false,
+            prompt_title: None,
+            prompt_message: None,
+            prompt_detail: None,
+            approval: None,
+            discouraged: false,
+            methods: None,
+            pam_service: None,
+        }
+    }
+
+    #[test]
+    fn masks_values_that_look_like_secrets() {
+        for (name, value) in [
+            ("GITHUB_TOKEN", "abc"),
+            ("db_password", "hunter2"),
+            ("AWS_SECRET_ACCESS_KEY", "abc"),
+            ("OPENAI_API_KEY", "abc"),
+            ("SESSION_COOKIE", "abc"),
+            ("NOTES", "ghp_0123456789"),
+            ("DATABASE_URL", "postgres://app:hunter2@db/app"),
+        ] {
+            assert_eq!(masked(name, value), MASK, "{name}");
+        }
+    }
+
+    #[test]
+    fn leaves_ordinary_values_readable() {
+        for (name, value) in [
+            ("WAYLAND_DISPLAY", "wayland-1"),
+            ("XDG_RUNTIME_DIR", "/run/user/1000"),
+            ("DBUS_SESSION_BUS_ADDRESS", "unix:path=/run/user/1000/bus"),
+            ("LANG", "en_US.UTF-8"),
+            ("HOMEPAGE", "https://example.org/@user"),
+        ] {
+            assert_eq!(masked(name, value), value, "{name}");
+        }
+    }
+
+    #[test]
+    fn lists_every_argument_and_sorted_masked_env() {
+        let details = Details::of(&request(&[
+            ("WAYLAND_DISPLAY", "wayland-1"),
+            ("API_TOKEN", "s3cret"),
+        ]));
+
+        assert_eq!(
+            details.to_string(),
+            "argv[0] \"/usr/bin/env\"\n\
+             argv[1] \"-i\"\n\
+             argv[2] \"a b\"\n\
+             argv[3] \"line\\nbreak\"\n\
+             environment:\n  \
+             API_TOKEN=********\n  \
+             WAYLAND_DISPLAY=wayland-1"
+        );
+        assert!(!details.to_string().contains("s3cret"));
+    }
+
+    #[test]
+    fn says_so_when_no_environment_is_passed() {
+        let details = Details::of(&request(&[]));
+        assert!(details.to_string().ends_with("environment: (none)"));
+    }
+}
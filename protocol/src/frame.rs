@@ -0,0 +1,372 @@
+//! Length-prefixed msgpack framing
+//!
+//! Every message is a big-endian `u32` payload length followed by the msgpack
+//! payload. Anything arriving here may come from an untrusted local process,
+//! so every path returns a [`FrameError`] instead of panicking, and the length
+//! prefix is checked against [`MAX_FRAME_LEN`] before any buffer is allocated.
+//!
+//! Stream sockets may split a frame across any number of reads and writes, so
+//! the stream functions loop until a whole frame has moved rather than
+//! trusting a single `read`/`write` call.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use thiserror::Error;
+
+/// Largest payload accepted in either direction.
+pub const MAX_FRAME_LEN: usize = 1024 * 1024;
+
+/// Bytes of the big-endian length prefix ahead of every payload.
+pub const PREFIX_LEN: usize = 4;
+
+#[derive(Debug, Error)]
+pub enum FrameError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("frame too large: {0} bytes (max {MAX_FRAME_LEN})")]
+    TooLarge(usize),
+    #[error("truncated frame: expected {expected} bytes, got {actual}")]
+    Truncated { expected: usize, actual: usize },
+    #[error("trailing bytes after frame: {0}")]
+    Trailing(usize),
+    #[error("decode error: {0}")]
+    Decode(#[from] rmp_serde::decode::Error),
+    #[error("encode error: {0}")]
+    Encode(#[from] rmp_serde::encode::Error),
+}
+
+/// Serialize `message` into a complete frame (prefix + payload).
+pub fn encode<T: Serialize>(message: &T) -> Result<Vec<u8>, FrameError> {
+    let payload = rmp_serde::to_vec(message)?;
+    let len = checked_len(payload.len())?;
+    let mut frame = Vec::with_capacity(PREFIX_LEN + payload.len());
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// Decode exactly one complete frame held in `bytes`.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, FrameError> {
+    let Some((prefix, payload)) = bytes.split_first_chunk::<PREFIX_LEN>() else {
+        return Err(FrameError::Truncated {
+            expected: PREFIX_LEN,
+            actual: bytes.len(),
+        });
+    };
+    let len = payload_len(*prefix)?;
+    if payload.len() < len {
+        return Err(FrameError::Truncated {
+            expected: len,
+            actual: payload.len(),
+        });
+    }
+    if payload.len() > len {
+        return Err(FrameError::Trailing(payload.len() - len));
+    }
+    decode_payload(payload)
+}
+
+/// Decode a bare msgpack payload (no prefix), enforcing the size limit.
+pub fn decode_payload<T: DeserializeOwned>(payload: &[u8]) -> Result<T, FrameError> {
+    checked_len(payload.len())?;
+    Ok(rmp_serde::from_slice(payload)?)
+}
+
+/// Read one frame from a blocking stream, however many reads it takes.
+pub fn read_frame<T: DeserializeOwned, R: Read>(reader: &mut R) -> Result<T, FrameError> {
+    let mut prefix = [0u8; PREFIX_LEN];
+    reader.read_exact(&mut prefix)?;
+    let len = payload_len(prefix)?;
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+    decode_payload(&payload)
+}
+
+/// Write one frame to a blocking stream, however many writes it takes.
+pub fn write_frame<T: Serialize, W: Write>(writer: &mut W, message: &T) -> Result<(), FrameError> {
+    writer.write_all(&encode(message)?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Send `request` as one frame and read one frame back as the response.
+pub fn call<Req, Resp, S>(stream: &mut S, request: &Req) -> Result<Resp, FrameError>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned,
+    S: Read + Write,
+{
+    write_frame(stream, request)?;
+    read_frame(stream)
+}
+
+/// Connect to the Unix socket at `path` and [`call`] over it: one request,
+/// one response, as every authd client talks to the daemon.
+pub fn call_socket<Req, Resp>(path: impl AsRef<Path>, request: &Req) -> Result<Resp, FrameError>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned,
+{
+    let mut stream = UnixStream::connect(path)?;
+    call(&mut stream, request)
+}
+
+/// The payload length a prefix announces, refused if over [`MAX_FRAME_LEN`],
+/// for readers that can't use [`read_frame`] (async streams).
+pub fn payload_len(prefix: [u8; PREFIX_LEN]) -> Result<usize, FrameError> {
+    let len = u32::from_be_bytes(prefix) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(FrameError::TooLarge(len));
+    }
+    Ok(len)
+}
+
+fn checked_len(len: usize) -> Result<u32, FrameError> {
+    if len > MAX_FRAME_LEN {
+        return Err(FrameError::TooLarge(len));
+    }
+    Ok(len as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AuthRequest, DaemonRequest};
+    use std::collections::HashMap;
+    use std::io::Cursor;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+
+    fn request() -> DaemonRequest {
+        DaemonRequest::Exec(AuthRequest {
+            target: PathBuf::from("/usr/bin/id"),
+            args: vec!["-u".into()],
+            env: HashMap::from([("TERM".into(), "xterm".into())]),
+            password: String::new(),
+            confirm_only: false,
+            prompt_title: None,
+            prompt_message: None,
+            prompt_detail: None,
+            approval: None,
+            discouraged: false,
+            methods: None,
+            pam_service: None,
+        })
+    }
+
+    fn frame_with_payload(payload: &[u8]) -> Vec<u8> {
+        let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn roundtrip_through_bytes_and_stream() {
+        let frame = encode(&request()).unwrap();
+        assert!(matches!(
+            decode::<DaemonRequest>(&frame).unwrap(),
+            DaemonRequest::Exec(r) if r.args == ["-u"]
+        ));
+
+        let mut stream = Vec::new();
+        write_frame(&mut stream, &request()).unwrap();
+        let decoded: DaemonRequest = read_frame(&mut Cursor::new(stream)).unwrap();
+        assert!(matches!(decoded, DaemonRequest::Exec(_)));
+    }
+
+    /// Hands over at most `chunk` bytes per call, like a busy socket
+    struct Trickle<T> {
+        inner: T,
+        chunk: usize,
+        calls: usize,
+    }
+
+    impl<T: Read> Read for Trickle<T> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.calls += 1;
+            let len = buf.len().min(self.chunk);
+            self.inner.read(&mut buf[..len])
+        }
+    }
+
+    impl<T: Write> Write for Trickle<T> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.calls += 1;
+            let len = buf.len().min(self.chunk);
+            self.inner.write(&buf[..len])
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    #[test]
+    fn frames_survive_short_reads_and_writes() {
+        let mut writer = Trickle {
+            inner: Vec::new(),
+            chunk: 3,
+            calls: 0,
+        };
+        write_frame(&mut writer, &request()).unwrap();
+        assert!(writer.calls > 1);
+
+        let mut reader = Trickle {
+            inner: Cursor::new(writer.inner),
+            chunk: 3,
+            calls: 0,
+        };
+        let decoded: DaemonRequest = read_frame(&mut reader).unwrap();
+        assert!(matches!(decoded, DaemonRequest::Exec(r) if r.args == ["-u"]));
+        assert!(reader.calls > 1);
+    }
+
+    fn shrink_buffers(stream: &UnixStream) {
+        let size: libc::c_int = 4096;
+        for option in [libc::SO_SNDBUF, libc::SO_RCVBUF] {
+            let rc = unsafe {
+                libc::setsockopt(
+                    stream.as_raw_fd(),
+                    libc::SOL_SOCKET,
+                    option,
+                    (&size as *const libc::c_int).cast(),
+                    std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+                )
+            };
+            assert_eq!(rc, 0);
+        }
+    }
+
+    #[test]
+    fn large_response_crosses_a_small_socket_buffer_intact() {
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        shrink_buffers(&client);
+        shrink_buffers(&server);
+        // Far more than the buffers hold, so it needs many reads and writes
+        let listing: Vec<String> = (0..20_000).map(|i| format!("rule {i}")).collect();
+
+        let daemon = std::thread::spawn({
+            let listing = listing.clone();
+            move || {
+                let _: DaemonRequest = read_frame(&mut server).unwrap();
+                write_frame(&mut server, &listing).unwrap();
+            }
+        });
+        let response: Vec<String> = call(&mut client, &request()).unwrap();
+        daemon.join().unwrap();
+
+        assert_eq!(response, listing);
+    }
+
+    #[test]
+    fn short_prefix_is_truncated() {
+        for len in 0..PREFIX_LEN {
+            assert!(matches!(
+                decode::<DaemonRequest>(&[0u8; PREFIX_LEN][..len]),
+                Err(FrameError::Truncated { .. })
+            ));
+        }
+        assert!(matches!(
+            read_frame::<DaemonRequest, _>(&mut Cursor::new(vec![0u8, 0])),
+            Err(FrameError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn oversized_prefix_is_rejected_before_allocating() {
+        let frame = u32::MAX.to_be_bytes();
+        assert!(matches!(
+            decode::<DaemonRequest>(&frame),
+            Err(FrameError::TooLarge(_))
+        ));
+        assert!(matches!(
+            read_frame::<DaemonRequest, _>(&mut Cursor::new(frame.to_vec())),
+            Err(FrameError::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn length_mismatch_is_rejected() {
+        let mut frame = encode(&request()).unwrap();
+        frame.push(0);
+        assert!(matches!(
+            decode::<DaemonRequest>(&frame),
+            Err(FrameError::Trailing(1))
+        ));
+
+        frame.truncate(frame.len() - 2);
+        assert!(matches!(
+            decode::<DaemonRequest>(&frame),
+            Err(FrameError::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn garbage_payload_is_a_decode_error() {
+        let payloads: [&[u8]; 4] = [&[], &[0xc1], &[0xff, 0xff, 0xff], b"not msgpack"];
+        for payload in payloads {
+            assert!(decode::<DaemonRequest>(&frame_with_payload(payload)).is_err());
+        }
+    }
+
+    #[test]
+    fn huge_declared_collection_lengths_do_not_panic() {
+        // fixmap { "Exec": array32 with 2^32-1 elements } and no element data
+        let mut payload = vec![0x81, 0xa4];
+        payload.extend_from_slice(b"Exec");
+        payload.extend_from_slice(&[0xdd, 0xff, 0xff, 0xff, 0xff]);
+        assert!(decode::<DaemonRequest>(&frame_with_payload(&payload)).is_err());
+
+        // AuthRequest as array: target, then args as array32 of 2^32-1 strings
+        let mut payload = vec![0x98, 0xa2];
+        payload.extend_from_slice(b"/x");
+        payload.extend_from_slice(&[0xdd, 0xff, 0xff, 0xff, 0xff]);
+        assert!(decode_payload::<AuthRequest>(&payload).is_err());
+
+        // env as map32 with 2^32-1 entries
+        let mut payload = vec![0x98, 0xa2];
+        payload.extend_from_slice(b"/x");
+        payload.extend_from_slice(&[0x90, 0xdf, 0xff, 0xff, 0xff, 0xff]);
+        assert!(decode_payload::<AuthRequest>(&payload).is_err());
+    }
+
+    #[test]
+    fn oversized_message_is_not_encoded() {
+        let DaemonRequest::Exec(mut big) = request() else {
+            unreachable!()
+        };
+        big.args = vec!["x".repeat(MAX_FRAME_LEN)];
+        assert!(matches!(
+            encode(&DaemonRequest::Exec(big)),
+            Err(FrameError::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn call_socket_gets_one_response_per_request() {
+        let dir = std::env::temp_dir().join(format!("authd-frame-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("authd.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let daemon = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request: DaemonRequest = read_frame(&mut stream).unwrap();
+            write_frame(&mut stream, &matches!(request, DaemonRequest::Exec(_))).unwrap();
+        });
+
+        let answered: bool = call_socket(&path, &request()).unwrap();
+        assert!(answered);
+        daemon.join().unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(
+            call_socket::<_, bool>(&path, &request()),
+            Err(FrameError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,78 @@
+//! Named authd instances
+//!
+//! Several daemons can run side by side (per seat, test next to production),
+//! each with its own socket at `/run/authd/<name>.sock`. Leaving the name out
+//! addresses the default instance on [`SOCKET_PATH`](crate::SOCKET_PATH).
+
+use std::fmt;
+use thiserror::Error;
+
+/// Directory holding the sockets of named instances
+pub const INSTANCE_DIR: &str = "/run/authd";
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("invalid instance name {0:?}: use letters, digits, '-' and '_'")]
+pub struct InvalidInstanceName(pub String);
+
+/// An instance name that is safe to splice into a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceName(String);
+
+impl InstanceName {
+    pub fn new(name: &str) -> Result<Self, InvalidInstanceName> {
+        let valid = !name.is_empty()
+            && name.len() <= 64
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if !valid {
+            return Err(InvalidInstanceName(name.to_string()));
+        }
+        Ok(Self(name.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for InstanceName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Socket of `instance`, or of the default instance for `None`. A string,
+/// like `SOCKET_PATH`, since names are plain ASCII.
+pub fn socket_path(instance: Option<&InstanceName>) -> String {
+    match instance {
+        Some(name) => format!("{}/{}.sock", INSTANCE_DIR, name),
+        None => crate::SOCKET_PATH.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_instances_get_their_own_socket() {
+        let test = InstanceName::new("test").unwrap();
+        let seat1 = InstanceName::new("seat_1").unwrap();
+
+        assert_eq!(socket_path(None), "/run/authd.sock");
+        assert_eq!(socket_path(Some(&test)), "/run/authd/test.sock");
+        assert_ne!(socket_path(Some(&test)), socket_path(Some(&seat1)));
+    }
+
+    #[test]
+    fn rejects_names_that_could_escape_the_directory() {
+        for name in ["", ".", "..", "../etc", "a/b", "sp ace", "x.sock"] {
+            assert_eq!(
+                InstanceName::new(name),
+                Err(InvalidInstanceName(name.to_string()))
+            );
+        }
+        assert!(InstanceName::new(&"a".repeat(65)).is_err());
+    }
+}
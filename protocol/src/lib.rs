@@ -1,3 +1,16 @@
+pub mod auth;
+pub mod details;
+pub mod frame;
+pub mod instance;
+pub mod limits;
+pub mod lockdown;
+pub mod messages;
+pub mod pam;
+pub mod preflight;
+
+pub use auth::{AuthMethod, CHECKED_METHODS, MethodSet};
+use limits::ResourceLimits;
+use pam::PamService;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::env;
@@ -27,18 +40,99 @@ pub struct AuthRequest {
     /// Optional dialog detail text for confirm-only callers.
     #[serde(default)]
     pub prompt_detail: Option<String>,
+    /// Second person's credentials, for targets under `require_approver_group`
+    #[serde(default)]
+    pub approval: Option<Approval>,
+    /// A confirm-only override of an `auth = "discourage"` target: authd
+    /// shows its warning and audits the answer as a discouraged run
+    #[serde(default)]
+    pub discouraged: bool,
+    /// For a confirm-only request, the methods authsudo's deciding rule
+    /// takes, so authd asks for the password where they need one. None
+    /// leaves it to the dialog.
+    #[serde(default)]
+    pub methods: Option<MethodSet>,
+    /// For a confirm-only request, the `pam_service` of authsudo's deciding
+    /// rule, which a password asked for is checked through. None is `authd`.
+    #[serde(default)]
+    pub pam_service: Option<PamService>,
+}
+
+impl AuthRequest {
+    /// A request to run `target` with `args` and `env`, everything else
+    /// left empty. Fails if it wouldn't fit in one frame, so an oversized
+    /// command is refused here rather than by authd after a round-trip.
+    pub fn try_new(
+        target: PathBuf,
+        args: Vec<String>,
+        env: HashMap<String, String>,
+    ) -> Result<Self, frame::FrameError> {
+        let request = Self {
+            target,
+            args,
+            env,
+            password: String::new(),
+            confirm_only: false,
+            prompt_title: None,
+            prompt_message: None,
+            prompt_detail: None,
+            approval: None,
+            discouraged: false,
+            methods: None,
+            pam_service: None,
+        };
+        request.check_size()?;
+        Ok(request)
+    }
+
+    /// `FrameError::TooLarge` if the request, sent as a [`DaemonRequest`],
+    /// is over [`frame::MAX_FRAME_LEN`]: recheck after filling in prompt
+    /// text or a password
+    pub fn check_size(&self) -> Result<(), frame::FrameError> {
+        frame::encode(&DaemonRequest::Exec(self.clone())).map(drop)
+    }
+}
+
+/// Another user vouching for a request by authenticating as themselves
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Approval {
+    pub user: String,
+    pub password: String,
+    /// Why the requester asked, as the approver was shown it; recorded in
+    /// the audit log with the approval
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// Keeps the password out of logs
+impl std::fmt::Debug for Approval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Approval")
+            .field("user", &self.user)
+            .field("password", &"<redacted>")
+            .field("reason", &self.reason)
+            .finish()
+    }
 }
 
 /// Check if user has cached auth (no password needed)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthCheckRequest {
     pub target: PathBuf,
+    /// Arguments the target would get; checked against `allow_scripts`
+    #[serde(default)]
+    pub args: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AuthCheckResponse {
     /// User has valid cached auth - can proceed without password
-    Cached,
+    Cached {
+        /// Seconds until the cached authorization expires (rounded up)
+        remaining_secs: u64,
+    },
+    /// Policy allows the target with no interaction at all
+    Allowed,
     /// Password required
     PasswordRequired,
     /// Target not allowed
@@ -50,7 +144,13 @@ pub enum AuthCheckResponse {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AuthResponse {
     /// Success - returns PID of spawned process
-    Success { pid: u32 },
+    Success {
+        pid: u32,
+        /// Whether a cached authorization let the request through, rather
+        /// than a fresh password or confirmation (or none being needed)
+        #[serde(default)]
+        cached: bool,
+    },
     /// Authentication failed (wrong password)
     AuthFailed,
     /// Target denied by policy
@@ -59,6 +159,13 @@ pub enum AuthResponse {
     UnknownTarget,
     /// Internal daemon error
     Error { message: String },
+    /// Answer to [`DaemonRequest::ExecWait`]: the launched process has exited,
+    /// with `code` if it exited normally or `signal` if it was killed
+    Exited {
+        pid: u32,
+        code: Option<i32>,
+        signal: Option<i32>,
+    },
 }
 
 /// Top-level request envelope read by authd. Keeps the legacy exec/confirm
@@ -68,8 +175,204 @@ pub enum AuthResponse {
 pub enum DaemonRequest {
     /// Legacy authsudo/authctl request: check policy, optionally confirm, spawn.
     Exec(AuthRequest),
+    /// Like `Exec`, but answered with `AuthResponse::Exited` once the
+    /// launched process has exited instead of right after spawning it.
+    ExecWait(AuthRequest),
     /// polkit agent forwarded a `BeginAuthentication`: confirm, then assert.
     Polkit(PolkitRequest),
+    /// Ask whether a target would need interaction, without side effects.
+    Check(AuthCheckRequest),
+    /// List privileged scopes authd launched that are still running.
+    ListActive,
+    /// Stop a scope authd launched (own launches, or any for admins).
+    KillScope(KillScopeRequest),
+    /// Turn lockdown on or off (admins only).
+    Lockdown(LockdownRequest),
+    /// Check the caller's own password, without policy or launching anything.
+    TestAuth(TestAuthRequest),
+    /// List the caller's own cached authorizations, answered with
+    /// `Vec<CachedAuth>`.
+    ListCache,
+    /// Hold the connection open and receive each audit event as it is
+    /// recorded, as a stream of `AuditStreamMessage`s (admins only).
+    SubscribeAudit,
+}
+
+/// One message of a `SubscribeAudit` stream
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuditStreamMessage {
+    /// An audit event, as the JSON object authd writes under
+    /// `log_format = "json"` (same schema and `schema_version`)
+    Event { json: String },
+    /// The subscriber fell behind and this many events were dropped for it;
+    /// the stream goes on with the next one
+    Lagged { missed: u64 },
+    /// Not an admin; the stream ends here
+    Denied { reason: String },
+}
+
+/// A target the caller may run again without confirming, for now
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedAuth {
+    pub target: PathBuf,
+    /// Seconds until the cached authorization expires (rounded up)
+    pub remaining_secs: u64,
+}
+
+/// A still-running process authd launched in its own systemd scope
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActiveScope {
+    /// systemd unit name, e.g. `authd-1000-4242-7.scope`
+    pub unit: String,
+    pub target: PathBuf,
+    /// Executable of the client that requested the launch
+    pub caller: PathBuf,
+    /// uid that requested the launch
+    pub uid: u32,
+    pub pid: u32,
+    /// Launch time, seconds since the Unix epoch
+    pub started_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KillScopeRequest {
+    pub unit: String,
+    /// Signal number to send, checked against [`KillSignal`]. Clients from
+    /// before this field existed send none and get `SIGTERM`.
+    #[serde(default = "default_kill_signal")]
+    pub signal: i32,
+}
+
+fn default_kill_signal() -> i32 {
+    KillSignal::Term.number()
+}
+
+impl KillScopeRequest {
+    pub fn new(unit: impl Into<String>, signal: KillSignal) -> Self {
+        Self {
+            unit: unit.into(),
+            signal: signal.number(),
+        }
+    }
+
+    /// The requested signal, if it's one a client may send
+    pub fn checked_signal(&self) -> Result<KillSignal, String> {
+        KillSignal::from_number(self.signal)
+    }
+}
+
+/// The signals a `KillScope` may send. Anything else, `SIGKILL` above all,
+/// could leave a scope wedged without running its own cleanup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KillSignal {
+    /// Stop the scope (`systemctl stop`); the default
+    Term,
+    Hup,
+    Int,
+}
+
+impl KillSignal {
+    pub const ALLOWED: [KillSignal; 3] = [KillSignal::Term, KillSignal::Hup, KillSignal::Int];
+
+    /// Linux signal number
+    pub fn number(self) -> i32 {
+        match self {
+            Self::Term => 15,
+            Self::Hup => 1,
+            Self::Int => 2,
+        }
+    }
+
+    /// Name without the `SIG` prefix, as `systemctl kill --signal` takes it
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Term => "TERM",
+            Self::Hup => "HUP",
+            Self::Int => "INT",
+        }
+    }
+
+    pub fn from_number(number: i32) -> Result<Self, String> {
+        Self::ALLOWED
+            .into_iter()
+            .find(|signal| signal.number() == number)
+            .ok_or_else(|| format!("signal {} is not allowed (use TERM, HUP or INT)", number))
+    }
+
+    /// Parse `TERM`, `SIGTERM`, `term` or `15`
+    pub fn parse(name: &str) -> Result<Self, String> {
+        if let Ok(number) = name.parse() {
+            return Self::from_number(number);
+        }
+        let upper = name.to_ascii_uppercase();
+        let bare = upper.strip_prefix("SIG").unwrap_or(&upper);
+        Self::ALLOWED
+            .into_iter()
+            .find(|signal| signal.name() == bare)
+            .ok_or_else(|| format!("signal {} is not allowed (use TERM, HUP or INT)", name))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum KillScopeResponse {
+    /// The scope was asked to stop
+    Stopped,
+    /// No active scope launched by authd has that name
+    NotFound,
+    /// Not the launching user and not an admin
+    Denied {
+        reason: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockdownRequest {
+    /// On, or cleared
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LockdownResponse {
+    /// Lockdown is now `active`
+    Set {
+        active: bool,
+    },
+    /// Not an admin
+    Denied {
+        reason: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// `authsudo --test-auth`: the caller's password, checked the way approvals
+/// are (PAM service `authd`) to tell PAM problems from policy ones
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TestAuthRequest {
+    pub password: String,
+}
+
+/// Keeps the password out of logs
+impl std::fmt::Debug for TestAuthRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TestAuthRequest")
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TestAuthResponse {
+    /// The password is right
+    Accepted,
+    /// The password is wrong
+    Rejected,
+    /// The password couldn't be checked; `message` has the PAM error
+    Error { message: String },
 }
 
 /// A polkit `BeginAuthentication` forwarded from `authd-polkit-agent`.
@@ -111,7 +414,8 @@ pub struct CallerInfo {
 /// Policy rule (declarative, loaded from TOML)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PolicyRule {
-    /// Target binary path
+    /// Target binary path; may be left out when `command` names it
+    #[serde(default)]
     pub target: PathBuf,
     /// Groups allowed to run this target
     #[serde(default)]
@@ -122,20 +426,121 @@ pub struct PolicyRule {
     /// Caller binaries that bypass auth (e.g., "/usr/bin/claude")
     #[serde(default)]
     pub allow_callers: Vec<PathBuf>,
-    /// Auth requirement: "password", "none", "deny"
+    /// systemd units (globs, e.g. "backup.service") whose processes are
+    /// allowed to run this target, whatever their executable
+    #[serde(default)]
+    pub allow_units: Vec<String>,
+    /// Auth requirement: "none", "confirm", "password", "discourage", "deny"
     #[serde(default)]
     pub auth: AuthRequirement,
-    /// Cache timeout in seconds (default 300 = 5 minutes)
+    /// Cache timeout in seconds (default 300 = 5 minutes). 0 never caches:
+    /// every request for the target is confirmed afresh.
     #[serde(default = "default_cache_timeout")]
     pub cache_timeout: u64,
+    /// How long a confirmation alone is remembered, when it's what the rule
+    /// asks for (default: `cache_timeout`). A password or other credential
+    /// is remembered for `cache_timeout` and also stands in for a
+    /// confirmation; a confirmation never stands in for a credential.
+    #[serde(default)]
+    pub confirm_cache_timeout: Option<u64>,
+    /// File that a launched process's stdout/stderr is appended to. May use
+    /// `%u`, `%U` and `%h` for the calling user (see `authd_policy::template`).
+    #[serde(default)]
+    pub log_output: Option<PathBuf>,
+    /// Set to false to switch a rule off without deleting it (default true)
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Refuse the target if its binary was modified or had its inode changed
+    /// less than this many seconds ago, or isn't root-owned and unwritable by
+    /// others (authsudo only; off by default)
+    #[serde(default)]
+    pub min_binary_age_secs: Option<u64>,
+    /// Two-person rule: a member of this group other than the requester must
+    /// approve each run by authenticating as themselves
+    #[serde(default)]
+    pub require_approver_group: Option<String>,
+    /// Login session kinds the rule applies in (`tty`, `graphical`, `ssh`);
+    /// empty means any
+    #[serde(default)]
+    pub allow_session_types: Vec<String>,
+    /// SELinux contexts (globs) the caller must run in; empty means any.
+    /// Only authd built with the `selinux` feature knows a caller's context.
+    #[serde(default)]
+    pub allow_contexts: Vec<String>,
+    /// URL authd POSTs a JSON summary to each time it authorizes the target
+    /// (authd built with the `webhook` feature only)
+    #[serde(default)]
+    pub notify_webhook: Option<String>,
+    /// For an interpreter target: scripts (exact paths or globs) its first
+    /// argument must name. Inline code such as `-c` is refused then.
+    #[serde(default)]
+    pub allow_scripts: Vec<PathBuf>,
+    /// For a multi-call binary (busybox, toybox): names it may be started
+    /// as, i.e. the file name of the link invoked. Without it, authsudo
+    /// passes the target's real path as `argv[0]`.
+    #[serde(default)]
+    pub allow_argv0: Vec<String>,
+    /// Refuse the target if it is setuid or setgid (authsudo only)
+    #[serde(default)]
+    pub forbid_setuid_target: bool,
+    /// Refuse the target if it has file capabilities (authsudo only)
+    #[serde(default)]
+    pub forbid_file_caps: bool,
+    /// Under `rule_resolution = "priority"`, the admitting rule with the
+    /// highest priority decides (default 0)
+    #[serde(default)]
+    pub priority: i32,
+    /// Resource limits the target runs under; with several matching rules
+    /// the lowest value of each applies
+    #[serde(default)]
+    pub limits: ResourceLimits,
+    /// How many launches of the target authd lets run at once; with several
+    /// matching rules the lowest applies (authd only)
+    #[serde(default)]
+    pub max_concurrent: Option<usize>,
+    /// Refuse the target if the process that ran authsudo started less than
+    /// this many seconds ago, as a freshly spawned shell would have
+    /// (authsudo only; off by default)
+    #[serde(default)]
+    pub min_caller_age_secs: Option<u64>,
+    /// Account authd launches the target as: `"caller"` for the requesting
+    /// user, or a user name, which must be on `allow_runas` in authd.toml.
+    /// Unset runs it as root (authd only).
+    #[serde(default)]
+    pub runas: Option<String>,
+    /// Whether a confirmation needs the target's file name typed out rather
+    /// than a keypress (authd only)
+    #[serde(default)]
+    pub require_type_confirm: bool,
+    /// Deny the target while any other user has a session open, as seen by
+    /// logind
+    #[serde(default)]
+    pub forbid_if_others_logged_in: bool,
+    /// PAM service passwords for this target are checked against, in place
+    /// of `authd` (authd only)
+    #[serde(default)]
+    pub pam_service: Option<pam::PamService>,
+    /// The target followed by the leading arguments the rule is limited to,
+    /// e.g. `"/usr/bin/apt install"`: the rule applies only to runs whose
+    /// arguments start with those words
+    #[serde(default)]
+    pub command: Option<String>,
+    /// Overrides authd.toml's `clean_env` for this target: false runs it with
+    /// the caller's whole environment (authsudo only)
+    #[serde(default)]
+    pub clean_env: Option<bool>,
 }
 
 fn default_cache_timeout() -> u64 {
     300
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-#[serde(rename_all = "lowercase")]
+fn default_enabled() -> bool {
+    true
+}
+
+/// A rule's `auth`; (de)serialized as [`auth`] describes
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub enum AuthRequirement {
     /// No interaction - run immediately
     None,
@@ -144,8 +549,13 @@ pub enum AuthRequirement {
     Confirm,
     /// Require password authentication
     Password,
+    /// Soft deny: warn that the action is discouraged and let the user
+    /// proceed anyway after an explicit confirmation (logged prominently)
+    Discourage,
     /// Always deny
     Deny,
+    /// Any one, or all, of several methods (`auth = ["password", "hwkey"]`)
+    Methods(MethodSet),
 }
 
 /// Wayland environment variables to pass through
@@ -207,6 +617,10 @@ mod tests {
             prompt_title: None,
             prompt_message: None,
             prompt_detail: None,
+            approval: None,
+            discouraged: false,
+            methods: None,
+            pam_service: None,
         });
 
         let encoded = rmp_serde::to_vec(&request).unwrap();
@@ -214,6 +628,126 @@ mod tests {
         assert!(matches!(decoded, DaemonRequest::Exec(_)));
     }
 
+    #[test]
+    fn auth_check_roundtrip() {
+        let request = DaemonRequest::Check(AuthCheckRequest {
+            target: PathBuf::from("/usr/bin/gparted"),
+            args: vec!["/dev/sda".into()],
+        });
+        let encoded = rmp_serde::to_vec(&request).unwrap();
+        let decoded: DaemonRequest = rmp_serde::from_slice(&encoded).unwrap();
+        assert!(
+            matches!(decoded, DaemonRequest::Check(r) if r.target == PathBuf::from("/usr/bin/gparted"))
+        );
+
+        for response in [
+            AuthCheckResponse::Cached {
+                remaining_secs: 240,
+            },
+            AuthCheckResponse::Allowed,
+            AuthCheckResponse::PasswordRequired,
+            AuthCheckResponse::Denied {
+                reason: "no".into(),
+            },
+            AuthCheckResponse::Unknown,
+        ] {
+            let encoded = rmp_serde::to_vec(&response).unwrap();
+            let decoded: AuthCheckResponse = rmp_serde::from_slice(&encoded).unwrap();
+            assert_eq!(format!("{decoded:?}"), format!("{response:?}"));
+        }
+    }
+
+    #[test]
+    fn scope_messages_roundtrip() {
+        let scope = ActiveScope {
+            unit: "authd-1000-42-1.scope".into(),
+            target: PathBuf::from("/usr/bin/gparted"),
+            caller: PathBuf::from("/usr/bin/authctl"),
+            uid: 1000,
+            pid: 4242,
+            started_secs: 1_700_000_000,
+        };
+        let encoded = rmp_serde::to_vec(&vec![scope.clone()]).unwrap();
+        let decoded: Vec<ActiveScope> = rmp_serde::from_slice(&encoded).unwrap();
+        assert_eq!(decoded, vec![scope]);
+
+        let request = DaemonRequest::KillScope(KillScopeRequest::new(
+            "authd-1000-42-1.scope",
+            KillSignal::Hup,
+        ));
+        let encoded = rmp_serde::to_vec(&request).unwrap();
+        let decoded: DaemonRequest = rmp_serde::from_slice(&encoded).unwrap();
+        assert!(matches!(
+            decoded,
+            DaemonRequest::KillScope(r)
+                if r.unit == "authd-1000-42-1.scope" && r.checked_signal() == Ok(KillSignal::Hup)
+        ));
+
+        let request = DaemonRequest::TestAuth(TestAuthRequest {
+            password: "hunter2".into(),
+        });
+        assert!(!format!("{:?}", request).contains("hunter2"));
+        let encoded = rmp_serde::to_vec(&request).unwrap();
+        let decoded: DaemonRequest = rmp_serde::from_slice(&encoded).unwrap();
+        assert!(matches!(decoded, DaemonRequest::TestAuth(r) if r.password == "hunter2"));
+
+        let encoded = rmp_serde::to_vec(&DaemonRequest::ListActive).unwrap();
+        let decoded: DaemonRequest = rmp_serde::from_slice(&encoded).unwrap();
+        assert!(matches!(decoded, DaemonRequest::ListActive));
+
+        let encoded = rmp_serde::to_vec(&DaemonRequest::ListCache).unwrap();
+        let decoded: DaemonRequest = rmp_serde::from_slice(&encoded).unwrap();
+        assert!(matches!(decoded, DaemonRequest::ListCache));
+
+        let encoded = rmp_serde::to_vec(&DaemonRequest::SubscribeAudit).unwrap();
+        let decoded: DaemonRequest = rmp_serde::from_slice(&encoded).unwrap();
+        assert!(matches!(decoded, DaemonRequest::SubscribeAudit));
+        let lagged = AuditStreamMessage::Lagged { missed: 3 };
+        let encoded = rmp_serde::to_vec(&lagged).unwrap();
+        assert_eq!(
+            rmp_serde::from_slice::<AuditStreamMessage>(&encoded).unwrap(),
+            lagged
+        );
+    }
+
+    #[test]
+    fn kill_requests_only_carry_safe_signals() {
+        let request = KillScopeRequest::new("a.scope", KillSignal::Term);
+        assert_eq!(request.signal, 15);
+        assert_eq!(request.checked_signal(), Ok(KillSignal::Term));
+
+        let sigkill = KillScopeRequest {
+            signal: 9,
+            ..request
+        };
+        assert_eq!(
+            sigkill.checked_signal(),
+            Err("signal 9 is not allowed (use TERM, HUP or INT)".into())
+        );
+
+        assert_eq!(KillSignal::parse("SIGINT"), Ok(KillSignal::Int));
+        assert_eq!(KillSignal::parse("hup"), Ok(KillSignal::Hup));
+        assert_eq!(KillSignal::parse("15"), Ok(KillSignal::Term));
+        assert!(KillSignal::parse("KILL").is_err());
+        assert!(KillSignal::parse("9").is_err());
+    }
+
+    #[test]
+    fn kill_requests_without_a_signal_default_to_term() {
+        /// A `KillScopeRequest` as clients sent it before `signal` existed
+        #[derive(Serialize)]
+        struct Legacy {
+            unit: String,
+        }
+
+        let encoded = rmp_serde::to_vec(&Legacy {
+            unit: "a.scope".into(),
+        })
+        .unwrap();
+        let decoded: KillScopeRequest = rmp_serde::from_slice(&encoded).unwrap();
+        assert_eq!(decoded.checked_signal(), Ok(KillSignal::Term));
+    }
+
     #[test]
     fn polkit_reply_roundtrip() {
         for reply in [
@@ -240,6 +774,10 @@ mod tests {
             prompt_title: None,
             prompt_message: None,
             prompt_detail: None,
+            approval: None,
+            discouraged: false,
+            methods: None,
+            pam_service: None,
         };
 
         let encoded = rmp_serde::to_vec(&request).unwrap();
@@ -250,6 +788,27 @@ mod tests {
         assert_eq!(decoded.env, request.env);
     }
 
+    #[test]
+    fn oversized_requests_are_refused_before_sending() {
+        let target = PathBuf::from("/usr/bin/echo");
+        let request = AuthRequest::try_new(target.clone(), vec!["hi".into()], HashMap::new());
+        assert!(request.is_ok_and(|request| request.args == ["hi"]));
+
+        let huge = vec!["x".repeat(64 * 1024); frame::MAX_FRAME_LEN / (64 * 1024)];
+        assert!(matches!(
+            AuthRequest::try_new(target.clone(), huge, HashMap::new()),
+            Err(frame::FrameError::TooLarge(_))
+        ));
+
+        // Prompt text filled in later counts too
+        let mut request = AuthRequest::try_new(target, Vec::new(), HashMap::new()).unwrap();
+        request.prompt_detail = Some("x".repeat(frame::MAX_FRAME_LEN));
+        assert!(matches!(
+            request.check_size(),
+            Err(frame::FrameError::TooLarge(_))
+        ));
+    }
+
     #[test]
     fn auth_request_roundtrip_with_prompt_text() {
         let request = AuthRequest {
@@ -261,6 +820,10 @@ mod tests {
             prompt_title: Some("Config access request".into()),
             prompt_message: Some("Allow this config access?".into()),
             prompt_detail: Some("/home/osso/.config/example".into()),
+            approval: None,
+            discouraged: false,
+            methods: None,
+            pam_service: None,
         };
 
         let encoded = rmp_serde::to_vec(&request).unwrap();
@@ -274,7 +837,14 @@ mod tests {
     #[test]
     fn auth_response_variants_roundtrip() {
         let responses = vec![
-            AuthResponse::Success { pid: 12345 },
+            AuthResponse::Success {
+                pid: 12345,
+                cached: false,
+            },
+            AuthResponse::Success {
+                pid: 12345,
+                cached: true,
+            },
             AuthResponse::AuthFailed,
             AuthResponse::Denied {
                 reason: "not allowed".into(),
@@ -283,6 +853,16 @@ mod tests {
             AuthResponse::Error {
                 message: "something went wrong".into(),
             },
+            AuthResponse::Exited {
+                pid: 12345,
+                code: Some(3),
+                signal: None,
+            },
+            AuthResponse::Exited {
+                pid: 12345,
+                code: None,
+                signal: Some(9),
+            },
         ];
 
         for response in responses {
@@ -292,6 +872,24 @@ mod tests {
         }
     }
 
+    #[test]
+    fn success_from_an_older_daemon_is_not_cached() {
+        #[derive(Serialize)]
+        enum OldResponse {
+            Success { pid: u32 },
+        }
+
+        let encoded = rmp_serde::to_vec(&OldResponse::Success { pid: 7 }).unwrap();
+        let decoded: AuthResponse = rmp_serde::from_slice(&encoded).unwrap();
+        assert!(matches!(
+            decoded,
+            AuthResponse::Success {
+                pid: 7,
+                cached: false
+            }
+        ));
+    }
+
     #[test]
     fn policy_rule_defaults() {
         let toml = r#"
@@ -304,6 +902,8 @@ mod tests {
         assert!(rule.allow_users.is_empty());
         assert!(matches!(rule.auth, AuthRequirement::Confirm));
         assert_eq!(rule.cache_timeout, 300);
+        assert_eq!(rule.confirm_cache_timeout, None);
+        assert_eq!(rule.clean_env, None);
     }
 
     #[test]
@@ -344,6 +944,12 @@ mod tests {
                 .auth,
             AuthRequirement::Password
         ));
+        assert!(matches!(
+            toml::from_str::<PolicyRule>("target = \"/bin/x\"\nauth = \"discourage\"")
+                .unwrap()
+                .auth,
+            AuthRequirement::Discourage
+        ));
         assert!(matches!(
             toml::from_str::<PolicyRule>("target = \"/bin/x\"\nauth = \"deny\"")
                 .unwrap()
@@ -0,0 +1,117 @@
+//! Resource limits a rule puts on its target
+//!
+//! authsudo applies them with `setrlimit` before exec; authd passes them to
+//! `systemd-run` as `Limit*=` properties of the target's scope. Each limit
+//! sets both the soft and the hard value, so the target can't raise it again.
+//!
+//! ```toml
+//! [[rules]]
+//! target = "/usr/bin/rsync"
+//! limits = { nofile = 1024, nproc = 64, as = 2147483648, cpu = 600 }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::num::NonZeroU64;
+
+/// Zero is refused at load: no target can run with no files, processes,
+/// memory or CPU time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResourceLimits {
+    /// Open file descriptors (`RLIMIT_NOFILE`)
+    pub nofile: Option<NonZeroU64>,
+    /// Processes of the user the target runs as (`RLIMIT_NPROC`)
+    pub nproc: Option<NonZeroU64>,
+    /// Address space in bytes (`RLIMIT_AS`)
+    #[serde(rename = "as")]
+    pub address_space: Option<NonZeroU64>,
+    /// CPU time in seconds (`RLIMIT_CPU`)
+    pub cpu: Option<NonZeroU64>,
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The lower of each limit set in `self` or `other`
+    pub fn strictest(self, other: Self) -> Self {
+        fn min(a: Option<NonZeroU64>, b: Option<NonZeroU64>) -> Option<NonZeroU64> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        }
+        Self {
+            nofile: min(self.nofile, other.nofile),
+            nproc: min(self.nproc, other.nproc),
+            address_space: min(self.address_space, other.address_space),
+            cpu: min(self.cpu, other.cpu),
+        }
+    }
+
+    /// The limits as systemd unit properties (`LimitNOFILE=1024`), for
+    /// `systemd-run --property`
+    pub fn systemd_properties(&self) -> Vec<String> {
+        [
+            ("LimitNOFILE", self.nofile),
+            ("LimitNPROC", self.nproc),
+            ("LimitAS", self.address_space),
+            ("LimitCPU", self.cpu),
+        ]
+        .into_iter()
+        .filter_map(|(property, limit)| limit.map(|limit| format!("{}={}", property, limit)))
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Result<ResourceLimits, toml::de::Error> {
+        toml::from_str(toml)
+    }
+
+    #[test]
+    fn limits_parse_with_as_for_address_space() {
+        let limits = parse("nofile = 1024\nas = 2147483648").unwrap();
+        assert_eq!(limits.nofile, NonZeroU64::new(1024));
+        assert_eq!(limits.address_space, NonZeroU64::new(2147483648));
+        assert_eq!(limits.nproc, None);
+        assert!(parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn zero_negative_and_unknown_limits_are_refused() {
+        assert!(parse("nofile = 0").is_err());
+        assert!(parse("cpu = -1").is_err());
+        assert!(parse("stack = 8192").is_err());
+    }
+
+    #[test]
+    fn strictest_keeps_the_lower_of_each_limit() {
+        let a = parse("nofile = 1024\ncpu = 60").unwrap();
+        let b = parse("nofile = 256\nnproc = 10").unwrap();
+        let both = a.strictest(b);
+        assert_eq!(both.nofile, NonZeroU64::new(256));
+        assert_eq!(both.nproc, NonZeroU64::new(10));
+        assert_eq!(both.cpu, NonZeroU64::new(60));
+        assert_eq!(both.address_space, None);
+    }
+
+    #[test]
+    fn limits_become_systemd_properties() {
+        let limits = parse("cpu = 600\nnofile = 1024\nnproc = 64\nas = 4096").unwrap();
+        assert_eq!(
+            limits.systemd_properties(),
+            [
+                "LimitNOFILE=1024",
+                "LimitNPROC=64",
+                "LimitAS=4096",
+                "LimitCPU=600"
+            ]
+        );
+        assert!(ResourceLimits::default().systemd_properties().is_empty());
+    }
+}
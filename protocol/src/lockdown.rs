@@ -0,0 +1,77 @@
+//! Lockdown: every escalation refused but those on `emergency_allow`
+//!
+//! An admin turns it on in an incident (`authctl --lockdown on`) and off
+//! again once it's over. It is kept as a flag file next to the instance's
+//! socket, so restarting authd keeps it and authsudo, which runs
+//! `auth = "none"` rules without asking authd, sees it too. The file is on
+//! tmpfs: a reboot clears lockdown.
+//!
+//! The default instance's flag locks down every instance, so that naming
+//! another one isn't a way around it. A named instance's own flag only
+//! covers that instance.
+
+use crate::instance::{INSTANCE_DIR, InstanceName};
+use std::path::{Path, PathBuf};
+
+/// Why a request was refused in lockdown
+pub const LOCKDOWN_REASON: &str = "system in lockdown";
+
+/// The flag file of `instance`; the default instance's for `None`
+pub fn flag_path(instance: Option<&InstanceName>) -> PathBuf {
+    match instance {
+        Some(name) => Path::new(INSTANCE_DIR).join(format!("{}.lockdown", name)),
+        None => Path::new(INSTANCE_DIR).join("lockdown"),
+    }
+}
+
+/// The flag that locks down `instance` besides its own: the default
+/// instance's, for every named one
+pub fn global_flag(instance: Option<&InstanceName>) -> Option<PathBuf> {
+    instance.map(|_| flag_path(None))
+}
+
+/// Whether `instance` is locked down, by its own flag or the global one
+pub fn instance_is_active(instance: Option<&InstanceName>) -> bool {
+    is_active(&flag_path(instance)) || global_flag(instance).is_some_and(|flag| is_active(&flag))
+}
+
+/// Whether lockdown is on, i.e. `flag` exists. Only root (or authd's
+/// service user) can create it, and it can only make things stricter.
+pub fn is_active(flag: &Path) -> bool {
+    flag.symlink_metadata().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instances_have_their_own_flag() {
+        let test = InstanceName::new("test").unwrap();
+        assert_eq!(flag_path(None), PathBuf::from("/run/authd/lockdown"));
+        assert_eq!(
+            flag_path(Some(&test)),
+            PathBuf::from("/run/authd/test.lockdown")
+        );
+    }
+
+    #[test]
+    fn the_default_flag_covers_every_instance() {
+        let test = InstanceName::new("test").unwrap();
+        assert_eq!(global_flag(None), None);
+        assert_eq!(
+            global_flag(Some(&test)),
+            Some(PathBuf::from("/run/authd/lockdown"))
+        );
+    }
+
+    #[test]
+    fn lockdown_is_on_while_the_flag_exists() {
+        let flag = std::env::temp_dir().join(format!("authd-lockdown-{}", std::process::id()));
+        assert!(!is_active(&flag));
+        std::fs::write(&flag, b"").unwrap();
+        assert!(is_active(&flag));
+        std::fs::remove_file(&flag).unwrap();
+        assert!(!is_active(&flag));
+    }
+}
@@ -0,0 +1,301 @@
+//! User-facing messages
+//!
+//! Every message authsudo, authctl and the dialogs show has an id in
+//! [`MessageId`] and an English template in which `{0}`, `{1}`, ... stand
+//! for its arguments. Code builds a [`Message`] from an id and arguments
+//! instead of formatting text, so the same thing is always said the same
+//! way, tests can match on ids, and a [`Catalog`] can supply translations.
+//!
+//! The program prefix (`authsudo: `) isn't part of a message.
+
+use std::collections::HashMap;
+use std::fmt;
+
+macro_rules! messages {
+    ($($(#[$doc:meta])* $id:ident => $english:literal,)*) => {
+        /// Id of a user-facing message
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum MessageId {
+            $($(#[$doc])* $id,)*
+        }
+
+        impl MessageId {
+            /// Every message id, in declaration order
+            pub const ALL: &[MessageId] = &[$(MessageId::$id,)*];
+
+            /// Stable name of the id, to key translations by
+            pub fn key(self) -> &'static str {
+                match self {
+                    $(MessageId::$id => stringify!($id),)*
+                }
+            }
+
+            /// The default English template
+            pub fn english(self) -> &'static str {
+                match self {
+                    $(MessageId::$id => $english,)*
+                }
+            }
+        }
+    };
+}
+
+messages! {
+    // Answers from authd
+    /// `{0}`: why policy refused
+    Denied => "denied - {0}",
+    NoPolicy => "no policy for this command",
+    /// `{0}`: the target
+    NoPolicyFor => "no policy for {0}",
+    AuthenticationFailed => "authentication failed",
+    AuthenticationSucceeded => "authentication succeeded",
+    /// `{0}`: why the password couldn't be checked (e.g. the PAM error)
+    AuthenticationError => "cannot authenticate: {0}",
+    IncorrectPassword => "incorrect password",
+    AuthorizationDenied => "authorization denied",
+    /// `{0}`: what went wrong in authd
+    DaemonError => "error - {0}",
+    DaemonNotRunning => "daemon not running",
+    /// `{0}`: the connect error
+    ConnectFailed => "cannot connect to authd: {0}",
+    UnexpectedResponse => "unexpected response from daemon",
+    /// `{0}`: the transport error
+    RequestFailed => "request to authd failed: {0}",
+
+    // Launching and stopping
+    /// `{0}`: pid of the launched process
+    ProcessSpawned => "process spawned (pid {0})",
+    /// `{0}`: pid of the process launched on a cached authorization
+    ProcessSpawnedCached => "authorized (cached), process spawned (pid {0})",
+    /// `{0}`: the full request, as [`Details`](crate::details::Details) shows it
+    AskingToRun => "asking to run\n{0}",
+    DetailsNeedsCommand => "--details requires a command",
+    /// `{0}`: unit name
+    ScopeStopped => "stopped {0}",
+    /// `{0}`: unit name
+    NoActiveScope => "no active scope {0}",
+    LockdownOn => "lockdown on: only emergency_allow targets may run",
+    LockdownCleared => "lockdown cleared",
+    /// `{0}`: the command
+    CommandNotFound => "command not found: {0}",
+    /// `{0}`: the program, `{1}`: the exec error
+    ExecFailed => "failed to execute {0}: {1}",
+    /// `{0}`: the target
+    RunningDiscouraged => "warning: running discouraged command {0}",
+    MissingProgram => "missing program",
+    /// `{0}`: the spawn error
+    RunAuthctlFailed => "failed to run authctl: {0}",
+
+    // Users and the local system
+    /// `{0}`: the `-u` argument
+    UnknownUser => "unknown user: {0}",
+    UserArgumentMissing => "-u requires an argument",
+    /// `{0}`: the uid
+    NoPasswdEntry => "uid {0} has no passwd entry",
+    /// `{0}`: the directory, `{1}`: the error
+    ChdirFailed => "cannot change to {0}: {1}",
+    DropPrivilegesFailed => "failed to drop privileges",
+    ReadGroupsFailed => "failed to read groups",
+    /// `{0}`: the load error
+    ConfigLoadFailed => "failed to load config: {0}",
+    /// `{0}`: the load error
+    PoliciesLoadFailed => "failed to load policies: {0}",
+    TestPolicyRootOnly => "--test-policy is restricted to root",
+    RecordingRootOnly => "--record and --verify-recordings are restricted to root",
+    /// `{0}`: the recording file
+    Recorded => "recorded in {0}",
+    /// `{0}`: how many recordings
+    RecordingsUnchanged => "{0} recorded decisions unchanged",
+    /// `{0}`: how many changed, `{1}`: how many recordings
+    RecordingsChanged => "{0} of {1} recorded decisions changed",
+    NothingToRepeat => "no command to repeat",
+    /// `{0}`: the command line
+    Repeating => "repeating: {0}",
+    NothingCached => "no cached authorizations",
+    /// `{0}`: sudoers file, `{1}`: line number, `{2}`: why
+    SudoersLineSkipped => "{0}:{1}: skipped: {2}",
+
+    // Dialogs and terminal prompts
+    DiscouragedTitle => "Discouraged Action",
+    DiscouragedMessage => "This action is discouraged. Proceed anyway?",
+    PolkitTitle => "Authorization Required",
+    /// `{0}`: the command line
+    RunAsRootQuestion => "run {0} as root?",
+    /// `{0}`: the command line
+    DiscouragedQuestion => "{0} is discouraged. Run it anyway?",
+    NotConfirmed => "not confirmed",
+}
+
+impl MessageId {
+    /// This message with `args` for its placeholders
+    pub fn with(self, args: &[&dyn fmt::Display]) -> Message {
+        Message {
+            id: self,
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+}
+
+/// A message id with its arguments, shown in English by `Display`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub id: MessageId,
+    pub args: Vec<String>,
+}
+
+impl Message {
+    /// The message in `catalog`'s language, or in English where it has no
+    /// template for it
+    pub fn render(&self, catalog: &impl Catalog) -> String {
+        let template = catalog
+            .template(self.id)
+            .unwrap_or_else(|| self.id.english());
+        fill(template, &self.args)
+    }
+}
+
+impl From<MessageId> for Message {
+    fn from(id: MessageId) -> Self {
+        id.with(&[])
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(&English))
+    }
+}
+
+/// Templates for messages, by id
+pub trait Catalog {
+    fn template(&self, id: MessageId) -> Option<&str>;
+}
+
+/// The built-in English templates
+pub struct English;
+
+impl Catalog for English {
+    fn template(&self, id: MessageId) -> Option<&str> {
+        Some(id.english())
+    }
+}
+
+/// A translation keyed by [`MessageId::key`], e.g. loaded from a file
+impl Catalog for HashMap<String, String> {
+    fn template(&self, id: MessageId) -> Option<&str> {
+        self.get(id.key()).map(String::as_str)
+    }
+}
+
+/// Replace `{N}` in `template` with `args[N]`. Anything else in braces,
+/// or an index without an argument, is kept as written.
+fn fill(template: &str, args: &[String]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        rest = &rest[open..];
+        let arg = rest
+            .find('}')
+            .and_then(|close| Some((rest[1..close].parse::<usize>().ok()?, close)))
+            .and_then(|(index, close)| Some((args.get(index)?, close)));
+        match arg {
+            Some((arg, close)) => {
+                out.push_str(arg);
+                rest = &rest[close + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Indexes of the `{N}` placeholders in `template`
+    fn placeholders(template: &str) -> Vec<usize> {
+        template
+            .split('{')
+            .skip(1)
+            .filter_map(|part| part.split_once('}')?.0.parse().ok())
+            .collect()
+    }
+
+    #[test]
+    fn every_message_has_an_english_template() {
+        assert!(!MessageId::ALL.is_empty());
+        for &id in MessageId::ALL {
+            let template = id.english();
+            assert!(!template.trim().is_empty(), "{} has no text", id.key());
+            // Arguments are numbered from 0 without gaps
+            let mut used = placeholders(template);
+            used.sort_unstable();
+            used.dedup();
+            assert_eq!(
+                used,
+                (0..used.len()).collect::<Vec<_>>(),
+                "{} numbers its arguments wrongly",
+                id.key()
+            );
+        }
+    }
+
+    #[test]
+    fn keys_are_unique() {
+        let mut keys: Vec<&str> = MessageId::ALL.iter().map(|id| id.key()).collect();
+        keys.sort_unstable();
+        keys.dedup();
+        assert_eq!(keys.len(), MessageId::ALL.len());
+    }
+
+    #[test]
+    fn arguments_fill_placeholders() {
+        let message = MessageId::ExecFailed.with(&[&"/usr/bin/id", &"No such file"]);
+        assert_eq!(
+            message.to_string(),
+            "failed to execute /usr/bin/id: No such file"
+        );
+        assert_eq!(
+            MessageId::ProcessSpawned.with(&[&4242]).to_string(),
+            "process spawned (pid 4242)"
+        );
+        assert_eq!(
+            Message::from(MessageId::NoPolicy).to_string(),
+            "no policy for this command"
+        );
+    }
+
+    #[test]
+    fn missing_arguments_and_stray_braces_are_kept() {
+        assert_eq!(fill("{0} and {1}", &["a".into()]), "a and {1}");
+        assert_eq!(fill("{x} {", &[]), "{x} {");
+        assert_eq!(fill("{0}{0}", &["ab".into()]), "abab");
+    }
+
+    #[test]
+    fn translations_may_reorder_arguments() {
+        let german: HashMap<String, String> = [(
+            "ExecFailed".to_string(),
+            "{1}: {0} konnte nicht ausgeführt werden".to_string(),
+        )]
+        .into_iter()
+        .collect();
+
+        let message = MessageId::ExecFailed.with(&[&"/usr/bin/id", &"No such file"]);
+        assert_eq!(
+            message.render(&german),
+            "No such file: /usr/bin/id konnte nicht ausgeführt werden"
+        );
+        // Untranslated messages fall back to English
+        assert_eq!(
+            Message::from(MessageId::NoPolicy).render(&german),
+            "no policy for this command"
+        );
+    }
+}
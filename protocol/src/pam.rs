@@ -0,0 +1,104 @@
+//! A rule's `pam_service`: which `/etc/pam.d/` stack checks the passwords
+//! asked for its target
+//!
+//! Lets admins keep a stricter stack for sensitive targets (`poweroff`,
+//! `mkfs`) than for everyday ones. The name becomes a path under
+//! `/etc/pam.d`, so anything but a plain file name is refused at load.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The service used unless a rule names another
+pub const DEFAULT_SERVICE: &str = "authd";
+
+/// Longest service name accepted
+const MAX_LEN: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PamService(String);
+
+impl PamService {
+    /// Letters, digits, `.`, `_` and `-`, not starting with `.` or `-`
+    pub fn new(name: &str) -> Result<Self, String> {
+        let safe = !name.is_empty()
+            && name.len() <= MAX_LEN
+            && !name.starts_with(['.', '-'])
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'));
+        if !safe {
+            return Err(format!(
+                "invalid pam_service {:?}: use up to {} letters, digits, '.', '_' and '-'",
+                name, MAX_LEN
+            ));
+        }
+        Ok(Self(name.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for PamService {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, String> {
+        Self::new(&name)
+    }
+}
+
+impl From<PamService> for String {
+    fn from(service: PamService) -> Self {
+        service.0
+    }
+}
+
+impl fmt::Display for PamService {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_service_names_are_accepted() {
+        for name in ["authd", "authd-power", "authd.strict", "authd_2"] {
+            assert_eq!(PamService::new(name).unwrap().as_str(), name);
+        }
+    }
+
+    #[test]
+    fn names_that_leave_pam_d_are_refused() {
+        for name in [
+            "",
+            "../shadow",
+            "pam.d/authd",
+            ".hidden",
+            "-x",
+            "a b",
+            "authd\0",
+        ] {
+            assert!(PamService::new(name).is_err(), "{name:?}");
+        }
+        assert!(PamService::new(&"a".repeat(65)).is_err());
+    }
+
+    #[test]
+    fn invalid_names_fail_deserialization() {
+        #[derive(Deserialize)]
+        struct Rule {
+            pam_service: PamService,
+        }
+        let rule: Rule = toml::from_str("pam_service = \"authd-power\"").unwrap();
+        assert_eq!(rule.pam_service.as_str(), "authd-power");
+        let error = toml::from_str::<Rule>("pam_service = \"../shadow\"")
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("invalid pam_service"), "{error}");
+    }
+}
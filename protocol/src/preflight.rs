@@ -0,0 +1,171 @@
+//! Ask authd whether an action would be permitted, without side effects
+//!
+//! Backed by [`DaemonRequest::Check`]: nothing is prompted, spawned or
+//! cached, so GUIs can call this to gray out actions that would be denied.
+//!
+//! ```no_run
+//! use authd_protocol::preflight::{preflight, PreflightDecision};
+//! use std::path::Path;
+//!
+//! let result = preflight(Path::new("/usr/bin/gparted"), &[]).unwrap();
+//! let show_run_as_root = matches!(
+//!     result.decision,
+//!     PreflightDecision::Allow | PreflightDecision::Authenticate
+//! );
+//! ```
+
+use crate::instance::{self, InstanceName};
+use crate::{AuthCheckRequest, AuthCheckResponse, AuthRequirement, DaemonRequest, frame};
+use std::path::Path;
+use thiserror::Error;
+
+/// What running the target would come down to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreflightDecision {
+    /// Runs without any interaction
+    Allow,
+    /// Runs once the user gets through what `requires` asks for
+    Authenticate,
+    /// Refused by policy
+    Deny { reason: String },
+    /// No rule covers the target, so it would be refused
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreflightResult {
+    pub decision: PreflightDecision,
+    /// What the matching policy asks of the user. `Password` stands for any
+    /// interaction authd can't skip, since the check doesn't tell confirm
+    /// and discourage apart.
+    pub requires: AuthRequirement,
+    /// A cached confirmation covers the target, so the policy's
+    /// requirement is waived for now
+    pub cached: bool,
+}
+
+impl From<AuthCheckResponse> for PreflightResult {
+    fn from(response: AuthCheckResponse) -> Self {
+        let (decision, requires, cached) = match response {
+            AuthCheckResponse::Cached { .. } => {
+                (PreflightDecision::Allow, AuthRequirement::Confirm, true)
+            }
+            AuthCheckResponse::Allowed => (PreflightDecision::Allow, AuthRequirement::None, false),
+            AuthCheckResponse::PasswordRequired => (
+                PreflightDecision::Authenticate,
+                AuthRequirement::Password,
+                false,
+            ),
+            AuthCheckResponse::Denied { reason } => (
+                PreflightDecision::Deny { reason },
+                AuthRequirement::Deny,
+                false,
+            ),
+            AuthCheckResponse::Unknown => {
+                (PreflightDecision::Unknown, AuthRequirement::Deny, false)
+            }
+        };
+        Self {
+            decision,
+            requires,
+            cached,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("failed to reach authd: {0}")]
+pub struct PreflightError(pub String);
+
+/// Ask the default authd instance about running `target` with `args`
+pub fn preflight(target: &Path, args: &[String]) -> Result<PreflightResult, PreflightError> {
+    preflight_instance(None, target, args)
+}
+
+/// Ask the authd instance `instance` (the default one for `None`)
+pub fn preflight_instance(
+    instance: Option<&InstanceName>,
+    target: &Path,
+    args: &[String],
+) -> Result<PreflightResult, PreflightError> {
+    let request = DaemonRequest::Check(AuthCheckRequest {
+        target: target.to_path_buf(),
+        args: args.to_vec(),
+    });
+    let response: AuthCheckResponse = frame::call_socket(instance::socket_path(instance), &request)
+        .map_err(|e| PreflightError(e.to_string()))?;
+    Ok(response.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(
+        decision: PreflightDecision,
+        requires: AuthRequirement,
+        cached: bool,
+    ) -> PreflightResult {
+        PreflightResult {
+            decision,
+            requires,
+            cached,
+        }
+    }
+
+    #[test]
+    fn cached_confirmation_allows_without_interaction() {
+        let response = AuthCheckResponse::Cached {
+            remaining_secs: 240,
+        };
+        assert_eq!(
+            PreflightResult::from(response),
+            result(PreflightDecision::Allow, AuthRequirement::Confirm, true)
+        );
+    }
+
+    #[test]
+    fn allowed_needs_nothing() {
+        assert_eq!(
+            PreflightResult::from(AuthCheckResponse::Allowed),
+            result(PreflightDecision::Allow, AuthRequirement::None, false)
+        );
+    }
+
+    #[test]
+    fn password_required_needs_authentication() {
+        assert_eq!(
+            PreflightResult::from(AuthCheckResponse::PasswordRequired),
+            result(
+                PreflightDecision::Authenticate,
+                AuthRequirement::Password,
+                false
+            )
+        );
+    }
+
+    #[test]
+    fn denied_keeps_the_reason() {
+        let response = AuthCheckResponse::Denied {
+            reason: "not in wheel".into(),
+        };
+        assert_eq!(
+            PreflightResult::from(response),
+            result(
+                PreflightDecision::Deny {
+                    reason: "not in wheel".into()
+                },
+                AuthRequirement::Deny,
+                false
+            )
+        );
+    }
+
+    #[test]
+    fn unknown_target_is_refused() {
+        assert_eq!(
+            PreflightResult::from(AuthCheckResponse::Unknown),
+            result(PreflightDecision::Unknown, AuthRequirement::Deny, false)
+        );
+    }
+}